@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ed25519_dalek::Keypair;
+use icn_node::blockchain::executor::{Account, ExecutionEngine, Outcome};
+use icn_node::{Block, Blockchain, CurrencyType, Transaction};
+use rand::rngs::OsRng;
+use std::collections::HashMap;
+
+/// Signed transfers among `members` members; the fewer members, the more
+/// transactions conflict.
+fn transactions(count: usize, members: usize) -> Vec<Transaction> {
+    let keypair = Keypair::generate(&mut OsRng {});
+    (0..count).map(|i| {
+        let mut transaction = Transaction::new(
+            format!("member{}", i % members),
+            format!("member{}", (i * 7 + 1) % members),
+            1.0,
+            CurrencyType::BasicNeeds,
+            1000,
+        );
+        transaction.sign(&keypair).unwrap();
+        transaction
+    }).collect()
+}
+
+/// Checks the signature before transferring, as a node does for gossiped
+/// transactions, so each transaction costs about what it would in practice.
+fn verify_and_transfer(transaction: &Transaction, balances: &HashMap<Account, f64>) -> Outcome<bool> {
+    let valid = transaction.verify().unwrap_or(false);
+    let from = (transaction.from.clone(), transaction.currency_type.clone());
+    let to = (transaction.to.clone(), transaction.currency_type.clone());
+    let writes = if valid && from != to {
+        vec![(from.clone(), balances[&from] - transaction.amount), (to.clone(), balances[&to] + transaction.amount)]
+    } else {
+        Vec::new()
+    };
+    Outcome { result: valid, writes }
+}
+
+fn bench_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute_1000_transactions");
+    for members in [2000, 50, 2] {
+        let transactions = transactions(1000, members);
+        for (name, engine) in [("sequential", ExecutionEngine::sequential()), ("parallel", ExecutionEngine::new())] {
+            group.bench_with_input(BenchmarkId::new(name, format!("{}_members", members)), &transactions, |b, transactions| {
+                b.iter(|| engine.execute(transactions, |_| 100.0, verify_and_transfer));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_block_application(c: &mut Criterion) {
+    let mut source = Blockchain::new();
+    for transaction in transactions(1000, 2000) {
+        source.add_transaction(transaction).unwrap();
+    }
+    source.create_block("bench".to_string()).unwrap();
+    let block: Block = source.chain[1].clone();
+
+    let mut group = c.benchmark_group("append_block");
+    for (name, engine) in [("sequential", ExecutionEngine::sequential()), ("parallel", ExecutionEngine::new())] {
+        group.bench_function(name, |b| {
+            b.iter_batched(|| {
+                let mut blockchain = Blockchain::new();
+                blockchain.execution_engine = engine;
+                (blockchain, block.clone())
+            }, |(mut blockchain, block)| blockchain.append_block(block).unwrap(), BatchSize::LargeInput);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_engine, bench_block_application);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+// icn-client/build.rs
+use std::env;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let proto_root = "../proto";
+    let proto = "../proto/icn/node/v1/node.proto";
+    println!("cargo:rerun-if-changed={}", proto);
+    // A bundled protoc, so that building needs nothing installed
+    if env::var_os("PROTOC").is_none() {
+        env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_prost_build::configure()
+        .build_client(true)
+        .build_server(env::var_os("CARGO_FEATURE_SERVER").is_some())
+        .compile_protos(&[proto], &[proto_root])?;
+    Ok(())
+}
@@ -0,0 +1,20 @@
+// icn-client/src/lib.rs
+//! Typed gRPC client for the node control service of an ICN node, generated
+//! from `proto/icn/node/v1/node.proto`.
+//!
+//! ```no_run
+//! use icn_client::v1::{node_control_client::NodeControlClient, GetChainInfoRequest};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut client = NodeControlClient::connect("http://127.0.0.1:50051").await?;
+//! let info = client.get_chain_info(GetChainInfoRequest {}).await?.into_inner();
+//! println!("{} blocks", info.block_count);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod v1 {
+    tonic::include_proto!("icn.node.v1");
+}
+
+pub use tonic;
@@ -0,0 +1,687 @@
+// src/api/graphql/mod.rs
+//! A GraphQL view of the chain, governance and identities, for dashboards
+//! that need nested data, such as the reputation of everyone who voted on a
+//! proposal, in one request. Only queries are served; changes go through the
+//! REST calls of `ApiLayer`.
+
+pub mod parser;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use crate::blockchain::{Block, Blockchain, LogEntry, LogFilter, Transaction, TransactionReceipt};
+use crate::currency::CurrencyType;
+use crate::governance::democracy::{Proposal, Vote};
+use crate::governance::DemocraticSystem;
+use crate::identity::{DecentralizedIdentity, DidManager};
+use crate::smart_contract::SmartContract;
+use self::parser::{Field, Value};
+
+/// Most items a list field returns, whatever `limit` asks for.
+pub const MAX_PAGE_SIZE: usize = 100;
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// Most fields a query may resolve, as estimated by `complexity` before it
+/// runs.
+pub const MAX_COMPLEXITY: usize = 10_000;
+
+/// Fields that return lists. Those without a `limit` argument are assumed to
+/// return `MAX_PAGE_SIZE` items.
+const LIST_FIELDS: &[&str] = &["blocks", "transactions", "proposals", "votes", "events", "contracts"];
+
+/// The types and fields queries can select, for client tooling. Scalars
+/// typed `JSON` are the REST API's serialization of the value.
+pub const SCHEMA: &str = r#"
+scalar JSON
+
+type Query {
+  block(index: Int, hash: String): Block
+  blocks(from: Int = 0, limit: Int = 10): [Block!]!
+  latestBlock: Block
+  transaction(hash: String!): Transaction
+  account(address: String!): Account!
+  proposals(status: ProposalStatus): [Proposal!]!
+  proposal(id: String!): Proposal
+  identity(did: String!): Identity
+  contracts: [Contract!]!
+  contract(id: String!): Contract
+}
+
+enum ProposalStatus { Active Passed Rejected Implemented }
+
+type Block {
+  index: Int!
+  hash: String!
+  previousHash: String!
+  timestamp: Int!
+  nonce: Int!
+  gasUsed: Int!
+  protocolVersion: Int!
+  proposer: String
+  approved: Boolean!
+  transactionCount: Int!
+  transactions: [Transaction!]!
+}
+
+type Transaction {
+  hash: String!
+  from: Account!
+  to: Account!
+  amount: Float!
+  currency: JSON!
+  gasLimit: Int!
+  gasPrice: Float!
+  validUntil: JSON
+  outputs: JSON!
+  swap: JSON
+  stream: JSON
+  standingOrder: JSON
+  allowance: JSON
+  cosignatures: Int!
+  validation: JSON
+  organization: JSON
+  settlement: JSON
+  dividend: JSON
+  rentFor: String
+  contractCreation: JSON
+  vesting: JSON
+  crowdfund: JSON
+  agreement: JSON
+  market: JSON
+  networkId: String!
+  contract: Contract
+  receipt: TransactionReceipt
+  block: Block
+}
+
+type TransactionReceipt {
+  transactionHash: String!
+  blockIndex: Int!
+  blockHash: String!
+  success: Boolean!
+  status: JSON!
+  gasUsed: Int!
+  events: [Event!]!
+  balanceChanges: JSON!
+  contractAddress: String
+}
+
+type Event {
+  blockIndex: Int!
+  transactionHash: String!
+  contractId: String!
+  name: String!
+  data: String!
+  contract: Contract
+  transaction: Transaction
+}
+
+type Account {
+  address: String!
+  balance(currency: JSON): Float!
+  reputation: Float
+  isValidator: Boolean!
+  stake: JSON!
+  identity: Identity
+  transactions(limit: Int = 10): [Transaction!]!
+  proposals: [Proposal!]!
+  votes: [Vote!]!
+}
+
+type Proposal {
+  id: String!
+  title: String!
+  description: String!
+  proposer: Account!
+  status: ProposalStatus!
+  proposalType: JSON!
+  category: JSON!
+  createdAt: String!
+  votingEndsAt: String!
+  requiredQuorum: Float!
+  executionTimestamp: String
+  attachments: [String!]!
+  votes: [Vote!]!
+}
+
+type Vote {
+  voter: Account!
+  proposal: Proposal
+  inFavor: Boolean!
+  weight: Float!
+  timestamp: String!
+}
+
+type Identity {
+  id: String!
+  publicKey: String!
+  createdAt: String!
+  attributes: JSON!
+  revoked: Boolean!
+  account: Account!
+}
+
+type Contract {
+  id: String!
+  definition: JSON!
+  events(limit: Int = 10): [Event!]!
+}
+"#;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphQLRequest {
+    pub query: String,
+    #[serde(default)]
+    pub variables: Map<String, JsonValue>,
+    /// Which operation of `query` to run, if it holds several.
+    #[serde(default)]
+    pub operation_name: Option<String>,
+}
+
+impl GraphQLRequest {
+    pub fn new(query: &str) -> Self {
+        GraphQLRequest { query: query.to_string(), ..Default::default() }
+    }
+
+    pub fn with_variable(mut self, name: &str, value: JsonValue) -> Self {
+        self.variables.insert(name.to_string(), value);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphQLError {
+    pub message: String,
+    /// Response keys and list indices leading to the field that failed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path: Vec<JsonValue>,
+}
+
+/// As the GraphQL spec has it: fields that fail are null in `data` and
+/// reported in `errors`; `data` is only missing if the query could not run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphQLResponse {
+    pub data: Option<JsonValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<GraphQLError>,
+}
+
+impl GraphQLResponse {
+    fn failed(message: String) -> Self {
+        GraphQLResponse { data: None, errors: vec![GraphQLError { message, path: Vec::new() }] }
+    }
+}
+
+/// The state a query reads. Identities are only served if `dids` is given.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub blockchain: &'a Blockchain,
+    pub governance: &'a DemocraticSystem,
+    pub dids: Option<&'a DidManager>,
+}
+
+/// Runs the query of `request` against `context`.
+pub fn execute(context: Context, request: &GraphQLRequest) -> GraphQLResponse {
+    let operation = match parser::parse(&request.query, request.operation_name.as_deref()) {
+        Ok(operation) => operation,
+        Err(message) => return GraphQLResponse::failed(message),
+    };
+    let mut variables = request.variables.clone();
+    for (name, default) in &operation.variables {
+        if let (false, Some(default)) = (variables.contains_key(name), default) {
+            match to_json(default, &Map::new()) {
+                Ok(value) => variables.insert(name.clone(), value),
+                Err(message) => return GraphQLResponse::failed(message),
+            };
+        }
+    }
+    let cost = complexity(&operation.selection, &variables);
+    if cost > MAX_COMPLEXITY {
+        return GraphQLResponse::failed(format!("Query is too complex: it may resolve {} fields, at most {} are allowed", cost, MAX_COMPLEXITY));
+    }
+    let mut executor = Executor { context, variables, errors: Vec::new() };
+    let data = executor.select(&Node::Query, &operation.selection, &mut Vec::new());
+    GraphQLResponse { data: Some(data), errors: executor.errors }
+}
+
+/// An upper bound of the fields `selection` resolves: each field counts once,
+/// and the fields selected on a list count once per item it may return.
+fn complexity(selection: &[Field], variables: &Map<String, JsonValue>) -> usize {
+    selection.iter().fold(0usize, |cost, field| {
+        let items = if !LIST_FIELDS.contains(&field.name.as_str()) {
+            1
+        } else {
+            field.arguments.iter()
+                .find(|(name, _)| name == "limit")
+                .and_then(|(_, limit)| to_json(limit, variables).ok()?.as_u64())
+                .map_or(MAX_PAGE_SIZE, |limit| (limit as usize).min(MAX_PAGE_SIZE))
+        };
+        cost.saturating_add(items.saturating_mul(complexity(&field.selection, variables)).saturating_add(1))
+    })
+}
+
+fn to_json(value: &Value, variables: &Map<String, JsonValue>) -> Result<JsonValue, String> {
+    Ok(match value {
+        Value::Variable(name) => variables.get(name).cloned().unwrap_or(JsonValue::Null),
+        Value::Int(value) => (*value).into(),
+        Value::Float(value) => (*value).into(),
+        Value::String(value) | Value::Enum(value) => value.clone().into(),
+        Value::Boolean(value) => (*value).into(),
+        Value::Null => JsonValue::Null,
+        Value::List(values) => values.iter().map(|value| to_json(value, variables)).collect::<Result<_, _>>()?,
+        Value::Object(fields) => fields.iter()
+            .map(|(name, value)| Ok((name.clone(), to_json(value, variables)?)))
+            .collect::<Result<Map<_, _>, String>>()?
+            .into(),
+    })
+}
+
+/// Something with fields of its own.
+enum Node<'a> {
+    Query,
+    Block(&'a Block),
+    Transaction(&'a Transaction),
+    Receipt(&'a TransactionReceipt),
+    Event(LogEntry),
+    Account(String),
+    Proposal(&'a Proposal),
+    Vote(&'a Vote),
+    Identity(&'a DecentralizedIdentity),
+    Contract(String, &'a dyn SmartContract),
+}
+
+impl Node<'_> {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Node::Query => "Query",
+            Node::Block(_) => "Block",
+            Node::Transaction(_) => "Transaction",
+            Node::Receipt(_) => "TransactionReceipt",
+            Node::Event(_) => "Event",
+            Node::Account(_) => "Account",
+            Node::Proposal(_) => "Proposal",
+            Node::Vote(_) => "Vote",
+            Node::Identity(_) => "Identity",
+            Node::Contract(..) => "Contract",
+        }
+    }
+}
+
+/// What a field resolves to, before its subfields are selected.
+enum Output<'a> {
+    Scalar(JsonValue),
+    Object(Node<'a>),
+    List(Vec<Output<'a>>),
+}
+
+impl<'a> Output<'a> {
+    fn scalar(value: impl Serialize) -> Self {
+        Output::Scalar(serde_json::to_value(value).unwrap_or(JsonValue::Null))
+    }
+
+    fn optional(node: Option<Node<'a>>) -> Self {
+        node.map_or(Output::Scalar(JsonValue::Null), Output::Object)
+    }
+
+    fn list(nodes: impl IntoIterator<Item = Node<'a>>) -> Self {
+        Output::List(nodes.into_iter().map(Output::Object).collect())
+    }
+}
+
+struct Arguments<'f> {
+    values: Map<String, JsonValue>,
+    field: &'f str,
+}
+
+impl Arguments<'_> {
+    fn get(&self, name: &str) -> Option<&JsonValue> {
+        self.values.get(name).filter(|value| !value.is_null())
+    }
+
+    fn string(&self, name: &str) -> Result<Option<&str>, String> {
+        self.get(name).map(|value| value.as_str().ok_or_else(|| self.invalid(name, "a string"))).transpose()
+    }
+
+    fn required_string(&self, name: &str) -> Result<&str, String> {
+        self.string(name)?.ok_or_else(|| format!("Field \"{}\" needs argument \"{}\"", self.field, name))
+    }
+
+    fn count(&self, name: &str) -> Result<Option<usize>, String> {
+        self.get(name).map(|value| {
+            value.as_u64().map(|count| count as usize).ok_or_else(|| self.invalid(name, "a non-negative Int"))
+        }).transpose()
+    }
+
+    fn limit(&self) -> Result<usize, String> {
+        Ok(self.count("limit")?.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE))
+    }
+
+    fn invalid(&self, name: &str, expected: &str) -> String {
+        format!("Argument \"{}\" of field \"{}\" must be {}", name, self.field, expected)
+    }
+}
+
+struct Executor<'a> {
+    context: Context<'a>,
+    variables: Map<String, JsonValue>,
+    errors: Vec<GraphQLError>,
+}
+
+impl<'a> Executor<'a> {
+    fn select(&mut self, node: &Node<'a>, selection: &[Field], path: &mut Vec<JsonValue>) -> JsonValue {
+        let mut object = Map::new();
+        for field in selection {
+            let key = field.response_key().to_string();
+            path.push(key.clone().into());
+            let value = if field.name == "__typename" {
+                node.type_name().into()
+            } else {
+                match self.arguments(field).and_then(|arguments| self.resolve(node, &field.name, &arguments)) {
+                    Ok(output) => self.complete(output, field, path),
+                    Err(message) => self.error(message, path),
+                }
+            };
+            path.pop();
+            object.insert(key, value);
+        }
+        object.into()
+    }
+
+    fn complete(&mut self, output: Output<'a>, field: &Field, path: &mut Vec<JsonValue>) -> JsonValue {
+        match output {
+            Output::Scalar(value) if field.selection.is_empty() || value.is_null() => value,
+            Output::Scalar(_) => self.error(format!("Field \"{}\" has no subfields to select", field.name), path),
+            Output::Object(node) if field.selection.is_empty() => {
+                self.error(format!("Field \"{}\" of type {} needs a selection of subfields", field.name, node.type_name()), path)
+            }
+            Output::Object(node) => self.select(&node, &field.selection, path),
+            Output::List(items) => items.into_iter().enumerate().map(|(index, item)| {
+                path.push(index.into());
+                let value = self.complete(item, field, path);
+                path.pop();
+                value
+            }).collect(),
+        }
+    }
+
+    fn error(&mut self, message: String, path: &[JsonValue]) -> JsonValue {
+        self.errors.push(GraphQLError { message, path: path.to_vec() });
+        JsonValue::Null
+    }
+
+    fn arguments<'f>(&self, field: &'f Field) -> Result<Arguments<'f>, String> {
+        let values = field.arguments.iter()
+            .map(|(name, value)| Ok((name.clone(), to_json(value, &self.variables)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(Arguments { values, field: &field.name })
+    }
+
+    fn resolve(&self, node: &Node<'a>, field: &str, arguments: &Arguments) -> Result<Output<'a>, String> {
+        let blockchain = self.context.blockchain;
+        let governance = self.context.governance;
+        let output = match (node, field) {
+            (Node::Query, "block") => {
+                let block = match (arguments.count("index")?, arguments.string("hash")?) {
+                    (Some(index), _) => blockchain.chain.get(index),
+                    (None, Some(hash)) => blockchain.chain.iter().find(|block| block.hash == hash),
+                    (None, None) => return Err("Field \"block\" needs argument \"index\" or \"hash\"".to_string()),
+                };
+                Output::optional(block.map(Node::Block))
+            }
+            (Node::Query, "blocks") => {
+                let from = arguments.count("from")?.unwrap_or(0);
+                Output::list(blockchain.chain.iter().skip(from).take(arguments.limit()?).map(Node::Block))
+            }
+            (Node::Query, "latestBlock") => Output::optional(blockchain.chain.last().map(Node::Block)),
+            (Node::Query, "transaction") => Output::optional(self.find_transaction(arguments.required_string("hash")?).map(Node::Transaction)),
+            (Node::Query, "account") => Output::Object(Node::Account(arguments.required_string("address")?.to_string())),
+            (Node::Query, "proposals") => {
+                let status = arguments.get("status");
+                let mut proposals = governance.list_proposals();
+                proposals.retain(|proposal| status.is_none_or(|status| serde_json::to_value(&proposal.status).ok().as_ref() == Some(status)));
+                proposals.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+                Output::list(proposals.into_iter().map(Node::Proposal))
+            }
+            (Node::Query, "proposal") => Output::optional(governance.get_proposal(arguments.required_string("id")?).map(Node::Proposal)),
+            (Node::Query, "identity") => Output::optional(self.identity(arguments.required_string("did")?)?.map(Node::Identity)),
+            (Node::Query, "contracts") => Output::List(blockchain.execution_environment.registry.ids().into_iter()
+                .filter_map(|id| self.contract(&id))
+                .map(Output::Object)
+                .collect()),
+            (Node::Query, "contract") => Output::optional(self.contract(arguments.required_string("id")?)),
+
+            (Node::Block(block), "index") => Output::scalar(block.index),
+            (Node::Block(block), "hash") => Output::scalar(&block.hash),
+            (Node::Block(block), "previousHash") => Output::scalar(&block.previous_hash),
+            (Node::Block(block), "timestamp") => Output::scalar(block.timestamp),
+            (Node::Block(block), "nonce") => Output::scalar(block.nonce),
+            (Node::Block(block), "gasUsed") => Output::scalar(block.gas_used),
+            (Node::Block(block), "protocolVersion") => Output::scalar(block.protocol_version),
+            (Node::Block(block), "proposer") => Output::scalar(Some(&block.proposer).filter(|proposer| !proposer.is_empty())),
+            (Node::Block(block), "approved") => Output::scalar(blockchain.is_block_approved(&block.hash)),
+            (Node::Block(block), "transactionCount") => Output::scalar(block.transactions.len()),
+            (Node::Block(block), "transactions") => Output::list(block.transactions.iter().map(Node::Transaction)),
+
+            (Node::Transaction(transaction), "hash") => Output::scalar(transaction.hash()),
+            (Node::Transaction(transaction), "from") => Output::Object(Node::Account(transaction.from.clone())),
+            (Node::Transaction(transaction), "to") => Output::Object(Node::Account(transaction.to.clone())),
+            (Node::Transaction(transaction), "amount") => Output::scalar(transaction.amount),
+            (Node::Transaction(transaction), "currency") => Output::scalar(&transaction.currency_type),
+            (Node::Transaction(transaction), "gasLimit") => Output::scalar(transaction.gas_limit),
+            (Node::Transaction(transaction), "gasPrice") => Output::scalar(transaction.gas_price),
+            (Node::Transaction(transaction), "validUntil") => Output::scalar(transaction.valid_until),
+            (Node::Transaction(transaction), "outputs") => Output::scalar(&transaction.outputs),
+            (Node::Transaction(transaction), "swap") => Output::scalar(&transaction.swap),
+            (Node::Transaction(transaction), "stream") => Output::scalar(&transaction.stream),
+            (Node::Transaction(transaction), "standingOrder") => Output::scalar(&transaction.standing_order),
+            (Node::Transaction(transaction), "allowance") => Output::scalar(&transaction.allowance),
+            (Node::Transaction(transaction), "cosignatures") => Output::scalar(transaction.cosignatures.len()),
+            (Node::Transaction(transaction), "validation") => Output::scalar(&transaction.validation),
+            (Node::Transaction(transaction), "organization") => Output::scalar(&transaction.organization),
+            (Node::Transaction(transaction), "settlement") => Output::scalar(&transaction.settlement),
+            (Node::Transaction(transaction), "dividend") => Output::scalar(&transaction.dividend),
+            (Node::Transaction(transaction), "rentFor") => Output::scalar(&transaction.rent_for),
+            (Node::Transaction(transaction), "contractCreation") => Output::scalar(&transaction.contract_creation),
+            (Node::Transaction(transaction), "vesting") => Output::scalar(&transaction.vesting),
+            (Node::Transaction(transaction), "crowdfund") => Output::scalar(&transaction.crowdfund),
+            (Node::Transaction(transaction), "agreement") => Output::scalar(&transaction.agreement),
+            (Node::Transaction(transaction), "market") => Output::scalar(&transaction.market),
+            (Node::Transaction(transaction), "networkId") => Output::scalar(&transaction.network_id),
+            (Node::Transaction(transaction), "contract") => Output::optional(transaction.smart_contract_id.as_deref().and_then(|id| self.contract(id))),
+            (Node::Transaction(transaction), "receipt") => Output::optional(blockchain.get_transaction_receipt(&transaction.hash()).map(Node::Receipt)),
+            (Node::Transaction(transaction), "block") => Output::optional(blockchain.get_transaction_receipt(&transaction.hash())
+                .and_then(|receipt| blockchain.chain.get(receipt.block_index as usize))
+                .map(Node::Block)),
+
+            (Node::Receipt(receipt), "transactionHash") => Output::scalar(&receipt.transaction_hash),
+            (Node::Receipt(receipt), "blockIndex") => Output::scalar(receipt.block_index),
+            (Node::Receipt(receipt), "blockHash") => Output::scalar(&receipt.block_hash),
+            (Node::Receipt(receipt), "success") => Output::scalar(receipt.is_success()),
+            (Node::Receipt(receipt), "status") => Output::scalar(&receipt.status),
+            (Node::Receipt(receipt), "gasUsed") => Output::scalar(receipt.gas_used),
+            (Node::Receipt(receipt), "events") => Output::list(receipt.events.iter().map(|event| Node::Event(LogEntry {
+                block_index: receipt.block_index,
+                transaction_hash: receipt.transaction_hash.clone(),
+                event: event.clone(),
+            }))),
+            (Node::Receipt(receipt), "balanceChanges") => Output::scalar(&receipt.balance_changes),
+            (Node::Receipt(receipt), "contractAddress") => Output::scalar(&receipt.contract_address),
+
+            (Node::Event(entry), "blockIndex") => Output::scalar(entry.block_index),
+            (Node::Event(entry), "transactionHash") => Output::scalar(&entry.transaction_hash),
+            (Node::Event(entry), "contractId") => Output::scalar(&entry.event.contract_id),
+            (Node::Event(entry), "name") => Output::scalar(&entry.event.name),
+            (Node::Event(entry), "data") => Output::scalar(&entry.event.data),
+            (Node::Event(entry), "contract") => Output::optional(self.contract(&entry.event.contract_id)),
+            (Node::Event(entry), "transaction") => Output::optional(self.find_transaction(&entry.transaction_hash).map(Node::Transaction)),
+
+            (Node::Account(address), "address") => Output::scalar(address),
+            (Node::Account(address), "balance") => match arguments.get("currency") {
+                Some(currency) => {
+                    let currency: CurrencyType = serde_json::from_value(currency.clone())
+                        .map_err(|_| arguments.invalid("currency", "a currency type"))?;
+                    Output::scalar(blockchain.get_currency_balance(address, &currency))
+                }
+                None => Output::scalar(blockchain.get_balance(address)),
+            },
+            (Node::Account(address), "reputation") => Output::scalar(blockchain.consensus.get_reputation(address)),
+            (Node::Account(address), "isValidator") => Output::scalar(blockchain.consensus.is_validator(address)),
+            (Node::Account(address), "stake") => Output::scalar(blockchain.consensus.stakes.info(address)),
+            (Node::Account(address), "identity") => Output::optional(self.identity(address)?.map(Node::Identity)),
+            (Node::Account(address), "transactions") => Output::list(blockchain.chain.iter().rev()
+                .flat_map(|block| block.transactions.iter().rev())
+                .filter(|transaction| transaction.transfers().iter().any(|transfer| transfer.from == *address || transfer.to == *address))
+                .take(arguments.limit()?)
+                .map(Node::Transaction)),
+            (Node::Account(address), "proposals") => {
+                let mut proposals = governance.list_proposals();
+                proposals.retain(|proposal| proposal.proposer == *address);
+                proposals.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+                Output::list(proposals.into_iter().map(Node::Proposal))
+            }
+            (Node::Account(address), "votes") => {
+                let mut votes: Vec<&Vote> = governance.list_proposals().into_iter()
+                    .filter_map(|proposal| governance.get_votes(&proposal.id))
+                    .flatten()
+                    .filter(|vote| vote.voter == *address)
+                    .collect();
+                votes.sort_by_key(|vote| vote.timestamp);
+                Output::list(votes.into_iter().map(Node::Vote))
+            }
+
+            (Node::Proposal(proposal), "id") => Output::scalar(&proposal.id),
+            (Node::Proposal(proposal), "title") => Output::scalar(&proposal.title),
+            (Node::Proposal(proposal), "description") => Output::scalar(&proposal.description),
+            (Node::Proposal(proposal), "proposer") => Output::Object(Node::Account(proposal.proposer.clone())),
+            (Node::Proposal(proposal), "status") => Output::scalar(&proposal.status),
+            (Node::Proposal(proposal), "proposalType") => Output::scalar(&proposal.proposal_type),
+            (Node::Proposal(proposal), "category") => Output::scalar(&proposal.category),
+            (Node::Proposal(proposal), "createdAt") => Output::scalar(proposal.created_at),
+            (Node::Proposal(proposal), "votingEndsAt") => Output::scalar(proposal.voting_ends_at),
+            (Node::Proposal(proposal), "requiredQuorum") => Output::scalar(proposal.required_quorum),
+            (Node::Proposal(proposal), "executionTimestamp") => Output::scalar(proposal.execution_timestamp),
+            (Node::Proposal(proposal), "attachments") => Output::scalar(&proposal.attachments),
+            (Node::Proposal(proposal), "votes") => Output::list(governance.get_votes(&proposal.id).into_iter().flatten().map(Node::Vote)),
+
+            (Node::Vote(vote), "voter") => Output::Object(Node::Account(vote.voter.clone())),
+            (Node::Vote(vote), "proposal") => Output::optional(governance.get_proposal(&vote.proposal_id).map(Node::Proposal)),
+            (Node::Vote(vote), "inFavor") => Output::scalar(vote.in_favor),
+            (Node::Vote(vote), "weight") => Output::scalar(vote.weight),
+            (Node::Vote(vote), "timestamp") => Output::scalar(vote.timestamp),
+
+            (Node::Identity(identity), "id") => Output::scalar(&identity.id),
+            (Node::Identity(identity), "publicKey") => Output::scalar(hex::encode(identity.public_key.as_bytes())),
+            (Node::Identity(identity), "createdAt") => Output::scalar(identity.created_at),
+            (Node::Identity(identity), "attributes") => Output::scalar(&identity.attributes),
+            (Node::Identity(identity), "revoked") => Output::scalar(blockchain.revocation_registry.is_did_revoked(&identity.id)),
+            (Node::Identity(identity), "account") => Output::Object(Node::Account(identity.id.clone())),
+
+            (Node::Contract(id, _), "id") => Output::scalar(id),
+            (Node::Contract(_, contract), "definition") => Output::scalar(contract),
+            (Node::Contract(id, _), "events") => {
+                let filter = LogFilter { address: Some(id.clone()), ..Default::default() };
+                let mut logs = blockchain.get_logs(&filter);
+                logs.retain(|entry| entry.event.contract_id == *id);
+                let skip = logs.len().saturating_sub(arguments.limit()?);
+                Output::list(logs.into_iter().skip(skip).map(Node::Event))
+            }
+
+            (node, field) => return Err(format!("Cannot query field \"{}\" on type \"{}\"", field, node.type_name())),
+        };
+        Ok(output)
+    }
+
+    fn find_transaction(&self, hash: &str) -> Option<&'a Transaction> {
+        let blockchain = self.context.blockchain;
+        match blockchain.get_transaction_receipt(hash) {
+            Some(receipt) => blockchain.chain.get(receipt.block_index as usize)?.transactions.iter().find(|transaction| transaction.hash() == hash),
+            None => blockchain.chain.iter().flat_map(|block| &block.transactions).find(|transaction| transaction.hash() == hash),
+        }
+    }
+
+    fn identity(&self, did: &str) -> Result<Option<&'a DecentralizedIdentity>, String> {
+        let dids = self.context.dids.ok_or("Identities are not served by this node")?;
+        Ok(dids.get_did(did))
+    }
+
+    fn contract(&self, id: &str) -> Option<Node<'a>> {
+        let contract = self.context.blockchain.execution_environment.registry.get(id)?;
+        Some(Node::Contract(id.to_string(), contract))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::governance::democracy::{ProposalCategory, ProposalType};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_nested_query_and_field_errors() {
+        let mut blockchain = Blockchain::new();
+        blockchain.consensus.add_member("Alice".to_string(), true);
+        let mut governance = DemocraticSystem::new();
+        let mut dids = DidManager::new();
+        let (bob, _) = DecentralizedIdentity::new(HashMap::new());
+        blockchain.consensus.add_member(bob.id.clone(), false);
+        dids.add_did(bob.clone());
+
+        let proposal_id = governance.create_proposal(
+            "Buy a tractor".to_string(), "For the farm".to_string(), "Alice".to_string(), chrono::Duration::days(7),
+            ProposalType::EconomicAdjustment, ProposalCategory::Economic, 0.5, None,
+        ).unwrap();
+        governance.vote(bob.id.clone(), proposal_id.clone(), true, 1.0).unwrap();
+        governance.vote("Alice".to_string(), proposal_id.clone(), false, 1.0).unwrap();
+
+        let context = Context { blockchain: &blockchain, governance: &governance, dids: Some(&dids) };
+        let query = r#"
+            query Votes($id: String!) {
+                proposal(id: $id) {
+                    title
+                    votes { inFavor who: voter { isValidator reputation identity { id } } }
+                }
+                missing: proposal(id: "none") { title }
+                account(address: "Alice") { proposals { __typename } colour }
+            }
+        "#;
+        let response = execute(context, &GraphQLRequest::new(query).with_variable("id", proposal_id.clone().into()));
+        let data = response.data.unwrap();
+        let votes = &data["proposal"]["votes"];
+        assert_eq!(data["proposal"]["title"], "Buy a tractor");
+        assert_eq!(votes[0]["inFavor"], true);
+        assert_eq!(votes[0]["who"]["identity"]["id"], bob.id.as_str());
+        assert_eq!(votes[0]["who"]["isValidator"], false);
+        assert!(votes[0]["who"]["reputation"].is_number());
+        assert!(votes[1]["who"]["identity"].is_null(), "Alice has no DID");
+        assert!(data["missing"].is_null());
+        assert_eq!(data["account"]["proposals"][0]["__typename"], "Proposal");
+
+        assert!(data["account"]["colour"].is_null());
+        assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+        assert_eq!(response.errors[0].path, vec![JsonValue::from("account"), "colour".into()]);
+
+        let response = execute(context, &GraphQLRequest::new("mutation { vote }"));
+        assert!(response.data.is_none() && response.errors[0].message.contains("Only queries"));
+    }
+
+    #[test]
+    fn test_deep_and_costly_queries_are_refused() {
+        let blockchain = Blockchain::new();
+        let governance = DemocraticSystem::new();
+        let context = Context { blockchain: &blockchain, governance: &governance, dids: None };
+
+        let deep = format!("{{ {} hash {} }}", "latestBlock { transactions { block {".repeat(4), "} } }".repeat(4));
+        let response = execute(context, &GraphQLRequest::new(&deep));
+        assert!(response.data.is_none() && response.errors[0].message.contains("nested deeper"), "{:?}", response.errors);
+        let nested_value = format!("{{ account(address: {}\"a\"{}) {{ address }} }}", "[".repeat(20), "]".repeat(20));
+        assert!(execute(context, &GraphQLRequest::new(&nested_value)).errors[0].message.contains("nested deeper"));
+
+        let costly = "{ blocks(limit: 100) { transactions { from { transactions(limit: $limit) { hash } } } } }";
+        let request = GraphQLRequest::new(&format!("query Costly($limit: Int) {}", costly)).with_variable("limit", 100.into());
+        let response = execute(context, &request);
+        assert!(response.data.is_none() && response.errors[0].message.contains("too complex"), "{:?}", response.errors);
+
+        let response = execute(context, &GraphQLRequest::new("{ blocks(limit: 5) { transactions { hash } } }"));
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+}
@@ -0,0 +1,327 @@
+// src/api/graphql/parser.rs
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Deepest nesting of selection sets, lists and input objects a query may
+/// have, so that it cannot exhaust the stack of the parser or executor.
+pub const MAX_DEPTH: usize = 12;
+
+/// A field of a selection set, with the fields selected on its result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    /// The key the field's result is reported under.
+    pub fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Variable(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+/// A query operation: its variables, with their default values, and the
+/// fields it selects on the root type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub name: Option<String>,
+    pub variables: Vec<(String, Option<Value>)>,
+    pub selection: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {
+                chars.next();
+            }
+            '#' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '!' | '$' | '=' | '@' => {
+                chars.next();
+                tokens.push(Token::Punctuator(c));
+            }
+            '.' => {
+                if chars.by_ref().take(3).collect::<String>() != "..." {
+                    return Err("Expected ...".to_string());
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::String(string(&mut chars)?));
+            }
+            c if c == '-' || c.is_ascii_digit() => tokens.push(number(&mut chars)?),
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("Unexpected character {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None | Some('\n') => return Err("Unterminated string".to_string()),
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32)
+                        .ok_or_else(|| format!("Invalid escape \\u{}", code))?;
+                    value.push(c);
+                }
+                Some(c @ ('"' | '\\' | '/')) => value.push(c),
+                c => return Err(format!("Invalid escape {:?}", c)),
+            },
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Result<Token, String> {
+    let mut text = String::new();
+    while let Some(c) = chars.next_if(|&c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+        text.push(c);
+    }
+    if text.contains(['.', 'e', 'E']) {
+        text.parse().map(Token::Float).map_err(|_| format!("Invalid number {}", text))
+    } else {
+        text.parse().map(Token::Int).map_err(|_| format!("Invalid number {}", text))
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("Unexpected end of query")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Opens one more level of nesting, failing past `MAX_DEPTH`.
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("Query is nested deeper than {} levels", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn is_punctuator(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punctuator(c))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punctuator(found) if found == c => Ok(()),
+            found => Err(format!("Expected {:?}, found {:?}", c, found)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            found => Err(format!("Expected a name, found {:?}", found)),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Operation, String> {
+        let mut operation = Operation { name: None, variables: Vec::new(), selection: Vec::new() };
+        if let Some(Token::Name(keyword)) = self.peek() {
+            if keyword != "query" {
+                return Err(format!("Only queries are supported, not {}", keyword));
+            }
+            self.position += 1;
+            if let Some(Token::Name(_)) = self.peek() {
+                operation.name = Some(self.name()?);
+            }
+            if self.is_punctuator('(') {
+                operation.variables = self.variable_definitions()?;
+            }
+        }
+        operation.selection = self.selection_set()?;
+        Ok(operation)
+    }
+
+    fn variable_definitions(&mut self) -> Result<Vec<(String, Option<Value>)>, String> {
+        self.expect('(')?;
+        let mut variables = Vec::new();
+        while !self.is_punctuator(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.skip_type()?;
+            let default = if self.is_punctuator('=') {
+                self.position += 1;
+                Some(self.value()?)
+            } else {
+                None
+            };
+            variables.push((name, default));
+        }
+        self.expect(')')?;
+        Ok(variables)
+    }
+
+    /// Variable types are not checked; arguments are checked where used.
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.is_punctuator('[') {
+            self.position += 1;
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        if self.is_punctuator('!') {
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        self.nest()?;
+        let mut fields = Vec::new();
+        while !self.is_punctuator('}') {
+            if self.peek() == Some(&Token::Spread) {
+                return Err("Fragments are not supported".to_string());
+            }
+            fields.push(self.field()?);
+        }
+        self.expect('}')?;
+        self.depth -= 1;
+        if fields.is_empty() {
+            return Err("Empty selection set".to_string());
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, String> {
+        let mut name = self.name()?;
+        let mut alias = None;
+        if self.is_punctuator(':') {
+            self.position += 1;
+            alias = Some(std::mem::replace(&mut name, self.name()?));
+        }
+        let mut arguments = Vec::new();
+        if self.is_punctuator('(') {
+            self.position += 1;
+            while !self.is_punctuator(')') {
+                let argument = self.name()?;
+                self.expect(':')?;
+                arguments.push((argument, self.value()?));
+            }
+            self.expect(')')?;
+        }
+        if self.is_punctuator('@') {
+            return Err("Directives are not supported".to_string());
+        }
+        let selection = if self.is_punctuator('{') { self.selection_set()? } else { Vec::new() };
+        Ok(Field { alias, name, arguments, selection })
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        Ok(match self.next()? {
+            Token::Punctuator('$') => Value::Variable(self.name()?),
+            Token::Int(value) => Value::Int(value),
+            Token::Float(value) => Value::Float(value),
+            Token::String(value) => Value::String(value),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                "null" => Value::Null,
+                _ => Value::Enum(name),
+            },
+            Token::Punctuator('[') => {
+                self.nest()?;
+                let mut values = Vec::new();
+                while !self.is_punctuator(']') {
+                    values.push(self.value()?);
+                }
+                self.position += 1;
+                self.depth -= 1;
+                Value::List(values)
+            }
+            Token::Punctuator('{') => {
+                self.nest()?;
+                let mut fields = Vec::new();
+                while !self.is_punctuator('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                }
+                self.position += 1;
+                self.depth -= 1;
+                Value::Object(fields)
+            }
+            found => return Err(format!("Expected a value, found {:?}", found)),
+        })
+    }
+}
+
+/// Parses a document of query operations, returning the one named
+/// `operation_name`, or the only one if no name is given.
+pub fn parse(source: &str, operation_name: Option<&str>) -> Result<Operation, String> {
+    let mut parser = Parser { tokens: tokenize(source)?, position: 0, depth: 0 };
+    let mut operations = Vec::new();
+    while parser.peek().is_some() {
+        operations.push(parser.operation()?);
+    }
+    match operation_name {
+        Some(name) => operations.into_iter()
+            .find(|operation| operation.name.as_deref() == Some(name))
+            .ok_or_else(|| format!("No operation named {}", name)),
+        None if operations.len() == 1 => Ok(operations.remove(0)),
+        None => Err("An operation name is needed to pick among several operations".to_string()),
+    }
+}
@@ -0,0 +1,789 @@
+// src/api/grpc.rs
+//! The node control service of `proto/icn/node/v1/node.proto`, served over
+//! gRPC with the types generated in the `icn-client` crate.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::Stream;
+use icn_client::tonic::{self, Request, Response, Status};
+use icn_client::v1 as proto;
+use icn_client::v1::node_control_server::{NodeControl, NodeControlServer};
+use tokio::net::TcpListener;
+use crate::blockchain::{AgreementAction, AllowanceAction, Block, Cosignature, CrowdfundAction, DividendAction, Enactment, MarketAction, OrganizationAction, Resource, Role, NominationAction, ReceiptStatus, SettlementAction, StandingOrderAction, Transaction, StreamAction, SwapLeg, TransactionReceipt, TransferOutput, ValidUntil, ValidationAction, VestingAction, VestingSchedule};
+use crate::bridge::{BridgeAction, BridgeProof, BridgeTransfer, Direction};
+use crate::consensus::StakeAction;
+use crate::currency::CurrencyType;
+use crate::governance::ProposalAction;
+use crate::identity::RevocationAction;
+use crate::smart_contract::{CodeFormat, ContractCode, ContractCreation};
+use crate::error::{Error, Result};
+use super::{ApiLayer, ApiResponse};
+
+/// How often `StreamBlocks` looks for new blocks.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct GrpcService {
+    api: Arc<ApiLayer>,
+    poll_interval: Duration,
+}
+
+impl GrpcService {
+    pub fn new(api: Arc<ApiLayer>) -> Self {
+        GrpcService { api, poll_interval: BLOCK_POLL_INTERVAL }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn into_server(self) -> NodeControlServer<Self> {
+        NodeControlServer::new(self)
+    }
+
+    /// Serves node control on `listener` until the task is dropped.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_server())
+            .serve_with_incoming(tonic::transport::server::TcpIncoming::from(listener))
+            .await
+            .map_err(|e| Error::NetworkError(format!("gRPC server failed: {}", e)))
+    }
+}
+
+type BlockStream = Pin<Box<dyn Stream<Item = std::result::Result<proto::Block, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl NodeControl for GrpcService {
+    async fn submit_transaction(&self, request: Request<proto::SubmitTransactionRequest>) -> std::result::Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let transaction = request.into_inner().transaction
+            .ok_or_else(|| Status::invalid_argument("No transaction given"))
+            .and_then(transaction_from_proto)?;
+        let transaction_hash = transaction.hash();
+        into_status(self.api.submit_transaction(transaction).await)?;
+        Ok(Response::new(proto::SubmitTransactionResponse { transaction_hash }))
+    }
+
+    async fn get_chain_info(&self, _request: Request<proto::GetChainInfoRequest>) -> std::result::Result<Response<proto::ChainInfo>, Status> {
+        let blockchain = self.api.blockchain.read().await;
+        let latest = blockchain.chain.last();
+        Ok(Response::new(proto::ChainInfo {
+            block_count: blockchain.chain.len() as u64,
+            latest_block_hash: latest.map(|block| block.hash.clone()).unwrap_or_default(),
+            pending_transactions: blockchain.pending_transactions.len() as u64,
+            protocol_version: latest.map_or(0, |block| block.protocol_version),
+        }))
+    }
+
+    async fn get_block(&self, request: Request<proto::GetBlockRequest>) -> std::result::Result<Response<proto::Block>, Status> {
+        let blockchain = self.api.blockchain.read().await;
+        let block = match request.into_inner().block {
+            Some(proto::get_block_request::Block::Index(index)) => blockchain.chain.get(index as usize),
+            Some(proto::get_block_request::Block::Hash(hash)) => blockchain.chain.iter().find(|block| block.hash == hash),
+            None => return Err(Status::invalid_argument("Give a block index or hash")),
+        };
+        block.map(|block| Response::new(block_to_proto(block)))
+            .ok_or_else(|| Status::not_found("Block not found"))
+    }
+
+    async fn get_transaction_receipt(&self, request: Request<proto::GetTransactionReceiptRequest>) -> std::result::Result<Response<proto::TransactionReceipt>, Status> {
+        let receipt = into_status(self.api.get_transaction_receipt(&request.into_inner().transaction_hash).await)?;
+        Ok(Response::new(receipt_to_proto(&receipt)))
+    }
+
+    async fn get_balance(&self, request: Request<proto::GetBalanceRequest>) -> std::result::Result<Response<proto::GetBalanceResponse>, Status> {
+        let request = request.into_inner();
+        let blockchain = self.api.blockchain.read().await;
+        let balance = match request.currency {
+            Some(currency) => blockchain.get_currency_balance(&request.address, &currency_from_proto(currency)?),
+            None => blockchain.get_balance(&request.address),
+        };
+        Ok(Response::new(proto::GetBalanceResponse { balance }))
+    }
+
+    async fn list_balances(&self, request: Request<proto::ListBalancesRequest>) -> std::result::Result<Response<proto::ListBalancesResponse>, Status> {
+        let address = request.into_inner().address;
+        let mut balances: Vec<proto::Balance> = self.api.blockchain.read().await.get_balances(&address).iter()
+            .map(|(currency, balance)| proto::Balance { currency: Some(currency_to_proto(currency)), balance: *balance, shard_id: None })
+            .collect();
+        if let Some(sharding) = &self.api.sharding {
+            let sharding = sharding.read().unwrap();
+            let shard_id = sharding.get_shard_for_address(&address);
+            let shard_balances = sharding.get_balances(&address).map_err(|e| Status::internal(e.to_string()))?;
+            balances.extend(shard_balances.iter().map(|(currency, balance)| proto::Balance {
+                currency: Some(currency_to_proto(currency)),
+                balance: *balance,
+                shard_id: Some(shard_id),
+            }));
+        }
+        Ok(Response::new(proto::ListBalancesResponse { balances }))
+    }
+
+    async fn estimate_fees(&self, request: Request<proto::EstimateFeesRequest>) -> std::result::Result<Response<proto::EstimateFeesResponse>, Status> {
+        let currencies = request.into_inner().currencies.into_iter().map(currency_from_proto).collect::<std::result::Result<Vec<_>, _>>()?;
+        let estimates = into_status(self.api.estimate_fees(&currencies).await)?.into_iter()
+            .map(|estimate| proto::FeeEstimate {
+                currency: Some(currency_to_proto(&estimate.currency_type)),
+                low: estimate.low,
+                standard: estimate.standard,
+                fast: estimate.fast,
+                block_fullness: estimate.block_fullness,
+                pending: estimate.pending as u64,
+            })
+            .collect();
+        Ok(Response::new(proto::EstimateFeesResponse { estimates }))
+    }
+
+    type StreamBlocksStream = BlockStream;
+
+    async fn stream_blocks(&self, request: Request<proto::StreamBlocksRequest>) -> std::result::Result<Response<BlockStream>, Status> {
+        let api = self.api.clone();
+        let poll_interval = self.poll_interval;
+        let from_index = request.into_inner().from_index;
+        let stream = futures::stream::unfold(from_index, move |next| {
+            let api = api.clone();
+            async move {
+                loop {
+                    if let Some(block) = api.blockchain.read().await.chain.get(next as usize) {
+                        return Some((Ok(block_to_proto(block)), next + 1));
+                    }
+                    tokio::time::sleep(poll_interval).await;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+fn into_status<T>(response: ApiResponse<T>) -> std::result::Result<T, Status> {
+    if let Some(data) = response.data {
+        return Ok(data);
+    }
+    let message = response.error.unwrap_or_default();
+    Err(match response.error_code {
+        Some(900) => Status::not_found(message),
+        Some(901) => Status::unavailable(message),
+        Some(1000) => Status::internal(message),
+        _ => Status::failed_precondition(message),
+    })
+}
+
+const STANDARD_CURRENCIES: [(proto::StandardCurrency, CurrencyType); 10] = [
+    (proto::StandardCurrency::BasicNeeds, CurrencyType::BasicNeeds),
+    (proto::StandardCurrency::Education, CurrencyType::Education),
+    (proto::StandardCurrency::Environmental, CurrencyType::Environmental),
+    (proto::StandardCurrency::Community, CurrencyType::Community),
+    (proto::StandardCurrency::Volunteer, CurrencyType::Volunteer),
+    (proto::StandardCurrency::Storage, CurrencyType::Storage),
+    (proto::StandardCurrency::Processing, CurrencyType::Processing),
+    (proto::StandardCurrency::Energy, CurrencyType::Energy),
+    (proto::StandardCurrency::Luxury, CurrencyType::Luxury),
+    (proto::StandardCurrency::Service, CurrencyType::Service),
+];
+
+pub fn currency_to_proto(currency: &CurrencyType) -> proto::Currency {
+    use proto::currency::Kind;
+    let kind = match currency {
+        CurrencyType::Custom(name) => Kind::Custom(name.clone()),
+        CurrencyType::AssetToken(id) => Kind::AssetToken(id.clone()),
+        CurrencyType::Bond(id) => Kind::Bond(id.clone()),
+        standard => {
+            let (kind, _) = STANDARD_CURRENCIES.iter().find(|(_, currency)| currency == standard)
+                .expect("every other currency type is standard");
+            Kind::Standard(*kind as i32)
+        }
+    };
+    proto::Currency { kind: Some(kind) }
+}
+
+pub fn currency_from_proto(currency: proto::Currency) -> std::result::Result<CurrencyType, Status> {
+    use proto::currency::Kind;
+    match currency.kind {
+        Some(Kind::Custom(name)) => Ok(CurrencyType::Custom(name)),
+        Some(Kind::AssetToken(id)) => Ok(CurrencyType::AssetToken(id)),
+        Some(Kind::Bond(id)) => Ok(CurrencyType::Bond(id)),
+        Some(Kind::Standard(kind)) => STANDARD_CURRENCIES.iter()
+            .find(|(standard, _)| *standard as i32 == kind)
+            .map(|(_, currency)| currency.clone())
+            .ok_or_else(|| Status::invalid_argument(format!("Unknown currency {}", kind))),
+        None => Err(Status::invalid_argument("No currency given")),
+    }
+}
+
+fn resource_order(resource: Resource, quantity: f64, unit_price: f64) -> proto::ResourceOrder {
+    use proto::resource_order::Resource as Kind;
+    let resource = match resource {
+        Resource::Storage => Kind::Storage,
+        Resource::Compute => Kind::Compute,
+        Resource::Energy => Kind::Energy,
+    };
+    proto::ResourceOrder { resource: resource as i32, quantity, unit_price }
+}
+
+fn resource_from_proto(resource: i32) -> std::result::Result<Resource, Status> {
+    use proto::resource_order::Resource as Kind;
+    match Kind::try_from(resource) {
+        Ok(Kind::Storage) => Ok(Resource::Storage),
+        Ok(Kind::Compute) => Ok(Resource::Compute),
+        Ok(Kind::Energy) => Ok(Resource::Energy),
+        _ => Err(Status::invalid_argument(format!("Unknown resource {}", resource))),
+    }
+}
+
+pub fn transaction_to_proto(transaction: &Transaction) -> proto::Transaction {
+    proto::Transaction {
+        from: transaction.from.clone(),
+        to: transaction.to.clone(),
+        amount: transaction.amount,
+        currency: Some(currency_to_proto(&transaction.currency_type)),
+        gas_limit: transaction.gas_limit,
+        gas_price: transaction.gas_price,
+        smart_contract_id: transaction.smart_contract_id.clone(),
+        signature: transaction.signature.clone().unwrap_or_default(),
+        public_key: transaction.public_key.clone().unwrap_or_default(),
+        nomination: transaction.nomination.as_ref().map(|nomination| match nomination {
+            NominationAction::Nominate { validator } => proto::Nomination { action: proto::nomination::Action::Nominate as i32, validator: validator.clone(), amount: 0.0 },
+            NominationAction::Withdraw { validator, amount } => proto::Nomination { action: proto::nomination::Action::Withdraw as i32, validator: validator.clone(), amount: *amount },
+        }),
+        upgrade_signal: transaction.upgrade_signal.clone(),
+        valid_until: transaction.valid_until.map(|valid_until| match valid_until {
+            ValidUntil::Height(height) => proto::transaction::ValidUntil::ValidUntilHeight(height),
+            ValidUntil::Timestamp(timestamp) => proto::transaction::ValidUntil::ValidUntilTimestamp(timestamp),
+        }),
+        outputs: transaction.outputs.iter().map(|output| proto::TransferOutput {
+            to: output.to.clone(),
+            amount: output.amount,
+            currency: Some(currency_to_proto(&output.currency_type)),
+        }).collect(),
+        swap: transaction.swap.as_ref().map(|swap| proto::SwapLeg {
+            amount: swap.amount,
+            currency: Some(currency_to_proto(&swap.currency_type)),
+            signature: swap.signature.clone().unwrap_or_default(),
+            public_key: swap.public_key.clone().unwrap_or_default(),
+        }),
+        stream: transaction.stream.as_ref().map(|stream| proto::StreamAction {
+            action: Some(match stream {
+                StreamAction::Open { recipient, rate } => proto::stream_action::Action::Open(proto::OpenStream { recipient: recipient.clone(), rate: *rate }),
+                StreamAction::Withdraw { stream_id } => proto::stream_action::Action::Withdraw(stream_id.clone()),
+                StreamAction::Cancel { stream_id } => proto::stream_action::Action::Cancel(stream_id.clone()),
+            }),
+        }),
+        standing_order: transaction.standing_order.as_ref().map(|order| proto::StandingOrderAction {
+            action: Some(match order {
+                StandingOrderAction::Create { recipient, amount, interval, ends_at } => proto::standing_order_action::Action::Create(proto::CreateStandingOrder {
+                    recipient: recipient.clone(),
+                    amount: *amount,
+                    interval: *interval,
+                    ends_at: *ends_at,
+                }),
+                StandingOrderAction::Cancel { order_id } => proto::standing_order_action::Action::Cancel(order_id.clone()),
+                StandingOrderAction::Execute { order_id, sequence } => proto::standing_order_action::Action::Execute(proto::ExecuteStandingOrder { order_id: order_id.clone(), sequence: *sequence }),
+            }),
+        }),
+        allowance: transaction.allowance.as_ref().map(|allowance| proto::AllowanceAction {
+            action: Some(match allowance {
+                AllowanceAction::Grant { spender, limit, period } => proto::allowance_action::Action::Grant(proto::GrantAllowance {
+                    spender: spender.clone(),
+                    limit: *limit,
+                    period: *period,
+                }),
+                AllowanceAction::Revoke { spender } => proto::allowance_action::Action::Revoke(spender.clone()),
+                AllowanceAction::Spend { spender } => proto::allowance_action::Action::Spend(spender.clone()),
+            }),
+        }),
+        cosignatures: transaction.cosignatures.iter().map(|cosignature| proto::Cosignature {
+            public_key: cosignature.public_key.clone(),
+            signature: cosignature.signature.clone(),
+        }).collect(),
+        validation: transaction.validation.as_ref().map(|validation| proto::ValidationAction {
+            action: Some(match validation {
+                ValidationAction::Designate { contract_id } => proto::validation_action::Action::Designate(contract_id.clone()),
+                ValidationAction::Clear => proto::validation_action::Action::Clear(true),
+            }),
+        }),
+        organization: transaction.organization.as_ref().map(|organization| proto::OrganizationAction {
+            action: Some(match organization {
+                OrganizationAction::Create { name } => proto::organization_action::Action::Create(name.clone()),
+                OrganizationAction::Invite { organization, member, role } => proto::organization_action::Action::Invite(proto::MemberRole {
+                    organization: organization.clone(),
+                    member: member.clone(),
+                    role: match role {
+                        Role::Member => proto::member_role::Role::Member,
+                        Role::Auditor => proto::member_role::Role::Auditor,
+                        Role::Treasurer => proto::member_role::Role::Treasurer,
+                    } as i32,
+                }),
+                OrganizationAction::Remove { organization, member } => proto::organization_action::Action::Remove(proto::MemberRole {
+                    organization: organization.clone(),
+                    member: member.clone(),
+                    role: proto::member_role::Role::Unspecified as i32,
+                }),
+                OrganizationAction::Spend { by } => proto::organization_action::Action::Spend(by.clone()),
+            }),
+        }),
+        settlement: transaction.settlement.as_ref().map(|settlement| proto::SettlementAction {
+            action: Some(match settlement {
+                SettlementAction::Obligation { creditor, amount } => proto::settlement_action::Action::Obligation(proto::Obligation {
+                    creditor: creditor.clone(),
+                    amount: *amount,
+                }),
+                SettlementAction::Settle { epoch } => proto::settlement_action::Action::Settle(*epoch),
+            }),
+        }),
+        dividend: transaction.dividend.as_ref().map(|dividend| proto::DividendAction {
+            action: Some(match dividend {
+                DividendAction::Open { period_end, claim_blocks } => proto::dividend_action::Action::Open(proto::OpenDividend {
+                    period_end: *period_end,
+                    claim_blocks: *claim_blocks,
+                }),
+                DividendAction::Patronage { distribution_id, member, amount } => proto::dividend_action::Action::Patronage(proto::Patronage {
+                    distribution_id: distribution_id.clone(),
+                    member: member.clone(),
+                    amount: *amount,
+                }),
+                DividendAction::Fund { distribution_id } => proto::dividend_action::Action::Fund(distribution_id.clone()),
+                DividendAction::Claim { distribution_id } => proto::dividend_action::Action::Claim(distribution_id.clone()),
+            }),
+        }),
+        rent_for: transaction.rent_for.clone(),
+        network_id: transaction.network_id.clone(),
+        contract_creation: transaction.contract_creation.as_ref().map(|creation| proto::ContractCreation {
+            format: match creation.code.format {
+                CodeFormat::CoopVm => proto::contract_creation::Format::CoopVm,
+                CodeFormat::Wasm => proto::contract_creation::Format::Wasm,
+            } as i32,
+            bytecode: creation.code.bytecode.clone(),
+            args_json: serde_json::to_string(&creation.args).unwrap_or_default(),
+        }),
+        market: transaction.market.as_ref().map(|market| proto::MarketAction {
+            action: Some(match market {
+                MarketAction::Offer { resource, quantity, unit_price } => proto::market_action::Action::Offer(resource_order(*resource, *quantity, *unit_price)),
+                MarketAction::Request { resource, quantity, max_unit_price } => proto::market_action::Action::Request(resource_order(*resource, *quantity, *max_unit_price)),
+                MarketAction::Fulfil { allocation_id, proof } => proto::market_action::Action::Fulfil(proto::Fulfilment { allocation_id: allocation_id.clone(), proof: proof.clone() }),
+                MarketAction::Confirm { allocation_id } => proto::market_action::Action::Confirm(allocation_id.clone()),
+                MarketAction::Cancel { order_id } => proto::market_action::Action::Cancel(order_id.clone()),
+            }),
+        }),
+        enactment: transaction.enactment.as_ref().map(|enactment| proto::Enactment {
+            proposal_id: enactment.proposal_id.clone(),
+            action: Some(match &enactment.action {
+                ProposalAction::ScheduleFeature { feature, activation_height } => {
+                    proto::enactment::Action::ScheduleFeature(proto::ScheduleFeature { feature: feature.clone(), activation_height: *activation_height })
+                }
+                ProposalAction::ApproveRevocation { vesting_id } => proto::enactment::Action::ApproveRevocation(vesting_id.clone()),
+                ProposalAction::ResolveDispute { agreement_id, for_provider } => {
+                    proto::enactment::Action::ResolveDispute(proto::Ruling { agreement_id: agreement_id.clone(), for_provider: *for_provider })
+                }
+                ProposalAction::Halt { reason } => proto::enactment::Action::Halt(reason.clone()),
+                ProposalAction::Resume => proto::enactment::Action::Resume(proto::Resume {}),
+                ProposalAction::ReserveLanes { reserved } => proto::enactment::Action::ReserveLanes(proto::ReserveLanes {
+                    lanes: reserved.iter().map(|(currency, share)| proto::Lane { currency: Some(currency_to_proto(currency)), share: *share }).collect(),
+                }),
+            }),
+        }),
+        bridge: transaction.bridge.as_ref().map(|bridge| proto::BridgeAction {
+            action: Some(match bridge {
+                BridgeAction::Lock { chain_id, recipient } => proto::bridge_action::Action::Lock(proto::BridgeLock { chain_id: chain_id.clone(), recipient: recipient.clone() }),
+                BridgeAction::Release { transfer, proof } => proto::bridge_action::Action::Release(proto::BridgeRelease {
+                    transfer: Some(bridge_transfer_to_proto(transfer)),
+                    signatures: proof.signatures.clone().into_iter().collect(),
+                }),
+            }),
+        }),
+        revocation: transaction.revocation.as_ref().map(|revocation| proto::RevocationAction {
+            action: Some(match revocation {
+                RevocationAction::CreateStatusList { list_id } => proto::revocation_action::Action::CreateStatusList(list_id.clone()),
+                RevocationAction::RegisterCredential { list_id, holder } => proto::revocation_action::Action::RegisterCredential(proto::RegisterCredential { list_id: list_id.clone(), holder: holder.clone() }),
+                RevocationAction::RevokeCredential { list_id, index } => proto::revocation_action::Action::RevokeCredential(proto::RevokeCredential { list_id: list_id.clone(), index: *index as u64 }),
+                RevocationAction::RevokeDid { did_id, reason } => proto::revocation_action::Action::RevokeDid(proto::RevokeDid { did_id: did_id.clone(), reason: reason.clone() }),
+            }),
+        }),
+        stake: transaction.stake.as_ref().map(|stake| proto::StakeAction {
+            action: Some(match stake {
+                StakeAction::Bond { amount } => proto::stake_action::Action::Bond(*amount),
+                StakeAction::Unbond { amount } => proto::stake_action::Action::Unbond(*amount),
+                StakeAction::Withdraw => proto::stake_action::Action::Withdraw(true),
+            }),
+        }),
+        agreement: transaction.agreement.as_ref().map(|agreement| proto::AgreementAction {
+            action: Some(match agreement {
+                AgreementAction::Open { provider, terms } => proto::agreement_action::Action::Open(proto::OpenAgreement { provider: provider.clone(), terms: terms.clone() }),
+                AgreementAction::Release { agreement_id } => proto::agreement_action::Action::Release(agreement_id.clone()),
+                AgreementAction::Dispute { agreement_id, reason } => proto::agreement_action::Action::Dispute(proto::AgreementNote { agreement_id: agreement_id.clone(), text: reason.clone() }),
+                AgreementAction::Evidence { agreement_id, evidence } => proto::agreement_action::Action::Evidence(proto::AgreementNote { agreement_id: agreement_id.clone(), text: evidence.clone() }),
+                AgreementAction::Rule { agreement_id, for_provider } => proto::agreement_action::Action::Rule(proto::Ruling { agreement_id: agreement_id.clone(), for_provider: *for_provider }),
+            }),
+        }),
+        crowdfund: transaction.crowdfund.as_ref().map(|crowdfund| proto::CrowdfundAction {
+            action: Some(match crowdfund {
+                CrowdfundAction::Launch { goal, deadline } => proto::crowdfund_action::Action::Launch(proto::LaunchCampaign { goal: *goal, deadline: *deadline }),
+                CrowdfundAction::Pledge { campaign_id } => proto::crowdfund_action::Action::Pledge(campaign_id.clone()),
+            }),
+        }),
+        vesting: transaction.vesting.as_ref().map(|vesting| proto::VestingAction {
+            action: Some(match vesting {
+                VestingAction::Grant { beneficiary, schedule, revocable } => proto::vesting_action::Action::Grant(proto::GrantVesting {
+                    beneficiary: beneficiary.clone(),
+                    cliff_blocks: schedule.cliff_blocks,
+                    duration_blocks: schedule.duration_blocks,
+                    revocable: *revocable,
+                }),
+                VestingAction::Claim { vesting_id } => proto::vesting_action::Action::Claim(vesting_id.clone()),
+                VestingAction::Revoke { vesting_id } => proto::vesting_action::Action::Revoke(vesting_id.clone()),
+            }),
+        }),
+    }
+}
+
+pub fn transaction_from_proto(transaction: proto::Transaction) -> std::result::Result<Transaction, Status> {
+    let nomination = match transaction.nomination {
+        Some(nomination) => Some(match proto::nomination::Action::try_from(nomination.action) {
+            Ok(proto::nomination::Action::Nominate) => NominationAction::Nominate { validator: nomination.validator },
+            Ok(proto::nomination::Action::Withdraw) => NominationAction::Withdraw { validator: nomination.validator, amount: nomination.amount },
+            _ => return Err(Status::invalid_argument("Nomination has no action")),
+        }),
+        None => None,
+    };
+    let outputs = transaction.outputs.into_iter().map(|output| Ok(TransferOutput {
+        to: output.to,
+        amount: output.amount,
+        currency_type: currency_from_proto(output.currency.ok_or_else(|| Status::invalid_argument("No currency given"))?)?,
+    })).collect::<std::result::Result<_, Status>>()?;
+    let swap = match transaction.swap {
+        Some(swap) => Some(SwapLeg {
+            amount: swap.amount,
+            currency_type: currency_from_proto(swap.currency.ok_or_else(|| Status::invalid_argument("No currency given"))?)?,
+            signature: Some(swap.signature).filter(|signature| !signature.is_empty()),
+            public_key: Some(swap.public_key).filter(|public_key| !public_key.is_empty()),
+        }),
+        None => None,
+    };
+    let stream = match transaction.stream.map(|stream| stream.action) {
+        Some(Some(proto::stream_action::Action::Open(open))) => Some(StreamAction::Open { recipient: open.recipient, rate: open.rate }),
+        Some(Some(proto::stream_action::Action::Withdraw(stream_id))) => Some(StreamAction::Withdraw { stream_id }),
+        Some(Some(proto::stream_action::Action::Cancel(stream_id))) => Some(StreamAction::Cancel { stream_id }),
+        Some(None) => return Err(Status::invalid_argument("Stream has no action")),
+        None => None,
+    };
+    let standing_order = match transaction.standing_order.map(|order| order.action) {
+        Some(Some(proto::standing_order_action::Action::Create(create))) => Some(StandingOrderAction::Create {
+            recipient: create.recipient,
+            amount: create.amount,
+            interval: create.interval,
+            ends_at: create.ends_at,
+        }),
+        Some(Some(proto::standing_order_action::Action::Cancel(order_id))) => Some(StandingOrderAction::Cancel { order_id }),
+        Some(Some(proto::standing_order_action::Action::Execute(execute))) => Some(StandingOrderAction::Execute { order_id: execute.order_id, sequence: execute.sequence }),
+        Some(None) => return Err(Status::invalid_argument("Standing order has no action")),
+        None => None,
+    };
+    let allowance = match transaction.allowance.map(|allowance| allowance.action) {
+        Some(Some(proto::allowance_action::Action::Grant(grant))) => Some(AllowanceAction::Grant { spender: grant.spender, limit: grant.limit, period: grant.period }),
+        Some(Some(proto::allowance_action::Action::Revoke(spender))) => Some(AllowanceAction::Revoke { spender }),
+        Some(Some(proto::allowance_action::Action::Spend(spender))) => Some(AllowanceAction::Spend { spender }),
+        Some(None) => return Err(Status::invalid_argument("Allowance has no action")),
+        None => None,
+    };
+    let validation = match transaction.validation.map(|validation| validation.action) {
+        Some(Some(proto::validation_action::Action::Designate(contract_id))) => Some(ValidationAction::Designate { contract_id }),
+        Some(Some(proto::validation_action::Action::Clear(true))) => Some(ValidationAction::Clear),
+        Some(_) => return Err(Status::invalid_argument("Validation has no action")),
+        None => None,
+    };
+    let organization = match transaction.organization.map(|organization| organization.action) {
+        Some(Some(proto::organization_action::Action::Create(name))) => Some(OrganizationAction::Create { name }),
+        Some(Some(proto::organization_action::Action::Invite(invite))) => Some(OrganizationAction::Invite {
+            role: match proto::member_role::Role::try_from(invite.role) {
+                Ok(proto::member_role::Role::Member) => Role::Member,
+                Ok(proto::member_role::Role::Auditor) => Role::Auditor,
+                Ok(proto::member_role::Role::Treasurer) => Role::Treasurer,
+                _ => return Err(Status::invalid_argument("Invitation has no role")),
+            },
+            organization: invite.organization,
+            member: invite.member,
+        }),
+        Some(Some(proto::organization_action::Action::Remove(remove))) => Some(OrganizationAction::Remove { organization: remove.organization, member: remove.member }),
+        Some(Some(proto::organization_action::Action::Spend(by))) => Some(OrganizationAction::Spend { by }),
+        Some(None) => return Err(Status::invalid_argument("Organization transaction has no action")),
+        None => None,
+    };
+    let settlement = match transaction.settlement.map(|settlement| settlement.action) {
+        Some(Some(proto::settlement_action::Action::Obligation(obligation))) => Some(SettlementAction::Obligation { creditor: obligation.creditor, amount: obligation.amount }),
+        Some(Some(proto::settlement_action::Action::Settle(epoch))) => Some(SettlementAction::Settle { epoch }),
+        Some(None) => return Err(Status::invalid_argument("Settlement has no action")),
+        None => None,
+    };
+    let dividend = match transaction.dividend.map(|dividend| dividend.action) {
+        Some(Some(proto::dividend_action::Action::Open(open))) => Some(DividendAction::Open { period_end: open.period_end, claim_blocks: open.claim_blocks }),
+        Some(Some(proto::dividend_action::Action::Patronage(patronage))) => Some(DividendAction::Patronage {
+            distribution_id: patronage.distribution_id,
+            member: patronage.member,
+            amount: patronage.amount,
+        }),
+        Some(Some(proto::dividend_action::Action::Fund(distribution_id))) => Some(DividendAction::Fund { distribution_id }),
+        Some(Some(proto::dividend_action::Action::Claim(distribution_id))) => Some(DividendAction::Claim { distribution_id }),
+        Some(None) => return Err(Status::invalid_argument("Dividend has no action")),
+        None => None,
+    };
+    let vesting = match transaction.vesting.map(|vesting| vesting.action) {
+        Some(Some(proto::vesting_action::Action::Grant(grant))) => Some(VestingAction::Grant {
+            beneficiary: grant.beneficiary,
+            schedule: VestingSchedule { cliff_blocks: grant.cliff_blocks, duration_blocks: grant.duration_blocks },
+            revocable: grant.revocable,
+        }),
+        Some(Some(proto::vesting_action::Action::Claim(vesting_id))) => Some(VestingAction::Claim { vesting_id }),
+        Some(Some(proto::vesting_action::Action::Revoke(vesting_id))) => Some(VestingAction::Revoke { vesting_id }),
+        Some(None) => return Err(Status::invalid_argument("Vesting has no action")),
+        None => None,
+    };
+    let market = match transaction.market.map(|market| market.action) {
+        Some(Some(proto::market_action::Action::Offer(order))) => Some(MarketAction::Offer { resource: resource_from_proto(order.resource)?, quantity: order.quantity, unit_price: order.unit_price }),
+        Some(Some(proto::market_action::Action::Request(order))) => Some(MarketAction::Request { resource: resource_from_proto(order.resource)?, quantity: order.quantity, max_unit_price: order.unit_price }),
+        Some(Some(proto::market_action::Action::Fulfil(fulfilment))) => Some(MarketAction::Fulfil { allocation_id: fulfilment.allocation_id, proof: fulfilment.proof }),
+        Some(Some(proto::market_action::Action::Confirm(allocation_id))) => Some(MarketAction::Confirm { allocation_id }),
+        Some(Some(proto::market_action::Action::Cancel(order_id))) => Some(MarketAction::Cancel { order_id }),
+        Some(None) => return Err(Status::invalid_argument("Market action has no action")),
+        None => None,
+    };
+    let enactment = match transaction.enactment {
+        Some(proto::Enactment { proposal_id, action: Some(action) }) => Some(Enactment {
+            proposal_id,
+            action: match action {
+                proto::enactment::Action::ScheduleFeature(schedule) => ProposalAction::ScheduleFeature { feature: schedule.feature, activation_height: schedule.activation_height },
+                proto::enactment::Action::ApproveRevocation(vesting_id) => ProposalAction::ApproveRevocation { vesting_id },
+                proto::enactment::Action::ResolveDispute(ruling) => ProposalAction::ResolveDispute { agreement_id: ruling.agreement_id, for_provider: ruling.for_provider },
+                proto::enactment::Action::Halt(reason) => ProposalAction::Halt { reason },
+                proto::enactment::Action::Resume(proto::Resume {}) => ProposalAction::Resume,
+                proto::enactment::Action::ReserveLanes(lanes) => ProposalAction::ReserveLanes {
+                    reserved: lanes.lanes.into_iter()
+                        .map(|lane| Ok((currency_from_proto(lane.currency.ok_or_else(|| Status::invalid_argument("No currency given"))?)?, lane.share)))
+                        .collect::<std::result::Result<_, Status>>()?,
+                },
+            },
+        }),
+        Some(proto::Enactment { action: None, .. }) => return Err(Status::invalid_argument("Enactment has no action")),
+        None => None,
+    };
+    let bridge = match transaction.bridge.map(|bridge| bridge.action) {
+        Some(Some(proto::bridge_action::Action::Lock(lock))) => Some(BridgeAction::Lock { chain_id: lock.chain_id, recipient: lock.recipient }),
+        Some(Some(proto::bridge_action::Action::Release(release))) => Some(BridgeAction::Release {
+            transfer: bridge_transfer_from_proto(release.transfer.ok_or_else(|| Status::invalid_argument("Release has no transfer"))?)?,
+            proof: BridgeProof { signatures: release.signatures.into_iter().collect() },
+        }),
+        Some(None) => return Err(Status::invalid_argument("Bridge action has no action")),
+        None => None,
+    };
+    let agreement = match transaction.agreement.map(|agreement| agreement.action) {
+        Some(Some(proto::agreement_action::Action::Open(open))) => Some(AgreementAction::Open { provider: open.provider, terms: open.terms }),
+        Some(Some(proto::agreement_action::Action::Release(agreement_id))) => Some(AgreementAction::Release { agreement_id }),
+        Some(Some(proto::agreement_action::Action::Dispute(note))) => Some(AgreementAction::Dispute { agreement_id: note.agreement_id, reason: note.text }),
+        Some(Some(proto::agreement_action::Action::Evidence(note))) => Some(AgreementAction::Evidence { agreement_id: note.agreement_id, evidence: note.text }),
+        Some(Some(proto::agreement_action::Action::Rule(ruling))) => Some(AgreementAction::Rule { agreement_id: ruling.agreement_id, for_provider: ruling.for_provider }),
+        Some(None) => return Err(Status::invalid_argument("Agreement has no action")),
+        None => None,
+    };
+    let revocation = match transaction.revocation.map(|revocation| revocation.action) {
+        Some(Some(proto::revocation_action::Action::CreateStatusList(list_id))) => Some(RevocationAction::CreateStatusList { list_id }),
+        Some(Some(proto::revocation_action::Action::RegisterCredential(register))) => Some(RevocationAction::RegisterCredential { list_id: register.list_id, holder: register.holder }),
+        Some(Some(proto::revocation_action::Action::RevokeCredential(revoke))) => Some(RevocationAction::RevokeCredential {
+            list_id: revoke.list_id,
+            index: usize::try_from(revoke.index).map_err(|_| Status::invalid_argument("Status index out of range"))?,
+        }),
+        Some(Some(proto::revocation_action::Action::RevokeDid(revoke))) => Some(RevocationAction::RevokeDid { did_id: revoke.did_id, reason: revoke.reason }),
+        Some(None) => return Err(Status::invalid_argument("Revocation has no action")),
+        None => None,
+    };
+    let stake = match transaction.stake.map(|stake| stake.action) {
+        Some(Some(proto::stake_action::Action::Bond(amount))) => Some(StakeAction::Bond { amount }),
+        Some(Some(proto::stake_action::Action::Unbond(amount))) => Some(StakeAction::Unbond { amount }),
+        Some(Some(proto::stake_action::Action::Withdraw(true))) => Some(StakeAction::Withdraw),
+        Some(_) => return Err(Status::invalid_argument("Stake has no action")),
+        None => None,
+    };
+    let crowdfund = match transaction.crowdfund.map(|crowdfund| crowdfund.action) {
+        Some(Some(proto::crowdfund_action::Action::Launch(launch))) => Some(CrowdfundAction::Launch { goal: launch.goal, deadline: launch.deadline }),
+        Some(Some(proto::crowdfund_action::Action::Pledge(campaign_id))) => Some(CrowdfundAction::Pledge { campaign_id }),
+        Some(None) => return Err(Status::invalid_argument("Crowdfunding has no action")),
+        None => None,
+    };
+    let contract_creation = match transaction.contract_creation {
+        Some(creation) => Some(ContractCreation {
+            code: ContractCode::new(match proto::contract_creation::Format::try_from(creation.format) {
+                Ok(proto::contract_creation::Format::CoopVm) => CodeFormat::CoopVm,
+                Ok(proto::contract_creation::Format::Wasm) => CodeFormat::Wasm,
+                _ => return Err(Status::invalid_argument("Contract creation has no code format")),
+            }, creation.bytecode),
+            args: if creation.args_json.is_empty() {
+                Vec::new()
+            } else {
+                serde_json::from_str(&creation.args_json).map_err(|e| Status::invalid_argument(format!("Malformed constructor arguments: {}", e)))?
+            },
+        }),
+        None => None,
+    };
+    if transaction.network_id.is_empty() {
+        return Err(Status::invalid_argument("Transaction has no network id"));
+    }
+    Ok(Transaction {
+        from: transaction.from,
+        to: transaction.to,
+        amount: transaction.amount,
+        currency_type: currency_from_proto(transaction.currency.ok_or_else(|| Status::invalid_argument("No currency given"))?)?,
+        gas_limit: transaction.gas_limit,
+        gas_price: transaction.gas_price,
+        smart_contract_id: transaction.smart_contract_id,
+        signature: Some(transaction.signature).filter(|signature| !signature.is_empty()),
+        public_key: Some(transaction.public_key).filter(|public_key| !public_key.is_empty()),
+        nomination,
+        upgrade_signal: transaction.upgrade_signal,
+        valid_until: transaction.valid_until.map(|valid_until| match valid_until {
+            proto::transaction::ValidUntil::ValidUntilHeight(height) => ValidUntil::Height(height),
+            proto::transaction::ValidUntil::ValidUntilTimestamp(timestamp) => ValidUntil::Timestamp(timestamp),
+        }),
+        outputs,
+        swap,
+        stream,
+        standing_order,
+        allowance,
+        cosignatures: transaction.cosignatures.into_iter()
+            .map(|cosignature| Cosignature { public_key: cosignature.public_key, signature: cosignature.signature })
+            .collect(),
+        validation,
+        organization,
+        settlement,
+        dividend,
+        rent_for: transaction.rent_for,
+        contract_creation,
+        vesting,
+        crowdfund,
+        agreement,
+        market,
+        enactment,
+        bridge,
+        revocation,
+        stake,
+        network_id: transaction.network_id,
+    })
+}
+
+fn bridge_transfer_to_proto(transfer: &BridgeTransfer) -> proto::BridgeTransfer {
+    let direction = match transfer.direction {
+        Direction::Outbound => proto::bridge_transfer::Direction::Outbound,
+        Direction::Inbound => proto::bridge_transfer::Direction::Inbound,
+    };
+    proto::BridgeTransfer {
+        chain_id: transfer.chain_id.clone(),
+        direction: direction as i32,
+        nonce: transfer.nonce,
+        sender: transfer.sender.clone(),
+        recipient: transfer.recipient.clone(),
+        amount: transfer.amount,
+        currency: Some(currency_to_proto(&transfer.currency)),
+        source_tx: transfer.source_tx.clone(),
+    }
+}
+
+fn bridge_transfer_from_proto(transfer: proto::BridgeTransfer) -> std::result::Result<BridgeTransfer, Status> {
+    let direction = match proto::bridge_transfer::Direction::try_from(transfer.direction) {
+        Ok(proto::bridge_transfer::Direction::Outbound) => Direction::Outbound,
+        Ok(proto::bridge_transfer::Direction::Inbound) => Direction::Inbound,
+        _ => return Err(Status::invalid_argument("Bridge transfer has no direction")),
+    };
+    Ok(BridgeTransfer {
+        chain_id: transfer.chain_id,
+        direction,
+        nonce: transfer.nonce,
+        sender: transfer.sender,
+        recipient: transfer.recipient,
+        amount: transfer.amount,
+        currency: currency_from_proto(transfer.currency.ok_or_else(|| Status::invalid_argument("Bridge transfer has no currency"))?)?,
+        source_tx: transfer.source_tx,
+    })
+}
+
+fn block_to_proto(block: &Block) -> proto::Block {
+    proto::Block {
+        index: block.index,
+        timestamp: block.timestamp,
+        previous_hash: block.previous_hash.clone(),
+        hash: block.hash.clone(),
+        nonce: block.nonce,
+        gas_used: block.gas_used,
+        protocol_version: block.protocol_version,
+        transactions: block.transactions.iter().map(transaction_to_proto).collect(),
+        proposer: block.proposer.clone(),
+    }
+}
+
+fn receipt_to_proto(receipt: &TransactionReceipt) -> proto::TransactionReceipt {
+    proto::TransactionReceipt {
+        transaction_hash: receipt.transaction_hash.clone(),
+        block_index: receipt.block_index,
+        block_hash: receipt.block_hash.clone(),
+        success: receipt.is_success(),
+        failure_reason: match &receipt.status {
+            ReceiptStatus::Success => String::new(),
+            ReceiptStatus::Failed(reason) => reason.clone(),
+        },
+        gas_used: receipt.gas_used,
+        events: receipt.events.iter().map(|event| proto::ContractEvent {
+            contract_id: event.contract_id.clone(),
+            name: event.name.clone(),
+            data: event.data.clone(),
+        }).collect(),
+        contract_address: receipt.contract_address.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Blockchain, ChainSpec};
+    use crate::governance::DemocraticSystem;
+    use ed25519_dalek::Keypair;
+    use futures::StreamExt;
+    use icn_client::v1::node_control_client::NodeControlClient;
+    use rand::rngs::OsRng;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_client_submits_and_streams_blocks() {
+        let keypair = Keypair::generate(&mut OsRng {});
+        let alice = crate::wallet::address_of(&keypair.public);
+        let hours = CurrencyType::Custom("hours".to_string());
+        let blockchain = Blockchain::with_spec(ChainSpec::default().with_allocation(&alice, 100.0, hours.clone()));
+        let api = Arc::new(ApiLayer::new(Arc::new(RwLock::new(blockchain)), Arc::new(RwLock::new(DemocraticSystem::new()))));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(GrpcService::new(api.clone()).with_poll_interval(Duration::from_millis(10)).serve(listener));
+        let mut client = NodeControlClient::connect(format!("http://{}", address)).await.unwrap();
+
+        let mut transaction = Transaction::new(alice, "Bob".to_string(), 25.0, hours, 1000).with_gas_price(2.0);
+        transaction.sign(&keypair).unwrap();
+        let request = proto::SubmitTransactionRequest { transaction: Some(transaction_to_proto(&transaction)) };
+        let submitted = client.submit_transaction(request).await.unwrap().into_inner();
+        assert_eq!(submitted.transaction_hash, transaction.hash(), "the transaction survives the round trip");
+
+        let mut blocks = client.stream_blocks(proto::StreamBlocksRequest { from_index: 1 }).await.unwrap().into_inner();
+        api.blockchain.write().await.create_block("Miner1".to_string()).unwrap();
+        let block = blocks.next().await.unwrap().unwrap();
+        assert_eq!((block.index, block.transactions.len()), (1, 1));
+
+        let receipt = client.get_transaction_receipt(proto::GetTransactionReceiptRequest { transaction_hash: submitted.transaction_hash })
+            .await.unwrap().into_inner();
+        assert!(receipt.success);
+        let balance = client.get_balance(proto::GetBalanceRequest { address: "Bob".to_string(), currency: Some(currency_to_proto(&transaction.currency_type)) })
+            .await.unwrap().into_inner();
+        assert_eq!(balance.balance, 25.0);
+        let balances = client.list_balances(proto::ListBalancesRequest { address: "Bob".to_string() }).await.unwrap().into_inner().balances;
+        assert_eq!(balances.len(), 1);
+        assert_eq!(currency_from_proto(balances[0].currency.clone().unwrap()).unwrap(), transaction.currency_type);
+        let estimates = client.estimate_fees(proto::EstimateFeesRequest { currencies: vec![] }).await.unwrap().into_inner().estimates;
+        assert_eq!(estimates.len(), 1);
+        assert_eq!(estimates[0].low, 2.0, "the price paid in the lane");
+        let missing = client.get_block(proto::GetBlockRequest { block: Some(proto::get_block_request::Block::Index(9)) }).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+        server.abort();
+    }
+}
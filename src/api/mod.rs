@@ -1,26 +1,77 @@
-use crate::blockchain::{Blockchain, Transaction};
-use crate::governance::DemocraticSystem;
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod onboarding;
+
+use crate::blockchain::{fees, Blockchain, FeeConfig, FeeEstimate, LogEntry, LogFilter, Transaction, TransactionReceipt};
+use crate::currency::CurrencyType;
+use crate::error::Error;
+use crate::faucet::Faucet;
+use crate::governance::{DemocraticSystem, Federation};
+use crate::identity::DidManager;
+use crate::network::{BanEntry, Network};
+use onboarding::{MembershipIssuer, Onboarder, Onboarding, OnboardingRequest};
 use crate::governance::democracy::ProposalStatus as DemocracyProposalStatus;
-// Remove this line
-// use crate::error::Error;
+use crate::webhooks::{self, Delivery, Subscription, WebhookFilter, WebhookService};
+use crate::sharding::{AuditEntry, CrossShardTransaction, CrossShardTransactionManager, CrossShardTransactionStatus, ShardMetrics, ShardingManager};
 
 use serde::{Deserialize, Serialize, Serializer, Deserializer};
 use tokio::sync::RwLock;
 use std::sync::Arc;
 use chrono::{DateTime, Utc, Duration};
 
-// The rest of the file remains the same...
-
 #[derive(Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// `Error::code` of the error, for clients to act on without parsing
+    /// `error`.
+    #[serde(default)]
+    pub error_code: Option<u16>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+            error_code: None,
+        }
+    }
+
+    pub fn err(error: Error) -> Self {
+        ApiResponse {
+            success: false,
+            data: None,
+            error: Some(error.to_string()),
+            error_code: Some(error.code()),
+        }
+    }
+}
+
+impl<T> From<crate::error::Result<T>> for ApiResponse<T> {
+    fn from(result: crate::error::Result<T>) -> Self {
+        match result {
+            Ok(data) => ApiResponse::ok(data),
+            Err(error) => ApiResponse::err(error),
+        }
+    }
 }
 
 pub struct ApiLayer {
     blockchain: Arc<RwLock<Blockchain>>,
     governance: Arc<RwLock<DemocraticSystem>>,
+    network: Option<Network>,
+    cross_shard: Option<Arc<std::sync::RwLock<CrossShardTransactionManager>>>,
+    sharding: Option<Arc<std::sync::RwLock<ShardingManager>>>,
+    webhooks: Option<Arc<std::sync::Mutex<WebhookService>>>,
+    dids: Option<Arc<std::sync::RwLock<DidManager>>>,
+    faucet: Option<Arc<std::sync::Mutex<Faucet>>>,
+    federation: Option<Arc<std::sync::RwLock<Federation>>>,
+    membership_issuer: Option<Arc<MembershipIssuer>>,
+    fees: FeeConfig,
 }
 
 impl ApiLayer {
@@ -31,102 +82,450 @@ impl ApiLayer {
         Self {
             blockchain,
             governance,
+            network: None,
+            cross_shard: None,
+            sharding: None,
+            webhooks: None,
+            dids: None,
+            faucet: None,
+            federation: None,
+            membership_issuer: None,
+            fees: FeeConfig::default(),
         }
     }
 
+    /// Gossips accepted transactions to the rest of the network.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Reports on the cross-shard transfers run by `coordinator`, usually
+    /// `IcnNode::cross_shard_coordinator`.
+    pub fn with_cross_shard_coordinator(mut self, coordinator: Arc<std::sync::RwLock<CrossShardTransactionManager>>) -> Self {
+        self.cross_shard = Some(coordinator);
+        self
+    }
+
+    /// Lets operators register webhooks on `service`, which `poll_webhooks`
+    /// feeds and delivers.
+    pub fn with_webhooks(mut self, service: Arc<std::sync::Mutex<WebhookService>>) -> Self {
+        self.webhooks = Some(service);
+        self
+    }
+
+    /// Serves the identities of `did_manager`, usually `IcnNode::did_manager`,
+    /// to GraphQL queries.
+    pub fn with_did_manager(mut self, did_manager: Arc<std::sync::RwLock<DidManager>>) -> Self {
+        self.dids = Some(did_manager);
+        self
+    }
+
+    /// Hands out test funds from `faucet` through `request_funds`.
+    pub fn with_faucet(mut self, faucet: Arc<std::sync::Mutex<Faucet>>) -> Self {
+        self.faucet = Some(faucet);
+        self
+    }
+
+    /// Onboards members into the cooperatives of `federation`, with
+    /// membership credentials signed by `issuer`; see `onboard_member`.
+    pub fn with_onboarding(mut self, federation: Arc<std::sync::RwLock<Federation>>, issuer: MembershipIssuer) -> Self {
+        self.federation = Some(federation);
+        self.membership_issuer = Some(Arc::new(issuer));
+        self
+    }
+
+    /// How `estimate_fees` reads congestion; the block capacity should match
+    /// the network's production config.
+    pub fn with_fee_config(mut self, config: FeeConfig) -> Self {
+        self.fees = config;
+        self
+    }
+
     pub async fn get_blockchain_info(&self) -> ApiResponse<BlockchainInfo> {
         let blockchain = self.blockchain.read().await;
         let info = BlockchainInfo {
             block_count: blockchain.chain.len(),
             last_block_hash: blockchain.chain.last().map(|b| b.hash.clone()),
         };
-        ApiResponse {
-            success: true,
-            data: Some(info),
-            error: None,
-        }
+        ApiResponse::ok(info)
     }
 
     pub async fn submit_transaction(&self, transaction: Transaction) -> ApiResponse<String> {
-        let mut blockchain = self.blockchain.write().await;
-        match blockchain.add_transaction(transaction) {
-            Ok(()) => ApiResponse {
-                success: true,
-                data: Some("Transaction submitted successfully".to_string()),
-                error: None,
-            },
-            Err(e) => ApiResponse {
-                success: false,
-                data: None,
-                error: Some(e.to_string()),
-            },
+        let result = self.blockchain.write().await.add_transaction(transaction.clone());
+        if result.is_ok() {
+            if let Some(network) = &self.network {
+                if let Err(e) = network.publish_transaction(transaction).await {
+                    tracing::warn!("Failed to gossip submitted transaction: {}", e);
+                }
+            }
         }
+        result.map(|()| "Transaction submitted successfully".to_string()).into()
+    }
+
+    /// The receipt of a transaction in the chain, by transaction hash.
+    pub async fn get_transaction_receipt(&self, transaction_hash: &str) -> ApiResponse<TransactionReceipt> {
+        let blockchain = self.blockchain.read().await;
+        match blockchain.get_transaction_receipt(transaction_hash) {
+            Some(receipt) => ApiResponse::ok(receipt.clone()),
+            None => ApiResponse::err(Error::NotFound(format!("No receipt for transaction {}", transaction_hash))),
+        }
+    }
+
+    /// Contract events by block range, emitting contract or party, and name.
+    pub async fn get_logs(&self, filter: LogFilter) -> ApiResponse<Vec<LogEntry>> {
+        ApiResponse::ok(self.blockchain.read().await.get_logs(&filter))
+    }
+
+    /// Suggested gas prices in each currency in use and in `currencies`,
+    /// from how full the latest blocks were and how deep the mempool is.
+    pub async fn estimate_fees(&self, currencies: &[CurrencyType]) -> ApiResponse<Vec<FeeEstimate>> {
+        ApiResponse::ok(fees::estimate(&*self.blockchain.read().await, &self.fees, currencies))
     }
 
     pub async fn get_balance(&self, address: &str) -> ApiResponse<f64> {
         let blockchain = self.blockchain.read().await;
-        let balance = blockchain.get_balance(address);
-        ApiResponse {
-            success: true,
-            data: Some(balance),
-            error: None,
+        ApiResponse::ok(blockchain.get_balance(address))
+    }
+
+    /// The stake `member_id` has bonded, is bonding and is unbonding.
+    pub async fn get_stake(&self, member_id: &str) -> ApiResponse<crate::consensus::StakeInfo> {
+        ApiResponse::ok(self.blockchain.read().await.consensus.stakes.info(member_id))
+    }
+
+    /// Records a signed vote on a block, returning whether the block is now
+    /// approved.
+    pub async fn submit_vote(&self, vote: crate::consensus::SignedVote) -> ApiResponse<bool> {
+        self.blockchain.write().await.submit_vote(&vote).into()
+    }
+
+    /// The equivocations caught so far, each proving a validator signed
+    /// votes for two blocks at one height.
+    pub async fn get_equivocations(&self) -> ApiResponse<Vec<crate::consensus::Equivocation>> {
+        ApiResponse::ok(self.blockchain.read().await.consensus.vote_log.equivocations().to_vec())
+    }
+
+    /// Records a validator's call to halt or resume the chain and gossips
+    /// it to the rest of the network.
+    pub async fn call_emergency(&self, call: crate::consensus::EmergencyCall) -> ApiResponse<bool> {
+        let result = self.blockchain.write().await.call_emergency(&call);
+        if let (Ok(true), Some(network)) = (&result, &self.network) {
+            if let Err(e) = network.publish_emergency(call).await {
+                tracing::warn!("Failed to gossip emergency call: {}", e);
+            }
         }
+        result.into()
+    }
+
+    /// Why and since when the chain is halted, if it is.
+    pub async fn get_halt(&self) -> ApiResponse<Option<crate::consensus::Halt>> {
+        ApiResponse::ok(self.blockchain.read().await.circuit_breaker.halt().cloned())
+    }
+
+    /// The validators `member_id` has nominated.
+    pub async fn get_nominations(&self, member_id: &str) -> ApiResponse<Vec<crate::consensus::Nomination>> {
+        ApiResponse::ok(self.blockchain.read().await.consensus.nominations.nominations_of(member_id))
+    }
+
+    /// The open payment streams `address` pays or is paid by.
+    pub async fn get_streams(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Stream>> {
+        ApiResponse::ok(self.blockchain.read().await.streams.of(address).into_iter().cloned().collect())
+    }
+
+    /// The crowdfunding campaigns of the project `address`, or that it
+    /// pledged to.
+    pub async fn get_campaigns(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Campaign>> {
+        ApiResponse::ok(self.blockchain.read().await.crowdfunding.of(address).into_iter().cloned().collect())
+    }
+
+    /// The resource orders `address` has open.
+    pub async fn get_orders(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Order>> {
+        ApiResponse::ok(self.blockchain.read().await.market.orders_of(address).into_iter().cloned().collect())
+    }
+
+    /// The resource allocations `address` provides or consumes.
+    pub async fn get_allocations(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Allocation>> {
+        ApiResponse::ok(self.blockchain.read().await.market.allocations_of(address).into_iter().cloned().collect())
+    }
+
+    /// The service agreements `address` is a party to or a juror on.
+    pub async fn get_agreements(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::ServiceAgreement>> {
+        ApiResponse::ok(self.blockchain.read().await.agreements.of(address).into_iter().cloned().collect())
+    }
+
+    /// The vesting grants `address` made or benefits from.
+    pub async fn get_vestings(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Vesting>> {
+        ApiResponse::ok(self.blockchain.read().await.vesting.of(address).into_iter().cloned().collect())
+    }
+
+    /// The standing orders `address` pays or is paid by.
+    pub async fn get_standing_orders(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::StandingOrder>> {
+        ApiResponse::ok(self.blockchain.read().await.standing_orders.of(address).into_iter().cloned().collect())
+    }
+
+    /// The obligations recorded this settlement epoch, with the net position
+    /// of `address` in `currency_type` they add up to.
+    pub async fn get_obligations(&self, address: &str, currency_type: &CurrencyType) -> ApiResponse<(Vec<crate::blockchain::Obligation>, f64)> {
+        let blockchain = self.blockchain.read().await;
+        let obligations = blockchain.settlement.obligations().iter()
+            .filter(|obligation| obligation.currency_type == *currency_type && (obligation.debtor == address || obligation.creditor == address))
+            .cloned()
+            .collect();
+        ApiResponse::ok((obligations, blockchain.settlement.net_position(address, currency_type)))
+    }
+
+    /// The distributions of surplus of the cooperative `address`, or that it
+    /// has patronage in.
+    pub async fn get_dividends(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Distribution>> {
+        ApiResponse::ok(self.blockchain.read().await.dividends.of(address).into_iter().cloned().collect())
+    }
+
+    /// The rent standing of a deployed contract.
+    pub async fn get_rent(&self, contract_id: &str) -> ApiResponse<crate::blockchain::RentAccount> {
+        match self.blockchain.read().await.state_rent.account(contract_id) {
+            Some(account) => ApiResponse::ok(account.clone()),
+            None => ApiResponse::err(Error::NotFound(format!("No rent account for contract {}", contract_id))),
+        }
+    }
+
+    /// The compiled code a deployed contract runs; its hash is the code hash
+    /// the contract is bound to.
+    pub async fn get_contract_code(&self, contract_id: &str) -> ApiResponse<crate::smart_contract::ContractCode> {
+        match self.blockchain.read().await.code_store.code_of(contract_id) {
+            Some(code) => ApiResponse::ok(code.clone()),
+            None => ApiResponse::err(Error::NotFound(format!("No code for contract {}", contract_id))),
+        }
+    }
+
+    /// What calling the contract with `input` would return and emit, and the
+    /// gas it would use up to `gas_limit`, without making a transaction.
+    pub async fn call_contract(&self, contract_id: &str, input: crate::smart_contract::ContractInput, gas_limit: u64) -> ApiResponse<crate::smart_contract::ContractCall> {
+        self.blockchain.read().await.call_contract(contract_id, input, gas_limit).into()
+    }
+
+    pub async fn get_organization(&self, id: &str) -> ApiResponse<crate::blockchain::Organization> {
+        match self.blockchain.read().await.organizations.get(id) {
+            Some(organization) => ApiResponse::ok(organization.clone()),
+            None => ApiResponse::err(Error::NotFound(format!("No organization {}", id))),
+        }
+    }
+
+    /// The organizations `member` belongs to.
+    pub async fn get_organizations(&self, member: &str) -> ApiResponse<Vec<crate::blockchain::Organization>> {
+        ApiResponse::ok(self.blockchain.read().await.organizations.of(member).into_iter().cloned().collect())
+    }
+
+    /// The allowances `address` has granted or been granted, with what has
+    /// been spent out of them.
+    pub async fn get_allowances(&self, address: &str) -> ApiResponse<Vec<crate::blockchain::Allowance>> {
+        ApiResponse::ok(self.blockchain.read().await.allowances.of(address).into_iter().cloned().collect())
+    }
+
+    /// The members nominating `validator`.
+    pub async fn get_nominators(&self, validator: &str) -> ApiResponse<Vec<crate::consensus::Nomination>> {
+        ApiResponse::ok(self.blockchain.read().await.consensus.nominations.nominators_of(validator))
     }
 
     pub async fn create_proposal(&self, proposal: Proposal) -> ApiResponse<String> {
         let mut governance = self.governance.write().await;
-        match governance.create_proposal(
-            proposal.title,
-            proposal.description,
-            proposal.proposer,
-            proposal.voting_period,
-            proposal.proposal_type,
-            proposal.category,
-            proposal.required_quorum,
-            proposal.execution_timestamp,
-        ) {
-            Ok(id) => ApiResponse {
-                success: true,
-                data: Some(id),
-                error: None,
-            },
-            Err(e) => ApiResponse {
-                success: false,
-                data: None,
-                error: Some(e.to_string()),
-            },
+        let proposal_id = match proposal.action {
+            Some(action) => governance.propose_action(proposal.title, proposal.proposer, proposal.voting_period, proposal.required_quorum, action),
+            None => governance.create_proposal(
+                proposal.title,
+                proposal.description,
+                proposal.proposer,
+                proposal.voting_period,
+                proposal.proposal_type,
+                proposal.category,
+                proposal.required_quorum,
+                proposal.execution_timestamp,
+            ),
+        };
+        proposal_id.and_then(|proposal_id| {
+            for cid in proposal.attachments {
+                governance.attach(&proposal_id, cid)?;
+            }
+            Ok(proposal_id)
+        }).map_err(Error::GovernanceError).into()
+    }
+
+    /// Puts `proposal` to governance on behalf of `organization`, which it
+    /// is then proposed by, if its proposer has a role permitting it there.
+    pub async fn create_organization_proposal(&self, organization: &str, proposal: Proposal) -> ApiResponse<String> {
+        let permitted = self.blockchain.read().await.organizations.check(organization, &proposal.proposer, crate::blockchain::Permission::Propose);
+        if let Err(e) = permitted {
+            return ApiResponse::err(Error::GovernanceError(e));
         }
+        self.create_proposal(Proposal { proposer: organization.to_string(), ..proposal }).await
     }
 
     pub async fn vote_on_proposal(&self, vote: Vote) -> ApiResponse<String> {
         let mut governance = self.governance.write().await;
-        match governance.vote(vote.voter, vote.proposal_id, vote.in_favor, vote.weight) {
-            Ok(()) => ApiResponse {
-                success: true,
-                data: Some("Vote recorded successfully".to_string()),
-                error: None,
-            },
-            Err(e) => ApiResponse {
-                success: false,
-                data: None,
-                error: Some(e.to_string()),
-            },
+        governance.vote(vote.voter, vote.proposal_id, vote.in_favor, vote.weight)
+            .map(|()| "Vote recorded successfully".to_string())
+            .map_err(Error::GovernanceError)
+            .into()
+    }
+
+    /// Sends `did` the faucet's drip of each currency, for a request made
+    /// from `ip`, returning the hashes of the transfers. Refused if the DID
+    /// or the IP address was given funds within the cooldown.
+    pub async fn request_funds(&self, did: &str, ip: Option<std::net::IpAddr>) -> ApiResponse<Vec<String>> {
+        let faucet = match &self.faucet {
+            Some(faucet) => faucet,
+            None => return ApiResponse::err(Error::Unavailable("This node runs no faucet".to_string())),
+        };
+        let transactions = {
+            let mut blockchain = self.blockchain.write().await;
+            match faucet.lock().unwrap().drip(&mut blockchain, did, ip) {
+                Ok(transactions) => transactions,
+                Err(e) => return ApiResponse::err(e),
+            }
+        };
+        let hashes = transactions.iter().map(Transaction::hash).collect();
+        if let Some(network) = &self.network {
+            for transaction in transactions {
+                if let Err(e) = network.publish_transaction(transaction).await {
+                    tracing::warn!("Failed to gossip faucet transfer: {}", e);
+                }
+            }
+        }
+        ApiResponse::ok(hashes)
+    }
+
+    /// Onboards a member in one go: registers the DID of their key, issues
+    /// them a membership credential, admits them to their cooperative, and
+    /// opens their accounts in its currencies on the shard of their address.
+    /// If a step fails, those done are undone and nothing is left behind.
+    /// Needs a DID manager, sharding manager and `with_onboarding`.
+    pub async fn onboard_member(&self, request: OnboardingRequest) -> ApiResponse<Onboarding> {
+        match (&self.dids, &self.federation, &self.sharding, &self.membership_issuer) {
+            (Some(dids), Some(federation), Some(sharding), Some(issuer)) => Onboarder::new(dids, federation, sharding, issuer).onboard(request).into(),
+            _ => ApiResponse::err(Error::Unavailable("This node does not onboard members".to_string())),
+        }
+    }
+
+    pub async fn get_banned_peers(&self) -> ApiResponse<Vec<BanEntry>> {
+        self.network().map(Network::banned_peers).into()
+    }
+
+    pub async fn unban_peer(&self, peer_id: &str) -> ApiResponse<String> {
+        self.network().and_then(|network| match network.unban_peer(peer_id) {
+            true => Ok(format!("Peer {} unbanned", peer_id)),
+            false => Err(Error::NotFound(format!("Peer {} is not banned", peer_id))),
+        }).into()
+    }
+
+    /// Registers a webhook, returning its id.
+    pub async fn register_webhook(&self, registration: WebhookRegistration) -> ApiResponse<String> {
+        self.webhooks()
+            .and_then(|service| service.lock().unwrap().subscribe(&registration.url, &registration.secret, registration.filter))
+            .into()
+    }
+
+    pub async fn remove_webhook(&self, id: &str) -> ApiResponse<String> {
+        self.webhooks().and_then(|service| match service.lock().unwrap().unsubscribe(id) {
+            true => Ok(format!("Webhook {} removed", id)),
+            false => Err(Error::NotFound(format!("No webhook {}", id))),
+        }).into()
+    }
+
+    /// The registered webhooks, without their secrets.
+    pub async fn get_webhooks(&self) -> ApiResponse<Vec<Subscription>> {
+        self.webhooks().map(|service| service.lock().unwrap().subscriptions().cloned().collect()).into()
+    }
+
+    /// Notifications given up on after every retry failed.
+    pub async fn get_failed_notifications(&self) -> ApiResponse<Vec<Delivery>> {
+        self.webhooks().map(|service| service.lock().unwrap().dead_letters().cloned().collect()).into()
+    }
+
+    /// Finds what happened on the chain and in governance since the last
+    /// poll and sends the notifications due. Meant to be called on a timer.
+    pub async fn poll_webhooks(&self) {
+        let service = match &self.webhooks {
+            Some(service) => service,
+            None => return,
+        };
+        {
+            let blockchain = self.blockchain.read().await;
+            let governance = self.governance.read().await;
+            let mut service = service.lock().unwrap();
+            service.scan_chain(&blockchain);
+            service.scan_governance(&governance);
+        }
+        webhooks::deliver_due(service).await;
+    }
+
+    fn webhooks(&self) -> crate::error::Result<&Arc<std::sync::Mutex<WebhookService>>> {
+        self.webhooks.as_ref().ok_or_else(|| Error::Unavailable("Webhooks not available".to_string()))
+    }
+
+    fn network(&self) -> crate::error::Result<&Network> {
+        self.network.as_ref().ok_or_else(|| Error::Unavailable("Network not available".to_string()))
+    }
+
+    /// Reports the load of the shards of `sharding_manager`, usually
+    /// `IcnNode::sharding_manager`.
+    /// Runs a GraphQL query; see `graphql::SCHEMA` for what can be asked.
+    pub async fn graphql(&self, request: graphql::GraphQLRequest) -> graphql::GraphQLResponse {
+        let blockchain = self.blockchain.read().await;
+        let governance = self.governance.read().await;
+        let dids = self.dids.as_ref().map(|dids| dids.read().unwrap());
+        let context = graphql::Context { blockchain: &blockchain, governance: &governance, dids: dids.as_deref() };
+        graphql::execute(context, &request)
+    }
+
+    pub fn with_sharding_manager(mut self, sharding_manager: Arc<std::sync::RwLock<ShardingManager>>) -> Self {
+        self.sharding = Some(sharding_manager);
+        self
+    }
+
+    pub async fn get_shard_metrics(&self) -> ApiResponse<Vec<ShardMetrics>> {
+        match &self.sharding {
+            Some(sharding_manager) => sharding_manager.read().unwrap().load_metrics().into(),
+            None => ApiResponse::err(Error::Unavailable("Sharding manager not available".to_string())),
+        }
+    }
+
+    pub async fn get_transaction_status(&self, tx_id: &str) -> ApiResponse<CrossShardTransactionStatus> {
+        self.with_cross_shard_transaction(tx_id, |record| record.status.clone())
+    }
+
+    /// The transfer with its audit trail, from which the time of each phase
+    /// can be read.
+    pub async fn get_cross_shard_transaction(&self, tx_id: &str) -> ApiResponse<CrossShardTransaction> {
+        self.with_cross_shard_transaction(tx_id, CrossShardTransaction::clone)
+    }
+
+    pub async fn get_cross_shard_audit_trail(&self, tx_id: &str) -> ApiResponse<Vec<AuditEntry>> {
+        self.with_cross_shard_transaction(tx_id, |record| record.audit.clone())
+    }
+
+    /// Cross-shard transfers still unfinished after `older_than`.
+    pub async fn get_stuck_transactions(&self, older_than: Duration) -> ApiResponse<Vec<CrossShardTransaction>> {
+        match &self.cross_shard {
+            Some(coordinator) => ApiResponse::ok(coordinator.read().unwrap().stuck_transactions(older_than).into_iter().cloned().collect()),
+            None => ApiResponse::err(Error::Unavailable("Cross-shard coordinator not available".to_string())),
+        }
+    }
+
+    fn with_cross_shard_transaction<T>(&self, tx_id: &str, f: impl FnOnce(&CrossShardTransaction) -> T) -> ApiResponse<T> {
+        let coordinator = match &self.cross_shard {
+            Some(coordinator) => coordinator.read().unwrap(),
+            None => return ApiResponse::err(Error::Unavailable("Cross-shard coordinator not available".to_string())),
+        };
+        match coordinator.get(tx_id) {
+            Some(record) => ApiResponse::ok(f(record)),
+            None => ApiResponse::err(Error::NotFound("Cross-shard transaction not found".to_string())),
         }
     }
 
     pub async fn get_proposal_status(&self, proposal_id: &str) -> ApiResponse<ProposalStatus> {
         let governance = self.governance.read().await;
         match governance.get_proposal(proposal_id) {
-            Some(proposal) => ApiResponse {
-                success: true,
-                data: Some(ProposalStatus::from(proposal.status.clone())),
-                error: None,
-            },
-            None => ApiResponse {
-                success: false,
-                data: None,
-                error: Some("Proposal not found".to_string()),
-            },
+            Some(proposal) => ApiResponse::ok(ProposalStatus::from(proposal.status.clone())),
+            None => ApiResponse::err(Error::NotFound("Proposal not found".to_string())),
         }
     }
 }
@@ -148,6 +547,23 @@ pub struct Proposal {
     pub category: crate::governance::democracy::ProposalCategory,
     pub required_quorum: f64,
     pub execution_timestamp: Option<DateTime<Utc>>,
+    /// IPFS documents to attach.
+    #[serde(default)]
+    pub attachments: Vec<crate::ipfs::Cid>,
+    /// What the proposal does to the chain once enacted, e.g. reserving
+    /// lanes. The proposal then takes the type and category of the action.
+    #[serde(default)]
+    pub action: Option<crate::governance::ProposalAction>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookRegistration {
+    /// `http://host[:port]/path` to POST notifications to.
+    pub url: String,
+    /// Key of the HMAC in each notification's `X-ICN-Signature` header.
+    pub secret: String,
+    #[serde(default)]
+    pub filter: WebhookFilter,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -197,9 +613,10 @@ mod tests {
     use super::*;
     use crate::currency::CurrencyType;
 
-    // Helper function to create a mock ApiLayer for testing
+    // Helper function to create a mock ApiLayer for testing, with Alice funded
     async fn create_mock_api_layer() -> ApiLayer {
-        let blockchain = Arc::new(RwLock::new(Blockchain::new()));
+        let spec = crate::blockchain::ChainSpec::default().with_allocation("Alice", 1000.0, CurrencyType::BasicNeeds);
+        let blockchain = Arc::new(RwLock::new(Blockchain::with_spec(spec)));
         let governance = Arc::new(RwLock::new(DemocraticSystem::new()));
 
         ApiLayer::new(blockchain, governance)
@@ -217,15 +634,191 @@ mod tests {
     async fn test_submit_transaction() {
         let api = create_mock_api_layer().await;
         let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), 100.0, CurrencyType::BasicNeeds, 1000);
-        let result = api.submit_transaction(transaction).await;
+        let result = api.submit_transaction(transaction.clone()).await;
         assert!(result.success);
+
+        assert_eq!(api.get_transaction_receipt(&transaction.hash()).await.error_code, Some(900));
+        api.blockchain.write().await.create_block("Miner1".to_string()).unwrap();
+        let receipt = api.get_transaction_receipt(&transaction.hash()).await.data.unwrap();
+        assert_eq!((receipt.block_index, receipt.gas_used), (1, crate::blockchain::receipt::TRANSFER_GAS));
     }
 
     #[tokio::test]
     async fn test_get_balance() {
         let api = create_mock_api_layer().await;
-        let balance = api.get_balance("Alice").await;
+        let balance = api.get_balance("Bob").await;
         assert!(balance.success);
         assert_eq!(balance.data.unwrap(), 0.0); // Initial balance
     }
+
+    #[tokio::test]
+    async fn test_graphql_block_transactions() {
+        let api = create_mock_api_layer().await;
+        let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), 25.0, CurrencyType::BasicNeeds, 1000);
+        api.submit_transaction(transaction.clone()).await;
+        api.blockchain.write().await.create_block("Miner1".to_string()).unwrap();
+
+        let query = "{ latestBlock { index transactions { hash to { address balance(currency: BasicNeeds) } receipt { success } } } }";
+        let response = api.graphql(graphql::GraphQLRequest::new(query)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let block = &response.data.unwrap()["latestBlock"];
+        assert_eq!(block["index"], 1);
+        assert_eq!(block["transactions"][0]["hash"], transaction.hash().as_str());
+        assert_eq!(block["transactions"][0]["to"]["balance"], 25.0);
+        assert_eq!(block["transactions"][0]["receipt"]["success"], true);
+
+        let response = api.graphql(graphql::GraphQLRequest::new("{ identity(did: \"did:icn:x\") { id } }")).await;
+        assert!(response.errors[0].message.contains("not served"));
+    }
+
+    #[tokio::test]
+    async fn test_organization_proposals_need_a_member() {
+        let api = create_mock_api_layer().await;
+        api.submit_transaction(Transaction::create_organization("Alice".to_string(), "bakery".to_string(), 1000)).await;
+        api.blockchain.write().await.create_block("Miner1".to_string()).unwrap();
+        let proposal = |proposer: &str| Proposal {
+            title: "New oven".to_string(),
+            description: String::new(),
+            proposer: proposer.to_string(),
+            voting_period: Duration::days(1),
+            proposal_type: crate::governance::ProposalType::EconomicAdjustment,
+            category: crate::governance::ProposalCategory::Economic,
+            required_quorum: 0.5,
+            execution_timestamp: None,
+            attachments: Vec::new(),
+            action: None,
+        };
+        assert_eq!(api.create_organization_proposal("org:bakery", proposal("Mallory")).await.error.unwrap(), "Governance error: Mallory is not a member of org:bakery");
+        let proposal_id = api.create_organization_proposal("org:bakery", proposal("Alice")).await.data.unwrap();
+        assert_eq!(api.governance.read().await.get_proposal(&proposal_id).unwrap().proposer, "org:bakery");
+    }
+
+    #[tokio::test]
+    async fn test_ban_list() {
+        let config = crate::network::peer_scoring::ScoringConfig { ban_threshold: -50.0, ..Default::default() };
+        let network = crate::network::Network::new().with_scoring_config(config);
+        let api = create_mock_api_layer().await.with_network(network.clone());
+        assert!(api.get_banned_peers().await.data.unwrap().is_empty());
+
+        network.report_misbehavior("mallory", crate::network::Misbehavior::InvalidBlock).await;
+        let bans = api.get_banned_peers().await.data.unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].peer_id, "mallory");
+
+        assert!(api.unban_peer("mallory").await.success);
+        let response = api.unban_peer("mallory").await;
+        assert!(!response.success);
+        assert_eq!(response.error_code, Some(900));
+    }
+
+    #[tokio::test]
+    async fn test_onboarding_undoes_every_step_when_one_fails() {
+        use crate::identity::DidManager;
+        use crate::sharding::ShardingManager;
+        use ed25519_dalek::Keypair;
+        use rand::rngs::OsRng;
+
+        let dids = Arc::new(std::sync::RwLock::new(DidManager::new()));
+        let sharding = Arc::new(std::sync::RwLock::new(ShardingManager::new(2, 10)));
+        let mut federation = Federation::new();
+        federation.join("bakery", "Bakery").unwrap();
+        let bread = federation.issue_currency("bakery", "bread").unwrap();
+        let federation = Arc::new(std::sync::RwLock::new(federation));
+        let keypair = Keypair::generate(&mut OsRng {});
+        let issuer = MembershipIssuer { did: crate::wallet::address_of(&keypair.public), keypair };
+        let api = create_mock_api_layer().await
+            .with_did_manager(dids.clone())
+            .with_sharding_manager(sharding.clone())
+            .with_onboarding(federation.clone(), issuer);
+
+        let key = Keypair::generate(&mut OsRng {});
+        let request = |cooperative: &str| OnboardingRequest { public_key: key.public, cooperative: cooperative.to_string(), attributes: Default::default() };
+        let response = api.onboard_member(request("mill")).await;
+        assert_eq!(response.error.unwrap(), "Governance error: No cooperative mill");
+        let did = crate::identity::DecentralizedIdentity::from_public_key(key.public, Default::default()).id;
+        assert!(dids.read().unwrap().get_did(&did).is_none(), "the DID registered first was removed");
+
+        let onboarding = api.onboard_member(request("bakery")).await.data.unwrap();
+        assert_eq!(onboarding.did, did);
+        assert!(onboarding.credential.verify_signature());
+        assert!(federation.read().unwrap().get("bakery").unwrap().is_member(&did));
+        assert_eq!(onboarding.currencies, vec![CurrencyType::BasicNeeds, bread]);
+        assert_eq!(sharding.read().unwrap().get_balances(&onboarding.address).unwrap().len(), 2, "an account in each currency");
+        assert!(!api.onboard_member(request("bakery")).await.success, "a key is onboarded once");
+    }
+
+    #[tokio::test]
+    async fn test_cross_shard_transaction_audit() {
+        use crate::sharding::{CrossShardPhase, ShardingManager};
+        use ed25519_dalek::Keypair;
+        use rand::rngs::OsRng;
+
+        let mut manager = ShardingManager::new(2, 10);
+        manager.add_address_to_shard("Alice".to_string(), 0);
+        manager.add_address_to_shard("Bob".to_string(), 1);
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 100.0).unwrap();
+        let coordinator = Arc::new(std::sync::RwLock::new(CrossShardTransactionManager::new()));
+        let api = create_mock_api_layer().await.with_cross_shard_coordinator(coordinator.clone());
+        assert_eq!(api.get_shard_metrics().await.error_code, Some(901));
+
+        let mut transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), 40.0, CurrencyType::BasicNeeds, 1000);
+        transaction.sign(&Keypair::generate(&mut OsRng {})).unwrap();
+        let committed = coordinator.write().unwrap().execute(&manager, transaction.clone(), 0, 1).unwrap();
+        let pending = coordinator.write().unwrap().begin(transaction, 0, 1).unwrap();
+
+        assert_eq!(api.get_transaction_status(&committed).await.data, Some(CrossShardTransactionStatus::Committed));
+        assert_eq!(api.get_transaction_status(&pending).await.data, Some(CrossShardTransactionStatus::Preparing));
+        assert!(!api.get_transaction_status("unknown").await.success);
+        let audit = api.get_cross_shard_audit_trail(&committed).await.data.unwrap();
+        assert_eq!(audit.last().unwrap().phase, CrossShardPhase::Committed);
+
+        let stuck = api.get_stuck_transactions(Duration::milliseconds(-1)).await.data.unwrap();
+        assert_eq!(stuck.iter().map(|record| record.id.clone()).collect::<Vec<_>>(), vec![pending]);
+        assert!(api.get_stuck_transactions(Duration::minutes(5)).await.data.unwrap().is_empty());
+
+        let api = api.with_sharding_manager(Arc::new(std::sync::RwLock::new(manager)));
+        let metrics = api.get_shard_metrics().await.data.unwrap();
+        assert_eq!((metrics[0].transaction_count, metrics[1].transaction_count), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_notified_of_credit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/icn", listener.local_addr().unwrap());
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let service = Arc::new(std::sync::Mutex::new(WebhookService::default()));
+        let api = create_mock_api_layer().await.with_webhooks(service.clone());
+        let filter = WebhookFilter { kinds: [webhooks::EventKind::BalanceCredited].into(), addresses: ["Bob".to_string()].into(), ..Default::default() };
+        let registration = WebhookRegistration { url, secret: "s3cret".to_string(), filter };
+        assert!(api.register_webhook(registration).await.success);
+        api.poll_webhooks().await;
+
+        api.submit_transaction(Transaction::new("Alice".to_string(), "Bob".to_string(), 25.0, CurrencyType::BasicNeeds, 1000)).await;
+        api.blockchain.write().await.create_block("Miner1".to_string()).unwrap();
+        api.poll_webhooks().await;
+
+        let request = received.await.unwrap();
+        assert!(request.starts_with("POST /hooks/icn HTTP/1.1"));
+        assert!(request.contains("X-ICN-Event: balance_credited"));
+        let header = |name: &str| request.lines().find_map(|line| line.strip_prefix(name)).unwrap().trim().to_string();
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        let timestamp: i64 = header("X-ICN-Timestamp:").parse().unwrap();
+        assert_eq!(header("X-ICN-Signature:"), webhooks::signature("s3cret", timestamp, body.as_bytes()));
+        let notification: webhooks::Notification = serde_json::from_str(body).unwrap();
+        assert!(matches!(notification.event, webhooks::WebhookEvent::BalanceCredited { amount, .. } if amount == 25.0));
+        assert_eq!(service.lock().unwrap().pending().count(), 0);
+    }
 }
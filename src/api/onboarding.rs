@@ -0,0 +1,135 @@
+// src/api/onboarding.rs
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use ed25519_dalek::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::governance::Federation;
+use crate::identity::disclosure::AttributeSecret;
+use crate::identity::{CommittedCredential, DecentralizedIdentity, DidManager};
+use crate::sharding::ShardingManager;
+
+/// The DID that signs membership credentials, and the key it derives from.
+pub struct MembershipIssuer {
+    pub did: String,
+    pub keypair: Keypair,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnboardingRequest {
+    /// The key the new member generated; the secret never leaves them.
+    #[serde(with = "crate::identity::did::public_key_serde")]
+    pub public_key: PublicKey,
+    /// The cooperative of the federation joined.
+    pub cooperative: String,
+    /// Attributes of the member, recorded in their DID and credential.
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+}
+
+/// What a member was given on joining.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Onboarding {
+    pub did: String,
+    /// The wallet address of the member's key.
+    pub address: String,
+    pub credential: CommittedCredential,
+    /// The openings of the credential's commitments, for the member to keep.
+    pub secrets: HashMap<String, AttributeSecret>,
+    pub cooperative: String,
+    /// The currencies the member has an account in.
+    pub currencies: Vec<CurrencyType>,
+    pub shard_id: u64,
+}
+
+/// A step of onboarding that went through, undone if a later one fails.
+enum Step {
+    Did(String),
+    Membership { cooperative: String, member: String },
+    Accounts(String),
+}
+
+/// Runs the steps of onboarding one member, undoing those done if one fails.
+pub(crate) struct Onboarder<'a> {
+    pub dids: &'a RwLock<DidManager>,
+    pub federation: &'a RwLock<Federation>,
+    pub sharding: &'a RwLock<ShardingManager>,
+    pub issuer: &'a MembershipIssuer,
+    done: Vec<Step>,
+}
+
+impl<'a> Onboarder<'a> {
+    pub fn new(dids: &'a RwLock<DidManager>, federation: &'a RwLock<Federation>, sharding: &'a RwLock<ShardingManager>, issuer: &'a MembershipIssuer) -> Self {
+        Onboarder { dids, federation, sharding, issuer, done: Vec::new() }
+    }
+
+    pub fn onboard(mut self, request: OnboardingRequest) -> Result<Onboarding> {
+        match self.run(request) {
+            Ok(onboarding) => {
+                info!("Onboarded {} into {}", onboarding.did, onboarding.cooperative);
+                Ok(onboarding)
+            }
+            Err(e) => {
+                warn!("Onboarding failed, undoing {} steps: {}", self.done.len(), e);
+                self.roll_back();
+                Err(e)
+            }
+        }
+    }
+
+    fn run(&mut self, request: OnboardingRequest) -> Result<Onboarding> {
+        let did = DecentralizedIdentity::from_public_key(request.public_key, request.attributes.clone());
+        let did_id = did.id.clone();
+        {
+            let mut dids = self.dids.write().unwrap();
+            if dids.get_did(&did_id).is_some() {
+                return Err(Error::IdentityError(format!("{} is already registered", did_id)));
+            }
+            dids.add_did(did);
+        }
+        self.done.push(Step::Did(did_id.clone()));
+
+        let mut attributes = request.attributes;
+        attributes.insert("cooperative".to_string(), request.cooperative.clone());
+        let (credential, secrets) = CommittedCredential::issue(&self.issuer.did, &self.issuer.keypair, &did_id, &attributes);
+
+        let currencies = {
+            let mut federation = self.federation.write().unwrap();
+            federation.admit(&request.cooperative, &did_id).map_err(Error::GovernanceError)?;
+            self.done.push(Step::Membership { cooperative: request.cooperative.clone(), member: did_id.clone() });
+            let cooperative = federation.get(&request.cooperative).expect("the member was just admitted");
+            std::iter::once(CurrencyType::BasicNeeds).chain(cooperative.currencies().iter().cloned()).collect::<Vec<_>>()
+        };
+
+        let address = crate::wallet::address_of(&request.public_key);
+        let shard_id = {
+            let mut sharding = self.sharding.write().unwrap();
+            let shard_id = sharding.get_shard_for_address(&address);
+            sharding.add_address_to_shard(address.clone(), shard_id);
+            self.done.push(Step::Accounts(address.clone()));
+            for currency_type in &currencies {
+                sharding.initialize_balance(address.clone(), currency_type.clone(), 0.0)?;
+            }
+            shard_id
+        };
+
+        Ok(Onboarding { did: did_id, address, credential, secrets, cooperative: request.cooperative, currencies, shard_id })
+    }
+
+    fn roll_back(&mut self) {
+        while let Some(step) = self.done.pop() {
+            let undone = match &step {
+                Step::Did(did_id) => self.dids.write().unwrap().remove_did(did_id).map(|_| ())
+                    .ok_or_else(|| format!("DID {} is gone", did_id)),
+                Step::Membership { cooperative, member } => self.federation.write().unwrap().expel(cooperative, member),
+                Step::Accounts(address) => self.sharding.write().unwrap().remove_address(address).map_err(|e| e.to_string()),
+            };
+            if let Err(e) = undone {
+                warn!("Could not undo an onboarding step: {}", e);
+            }
+        }
+    }
+}
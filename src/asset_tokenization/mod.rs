@@ -27,6 +27,11 @@ impl AssetToken {
         }
     }
 
+    /// The IPFS content the metadata links to with `ipfs://` URIs.
+    pub fn references(&self) -> Vec<crate::ipfs::Cid> {
+        crate::ipfs::references_in(&self.metadata)
+    }
+
     pub fn transfer(&mut self, new_owner: String) {
         self.owner = new_owner;
         self.last_transferred = Utc::now();
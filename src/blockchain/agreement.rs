@@ -0,0 +1,234 @@
+// src/blockchain/agreement.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use super::payout::event;
+use super::{Transaction, Transfer};
+
+/// The account holding the payments of service agreements in escrow, and
+/// paying them out.
+pub const AGREEMENT_ACCOUNT: &str = "icn:agreements";
+/// Jurors drawn to decide a dispute.
+pub const JURY_SIZE: usize = 3;
+
+/// What a service agreement transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AgreementAction {
+    /// Escrows the amount of the transaction as the payment to `provider`
+    /// for the service described by `terms`.
+    Open { provider: String, terms: String },
+    /// Pays the provider, sent by the client once the service is delivered.
+    Release { agreement_id: String },
+    /// Holds the payment until a jury or governance decides who gets it,
+    /// sent by either party.
+    Dispute { agreement_id: String, reason: String },
+    /// Adds a statement or the CID of a document to a dispute, sent by
+    /// either party.
+    Evidence { agreement_id: String, evidence: String },
+    /// The vote of a juror on a dispute: for the provider to be paid, or
+    /// the client refunded.
+    Rule { agreement_id: String, for_provider: bool },
+}
+
+/// A breach of a service agreement raised by one of its parties.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dispute {
+    pub raised_by: String,
+    pub reason: String,
+    /// Index of the block the dispute was raised in.
+    pub raised_at: u64,
+    /// Submitted by the parties, in order, with who submitted it.
+    pub evidence: Vec<(String, String)>,
+    /// Drawn from the members with stake locked, other than the
+    /// parties when the dispute was raised; see `draw_jury`.
+    pub jury: Vec<String>,
+    /// Votes of the jurors, true for the provider.
+    pub votes: BTreeMap<String, bool>,
+}
+
+impl Dispute {
+    /// The side a majority of the whole jury voted for, if any yet.
+    pub fn verdict(&self) -> Option<bool> {
+        let majority = self.jury.len() / 2 + 1;
+        [true, false].into_iter().find(|side| self.votes.values().filter(|vote| *vote == side).count() >= majority)
+    }
+}
+
+/// A service paid for in advance into escrow, released to the provider on
+/// delivery or, if disputed, as decided by a jury or governance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceAgreement {
+    /// Hash of the transaction that opened the agreement.
+    pub id: String,
+    pub client: String,
+    pub provider: String,
+    pub terms: String,
+    pub currency_type: CurrencyType,
+    pub payment: f64,
+    /// Index of the block the agreement was opened in.
+    pub opened_at: u64,
+    pub dispute: Option<Dispute>,
+}
+
+impl ServiceAgreement {
+    fn is_party(&self, address: &str) -> bool {
+        self.client == address || self.provider == address
+    }
+}
+
+/// The service agreements whose payment is still in escrow. Releasing and
+/// resolving disputes pay out of `AGREEMENT_ACCOUNT`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceAgreements {
+    agreements: BTreeMap<String, ServiceAgreement>,
+}
+
+impl ServiceAgreements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, agreement_id: &str) -> Option<&ServiceAgreement> {
+        self.agreements.get(agreement_id)
+    }
+
+    /// The agreements `address` is a party to or sits on the jury of.
+    pub fn of(&self, address: &str) -> Vec<&ServiceAgreement> {
+        self.agreements.values()
+            .filter(|agreement| agreement.is_party(address) || agreement.dispute.as_ref().is_some_and(|dispute| dispute.jury.iter().any(|juror| juror == address)))
+            .collect()
+    }
+
+    /// Applies the agreement action of `transaction`, in the block at
+    /// `index` whose parent is `parent_hash`, returning its event and the
+    /// payouts it makes. Juries are drawn from `candidates`.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64, parent_hash: &str, candidates: &[String]) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        let by = &transaction.from;
+        match &transaction.agreement {
+            Some(AgreementAction::Open { provider, terms }) => {
+                if !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+                    return Err("A service agreement needs a positive payment".to_string());
+                }
+                if provider == by {
+                    return Err("A service agreement needs a provider other than its client".to_string());
+                }
+                if transaction.to != AGREEMENT_ACCOUNT {
+                    return Err(format!("A service agreement holds its payment with {}", AGREEMENT_ACCOUNT));
+                }
+                if self.agreements.contains_key(&transaction.hash()) {
+                    return Err(format!("Agreement {} is already open", transaction.hash()));
+                }
+                let agreement = ServiceAgreement {
+                    id: transaction.hash(),
+                    client: by.clone(),
+                    provider: provider.clone(),
+                    terms: terms.clone(),
+                    currency_type: transaction.currency_type.clone(),
+                    payment: transaction.amount,
+                    opened_at: index,
+                    dispute: None,
+                };
+                info!("Agreement {} escrows {} {} from {} for {}", agreement.id, agreement.payment, agreement.currency_type, agreement.client, agreement.provider);
+                let event = event(&agreement.id, "AgreementOpened", format!("{} for {}", agreement.payment, agreement.provider));
+                self.agreements.insert(agreement.id.clone(), agreement);
+                Ok((event, Vec::new()))
+            }
+            Some(AgreementAction::Release { agreement_id }) => {
+                let agreement = self.agreement(agreement_id)?;
+                if agreement.client != *by {
+                    return Err(format!("{} is not the client of agreement {}", by, agreement_id));
+                }
+                if agreement.dispute.is_some() {
+                    return Err(format!("Agreement {} is in dispute", agreement_id));
+                }
+                let payout = self.settle(agreement_id, true);
+                Ok((event(agreement_id, "PaymentReleased", String::new()), payout))
+            }
+            Some(AgreementAction::Dispute { agreement_id, reason }) => {
+                let agreement = self.agreements.get_mut(agreement_id).ok_or_else(|| format!("No open agreement {}", agreement_id))?;
+                if !agreement.is_party(by) {
+                    return Err(format!("{} is not a party to agreement {}", by, agreement_id));
+                }
+                if agreement.dispute.is_some() {
+                    return Err(format!("Agreement {} is already in dispute", agreement_id));
+                }
+                let jury = draw_jury(agreement, parent_hash, candidates);
+                info!("{} disputes agreement {} before jurors {:?}", by, agreement_id, jury);
+                let event = event(agreement_id, "DisputeRaised", reason.clone());
+                agreement.dispute = Some(Dispute { raised_by: by.clone(), reason: reason.clone(), raised_at: index, evidence: Vec::new(), jury, votes: BTreeMap::new() });
+                Ok((event, Vec::new()))
+            }
+            Some(AgreementAction::Evidence { agreement_id, evidence }) => {
+                let agreement = self.agreements.get_mut(agreement_id).ok_or_else(|| format!("No open agreement {}", agreement_id))?;
+                if !agreement.is_party(by) {
+                    return Err(format!("{} is not a party to agreement {}", by, agreement_id));
+                }
+                let dispute = agreement.dispute.as_mut().ok_or_else(|| format!("Agreement {} is not in dispute", agreement_id))?;
+                dispute.evidence.push((by.clone(), evidence.clone()));
+                Ok((event(agreement_id, "EvidenceSubmitted", evidence.clone()), Vec::new()))
+            }
+            Some(AgreementAction::Rule { agreement_id, for_provider }) => {
+                let agreement = self.agreements.get_mut(agreement_id).ok_or_else(|| format!("No open agreement {}", agreement_id))?;
+                let dispute = agreement.dispute.as_mut().ok_or_else(|| format!("Agreement {} is not in dispute", agreement_id))?;
+                if !dispute.jury.contains(by) {
+                    return Err(format!("{} is not on the jury of agreement {}", by, agreement_id));
+                }
+                dispute.votes.insert(by.clone(), *for_provider);
+                match dispute.verdict() {
+                    Some(for_provider) => {
+                        let payout = self.settle(agreement_id, for_provider);
+                        Ok((event(agreement_id, "DisputeResolved", verdict_text(for_provider).to_string()), payout))
+                    }
+                    None => Ok((event(agreement_id, "JurorVoted", by.clone()), Vec::new())),
+                }
+            }
+            None => Err("Not a service agreement transaction".to_string()),
+        }
+    }
+
+    /// Resolves a dispute as ruled by the enacted proposal `proposal_id`,
+    /// for one whose jury cannot decide it, e.g. for want of jurors.
+    /// Returns the payout it makes.
+    pub fn resolve(&mut self, agreement_id: &str, for_provider: bool, proposal_id: &str) -> Result<Vec<Transfer>, String> {
+        if self.agreement(agreement_id)?.dispute.is_none() {
+            return Err(format!("Agreement {} is not in dispute", agreement_id));
+        }
+        info!("Dispute over agreement {} resolved by proposal {}", agreement_id, proposal_id);
+        Ok(self.settle(agreement_id, for_provider))
+    }
+
+    fn agreement(&self, agreement_id: &str) -> Result<&ServiceAgreement, String> {
+        self.agreements.get(agreement_id).ok_or_else(|| format!("No open agreement {}", agreement_id))
+    }
+
+    /// Closes an agreement, paying the provider or refunding the client.
+    fn settle(&mut self, agreement_id: &str, for_provider: bool) -> Vec<Transfer> {
+        let agreement = match self.agreements.remove(agreement_id) {
+            Some(agreement) => agreement,
+            None => return Vec::new(),
+        };
+        let to = if for_provider { &agreement.provider } else { &agreement.client };
+        info!("Agreement {} closed, {}", agreement_id, verdict_text(for_provider));
+        vec![Transfer { from: AGREEMENT_ACCOUNT.to_string(), to: to.clone(), amount: agreement.payment, currency_type: agreement.currency_type.clone() }]
+    }
+}
+
+/// The jury of a dispute over `agreement`: the `JURY_SIZE` candidates
+/// other than its parties first when ordered by the hash of the parent of
+/// the block raising the dispute, the agreement and their id. No party can
+/// pick the jurors, yet every node draws the same.
+fn draw_jury(agreement: &ServiceAgreement, parent_hash: &str, candidates: &[String]) -> Vec<String> {
+    let mut drawn: Vec<(Vec<u8>, &String)> = candidates.iter()
+        .filter(|candidate| !agreement.is_party(candidate))
+        .map(|candidate| (Sha256::digest(format!("{}:{}:{}", parent_hash, agreement.id, candidate).as_bytes()).to_vec(), candidate))
+        .collect();
+    drawn.sort();
+    drawn.into_iter().take(JURY_SIZE).map(|(_, candidate)| candidate.clone()).collect()
+}
+
+fn verdict_text(for_provider: bool) -> &'static str {
+    if for_provider { "paying the provider" } else { "refunding the client" }
+}
@@ -0,0 +1,147 @@
+// src/blockchain/allowance.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::Transaction;
+
+/// The account allowances are granted and revoked with.
+pub const ALLOWANCE_ACCOUNT: &str = "icn:allowances";
+
+/// What an allowance transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AllowanceAction {
+    /// Lets `spender` pay up to `limit` of the currency of the transaction
+    /// on behalf of its sender, per `period` seconds if set, otherwise in
+    /// all.
+    Grant { spender: String, limit: f64, period: Option<i64> },
+    /// Withdraws the allowance of `spender` in the currency of the
+    /// transaction.
+    Revoke { spender: String },
+    /// Set on a transfer `spender` makes out of the allowance its sender
+    /// granted it, signed by `spender`.
+    Spend { spender: String },
+}
+
+/// What a spender may pay on behalf of an owner, in one currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Allowance {
+    pub owner: String,
+    pub spender: String,
+    pub currency_type: CurrencyType,
+    pub limit: f64,
+    /// Seconds after which the limit renews; `None` if it never does.
+    pub period: Option<i64>,
+    /// Unix time in seconds the current period started at.
+    pub period_start: i64,
+    /// Spent in the current period.
+    pub spent: f64,
+    /// Spent since the allowance was granted.
+    pub total_spent: f64,
+}
+
+impl Allowance {
+    /// Spent in the period running at `timestamp`.
+    pub fn spent_at(&self, timestamp: i64) -> f64 {
+        match self.period {
+            Some(period) if timestamp >= self.period_start + period => 0.0,
+            _ => self.spent,
+        }
+    }
+
+    /// What may still be spent at `timestamp`.
+    pub fn remaining_at(&self, timestamp: i64) -> f64 {
+        (self.limit - self.spent_at(timestamp)).max(0.0)
+    }
+
+    fn spend(&mut self, amount: f64, timestamp: i64) {
+        if let Some(period) = self.period.filter(|period| timestamp >= self.period_start + period) {
+            self.period_start += (timestamp - self.period_start) / period * period;
+            self.spent = 0.0;
+        }
+        self.spent += amount;
+        self.total_spent += amount;
+    }
+}
+
+/// The allowances in force, by owner, spender and currency.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Allowances {
+    allowances: BTreeMap<String, Allowance>,
+}
+
+impl Allowances {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, owner: &str, spender: &str, currency_type: &CurrencyType) -> Option<&Allowance> {
+        self.allowances.get(&key(owner, spender, currency_type))
+    }
+
+    /// The allowances `address` has granted or been granted.
+    pub fn of(&self, address: &str) -> Vec<&Allowance> {
+        self.allowances.values().filter(|allowance| allowance.owner == address || allowance.spender == address).collect()
+    }
+
+    /// Fails for a transfer out of an allowance that was not granted, or
+    /// that does not cover its amount at `timestamp`.
+    pub fn check(&self, transaction: &Transaction, timestamp: i64) -> Result<(), String> {
+        let spender = match &transaction.allowance {
+            Some(AllowanceAction::Spend { spender }) => spender,
+            _ => return Ok(()),
+        };
+        let allowance = self.get(&transaction.from, spender, &transaction.currency_type)
+            .ok_or_else(|| format!("{} has no {} allowance from {}", spender, transaction.currency_type, transaction.from))?;
+        let remaining = allowance.remaining_at(timestamp);
+        if transaction.amount > remaining {
+            return Err(format!("{} may spend only {} more {} of {}", spender, remaining, transaction.currency_type, transaction.from));
+        }
+        Ok(())
+    }
+
+    /// Applies the allowance action of `transaction`, in a block timestamped
+    /// `timestamp`.
+    pub fn apply(&mut self, transaction: &Transaction, timestamp: i64) -> Result<(), String> {
+        let owner = &transaction.from;
+        let currency_type = &transaction.currency_type;
+        match &transaction.allowance {
+            Some(AllowanceAction::Grant { spender, limit, period }) => {
+                if !limit.is_finite() || *limit <= 0.0 || period.is_some_and(|period| period <= 0) {
+                    return Err("An allowance needs a positive limit and period".to_string());
+                }
+                if spender == owner {
+                    return Err("An allowance cannot be granted to its owner".to_string());
+                }
+                info!("{} allows {} to spend {} {} on its behalf", owner, spender, limit, currency_type);
+                self.allowances.insert(key(owner, spender, currency_type), Allowance {
+                    owner: owner.clone(),
+                    spender: spender.clone(),
+                    currency_type: currency_type.clone(),
+                    limit: *limit,
+                    period: *period,
+                    period_start: timestamp,
+                    spent: 0.0,
+                    total_spent: 0.0,
+                });
+                Ok(())
+            }
+            Some(AllowanceAction::Revoke { spender }) => {
+                self.allowances.remove(&key(owner, spender, currency_type))
+                    .map(|_| info!("{} revoked the {} allowance of {}", owner, currency_type, spender))
+                    .ok_or_else(|| format!("{} has no {} allowance from {}", spender, currency_type, owner))
+            }
+            Some(AllowanceAction::Spend { spender }) => {
+                self.check(transaction, timestamp)?;
+                let allowance = self.allowances.get_mut(&key(owner, spender, currency_type)).expect("the allowance was just checked");
+                allowance.spend(transaction.amount, timestamp);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn key(owner: &str, spender: &str, currency_type: &CurrencyType) -> String {
+    format!("{}/{}/{}", owner, spender, currency_type)
+}
@@ -0,0 +1,280 @@
+// src/blockchain/archive.rs
+use crate::blockchain::{Block, BlockHeader, Blockchain, ChainSpec};
+use crate::consensus::PoCConsensus;
+use crate::error::{Error, Result};
+use serde::{Serialize, Deserialize};
+use std::io::{BufRead, Write};
+
+/// Names the file format in the first record of every archive.
+pub const ARCHIVE_FORMAT: &str = "icn-chain";
+/// Version of the archive format written by this node. Archives of a later
+/// version are refused.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// One line of a chain archive. An archive is a header, the spec of the
+/// chain, the consensus state the votes on its blocks are checked against,
+/// then the blocks from genesis, one JSON record per line, so that it is written and read
+/// without holding the chain in memory twice.
+#[derive(Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    Header { format: String, version: u32, height: u64 },
+    Spec(ChainSpec),
+    Consensus(Box<PoCConsensus>),
+    Block(Box<Block>),
+}
+
+/// `ArchiveRecord` as written, borrowing from the chain.
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum RecordRef<'a> {
+    Header { format: &'a str, version: u32, height: u64 },
+    Spec(&'a ChainSpec),
+    Consensus(&'a PoCConsensus),
+    Block(&'a Block),
+}
+
+/// What re-validating an archive found. Any invalid hash, link, signature
+/// or vote fails the audit with an error instead.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChainAudit {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub signatures_verified: u64,
+    /// Transactions that carry no signature.
+    pub unsigned_transactions: u64,
+    /// Blocks whose votes reach the consensus threshold and quorum.
+    pub approved_blocks: u64,
+    /// Blocks with votes that do not.
+    pub unapproved_blocks: u64,
+}
+
+/// Checks the blocks of an archive one at a time, in order.
+#[derive(Default)]
+struct ChainVerifier {
+    /// The spec the archive names, whose genesis block it must start at.
+    spec: ChainSpec,
+    previous: Option<BlockHeader>,
+    audit: ChainAudit,
+}
+
+impl ChainVerifier {
+    fn check(&mut self, block: &Block, consensus: &PoCConsensus) -> Result<()> {
+        match &self.previous {
+            None if block.hash != self.spec.genesis().hash => {
+                return Err(Error::BlockchainError("Archive does not start at the genesis block".to_string()));
+            }
+            Some(previous) if block.index != previous.index + 1 || block.previous_hash != previous.hash => {
+                return Err(Error::BlockchainError(format!("Block {} does not link to its parent", block.index)));
+            }
+            _ => {}
+        }
+        if block.hash != block.calculate_hash() {
+            return Err(Error::BlockchainError(format!("Block {} has an invalid hash", block.index)));
+        }
+        if block.proposer_signature.is_some() && !consensus.public_key(&block.proposer).is_some_and(|key| block.header().verify_proposer(&key)) {
+            return Err(Error::ConsensusError(format!("Block {} has an invalid proposer signature", block.index)));
+        }
+        for transaction in &block.transactions {
+            self.audit.transactions += 1;
+            if transaction.signature.is_none() {
+                self.audit.unsigned_transactions += 1;
+            } else if transaction.verify() == Ok(true) {
+                self.audit.signatures_verified += 1;
+            } else {
+                return Err(Error::BlockchainError(format!(
+                    "Transaction {} in block {} has an invalid signature", transaction.hash(), block.index
+                )));
+            }
+        }
+        if let Some(votes) = consensus.votes.get(&block.hash) {
+            if let Some(voter) = votes.keys().find(|member_id| !consensus.is_validator(member_id)) {
+                return Err(Error::ConsensusError(format!("Block {} has a vote from {}, who is not a validator", block.index, voter)));
+            }
+            if consensus.is_approved(&consensus.tally(&block.hash)) {
+                self.audit.approved_blocks += 1;
+            } else {
+                self.audit.unapproved_blocks += 1;
+            }
+        }
+        self.audit.blocks += 1;
+        self.previous = Some(block.header());
+        Ok(())
+    }
+}
+
+/// Reads the records of an archive, checking its header first.
+fn records<R: BufRead>(reader: R) -> impl Iterator<Item = Result<ArchiveRecord>> {
+    let mut header_seen = false;
+    reader.lines().map(move |line| {
+        let record: ArchiveRecord = serde_json::from_str(&line?)
+            .map_err(|e| Error::BlockchainError(format!("Malformed archive record: {}", e)))?;
+        match &record {
+            ArchiveRecord::Header { format, version, .. } if !header_seen => {
+                if format != ARCHIVE_FORMAT {
+                    return Err(Error::BlockchainError(format!("Not a chain archive: {}", format)));
+                }
+                if *version > ARCHIVE_VERSION {
+                    return Err(Error::BlockchainError(format!("Unsupported archive version {}", version)));
+                }
+                header_seen = true;
+            }
+            _ if !header_seen => return Err(Error::BlockchainError("Archive has no header".to_string())),
+            ArchiveRecord::Header { .. } => return Err(Error::BlockchainError("Archive has a second header".to_string())),
+            _ => {}
+        }
+        Ok(record)
+    })
+}
+
+/// Re-validates the hashes, links, transaction signatures and consensus
+/// votes of an archive without importing it.
+pub fn verify<R: BufRead>(reader: R) -> Result<ChainAudit> {
+    let mut verifier = ChainVerifier::default();
+    let mut consensus = PoCConsensus::new(0.5, 0.66);
+    let mut height = None;
+    for record in records(reader) {
+        match record? {
+            ArchiveRecord::Header { height: expected, .. } => height = Some(expected),
+            ArchiveRecord::Spec(spec) => verifier.spec = spec,
+            ArchiveRecord::Consensus(archived) => consensus = *archived,
+            ArchiveRecord::Block(block) => verifier.check(&block, &consensus)?,
+        }
+    }
+    if height != Some(verifier.audit.blocks) {
+        return Err(Error::BlockchainError(format!(
+            "Archive holds {} blocks, its header announces {:?}", verifier.audit.blocks, height
+        )));
+    }
+    Ok(verifier.audit)
+}
+
+impl Blockchain {
+    /// Writes the chain to the archive file at `path`.
+    pub fn export(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_archive(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Rebuilds a chain from the archive file at `path`, verifying it as
+    /// `verify` does and executing its blocks as they are appended.
+    pub fn import(path: impl AsRef<std::path::Path>) -> Result<Blockchain> {
+        Self::read_archive(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn write_archive<W: Write>(&self, mut writer: W) -> Result<()> {
+        write_record(&mut writer, &RecordRef::Header { format: ARCHIVE_FORMAT, version: ARCHIVE_VERSION, height: self.height() })?;
+        write_record(&mut writer, &RecordRef::Spec(&self.spec))?;
+        write_record(&mut writer, &RecordRef::Consensus(&self.consensus))?;
+        for block in &self.chain {
+            write_record(&mut writer, &RecordRef::Block(block))?;
+        }
+        Ok(())
+    }
+
+    pub fn read_archive<R: BufRead>(reader: R) -> Result<Blockchain> {
+        let mut blockchain = Blockchain::new();
+        let mut verifier = ChainVerifier::default();
+        for record in records(reader) {
+            match record? {
+                ArchiveRecord::Header { .. } => {}
+                ArchiveRecord::Spec(spec) => {
+                    verifier.spec = spec.clone();
+                    blockchain = Blockchain::with_spec(spec);
+                }
+                ArchiveRecord::Consensus(consensus) => blockchain.consensus = *consensus,
+                ArchiveRecord::Block(block) => {
+                    verifier.check(&block, &blockchain.consensus)?;
+                    if block.index > 0 {
+                        blockchain.append_block(*block)?;
+                    }
+                }
+            }
+        }
+        Ok(blockchain)
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &RecordRef) -> Result<()> {
+    serde_json::to_writer(&mut *writer, record).map_err(|e| Error::BlockchainError(e.to_string()))?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Transaction;
+    use crate::currency::CurrencyType;
+    use crate::consensus::SignedVote;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    fn archive(blockchain: &Blockchain) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        blockchain.write_archive(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_archive_round_trip_and_audit() {
+        let alice = Keypair::generate(&mut OsRng {});
+        let alice_address = crate::wallet::address_of(&alice.public);
+        let mut blockchain = Blockchain::with_spec(ChainSpec::default().with_allocation(&alice_address, 10.0, CurrencyType::BasicNeeds));
+        let validator = Keypair::generate(&mut OsRng {});
+        blockchain.consensus.add_member("validator".to_string(), true);
+        blockchain.consensus.register_key("validator", &validator.public).unwrap();
+        let mut signed = Transaction::new(alice_address.clone(), "Bob".to_string(), 10.0, CurrencyType::BasicNeeds, 1000);
+        signed.sign(&alice).unwrap();
+        blockchain.add_transaction(signed).unwrap();
+        blockchain.add_transaction(Transaction::new("Bob".to_string(), "Carol".to_string(), 4.0, CurrencyType::BasicNeeds, 1000)).unwrap();
+        blockchain.create_signed_block("validator".to_string(), &validator).unwrap();
+        let hash = blockchain.chain[1].hash.clone();
+        blockchain.submit_vote(&SignedVote::new("validator".to_string(), hash.clone(), 1, true, &validator)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("icn-chain-{}.jsonl", uuid::Uuid::new_v4()));
+        blockchain.export(&path).unwrap();
+        let imported = Blockchain::import(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(imported.height(), 2);
+        assert_eq!(imported.get_balance("Carol"), 4.0);
+        assert_eq!(imported.get_balance(&alice_address), 0.0, "the genesis allocation is paid again");
+        assert!(imported.is_block_approved(&hash));
+
+        let audit = verify(archive(&blockchain).as_slice()).unwrap();
+        assert_eq!(audit, ChainAudit {
+            blocks: 2,
+            transactions: 2,
+            signatures_verified: 1,
+            unsigned_transactions: 1,
+            approved_blocks: 1,
+            unapproved_blocks: 0,
+        });
+    }
+
+    #[test]
+    fn test_tampered_archive_rejected() {
+        let alice = Keypair::generate(&mut OsRng {});
+        let alice_address = crate::wallet::address_of(&alice.public);
+        let mut blockchain = Blockchain::with_spec(ChainSpec::default().with_allocation(&alice_address, 10.0, CurrencyType::BasicNeeds));
+        let mut transaction = Transaction::new(alice_address, "Bob".to_string(), 10.0, CurrencyType::BasicNeeds, 1000);
+        transaction.sign(&alice).unwrap();
+        blockchain.add_transaction(transaction).unwrap();
+        blockchain.create_block("proposer".to_string()).unwrap();
+
+        // A forged amount breaks the signature even with the hashes redone
+        blockchain.chain[1].transactions[0].amount = 1000.0;
+        blockchain.chain[1].hash = blockchain.chain[1].calculate_hash();
+        let error = verify(archive(&blockchain).as_slice()).unwrap_err();
+        assert!(error.to_string().contains("invalid signature"), "{}", error);
+
+        blockchain.chain[1].gas_used += 1;
+        let error = Blockchain::read_archive(archive(&blockchain).as_slice()).err().unwrap();
+        assert!(error.to_string().contains("invalid hash"), "{}", error);
+
+        let newer = String::from_utf8(archive(&blockchain)).unwrap().replacen("\"version\":1", "\"version\":2", 1);
+        assert!(verify(newer.as_bytes()).unwrap_err().to_string().contains("Unsupported archive version"));
+    }
+}
@@ -0,0 +1,126 @@
+// src/blockchain/balance.rs
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::currency::CurrencyType;
+use super::{Block, Transfer};
+
+/// What one block moved into (positive) or out of (negative) an address.
+type BlockDelta = Vec<(String, CurrencyType, f64)>;
+
+/// Balances of an address in one currency after each block that changed
+/// it, as the number of blocks committed and the balance then, oldest first.
+type History = Vec<(u64, f64)>;
+
+/// The balances of every address, kept up to date as blocks are committed
+/// instead of summed from the whole chain on each query. The change each
+/// block made is kept too, to roll blocks back, and the history of each
+/// balance, to tell past balances.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BalanceIndex {
+    /// Balance by currency, in the order the address first held them.
+    balances: HashMap<String, Vec<(CurrencyType, f64)>>,
+    /// Changes by block index.
+    blocks: Vec<BlockDelta>,
+    #[serde(default)]
+    history: HashMap<String, Vec<(CurrencyType, History)>>,
+}
+
+impl BalanceIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of blocks indexed.
+    pub fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    /// Applies the transfers of the next block, leaving out those of the
+    /// transactions at the positions `failed` tells, then the payouts the
+    /// block made.
+    pub fn commit(&mut self, block: &Block, failed: impl Fn(usize) -> bool, payouts: &[Transfer]) {
+        let mut delta = BlockDelta::new();
+        let transfers = block.transactions.iter().enumerate()
+            .filter(|(i, _)| !failed(*i))
+            .flat_map(|(_, transaction)| transaction.transfers());
+        for transfer in transfers.chain(payouts.iter().cloned()) {
+            delta.push((transfer.from.clone(), transfer.currency_type.clone(), -transfer.amount));
+            delta.push((transfer.to, transfer.currency_type, transfer.amount));
+        }
+        let committed = self.height() + 1;
+        for (address, currency_type, amount) in &delta {
+            let balance = self.adjust(address, currency_type, *amount);
+            let history = self.history_mut(address, currency_type);
+            match history.last_mut() {
+                Some((height, last)) if *height == committed => *last = balance,
+                _ => history.push((committed, balance)),
+            }
+        }
+        self.blocks.push(delta);
+    }
+
+    /// Undoes the blocks from `height` on, e.g. those of a fork given up.
+    pub fn revert_to(&mut self, height: u64) {
+        while self.height() > height {
+            let delta = self.blocks.pop().unwrap_or_default();
+            for (address, currency_type, amount) in delta.iter().rev() {
+                self.adjust(address, currency_type, -amount);
+            }
+            for (address, currency_type, _) in &delta {
+                let history = self.history_mut(address, currency_type);
+                history.retain(|(committed, _)| *committed <= height);
+            }
+        }
+    }
+
+    pub fn balance(&self, address: &str, currency_type: &CurrencyType) -> f64 {
+        self.balances.get(address)
+            .and_then(|balances| balances.iter().find(|(currency, _)| currency == currency_type))
+            .map_or(0.0, |(_, balance)| *balance)
+    }
+
+    /// The balance of `address` in each currency it has held.
+    pub fn balances(&self, address: &str) -> Vec<(CurrencyType, f64)> {
+        self.balances.get(address).cloned().unwrap_or_default()
+    }
+
+    /// The balance of `address` once the first `height` blocks were
+    /// committed, looked up in the history of the balance.
+    pub fn balance_at(&self, address: &str, currency_type: &CurrencyType, height: u64) -> f64 {
+        let history = match self.history.get(address).and_then(|histories| histories.iter().find(|(currency, _)| currency == currency_type)) {
+            Some((_, history)) => history,
+            None => return 0.0,
+        };
+        match history.partition_point(|(committed, _)| *committed <= height) {
+            0 => 0.0,
+            after => history[after - 1].1,
+        }
+    }
+
+    /// Moves `amount` into the balance, returning the balance then.
+    fn adjust(&mut self, address: &str, currency_type: &CurrencyType, amount: f64) -> f64 {
+        let balances = self.balances.entry(address.to_string()).or_default();
+        match balances.iter_mut().find(|(currency, _)| currency == currency_type) {
+            Some((_, balance)) => {
+                *balance += amount;
+                *balance
+            }
+            None => {
+                balances.push((currency_type.clone(), amount));
+                amount
+            }
+        }
+    }
+
+    fn history_mut(&mut self, address: &str, currency_type: &CurrencyType) -> &mut History {
+        let histories = self.history.entry(address.to_string()).or_default();
+        let position = match histories.iter().position(|(currency, _)| currency == currency_type) {
+            Some(position) => position,
+            None => {
+                histories.push((currency_type.clone(), History::new()));
+                histories.len() - 1
+            }
+        };
+        &mut histories[position].1
+    }
+}
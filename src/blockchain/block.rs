@@ -1,7 +1,103 @@
 // src/blockchain/block.rs
-use crate::blockchain::Transaction;
+use crate::blockchain::{Bloom, Transaction, TransactionReceipt, Transfer};
+use crate::blockchain::upgrade::BASE_PROTOCOL_VERSION;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// The part of a block that peers exchange while syncing before fetching
+/// bodies. Its hash commits to the transactions through `transactions_root`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockHeader {
+    pub index: u64,
+    pub timestamp: i64,
+    pub previous_hash: String,
+    pub transactions_root: String,
+    /// Commits to the shard state roots anchored in the block.
+    #[serde(default)]
+    pub shard_roots_root: String,
+    /// Commits to the outcomes and gas of the contracts the transactions
+    /// ran; empty if they ran none.
+    #[serde(default)]
+    pub results_root: String,
+    /// Commits to the status and gas of each transaction and to the payouts
+    /// of the block, as applying it gave; see `Block::receipts_root`.
+    #[serde(default)]
+    pub receipts_root: String,
+    pub nonce: u64,
+    pub gas_used: u64,
+    /// Event topics and addresses of the block's transactions.
+    #[serde(default)]
+    pub logs_bloom: Bloom,
+    /// Rules the block was produced under; see `UpgradeSchedule`.
+    #[serde(default)]
+    pub protocol_version: u32,
+    /// The member that proposed the block; empty on unsigned blocks.
+    #[serde(default)]
+    pub proposer: String,
+    /// The proposer's signature of `signing_hash`.
+    #[serde(default)]
+    pub proposer_signature: Option<Vec<u8>>,
+    pub hash: String,
+}
+
+impl BlockHeader {
+    /// The hash covers the proposer's signature, so a block signed by
+    /// someone else is a different block.
+    pub fn calculate_hash(&self) -> String {
+        let mut hasher = self.hasher();
+        if let Some(signature) = &self.proposer_signature {
+            hasher.update(signature);
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    /// What the proposer signs: the header but for the signature and hash.
+    pub fn signing_hash(&self) -> String {
+        hex::encode(self.hasher().finalize())
+    }
+
+    /// Whether the header is signed by `public_key`.
+    pub fn verify_proposer(&self, public_key: &PublicKey) -> bool {
+        self.proposer_signature.as_deref()
+            .and_then(|signature| Signature::from_bytes(signature).ok())
+            .is_some_and(|signature| public_key.verify(&Self::signed_message(&self.signing_hash()), &signature).is_ok())
+    }
+
+    fn signed_message(signing_hash: &str) -> Vec<u8> {
+        [b"icn-block-header:".as_slice(), signing_hash.as_bytes()].concat()
+    }
+
+    fn hasher(&self) -> Sha256 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.index.to_le_bytes());
+        hasher.update(self.timestamp.to_le_bytes());
+        hasher.update(self.previous_hash.as_bytes());
+        hasher.update(self.transactions_root.as_bytes());
+        hasher.update(self.shard_roots_root.as_bytes());
+        hasher.update(self.nonce.to_le_bytes());
+        hasher.update(self.gas_used.to_le_bytes());
+        hasher.update(self.logs_bloom.as_bytes());
+        // blocks from before these roots keep their hashes
+        for (tag, root) in [(b"results:".as_slice(), &self.results_root), (b"receipts:".as_slice(), &self.receipts_root)] {
+            if !root.is_empty() {
+                hasher.update(tag);
+                hasher.update(root.as_bytes());
+            }
+        }
+        // blocks from before versioning keep their hashes
+        if self.protocol_version != 0 {
+            hasher.update(self.protocol_version.to_le_bytes());
+        }
+        // nor do unsigned blocks change
+        if !self.proposer.is_empty() {
+            hasher.update(b"proposer:");
+            hasher.update(self.proposer.as_bytes());
+        }
+        hasher
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
@@ -12,7 +108,25 @@ pub struct Block {
     pub hash: String,
     pub nonce: u64,
     pub gas_used: u64,
+    #[serde(default)]
+    pub logs_bloom: Bloom,
     pub smart_contract_results: HashMap<String, String>,
+    /// Gas the contracts of the transactions took for their steps, by
+    /// transaction hash.
+    #[serde(default)]
+    pub contract_gas: BTreeMap<String, u64>,
+    /// Latest state root of each shard, by shard id, anchored by the beacon.
+    #[serde(default)]
+    pub shard_roots: BTreeMap<u64, String>,
+    /// Set by the proposer once it applied the block; see `BlockHeader`.
+    #[serde(default)]
+    pub receipts_root: String,
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub proposer: String,
+    #[serde(default)]
+    pub proposer_signature: Option<Vec<u8>>,
 }
 
 impl Block {
@@ -26,14 +140,91 @@ impl Block {
             hash: String::new(),
             nonce: 0,
             gas_used: 0,
+            logs_bloom: Bloom::new(),
             smart_contract_results: HashMap::new(),
+            contract_gas: BTreeMap::new(),
+            shard_roots: BTreeMap::new(),
+            receipts_root: String::new(),
+            protocol_version: BASE_PROTOCOL_VERSION,
+            proposer: String::new(),
+            proposer_signature: None,
         };
         block.hash = block.calculate_hash();
         block
     }
 
+    /// The genesis block is identical on every node so chains can be compared.
+    pub fn genesis() -> Self {
+        let mut block = Block::new(0, vec![], String::new());
+        block.timestamp = 0;
+        block.hash = block.calculate_hash();
+        block
+    }
+
+    pub fn transactions_root(transactions: &[Transaction]) -> String {
+        let mut hasher = Sha256::new();
+        for transaction in transactions {
+            hasher.update(Sha256::digest(&serde_json::to_vec(transaction).unwrap_or_default()));
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn shard_roots_root(shard_roots: &BTreeMap<u64, String>) -> String {
+        hex::encode(Sha256::digest(&serde_json::to_vec(shard_roots).unwrap_or_default()))
+    }
+
+    /// Empty for a block whose transactions ran no contracts.
+    pub fn results_root(results: &HashMap<String, String>, contract_gas: &BTreeMap<String, u64>) -> String {
+        if results.is_empty() && contract_gas.is_empty() {
+            return String::new();
+        }
+        let results: BTreeMap<&String, &String> = results.iter().collect();
+        hex::encode(Sha256::digest(&serde_json::to_vec(&(results, contract_gas)).unwrap_or_default()))
+    }
+
+    /// Commits to what applying a block did: each transaction's status and
+    /// gas, in order, then the payouts of the block.
+    pub fn receipts_root(receipts: &[TransactionReceipt], payouts: &[Transfer]) -> String {
+        let mut hasher = Sha256::new();
+        for receipt in receipts {
+            hasher.update(receipt.transaction_hash.as_bytes());
+            hasher.update(serde_json::to_vec(&receipt.status).unwrap_or_default());
+            hasher.update(receipt.gas_used.to_le_bytes());
+        }
+        hasher.update(serde_json::to_vec(payouts).unwrap_or_default());
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash.clone(),
+            transactions_root: Block::transactions_root(&self.transactions),
+            shard_roots_root: Block::shard_roots_root(&self.shard_roots),
+            results_root: Block::results_root(&self.smart_contract_results, &self.contract_gas),
+            receipts_root: self.receipts_root.clone(),
+            nonce: self.nonce,
+            gas_used: self.gas_used,
+            logs_bloom: self.logs_bloom.clone(),
+            protocol_version: self.protocol_version,
+            proposer: self.proposer.clone(),
+            proposer_signature: self.proposer_signature.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+
+    /// Signs the block as proposed by `proposer`, whose key `keypair` is,
+    /// and seals it with the hash that covers the signature.
+    pub fn sign(&mut self, proposer: &str, keypair: &Keypair) {
+        self.proposer = proposer.to_string();
+        self.proposer_signature = None;
+        let message = BlockHeader::signed_message(&self.header().signing_hash());
+        self.proposer_signature = Some(keypair.sign(&message).to_bytes().to_vec());
+        self.hash = self.calculate_hash();
+    }
+
     pub fn calculate_hash(&self) -> String {
-        // Implement proper hash calculation logic
-        format!("block_hash_{}", self.index)
+        self.header().calculate_hash()
     }
 }
\ No newline at end of file
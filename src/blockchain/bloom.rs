@@ -0,0 +1,93 @@
+// src/blockchain/bloom.rs
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+pub const BLOOM_BYTES: usize = 256;
+const BLOOM_BITS: usize = BLOOM_BYTES * 8;
+/// Bits set per item.
+const HASHES: usize = 3;
+
+/// A bloom filter over the event topics and addresses of a block. It can
+/// tell for sure that a block has nothing to do with a topic or address, so
+/// queries skip such blocks without reading their receipts; a match may be a
+/// false positive.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Bloom(Box<[u8; BLOOM_BYTES]>);
+
+impl Bloom {
+    pub fn new() -> Self {
+        Bloom(Box::new([0; BLOOM_BYTES]))
+    }
+
+    pub fn accrue(&mut self, item: &str) {
+        for bit in Self::bits(item) {
+            self.0[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// False if `item` was certainly never added.
+    pub fn may_contain(&self, item: &str) -> bool {
+        Self::bits(item).all(|bit| self.0[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    fn bits(item: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(item.as_bytes());
+        (0..HASHES).map(move |i| u16::from_be_bytes([digest[2 * i], digest[2 * i + 1]]) as usize % BLOOM_BITS)
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Bloom({})", hex::encode(self.as_bytes()))
+    }
+}
+
+impl Serialize for Bloom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.as_bytes()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bloom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        let bytes: [u8; BLOOM_BYTES] = bytes.try_into()
+            .map_err(|bytes: Vec<u8>| serde::de::Error::invalid_length(bytes.len(), &"256 bytes"))?;
+        Ok(Bloom(Box::new(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_membership() {
+        let mut bloom = Bloom::new();
+        assert!(bloom.is_empty());
+        bloom.accrue("AssetTokenCreated");
+        bloom.accrue("Alice");
+        assert!(bloom.may_contain("AssetTokenCreated"));
+        assert!(bloom.may_contain("Alice"));
+        assert!(!bloom.may_contain("Bob"));
+
+        let json = serde_json::to_string(&bloom).unwrap();
+        assert_eq!(json.len(), 2 * BLOOM_BYTES + 2);
+        assert_eq!(serde_json::from_str::<Bloom>(&json).unwrap(), bloom);
+        assert!(serde_json::from_str::<Bloom>("\"00ff\"").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// src/blockchain/crowdfund.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::payout::payout;
+use super::{Transaction, Transfer};
+
+/// The account holding pledges until their campaign closes, and paying them
+/// out to the project or back to those who pledged.
+pub const CROWDFUND_ACCOUNT: &str = "icn:crowdfund";
+
+/// What a crowdfunding transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum CrowdfundAction {
+    /// Opens a campaign for the sender's project, raising `goal` in the
+    /// currency of the transaction by the block at `deadline`.
+    Launch { goal: f64, deadline: u64 },
+    /// Pledges the amount of the transaction to a campaign.
+    Pledge { campaign_id: String },
+}
+
+/// Pledges toward a project, paid to it only if they reach the goal by the
+/// deadline and otherwise returned to those who made them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Campaign {
+    /// Hash of the transaction that launched the campaign.
+    pub id: String,
+    pub project: String,
+    pub currency_type: CurrencyType,
+    pub goal: f64,
+    /// Index of the last block pledges are taken in.
+    pub deadline: u64,
+    /// What each member pledged.
+    pub pledges: BTreeMap<String, f64>,
+}
+
+impl Campaign {
+    pub fn raised(&self) -> f64 {
+        self.pledges.values().sum()
+    }
+
+    pub fn is_funded(&self) -> bool {
+        self.raised() >= self.goal
+    }
+}
+
+/// The campaigns taking pledges. Closing a campaign after its deadline
+/// pays the project, or refunds the pledges, from `CROWDFUND_ACCOUNT` in
+/// the block applying the close.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Crowdfunding {
+    campaigns: BTreeMap<String, Campaign>,
+}
+
+impl Crowdfunding {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, campaign_id: &str) -> Option<&Campaign> {
+        self.campaigns.get(campaign_id)
+    }
+
+    /// The campaigns of the project `address`, or that it pledged to.
+    pub fn of(&self, address: &str) -> Vec<&Campaign> {
+        self.campaigns.values()
+            .filter(|campaign| campaign.project == address || campaign.pledges.contains_key(address))
+            .collect()
+    }
+
+    /// Applies the crowdfunding action of `transaction`, in the block at
+    /// `index`.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64) -> Result<(), String> {
+        match &transaction.crowdfund {
+            Some(CrowdfundAction::Launch { goal, deadline }) => {
+                if !goal.is_finite() || *goal <= 0.0 {
+                    return Err("A campaign needs a positive goal".to_string());
+                }
+                if *deadline < index {
+                    return Err(format!("Deadline {} has passed", deadline));
+                }
+                let campaign = Campaign {
+                    id: transaction.hash(),
+                    project: transaction.from.clone(),
+                    currency_type: transaction.currency_type.clone(),
+                    goal: *goal,
+                    deadline: *deadline,
+                    pledges: BTreeMap::new(),
+                };
+                info!("Campaign {} of {} raises {} {} by block {}", campaign.id, campaign.project, goal, campaign.currency_type, deadline);
+                self.campaigns.insert(campaign.id.clone(), campaign);
+                Ok(())
+            }
+            Some(CrowdfundAction::Pledge { campaign_id }) => {
+                let campaign = self.campaigns.get_mut(campaign_id).ok_or_else(|| format!("No open campaign {}", campaign_id))?;
+                if index > campaign.deadline {
+                    return Err(format!("Campaign {} closed at block {}", campaign_id, campaign.deadline));
+                }
+                if transaction.currency_type != campaign.currency_type || !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+                    return Err(format!("Campaign {} takes positive pledges of {}", campaign_id, campaign.currency_type));
+                }
+                if transaction.to != CROWDFUND_ACCOUNT {
+                    return Err(format!("Pledges to campaign {} are paid to {}", campaign_id, CROWDFUND_ACCOUNT));
+                }
+                *campaign.pledges.entry(transaction.from.clone()).or_insert(0.0) += transaction.amount;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the campaigns whose deadline was before the block at `index`,
+    /// paying those that reached their goal to their project and refunding
+    /// the pledges of the others.
+    pub fn close_expired(&mut self, index: u64) -> Vec<Transfer> {
+        let expired: Vec<String> = self.campaigns.values()
+            .filter(|campaign| index > campaign.deadline)
+            .map(|campaign| campaign.id.clone())
+            .collect();
+        let mut payouts = Vec::new();
+        for campaign in expired.iter().filter_map(|id| self.campaigns.remove(id)) {
+            if campaign.is_funded() {
+                info!("Campaign {} reached its goal, paying {} to {}", campaign.id, campaign.raised(), campaign.project);
+                payouts.push(payout(CROWDFUND_ACCOUNT, &campaign.project, campaign.raised(), &campaign.currency_type));
+            } else {
+                info!("Campaign {} missed its goal, refunding {} pledges", campaign.id, campaign.pledges.len());
+                payouts.extend(campaign.pledges.iter().map(|(member, amount)| payout(CROWDFUND_ACCOUNT, member, *amount, &campaign.currency_type)));
+            }
+        }
+        payouts
+    }
+}
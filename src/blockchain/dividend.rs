@@ -0,0 +1,174 @@
+// src/blockchain/dividend.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::payout::payout;
+use super::{Transaction, Transfer};
+
+/// The account holding the surplus of funded distributions until it is
+/// claimed, and paying it out.
+pub const DIVIDEND_ACCOUNT: &str = "icn:dividends";
+
+/// What a dividend transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DividendAction {
+    /// Opens a distribution of the sender's surplus in the currency of the
+    /// transaction, by the patronage recorded up to the block at
+    /// `period_end`. Members may claim their share for `claim_blocks` blocks
+    /// once it is funded.
+    Open { period_end: u64, claim_blocks: u64 },
+    /// Records `amount` of patronage by `member`, such as purchases or hours
+    /// of labor, sent by the cooperative.
+    Patronage { distribution_id: String, member: String, amount: f64 },
+    /// Escrows the amount of the transaction as the surplus to distribute,
+    /// once the period is over, fixing the share of each member.
+    Fund { distribution_id: String },
+    /// Pays the sender its share.
+    Claim { distribution_id: String },
+}
+
+/// A cooperative's surplus, shared among its members in proportion to their
+/// patronage over a period. What is not claimed in time goes back to the
+/// cooperative.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Distribution {
+    /// Hash of the transaction that opened the distribution.
+    pub id: String,
+    pub cooperative: String,
+    pub currency_type: CurrencyType,
+    /// Index of the last block patronage is recorded in.
+    pub period_end: u64,
+    pub claim_blocks: u64,
+    pub patronage: BTreeMap<String, f64>,
+    pub surplus: f64,
+    /// The share of each member, fixed when the distribution is funded.
+    pub shares: BTreeMap<String, f64>,
+    /// Index of the last block shares may be claimed in; set once funded.
+    pub claims_end: Option<u64>,
+}
+
+impl Distribution {
+    /// What has been funded and not claimed.
+    pub fn unclaimed(&self) -> f64 {
+        self.shares.values().sum()
+    }
+}
+
+/// The distributions opened and not yet closed. Claims and the return of
+/// unclaimed surplus pay out from `DIVIDEND_ACCOUNT` in the block applying them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Dividends {
+    distributions: BTreeMap<String, Distribution>,
+}
+
+impl Dividends {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, distribution_id: &str) -> Option<&Distribution> {
+        self.distributions.get(distribution_id)
+    }
+
+    /// The distributions of the cooperative `address`, or that it has
+    /// patronage in.
+    pub fn of(&self, address: &str) -> Vec<&Distribution> {
+        self.distributions.values()
+            .filter(|distribution| distribution.cooperative == address || distribution.patronage.contains_key(address))
+            .collect()
+    }
+
+    /// Applies the dividend action of `transaction`, in the block at `index`,
+    /// returning the payouts it makes.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64) -> Result<Vec<Transfer>, String> {
+        match &transaction.dividend {
+            Some(DividendAction::Open { period_end, claim_blocks }) => {
+                if *period_end < index || *claim_blocks == 0 {
+                    return Err("A distribution needs a period ending from now on and a claim window".to_string());
+                }
+                let distribution = Distribution {
+                    id: transaction.hash(),
+                    cooperative: transaction.from.clone(),
+                    currency_type: transaction.currency_type.clone(),
+                    period_end: *period_end,
+                    claim_blocks: *claim_blocks,
+                    patronage: BTreeMap::new(),
+                    surplus: 0.0,
+                    shares: BTreeMap::new(),
+                    claims_end: None,
+                };
+                info!("{} opened distribution {} for patronage up to block {}", distribution.cooperative, distribution.id, period_end);
+                self.distributions.insert(distribution.id.clone(), distribution);
+                Ok(Vec::new())
+            }
+            Some(DividendAction::Patronage { distribution_id, member, amount }) => {
+                let distribution = self.of_cooperative(distribution_id, &transaction.from)?;
+                if index > distribution.period_end {
+                    return Err(format!("The period of distribution {} is over", distribution_id));
+                }
+                if !amount.is_finite() || *amount <= 0.0 {
+                    return Err("Patronage needs a positive amount".to_string());
+                }
+                *distribution.patronage.entry(member.clone()).or_default() += amount;
+                Ok(Vec::new())
+            }
+            Some(DividendAction::Fund { distribution_id }) => {
+                let distribution = self.of_cooperative(distribution_id, &transaction.from)?;
+                if index <= distribution.period_end || distribution.claims_end.is_some() {
+                    return Err(format!("Distribution {} is not ready to fund", distribution_id));
+                }
+                let total: f64 = distribution.patronage.values().sum();
+                if total <= 0.0 || !transaction.amount.is_finite() || transaction.amount <= 0.0 || transaction.currency_type != distribution.currency_type {
+                    return Err(format!("Distribution {} needs patronage and a positive surplus in {}", distribution_id, distribution.currency_type));
+                }
+                if transaction.to != DIVIDEND_ACCOUNT {
+                    return Err(format!("Distribution {} is funded by paying {}", distribution_id, DIVIDEND_ACCOUNT));
+                }
+                distribution.surplus = transaction.amount;
+                distribution.shares = distribution.patronage.iter()
+                    .map(|(member, patronage)| (member.clone(), transaction.amount * patronage / total))
+                    .collect();
+                distribution.claims_end = Some(index + distribution.claim_blocks);
+                info!("Distribution {} shares {} {} among {} members", distribution_id, transaction.amount, distribution.currency_type, distribution.shares.len());
+                Ok(Vec::new())
+            }
+            Some(DividendAction::Claim { distribution_id }) => {
+                let distribution = self.distributions.get_mut(distribution_id).ok_or_else(|| format!("No distribution {}", distribution_id))?;
+                if distribution.claims_end.is_none_or(|claims_end| index > claims_end) {
+                    return Err(format!("Distribution {} is not open for claims", distribution_id));
+                }
+                let share = distribution.shares.remove(&transaction.from)
+                    .ok_or_else(|| format!("{} has no share to claim in distribution {}", transaction.from, distribution_id))?;
+                Ok(vec![payout(DIVIDEND_ACCOUNT, &transaction.from, share, &distribution.currency_type)])
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Closes the distributions whose claims ended before the block at
+    /// `index`, returning the payouts of their unclaimed surplus to the
+    /// cooperatives.
+    pub fn close_expired(&mut self, index: u64) -> Vec<Transfer> {
+        let expired: Vec<String> = self.distributions.values()
+            .filter(|distribution| distribution.claims_end.is_some_and(|claims_end| index > claims_end))
+            .map(|distribution| distribution.id.clone())
+            .collect();
+        expired.iter()
+            .filter_map(|id| self.distributions.remove(id))
+            .filter(|distribution| distribution.unclaimed() > 0.0)
+            .map(|distribution| {
+                info!("Distribution {} closed, returning {} unclaimed to {}", distribution.id, distribution.unclaimed(), distribution.cooperative);
+                payout(DIVIDEND_ACCOUNT, &distribution.cooperative, distribution.unclaimed(), &distribution.currency_type)
+            })
+            .collect()
+    }
+
+    fn of_cooperative(&mut self, distribution_id: &str, by: &str) -> Result<&mut Distribution, String> {
+        match self.distributions.get_mut(distribution_id) {
+            Some(distribution) if distribution.cooperative == by => Ok(distribution),
+            Some(_) => Err(format!("{} did not open distribution {}", by, distribution_id)),
+            None => Err(format!("No distribution {}", distribution_id)),
+        }
+    }
+}
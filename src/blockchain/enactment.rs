@@ -0,0 +1,77 @@
+// src/blockchain/enactment.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::consensus::PoCConsensus;
+use crate::governance::ProposalAction;
+use crate::smart_contract::ContractEvent;
+use crate::wallet::address_of;
+use super::Transaction;
+
+/// The recipient named by enactment transactions. Nothing is ever paid to
+/// it.
+pub const GOVERNANCE_ACCOUNT: &str = "icn:governance";
+
+/// A passed proposal and the action it takes, put into effect by a
+/// transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Enactment {
+    pub proposal_id: String,
+    pub action: ProposalAction,
+}
+
+/// The proposals enacted in the chain, with the index of the block enacting
+/// each. Votes are tallied off the chain, so an enactment goes through only
+/// if two thirds of the active validators cosign it with their registered
+/// keys, attesting the proposal passed. A proposal is enacted once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Enactments {
+    enacted: BTreeMap<String, u64>,
+}
+
+impl Enactments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enacted(&self, proposal_id: &str) -> bool {
+        self.enacted.contains_key(proposal_id)
+    }
+
+    /// Index of the block that enacted `proposal_id`.
+    pub fn enacted_at(&self, proposal_id: &str) -> Option<u64> {
+        self.enacted.get(proposal_id).copied()
+    }
+
+    /// The enactment of `transaction`, if enough validators signed it and
+    /// its proposal is not enacted yet.
+    pub fn check<'a>(&self, transaction: &'a Transaction, consensus: &PoCConsensus) -> Result<&'a Enactment, String> {
+        let enactment = transaction.enactment.as_ref().ok_or("Not an enactment")?;
+        if transaction.to != GOVERNANCE_ACCOUNT || transaction.amount != 0.0 {
+            return Err(format!("An enactment is addressed to {} and moves no funds", GOVERNANCE_ACCOUNT));
+        }
+        if self.is_enacted(&enactment.proposal_id) {
+            return Err(format!("Proposal {} is already enacted", enactment.proposal_id));
+        }
+        let validators: Vec<&str> = consensus.members.iter()
+            .filter(|member| consensus.is_active_validator(&member.id))
+            .map(|member| member.id.as_str())
+            .collect();
+        let signers = transaction.signers();
+        let signed = validators.iter()
+            .filter_map(|validator| consensus.public_key(validator))
+            .filter(|key| signers.contains(&address_of(key)))
+            .count();
+        if validators.is_empty() || signed * 3 < validators.len() * 2 {
+            return Err(format!("Proposal {} is signed by {} of {} validators, two thirds must sign", enactment.proposal_id, signed, validators.len()));
+        }
+        Ok(enactment)
+    }
+
+    /// Records that `enactment` took effect in the block at `index`.
+    pub fn record(&mut self, enactment: &Enactment, index: u64) -> ContractEvent {
+        info!("Proposal {} enacted in block {}", enactment.proposal_id, index);
+        self.enacted.insert(enactment.proposal_id.clone(), index);
+        ContractEvent { contract_id: enactment.proposal_id.clone(), name: "ProposalEnacted".to_string(), data: format!("{:?}", enactment.action) }
+    }
+}
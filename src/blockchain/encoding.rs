@@ -0,0 +1,100 @@
+// src/blockchain/encoding.rs
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::error::{Error, Result};
+use super::{Block, Transaction};
+
+/// A value stored or sent with the version of its format, so that the chain
+/// can evolve without breaking history: fields added as optional are ignored
+/// by older readers, while a change they cannot ignore raises `MIN_VERSION`
+/// and is refused by them instead of misread.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Names the kind of value, checked on decoding.
+    const FORMAT: &'static str;
+    /// Version of the format this build writes, and the newest it reads.
+    const VERSION: u32;
+    /// Oldest version a reader must know to decode what this build writes.
+    const MIN_VERSION: u32;
+}
+
+impl Versioned for Block {
+    const FORMAT: &'static str = "icn-block";
+    const VERSION: u32 = 1;
+    const MIN_VERSION: u32 = 1;
+}
+
+impl Versioned for Transaction {
+    const FORMAT: &'static str = "icn-transaction";
+    const VERSION: u32 = 1;
+    const MIN_VERSION: u32 = 1;
+}
+
+#[derive(Serialize)]
+struct EnvelopeRef<'a, T> {
+    format: &'a str,
+    version: u32,
+    min_version: u32,
+    body: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    format: String,
+    version: u32,
+    #[serde(default)]
+    min_version: u32,
+    body: serde_json::Value,
+}
+
+pub fn encode<T: Versioned>(value: &T) -> Result<Vec<u8>> {
+    let envelope = EnvelopeRef { format: T::FORMAT, version: T::VERSION, min_version: T::MIN_VERSION, body: value };
+    serde_json::to_vec(&envelope).map_err(|e| Error::BlockchainError(e.to_string()))
+}
+
+/// Decodes what `encode` wrote, in this version or any other this build
+/// knows enough of. Bare JSON from before versioning is read as version 0.
+pub fn decode<T: Versioned>(bytes: &[u8]) -> Result<T> {
+    let malformed = |e: serde_json::Error| Error::BlockchainError(format!("Malformed {}: {}", T::FORMAT, e));
+    let value: serde_json::Value = serde_json::from_slice(bytes).map_err(malformed)?;
+    let is_envelope = value.get("format").is_some_and(serde_json::Value::is_string) && value.get("body").is_some();
+    if !is_envelope {
+        return serde_json::from_value(value).map_err(malformed);
+    }
+    let envelope: Envelope = serde_json::from_value(value).map_err(malformed)?;
+    if envelope.format != T::FORMAT {
+        return Err(Error::BlockchainError(format!("Expected {}, got {}", T::FORMAT, envelope.format)));
+    }
+    if envelope.min_version > T::VERSION {
+        return Err(Error::BlockchainError(format!(
+            "{} version {} needs a reader of version {}; this node reads up to {}",
+            T::FORMAT, envelope.version, envelope.min_version, T::VERSION
+        )));
+    }
+    serde_json::from_value(envelope.body).map_err(malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::CurrencyType;
+
+    #[test]
+    fn test_decoding_skips_new_optional_fields_and_refuses_required_versions() {
+        let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), 10.0, CurrencyType::BasicNeeds, 1000);
+        let encoded = encode(&transaction).unwrap();
+        assert_eq!(decode::<Transaction>(&encoded).unwrap(), transaction);
+        assert_eq!(decode::<Transaction>(&serde_json::to_vec(&transaction).unwrap()).unwrap(), transaction, "bare JSON from before versioning");
+        assert!(decode::<Block>(&encoded).is_err());
+
+        let mut newer: serde_json::Value = serde_json::from_slice(&encoded).unwrap();
+        newer["version"] = (Transaction::VERSION + 1).into();
+        newer["body"]["memo"] = "a field of a later version".into();
+        assert_eq!(decode::<Transaction>(&serde_json::to_vec(&newer).unwrap()).unwrap(), transaction);
+
+        newer["min_version"] = (Transaction::VERSION + 1).into();
+        assert!(decode::<Transaction>(&serde_json::to_vec(&newer).unwrap()).is_err());
+
+        let block = Block::genesis();
+        assert_eq!(decode::<Block>(&encode(&block).unwrap()).unwrap().hash, block.hash);
+    }
+}
@@ -0,0 +1,182 @@
+// src/blockchain/executor.rs
+use crate::blockchain::Transaction;
+use crate::currency::CurrencyType;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+/// A balance: an address's holdings in one currency.
+pub type Account = (String, CurrencyType);
+
+/// What running a transaction produced, and the balances it set.
+pub struct Outcome<R> {
+    pub result: R,
+    pub writes: Vec<(Account, f64)>,
+}
+
+/// The accounts a transaction transfers between, as known before running it.
+pub fn accounts(transaction: &Transaction) -> Vec<Account> {
+    let mut accounts: Vec<Account> = Vec::new();
+    for transfer in transaction.transfers() {
+        for address in [transfer.from, transfer.to] {
+            let account = (address, transfer.currency_type.clone());
+            if !accounts.contains(&account) {
+                accounts.push(account);
+            }
+        }
+    }
+    accounts
+}
+
+/// Splits transactions into waves of indices. Transactions in a wave share no
+/// account, and each comes in a later wave than every earlier transaction it
+/// shares one with, so running the waves in order with each wave in any order
+/// gives the result of running everything in order.
+pub fn schedule(transactions: &[Transaction]) -> Vec<Vec<usize>> {
+    let mut waves: Vec<Vec<usize>> = Vec::new();
+    let mut last_wave: HashMap<Account, usize> = HashMap::new();
+    for (index, transaction) in transactions.iter().enumerate() {
+        let accounts = accounts(transaction);
+        let wave = accounts.iter().filter_map(|account| last_wave.get(account)).map(|wave| wave + 1).max().unwrap_or(0);
+        if wave == waves.len() {
+            waves.push(Vec::new());
+        }
+        waves[wave].push(index);
+        for account in accounts {
+            last_wave.insert(account, wave);
+        }
+    }
+    waves
+}
+
+/// Runs the transactions of a block, those that share no account in
+/// parallel. `run` is given the index of a transaction in the block, the
+/// transaction and the balances before it, and must only set balances of the
+/// transaction's `accounts`; should it set any other, the schedule no longer
+/// holds and the block is run again one transaction at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionEngine {
+    parallel: bool,
+}
+
+impl ExecutionEngine {
+    pub fn new() -> Self {
+        ExecutionEngine { parallel: true }
+    }
+
+    /// Runs every transaction in order on the calling thread.
+    pub fn sequential() -> Self {
+        ExecutionEngine { parallel: false }
+    }
+
+    /// Returns the result of each transaction in the order given. Balances
+    /// not yet set are taken from `seed`.
+    pub fn execute<R, S, F>(&self, transactions: &[Transaction], seed: S, run: F) -> Vec<R>
+    where
+        R: Send,
+        S: Fn(&Account) -> f64 + Sync,
+        F: Fn(usize, &Transaction, &HashMap<Account, f64>) -> Outcome<R> + Sync,
+    {
+        let accounts: HashSet<Account> = transactions.iter().flat_map(accounts).collect();
+        let balances: HashMap<Account, f64> = accounts.into_par_iter().map(|account| {
+            let balance = seed(&account);
+            (account, balance)
+        }).collect();
+
+        if self.parallel {
+            if let Some(results) = Self::execute_waves(transactions, balances.clone(), &run) {
+                return results;
+            }
+            warn!("Transactions set balances outside their accounts, executing the block sequentially");
+        }
+        Self::execute_in_order(transactions, balances, &run)
+    }
+
+    fn execute_waves<R, F>(transactions: &[Transaction], mut balances: HashMap<Account, f64>, run: &F) -> Option<Vec<R>>
+    where
+        R: Send,
+        F: Fn(usize, &Transaction, &HashMap<Account, f64>) -> Outcome<R> + Sync,
+    {
+        let mut results: Vec<Option<R>> = transactions.iter().map(|_| None).collect();
+        for wave in schedule(transactions) {
+            let outcomes: Vec<(usize, Outcome<R>)> = wave.into_par_iter()
+                .map(|index| (index, run(index, &transactions[index], &balances)))
+                .collect();
+            for (index, outcome) in outcomes {
+                let declared = accounts(&transactions[index]);
+                if outcome.writes.iter().any(|(account, _)| !declared.contains(account)) {
+                    return None;
+                }
+                balances.extend(outcome.writes);
+                results[index] = Some(outcome.result);
+            }
+        }
+        Some(results.into_iter().map(|result| result.expect("every transaction is in a wave")).collect())
+    }
+
+    fn execute_in_order<R, F>(transactions: &[Transaction], mut balances: HashMap<Account, f64>, run: &F) -> Vec<R>
+    where
+        F: Fn(usize, &Transaction, &HashMap<Account, f64>) -> Outcome<R>,
+    {
+        transactions.iter().enumerate().map(|(index, transaction)| {
+            let outcome = run(index, transaction, &balances);
+            balances.extend(outcome.writes);
+            outcome.result
+        }).collect()
+    }
+}
+
+impl Default for ExecutionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(from: &str, to: &str, amount: f64) -> Transaction {
+        Transaction::new(from.to_string(), to.to_string(), amount, CurrencyType::BasicNeeds, 1000)
+    }
+
+    fn apply(_: usize, transaction: &Transaction, balances: &HashMap<Account, f64>) -> Outcome<f64> {
+        let [from, to] = [&transaction.from, &transaction.to].map(|address| (address.clone(), transaction.currency_type.clone()));
+        let to_balance = balances[&to] + transaction.amount;
+        Outcome { result: to_balance, writes: vec![(from.clone(), balances[&from] - transaction.amount), (to, to_balance)] }
+    }
+
+    #[test]
+    fn test_schedule_keeps_conflicting_transactions_in_order() {
+        let mut education = transfer("Alice", "Bob", 1.0);
+        education.currency_type = CurrencyType::Education;
+        let transactions = vec![
+            transfer("Alice", "Bob", 1.0),
+            transfer("Carol", "Dave", 1.0),
+            education,
+            transfer("Bob", "Carol", 1.0),
+            transfer("Erin", "Frank", 1.0),
+            transfer("Dave", "Alice", 1.0),
+        ];
+        assert_eq!(schedule(&transactions), vec![vec![0, 1, 2, 4], vec![3, 5]]);
+    }
+
+    #[test]
+    fn test_parallel_execution_matches_sequential() {
+        let transactions: Vec<Transaction> = (0..200)
+            .map(|i| transfer(&format!("member{}", i % 37), &format!("member{}", (i * 7 + 3) % 41), i as f64))
+            .collect();
+        let seed = |account: &Account| account.0.len() as f64;
+        let sequential = ExecutionEngine::sequential().execute(&transactions, seed, apply);
+        assert_eq!(ExecutionEngine::new().execute(&transactions, seed, apply), sequential);
+
+        // writing outside the declared accounts makes the engine start over in order
+        let sneaky = |_: usize, transaction: &Transaction, balances: &HashMap<Account, f64>| {
+            let shared = ("treasury".to_string(), CurrencyType::BasicNeeds);
+            let total = balances.get(&shared).copied().unwrap_or(0.0) + transaction.amount;
+            Outcome { result: total, writes: vec![(shared, total)] }
+        };
+        let expected: Vec<f64> = (0..200).scan(0.0, |total, i| { *total += i as f64; Some(*total) }).collect();
+        assert_eq!(ExecutionEngine::new().execute(&transactions, seed, sneaky), expected);
+    }
+}
@@ -0,0 +1,135 @@
+// src/blockchain/fees.rs
+use serde::{Serialize, Deserialize};
+use crate::currency::CurrencyType;
+use super::{Blockchain, ProductionConfig, Transaction};
+
+/// How `estimate` reads congestion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeConfig {
+    /// Lowest gas price ever suggested.
+    pub min_gas_price: f64,
+    /// Transactions a block holds, as `ProductionConfig::max_transactions`.
+    pub block_capacity: usize,
+    /// How many of the latest blocks are looked at.
+    pub history: usize,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig {
+            min_gas_price: 1.0,
+            block_capacity: ProductionConfig::default().max_transactions,
+            history: 20,
+        }
+    }
+}
+
+/// Suggested gas prices for transactions in one currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub currency_type: CurrencyType,
+    /// Likely included once the mempool clears.
+    pub low: f64,
+    /// Likely included in the next few blocks.
+    pub standard: f64,
+    /// Likely included in the next block.
+    pub fast: f64,
+    /// Share of the capacity of the latest blocks that was used.
+    pub block_fullness: f64,
+    /// Transactions in this currency waiting in the mempool.
+    pub pending: usize,
+}
+
+/// Suggests gas prices in each currency lane used by the latest blocks or
+/// waiting in the mempool, and in `currencies`. The prices paid recently
+/// in a lane are the baseline; the fuller the latest blocks and the deeper
+/// the mempool, the higher the standard and fast prices are pushed, and the
+/// fast price outbids everything waiting once more than a block's worth is.
+pub fn estimate(blockchain: &Blockchain, config: &FeeConfig, currencies: &[CurrencyType]) -> Vec<FeeEstimate> {
+    let capacity = config.block_capacity.max(1) as f64;
+    let recent = &blockchain.chain[blockchain.chain.len().saturating_sub(config.history)..];
+    let block_fullness = if recent.is_empty() {
+        0.0
+    } else {
+        recent.iter().map(|block| block.transactions.len() as f64 / capacity).sum::<f64>() / recent.len() as f64
+    };
+    let waiting_blocks = blockchain.pending_transactions.len() as f64 / capacity;
+    // nothing to pay for while blocks are at most half full
+    let congestion = 1.0 + 2.0 * (block_fullness.max(waiting_blocks) - 0.5).max(0.0);
+
+    let included: Vec<&Transaction> = recent.iter().flat_map(|block| &block.transactions).collect();
+    let mut lanes: Vec<CurrencyType> = Vec::new();
+    let used = included.iter().copied().chain(&blockchain.pending_transactions).map(|transaction| &transaction.currency_type);
+    for currency in used.chain(currencies) {
+        if !lanes.contains(currency) {
+            lanes.push(currency.clone());
+        }
+    }
+
+    lanes.into_iter().map(|currency_type| {
+        let mut paid: Vec<f64> = included.iter()
+            .filter(|transaction| transaction.currency_type == currency_type)
+            .map(|transaction| transaction.gas_price)
+            .collect();
+        paid.sort_by(f64::total_cmp);
+        let waiting: Vec<f64> = blockchain.pending_transactions.iter()
+            .filter(|transaction| transaction.currency_type == currency_type)
+            .map(|transaction| transaction.gas_price)
+            .collect();
+
+        let low = percentile(&paid, 0.25).max(config.min_gas_price);
+        let standard = (percentile(&paid, 0.5) * congestion).max(low);
+        let mut fast = (percentile(&paid, 0.9) * congestion).max(standard * congestion);
+        if waiting_blocks > 1.0 {
+            let highest_waiting = waiting.iter().copied().fold(0.0, f64::max);
+            fast = fast.max(highest_waiting * 1.1);
+        }
+        FeeEstimate { currency_type, low, standard, fast, block_fullness, pending: waiting.len() }
+    }).collect()
+}
+
+/// The value below which `fraction` of the sorted `values` lie, or 0 if
+/// there are none.
+fn percentile(values: &[f64], fraction: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values[((values.len() - 1) as f64 * fraction).round() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ChainSpec;
+
+    #[test]
+    fn test_prices_rise_with_congestion() {
+        let config = FeeConfig { block_capacity: 4, ..FeeConfig::default() };
+        let spec = [CurrencyType::Energy, CurrencyType::Storage].into_iter()
+            .fold(ChainSpec::default(), |spec, currency_type| spec.with_allocation("Alice", 100.0, currency_type));
+        let mut blockchain = Blockchain::with_spec(spec);
+        let transfer = |price, currency_type| Transaction::new("Alice".to_string(), "Bob".to_string(), 1.0, currency_type, 1000).with_gas_price(price);
+
+        let quiet = estimate(&blockchain, &config, &[CurrencyType::Energy]);
+        assert_eq!(quiet.len(), 1);
+        assert_eq!((quiet[0].low, quiet[0].standard, quiet[0].fast), (1.0, 1.0, 1.0));
+
+        for price in [2.0, 4.0, 6.0, 8.0] {
+            blockchain.add_transaction(transfer(price, CurrencyType::Energy)).unwrap();
+        }
+        blockchain.add_transaction(transfer(3.0, CurrencyType::Storage)).unwrap();
+        blockchain.create_block("Miner1".to_string()).unwrap();
+        let busy = estimate(&blockchain, &config, &[]);
+        let energy = busy.iter().find(|estimate| estimate.currency_type == CurrencyType::Energy).unwrap();
+        assert!(busy.iter().any(|estimate| estimate.currency_type == CurrencyType::Storage));
+        assert!(energy.low >= 2.0 && energy.standard > energy.low && energy.fast > energy.standard, "{:?}", energy);
+
+        for _ in 0..10 {
+            blockchain.add_transaction(transfer(50.0, CurrencyType::Energy)).unwrap();
+        }
+        let backlog = estimate(&blockchain, &config, &[]);
+        let energy = backlog.iter().find(|estimate| estimate.currency_type == CurrencyType::Energy).unwrap();
+        assert_eq!(energy.pending, 10);
+        assert!(energy.fast > 50.0, "outbids the waiting transactions: {:?}", energy);
+    }
+}
@@ -0,0 +1,144 @@
+// src/blockchain/lanes.rs
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::{receipt, Transaction};
+
+/// Shares of every block kept for transactions in essential currencies, so
+/// that congestion in other markets never crowds them out. Set by an enacted
+/// proposal, they are shares of the block limits of the chain spec. A lane's
+/// share of a block's gas, and of its transactions, goes to nothing else
+/// even when none of its transactions are waiting, since the validators
+/// checking a block cannot know what was waiting when it was sealed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lanes {
+    reserved: Vec<(CurrencyType, f64)>,
+}
+
+impl Lanes {
+    /// The share of each block reserved for each currency.
+    pub fn reserved(&self) -> &[(CurrencyType, f64)] {
+        &self.reserved
+    }
+
+    pub fn share(&self, currency_type: &CurrencyType) -> f64 {
+        self.reserved.iter().find(|(currency, _)| currency == currency_type).map_or(0.0, |(_, share)| *share)
+    }
+
+    /// Replaces the reservations, as enacted by proposal `proposal_id`.
+    /// Shares are fractions of a block and may add up to at most 1.
+    pub fn reserve(&mut self, reserved: Vec<(CurrencyType, f64)>, proposal_id: &str) -> Result<(), String> {
+        if let Some((currency, share)) = reserved.iter().find(|(_, share)| !(0.0..=1.0).contains(share)) {
+            return Err(format!("Cannot reserve {} of each block for {}", share, currency));
+        }
+        if reserved.iter().map(|(_, share)| share).sum::<f64>() > 1.0 {
+            return Err("Lanes cannot reserve more than the whole block".to_string());
+        }
+        for (index, (currency, _)) in reserved.iter().enumerate() {
+            if reserved[..index].iter().any(|(earlier, _)| earlier == currency) {
+                return Err(format!("{} has two lanes", currency));
+            }
+        }
+        info!("Lanes set by proposal {}: {:?}", proposal_id, reserved);
+        self.reserved = reserved;
+        Ok(())
+    }
+
+    /// Fails if `transactions` do not fit in a block of at most
+    /// `max_transactions` and `max_gas`, or if those outside a lane take
+    /// any of what it reserves.
+    pub fn check(&self, transactions: &[Transaction], max_transactions: usize, max_gas: u64) -> Result<(), String> {
+        let limits = limits(max_transactions, max_gas);
+        let mut used = [0.0; 2];
+        let mut used_by_lane = vec![[0.0; 2]; self.reserved.len()];
+        for transaction in transactions {
+            self.add(&mut used, &mut used_by_lane, transaction);
+        }
+        if (0..2).any(|resource| used[resource] > limits[resource]) {
+            return Err(format!("{} transactions needing {} gas exceed the block limits", used[0], used[1]));
+        }
+        match self.overdrawn(&used, &used_by_lane, &limits) {
+            Some(currency) => Err(format!("Other transactions take room reserved for {}", currency)),
+            None => Ok(()),
+        }
+    }
+
+    /// Splits `pending`, best offers first, into what goes in a block of at
+    /// most `max_transactions` and `max_gas` and what waits. A transaction
+    /// is taken if the block, with it, still leaves every other lane its
+    /// reservation.
+    pub fn select(&self, pending: Vec<Transaction>, max_transactions: usize, max_gas: u64) -> (Vec<Transaction>, Vec<Transaction>) {
+        let limits = limits(max_transactions, max_gas);
+        let mut used = [0.0; 2];
+        let mut used_by_lane = vec![[0.0; 2]; self.reserved.len()];
+        let (mut selected, mut waiting) = (Vec::new(), Vec::new());
+        for transaction in pending {
+            let (mut with_used, mut with_lanes) = (used, used_by_lane.clone());
+            self.add(&mut with_used, &mut with_lanes, &transaction);
+            let fits = (0..2).all(|resource| with_used[resource] <= limits[resource])
+                && self.overdrawn(&with_used, &with_lanes, &limits).is_none();
+            if fits {
+                used = with_used;
+                used_by_lane = with_lanes;
+                selected.push(transaction);
+            } else {
+                waiting.push(transaction);
+            }
+        }
+        (selected, waiting)
+    }
+
+    /// Counts what `transaction` takes of a block, and of its lane.
+    fn add(&self, used: &mut [f64; 2], used_by_lane: &mut [[f64; 2]], transaction: &Transaction) {
+        let cost = [1.0, receipt::gas_required(transaction) as f64];
+        let lane = self.reserved.iter().position(|(currency, _)| *currency == transaction.currency_type);
+        for resource in 0..2 {
+            used[resource] += cost[resource];
+            if let Some(index) = lane {
+                used_by_lane[index][resource] += cost[resource];
+            }
+        }
+    }
+
+    /// The first lane whose reservation the transactions outside it have
+    /// taken some of.
+    fn overdrawn(&self, used: &[f64; 2], used_by_lane: &[[f64; 2]], limits: &[f64; 2]) -> Option<&CurrencyType> {
+        self.reserved.iter().zip(used_by_lane).find(|((_, share), lane_used)| {
+            (0..2).any(|resource| used[resource] - lane_used[resource] > (1.0 - share) * limits[resource] + f64::EPSILON * limits[resource])
+        }).map(|((currency, _), _)| currency)
+    }
+}
+
+fn limits(max_transactions: usize, max_gas: u64) -> [f64; 2] {
+    [max_transactions as f64, max_gas as f64]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lanes_keep_room_for_essential_transfers() {
+        let mut lanes = Lanes::default();
+        assert!(lanes.reserve(vec![(CurrencyType::BasicNeeds, 1.5)], "p").is_err());
+        assert!(lanes.reserve(vec![(CurrencyType::BasicNeeds, 0.5), (CurrencyType::BasicNeeds, 0.25)], "p").is_err());
+        lanes.reserve(vec![(CurrencyType::BasicNeeds, 0.25)], "p").unwrap();
+
+        let transfer = |currency_type, price| Transaction::new("Alice".to_string(), "Bob".to_string(), 1.0, currency_type, 1000).with_gas_price(price);
+        let mut pending: Vec<Transaction> = (0..8).map(|_| transfer(CurrencyType::Luxury, 9.0)).collect();
+        pending.extend((0..3).map(|_| transfer(CurrencyType::BasicNeeds, 1.0)));
+        let (selected, waiting) = lanes.select(pending.clone(), 8, 1_000_000);
+        let essential = selected.iter().filter(|transaction| transaction.currency_type == CurrencyType::BasicNeeds).count();
+        assert_eq!((selected.len(), essential, waiting.len()), (8, 2, 3), "a quarter of the block goes to basic needs");
+        assert!(lanes.check(&selected, 8, 1_000_000).is_ok());
+
+        // the reservation holds for gas as well
+        let (selected, _) = lanes.select(pending.clone(), 100, 8 * receipt::TRANSFER_GAS);
+        assert_eq!(selected.iter().filter(|transaction| transaction.currency_type == CurrencyType::BasicNeeds).count(), 2);
+        // and whether or not anything waits in the lane
+        let (selected, _) = lanes.select(pending[..8].to_vec(), 8, 1_000_000);
+        assert_eq!(selected.len(), 6);
+        assert!(lanes.check(&pending[..8], 8, 1_000_000).is_err());
+        assert!(lanes.check(&pending, 10, 1_000_000).is_err(), "over the block limit");
+    }
+}
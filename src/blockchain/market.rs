@@ -0,0 +1,277 @@
+// src/blockchain/market.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use crate::reputation::ContributionCategory;
+use crate::smart_contract::ContractEvent;
+use super::payout::{event, payout};
+use super::{Transaction, Transfer};
+
+/// The account holding what consumers put up for their requests until the
+/// resources are delivered, and paying providers out of it.
+pub const MARKET_ACCOUNT: &str = "icn:market";
+
+/// A resource traded on the marketplace.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Storage,
+    Compute,
+    Energy,
+}
+
+impl Resource {
+    /// The currency the resource is priced and paid in.
+    pub fn currency(&self) -> CurrencyType {
+        match self {
+            Resource::Storage => CurrencyType::Storage,
+            Resource::Compute => CurrencyType::Processing,
+            Resource::Energy => CurrencyType::Energy,
+        }
+    }
+
+    /// The contribution delivering the resource earns its provider.
+    pub fn contribution(&self) -> ContributionCategory {
+        match self {
+            Resource::Storage => ContributionCategory::Storage,
+            Resource::Compute => ContributionCategory::Compute,
+            Resource::Energy => ContributionCategory::Community,
+        }
+    }
+}
+
+/// What a marketplace transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum MarketAction {
+    /// Offers `quantity` units of a resource at `unit_price` each.
+    Offer { resource: Resource, quantity: f64, unit_price: f64 },
+    /// Asks for `quantity` units of a resource at up to `max_unit_price`
+    /// each, putting up the amount of the transaction, their product.
+    Request { resource: Resource, quantity: f64, max_unit_price: f64 },
+    /// Proves the provider delivered an allocation, e.g. by the CID of a
+    /// storage proof or a metering record.
+    Fulfil { allocation_id: String, proof: String },
+    /// Accepts the proof of an allocation, sent by its consumer, paying the
+    /// provider.
+    Confirm { allocation_id: String },
+    /// Withdraws what is left of an order, refunding a request.
+    Cancel { order_id: String },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Side {
+    Offer,
+    Request,
+}
+
+/// An offer or request not yet wholly matched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Order {
+    /// Hash of the transaction that placed the order.
+    pub id: String,
+    pub owner: String,
+    pub side: Side,
+    pub resource: Resource,
+    /// What is left to match.
+    pub quantity: f64,
+    /// The price asked by an offer, or the most a request pays.
+    pub unit_price: f64,
+    /// Index of the block the order was placed in.
+    pub placed_at: u64,
+}
+
+/// Units of a resource a provider owes a consumer, paid for at the price of
+/// the offer once the consumer accepts the proof of delivery.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Allocation {
+    /// The request and offer matched, joined by a colon.
+    pub id: String,
+    pub provider: String,
+    pub consumer: String,
+    pub resource: Resource,
+    pub quantity: f64,
+    pub unit_price: f64,
+    /// Index of the block the orders were matched in.
+    pub allocated_at: u64,
+    pub proof: Option<String>,
+}
+
+impl Allocation {
+    pub fn price(&self) -> f64 {
+        self.quantity * self.unit_price
+    }
+}
+
+/// What applying a marketplace transaction did: its events, the payouts made
+/// by the block applying it and the contributions to credit providers with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarketOutcome {
+    pub events: Vec<ContractEvent>,
+    pub payouts: Vec<Transfer>,
+    pub contributions: Vec<(String, ContributionCategory, f64)>,
+}
+
+/// The order book and the allocations awaiting delivery. A new order is
+/// matched against the other side at once: requests with the cheapest offers
+/// first, offers with the requests paying most first, earlier orders first
+/// at the same price. Trades happen at the price of the offer, the rest of
+/// what a request put up for them being refunded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceMarket {
+    orders: BTreeMap<String, Order>,
+    allocations: BTreeMap<String, Allocation>,
+}
+
+impl ResourceMarket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&Order> {
+        self.orders.get(order_id)
+    }
+
+    pub fn allocation(&self, allocation_id: &str) -> Option<&Allocation> {
+        self.allocations.get(allocation_id)
+    }
+
+    /// The open orders for `resource` on `side`, best first.
+    pub fn book(&self, resource: Resource, side: Side) -> Vec<&Order> {
+        let mut orders: Vec<&Order> = self.orders.values().filter(|order| order.resource == resource && order.side == side).collect();
+        orders.sort_by(|a, b| {
+            let by_price = match side {
+                Side::Offer => a.unit_price.total_cmp(&b.unit_price),
+                Side::Request => b.unit_price.total_cmp(&a.unit_price),
+            };
+            by_price.then(a.placed_at.cmp(&b.placed_at)).then_with(|| a.id.cmp(&b.id))
+        });
+        orders
+    }
+
+    /// The open orders of `address`.
+    pub fn orders_of(&self, address: &str) -> Vec<&Order> {
+        self.orders.values().filter(|order| order.owner == address).collect()
+    }
+
+    /// The allocations `address` provides or consumes.
+    pub fn allocations_of(&self, address: &str) -> Vec<&Allocation> {
+        self.allocations.values().filter(|allocation| allocation.provider == address || allocation.consumer == address).collect()
+    }
+
+    /// Applies the marketplace action of `transaction`, in the block at
+    /// `index`.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64) -> Result<MarketOutcome, String> {
+        let by = &transaction.from;
+        match &transaction.market {
+            Some(MarketAction::Offer { resource, quantity, unit_price }) => {
+                let order = Order { id: transaction.hash(), owner: by.clone(), side: Side::Offer, resource: *resource, quantity: *quantity, unit_price: *unit_price, placed_at: index };
+                self.place(order, index)
+            }
+            Some(MarketAction::Request { resource, quantity, max_unit_price }) => {
+                if transaction.currency_type != resource.currency() || transaction.amount != quantity * max_unit_price {
+                    return Err(format!("A request for {:?} puts up its quantity times its price in {}", resource, resource.currency()));
+                }
+                if transaction.to != MARKET_ACCOUNT {
+                    return Err(format!("A request puts up its price with {}", MARKET_ACCOUNT));
+                }
+                let order = Order { id: transaction.hash(), owner: by.clone(), side: Side::Request, resource: *resource, quantity: *quantity, unit_price: *max_unit_price, placed_at: index };
+                self.place(order, index)
+            }
+            Some(MarketAction::Fulfil { allocation_id, proof }) => {
+                let allocation = self.allocations.get_mut(allocation_id).ok_or_else(|| format!("No open allocation {}", allocation_id))?;
+                if allocation.provider != *by {
+                    return Err(format!("{} is not the provider of allocation {}", by, allocation_id));
+                }
+                allocation.proof = Some(proof.clone());
+                Ok(MarketOutcome { events: vec![event(allocation_id, "ResourceDelivered", proof.clone())], ..MarketOutcome::default() })
+            }
+            Some(MarketAction::Confirm { allocation_id }) => {
+                let allocation = self.allocations.get(allocation_id).ok_or_else(|| format!("No open allocation {}", allocation_id))?;
+                if allocation.consumer != *by {
+                    return Err(format!("{} is not the consumer of allocation {}", by, allocation_id));
+                }
+                if allocation.proof.is_none() {
+                    return Err(format!("Allocation {} has no proof of delivery", allocation_id));
+                }
+                let allocation = self.allocations.remove(allocation_id).expect("the allocation was just found");
+                info!("Allocation {} confirmed, paying {} {} to {}", allocation_id, allocation.price(), allocation.resource.currency(), allocation.provider);
+                Ok(MarketOutcome {
+                    events: vec![event(allocation_id, "AllocationSettled", allocation.price().to_string())],
+                    payouts: vec![payout(MARKET_ACCOUNT, &allocation.provider, allocation.price(), &allocation.resource.currency())],
+                    contributions: vec![(allocation.provider.clone(), allocation.resource.contribution(), allocation.quantity)],
+                })
+            }
+            Some(MarketAction::Cancel { order_id }) => {
+                let order = self.orders.get(order_id).ok_or_else(|| format!("No open order {}", order_id))?;
+                if order.owner != *by {
+                    return Err(format!("{} did not place order {}", by, order_id));
+                }
+                let order = self.orders.remove(order_id).expect("the order was just found");
+                let payouts = match order.side {
+                    Side::Request => vec![payout(MARKET_ACCOUNT, &order.owner, order.quantity * order.unit_price, &order.resource.currency())],
+                    Side::Offer => Vec::new(),
+                };
+                Ok(MarketOutcome { events: vec![event(order_id, "OrderCancelled", order.quantity.to_string())], payouts, ..MarketOutcome::default() })
+            }
+            None => Err("Not a marketplace transaction".to_string()),
+        }
+    }
+
+    /// Matches a new order against the book, keeping what is left of it open.
+    fn place(&mut self, mut order: Order, index: u64) -> Result<MarketOutcome, String> {
+        if !order.quantity.is_finite() || order.quantity <= 0.0 || !order.unit_price.is_finite() || order.unit_price <= 0.0 {
+            return Err("An order needs a positive quantity and price".to_string());
+        }
+        let mut outcome = MarketOutcome {
+            events: vec![event(&order.id, "OrderPlaced", format!("{:?} {} {:?} at {}", order.side, order.quantity, order.resource, order.unit_price))],
+            ..MarketOutcome::default()
+        };
+        let other_side = match order.side {
+            Side::Offer => Side::Request,
+            Side::Request => Side::Offer,
+        };
+        let counterparts: Vec<String> = self.book(order.resource, other_side).into_iter()
+            .filter(|other| other.owner != order.owner)
+            .filter(|other| match order.side {
+                Side::Offer => other.unit_price >= order.unit_price,
+                Side::Request => other.unit_price <= order.unit_price,
+            })
+            .map(|other| other.id.clone())
+            .collect();
+        for other_id in counterparts {
+            if order.quantity <= 0.0 {
+                break;
+            }
+            let other = self.orders.get_mut(&other_id).expect("the order is in the book");
+            let quantity = order.quantity.min(other.quantity);
+            order.quantity -= quantity;
+            other.quantity -= quantity;
+            let other = if other.quantity <= 0.0 { self.orders.remove(&other_id).expect("the order is in the book") } else { other.clone() };
+            let (offer, request) = match order.side {
+                Side::Offer => (&order, &other),
+                Side::Request => (&other, &order),
+            };
+            let allocation = Allocation {
+                id: format!("{}:{}", request.id, offer.id),
+                provider: offer.owner.clone(),
+                consumer: request.owner.clone(),
+                resource: order.resource,
+                quantity,
+                unit_price: offer.unit_price,
+                allocated_at: index,
+                proof: None,
+            };
+            let refund = quantity * (request.unit_price - offer.unit_price);
+            if refund > 0.0 {
+                outcome.payouts.push(payout(MARKET_ACCOUNT, &request.owner, refund, &order.resource.currency()));
+            }
+            info!("Allocated {} {:?} from {} to {} at {}", quantity, allocation.resource, allocation.provider, allocation.consumer, allocation.unit_price);
+            outcome.events.push(event(&allocation.id, "ResourceAllocated", format!("{} at {}", quantity, allocation.unit_price)));
+            self.allocations.insert(allocation.id.clone(), allocation);
+        }
+        if order.quantity > 0.0 {
+            self.orders.insert(order.id.clone(), order);
+        }
+        Ok(outcome)
+    }
+}
@@ -0,0 +1,170 @@
+// src/blockchain/organization.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use super::Transaction;
+
+/// The account organizations are created and managed with.
+pub const ORGANIZATION_ACCOUNT: &str = "icn:organizations";
+
+/// What a member may do in an organization.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Role {
+    Member,
+    Auditor,
+    Treasurer,
+}
+
+/// The operations gated by role.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Permission {
+    /// Paying out of the treasury.
+    Spend,
+    /// Putting proposals to governance for the organization.
+    Propose,
+    /// Admitting, removing and changing the roles of members.
+    Invite,
+}
+
+impl Role {
+    pub fn permits(&self, permission: Permission) -> bool {
+        match self {
+            Role::Treasurer => true,
+            Role::Auditor | Role::Member => permission == Permission::Propose,
+        }
+    }
+}
+
+/// What an organization transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum OrganizationAction {
+    /// Founds the organization `name`, with the sender as its treasurer.
+    Create { name: String },
+    /// Admits `member` to `organization` as `role`, or gives a member that
+    /// role.
+    Invite { organization: String, member: String, role: Role },
+    /// Removes `member` from `organization`; members may remove themselves.
+    Remove { organization: String, member: String },
+    /// Set on a transfer out of the treasury of the sending organization,
+    /// signed by the member `by`.
+    Spend { by: String },
+}
+
+/// A cooperative on chain. Its treasury is the account named by its id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Organization {
+    /// `org:` followed by its name.
+    pub id: String,
+    pub name: String,
+    pub members: BTreeMap<String, Role>,
+}
+
+impl Organization {
+    pub fn role_of(&self, member: &str) -> Option<Role> {
+        self.members.get(member).copied()
+    }
+
+    /// Fails unless `member` has a role permitting `permission`.
+    pub fn check(&self, member: &str, permission: Permission) -> Result<(), String> {
+        match self.role_of(member) {
+            Some(role) if role.permits(permission) => Ok(()),
+            Some(role) => Err(format!("{} is {:?} of {}, which does not permit {:?}", member, role, self.id, permission)),
+            None => Err(format!("{} is not a member of {}", member, self.id)),
+        }
+    }
+}
+
+/// The organizations founded on chain, by id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Organizations {
+    organizations: BTreeMap<String, Organization>,
+}
+
+impl Organizations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Organization> {
+        self.organizations.get(id)
+    }
+
+    /// The organizations `member` belongs to.
+    pub fn of(&self, member: &str) -> Vec<&Organization> {
+        self.organizations.values().filter(|organization| organization.members.contains_key(member)).collect()
+    }
+
+    /// Fails unless `member` may do what `permission` gates in `organization`.
+    pub fn check(&self, organization: &str, member: &str, permission: Permission) -> Result<(), String> {
+        self.get(organization)
+            .ok_or_else(|| format!("No organization {}", organization))?
+            .check(member, permission)
+    }
+
+    /// Fails for an organization transaction its sender, or the member
+    /// spending for it, has no role for.
+    pub fn authorize(&self, transaction: &Transaction) -> Result<(), String> {
+        match &transaction.organization {
+            Some(OrganizationAction::Create { name }) if self.organizations.contains_key(&id_of(name)) => {
+                Err(format!("Organization {} already exists", id_of(name)))
+            }
+            Some(OrganizationAction::Invite { organization, .. }) => self.check(organization, &transaction.from, Permission::Invite),
+            Some(OrganizationAction::Remove { organization, member }) if *member == transaction.from => {
+                self.get(organization).map(|_| ()).ok_or_else(|| format!("No organization {}", organization))
+            }
+            Some(OrganizationAction::Remove { organization, .. }) => self.check(organization, &transaction.from, Permission::Invite),
+            Some(OrganizationAction::Spend { by }) => self.check(&transaction.from, by, Permission::Spend),
+            Some(OrganizationAction::Create { .. }) | None => Ok(()),
+        }
+    }
+
+    /// Applies the organization action of `transaction`.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), String> {
+        self.authorize(transaction)?;
+        match &transaction.organization {
+            Some(OrganizationAction::Create { name }) => {
+                if name.is_empty() {
+                    return Err("An organization needs a name".to_string());
+                }
+                let id = id_of(name);
+                info!("{} founded organization {}", transaction.from, id);
+                self.organizations.insert(id.clone(), Organization {
+                    id,
+                    name: name.clone(),
+                    members: BTreeMap::from([(transaction.from.clone(), Role::Treasurer)]),
+                });
+            }
+            Some(OrganizationAction::Invite { organization, member, role }) => {
+                let organization = self.organizations.get_mut(organization).expect("the organization was just authorized");
+                let mut members = organization.members.clone();
+                members.insert(member.clone(), *role);
+                organization.members = with_treasurer(&organization.id, members)?;
+                info!("{} made {} {:?} of {}", transaction.from, member, role, organization.id);
+            }
+            Some(OrganizationAction::Remove { organization, member }) => {
+                let organization = self.organizations.get_mut(organization).expect("the organization was just authorized");
+                let mut members = organization.members.clone();
+                if members.remove(member).is_none() {
+                    return Err(format!("{} is not a member of {}", member, organization.id));
+                }
+                organization.members = with_treasurer(&organization.id, members)?;
+                info!("{} removed {} from {}", transaction.from, member, organization.id);
+            }
+            Some(OrganizationAction::Spend { .. }) | None => {}
+        }
+        Ok(())
+    }
+}
+
+/// The id, and treasury account, of the organization named `name`.
+pub fn id_of(name: &str) -> String {
+    format!("org:{}", name)
+}
+
+/// `members`, if one of them is still a treasurer.
+fn with_treasurer(id: &str, members: BTreeMap<String, Role>) -> Result<BTreeMap<String, Role>, String> {
+    if !members.values().any(|role| *role == Role::Treasurer) {
+        return Err(format!("{} would be left without a treasurer", id));
+    }
+    Ok(members)
+}
@@ -0,0 +1,58 @@
+// src/blockchain/parameters.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+
+/// Once in force, every transaction must be signed, save those authorised
+/// when they were set up; see `TransactionValidator`.
+pub const SIGNED_TRANSACTIONS: &str = "signed-transactions";
+
+/// A feature flag switched on by governance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    /// Index of the first block the feature is in force for.
+    pub activation_height: u64,
+    /// The proposal that adopted it.
+    pub proposal_id: String,
+}
+
+/// The chain parameters adopted by governance, kept in the chain state so
+/// that every node switches behavior at the same block.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParameterRegistry {
+    features: BTreeMap<String, FeatureFlag>,
+}
+
+impl ParameterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `name` is in force from the block at `activation_height`
+    /// on. A feature is only scheduled once.
+    pub fn schedule_feature(&mut self, name: &str, activation_height: u64, proposal_id: &str) -> Result<(), String> {
+        if self.features.contains_key(name) {
+            return Err(format!("Feature {} is already scheduled", name));
+        }
+        info!("Feature {} scheduled from block {} by proposal {}", name, activation_height, proposal_id);
+        self.features.insert(name.to_string(), FeatureFlag { activation_height, proposal_id: proposal_id.to_string() });
+        Ok(())
+    }
+
+    pub fn feature(&self, name: &str) -> Option<&FeatureFlag> {
+        self.features.get(name)
+    }
+
+    /// Whether `name` is in force for the block at `height`.
+    pub fn is_feature_active(&self, name: &str, height: u64) -> bool {
+        self.features.get(name).is_some_and(|feature| feature.activation_height <= height)
+    }
+
+    /// The features in force for the block at `height`.
+    pub fn active_features(&self, height: u64) -> Vec<String> {
+        self.features.iter()
+            .filter(|(_, feature)| feature.activation_height <= height)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,16 @@
+// src/blockchain/payout.rs
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use super::Transfer;
+
+/// A transfer out of one of the chain's holding accounts, made by the block
+/// that applies the transaction owing it.
+pub fn payout(from: &str, to: &str, amount: f64, currency_type: &CurrencyType) -> Transfer {
+    Transfer { from: from.to_string(), to: to.to_string(), amount, currency_type: currency_type.clone() }
+}
+
+/// An event raised by the chain for one of its records rather than by a
+/// deployed contract.
+pub fn event(id: &str, name: &str, data: String) -> ContractEvent {
+    ContractEvent { contract_id: id.to_string(), name: name.to_string(), data }
+}
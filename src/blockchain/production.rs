@@ -0,0 +1,172 @@
+// src/blockchain/production.rs
+use std::time::Duration;
+use ed25519_dalek::Keypair;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
+use crate::clock::SharedClock;
+use crate::consensus::SignedVote;
+use crate::error::{Error, Result};
+use super::spec::{MAX_BLOCK_GAS, MAX_BLOCK_TRANSACTIONS};
+use super::{Block, Blockchain};
+
+/// When blocks are produced. Set alike on every validator of a network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductionConfig {
+    /// How often the proposer seals the transactions waiting in the mempool.
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// Most transactions in a block, within the limit of the chain spec; a
+    /// full block's worth is sealed without waiting for the interval.
+    pub max_transactions: usize,
+    /// Most gas the transactions of a block may need, within the limit of
+    /// the chain spec.
+    pub max_gas: u64,
+    /// How long after the tip the next validator in turn takes over from a
+    /// proposer that produced nothing.
+    #[serde(with = "humantime_serde")]
+    pub proposer_timeout: Duration,
+}
+
+impl Default for ProductionConfig {
+    fn default() -> Self {
+        ProductionConfig {
+            interval: Duration::from_secs(5),
+            max_transactions: MAX_BLOCK_TRANSACTIONS,
+            max_gas: MAX_BLOCK_GAS,
+            proposer_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A validator's part in producing blocks: proposing them in its turn and
+/// voting on those of the others.
+#[derive(Debug)]
+pub struct BlockProducer {
+    member_id: String,
+    keypair: Keypair,
+    config: ProductionConfig,
+    clock: SharedClock,
+}
+
+impl BlockProducer {
+    /// Produces blocks as `member_id`, signing votes with `keypair`, the key
+    /// registered for it with the consensus.
+    pub fn new(member_id: String, keypair: Keypair, config: ProductionConfig) -> Self {
+        BlockProducer { member_id, keypair, config, clock: SharedClock::default() }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn member_id(&self) -> &str {
+        &self.member_id
+    }
+
+    pub fn config(&self) -> &ProductionConfig {
+        &self.config
+    }
+
+    /// The validator to propose the next block of `blockchain`. Validators
+    /// take turns by height; each `proposer_timeout` that passes without a
+    /// block hands the turn to the next one.
+    pub fn proposer(&self, blockchain: &Blockchain) -> Option<String> {
+        let tip = blockchain.get_latest_block()?;
+        let waited = (self.clock.now().timestamp() - tip.timestamp).max(0) as u64;
+        let round = waited / self.config.proposer_timeout.as_secs().max(1);
+        blockchain.consensus.proposer(blockchain.height().wrapping_add(round)).map(str::to_string)
+    }
+
+    /// Seals the pending transactions offering the highest gas prices, oldest
+    /// first among equal offers, up to `max_transactions` and `max_gas` and
+    /// within the chain's lanes, in a block if it is this validator's turn: once `interval_elapsed`
+    /// whenever any are waiting, otherwise only when a full block's worth
+    /// is. Returns the block, to broadcast and vote on.
+    pub fn propose(&self, blockchain: &mut Blockchain, interval_elapsed: bool) -> Result<Option<Block>> {
+        let waiting = blockchain.pending_transactions.len();
+        if waiting == 0 || (!interval_elapsed && waiting < self.config.max_transactions) {
+            return Ok(None);
+        }
+        if self.proposer(blockchain).as_deref() != Some(self.member_id.as_str()) {
+            return Ok(None);
+        }
+        // the best offers first, in the order they came otherwise
+        blockchain.pending_transactions.sort_by(|a, b| b.gas_price.total_cmp(&a.gas_price));
+        let pending = std::mem::take(&mut blockchain.pending_transactions);
+        let max_transactions = self.config.max_transactions.min(blockchain.spec.max_block_transactions);
+        let max_gas = self.config.max_gas.min(blockchain.spec.max_block_gas);
+        let (selected, overflow) = blockchain.lanes.select(pending, max_transactions, max_gas);
+        blockchain.pending_transactions = selected;
+        let result = blockchain.create_signed_block(self.member_id.clone(), &self.keypair);
+        // payouts queued by the new block wait behind the overflow
+        let queued = std::mem::take(&mut blockchain.pending_transactions);
+        blockchain.pending_transactions = match result {
+            Ok(()) => overflow.into_iter().chain(queued).collect(),
+            Err(_) => queued.into_iter().chain(overflow).collect(),
+        };
+        result?;
+        let block = blockchain.get_latest_block().cloned()
+            .ok_or_else(|| Error::BlockchainError("No block was created".to_string()))?;
+        info!("Proposed block {} with {} transactions", block.index, block.transactions.len());
+        Ok(Some(block))
+    }
+
+    /// Signs this validator's approval of `block`, which `blockchain` has
+    /// just taken, and counts it. Returns the vote to broadcast, or None if
+    /// this member is not an active validator.
+    pub fn vote(&self, blockchain: &mut Blockchain, block: &Block) -> Result<Option<SignedVote>> {
+        if !blockchain.consensus.is_active_validator(&self.member_id) {
+            return Ok(None);
+        }
+        let vote = SignedVote::new(self.member_id.clone(), block.hash.clone(), block.index, true, &self.keypair);
+        let approved = blockchain.submit_vote(&vote)?;
+        debug!("Voted for block {}; approved: {}", block.index, approved);
+        Ok(Some(vote))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::currency::CurrencyType;
+    use crate::blockchain::{ChainSpec, Transaction};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_validators_take_turns_proposing() {
+        let clock = MockClock::new();
+        let config = ProductionConfig { max_transactions: 2, ..ProductionConfig::default() };
+        let mut blockchain = Blockchain::with_spec(ChainSpec::default().with_allocation("Alice", 100.0, CurrencyType::BasicNeeds));
+        let producers: Vec<BlockProducer> = ["alice", "bob"].iter().map(|id| {
+            let keypair = Keypair::generate(&mut OsRng {});
+            blockchain.consensus.add_member(id.to_string(), true);
+            blockchain.consensus.register_key(id, &keypair.public).unwrap();
+            BlockProducer::new(id.to_string(), keypair, config.clone()).with_clock(clock.clone().into())
+        }).collect();
+        blockchain.create_block("alice".to_string()).unwrap();
+        let turn = |blockchain: &Blockchain| producers.iter().position(|producer| producer.proposer(blockchain).as_deref() == Some(producer.member_id())).unwrap();
+
+        let transfer = |amount| Transaction::new("Alice".to_string(), "Bob".to_string(), amount, CurrencyType::BasicNeeds, 1000);
+        blockchain.add_transaction(transfer(1.0)).unwrap();
+        let proposer = turn(&blockchain);
+        assert!(producers[proposer].propose(&mut blockchain, false).unwrap().is_none(), "the block is not full yet");
+        assert!(producers[1 - proposer].propose(&mut blockchain, true).unwrap().is_none(), "not its turn");
+        for amount in [2.0, 3.0] {
+            blockchain.add_transaction(transfer(amount)).unwrap();
+        }
+        let block = producers[proposer].propose(&mut blockchain, false).unwrap().unwrap();
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(blockchain.pending_transactions, vec![transfer(3.0)]);
+
+        assert_eq!(turn(&blockchain), 1 - proposer, "the turn passes on");
+        for producer in &producers {
+            producer.vote(&mut blockchain, &block).unwrap().unwrap();
+        }
+        assert!(blockchain.is_block_approved(&block.hash));
+
+        clock.advance(config.proposer_timeout + Duration::from_secs(1));
+        assert_eq!(turn(&blockchain), proposer, "a silent proposer is skipped");
+    }
+}
@@ -0,0 +1,99 @@
+// src/blockchain/receipt.rs
+use crate::blockchain::Transaction;
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use serde::{Serialize, Deserialize};
+
+/// Gas charged for every transaction, covering the transfer.
+pub const TRANSFER_GAS: u64 = 100;
+/// Gas charged on top of `TRANSFER_GAS` for running the contract a
+/// transaction names.
+pub const CONTRACT_GAS: u64 = 500;
+/// Gas charged on top of `TRANSFER_GAS` for each output of a batch
+/// transaction after the first.
+pub const OUTPUT_GAS: u64 = 20;
+/// Gas charged for each step a contract deployed from code takes, on top of
+/// `CONTRACT_GAS`.
+pub const STEP_GAS: u64 = 1;
+/// Gas charged per byte of the code a contract creation deploys, on top of
+/// `CONTRACT_GAS` for running its constructor.
+pub const CODE_BYTE_GAS: u64 = 1;
+
+/// The gas a transaction needs to go through before the steps of the
+/// contracts it runs. A swap pays for the transfers of both sides.
+pub fn gas_required(transaction: &Transaction) -> u64 {
+    let outputs = OUTPUT_GAS * transaction.outputs.len().saturating_sub(1) as u64;
+    let transfers = if transaction.swap.is_some() { 2 * TRANSFER_GAS } else { TRANSFER_GAS };
+    let creation = transaction.contract_creation.as_ref()
+        .map_or(0, |creation| CONTRACT_GAS + CODE_BYTE_GAS * creation.code.bytecode.len() as u64);
+    match transaction.smart_contract_id {
+        Some(_) => transfers + CONTRACT_GAS + outputs + creation,
+        None => transfers + outputs + creation,
+    }
+}
+
+/// The most steps the contracts a transaction runs may take within its gas
+/// limit.
+pub fn step_budget(transaction: &Transaction) -> u64 {
+    transaction.gas_limit.saturating_sub(gas_required(transaction)) / STEP_GAS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ReceiptStatus {
+    Success,
+    /// The transaction was included but had no effect, for the given reason.
+    Failed(String),
+}
+
+/// A balance a transaction changed, and its value in that currency right
+/// after the transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BalanceChange {
+    pub address: String,
+    pub currency_type: CurrencyType,
+    pub delta: f64,
+    pub balance: f64,
+}
+
+/// The outcome of a transaction once its block has been executed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransactionReceipt {
+    pub transaction_hash: String,
+    pub block_index: u64,
+    pub block_hash: String,
+    pub status: ReceiptStatus,
+    pub gas_used: u64,
+    /// Events emitted by the contract the transaction ran, in order.
+    pub events: Vec<ContractEvent>,
+    pub balance_changes: Vec<BalanceChange>,
+    /// The address of the contract a contract creation deployed.
+    #[serde(default)]
+    pub contract_address: Option<String>,
+}
+
+impl TransactionReceipt {
+    pub fn is_success(&self) -> bool {
+        self.status == ReceiptStatus::Success
+    }
+}
+
+/// Selects the events of a range of blocks. Each criterion left unset
+/// matches every event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogFilter {
+    pub from_block: u64,
+    pub to_block: Option<u64>,
+    /// The contract that emitted the event, or the sender or recipient of the
+    /// transaction that ran it.
+    pub address: Option<String>,
+    /// The name of the event.
+    pub topic: Option<String>,
+}
+
+/// An event found by a `LogFilter`, with where it was emitted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LogEntry {
+    pub block_index: u64,
+    pub transaction_hash: String,
+    pub event: ContractEvent,
+}
@@ -0,0 +1,135 @@
+// src/blockchain/rent.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+use crate::currency::CurrencyType;
+use crate::governance::{DemocraticSystem, ProposalType};
+use crate::governance::democracy::ProposalStatus;
+use crate::smart_contract::ContractRegistry;
+use super::Transaction;
+
+/// The account rent for contract storage is paid to.
+pub const RENT_ACCOUNT: &str = "icn:rent";
+
+/// What keeping contract state costs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RentSchedule {
+    /// Charged per byte of a contract's state for every block it is kept.
+    pub price_per_byte_block: f64,
+    pub currency_type: CurrencyType,
+    /// Blocks a contract that ran out of rent is kept for before its state
+    /// is reclaimed.
+    pub grace_blocks: u64,
+}
+
+impl Default for RentSchedule {
+    fn default() -> Self {
+        RentSchedule {
+            price_per_byte_block: 0.0001,
+            currency_type: CurrencyType::BasicNeeds,
+            grace_blocks: 1000,
+        }
+    }
+}
+
+/// The rent standing of a deployed contract.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RentAccount {
+    /// Rent paid in advance and not charged yet.
+    pub balance: f64,
+    /// Bytes of state charged for at the last block.
+    pub size: usize,
+    /// Index of the block the contract ran out of rent at.
+    pub overdue_since: Option<u64>,
+    /// Exempted by governance, as a public good, from paying rent.
+    pub exempt: bool,
+}
+
+/// Charges deployed contracts rent for the bytes of state every node keeps
+/// for them, out of what was paid in advance with `Transaction::pay_rent`.
+/// A contract whose rent ran out has its state reclaimed once the grace
+/// period is over, unless it is paid for again first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateRent {
+    pub schedule: RentSchedule,
+    accounts: BTreeMap<String, RentAccount>,
+}
+
+impl StateRent {
+    pub fn new(schedule: RentSchedule) -> Self {
+        StateRent { schedule, accounts: BTreeMap::new() }
+    }
+
+    pub fn account(&self, contract_id: &str) -> Option<&RentAccount> {
+        self.accounts.get(contract_id)
+    }
+
+    /// Credits the amount of a rent payment to the contract it names, which
+    /// is no longer overdue if that covers the next block.
+    pub fn deposit(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let contract_id = match &transaction.rent_for {
+            Some(contract_id) => contract_id,
+            None => return Ok(()),
+        };
+        if transaction.currency_type != self.schedule.currency_type || !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+            return Err(format!("Rent is paid in positive amounts of {}", self.schedule.currency_type));
+        }
+        if transaction.to != RENT_ACCOUNT {
+            return Err(format!("Rent is paid to {}", RENT_ACCOUNT));
+        }
+        let price = self.schedule.price_per_byte_block;
+        let account = self.accounts.entry(contract_id.clone()).or_default();
+        account.balance += transaction.amount;
+        if account.balance >= account.size as f64 * price {
+            account.overdue_since = None;
+        }
+        info!("{} paid {} rent for contract {}", transaction.from, transaction.amount, contract_id);
+        Ok(())
+    }
+
+    /// Exempts a contract from rent by a passed economic adjustment proposal,
+    /// which is marked implemented so it cannot be applied twice.
+    pub fn exempt(&mut self, governance: &mut DemocraticSystem, proposal_id: &str, contract_id: &str) -> Result<(), String> {
+        let proposal = governance.get_proposal(proposal_id).ok_or("Proposal not found")?;
+        if proposal.proposal_type != ProposalType::EconomicAdjustment {
+            return Err(format!("Proposal {} is not an economic adjustment", proposal_id));
+        }
+        if proposal.status != ProposalStatus::Passed {
+            return Err(format!("Proposal {} has not passed", proposal_id));
+        }
+        governance.mark_as_implemented(proposal_id)?;
+        let account = self.accounts.entry(contract_id.to_string()).or_default();
+        account.exempt = true;
+        account.overdue_since = None;
+        info!("Contract {} exempted from rent by proposal {}", contract_id, proposal_id);
+        Ok(())
+    }
+
+    /// Charges every contract of `registry` the rent of the block at `index`,
+    /// returning those whose grace period is over and whose state is to be
+    /// reclaimed. Accounts of contracts no longer deployed are dropped.
+    pub fn charge(&mut self, index: u64, registry: &ContractRegistry) -> Vec<String> {
+        self.accounts.retain(|contract_id, _| registry.contains(contract_id));
+        let mut expired = Vec::new();
+        for contract_id in registry.ids() {
+            let account = self.accounts.entry(contract_id.clone()).or_default();
+            account.size = registry.state_size(&contract_id).unwrap_or(0);
+            if account.exempt {
+                continue;
+            }
+            let rent = account.size as f64 * self.schedule.price_per_byte_block;
+            if account.balance >= rent {
+                account.balance -= rent;
+                continue;
+            }
+            account.balance = 0.0;
+            let overdue_since = *account.overdue_since.get_or_insert(index);
+            if index - overdue_since >= self.schedule.grace_blocks {
+                warn!("Contract {} has not paid rent since block {}; reclaiming its state", contract_id, overdue_since);
+                self.accounts.remove(&contract_id);
+                expired.push(contract_id);
+            }
+        }
+        expired
+    }
+}
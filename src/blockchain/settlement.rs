@@ -0,0 +1,169 @@
+// src/blockchain/settlement.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::{receipt, Transaction};
+
+/// The account obligations are recorded with.
+pub const SETTLEMENT_ACCOUNT: &str = "icn:settlement";
+
+/// What a settlement transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum SettlementAction {
+    /// Records that the sender owes `creditor` `amount` of the currency of
+    /// the transaction, to be settled at the end of the epoch.
+    Obligation { creditor: String, amount: f64 },
+    /// One of the net payments settling the epoch ending at block `epoch`,
+    /// queued by the chain for the block after it.
+    Settle { epoch: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Obligation {
+    pub debtor: String,
+    pub creditor: String,
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+}
+
+/// Multilateral netting of what cooperatives owe each other. Obligations
+/// accumulate over an epoch of `interval` blocks without moving funds; at
+/// its end each party's net position in each currency is worked out, and
+/// only the payments evening those out go through the chain. A net payment
+/// its debtor cannot afford is carried into the next epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NettingEngine {
+    /// Blocks per epoch.
+    pub interval: u64,
+    obligations: Vec<Obligation>,
+    /// The net payments of the last epoch not yet made.
+    due: Vec<Transaction>,
+}
+
+impl Default for NettingEngine {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl NettingEngine {
+    pub fn new(interval: u64) -> Self {
+        NettingEngine { interval, obligations: Vec::new(), due: Vec::new() }
+    }
+
+    /// The obligations recorded this epoch.
+    pub fn obligations(&self) -> &[Obligation] {
+        &self.obligations
+    }
+
+    /// What `address` is owed in `currency_type` this epoch, net of what it
+    /// owes; negative if it owes more.
+    pub fn net_position(&self, address: &str, currency_type: &CurrencyType) -> f64 {
+        self.obligations.iter()
+            .filter(|obligation| obligation.currency_type == *currency_type)
+            .map(|obligation| if obligation.creditor == address {
+                obligation.amount
+            } else if obligation.debtor == address {
+                -obligation.amount
+            } else {
+                0.0
+            })
+            .sum()
+    }
+
+    /// Whether the block at `index` ends an epoch.
+    pub fn is_epoch_end(&self, index: u64) -> bool {
+        self.interval > 0 && index > 0 && index.is_multiple_of(self.interval)
+    }
+
+    /// Applies the settlement action of `transaction`. A net payment is
+    /// passed whether it went through.
+    pub fn apply(&mut self, transaction: &Transaction, paid: bool) -> Result<(), String> {
+        match &transaction.settlement {
+            Some(SettlementAction::Obligation { creditor, amount }) => {
+                if !amount.is_finite() || *amount <= 0.0 {
+                    return Err("An obligation needs a positive amount".to_string());
+                }
+                if *creditor == transaction.from {
+                    return Err("An obligation is owed to someone else".to_string());
+                }
+                self.obligations.push(Obligation {
+                    debtor: transaction.from.clone(),
+                    creditor: creditor.clone(),
+                    amount: *amount,
+                    currency_type: transaction.currency_type.clone(),
+                });
+                Ok(())
+            }
+            Some(SettlementAction::Settle { .. }) => {
+                let position = self.due.iter().position(|due| due == transaction)
+                    .ok_or_else(|| "Not a net payment due".to_string())?;
+                let payment = self.due.remove(position);
+                if !paid {
+                    info!("{} could not pay its net {} {} to {}, carried into the next epoch", payment.from, payment.amount, payment.currency_type, payment.to);
+                    self.obligations.push(Obligation { debtor: payment.from, creditor: payment.to, amount: payment.amount, currency_type: payment.currency_type });
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Nets the obligations of the epoch ending at block `epoch`, returning
+    /// the payments settling them. Net payments still due from the last
+    /// epoch are carried into this one.
+    pub fn end_epoch(&mut self, epoch: u64) -> Vec<Transaction> {
+        for payment in std::mem::take(&mut self.due) {
+            self.obligations.push(Obligation { debtor: payment.from, creditor: payment.to, amount: payment.amount, currency_type: payment.currency_type });
+        }
+        let mut positions: Vec<(CurrencyType, BTreeMap<String, f64>)> = Vec::new();
+        for obligation in &self.obligations {
+            let index = match positions.iter().position(|(currency_type, _)| *currency_type == obligation.currency_type) {
+                Some(index) => index,
+                None => {
+                    positions.push((obligation.currency_type.clone(), BTreeMap::new()));
+                    positions.len() - 1
+                }
+            };
+            *positions[index].1.entry(obligation.debtor.clone()).or_default() -= obligation.amount;
+            *positions[index].1.entry(obligation.creditor.clone()).or_default() += obligation.amount;
+        }
+        for (currency_type, positions) in positions {
+            self.due.extend(net_payments(&positions).into_iter().map(|(debtor, creditor, amount)| Transaction {
+                settlement: Some(SettlementAction::Settle { epoch }),
+                ..Transaction::new(debtor, creditor, amount, currency_type.clone(), receipt::TRANSFER_GAS)
+            }));
+        }
+        info!("Epoch ending at block {} nets {} obligations into {} payments", epoch, self.obligations.len(), self.due.len());
+        self.obligations.clear();
+        self.due.clone()
+    }
+}
+
+/// Payments from those with negative positions to those with positive ones
+/// that even them all out, matching the largest debtor with the largest
+/// creditor first; at most one fewer than the parties.
+fn net_payments(positions: &BTreeMap<String, f64>) -> Vec<(String, String, f64)> {
+    const DUST: f64 = 1e-9;
+    let by_size = |a: &(String, f64), b: &(String, f64)| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0));
+    let mut debtors: Vec<(String, f64)> = positions.iter().filter(|(_, net)| **net < -DUST).map(|(party, net)| (party.clone(), -net)).collect();
+    let mut creditors: Vec<(String, f64)> = positions.iter().filter(|(_, net)| **net > DUST).map(|(party, net)| (party.clone(), *net)).collect();
+    debtors.sort_by(by_size);
+    creditors.sort_by(by_size);
+    let mut payments = Vec::new();
+    let (mut debtor, mut creditor) = (0, 0);
+    while debtor < debtors.len() && creditor < creditors.len() {
+        let amount = debtors[debtor].1.min(creditors[creditor].1);
+        payments.push((debtors[debtor].0.clone(), creditors[creditor].0.clone(), amount));
+        debtors[debtor].1 -= amount;
+        creditors[creditor].1 -= amount;
+        if debtors[debtor].1 <= DUST {
+            debtor += 1;
+        }
+        if creditors[creditor].1 <= DUST {
+            creditor += 1;
+        }
+    }
+    payments
+}
@@ -0,0 +1,132 @@
+// src/blockchain/snapshot.rs
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+use crate::bridge::BridgeLedger;
+use crate::consensus::{CircuitBreaker, ContributionTracker, NominationLedger, StakeLedger, VoteLog};
+use crate::currency::CurrencyType;
+use crate::identity::RevocationRegistry;
+use crate::reputation::ReputationStore;
+use crate::smart_contract::CodeStore;
+use super::{
+    Blockchain, Crowdfunding, Dividends, Enactments, Lanes, NettingEngine, Organizations, ParameterRegistry, ResourceMarket,
+    ServiceAgreements, StandingOrders, StateRent, StreamRegistry, UpgradeSchedule, VestingRegistry, Allowances,
+};
+
+/// How many of the latest blocks a node can roll back, e.g. to switch to a
+/// fork. Older blocks are final.
+pub const MAX_REORG_DEPTH: usize = 64;
+
+/// The state applying blocks changes, other than balances and receipts, as
+/// it was before the block at `height`, to roll the block back. It includes
+/// the state of contracts, as they run for a block before it is applied.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub height: u64,
+    contract_state: String,
+    asset_tokens: HashMap<String, CurrencyType>,
+    bonds: HashMap<String, CurrencyType>,
+    stakes: StakeLedger,
+    votes: HashMap<String, BTreeMap<String, bool>>,
+    vote_log: VoteLog,
+    nominations: NominationLedger,
+    reputation: ReputationStore,
+    contributions: ContributionTracker,
+    revocation_registry: RevocationRegistry,
+    upgrades: UpgradeSchedule,
+    parameters: ParameterRegistry,
+    enactments: Enactments,
+    circuit_breaker: CircuitBreaker,
+    bridge: BridgeLedger,
+    lanes: Lanes,
+    streams: StreamRegistry,
+    standing_orders: StandingOrders,
+    allowances: Allowances,
+    validation_contracts: BTreeMap<String, String>,
+    organizations: Organizations,
+    settlement: NettingEngine,
+    dividends: Dividends,
+    vesting: VestingRegistry,
+    crowdfunding: Crowdfunding,
+    agreements: ServiceAgreements,
+    market: ResourceMarket,
+    state_rent: StateRent,
+    code_store: CodeStore,
+}
+
+impl StateSnapshot {
+    pub fn take(blockchain: &Blockchain, height: u64) -> Self {
+        StateSnapshot {
+            height,
+            contract_state: blockchain.execution_environment.state.clone(),
+            asset_tokens: blockchain.asset_tokens.clone(),
+            bonds: blockchain.bonds.clone(),
+            stakes: blockchain.consensus.stakes.clone(),
+            votes: blockchain.consensus.votes.clone(),
+            vote_log: blockchain.consensus.vote_log.clone(),
+            nominations: blockchain.consensus.nominations.clone(),
+            reputation: blockchain.consensus.reputation.clone(),
+            contributions: blockchain.contributions.clone(),
+            revocation_registry: blockchain.revocation_registry.clone(),
+            upgrades: blockchain.upgrades.clone(),
+            parameters: blockchain.parameters.clone(),
+            enactments: blockchain.enactments.clone(),
+            circuit_breaker: blockchain.circuit_breaker.clone(),
+            bridge: blockchain.bridge.clone(),
+            lanes: blockchain.lanes.clone(),
+            streams: blockchain.streams.clone(),
+            standing_orders: blockchain.standing_orders.clone(),
+            allowances: blockchain.allowances.clone(),
+            validation_contracts: blockchain.validation_contracts.clone(),
+            organizations: blockchain.organizations.clone(),
+            settlement: blockchain.settlement.clone(),
+            dividends: blockchain.dividends.clone(),
+            vesting: blockchain.vesting.clone(),
+            crowdfunding: blockchain.crowdfunding.clone(),
+            agreements: blockchain.agreements.clone(),
+            market: blockchain.market.clone(),
+            state_rent: blockchain.state_rent.clone(),
+            code_store: blockchain.code_store.clone(),
+        }
+    }
+
+    /// Puts the state back in `blockchain`. Contracts created since are
+    /// undeployed, and those reclaimed since deployed again.
+    pub fn restore(self, blockchain: &mut Blockchain) {
+        let clock = blockchain.consensus.reputation.clock();
+        blockchain.execution_environment.state = self.contract_state;
+        blockchain.asset_tokens = self.asset_tokens;
+        blockchain.bonds = self.bonds;
+        blockchain.consensus.stakes = self.stakes;
+        blockchain.consensus.votes = self.votes;
+        blockchain.consensus.vote_log = self.vote_log;
+        blockchain.consensus.nominations = self.nominations;
+        blockchain.consensus.reputation = self.reputation.with_clock(clock);
+        blockchain.contributions = self.contributions;
+        blockchain.revocation_registry = self.revocation_registry;
+        blockchain.upgrades = self.upgrades;
+        blockchain.parameters = self.parameters;
+        blockchain.enactments = self.enactments;
+        blockchain.circuit_breaker = self.circuit_breaker;
+        blockchain.bridge = self.bridge;
+        blockchain.lanes = self.lanes;
+        blockchain.streams = self.streams;
+        blockchain.standing_orders = self.standing_orders;
+        blockchain.allowances = self.allowances;
+        blockchain.validation_contracts = self.validation_contracts;
+        blockchain.organizations = self.organizations;
+        blockchain.settlement = self.settlement;
+        blockchain.dividends = self.dividends;
+        blockchain.vesting = self.vesting;
+        blockchain.crowdfunding = self.crowdfunding;
+        blockchain.agreements = self.agreements;
+        blockchain.market = self.market;
+        blockchain.state_rent = self.state_rent;
+        for contract in blockchain.code_store.code_contracts() {
+            if self.code_store.code_hash(&contract.contract_id).is_none() {
+                blockchain.execution_environment.registry.remove(&contract.contract_id);
+            }
+        }
+        blockchain.code_store = self.code_store;
+        blockchain.restore_contracts();
+    }
+}
@@ -0,0 +1,94 @@
+// src/blockchain/spec.rs
+use serde::{Serialize, Deserialize};
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::network::protocol::DEFAULT_NETWORK_ID;
+use crate::network::ProtocolInfo;
+use super::receipt::TRANSFER_GAS;
+use super::{Block, Transfer, TransferOutput};
+
+/// The account the genesis allocations are paid from. No transaction may
+/// send from it, so the allocations are all it ever pays.
+pub const GENESIS_ACCOUNT: &str = "icn:genesis";
+
+/// Most transactions a block may hold, unless the spec says otherwise.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 500;
+/// Most gas the transactions of a block may need, unless the spec says
+/// otherwise.
+pub const MAX_BLOCK_GAS: u64 = MAX_BLOCK_TRANSACTIONS as u64 * TRANSFER_GAS;
+
+/// What sets a chain apart from the others running the same software.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainSpec {
+    /// Signed into every transaction and announced in the handshake, so that
+    /// neither transactions nor peers cross from one network to another.
+    pub network_id: String,
+    /// What the genesis block pays to whom: the money the chain starts
+    /// with. The genesis hash commits to it.
+    #[serde(default)]
+    pub allocations: Vec<TransferOutput>,
+    /// Most transactions a block may hold. The lanes reserve shares of it.
+    #[serde(default = "default_max_block_transactions")]
+    pub max_block_transactions: usize,
+    /// Most gas the transactions of a block may need, as
+    /// `receipt::gas_required` counts it. The lanes reserve shares of it.
+    #[serde(default = "default_max_block_gas")]
+    pub max_block_gas: u64,
+}
+
+fn default_max_block_transactions() -> usize {
+    MAX_BLOCK_TRANSACTIONS
+}
+
+fn default_max_block_gas() -> u64 {
+    MAX_BLOCK_GAS
+}
+
+impl ChainSpec {
+    pub fn new(network_id: &str) -> Result<Self> {
+        if network_id.trim().is_empty() {
+            return Err(Error::BlockchainError("A chain spec needs a network id".to_string()));
+        }
+        Ok(ChainSpec { network_id: network_id.to_string(), ..ChainSpec::default() })
+    }
+
+    /// Has the genesis block pay `amount` of `currency_type` to `to`.
+    pub fn with_allocation(mut self, to: &str, amount: f64, currency_type: CurrencyType) -> Self {
+        self.allocations.push(TransferOutput { to: to.to_string(), amount, currency_type });
+        self
+    }
+
+    /// The payouts of the genesis block.
+    pub fn genesis_payouts(&self) -> Vec<Transfer> {
+        self.allocations.iter()
+            .map(|allocation| Transfer { from: GENESIS_ACCOUNT.to_string(), to: allocation.to.clone(), amount: allocation.amount, currency_type: allocation.currency_type.clone() })
+            .collect()
+    }
+
+    /// The first block of the chain. Without allocations it is the same on
+    /// every network.
+    pub fn genesis(&self) -> Block {
+        let mut block = Block::genesis();
+        if !self.allocations.is_empty() {
+            block.receipts_root = Block::receipts_root(&[], &self.genesis_payouts());
+            block.hash = block.calculate_hash();
+        }
+        block
+    }
+
+    /// What nodes of this chain announce to peers in the handshake.
+    pub fn protocol_info(&self) -> ProtocolInfo {
+        ProtocolInfo::new(&self.network_id, &self.genesis().hash)
+    }
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec {
+            network_id: DEFAULT_NETWORK_ID.to_string(),
+            allocations: Vec::new(),
+            max_block_transactions: MAX_BLOCK_TRANSACTIONS,
+            max_block_gas: MAX_BLOCK_GAS,
+        }
+    }
+}
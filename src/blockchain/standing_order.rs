@@ -0,0 +1,151 @@
+// src/blockchain/standing_order.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use super::payout::event;
+use super::{receipt, Transaction};
+
+/// The account standing orders are set up and cancelled with.
+pub const STANDING_ORDER_ACCOUNT: &str = "icn:orders";
+/// Missed payments in a row after which an order is cancelled.
+pub const MAX_MISSED_PAYMENTS: u32 = 3;
+
+/// What a standing order transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum StandingOrderAction {
+    /// Pays `recipient` `amount` of the currency of the transaction every
+    /// `interval` blocks, until the first block timestamped after `ends_at`
+    /// if set.
+    Create { recipient: String, amount: f64, interval: u64, ends_at: Option<i64> },
+    /// Stops the order, as its payer.
+    Cancel { order_id: String },
+    /// One payment of the order, put in the block it is due in by the
+    /// proposer; `sequence` counts the payments due so far.
+    Execute { order_id: String, sequence: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StandingOrder {
+    /// Hash of the transaction that created the order.
+    pub id: String,
+    pub payer: String,
+    pub recipient: String,
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+    /// Blocks between payments.
+    pub interval: u64,
+    /// Unix time in seconds after which no payment is made.
+    pub ends_at: Option<i64>,
+    /// Index of the block the next payment is due in.
+    pub next_due: u64,
+    /// Payments due so far, made or missed.
+    pub sequence: u64,
+    /// Payments missed in a row for lack of funds.
+    pub missed: u32,
+}
+
+impl StandingOrder {
+    /// The transaction making the next payment.
+    pub fn payment(&self) -> Transaction {
+        Transaction {
+            standing_order: Some(StandingOrderAction::Execute { order_id: self.id.clone(), sequence: self.sequence }),
+            ..Transaction::new(self.payer.clone(), self.recipient.clone(), self.amount, self.currency_type.clone(), receipt::TRANSFER_GAS)
+        }
+    }
+}
+
+/// The standing orders in force. Their payments go through the chain as
+/// transactions of their own, so each has a receipt, with an event telling
+/// whether it was made.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StandingOrders {
+    orders: BTreeMap<String, StandingOrder>,
+}
+
+impl StandingOrders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&StandingOrder> {
+        self.orders.get(order_id)
+    }
+
+    /// The orders `address` pays or is paid by.
+    pub fn of(&self, address: &str) -> Vec<&StandingOrder> {
+        self.orders.values().filter(|order| order.payer == address || order.recipient == address).collect()
+    }
+
+    /// The payments due in the block at `index`, timestamped `timestamp`.
+    pub fn due(&self, index: u64, timestamp: i64) -> Vec<Transaction> {
+        self.orders.values()
+            .filter(|order| order.next_due <= index && order.ends_at.is_none_or(|ends_at| timestamp <= ends_at))
+            .map(StandingOrder::payment)
+            .collect()
+    }
+
+    /// Applies the standing order action of `transaction`, in the block at
+    /// `index`, timestamped `timestamp`. A payment is passed whether it went
+    /// through, or why not, and returns its event.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64, timestamp: i64, paid: Result<(), &str>) -> Result<Option<ContractEvent>, String> {
+        match &transaction.standing_order {
+            Some(StandingOrderAction::Create { recipient, amount, interval, ends_at }) => {
+                if !amount.is_finite() || *amount <= 0.0 || *interval == 0 {
+                    return Err("A standing order needs a positive amount and interval".to_string());
+                }
+                let order = StandingOrder {
+                    id: transaction.hash(),
+                    payer: transaction.from.clone(),
+                    recipient: recipient.clone(),
+                    amount: *amount,
+                    currency_type: transaction.currency_type.clone(),
+                    interval: *interval,
+                    ends_at: *ends_at,
+                    next_due: index + interval,
+                    sequence: 0,
+                    missed: 0,
+                };
+                info!("Standing order {} pays {} {} to {} every {} blocks", order.id, amount, order.currency_type, recipient, interval);
+                self.orders.insert(order.id.clone(), order);
+                Ok(None)
+            }
+            Some(StandingOrderAction::Cancel { order_id }) => {
+                match self.orders.get(order_id) {
+                    Some(order) if order.payer == transaction.from => {
+                        self.orders.remove(order_id);
+                        Ok(Some(event(order_id, "StandingOrderCancelled", format!("by {}", transaction.from))))
+                    }
+                    Some(_) => Err(format!("{} does not pay standing order {}", transaction.from, order_id)),
+                    None => Err(format!("No standing order {}", order_id)),
+                }
+            }
+            Some(StandingOrderAction::Execute { order_id, .. }) => {
+                let order = self.orders.get_mut(order_id).ok_or_else(|| format!("No standing order {}", order_id))?;
+                let expected = order.payment();
+                if *transaction != expected || order.next_due > index || order.ends_at.is_some_and(|ends_at| timestamp > ends_at) {
+                    return Err(format!("Not a payment due on standing order {}", order_id));
+                }
+                order.sequence += 1;
+                order.next_due = index + order.interval;
+                let event = match paid {
+                    Ok(()) => {
+                        order.missed = 0;
+                        event(order_id, "StandingOrderExecuted", format!("{} {} to {}", order.amount, order.currency_type, order.recipient))
+                    }
+                    Err(reason) => {
+                        order.missed += 1;
+                        event(order_id, "StandingOrderFailed", reason.to_string())
+                    }
+                };
+                if order.missed >= MAX_MISSED_PAYMENTS {
+                    info!("Standing order {} cancelled after {} missed payments", order_id, order.missed);
+                    self.orders.remove(order_id);
+                }
+                Ok(Some(event))
+            }
+            None => Ok(None),
+        }
+    }
+}
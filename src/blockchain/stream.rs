@@ -0,0 +1,147 @@
+// src/blockchain/stream.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use super::payout::payout;
+use super::{Transaction, Transfer};
+
+/// The account holding the deposits of open streams, and paying out of them.
+pub const STREAM_ACCOUNT: &str = "icn:streams";
+
+/// What a stream transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum StreamAction {
+    /// Escrows the amount of the transaction for `recipient`, who earns
+    /// `rate` of it with every block until it runs out or is cancelled.
+    Open { recipient: String, rate: f64 },
+    /// Pays the recipient what the stream has earned it so far.
+    Withdraw { stream_id: String },
+    /// Stops the stream, paying the recipient what it has earned and
+    /// refunding the rest of the deposit to the sender.
+    Cancel { stream_id: String },
+}
+
+/// A payment made block by block out of a deposit. Nothing moves until the
+/// recipient withdraws or either party cancels; what has accrued is worked
+/// out then, from the number of blocks since the stream was opened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stream {
+    /// Hash of the transaction that opened the stream.
+    pub id: String,
+    pub sender: String,
+    pub recipient: String,
+    pub currency_type: CurrencyType,
+    pub deposit: f64,
+    /// Accrued to the recipient with every block.
+    pub rate: f64,
+    /// Index of the block the stream was opened in.
+    pub opened_at: u64,
+    /// Paid to the recipient so far.
+    pub withdrawn: f64,
+}
+
+impl Stream {
+    /// What the recipient has earned as of the block at `index`.
+    pub fn accrued_at(&self, index: u64) -> f64 {
+        (self.rate * index.saturating_sub(self.opened_at) as f64).min(self.deposit)
+    }
+
+    /// What the recipient could withdraw in the block at `index`.
+    pub fn withdrawable_at(&self, index: u64) -> f64 {
+        self.accrued_at(index) - self.withdrawn
+    }
+}
+
+/// The open streams, settled lazily by the transactions of their parties.
+/// Settling pays out from `STREAM_ACCOUNT` in the block applying it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamRegistry {
+    streams: BTreeMap<String, Stream>,
+}
+
+impl StreamRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, stream_id: &str) -> Option<&Stream> {
+        self.streams.get(stream_id)
+    }
+
+    /// The open streams `address` pays or is paid by.
+    pub fn of(&self, address: &str) -> Vec<&Stream> {
+        self.streams.values().filter(|stream| stream.sender == address || stream.recipient == address).collect()
+    }
+
+    /// Applies the stream action of `transaction`, in the block at `index`,
+    /// returning the payouts it makes.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64) -> Result<Vec<Transfer>, String> {
+        match &transaction.stream {
+            Some(StreamAction::Open { recipient, rate }) => self.open(transaction, recipient, *rate, index).map(|()| Vec::new()),
+            Some(StreamAction::Withdraw { stream_id }) => self.withdraw(stream_id, &transaction.from, index),
+            Some(StreamAction::Cancel { stream_id }) => self.cancel(stream_id, &transaction.from, index),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn open(&mut self, transaction: &Transaction, recipient: &str, rate: f64, index: u64) -> Result<(), String> {
+        if !rate.is_finite() || rate <= 0.0 || !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+            return Err("A stream needs a positive deposit and rate".to_string());
+        }
+        if recipient == transaction.from {
+            return Err("A stream cannot pay its sender".to_string());
+        }
+        if transaction.to != STREAM_ACCOUNT {
+            return Err(format!("A stream is opened with its deposit paid to {}", STREAM_ACCOUNT));
+        }
+        if self.streams.contains_key(&transaction.hash()) {
+            return Err(format!("Stream {} is already open", transaction.hash()));
+        }
+        let stream = Stream {
+            id: transaction.hash(),
+            sender: transaction.from.clone(),
+            recipient: recipient.to_string(),
+            currency_type: transaction.currency_type.clone(),
+            deposit: transaction.amount,
+            rate,
+            opened_at: index,
+            withdrawn: 0.0,
+        };
+        info!("Stream {} pays {} {} per block from {} to {}", stream.id, rate, stream.currency_type, stream.sender, stream.recipient);
+        self.streams.insert(stream.id.clone(), stream);
+        Ok(())
+    }
+
+    fn withdraw(&mut self, stream_id: &str, by: &str, index: u64) -> Result<Vec<Transfer>, String> {
+        let stream = self.streams.get_mut(stream_id).ok_or_else(|| format!("No open stream {}", stream_id))?;
+        if stream.recipient != by {
+            return Err(format!("{} is not the recipient of stream {}", by, stream_id));
+        }
+        let amount = stream.withdrawable_at(index);
+        if amount <= 0.0 {
+            return Err(format!("Stream {} has nothing to withdraw", stream_id));
+        }
+        stream.withdrawn += amount;
+        let payout = payout(STREAM_ACCOUNT, &stream.recipient, amount, &stream.currency_type);
+        if stream.withdrawn >= stream.deposit {
+            self.streams.remove(stream_id);
+        }
+        Ok(vec![payout])
+    }
+
+    fn cancel(&mut self, stream_id: &str, by: &str, index: u64) -> Result<Vec<Transfer>, String> {
+        let stream = self.streams.get(stream_id).ok_or_else(|| format!("No open stream {}", stream_id))?;
+        if stream.sender != by && stream.recipient != by {
+            return Err(format!("{} is not a party to stream {}", by, stream_id));
+        }
+        let stream = self.streams.remove(stream_id).expect("the stream was just found");
+        let earned = stream.withdrawable_at(index);
+        let refund = stream.deposit - stream.accrued_at(index);
+        info!("Stream {} cancelled by {}, paying {} and refunding {}", stream_id, by, earned, refund);
+        Ok([(&stream.recipient, earned), (&stream.sender, refund)].into_iter()
+            .filter(|(_, amount)| *amount > 0.0)
+            .map(|(to, amount)| payout(STREAM_ACCOUNT, to, amount, &stream.currency_type))
+            .collect())
+    }
+}
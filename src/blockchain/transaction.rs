@@ -2,7 +2,38 @@
 
 use serde::{Deserialize, Serialize};
 use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use sha2::{Digest, Sha256};
+use crate::blockchain::agreement::{AgreementAction, AGREEMENT_ACCOUNT};
+use crate::blockchain::allowance::{AllowanceAction, ALLOWANCE_ACCOUNT};
+use crate::blockchain::crowdfund::{CrowdfundAction, CROWDFUND_ACCOUNT};
+use crate::blockchain::market::{MarketAction, Resource, MARKET_ACCOUNT};
+use crate::blockchain::dividend::{DividendAction, DIVIDEND_ACCOUNT};
+use crate::blockchain::enactment::{Enactment, GOVERNANCE_ACCOUNT};
+use crate::blockchain::rent::RENT_ACCOUNT;
+use crate::network::protocol::DEFAULT_NETWORK_ID;
+use crate::blockchain::organization::{OrganizationAction, Role, ORGANIZATION_ACCOUNT};
+use crate::blockchain::settlement::{SettlementAction, SETTLEMENT_ACCOUNT};
+use crate::blockchain::standing_order::{StandingOrderAction, STANDING_ORDER_ACCOUNT};
+use crate::blockchain::stream::{StreamAction, STREAM_ACCOUNT};
+use crate::blockchain::transaction_validator::{ValidationAction, VALIDATION_ACCOUNT};
+use crate::blockchain::upgrade::UPGRADE_ACCOUNT;
+use crate::blockchain::vesting::{VestingAction, VestingSchedule, VESTING_ACCOUNT};
+use crate::bridge::{BridgeAction, BridgeProof, BridgeTransfer, BRIDGE_ACCOUNT};
+use crate::consensus::nomination::NOMINATION_ACCOUNT;
+use crate::consensus::staking::{StakeAction, STAKE_ACCOUNT};
 use crate::currency::CurrencyType;
+use crate::governance::ProposalAction;
+use crate::identity::revocation::{RevocationAction, REVOCATION_ACCOUNT};
+use crate::smart_contract::ContractCreation;
+use crate::smart_contract::code::CREATION_ACCOUNT;
+
+/// The recipient named by batch transactions, whose transfers are in their
+/// outputs instead. Nothing is ever paid to it.
+pub const BATCH_ACCOUNT: &str = "icn:batch";
+/// Starts the names of the accounts the chain holds funds in. Only the
+/// chain pays out of them, as block payouts; no transaction is sent from
+/// one.
+pub const CHAIN_ACCOUNT_PREFIX: &str = "icn:";
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
@@ -11,9 +42,180 @@ pub struct Transaction {
     pub amount: f64,
     pub currency_type: CurrencyType,
     pub gas_limit: u64,
+    /// What the sender offers per unit of gas, in the transaction's
+    /// currency. Blocks are filled with the best offers first.
+    #[serde(default)]
+    pub gas_price: f64,
     pub smart_contract_id: Option<String>,
     pub signature: Option<Vec<u8>>,
     pub public_key: Option<Vec<u8>>,
+    /// Set on transactions that nominate a validator, moving the amount into
+    /// `NOMINATION_ACCOUNT`, or withdraw a nomination, which the block pays
+    /// back out of it.
+    #[serde(default)]
+    pub nomination: Option<NominationAction>,
+    /// Set on transactions by which a validator signals readiness for the
+    /// named upgrade.
+    #[serde(default)]
+    pub upgrade_signal: Option<String>,
+    /// The last block the transaction may be included in, if its sender
+    /// wants it dropped once its intent is stale.
+    #[serde(default)]
+    pub valid_until: Option<ValidUntil>,
+    /// Set on batch transactions: the transfers they make, all or none.
+    #[serde(default)]
+    pub outputs: Vec<TransferOutput>,
+    /// Set on swaps: what the recipient pays the sender in return, going
+    /// through only together with the sender's payment.
+    #[serde(default)]
+    pub swap: Option<SwapLeg>,
+    /// Set on transactions that open, withdraw from or cancel a payment
+    /// stream; see `blockchain::stream`.
+    #[serde(default)]
+    pub stream: Option<StreamAction>,
+    /// Set on transactions that set up or cancel a standing order, and on
+    /// the payments it makes; see `blockchain::standing_order`.
+    #[serde(default)]
+    pub standing_order: Option<StandingOrderAction>,
+    /// Set on transactions that grant or revoke an allowance, and on the
+    /// transfers made out of one; see `blockchain::allowance`.
+    #[serde(default)]
+    pub allowance: Option<AllowanceAction>,
+    /// Signatures over the same bytes by others than the signer, for
+    /// accounts whose validation logic needs several.
+    #[serde(default)]
+    pub cosignatures: Vec<Cosignature>,
+    /// Set on transactions that designate or clear the validation logic of
+    /// their sender; see `blockchain::transaction_validator`.
+    #[serde(default)]
+    pub validation: Option<ValidationAction>,
+    /// Set on transactions that found or manage an organization, and on the
+    /// transfers out of its treasury; see `blockchain::organization`.
+    #[serde(default)]
+    pub organization: Option<OrganizationAction>,
+    /// Set on transactions recording an obligation between cooperatives, and
+    /// on the net payments settling them; see `blockchain::settlement`.
+    #[serde(default)]
+    pub settlement: Option<SettlementAction>,
+    /// Set on transactions that open, record patronage for, fund or claim
+    /// from a distribution of surplus; see `blockchain::dividend`.
+    #[serde(default)]
+    pub dividend: Option<DividendAction>,
+    /// Set on payments of rent in advance for the storage of the contract
+    /// named; see `blockchain::rent`.
+    #[serde(default)]
+    pub rent_for: Option<String>,
+    /// Set on transactions that deploy a contract from code, whose address
+    /// is given in the receipt.
+    #[serde(default)]
+    pub contract_creation: Option<ContractCreation>,
+    /// Set on transactions that grant, claim from or revoke a vesting grant;
+    /// see `blockchain::vesting`.
+    #[serde(default)]
+    pub vesting: Option<VestingAction>,
+    /// Set on transactions that launch or pledge to a crowdfunding campaign;
+    /// see `blockchain::crowdfund`.
+    #[serde(default)]
+    pub crowdfund: Option<CrowdfundAction>,
+    /// Set on transactions that open, release, dispute or rule on a service
+    /// agreement; see `blockchain::agreement`.
+    #[serde(default)]
+    pub agreement: Option<AgreementAction>,
+    /// Set on transactions that place, fulfil or cancel resource orders;
+    /// see `blockchain::market`.
+    #[serde(default)]
+    pub market: Option<MarketAction>,
+    /// Set on transactions that put a passed proposal into effect; see
+    /// `blockchain::enactment`.
+    #[serde(default)]
+    pub enactment: Option<Enactment>,
+    /// Set on transactions that lock funds for external chains or release
+    /// transfers from them; see `bridge::BridgeLedger`.
+    #[serde(default)]
+    pub bridge: Option<BridgeAction>,
+    /// Set on transactions that manage credential status lists or revoke a
+    /// DID; see `identity::RevocationRegistry`.
+    #[serde(default)]
+    pub revocation: Option<RevocationAction>,
+    /// Set on transactions that bond, unbond or withdraw validator stake;
+    /// see `consensus::StakeLedger`.
+    #[serde(default)]
+    pub stake: Option<StakeAction>,
+    /// The network the transaction is meant for, signed along with the rest
+    /// so that it cannot be replayed on another; see `ChainSpec`.
+    #[serde(default = "default_network_id")]
+    pub network_id: String,
+}
+
+fn default_network_id() -> String {
+    DEFAULT_NETWORK_ID.to_string()
+}
+
+/// Appends `value` to signed bytes behind `tag` and its length, so that it
+/// cannot be read as another field or run into the next one.
+fn extend_tagged(bytes: &mut Vec<u8>, tag: &[u8], value: &[u8]) {
+    bytes.extend_from_slice(tag);
+    bytes.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+/// A signature added to a transaction by `Transaction::cosign`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Cosignature {
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// The recipient's side of a swap, signed by the recipient.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SwapLeg {
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+    pub signature: Option<Vec<u8>>,
+    pub public_key: Option<Vec<u8>>,
+}
+
+/// A payment a transaction makes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Transfer {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+}
+
+/// One transfer of a batch transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransferOutput {
+    pub to: String,
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+}
+
+/// Bounds the blocks a transaction may be included in.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ValidUntil {
+    /// Index of the last block.
+    Height(u64),
+    /// Unix time in seconds; blocks timestamped later may not include it.
+    Timestamp(i64),
+}
+
+impl ValidUntil {
+    /// Whether the block at `height`, timestamped `timestamp`, is too late.
+    pub fn is_expired_at(&self, height: u64, timestamp: i64) -> bool {
+        match *self {
+            ValidUntil::Height(last) => height > last,
+            ValidUntil::Timestamp(last) => timestamp > last,
+        }
+    }
+}
+
+/// What a nomination transaction does, for the validator it names.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum NominationAction {
+    Nominate { validator: String },
+    Withdraw { validator: String, amount: f64 },
 }
 
 impl Transaction {
@@ -24,9 +226,589 @@ impl Transaction {
             amount,
             currency_type,
             gas_limit,
+            gas_price: 0.0,
             smart_contract_id: None,
             signature: None,
             public_key: None,
+            nomination: None,
+            upgrade_signal: None,
+            valid_until: None,
+            outputs: Vec::new(),
+            swap: None,
+            stream: None,
+            standing_order: None,
+            allowance: None,
+            cosignatures: Vec::new(),
+            validation: None,
+            organization: None,
+            settlement: None,
+            dividend: None,
+            rent_for: None,
+            contract_creation: None,
+            vesting: None,
+            crowdfund: None,
+            agreement: None,
+            market: None,
+            enactment: None,
+            bridge: None,
+            revocation: None,
+            stake: None,
+            network_id: default_network_id(),
+        }
+    }
+
+    /// Pays each of `outputs` from `from` in a single transaction, going
+    /// through only if every transfer does. Gas is paid in the currency of
+    /// the first output.
+    pub fn batch(from: String, outputs: Vec<TransferOutput>, gas_limit: u64) -> Self {
+        let currency_type = outputs.first().map_or(CurrencyType::BasicNeeds, |output| output.currency_type.clone());
+        Transaction {
+            outputs,
+            ..Self::new(from, BATCH_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Exchanges `amount` of the currency of `from` for `counter_amount`
+    /// of the currency of `to`: both payments go through or neither does.
+    /// Needs the signatures of both, by `sign` and `countersign`.
+    pub fn swap(from: String, to: String, amount: f64, currency_type: CurrencyType, counter_amount: f64, counter_currency: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            swap: Some(SwapLeg { amount: counter_amount, currency_type: counter_currency, signature: None, public_key: None }),
+            ..Self::new(from, to, amount, currency_type, gas_limit)
+        }
+    }
+
+    pub fn is_batch(&self) -> bool {
+        !self.outputs.is_empty()
+    }
+
+    /// The transfers the transaction makes: its outputs if it is a batch,
+    /// both payments if it is a swap, otherwise its amount to its recipient.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        let transfer = |from: &String, to: &String, amount, currency_type: &CurrencyType| Transfer { from: from.clone(), to: to.clone(), amount, currency_type: currency_type.clone() };
+        if self.is_batch() {
+            return self.outputs.iter().map(|output| transfer(&self.from, &output.to, output.amount, &output.currency_type)).collect();
+        }
+        let mut transfers = vec![transfer(&self.from, &self.to, self.amount, &self.currency_type)];
+        if let Some(swap) = &self.swap {
+            transfers.push(transfer(&self.to, &self.from, swap.amount, &swap.currency_type));
+        }
+        transfers
+    }
+
+    /// The swap as seen by its recipient: the recipient's payment as the
+    /// transaction, signed by the recipient. It signs the same bytes, so
+    /// either side verifies. None if this is not a swap.
+    pub fn counter_leg(&self) -> Option<Transaction> {
+        let swap = self.swap.as_ref()?;
+        Some(Transaction {
+            from: self.to.clone(),
+            to: self.from.clone(),
+            amount: swap.amount,
+            currency_type: swap.currency_type.clone(),
+            signature: swap.signature.clone(),
+            public_key: swap.public_key.clone(),
+            swap: Some(SwapLeg {
+                amount: self.amount,
+                currency_type: self.currency_type.clone(),
+                signature: self.signature.clone(),
+                public_key: self.public_key.clone(),
+            }),
+            ..self.clone()
+        })
+    }
+
+    /// The transaction as the payments it makes from each side: itself, and
+    /// its counter leg if it is a swap.
+    pub fn legs(&self) -> Vec<Transaction> {
+        std::iter::once(self.clone()).chain(self.counter_leg()).collect()
+    }
+
+    /// Fails for a swap whose payments are not both positive, that is not
+    /// between two parties, or that lacks the signature of either, made with
+    /// the key of its address.
+    pub fn check_swap(&self) -> Result<(), String> {
+        let swap = match &self.swap {
+            Some(swap) => swap,
+            None => return Ok(()),
+        };
+        if [self.amount, swap.amount].iter().any(|amount| !amount.is_finite() || *amount <= 0.0) {
+            return Err("Both payments of a swap must be positive".to_string());
+        }
+        if self.from == self.to || self.is_batch() {
+            return Err("A swap is between two parties".to_string());
+        }
+        let message = self.to_bytes();
+        for (party, public_key, signature) in [(&self.from, &self.public_key, &self.signature), (&self.to, &swap.public_key, &swap.signature)] {
+            let (public_key, signature) = match (public_key, signature) {
+                (Some(public_key), Some(signature)) => (public_key, signature),
+                _ => return Err(format!("The swap is not signed by {}", party)),
+            };
+            let public_key = PublicKey::from_bytes(public_key).map_err(|e| e.to_string())?;
+            let signature = Signature::from_bytes(signature).map_err(|e| e.to_string())?;
+            if crate::wallet::address_of(&public_key) != *party || public_key.verify(&message, &signature).is_err() {
+                return Err(format!("The swap is not signed by {}", party));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails for a transaction sent from an account of the chain.
+    pub fn check_sender(&self) -> Result<(), String> {
+        if self.from.starts_with(CHAIN_ACCOUNT_PREFIX) {
+            return Err(format!("No transaction may be sent from {}", self.from));
+        }
+        Ok(())
+    }
+
+    /// Fails for a transfer out of an allowance that is not a plain payment,
+    /// or that lacks the signature of its spender, made with the key of its
+    /// address.
+    pub fn check_delegation(&self) -> Result<(), String> {
+        let spender = match &self.allowance {
+            Some(AllowanceAction::Spend { spender }) => spender,
+            _ => return Ok(()),
+        };
+        if !self.amount.is_finite() || self.amount <= 0.0 || self.is_batch() || self.swap.is_some() || self.stream.is_some() || self.standing_order.is_some() || self.nomination.is_some() {
+            return Err("An allowance only pays a positive amount to a single recipient".to_string());
+        }
+        self.check_signed_by(spender)
+    }
+
+    /// Fails for a transfer out of the treasury of an organization that
+    /// lacks the signature of the member making it.
+    pub fn check_treasury_spend(&self) -> Result<(), String> {
+        match &self.organization {
+            Some(OrganizationAction::Spend { by }) => self.check_signed_by(by),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_signed_by(&self, address: &str) -> Result<(), String> {
+        let (public_key, signature) = match (&self.public_key, &self.signature) {
+            (Some(public_key), Some(signature)) => (public_key, signature),
+            _ => return Err(format!("The transfer is not signed by {}", address)),
+        };
+        let public_key = PublicKey::from_bytes(public_key).map_err(|e| e.to_string())?;
+        let signature = Signature::from_bytes(signature).map_err(|e| e.to_string())?;
+        if crate::wallet::address_of(&public_key) != address || public_key.verify(&self.to_bytes(), &signature).is_err() {
+            return Err(format!("The transfer is not signed by {}", address));
+        }
+        Ok(())
+    }
+
+    /// Fails for a batch transaction with an output that pays nothing or
+    /// cannot be paid.
+    pub fn check_outputs(&self) -> Result<(), String> {
+        match self.outputs.iter().position(|output| !output.amount.is_finite() || output.amount <= 0.0 || output.to == BATCH_ACCOUNT) {
+            Some(index) => Err(format!("Output {} of the batch is invalid", index)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails for a transaction paying out of an account more than `balance`
+    /// gives it.
+    pub fn check_affordable(&self, balance: impl Fn(&str, &CurrencyType) -> f64) -> Result<(), String> {
+        let mut nets: Vec<(&str, &CurrencyType, f64)> = Vec::new();
+        let transfers = self.transfers();
+        for transfer in &transfers {
+            for (address, delta) in [(transfer.from.as_str(), -transfer.amount), (transfer.to.as_str(), transfer.amount)] {
+                match nets.iter_mut().find(|(spender, currency_type, _)| *spender == address && **currency_type == transfer.currency_type) {
+                    Some((_, _, net)) => *net += delta,
+                    None => nets.push((address, &transfer.currency_type, delta)),
+                }
+            }
+        }
+        match nets.into_iter().find(|(address, currency_type, net)| *net < 0.0 && balance(address, currency_type) + net < 0.0) {
+            Some((address, currency_type, net)) => Err(format!("{} cannot afford {} {}", address, -net, currency_type)),
+            None => Ok(()),
+        }
+    }
+
+    /// Offers `gas_price` per unit of gas; see `blockchain::fees::estimate` for
+    /// what to offer.
+    pub fn with_gas_price(mut self, gas_price: f64) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    /// Meant for the network `network_id` rather than the main network.
+    pub fn on_network(mut self, network_id: &str) -> Self {
+        self.network_id = network_id.to_string();
+        self
+    }
+
+    /// Limits the transaction to blocks up to `valid_until`.
+    pub fn with_valid_until(mut self, valid_until: ValidUntil) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    /// Whether the transaction may no longer go in the block at `height`,
+    /// timestamped `timestamp`.
+    pub fn is_expired_at(&self, height: u64, timestamp: i64) -> bool {
+        self.valid_until.is_some_and(|valid_until| valid_until.is_expired_at(height, timestamp))
+    }
+
+    /// Signals that `validator` is ready for the upgrade named `upgrade`.
+    pub fn signal_upgrade(validator: String, upgrade: String, gas_limit: u64) -> Self {
+        Transaction {
+            upgrade_signal: Some(upgrade),
+            ..Self::new(validator, UPGRADE_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Escrows `deposit` for a stream paying `recipient` `rate` per block.
+    pub fn open_stream(sender: String, recipient: String, deposit: f64, currency_type: CurrencyType, rate: f64, gas_limit: u64) -> Self {
+        Transaction {
+            stream: Some(StreamAction::Open { recipient, rate }),
+            ..Self::new(sender, STREAM_ACCOUNT.to_string(), deposit, currency_type, gas_limit)
+        }
+    }
+
+    /// Claims what the stream `stream_id` has earned `recipient`.
+    pub fn withdraw_stream(recipient: String, stream_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            stream: Some(StreamAction::Withdraw { stream_id }),
+            ..Self::new(recipient, STREAM_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Stops the stream `stream_id`, as its sender or its recipient.
+    pub fn cancel_stream(party: String, stream_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            stream: Some(StreamAction::Cancel { stream_id }),
+            ..Self::new(party, STREAM_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Sets up a standing order paying `recipient` `amount` every `interval`
+    /// blocks, until `ends_at` if given.
+    pub fn create_standing_order(payer: String, recipient: String, amount: f64, currency_type: CurrencyType, interval: u64, ends_at: Option<i64>, gas_limit: u64) -> Self {
+        Transaction {
+            standing_order: Some(StandingOrderAction::Create { recipient, amount, interval, ends_at }),
+            ..Self::new(payer, STANDING_ORDER_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Cancels the standing order `order_id` of `payer`.
+    pub fn cancel_standing_order(payer: String, order_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            standing_order: Some(StandingOrderAction::Cancel { order_id }),
+            ..Self::new(payer, STANDING_ORDER_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Lets `spender` pay up to `limit` on behalf of `owner`, per `period`
+    /// seconds if given.
+    pub fn grant_allowance(owner: String, spender: String, limit: f64, currency_type: CurrencyType, period: Option<i64>, gas_limit: u64) -> Self {
+        Transaction {
+            allowance: Some(AllowanceAction::Grant { spender, limit, period }),
+            ..Self::new(owner, ALLOWANCE_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Withdraws the allowance in `currency_type` `owner` granted `spender`.
+    pub fn revoke_allowance(owner: String, spender: String, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            allowance: Some(AllowanceAction::Revoke { spender }),
+            ..Self::new(owner, ALLOWANCE_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Pays `amount` from `owner` to `to` out of the allowance of `spender`,
+    /// who signs it.
+    pub fn spend_allowance(spender: String, owner: String, to: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            allowance: Some(AllowanceAction::Spend { spender }),
+            ..Self::new(owner, to, amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Has the deployed contract `contract_id` validate the transactions of
+    /// `account` in place of the signature check.
+    pub fn designate_validation(account: String, contract_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            validation: Some(ValidationAction::Designate { contract_id }),
+            ..Self::new(account, VALIDATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Goes back to the signature check for the transactions of `account`.
+    pub fn clear_validation(account: String, gas_limit: u64) -> Self {
+        Transaction {
+            validation: Some(ValidationAction::Clear),
+            ..Self::new(account, VALIDATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Founds the organization `name`, with `founder` as its treasurer.
+    pub fn create_organization(founder: String, name: String, gas_limit: u64) -> Self {
+        Transaction {
+            organization: Some(OrganizationAction::Create { name }),
+            ..Self::new(founder, ORGANIZATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Admits `member` to `organization` as `role`, on the authority of `by`.
+    pub fn invite_member(by: String, organization: String, member: String, role: Role, gas_limit: u64) -> Self {
+        Transaction {
+            organization: Some(OrganizationAction::Invite { organization, member, role }),
+            ..Self::new(by, ORGANIZATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Removes `member` from `organization`, on the authority of `by`.
+    pub fn remove_member(by: String, organization: String, member: String, gas_limit: u64) -> Self {
+        Transaction {
+            organization: Some(OrganizationAction::Remove { organization, member }),
+            ..Self::new(by, ORGANIZATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Pays `amount` to `to` out of the treasury of `organization`, made by
+    /// the member `by`, who signs it.
+    pub fn spend_treasury(by: String, organization: String, to: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            organization: Some(OrganizationAction::Spend { by }),
+            ..Self::new(organization, to, amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Records that `debtor` owes `creditor` `amount` of `currency_type`,
+    /// settled net of the other obligations at the end of the epoch.
+    pub fn record_obligation(debtor: String, creditor: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            settlement: Some(SettlementAction::Obligation { creditor, amount }),
+            ..Self::new(debtor, SETTLEMENT_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Opens a distribution of the surplus of `cooperative` in
+    /// `currency_type`, by patronage recorded up to block `period_end`, with
+    /// `claim_blocks` blocks to claim shares in once funded.
+    pub fn open_dividend(cooperative: String, currency_type: CurrencyType, period_end: u64, claim_blocks: u64, gas_limit: u64) -> Self {
+        Transaction {
+            dividend: Some(DividendAction::Open { period_end, claim_blocks }),
+            ..Self::new(cooperative, DIVIDEND_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Records `amount` of patronage by `member` in a distribution of
+    /// `cooperative`.
+    pub fn record_patronage(cooperative: String, distribution_id: String, member: String, amount: f64, gas_limit: u64) -> Self {
+        Transaction {
+            dividend: Some(DividendAction::Patronage { distribution_id, member, amount }),
+            ..Self::new(cooperative, DIVIDEND_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Escrows `surplus` for members to claim from a distribution of
+    /// `cooperative`.
+    pub fn fund_dividend(cooperative: String, distribution_id: String, surplus: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            dividend: Some(DividendAction::Fund { distribution_id }),
+            ..Self::new(cooperative, DIVIDEND_ACCOUNT.to_string(), surplus, currency_type, gas_limit)
+        }
+    }
+
+    /// Claims the share of `member` in a distribution.
+    pub fn claim_dividend(member: String, distribution_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            dividend: Some(DividendAction::Claim { distribution_id }),
+            ..Self::new(member, DIVIDEND_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Pays `amount` of rent in advance for the storage of `contract_id`.
+    pub fn pay_rent(payer: String, contract_id: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            rent_for: Some(contract_id),
+            ..Self::new(payer, RENT_ACCOUNT.to_string(), amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Locks `amount` for `beneficiary`, vesting on `schedule`.
+    pub fn grant_vesting(grantor: String, beneficiary: String, amount: f64, currency_type: CurrencyType, schedule: VestingSchedule, revocable: bool, gas_limit: u64) -> Self {
+        Transaction {
+            vesting: Some(VestingAction::Grant { beneficiary, schedule, revocable }),
+            ..Self::new(grantor, VESTING_ACCOUNT.to_string(), amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Claims what has vested of `vesting_id` for its beneficiary.
+    pub fn claim_vested(beneficiary: String, vesting_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            vesting: Some(VestingAction::Claim { vesting_id }),
+            ..Self::new(beneficiary, VESTING_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Revokes `vesting_id`, once governance approved, for its grantor.
+    pub fn revoke_vesting(grantor: String, vesting_id: String, gas_limit: u64) -> Self {
+        Transaction {
+            vesting: Some(VestingAction::Revoke { vesting_id }),
+            ..Self::new(grantor, VESTING_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Launches a campaign for `project`, raising `goal` of `currency_type`
+    /// by the block at `deadline`.
+    pub fn launch_campaign(project: String, goal: f64, currency_type: CurrencyType, deadline: u64, gas_limit: u64) -> Self {
+        Transaction {
+            crowdfund: Some(CrowdfundAction::Launch { goal, deadline }),
+            ..Self::new(project, CROWDFUND_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Pledges `amount` to `campaign_id`, paid to the project only if the
+    /// campaign reaches its goal.
+    pub fn pledge(member: String, campaign_id: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            crowdfund: Some(CrowdfundAction::Pledge { campaign_id }),
+            ..Self::new(member, CROWDFUND_ACCOUNT.to_string(), amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Offers `quantity` units of `resource` at `unit_price` each.
+    pub fn offer_resource(provider: String, resource: Resource, quantity: f64, unit_price: f64, gas_limit: u64) -> Self {
+        Transaction {
+            market: Some(MarketAction::Offer { resource, quantity, unit_price }),
+            ..Self::new(provider, MARKET_ACCOUNT.to_string(), 0.0, resource.currency(), gas_limit)
+        }
+    }
+
+    /// Asks for `quantity` units of `resource`, putting up what they cost at
+    /// `max_unit_price`.
+    pub fn request_resource(consumer: String, resource: Resource, quantity: f64, max_unit_price: f64, gas_limit: u64) -> Self {
+        Transaction {
+            market: Some(MarketAction::Request { resource, quantity, max_unit_price }),
+            ..Self::new(consumer, MARKET_ACCOUNT.to_string(), quantity * max_unit_price, resource.currency(), gas_limit)
+        }
+    }
+
+    pub fn fulfil_allocation(provider: String, allocation_id: String, proof: String, gas_limit: u64) -> Self {
+        Self::market_action(provider, MarketAction::Fulfil { allocation_id, proof }, gas_limit)
+    }
+
+    /// Accepts the proof of delivery of `allocation_id`, paying its provider.
+    pub fn confirm_allocation(consumer: String, allocation_id: String, gas_limit: u64) -> Self {
+        Self::market_action(consumer, MarketAction::Confirm { allocation_id }, gas_limit)
+    }
+
+    pub fn cancel_order(owner: String, order_id: String, gas_limit: u64) -> Self {
+        Self::market_action(owner, MarketAction::Cancel { order_id }, gas_limit)
+    }
+
+    fn market_action(from: String, action: MarketAction, gas_limit: u64) -> Self {
+        Transaction {
+            market: Some(action),
+            ..Self::new(from, MARKET_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Escrows `payment` for `provider` to deliver the service of `terms`.
+    pub fn open_agreement(client: String, provider: String, terms: String, payment: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            agreement: Some(AgreementAction::Open { provider, terms }),
+            ..Self::new(client, AGREEMENT_ACCOUNT.to_string(), payment, currency_type, gas_limit)
+        }
+    }
+
+    /// Pays the provider of `agreement_id` its escrowed payment.
+    pub fn release_payment(client: String, agreement_id: String, gas_limit: u64) -> Self {
+        Self::agreement_action(client, AgreementAction::Release { agreement_id }, gas_limit)
+    }
+
+    /// Disputes `agreement_id`, holding its payment until the dispute is
+    /// resolved.
+    pub fn raise_dispute(party: String, agreement_id: String, reason: String, gas_limit: u64) -> Self {
+        Self::agreement_action(party, AgreementAction::Dispute { agreement_id, reason }, gas_limit)
+    }
+
+    pub fn submit_evidence(party: String, agreement_id: String, evidence: String, gas_limit: u64) -> Self {
+        Self::agreement_action(party, AgreementAction::Evidence { agreement_id, evidence }, gas_limit)
+    }
+
+    /// The vote of `juror` on the dispute over `agreement_id`.
+    pub fn rule_on_dispute(juror: String, agreement_id: String, for_provider: bool, gas_limit: u64) -> Self {
+        Self::agreement_action(juror, AgreementAction::Rule { agreement_id, for_provider }, gas_limit)
+    }
+
+    fn agreement_action(from: String, action: AgreementAction, gas_limit: u64) -> Self {
+        Transaction {
+            agreement: Some(action),
+            ..Self::new(from, AGREEMENT_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Deploys a contract running `creation.code`, whose constructor runs with
+    /// `creation.args` when the block is applied.
+    pub fn create_contract(deployer: String, creation: ContractCreation, gas_limit: u64) -> Self {
+        Transaction {
+            contract_creation: Some(creation),
+            ..Self::new(deployer, CREATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Puts the action of the passed proposal `proposal_id` into effect, once
+    /// cosigned by the validators.
+    pub fn enact(enactor: String, proposal_id: String, action: ProposalAction, gas_limit: u64) -> Self {
+        Transaction {
+            enactment: Some(Enactment { proposal_id, action }),
+            ..Self::new(enactor, GOVERNANCE_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Locks `amount` of the funds of `sender` for `recipient` on the
+    /// external chain `chain_id`.
+    pub fn lock_bridged(sender: String, chain_id: String, recipient: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            bridge: Some(BridgeAction::Lock { chain_id, recipient }),
+            ..Self::new(sender, BRIDGE_ACCOUNT.to_string(), amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Has the chain pay out `transfer`, seen on an external chain and
+    /// signed by the validators in `proof`, to its recipient.
+    pub fn release_bridged(relayer: String, transfer: BridgeTransfer, proof: BridgeProof, gas_limit: u64) -> Self {
+        let currency_type = transfer.currency.clone();
+        Transaction {
+            bridge: Some(BridgeAction::Release { transfer, proof }),
+            ..Self::new(relayer, BRIDGE_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
+        }
+    }
+
+    /// Takes `action` on the revocation registry as `sender`, who must sign
+    /// it.
+    pub fn revoke(sender: String, action: RevocationAction, gas_limit: u64) -> Self {
+        Transaction {
+            revocation: Some(action),
+            ..Self::new(sender, REVOCATION_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Takes `action` on the stake of `validator`, who must sign it.
+    pub fn stake(validator: String, action: StakeAction, gas_limit: u64) -> Self {
+        Transaction {
+            stake: Some(action),
+            ..Self::new(validator, STAKE_ACCOUNT.to_string(), 0.0, CurrencyType::BasicNeeds, gas_limit)
+        }
+    }
+
+    /// Puts `amount` of the currency of `nominator` behind `validator`.
+    pub fn nominate(nominator: String, validator: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            nomination: Some(NominationAction::Nominate { validator }),
+            ..Self::new(nominator, NOMINATION_ACCOUNT.to_string(), amount, currency_type, gas_limit)
+        }
+    }
+
+    /// Takes `amount` of the nomination of `validator` by `nominator` back.
+    pub fn withdraw_nomination(nominator: String, validator: String, amount: f64, currency_type: CurrencyType, gas_limit: u64) -> Self {
+        Transaction {
+            nomination: Some(NominationAction::Withdraw { validator, amount }),
+            ..Self::new(nominator, NOMINATION_ACCOUNT.to_string(), 0.0, currency_type, gas_limit)
         }
     }
 
@@ -38,6 +820,41 @@ impl Transaction {
         Ok(())
     }
 
+    /// Signs a swap as its recipient.
+    pub fn countersign(&mut self, keypair: &Keypair) -> Result<(), String> {
+        let message = self.to_bytes();
+        let swap = self.swap.as_mut().ok_or("Not a swap")?;
+        swap.signature = Some(keypair.sign(&message).to_bytes().to_vec());
+        swap.public_key = Some(keypair.public.to_bytes().to_vec());
+        Ok(())
+    }
+
+    /// Adds the signature of another party to the transaction.
+    pub fn cosign(&mut self, keypair: &Keypair) -> Result<(), String> {
+        let signature = keypair.sign(&self.to_bytes());
+        self.cosignatures.push(Cosignature { public_key: keypair.public.to_bytes().to_vec(), signature: signature.to_bytes().to_vec() });
+        Ok(())
+    }
+
+    /// The addresses whose keys validly signed or cosigned the transaction.
+    pub fn signers(&self) -> Vec<String> {
+        let message = self.to_bytes();
+        let signatures = self.public_key.iter().zip(&self.signature)
+            .chain(self.cosignatures.iter().map(|cosignature| (&cosignature.public_key, &cosignature.signature)));
+        let mut signers: Vec<String> = Vec::new();
+        for (public_key, signature) in signatures {
+            let (public_key, signature) = match (PublicKey::from_bytes(public_key), Signature::from_bytes(signature)) {
+                (Ok(public_key), Ok(signature)) => (public_key, signature),
+                _ => continue,
+            };
+            let signer = crate::wallet::address_of(&public_key);
+            if public_key.verify(&message, &signature).is_ok() && !signers.contains(&signer) {
+                signers.push(signer);
+            }
+        }
+        signers
+    }
+
     pub fn verify(&self) -> Result<bool, String> {
         let public_key_bytes = self.public_key.as_ref().ok_or("No public key present")?;
         let signature_bytes = self.signature.as_ref().ok_or("No signature present")?;
@@ -49,16 +866,113 @@ impl Transaction {
         Ok(public_key.verify(&message, &signature).is_ok())
     }
 
+    /// Hex SHA-256 of the signed fields, naming the transaction on the network.
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(&self.to_bytes()))
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        bytes.extend_from_slice(self.from.as_bytes());
-        bytes.extend_from_slice(self.to.as_bytes());
-        bytes.extend_from_slice(&self.amount.to_le_bytes());
-        bytes.extend_from_slice(&self.gas_limit.to_le_bytes());
-        bytes.extend_from_slice(&serde_json::to_vec(&self.currency_type).unwrap());
+        match &self.swap {
+            // both payments, in an order that does not depend on the side
+            // the swap is seen from
+            Some(swap) => {
+                let payment = |from: &str, to: &str, amount: f64, currency_type: &CurrencyType| {
+                    [from.as_bytes(), to.as_bytes(), &amount.to_le_bytes(), &serde_json::to_vec(currency_type).unwrap()].concat()
+                };
+                let mut payments = [
+                    payment(&self.from, &self.to, self.amount, &self.currency_type),
+                    payment(&self.to, &self.from, swap.amount, &swap.currency_type),
+                ];
+                payments.sort();
+                bytes.extend_from_slice(&payments.concat());
+                bytes.extend_from_slice(&self.gas_limit.to_le_bytes());
+            }
+            None => {
+                bytes.extend_from_slice(self.from.as_bytes());
+                bytes.extend_from_slice(self.to.as_bytes());
+                bytes.extend_from_slice(&self.amount.to_le_bytes());
+                bytes.extend_from_slice(&self.gas_limit.to_le_bytes());
+                bytes.extend_from_slice(&serde_json::to_vec(&self.currency_type).unwrap());
+            }
+        }
         if let Some(contract_id) = &self.smart_contract_id {
             bytes.extend_from_slice(contract_id.as_bytes());
         }
+        if let Some(nomination) = &self.nomination {
+            bytes.extend_from_slice(&serde_json::to_vec(nomination).unwrap());
+        }
+        if let Some(upgrade) = &self.upgrade_signal {
+            extend_tagged(&mut bytes, b"upgrade_signal:", upgrade.as_bytes());
+        }
+        // transactions offering nothing keep the hashes they had before
+        // gas prices
+        if self.gas_price != 0.0 {
+            bytes.extend_from_slice(&self.gas_price.to_le_bytes());
+        }
+        if let Some(valid_until) = &self.valid_until {
+            bytes.extend_from_slice(&serde_json::to_vec(valid_until).unwrap());
+        }
+        if self.is_batch() {
+            bytes.extend_from_slice(&serde_json::to_vec(&self.outputs).unwrap());
+        }
+        if let Some(stream) = &self.stream {
+            bytes.extend_from_slice(&serde_json::to_vec(stream).unwrap());
+        }
+        if let Some(standing_order) = &self.standing_order {
+            bytes.extend_from_slice(&serde_json::to_vec(standing_order).unwrap());
+        }
+        if let Some(allowance) = &self.allowance {
+            bytes.extend_from_slice(&serde_json::to_vec(allowance).unwrap());
+        }
+        if let Some(validation) = &self.validation {
+            bytes.extend_from_slice(&serde_json::to_vec(validation).unwrap());
+        }
+        if let Some(organization) = &self.organization {
+            bytes.extend_from_slice(&serde_json::to_vec(organization).unwrap());
+        }
+        if let Some(settlement) = &self.settlement {
+            bytes.extend_from_slice(&serde_json::to_vec(settlement).unwrap());
+        }
+        if let Some(dividend) = &self.dividend {
+            bytes.extend_from_slice(&serde_json::to_vec(dividend).unwrap());
+        }
+        if let Some(contract_id) = &self.rent_for {
+            extend_tagged(&mut bytes, b"rent_for:", contract_id.as_bytes());
+        }
+        if let Some(creation) = &self.contract_creation {
+            bytes.extend_from_slice(&serde_json::to_vec(creation).unwrap());
+        }
+        if let Some(vesting) = &self.vesting {
+            bytes.extend_from_slice(&serde_json::to_vec(vesting).unwrap());
+        }
+        if let Some(crowdfund) = &self.crowdfund {
+            bytes.extend_from_slice(&serde_json::to_vec(crowdfund).unwrap());
+        }
+        if let Some(agreement) = &self.agreement {
+            bytes.extend_from_slice(&serde_json::to_vec(agreement).unwrap());
+        }
+        if let Some(market) = &self.market {
+            bytes.extend_from_slice(&serde_json::to_vec(market).unwrap());
+        }
+        if let Some(enactment) = &self.enactment {
+            bytes.extend_from_slice(&serde_json::to_vec(enactment).unwrap());
+        }
+        if let Some(bridge) = &self.bridge {
+            bytes.extend_from_slice(&serde_json::to_vec(bridge).unwrap());
+        }
+        if let Some(revocation) = &self.revocation {
+            bytes.extend_from_slice(&serde_json::to_vec(revocation).unwrap());
+        }
+        if let Some(stake) = &self.stake {
+            bytes.extend_from_slice(&serde_json::to_vec(stake).unwrap());
+        }
+        // left out on the main network, so that transactions signed before
+        // network ids keep their hashes; changing it still voids signatures
+        if self.network_id != DEFAULT_NETWORK_ID {
+            bytes.extend_from_slice(b"network:");
+            bytes.extend_from_slice(self.network_id.as_bytes());
+        }
         bytes
     }
 }
\ No newline at end of file
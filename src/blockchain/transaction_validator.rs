@@ -1,36 +1,85 @@
 // Filename: src/blockchain/transaction_validator.rs
 
-use crate::blockchain::{Transaction, Blockchain};
+use serde::{Deserialize, Serialize};
+use crate::blockchain::parameters::SIGNED_TRANSACTIONS;
+use crate::blockchain::{AllowanceAction, OrganizationAction, SettlementAction, StandingOrderAction, Transaction, Blockchain};
+use crate::smart_contract::ValidationContext;
+
+/// The account validation logic is designated and cleared with.
+pub const VALIDATION_ACCOUNT: &str = "icn:validation";
+
+const DAY_SECONDS: i64 = 24 * 60 * 60;
+
+/// What a validation transaction does for its sender.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ValidationAction {
+    /// Has the deployed contract `contract_id` validate the transactions of
+    /// the sender in place of the signature check.
+    Designate { contract_id: String },
+    /// Goes back to the signature check.
+    Clear,
+}
 
 // Struct to validate transactions
 pub struct TransactionValidator;
 
 impl TransactionValidator {
-    // Function to validate a transaction
-    pub fn validate_transaction(transaction: &Transaction, blockchain: &Blockchain) -> bool {
-        if !Self::is_double_spend(transaction, blockchain) &&
-           Self::validate_currency_and_amount(transaction) &&
-           Self::check_sufficient_balance(transaction, blockchain) {
-            true
-        } else {
-            false
+    /// Fails for a transaction its sender did not authorise, in a block
+    /// timestamped `timestamp`, or for one moving an amount that is negative
+    /// or not a number.
+    ///
+    /// The transactions of an account that designated a validation contract
+    /// must be accepted by it. Those of any other must, if signed at all, be
+    /// signed with the key of the sending address, as must a designation.
+    /// Once the `SIGNED_TRANSACTIONS` feature is in force they must be signed
+    /// with that key in any case, and the transactions of an account with a
+    /// validation contract must be signed by someone. Signed transactions
+    /// must be meant for the network of the chain.
+    ///
+    /// Transfers out of an allowance or a treasury, standing order payments
+    /// and net settlement payments are signed by someone other than their
+    /// sender, or by no one, and enactments by the validators; they are
+    /// authorised by the checks of their own modules and pass here.
+    pub fn validate_transaction(transaction: &Transaction, blockchain: &Blockchain, timestamp: i64) -> Result<(), String> {
+        Self::check_amount(transaction)?;
+        let signed = transaction.signature.is_some() || !transaction.cosignatures.is_empty();
+        if signed && transaction.network_id != blockchain.spec.network_id {
+            return Err(format!("Transaction is for network {}, not {}", transaction.network_id, blockchain.spec.network_id));
+        }
+        if matches!(transaction.allowance, Some(AllowanceAction::Spend { .. }))
+            || matches!(transaction.organization, Some(OrganizationAction::Spend { .. }))
+            || matches!(transaction.standing_order, Some(StandingOrderAction::Execute { .. }))
+            || matches!(transaction.settlement, Some(SettlementAction::Settle { .. }))
+            || transaction.enactment.is_some() {
+            return Ok(());
+        }
+        let enforced = blockchain.is_feature_active(SIGNED_TRANSACTIONS);
+        match blockchain.validation_contracts.get(&transaction.from) {
+            Some(_) if enforced && !signed => {
+                Err(format!("Transactions must be signed since feature {} is in force", SIGNED_TRANSACTIONS))
+            }
+            Some(contract_id) => {
+                let contract = blockchain.execution_environment.registry.get(contract_id)
+                    .ok_or_else(|| format!("Validation contract {} of {} is not deployed", contract_id, transaction.from))?;
+                let context = ValidationContext {
+                    timestamp,
+                    sent_today: blockchain.sent_since(&transaction.from, &transaction.currency_type, timestamp - DAY_SECONDS),
+                };
+                contract.validate(transaction, &context)
+            }
+            None if enforced => transaction.check_signed_by(&transaction.from)
+                .map_err(|e| format!("{} since feature {} is in force", e, SIGNED_TRANSACTIONS)),
+            None if signed || transaction.validation.is_some() => transaction.check_signed_by(&transaction.from),
+            None => Ok(()),
         }
     }
 
-    // Function to check for double spending
-    fn is_double_spend(_transaction: &Transaction, _blockchain: &Blockchain) -> bool {
-        // TODO: Implement double spend check
-        false
-    }
-
-    // Function to validate the currency and amount of a transaction
-    fn validate_currency_and_amount(transaction: &Transaction) -> bool {
-        transaction.amount > 0.0
-    }
-
-    // Function to check if the sender has sufficient balance for the transaction
-    fn check_sufficient_balance(_transaction: &Transaction, _blockchain: &Blockchain) -> bool {
-        // TODO: Implement balance check
-        true
+    // Function to validate the amounts of a transaction
+    fn check_amount(transaction: &Transaction) -> Result<(), String> {
+        let invalid = |amount: f64| !amount.is_finite() || amount < 0.0;
+        if invalid(transaction.amount) || transaction.swap.as_ref().is_some_and(|swap| invalid(swap.amount)) {
+            return Err(format!("Invalid amount {}", transaction.amount));
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
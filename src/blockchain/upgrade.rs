@@ -0,0 +1,148 @@
+// src/blockchain/upgrade.rs
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::governance::DemocraticSystem;
+use crate::governance::democracy::{ProposalStatus, ProposalType};
+
+/// Version of the blocks produced before any upgrade is activated.
+pub const BASE_PROTOCOL_VERSION: u32 = 1;
+/// Highest block protocol version this build knows how to produce and run.
+pub const MAX_PROTOCOL_VERSION: u32 = 1;
+/// The account upgrade readiness signals are sent to.
+pub const UPGRADE_ACCOUNT: &str = "icn:upgrades";
+
+/// A change of behavior that all nodes switch to at the same block, so that
+/// nodes running old and new rules do not build different chains.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Upgrade {
+    /// Names the feature the upgrade turns on, e.g. a set of new opcodes.
+    pub name: String,
+    /// Block protocol version from the activation on.
+    pub version: u32,
+    /// The earliest height the upgrade can activate at.
+    pub activation_height: u64,
+    /// Share of the validators that must have signalled readiness.
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScheduledUpgrade {
+    upgrade: Upgrade,
+    /// Validators that signalled readiness, in blocks of the chain.
+    ready: BTreeSet<String>,
+    activated_at: Option<u64>,
+}
+
+/// Upgrades scheduled by governance. Each activates at the first block from
+/// its activation height on that follows enough readiness signals, all of
+/// which are in the chain, so every node activates it at the same block.
+/// Blocks carry the protocol version in force at their height.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpgradeSchedule {
+    upgrades: BTreeMap<String, ScheduledUpgrade>,
+    /// Overrides `MAX_PROTOCOL_VERSION`, for tests.
+    #[serde(skip)]
+    supported_version: Option<u32>,
+}
+
+impl UpgradeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_supported_version(mut self, version: u32) -> Self {
+        self.supported_version = Some(version);
+        self
+    }
+
+    pub fn supported_version(&self) -> u32 {
+        self.supported_version.unwrap_or(MAX_PROTOCOL_VERSION)
+    }
+
+    /// Schedules the upgrade of a passed network upgrade proposal, which is
+    /// marked implemented.
+    pub fn schedule(&mut self, governance: &mut DemocraticSystem, proposal_id: &str, upgrade: Upgrade) -> Result<(), String> {
+        let proposal = governance.get_proposal(proposal_id).ok_or("Proposal not found")?;
+        if proposal.proposal_type != ProposalType::NetworkUpgrade {
+            return Err(format!("Proposal {} is not a network upgrade", proposal_id));
+        }
+        if proposal.status != ProposalStatus::Passed {
+            return Err(format!("Proposal {} has not passed", proposal_id));
+        }
+        if self.upgrades.contains_key(&upgrade.name) {
+            return Err(format!("Upgrade {} is already scheduled", upgrade.name));
+        }
+        if upgrade.version <= self.latest_version() {
+            return Err(format!("Upgrade {} must raise the protocol version above {}", upgrade.name, self.latest_version()));
+        }
+        governance.mark_as_implemented(proposal_id)?;
+        info!("Scheduled upgrade {} to version {} from height {}", upgrade.name, upgrade.version, upgrade.activation_height);
+        self.upgrades.insert(upgrade.name.clone(), ScheduledUpgrade { upgrade, ready: BTreeSet::new(), activated_at: None });
+        Ok(())
+    }
+
+    fn latest_version(&self) -> u32 {
+        self.upgrades.values().map(|scheduled| scheduled.upgrade.version).max().unwrap_or(BASE_PROTOCOL_VERSION)
+    }
+
+    pub fn upgrade(&self, name: &str) -> Option<&Upgrade> {
+        self.upgrades.get(name).map(|scheduled| &scheduled.upgrade)
+    }
+
+    /// Records that `validator` runs a build ready for the upgrade.
+    pub fn signal(&mut self, name: &str, validator: &str) -> Result<(), String> {
+        let scheduled = self.upgrades.get_mut(name).ok_or_else(|| format!("No upgrade named {}", name))?;
+        if scheduled.activated_at.is_none() {
+            scheduled.ready.insert(validator.to_string());
+        }
+        Ok(())
+    }
+
+    /// The validators ready for the upgrade.
+    pub fn ready(&self, name: &str) -> Vec<String> {
+        self.upgrades.get(name).map_or_else(Vec::new, |scheduled| scheduled.ready.iter().cloned().collect())
+    }
+
+    /// The height the upgrade activated at, if it has.
+    pub fn activated_at(&self, name: &str) -> Option<u64> {
+        self.upgrades.get(name).and_then(|scheduled| scheduled.activated_at)
+    }
+
+    /// Whether the upgrade is in force for the block at `height`.
+    pub fn is_active(&self, name: &str, height: u64) -> bool {
+        self.activated_at(name).is_some_and(|activated_at| activated_at <= height)
+    }
+
+    /// The upgrades that activate with the block at `height`, given the
+    /// current validators.
+    pub fn due(&self, height: u64, is_validator: impl Fn(&str) -> bool, validators: usize) -> Vec<String> {
+        self.upgrades.values()
+            .filter(|scheduled| scheduled.activated_at.is_none() && scheduled.upgrade.activation_height <= height)
+            .filter(|scheduled| {
+                let ready = scheduled.ready.iter().filter(|validator| is_validator(validator)).count();
+                validators > 0 && ready as f64 / validators as f64 >= scheduled.upgrade.threshold
+            })
+            .map(|scheduled| scheduled.upgrade.name.clone())
+            .collect()
+    }
+
+    /// The protocol version of the block at `height` once `activating`
+    /// upgrades are in force.
+    pub fn version_at(&self, height: u64, activating: &[String]) -> u32 {
+        self.upgrades.values()
+            .filter(|scheduled| self.is_active(&scheduled.upgrade.name, height) || activating.contains(&scheduled.upgrade.name))
+            .map(|scheduled| scheduled.upgrade.version)
+            .max()
+            .unwrap_or(BASE_PROTOCOL_VERSION)
+    }
+
+    pub fn activate(&mut self, names: &[String], height: u64) {
+        for name in names {
+            if let Some(scheduled) = self.upgrades.get_mut(name) {
+                info!("Upgrade {} active from block {}", name, height);
+                scheduled.activated_at = Some(height);
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// src/blockchain/vesting.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use super::payout::{event, payout};
+use super::{Transaction, Transfer};
+
+/// The account holding the locked amounts of vesting grants, and paying out
+/// of them.
+pub const VESTING_ACCOUNT: &str = "icn:vesting";
+
+/// What a vesting transaction does.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum VestingAction {
+    /// Locks the amount of the transaction for `beneficiary`, to vest on
+    /// `schedule`.
+    Grant { beneficiary: String, schedule: VestingSchedule, revocable: bool },
+    /// Pays the beneficiary what has vested and not been claimed yet.
+    Claim { vesting_id: String },
+    /// Ends a grant whose revocation governance approved, paying the
+    /// beneficiary what has vested and refunding the rest to the grantor.
+    Revoke { vesting_id: String },
+}
+
+/// When a grant vests, counted in blocks from the one it was made in.
+/// Nothing vests before the cliff; then the amount vests linearly until the
+/// end of the duration, when all of it has.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VestingSchedule {
+    pub cliff_blocks: u64,
+    pub duration_blocks: u64,
+}
+
+impl VestingSchedule {
+    /// The share vested `elapsed` blocks after the grant.
+    pub fn vested_share(&self, elapsed: u64) -> f64 {
+        if elapsed < self.cliff_blocks {
+            return 0.0;
+        }
+        elapsed.min(self.duration_blocks) as f64 / self.duration_blocks as f64
+    }
+}
+
+/// An amount locked for a contributor and released to them over time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vesting {
+    /// Hash of the transaction that made the grant.
+    pub id: String,
+    pub grantor: String,
+    pub beneficiary: String,
+    pub currency_type: CurrencyType,
+    pub amount: f64,
+    /// Index of the block the grant was made in.
+    pub granted_at: u64,
+    pub schedule: VestingSchedule,
+    /// Whether the grantor may take back what has not vested, once
+    /// governance approves.
+    pub revocable: bool,
+    /// The proposal that approved revoking the grant.
+    pub revocation_approved_by: Option<String>,
+    /// Paid to the beneficiary so far.
+    pub claimed: f64,
+}
+
+impl Vesting {
+    /// What has vested as of the block at `index`.
+    pub fn vested_at(&self, index: u64) -> f64 {
+        self.amount * self.schedule.vested_share(index.saturating_sub(self.granted_at))
+    }
+
+    /// What the beneficiary could claim in the block at `index`.
+    pub fn claimable_at(&self, index: u64) -> f64 {
+        self.vested_at(index) - self.claimed
+    }
+}
+
+/// The vesting grants not yet fully claimed or revoked. Claims and
+/// revocations pay out from `VESTING_ACCOUNT` in the block applying them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VestingRegistry {
+    grants: BTreeMap<String, Vesting>,
+}
+
+impl VestingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, vesting_id: &str) -> Option<&Vesting> {
+        self.grants.get(vesting_id)
+    }
+
+    /// The grants `address` made or benefits from.
+    pub fn of(&self, address: &str) -> Vec<&Vesting> {
+        self.grants.values().filter(|vesting| vesting.grantor == address || vesting.beneficiary == address).collect()
+    }
+
+    /// Approves revoking a revocable grant, as enacted by the proposal
+    /// `proposal_id`.
+    pub fn approve_revocation(&mut self, vesting_id: &str, proposal_id: &str) -> Result<(), String> {
+        let vesting = self.grants.get_mut(vesting_id).ok_or_else(|| format!("No vesting grant {}", vesting_id))?;
+        if !vesting.revocable {
+            return Err(format!("Vesting grant {} is not revocable", vesting_id));
+        }
+        info!("Revocation of vesting grant {} approved by proposal {}", vesting_id, proposal_id);
+        vesting.revocation_approved_by = Some(proposal_id.to_string());
+        Ok(())
+    }
+
+    /// Applies the vesting action of `transaction`, in the block at `index`,
+    /// returning its event and the payouts it makes.
+    pub fn apply(&mut self, transaction: &Transaction, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        match &transaction.vesting {
+            Some(VestingAction::Grant { beneficiary, schedule, revocable }) => self.grant(transaction, beneficiary, *schedule, *revocable, index),
+            Some(VestingAction::Claim { vesting_id }) => self.claim(vesting_id, &transaction.from, index),
+            Some(VestingAction::Revoke { vesting_id }) => self.revoke(vesting_id, &transaction.from, index),
+            None => Err("Not a vesting transaction".to_string()),
+        }
+    }
+
+    fn grant(&mut self, transaction: &Transaction, beneficiary: &str, schedule: VestingSchedule, revocable: bool, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        if !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+            return Err("A vesting grant needs a positive amount".to_string());
+        }
+        if schedule.duration_blocks == 0 || schedule.cliff_blocks > schedule.duration_blocks {
+            return Err("A vesting grant needs a duration of at least its cliff".to_string());
+        }
+        if beneficiary == transaction.from {
+            return Err("A vesting grant cannot benefit its grantor".to_string());
+        }
+        if transaction.to != VESTING_ACCOUNT {
+            return Err(format!("A vesting grant locks its amount with {}", VESTING_ACCOUNT));
+        }
+        if self.grants.contains_key(&transaction.hash()) {
+            return Err(format!("Vesting grant {} is already made", transaction.hash()));
+        }
+        let vesting = Vesting {
+            id: transaction.hash(),
+            grantor: transaction.from.clone(),
+            beneficiary: beneficiary.to_string(),
+            currency_type: transaction.currency_type.clone(),
+            amount: transaction.amount,
+            granted_at: index,
+            schedule,
+            revocable,
+            revocation_approved_by: None,
+            claimed: 0.0,
+        };
+        info!("Vesting grant {} locks {} {} for {} over {} blocks", vesting.id, vesting.amount, vesting.currency_type, vesting.beneficiary, schedule.duration_blocks);
+        let event = event(&vesting.id, "VestingGranted", format!("{} {} for {}", vesting.amount, vesting.currency_type, vesting.beneficiary));
+        self.grants.insert(vesting.id.clone(), vesting);
+        Ok((event, Vec::new()))
+    }
+
+    fn claim(&mut self, vesting_id: &str, by: &str, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        let vesting = self.grants.get_mut(vesting_id).ok_or_else(|| format!("No vesting grant {}", vesting_id))?;
+        if vesting.beneficiary != by {
+            return Err(format!("{} is not the beneficiary of vesting grant {}", by, vesting_id));
+        }
+        let amount = vesting.claimable_at(index);
+        if amount <= 0.0 {
+            return Err(format!("Vesting grant {} has nothing to claim", vesting_id));
+        }
+        vesting.claimed += amount;
+        let payout = payout(VESTING_ACCOUNT, &vesting.beneficiary, amount, &vesting.currency_type);
+        if vesting.claimed >= vesting.amount {
+            self.grants.remove(vesting_id);
+        }
+        Ok((event(vesting_id, "VestingClaimed", amount.to_string()), vec![payout]))
+    }
+
+    fn revoke(&mut self, vesting_id: &str, by: &str, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        let vesting = self.grants.get(vesting_id).ok_or_else(|| format!("No vesting grant {}", vesting_id))?;
+        if vesting.grantor != by {
+            return Err(format!("{} is not the grantor of vesting grant {}", by, vesting_id));
+        }
+        if vesting.revocation_approved_by.is_none() {
+            return Err(format!("Revoking vesting grant {} has not been approved by governance", vesting_id));
+        }
+        let vesting = self.grants.remove(vesting_id).expect("the grant was just found");
+        let vested = vesting.claimable_at(index);
+        let refund = vesting.amount - vesting.vested_at(index);
+        info!("Vesting grant {} revoked, paying {} and refunding {}", vesting_id, vested, refund);
+        let payouts = [(&vesting.beneficiary, vested), (&vesting.grantor, refund)].into_iter()
+            .filter(|(_, amount)| *amount > 0.0)
+            .map(|(to, amount)| payout(VESTING_ACCOUNT, to, amount, &vesting.currency_type))
+            .collect();
+        Ok((event(vesting_id, "VestingRevoked", format!("{} vested, {} refunded", vested, refund)), payouts))
+    }
+}
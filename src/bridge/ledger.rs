@@ -0,0 +1,111 @@
+// src/bridge/ledger.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::blockchain::{Transaction, Transfer};
+use crate::consensus::PoCConsensus;
+use crate::currency::CurrencyType;
+use crate::smart_contract::ContractEvent;
+use super::{Bridge, BridgeAction, BridgeTransfer, Direction, BRIDGE_ACCOUNT, BRIDGE_THRESHOLD};
+
+/// What the chain knows of the bridge. A lock, signed by its sender, moves
+/// funds into `BRIDGE_ACCOUNT` and is numbered per chain as the block
+/// includes it, with a `BridgeLocked` event carrying the transfer for the
+/// validators to sign. A release goes through once, if validators holding
+/// `BRIDGE_THRESHOLD` of the validators' weight signed it, and is paid out
+/// of `BRIDGE_ACCOUNT` by the block: wrapped assets of its chain are minted,
+/// native funds only come out of what was locked for that chain.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BridgeLedger {
+    /// Next outbound nonce, by chain.
+    next_nonce: BTreeMap<String, u64>,
+    /// Native funds locked for each chain, by chain and currency.
+    locked: BTreeMap<String, f64>,
+    /// The inbound transfers released, by chain, direction and nonce, with
+    /// the index of the block releasing each.
+    released: BTreeMap<String, u64>,
+}
+
+impl BridgeLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_released(&self, transfer: &BridgeTransfer) -> bool {
+        self.released.contains_key(&transfer.replay_key())
+    }
+
+    /// What is locked of `currency` for `chain_id`.
+    pub fn locked(&self, chain_id: &str, currency: &CurrencyType) -> f64 {
+        self.locked.get(&locked_key(chain_id, currency)).copied().unwrap_or(0.0)
+    }
+
+    /// Applies the lock or release of `transaction`, in the block at
+    /// `index`, returning its event and the payouts it makes.
+    pub fn apply(&mut self, transaction: &Transaction, consensus: &PoCConsensus, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        if transaction.to != BRIDGE_ACCOUNT {
+            return Err(format!("A bridge transaction is addressed to {}", BRIDGE_ACCOUNT));
+        }
+        match &transaction.bridge {
+            Some(BridgeAction::Lock { chain_id, recipient }) => self.lock(transaction, chain_id, recipient),
+            Some(BridgeAction::Release { transfer, proof }) => {
+                if transaction.amount != 0.0 {
+                    return Err("A release moves no funds itself".to_string());
+                }
+                if transfer.direction != Direction::Inbound || !transfer.amount.is_finite() || transfer.amount <= 0.0 {
+                    return Err(format!("Transfer {} is not a positive transfer from {}", transfer.id(), transfer.chain_id));
+                }
+                if self.is_released(transfer) {
+                    return Err(format!("Transfer {} from {} was already carried out", transfer.nonce, transfer.chain_id));
+                }
+                proof.verify(transfer, consensus, BRIDGE_THRESHOLD)?;
+                self.release(transfer, index)
+            }
+            None => Err("Not a bridge transaction".to_string()),
+        }
+    }
+
+    fn lock(&mut self, transaction: &Transaction, chain_id: &str, recipient: &str) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        if !transaction.amount.is_finite() || transaction.amount <= 0.0 {
+            return Err("Bridged amount must be positive".to_string());
+        }
+        transaction.check_signed_by(&transaction.from)?;
+        let nonce = self.next_nonce.entry(chain_id.to_string()).or_insert(0);
+        let transfer = BridgeTransfer {
+            chain_id: chain_id.to_string(),
+            direction: Direction::Outbound,
+            nonce: *nonce,
+            sender: transaction.from.clone(),
+            recipient: recipient.to_string(),
+            amount: transaction.amount,
+            currency: transaction.currency_type.clone(),
+            source_tx: transaction.hash(),
+        };
+        *nonce += 1;
+        if !Bridge::is_wrapped(chain_id, &transfer.currency) {
+            *self.locked.entry(locked_key(chain_id, &transfer.currency)).or_insert(0.0) += transfer.amount;
+        }
+        info!("Locked {} {} of {} for {} on {}", transfer.amount, transfer.currency, transfer.sender, recipient, chain_id);
+        let data = serde_json::to_string(&transfer).map_err(|e| e.to_string())?;
+        Ok((ContractEvent { contract_id: transfer.id(), name: "BridgeLocked".to_string(), data }, Vec::new()))
+    }
+
+    fn release(&mut self, transfer: &BridgeTransfer, index: u64) -> Result<(ContractEvent, Vec<Transfer>), String> {
+        if !Bridge::is_wrapped(&transfer.chain_id, &transfer.currency) {
+            let locked = self.locked(&transfer.chain_id, &transfer.currency);
+            if transfer.amount > locked {
+                return Err(format!("Only {} {} is locked for {}, cannot release {}", locked, transfer.currency, transfer.chain_id, transfer.amount));
+            }
+            self.locked.insert(locked_key(&transfer.chain_id, &transfer.currency), locked - transfer.amount);
+        }
+        info!("Released {} {} from {} to {}", transfer.amount, transfer.currency, transfer.chain_id, transfer.recipient);
+        self.released.insert(transfer.replay_key(), index);
+        let event = ContractEvent { contract_id: transfer.id(), name: "BridgeReleased".to_string(), data: transfer.amount.to_string() };
+        let payout = Transfer { from: BRIDGE_ACCOUNT.to_string(), to: transfer.recipient.clone(), amount: transfer.amount, currency_type: transfer.currency.clone() };
+        Ok((event, vec![payout]))
+    }
+}
+
+fn locked_key(chain_id: &str, currency: &CurrencyType) -> String {
+    format!("{}:{}", chain_id, currency)
+}
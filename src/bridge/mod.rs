@@ -0,0 +1,405 @@
+// src/bridge/mod.rs
+use std::collections::{BTreeSet, VecDeque};
+use ed25519_dalek::Keypair;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::blockchain::{Blockchain, Transaction};
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::wallet::address_of;
+
+pub mod ledger;
+pub mod proof;
+
+pub use ledger::BridgeLedger;
+pub use proof::BridgeProof;
+
+/// The account funds sent to other chains are locked in, and funds coming
+/// from them are released or minted from.
+pub const BRIDGE_ACCOUNT: &str = "icn:bridge";
+/// Share of the validators' weight that must sign a transfer.
+pub const BRIDGE_THRESHOLD: f64 = 2.0 / 3.0;
+/// Gas limit of the transactions the bridge submits.
+const BRIDGE_GAS_LIMIT: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Locked on ICN, to be minted on the external chain.
+    Outbound,
+    /// Locked or burned on the external chain, to be released on ICN.
+    Inbound,
+}
+
+/// A transfer between ICN and an external chain, as signed by validators.
+/// `nonce` numbers the transfers of a chain and direction, so each can be
+/// carried out only once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeTransfer {
+    pub chain_id: String,
+    pub direction: Direction,
+    pub nonce: u64,
+    pub sender: String,
+    pub recipient: String,
+    pub amount: f64,
+    pub currency: CurrencyType,
+    /// Hash of the transaction on the source chain.
+    pub source_tx: String,
+}
+
+impl BridgeTransfer {
+    pub fn signing_payload(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Hex SHA-256 of the signed fields.
+    pub fn id(&self) -> String {
+        hex::encode(Sha256::digest(&self.signing_payload()))
+    }
+
+    fn replay_key(&self) -> String {
+        format!("{}:{:?}:{}", self.chain_id, self.direction, self.nonce)
+    }
+}
+
+/// What a bridge transaction does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BridgeAction {
+    /// Locks the amount of the transaction, signed by its sender, for
+    /// `recipient` on `chain_id`.
+    Lock { chain_id: String, recipient: String },
+    /// Pays out a transfer from an external chain, signed by the validators,
+    /// to its recipient; see `BridgeLedger`.
+    Release { transfer: BridgeTransfer, proof: BridgeProof },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    /// External chains transfers may go to and come from.
+    pub chains: BTreeSet<String>,
+}
+
+/// A transfer waiting for validator signatures, and for outbound transfers
+/// then for relayers to carry it to its chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeEvent {
+    pub transfer: BridgeTransfer,
+    pub proof: BridgeProof,
+}
+
+/// Moves funds between ICN and external chains by lock and mint. Funds
+/// leaving ICN are locked in `BRIDGE_ACCOUNT` by a transaction their owner
+/// signs, and once a block includes it the transfer is queued as an event
+/// for validators to sign; relayers take the signed events to the external
+/// chain, which mints a representation once it checks the multi-signature.
+/// The other way, relayers report transfers seen on the external chain,
+/// which are queued until enough validators sign them, or bring them
+/// already signed; a release transaction then has the chain pay out locked
+/// funds or mint wrapped external assets on ICN. Transfers
+/// are numbered per chain and direction, and a number is only ever used
+/// once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bridge {
+    config: BridgeConfig,
+    /// Height of the chain up to which locks were queued.
+    synced: u64,
+    outbound: VecDeque<BridgeEvent>,
+    inbound: VecDeque<BridgeEvent>,
+    /// Transfers carried out, by chain, direction and nonce.
+    processed: BTreeSet<String>,
+}
+
+impl Bridge {
+    pub fn new(config: BridgeConfig) -> Self {
+        Bridge { config, ..Bridge::default() }
+    }
+
+    pub fn config(&self) -> &BridgeConfig {
+        &self.config
+    }
+
+    /// The currency an asset of an external chain is represented by on ICN.
+    pub fn wrapped_currency(chain_id: &str, asset: &str) -> CurrencyType {
+        CurrencyType::AssetToken(format!("{}:{}", chain_id, asset))
+    }
+
+    pub fn is_wrapped(chain_id: &str, currency: &CurrencyType) -> bool {
+        matches!(currency, CurrencyType::AssetToken(name) if name.starts_with(&format!("{}:", chain_id)))
+    }
+
+    fn check_chain(&self, chain_id: &str) -> Result<()> {
+        if self.config.chains.contains(chain_id) {
+            Ok(())
+        } else {
+            Err(Error::BridgeError(format!("Chain {} is not bridged", chain_id)))
+        }
+    }
+
+    /// Submits the lock of `amount` of the funds of the account of `sender`
+    /// for `recipient` on `chain_id`, signed with its key, returning the
+    /// transaction. The transfer is queued for signing once a block includes
+    /// it; see `sync`.
+    pub fn lock(&mut self, blockchain: &mut Blockchain, sender: &Keypair, chain_id: &str, recipient: &str, amount: f64, currency: CurrencyType) -> Result<Transaction> {
+        self.check_chain(chain_id)?;
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(Error::BridgeError("Bridged amount must be positive".to_string()));
+        }
+        let address = address_of(&sender.public);
+        let available = blockchain.get_currency_balance(&address, &currency);
+        if amount > available {
+            return Err(Error::BridgeError(format!("{} has {} {}, cannot bridge {}", address, available, currency, amount)));
+        }
+        let mut transaction = Transaction::lock_bridged(address, chain_id.to_string(), recipient.to_string(), amount, currency, BRIDGE_GAS_LIMIT)
+            .on_network(&blockchain.spec.network_id);
+        transaction.sign(sender).map_err(Error::BridgeError)?;
+        blockchain.add_transaction(transaction.clone())?;
+        Ok(transaction)
+    }
+
+    /// Queues for signing the locks to bridged chains that blocks included
+    /// since the last sync, as the `BridgeLocked` events of their receipts
+    /// tell, returning how many were queued.
+    pub fn sync(&mut self, blockchain: &Blockchain) -> usize {
+        let mut queued = 0;
+        for block in blockchain.chain.iter().skip(self.synced as usize + 1) {
+            for transaction in block.transactions.iter().filter(|transaction| transaction.bridge.is_some()) {
+                let events = blockchain.get_transaction_receipt(&transaction.hash()).map(|receipt| receipt.events.clone()).unwrap_or_default();
+                for event in events.into_iter().filter(|event| event.name == "BridgeLocked") {
+                    let transfer: BridgeTransfer = match serde_json::from_str(&event.data) {
+                        Ok(transfer) => transfer,
+                        Err(_) => continue,
+                    };
+                    if self.check_chain(&transfer.chain_id).is_ok() && !self.outbound.iter().any(|queued| queued.transfer == transfer) {
+                        self.outbound.push_back(BridgeEvent { transfer, proof: BridgeProof::new() });
+                        queued += 1;
+                    }
+                }
+            }
+            self.synced = block.index;
+        }
+        queued
+    }
+
+    /// Outbound transfers still waiting to be relayed.
+    pub fn outbound(&self) -> impl Iterator<Item = &BridgeEvent> {
+        self.outbound.iter()
+    }
+
+    /// Inbound transfers still waiting for signatures.
+    pub fn inbound(&self) -> impl Iterator<Item = &BridgeEvent> {
+        self.inbound.iter()
+    }
+
+    /// Queues a transfer seen on an external chain for validators to sign.
+    pub fn observe(&mut self, transfer: BridgeTransfer) -> Result<()> {
+        self.check_inbound(&transfer)?;
+        if !self.inbound.iter().any(|event| event.transfer == transfer) {
+            self.inbound.push_back(BridgeEvent { transfer, proof: BridgeProof::new() });
+        }
+        Ok(())
+    }
+
+    /// Adds a validator's signature to a queued transfer.
+    pub fn attest(&mut self, transfer_id: &str, member_id: &str, signature: Vec<u8>) -> Result<()> {
+        let event = self.outbound.iter_mut().chain(self.inbound.iter_mut())
+            .find(|event| event.transfer.id() == transfer_id)
+            .ok_or_else(|| Error::NotFound(format!("No queued transfer {}", transfer_id)))?;
+        event.proof.signatures.insert(member_id.to_string(), signature);
+        Ok(())
+    }
+
+    /// Outbound transfers signed by enough validators to be relayed.
+    pub fn ready(&self, blockchain: &Blockchain) -> Vec<&BridgeEvent> {
+        self.outbound.iter()
+            .filter(|event| event.proof.verify(&event.transfer, &blockchain.consensus, BRIDGE_THRESHOLD).is_ok())
+            .collect()
+    }
+
+    /// Drops an outbound transfer a relayer carried to its chain.
+    pub fn confirm_relayed(&mut self, transfer_id: &str) -> Result<BridgeTransfer> {
+        let position = self.outbound.iter()
+            .position(|event| event.transfer.id() == transfer_id)
+            .ok_or_else(|| Error::NotFound(format!("No outbound transfer {}", transfer_id)))?;
+        let event = self.outbound.remove(position).expect("position is in range");
+        self.processed.insert(event.transfer.replay_key());
+        Ok(event.transfer)
+    }
+
+    /// Submits, from `relayer`, the releases of the queued inbound transfers
+    /// signed by enough validators, returning them.
+    pub fn release_ready(&mut self, blockchain: &mut Blockchain, relayer: &str) -> Result<Vec<BridgeTransfer>> {
+        let ready: Vec<BridgeEvent> = self.inbound.iter()
+            .filter(|event| event.proof.verify(&event.transfer, &blockchain.consensus, BRIDGE_THRESHOLD).is_ok())
+            .cloned()
+            .collect();
+        let mut released = Vec::new();
+        for event in ready {
+            self.release(blockchain, relayer, &event.transfer, &event.proof)?;
+            released.push(event.transfer);
+        }
+        Ok(released)
+    }
+
+    fn check_inbound(&self, transfer: &BridgeTransfer) -> Result<()> {
+        self.check_inbound_on(transfer, None)
+    }
+
+    /// Like `check_inbound`, also failing for a transfer `blockchain`
+    /// released.
+    fn check_inbound_on(&self, transfer: &BridgeTransfer, blockchain: Option<&Blockchain>) -> Result<()> {
+        self.check_chain(&transfer.chain_id)?;
+        if transfer.direction != Direction::Inbound {
+            return Err(Error::BridgeError(format!("Transfer {} does not come from {}", transfer.id(), transfer.chain_id)));
+        }
+        if self.processed.contains(&transfer.replay_key()) || blockchain.is_some_and(|blockchain| blockchain.bridge.is_released(transfer)) {
+            return Err(Error::BridgeError(format!("Transfer {} from {} was already carried out", transfer.nonce, transfer.chain_id)));
+        }
+        Ok(())
+    }
+
+    /// Submits, from `relayer`, the release of a transfer from an external
+    /// chain once its proof checks out: wrapped assets of that chain are
+    /// minted, anything else is released from the funds locked earlier. The
+    /// chain checks the proof again as it pays the transfer out.
+    pub fn release(&mut self, blockchain: &mut Blockchain, relayer: &str, transfer: &BridgeTransfer, proof: &BridgeProof) -> Result<()> {
+        self.check_inbound_on(transfer, Some(blockchain))?;
+        proof.verify(transfer, &blockchain.consensus, BRIDGE_THRESHOLD).map_err(Error::BridgeError)?;
+        if !Self::is_wrapped(&transfer.chain_id, &transfer.currency) {
+            let locked = blockchain.get_currency_balance(BRIDGE_ACCOUNT, &transfer.currency);
+            if transfer.amount > locked {
+                return Err(Error::BridgeError(format!("Only {} {} is locked, cannot release {}", locked, transfer.currency, transfer.amount)));
+            }
+        }
+        blockchain.add_transaction(Transaction::release_bridged(relayer.to_string(), transfer.clone(), proof.clone(), BRIDGE_GAS_LIMIT))?;
+        self.processed.insert(transfer.replay_key());
+        self.inbound.retain(|event| event.transfer.replay_key() != transfer.replay_key());
+        info!("Submitted the release of {} {} from {} to {}", transfer.amount, transfer.currency, transfer.chain_id, transfer.recipient);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ChainSpec;
+    use rand::rngs::OsRng;
+
+    fn bridged_chain(spec: ChainSpec) -> (Blockchain, Vec<(&'static str, Keypair)>) {
+        let mut blockchain = Blockchain::with_spec(spec);
+        let validators: Vec<_> = ["Alice", "Bob", "Carol"].into_iter()
+            .map(|id| (id, Keypair::generate(&mut OsRng {})))
+            .collect();
+        for (id, keypair) in &validators {
+            blockchain.consensus.add_member(id.to_string(), true);
+            blockchain.consensus.register_key(id, &keypair.public).unwrap();
+        }
+        (blockchain, validators)
+    }
+
+    #[test]
+    fn test_lock_and_relay_outbound() {
+        let dave = Keypair::generate(&mut OsRng {});
+        let (mut blockchain, validators) = bridged_chain(ChainSpec::default().with_allocation(&address_of(&dave.public), 50.0, CurrencyType::BasicNeeds).with_allocation("Erin", 5.0, CurrencyType::BasicNeeds));
+        let mut bridge = Bridge::new(BridgeConfig { chains: ["ethereum".to_string()].into() });
+
+        assert!(bridge.lock(&mut blockchain, &dave, "solana", "0xdave", 10.0, CurrencyType::BasicNeeds).is_err());
+        assert!(bridge.lock(&mut blockchain, &dave, "ethereum", "0xdave", 60.0, CurrencyType::BasicNeeds).is_err());
+        let unsigned = Transaction::lock_bridged("Erin".to_string(), "ethereum".to_string(), "0xerin".to_string(), 5.0, CurrencyType::BasicNeeds, 1000);
+        blockchain.add_transaction(unsigned.clone()).unwrap();
+        let lock = bridge.lock(&mut blockchain, &dave, "ethereum", "0xdave", 20.0, CurrencyType::BasicNeeds).unwrap();
+        assert_eq!(bridge.sync(&blockchain), 0, "nothing is queued before the lock is in a block");
+        blockchain.create_block("Alice".to_string()).unwrap();
+        assert!(!blockchain.get_transaction_receipt(&unsigned.hash()).unwrap().is_success());
+        assert_eq!(blockchain.get_balance(BRIDGE_ACCOUNT), 20.0);
+        assert_eq!(bridge.sync(&blockchain), 1);
+        assert_eq!(bridge.sync(&blockchain), 0);
+        let transfer = bridge.outbound().next().unwrap().transfer.clone();
+        assert_eq!((transfer.nonce, transfer.source_tx.as_str()), (0, lock.hash().as_str()));
+
+        for (id, keypair) in &validators[..2] {
+            let mut proof = BridgeProof::new();
+            proof.sign(&transfer, id, keypair);
+            assert!(bridge.ready(&blockchain).is_empty());
+            bridge.attest(&transfer.id(), id, proof.signatures.remove(*id).unwrap()).unwrap();
+        }
+        assert_eq!(bridge.ready(&blockchain).len(), 1);
+        bridge.confirm_relayed(&transfer.id()).unwrap();
+        assert_eq!(bridge.outbound().count(), 0);
+
+        // What is locked for one chain cannot be released from another
+        let drain = BridgeTransfer {
+            chain_id: "solana".to_string(),
+            direction: Direction::Inbound,
+            nonce: 0,
+            sender: "mallory.sol".to_string(),
+            recipient: "Mallory".to_string(),
+            amount: 20.0,
+            currency: CurrencyType::BasicNeeds,
+            source_tx: "0xdef".to_string(),
+        };
+        let mut proof = BridgeProof::new();
+        for (id, keypair) in &validators {
+            proof.sign(&drain, id, keypair);
+        }
+        let drain = Transaction::release_bridged("Mallory".to_string(), drain, proof, 1000);
+        blockchain.add_transaction(drain.clone()).unwrap();
+        blockchain.create_block("Alice".to_string()).unwrap();
+        assert!(!blockchain.get_transaction_receipt(&drain.hash()).unwrap().is_success());
+        assert_eq!(blockchain.bridge.locked("ethereum", &CurrencyType::BasicNeeds), 20.0);
+    }
+
+    #[test]
+    fn test_inbound_needs_proof_and_cannot_be_replayed() {
+        let (mut blockchain, validators) = bridged_chain(ChainSpec::default());
+        let mut bridge = Bridge::new(BridgeConfig { chains: ["ethereum".to_string()].into() });
+        let transfer = BridgeTransfer {
+            chain_id: "ethereum".to_string(),
+            direction: Direction::Inbound,
+            nonce: 0,
+            sender: "0xerin".to_string(),
+            recipient: "Erin".to_string(),
+            amount: 5.0,
+            currency: Bridge::wrapped_currency("ethereum", "ETH"),
+            source_tx: "0xabc".to_string(),
+        };
+        let mut proof = BridgeProof::new();
+        proof.sign(&transfer, "Alice", &validators[0].1);
+        // Bob's key signing for Carol does not count
+        proof.sign(&transfer, "Carol", &validators[1].1);
+        assert!(bridge.release(&mut blockchain, "Relayer", &transfer, &proof).is_err());
+
+        bridge.observe(transfer.clone()).unwrap();
+        for (id, signature) in proof.signatures.clone() {
+            bridge.attest(&transfer.id(), &id, signature).unwrap();
+        }
+        assert!(bridge.release_ready(&mut blockchain, "Relayer").unwrap().is_empty());
+        proof.sign(&transfer, "Carol", &validators[2].1);
+        bridge.attest(&transfer.id(), "Carol", proof.signatures["Carol"].clone()).unwrap();
+        assert_eq!(bridge.release_ready(&mut blockchain, "Relayer").unwrap(), vec![transfer.clone()]);
+        assert_eq!(bridge.inbound().count(), 0);
+        assert!(matches!(bridge.release(&mut blockchain, "Relayer", &transfer, &proof), Err(Error::BridgeError(_))));
+        assert!(bridge.observe(transfer.clone()).is_err());
+        blockchain.create_block("Alice".to_string()).unwrap();
+        assert_eq!(blockchain.get_currency_balance("Erin", &Bridge::wrapped_currency("ethereum", "ETH")), 5.0);
+
+        // The chain pays a transfer once, whoever relays it
+        let replayed = Transaction::release_bridged("Mallory".to_string(), transfer.clone(), proof.clone(), 1000);
+        blockchain.add_transaction(replayed.clone()).unwrap();
+        blockchain.create_block("Alice".to_string()).unwrap();
+        assert!(!blockchain.get_transaction_receipt(&replayed.hash()).unwrap().is_success());
+        assert!(Bridge::new(BridgeConfig { chains: ["ethereum".to_string()].into() }).release(&mut blockchain, "Relayer", &transfer, &proof).is_err());
+        assert_eq!(blockchain.get_currency_balance("Erin", &Bridge::wrapped_currency("ethereum", "ETH")), 5.0);
+
+        // Native funds are only released while locked
+        let native = BridgeTransfer { nonce: 1, currency: CurrencyType::BasicNeeds, ..transfer };
+        let mut proof = BridgeProof::new();
+        for (id, keypair) in &validators {
+            proof.sign(&native, id, keypair);
+        }
+        assert!(bridge.release(&mut blockchain, "Relayer", &native, &proof).is_err());
+        let unlocked = Transaction::release_bridged("Relayer".to_string(), native, proof, 1000);
+        blockchain.add_transaction(unlocked.clone()).unwrap();
+        blockchain.create_block("Alice".to_string()).unwrap();
+        assert!(!blockchain.get_transaction_receipt(&unlocked.hash()).unwrap().is_success());
+    }
+}
@@ -0,0 +1,54 @@
+// src/bridge/proof.rs
+use std::collections::BTreeMap;
+use ed25519_dalek::{Keypair, Signature, Signer, Verifier};
+use serde::{Serialize, Deserialize};
+use crate::consensus::PoCConsensus;
+use super::BridgeTransfer;
+
+/// Signatures of validators over a bridge transfer, by validator. Together
+/// they prove to either chain that the validators saw the transfer on the
+/// other.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BridgeProof {
+    pub signatures: BTreeMap<String, Vec<u8>>,
+}
+
+impl BridgeProof {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the signature of `member_id` over `transfer`.
+    pub fn sign(&mut self, transfer: &BridgeTransfer, member_id: &str, keypair: &Keypair) {
+        let signature = keypair.sign(&transfer.signing_payload()).to_bytes().to_vec();
+        self.signatures.insert(member_id.to_string(), signature);
+    }
+
+    /// The validators whose signatures over `transfer` check out against
+    /// their registered keys.
+    pub fn signers(&self, transfer: &BridgeTransfer, consensus: &PoCConsensus) -> Vec<String> {
+        let payload = transfer.signing_payload();
+        self.signatures.iter()
+            .filter(|(member_id, _)| consensus.is_active_validator(member_id))
+            .filter(|(member_id, signature)| {
+                let public_key = match consensus.public_key(member_id) {
+                    Some(public_key) => public_key,
+                    None => return false,
+                };
+                Signature::from_bytes(signature).is_ok_and(|signature| public_key.verify(&payload, &signature).is_ok())
+            })
+            .map(|(member_id, _)| member_id.clone())
+            .collect()
+    }
+
+    /// Checks that validators holding at least `threshold` of the
+    /// validators' weight signed `transfer`.
+    pub fn verify(&self, transfer: &BridgeTransfer, consensus: &PoCConsensus, threshold: f64) -> Result<(), String> {
+        let tally = consensus.tally_approvals(&self.signers(transfer, consensus));
+        if tally.total > 0.0 && tally.approval() >= threshold {
+            Ok(())
+        } else {
+            Err(format!("Transfer {} is signed by {:.2} of {:.2} validator weight", transfer.id(), tally.approve, tally.total))
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// src/clock.rs
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where time comes from. Code that expires, decays or schedules things
+/// asks its clock rather than the system, so that tests and simulations can
+/// move time forward instead of waiting for it.
+pub trait Clock: Debug + Send + Sync {
+    /// Wall-clock time, for timestamps that are stored or shared.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Monotonic time, for measuring how long something has waited.
+    fn instant(&self) -> Instant;
+}
+
+/// The time of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still until `advance` is called. Clones share the
+/// same time, so a test keeps one to move the time of what it handed the
+/// others to.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    /// A clock starting at the current system time.
+    pub fn new() -> Self {
+        Self::starting_at(Utc::now())
+    }
+
+    pub fn starting_at(start: DateTime<Utc>) -> Self {
+        MockClock { start, start_instant: Instant::now(), elapsed: Arc::new(Mutex::new(Duration::ZERO)) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// How far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.start + chrono::Duration::from_std(self.elapsed()).expect("mock clock advanced too far")
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+}
+
+/// A handle on the clock of a component, the system clock unless it was
+/// built with another. Clones share the clock.
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl std::ops::Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl From<MockClock> for SharedClock {
+    fn from(clock: MockClock) -> Self {
+        Self::new(clock)
+    }
+}
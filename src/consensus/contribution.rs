@@ -0,0 +1,240 @@
+// src/consensus/contribution.rs
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
+use crate::governance::DemocraticSystem;
+use crate::governance::democracy::{ProposalStatus, ProposalType};
+use crate::node::content_store::ContentStoreStats;
+use super::PoCConsensus;
+
+/// Blocks between settlements of measured work by default.
+pub const DEFAULT_SETTLEMENT_EPOCH: u64 = 100;
+const GIB: f64 = (1u64 << 30) as f64;
+
+/// Reputation earned per unit of work in each category. Changed only by a
+/// passed governance proposal, see `ContributionTracker::adopt_weights`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContributionWeights {
+    /// Per block voted on.
+    pub validation: f64,
+    /// Per interest answered from the content store.
+    pub content_serving: f64,
+    /// Per GiB pledged, for each settlement it stays pledged for.
+    pub storage: f64,
+    /// Per thousand units of gas spent executing blocks.
+    pub compute: f64,
+}
+
+impl Default for ContributionWeights {
+    fn default() -> Self {
+        ContributionWeights {
+            validation: 0.1,
+            content_serving: 0.001,
+            storage: 0.05,
+            compute: 0.01,
+        }
+    }
+}
+
+/// Work a member did since the last settlement.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeasuredWork {
+    pub blocks_validated: u64,
+    pub content_served: u64,
+    pub gas_used: u64,
+}
+
+/// Measures the work members do for the network and credits it as
+/// reputation through `PoCConsensus::update_reputation` once every epoch of
+/// blocks, so that voting weight follows contribution rather than manual
+/// adjustments. Settling at the same heights, nodes crediting the same work
+/// agree on the reputation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributionTracker {
+    weights: ContributionWeights,
+    work: BTreeMap<String, MeasuredWork>,
+    /// Bytes of storage each member has pledged, until withdrawn.
+    pledges: BTreeMap<String, u64>,
+    /// Content store hits already credited, by member serving from it.
+    hits_seen: HashMap<String, u64>,
+    /// Blocks from one settlement to the next.
+    epoch: u64,
+}
+
+impl ContributionTracker {
+    pub fn new() -> Self {
+        ContributionTracker {
+            weights: ContributionWeights::default(),
+            work: BTreeMap::new(),
+            pledges: BTreeMap::new(),
+            hits_seen: HashMap::new(),
+            epoch: DEFAULT_SETTLEMENT_EPOCH,
+        }
+    }
+
+    pub fn with_epoch(mut self, blocks: u64) -> Self {
+        self.epoch = blocks.max(1);
+        self
+    }
+
+    pub fn weights(&self) -> &ContributionWeights {
+        &self.weights
+    }
+
+    /// Replaces the weights with those of a passed economic adjustment
+    /// proposal, which is marked implemented so it cannot be applied twice.
+    pub fn adopt_weights(&mut self, governance: &mut DemocraticSystem, proposal_id: &str, weights: ContributionWeights) -> Result<(), String> {
+        let proposal = governance.get_proposal(proposal_id).ok_or("Proposal not found")?;
+        if proposal.proposal_type != ProposalType::EconomicAdjustment {
+            return Err(format!("Proposal {} is not an economic adjustment", proposal_id));
+        }
+        if proposal.status != ProposalStatus::Passed {
+            return Err(format!("Proposal {} has not passed", proposal_id));
+        }
+        governance.mark_as_implemented(proposal_id)?;
+        info!("Contribution weights set by proposal {}: {:?}", proposal_id, weights);
+        self.weights = weights;
+        Ok(())
+    }
+
+    pub fn record_block_validated(&mut self, member_id: &str) {
+        self.work.entry(member_id.to_string()).or_default().blocks_validated += 1;
+    }
+
+    pub fn record_content_served(&mut self, member_id: &str, count: u64) {
+        self.work.entry(member_id.to_string()).or_default().content_served += count;
+    }
+
+    /// Credits `member_id` with the interests its content store answered
+    /// since the store was last observed.
+    pub fn observe_content_store(&mut self, member_id: &str, stats: &ContentStoreStats) {
+        let seen = self.hits_seen.entry(member_id.to_string()).or_insert(0);
+        let served = stats.hits.saturating_sub(*seen);
+        *seen = stats.hits;
+        if served > 0 {
+            self.record_content_served(member_id, served);
+        }
+    }
+
+    pub fn record_compute(&mut self, member_id: &str, gas_used: u64) {
+        self.work.entry(member_id.to_string()).or_default().gas_used += gas_used;
+    }
+
+    /// Sets the storage `member_id` offers the network; 0 withdraws it.
+    pub fn pledge_storage(&mut self, member_id: &str, bytes: u64) {
+        if bytes == 0 {
+            self.pledges.remove(member_id);
+        } else {
+            self.pledges.insert(member_id.to_string(), bytes);
+        }
+    }
+
+    pub fn pledged_storage(&self, member_id: &str) -> u64 {
+        self.pledges.get(member_id).copied().unwrap_or(0)
+    }
+
+    /// Work measured for `member_id` since the last settlement.
+    pub fn measured(&self, member_id: &str) -> Option<&MeasuredWork> {
+        self.work.get(member_id)
+    }
+
+    /// Whether the block at `height` ends an epoch.
+    pub fn is_due(&self, height: u64) -> bool {
+        height > 0 && height.is_multiple_of(self.epoch)
+    }
+
+    /// Turns the work measured since the last settlement into reputation,
+    /// pledged storage counting once. Members the consensus does not know
+    /// are skipped. Returns what each member was credited.
+    pub fn settle(&mut self, consensus: &mut PoCConsensus) -> Vec<(String, f64)> {
+        let mut credits: BTreeMap<String, f64> = BTreeMap::new();
+        for (member_id, work) in std::mem::take(&mut self.work) {
+            *credits.entry(member_id).or_insert(0.0) += work.blocks_validated as f64 * self.weights.validation
+                + work.content_served as f64 * self.weights.content_serving
+                + work.gas_used as f64 / 1000.0 * self.weights.compute;
+        }
+        for (member_id, bytes) in &self.pledges {
+            *credits.entry(member_id.clone()).or_insert(0.0) += *bytes as f64 / GIB * self.weights.storage;
+        }
+        credits.into_iter()
+            .filter(|(_, credit)| *credit > 0.0)
+            .filter(|(member_id, credit)| match consensus.update_reputation(member_id, *credit) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("Not crediting {} for its contribution: {}", member_id, e);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// `settle` if the block at `height` ends an epoch.
+    pub fn settle_if_due(&mut self, height: u64, consensus: &mut PoCConsensus) -> Vec<(String, f64)> {
+        if self.is_due(height) { self.settle(consensus) } else { Vec::new() }
+    }
+}
+
+impl Default for ContributionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::governance::ProposalCategory;
+    use std::time::Duration;
+
+    #[test]
+    fn test_work_settles_into_reputation() {
+        let mut tracker = ContributionTracker::new().with_epoch(10);
+        let mut consensus = PoCConsensus::new(0.5, 0.66);
+        consensus.add_member("Alice".to_string(), true);
+        consensus.add_member("Bob".to_string(), true);
+
+        for _ in 0..10 {
+            tracker.record_block_validated("Alice");
+        }
+        tracker.observe_content_store("Bob", &ContentStoreStats { hits: 500, ..ContentStoreStats::default() });
+        tracker.observe_content_store("Bob", &ContentStoreStats { hits: 1000, ..ContentStoreStats::default() });
+        tracker.record_compute("Bob", 20_000);
+        tracker.pledge_storage("Bob", 2 << 30);
+        tracker.record_block_validated("Mallory");
+        assert!(tracker.settle_if_due(9, &mut consensus).is_empty());
+
+        let credits = tracker.settle_if_due(10, &mut consensus);
+        assert_eq!(credits.len(), 2);
+        assert!((consensus.get_reputation("Alice").unwrap() - 2.0).abs() < 1e-9);
+        // 1000 served, 20 thousand gas and 2 GiB for one epoch
+        assert!((consensus.get_reputation("Bob").unwrap() - 2.3).abs() < 1e-9);
+        assert!(tracker.measured("Alice").is_none());
+    }
+
+    #[test]
+    fn test_weights_adopted_from_passed_proposal() {
+        let clock = MockClock::new();
+        let mut governance = DemocraticSystem::new().with_clock(clock.clone().into());
+        let id = governance.create_proposal(
+            "Reward storage".to_string(),
+            "Weigh storage pledges higher".to_string(),
+            "Alice".to_string(),
+            chrono::Duration::days(1),
+            ProposalType::EconomicAdjustment,
+            ProposalCategory::Economic,
+            1.0,
+            None,
+        ).unwrap();
+        let weights = ContributionWeights { storage: 1.0, ..ContributionWeights::default() };
+        let mut tracker = ContributionTracker::new();
+        assert!(tracker.adopt_weights(&mut governance, &id, weights.clone()).is_err());
+
+        governance.vote("Bob".to_string(), id.clone(), true, 1.0).unwrap();
+        clock.advance(Duration::from_secs(86_401));
+        governance.tally_votes(&id).unwrap();
+        tracker.adopt_weights(&mut governance, &id, weights.clone()).unwrap();
+        assert_eq!(tracker.weights(), &weights);
+        assert!(tracker.adopt_weights(&mut governance, &id, ContributionWeights::default()).is_err());
+    }
+}
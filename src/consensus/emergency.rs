@@ -0,0 +1,158 @@
+// src/consensus/emergency.rs
+use std::collections::BTreeMap;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Serialize, Deserialize};
+use crate::blockchain::{Cosignature, Transaction};
+use crate::governance::ProposalAction;
+
+/// Gas limit of the enactments emergency calls cosign.
+pub const EMERGENCY_GAS_LIMIT: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EmergencyAction {
+    Halt,
+    Resume,
+}
+
+/// A validator's call to halt or resume the chain: its cosignature of the
+/// enactment doing so, which goes in a block once two thirds of the active
+/// validators have cosigned it. A call is only good for the round it names,
+/// so calls from before the last halt or resumption cannot be replayed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyCall {
+    pub member_id: String,
+    pub action: EmergencyAction,
+    pub round: u64,
+    pub reason: String,
+    pub cosignature: Cosignature,
+}
+
+impl EmergencyCall {
+    /// The call of `member_id`, whose key `keypair` is, on the chain of
+    /// `network_id`.
+    pub fn new(member_id: String, action: EmergencyAction, round: u64, reason: String, network_id: &str, keypair: &Keypair) -> Self {
+        let enactment = Self::enactment_for(action, round, &reason, network_id);
+        let cosignature = Cosignature {
+            public_key: keypair.public.to_bytes().to_vec(),
+            signature: keypair.sign(&enactment.to_bytes()).to_bytes().to_vec(),
+        };
+        EmergencyCall { member_id, action, round, reason, cosignature }
+    }
+
+    /// The enactment calls for `action` in `round` cosign. It names the
+    /// round as its proposal, so each is enacted once.
+    pub fn enactment_for(action: EmergencyAction, round: u64, reason: &str, network_id: &str) -> Transaction {
+        let proposal_id = format!("emergency:{}", round);
+        let action = match action {
+            EmergencyAction::Halt => ProposalAction::Halt { reason: reason.to_string() },
+            EmergencyAction::Resume => ProposalAction::Resume,
+        };
+        Transaction::enact(proposal_id.clone(), proposal_id, action, EMERGENCY_GAS_LIMIT).on_network(network_id)
+    }
+
+    pub fn enactment(&self, network_id: &str) -> Transaction {
+        Self::enactment_for(self.action, self.round, &self.reason, network_id)
+    }
+
+    /// Whether the call is cosigned with `public_key`.
+    pub fn verify(&self, public_key: &PublicKey, network_id: &str) -> bool {
+        self.cosignature.public_key == public_key.to_bytes()
+            && Signature::from_bytes(&self.cosignature.signature)
+                .is_ok_and(|signature| public_key.verify(&self.enactment(network_id).to_bytes(), &signature).is_ok())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Halt {
+    pub reason: String,
+    /// The proposal enacting the halt, or `emergency:<round>` for one the
+    /// validators called.
+    pub proposal_id: String,
+    /// Index of the block enacting the halt.
+    pub since: u64,
+}
+
+/// Stops the chain in an emergency. Halts and resumptions are enactments,
+/// so every node takes them at the same block. While halted, blocks hold
+/// enactments only: no funds move, and governance keeps working so that
+/// members can decide how to recover, and the chain runs again once resumed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CircuitBreaker {
+    halt: Option<Halt>,
+    /// Incremented on every halt and resumption.
+    round: u64,
+    /// Calls for the next change of state gathered by this node, by
+    /// validator.
+    #[serde(skip)]
+    calls: BTreeMap<String, EmergencyCall>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt.is_some()
+    }
+
+    pub fn halt(&self) -> Option<&Halt> {
+        self.halt.as_ref()
+    }
+
+    /// The round calls must name to count.
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    /// The action calls are currently gathered for.
+    pub fn pending_action(&self) -> EmergencyAction {
+        if self.is_halted() { EmergencyAction::Resume } else { EmergencyAction::Halt }
+    }
+
+    /// Adds a call, whose signature the caller has checked. Returns whether
+    /// it is new. Calls of a round must agree on the reason.
+    pub fn record(&mut self, call: &EmergencyCall) -> Result<bool, String> {
+        if call.round != self.round {
+            return Err(format!("Call is for round {}, the current round is {}", call.round, self.round));
+        }
+        if call.action != self.pending_action() {
+            return Err(format!("Cannot {:?} a chain that is {}", call.action, if self.is_halted() { "halted" } else { "running" }));
+        }
+        if let Some(other) = self.calls.values().find(|other| other.reason != call.reason) {
+            return Err(format!("{} already called for {:?} because: {}", other.member_id, other.action, other.reason));
+        }
+        Ok(self.calls.insert(call.member_id.clone(), call.clone()).is_none())
+    }
+
+    /// The calls gathered for the pending action.
+    pub fn calls(&self) -> impl Iterator<Item = &EmergencyCall> {
+        self.calls.values()
+    }
+
+    /// Halts the chain from the block after `index`, as enacted by
+    /// `proposal_id`.
+    pub fn trip(&mut self, reason: String, proposal_id: &str, index: u64) -> Result<(), String> {
+        if self.is_halted() {
+            return Err("The chain is already halted".to_string());
+        }
+        self.halt = Some(Halt { reason, proposal_id: proposal_id.to_string(), since: index });
+        self.next_round();
+        Ok(())
+    }
+
+    /// Resumes the chain from the block after the one enacting it.
+    pub fn reset(&mut self) -> Result<(), String> {
+        if !self.is_halted() {
+            return Err("The chain is not halted".to_string());
+        }
+        self.halt = None;
+        self.next_round();
+        Ok(())
+    }
+
+    fn next_round(&mut self) {
+        self.round += 1;
+        self.calls.clear();
+    }
+}
@@ -1,21 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
 use serde::{Serialize, Deserialize};
+use crate::reputation::{ContributionCategory, ReputationStore};
+use ed25519_dalek::PublicKey;
+use tracing::{debug, warn};
 
+pub mod contribution;
+pub mod emergency;
+pub mod nomination;
+pub mod staking;
+pub mod vote;
+
+pub use contribution::{ContributionTracker, ContributionWeights};
+pub use emergency::{CircuitBreaker, EmergencyAction, EmergencyCall, Halt};
+pub use nomination::{Nomination, NominationLedger, RewardConfig};
+pub use staking::{StakeAction, StakeInfo, StakeLedger, StakeRequirement};
+pub use vote::{Equivocation, SignedVote, VoteLog};
+
+/// Proof of Cooperation: validators vote on what the network accepts, such as
+/// blocks or shard headers, with weights taken from their reputation.
 #[derive(Serialize, Deserialize)]
 pub struct PoCConsensus {
     pub members: Vec<Member>,
+    /// Share of the validators' weight that must approve.
     pub threshold: f64,
+    /// Share of the validators' weight that must vote at all.
+    #[serde(default)]
+    pub quorum: f64,
+    pub reputation: ReputationStore,
+    /// Votes cast so far, by what they are about and then by validator.
+    #[serde(default)]
+    pub votes: HashMap<String, BTreeMap<String, bool>>,
+    /// Stake bonded by validators; see `StakeLedger` for when it is required.
+    #[serde(default)]
+    pub stakes: StakeLedger,
+    /// Validators nominated by other members, and the rewards they share.
+    #[serde(default)]
+    pub nominations: NominationLedger,
+    /// Signed block votes seen, kept to catch validators voting twice.
+    #[serde(default)]
+    pub vote_log: VoteLog,
+}
+
+/// The weight of the validators for and against something.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VoteTally {
+    pub approve: f64,
+    pub reject: f64,
+    /// Weight of all validators, whether they voted or not.
+    pub total: f64,
+}
+
+impl VoteTally {
+    pub fn approval(&self) -> f64 {
+        if self.total > 0.0 { self.approve / self.total } else { 0.0 }
+    }
+
+    pub fn participation(&self) -> f64 {
+        if self.total > 0.0 { (self.approve + self.reject) / self.total } else { 0.0 }
+    }
 }
 
 impl PoCConsensus {
-    pub fn new(threshold: f64, _quorum: f64) -> Self {
+    pub fn new(threshold: f64, quorum: f64) -> Self {
         PoCConsensus {
             members: Vec::new(),
             threshold,
+            quorum,
+            reputation: ReputationStore::new(),
+            votes: HashMap::new(),
+            stakes: StakeLedger::new(),
+            nominations: NominationLedger::new(),
+            vote_log: VoteLog::new(),
         }
     }
 
+    /// Requires validators to bond stake before their votes count.
+    pub fn with_stake_requirement(mut self, requirement: StakeRequirement) -> Self {
+        self.stakes = std::mem::take(&mut self.stakes).with_requirement(requirement);
+        self
+    }
+
+    /// Rewards validators for the blocks they create, shared with their
+    /// nominators at the end of each epoch.
+    pub fn with_rewards(mut self, rewards: RewardConfig) -> Self {
+        self.nominations = std::mem::take(&mut self.nominations).with_rewards(rewards);
+        self
+    }
+
     pub fn add_member(&mut self, member_id: String, is_validator: bool) {
-        self.members.push(Member { id: member_id, is_validator });
+        self.reputation.register(&member_id);
+        self.members.push(Member { id: member_id, is_validator, public_key: None });
+    }
+
+    /// Sets the key the signed votes of `member_id` are checked against.
+    pub fn register_key(&mut self, member_id: &str, public_key: &PublicKey) -> Result<(), String> {
+        let member = self.members.iter_mut().find(|member| member.id == member_id)
+            .ok_or_else(|| format!("Member not found: {}", member_id))?;
+        member.public_key = Some(public_key.to_bytes().to_vec());
+        Ok(())
+    }
+
+    pub fn public_key(&self, member_id: &str) -> Option<PublicKey> {
+        self.members.iter()
+            .find(|member| member.id == member_id)
+            .and_then(|member| member.public_key.as_ref())
+            .and_then(|bytes| PublicKey::from_bytes(bytes).ok())
+    }
+
+    pub fn get_reputation(&self, member_id: &str) -> Option<f64> {
+        self.reputation.get_reputation(member_id)
+    }
+
+    pub fn update_reputation(&mut self, member_id: &str, delta: f64) -> Result<(), String> {
+        self.reputation.adjust(member_id, ContributionCategory::Validation, delta)
+    }
+
+    /// Lets the reputation behind voting weights decay for the time passed
+    /// since it last changed, as told by the reputation store's clock.
+    pub fn decay_reputation(&mut self) {
+        self.reputation.decay();
+    }
+
+    /// Weight a member's block vote carries, taken from the shared reputation store.
+    pub fn voting_weight(&self, member_id: &str) -> f64 {
+        self.reputation.consensus_weight(member_id)
+    }
+
+    pub fn is_validator(&self, member_id: &str) -> bool {
+        self.members.iter().any(|member| member.id == member_id && member.is_validator)
+    }
+
+    /// A validator that has bonded the stake required, if any is.
+    pub fn is_active_validator(&self, member_id: &str) -> bool {
+        self.is_validator(member_id) && self.stakes.meets_requirement(member_id)
+    }
+
+    /// The validator whose turn it is to propose the block at `height`: the
+    /// active validators take turns in order of id.
+    pub fn proposer(&self, height: u64) -> Option<&str> {
+        let mut validators: Vec<&str> = self.members.iter()
+            .filter(|member| self.is_active_validator(&member.id))
+            .map(|member| member.id.as_str())
+            .collect();
+        validators.sort_unstable();
+        validators.dedup();
+        if validators.is_empty() {
+            return None;
+        }
+        Some(validators[(height % validators.len() as u64) as usize])
+    }
+
+    /// Records a validator's vote on `subject`, e.g. a block hash. A later
+    /// vote by the same validator replaces the earlier one.
+    pub fn vote(&mut self, subject: &str, member_id: &str, approve: bool) -> Result<(), String> {
+        if !self.is_validator(member_id) {
+            return Err(format!("{} is not a validator", member_id));
+        }
+        if !self.stakes.meets_requirement(member_id) {
+            return Err(format!("{} has not bonded the required stake", member_id));
+        }
+        debug!("{} votes {} on {}", member_id, if approve { "for" } else { "against" }, subject);
+        self.votes.entry(subject.to_string()).or_default().insert(member_id.to_string(), approve);
+        Ok(())
+    }
+
+    /// Records a signed vote on a block and counts it.
+    pub fn submit_vote(&mut self, vote: &SignedVote) -> Result<(), String> {
+        self.check_vote(vote)?;
+        self.vote(&vote.block_hash, &vote.member_id, vote.approve)
+    }
+
+    /// Checks a signed vote against the registered key of its validator and
+    /// keeps it, without counting it. If it conflicts with a vote the
+    /// validator signed before, the validator is slashed and the vote refused.
+    pub fn check_vote(&mut self, vote: &SignedVote) -> Result<(), String> {
+        let public_key = self.public_key(&vote.member_id)
+            .ok_or_else(|| format!("{} has no registered key", vote.member_id))?;
+        if !vote.verify(&public_key) {
+            return Err(format!("Vote by {} has an invalid signature", vote.member_id));
+        }
+        if let Some(proof) = self.vote_log.record(vote) {
+            self.punish_equivocation(proof)?;
+            return Err(format!("{} equivocated at height {}", vote.member_id, vote.height));
+        }
+        Ok(())
+    }
+
+    /// Acts on an equivocation proven elsewhere, returning the stake taken.
+    /// A validator is punished once per height.
+    pub fn report_equivocation(&mut self, proof: Equivocation) -> Result<f64, String> {
+        let public_key = self.public_key(proof.member_id())
+            .ok_or_else(|| format!("{} has no registered key", proof.member_id()))?;
+        proof.verify(&public_key)?;
+        for vote in [&proof.first, &proof.second] {
+            self.vote_log.record(vote);
+        }
+        self.punish_equivocation(proof)
+    }
+
+    fn punish_equivocation(&mut self, proof: Equivocation) -> Result<f64, String> {
+        let (member_id, height) = (proof.member_id().to_string(), proof.height());
+        if self.vote_log.is_punished(&member_id, height) {
+            return Ok(0.0);
+        }
+        // neither of the conflicting votes counts
+        for vote in [&proof.first, &proof.second] {
+            if let Some(ballots) = self.votes.get_mut(&vote.block_hash) {
+                ballots.remove(&member_id);
+            }
+        }
+        self.vote_log.add_equivocation(proof);
+        self.slash(&member_id, vote::EQUIVOCATION_SLASH_FRACTION, &format!("Equivocation at height {}", height))
+    }
+
+    /// The votes recorded on `subject`.
+    pub fn tally(&self, subject: &str) -> VoteTally {
+        let votes = self.votes.get(subject);
+        self.tally_ballots(votes.into_iter().flatten().map(|(member_id, approve)| (member_id.as_str(), *approve)))
+    }
+
+    /// Approvals gathered elsewhere, e.g. signed into a shard header. Each
+    /// active validator counts once; anyone else is ignored.
+    pub fn tally_approvals(&self, approvals: &[String]) -> VoteTally {
+        let mut approvals: Vec<&str> = approvals.iter().map(String::as_str).collect();
+        approvals.sort_unstable();
+        approvals.dedup();
+        self.tally_ballots(approvals.into_iter().map(|member_id| (member_id, true)))
+    }
+
+    fn tally_ballots<'a>(&self, ballots: impl Iterator<Item = (&'a str, bool)>) -> VoteTally {
+        let mut tally = VoteTally {
+            total: self.members.iter()
+                .filter(|member| member.is_validator && self.stakes.meets_requirement(&member.id))
+                .map(|member| self.voting_weight(&member.id))
+                .sum(),
+            ..VoteTally::default()
+        };
+        for (member_id, approve) in ballots.filter(|(member_id, _)| self.is_active_validator(member_id)) {
+            if approve {
+                tally.approve += self.voting_weight(member_id);
+            } else {
+                tally.reject += self.voting_weight(member_id);
+            }
+        }
+        tally
+    }
+
+    /// Whether a tally meets both the quorum and the approval threshold.
+    pub fn is_approved(&self, tally: &VoteTally) -> bool {
+        tally.total > 0.0 && tally.participation() >= self.quorum && tally.approval() >= self.threshold
+    }
+
+    /// Punishes provable misbehavior by `member_id`: `fraction` of its stake
+    /// and of the nominations backing it is slashed, and its reputation
+    /// penalized by the same fraction. Returns the stake taken.
+    pub fn slash(&mut self, member_id: &str, fraction: f64, reason: &str) -> Result<f64, String> {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let reputation = self.get_reputation(member_id).ok_or_else(|| format!("Member not found: {}", member_id))?;
+        self.reputation.penalize(member_id, reputation * fraction, reason)?;
+        let taken = self.stakes.slash(member_id, fraction) + self.nominations.slash(member_id, fraction);
+        warn!("Slashed {} of the stake of {}: {}", taken, member_id, reason);
+        Ok(taken)
+    }
+
+    /// Forgets the votes on `subject` once it is decided.
+    pub fn clear_votes(&mut self, subject: &str) {
+        self.votes.remove(subject);
     }
 }
 
@@ -23,4 +273,64 @@ impl PoCConsensus {
 pub struct Member {
     pub id: String,
     pub is_validator: bool,
+    /// Key of the member's signed votes, once registered.
+    #[serde(default)]
+    pub public_key: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reputation_update() {
+        let mut consensus = PoCConsensus::new(0.5, 0.66);
+        consensus.add_member("Alice".to_string(), true);
+        consensus.update_reputation("Alice", 0.5).unwrap();
+        assert_eq!(consensus.get_reputation("Alice"), Some(1.5));
+        assert_eq!(consensus.voting_weight("Alice"), 1.5);
+        assert!(consensus.update_reputation("Bob", 0.5).is_err());
+    }
+
+    #[test]
+    fn test_weighted_votes_need_quorum_and_threshold() {
+        let mut consensus = PoCConsensus::new(0.5, 0.66);
+        for (id, is_validator) in [("Alice", true), ("Bob", true), ("Carol", true), ("Dave", false)] {
+            consensus.add_member(id.to_string(), is_validator);
+        }
+        consensus.update_reputation("Alice", 1.0).unwrap();
+        assert!(consensus.vote("block", "Dave", true).is_err());
+
+        consensus.vote("block", "Alice", true).unwrap();
+        let tally = consensus.tally("block");
+        assert_eq!((tally.approve, tally.total), (2.0, 4.0));
+        assert!(!consensus.is_approved(&tally), "half the weight voted, short of the quorum");
+
+        consensus.vote("block", "Bob", false).unwrap();
+        assert!(consensus.is_approved(&consensus.tally("block")));
+        consensus.vote("block", "Alice", false).unwrap();
+        assert!(!consensus.is_approved(&consensus.tally("block")));
+
+        let approvals = ["Bob", "Carol", "Carol", "Dave"].map(String::from);
+        assert_eq!(consensus.tally_approvals(&approvals).approve, 2.0);
+    }
+
+    #[test]
+    fn test_stake_required_and_slashed() {
+        let requirement = StakeRequirement { bonding_period: std::time::Duration::ZERO, ..StakeRequirement::default() };
+        let mut consensus = PoCConsensus::new(0.5, 0.5).with_stake_requirement(requirement);
+        consensus.add_member("Alice".to_string(), true);
+        consensus.add_member("Bob".to_string(), true);
+        consensus.stakes.bond("Alice", 200.0, chrono::Utc::now()).unwrap();
+
+        assert!(consensus.vote("block", "Bob", false).is_err());
+        consensus.vote("block", "Alice", true).unwrap();
+        let tally = consensus.tally("block");
+        assert_eq!((tally.approve, tally.total), (1.0, 1.0), "only staked validators count");
+
+        assert_eq!(consensus.slash("Alice", 0.6, "Double vote"), Ok(120.0));
+        assert!((consensus.get_reputation("Alice").unwrap() - 0.4).abs() < 1e-9);
+        assert!(!consensus.is_active_validator("Alice"));
+        assert_eq!(consensus.tally("block").total, 0.0);
+    }
 }
@@ -0,0 +1,216 @@
+// src/consensus/nomination.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::currency::CurrencyType;
+
+/// The account block rewards are paid from.
+pub const REWARD_ACCOUNT: &str = "icn:rewards";
+/// The account nominated currency is held in until it is withdrawn.
+pub const NOMINATION_ACCOUNT: &str = "icn:nominations";
+
+/// How validators are rewarded for the blocks they create.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RewardConfig {
+    pub currency: CurrencyType,
+    /// Earned by a validator for each block it creates.
+    pub block_reward: f64,
+    /// Share of its rewards a validator keeps before splitting the rest with
+    /// its nominators.
+    pub commission: f64,
+    /// Blocks per epoch; rewards are paid out at the end of each.
+    pub epoch_length: u64,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig {
+            currency: CurrencyType::BasicNeeds,
+            block_reward: 10.0,
+            commission: 0.1,
+            epoch_length: 100,
+        }
+    }
+}
+
+/// Currency a member has put behind a validator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Nomination {
+    pub nominator: String,
+    pub validator: String,
+    pub amount: f64,
+}
+
+/// Nominations of validators by members who do not validate themselves.
+/// Nominators share in the rewards of the validators they back, in
+/// proportion to what they put in, and lose the same fraction when a
+/// validator is slashed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NominationLedger {
+    rewards: Option<RewardConfig>,
+    /// Nominated amounts, by validator and then by nominator.
+    nominations: BTreeMap<String, BTreeMap<String, f64>>,
+    /// Rewards earned by each validator in the current epoch.
+    earned: BTreeMap<String, f64>,
+}
+
+impl NominationLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rewards(mut self, rewards: RewardConfig) -> Self {
+        self.rewards = Some(rewards);
+        self
+    }
+
+    pub fn rewards(&self) -> Option<&RewardConfig> {
+        self.rewards.as_ref()
+    }
+
+    pub fn nominate(&mut self, nominator: &str, validator: &str, amount: f64) -> Result<(), String> {
+        if amount <= 0.0 {
+            return Err("Nominated amount must be positive".to_string());
+        }
+        if nominator == validator {
+            return Err(format!("{} cannot nominate itself", nominator));
+        }
+        *self.nominations.entry(validator.to_string()).or_default().entry(nominator.to_string()).or_insert(0.0) += amount;
+        info!("{} nominated {} with {}", nominator, validator, amount);
+        Ok(())
+    }
+
+    /// Takes `amount` of the nomination of `validator` by `nominator` back.
+    pub fn withdraw(&mut self, nominator: &str, validator: &str, amount: f64) -> Result<(), String> {
+        let nominated = self.nominated(nominator, validator);
+        if amount <= 0.0 || amount > nominated {
+            return Err(format!("{} has nominated {} with {}, cannot withdraw {}", nominator, validator, nominated, amount));
+        }
+        let nominators = self.nominations.get_mut(validator).expect("nomination exists");
+        if amount == nominated {
+            nominators.remove(nominator);
+            if nominators.is_empty() {
+                self.nominations.remove(validator);
+            }
+        } else {
+            *nominators.get_mut(nominator).expect("nomination exists") -= amount;
+        }
+        Ok(())
+    }
+
+    pub fn nominated(&self, nominator: &str, validator: &str) -> f64 {
+        self.nominations.get(validator).and_then(|nominators| nominators.get(nominator)).copied().unwrap_or(0.0)
+    }
+
+    /// The validators `nominator` backs.
+    pub fn nominations_of(&self, nominator: &str) -> Vec<Nomination> {
+        self.nominations.iter()
+            .filter_map(|(validator, nominators)| nominators.get(nominator).map(|amount| Nomination {
+                nominator: nominator.to_string(),
+                validator: validator.clone(),
+                amount: *amount,
+            }))
+            .collect()
+    }
+
+    /// The members backing `validator`.
+    pub fn nominators_of(&self, validator: &str) -> Vec<Nomination> {
+        self.nominations.get(validator).into_iter().flatten()
+            .map(|(nominator, amount)| Nomination {
+                nominator: nominator.clone(),
+                validator: validator.to_string(),
+                amount: *amount,
+            })
+            .collect()
+    }
+
+    /// Everything `nominator` has nominated.
+    pub fn locked(&self, nominator: &str) -> f64 {
+        self.nominations.values().filter_map(|nominators| nominators.get(nominator)).sum()
+    }
+
+    /// Credits `validator` with the reward for a block, if rewards are on.
+    pub fn credit_block(&mut self, validator: &str) {
+        if let Some(rewards) = &self.rewards {
+            *self.earned.entry(validator.to_string()).or_insert(0.0) += rewards.block_reward;
+        }
+    }
+
+    pub fn earned(&self, validator: &str) -> f64 {
+        self.earned.get(validator).copied().unwrap_or(0.0)
+    }
+
+    /// Whether the block at `index` ends an epoch.
+    pub fn is_epoch_end(&self, index: u64) -> bool {
+        self.rewards.as_ref().is_some_and(|rewards| rewards.epoch_length > 0 && index > 0 && index.is_multiple_of(rewards.epoch_length))
+    }
+
+    /// Splits the rewards earned this epoch and starts the next one. Each
+    /// validator keeps its commission; the rest is shared between its own
+    /// stake, as told by `own_stake`, and its nominations, pro rata. Returns
+    /// the payout of each member.
+    pub fn end_epoch(&mut self, own_stake: impl Fn(&str) -> f64) -> Vec<(String, f64)> {
+        let commission = self.rewards.as_ref().map_or(0.0, |rewards| rewards.commission.clamp(0.0, 1.0));
+        let mut payouts: BTreeMap<String, f64> = BTreeMap::new();
+        for (validator, earned) in std::mem::take(&mut self.earned) {
+            let stake = own_stake(&validator);
+            let nominators = self.nominators_of(&validator);
+            let total = stake + nominators.iter().map(|nomination| nomination.amount).sum::<f64>();
+            if total <= 0.0 {
+                *payouts.entry(validator).or_insert(0.0) += earned;
+                continue;
+            }
+            let shared = earned * (1.0 - commission);
+            *payouts.entry(validator.clone()).or_insert(0.0) += earned * commission + shared * stake / total;
+            for nomination in nominators {
+                *payouts.entry(nomination.nominator).or_insert(0.0) += shared * nomination.amount / total;
+            }
+        }
+        payouts.into_iter().filter(|(_, amount)| *amount > 0.0).collect()
+    }
+
+    /// Takes `fraction` of every nomination of `validator`, returning the
+    /// amount taken.
+    pub fn slash(&mut self, validator: &str, fraction: f64) -> f64 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let mut taken = 0.0;
+        for amount in self.nominations.get_mut(validator).into_iter().flat_map(|nominators| nominators.values_mut()) {
+            taken += *amount * fraction;
+            *amount *= 1.0 - fraction;
+        }
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewards_and_slashing_shared_pro_rata() {
+        let mut ledger = NominationLedger::new()
+            .with_rewards(RewardConfig { block_reward: 10.0, commission: 0.2, epoch_length: 2, ..RewardConfig::default() });
+        ledger.nominate("Bob", "Alice", 100.0).unwrap();
+        ledger.nominate("Carol", "Alice", 200.0).unwrap();
+        ledger.nominate("Carol", "Dave", 50.0).unwrap();
+        assert!(ledger.nominate("Alice", "Alice", 1.0).is_err());
+        assert_eq!(ledger.locked("Carol"), 250.0);
+        assert_eq!(ledger.nominations_of("Carol").len(), 2);
+
+        ledger.credit_block("Alice");
+        ledger.credit_block("Alice");
+        assert!(ledger.is_epoch_end(2) && !ledger.is_epoch_end(3));
+        // Alice keeps 4 of the 20 and shares 16 with 100 of her own stake
+        let payouts: BTreeMap<String, f64> = ledger.end_epoch(|validator| if validator == "Alice" { 100.0 } else { 0.0 }).into_iter().collect();
+        assert_eq!(payouts["Alice"], 8.0);
+        assert_eq!(payouts["Bob"], 4.0);
+        assert_eq!(payouts["Carol"], 8.0);
+        assert_eq!(ledger.earned("Alice"), 0.0);
+
+        assert_eq!(ledger.slash("Alice", 0.5), 150.0);
+        assert_eq!(ledger.nominated("Carol", "Alice"), 100.0);
+        assert!(ledger.withdraw("Bob", "Alice", 60.0).is_err());
+        ledger.withdraw("Bob", "Alice", 50.0).unwrap();
+        assert_eq!(ledger.nominators_of("Alice").len(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+// src/consensus/staking.rs
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+
+/// The account stake transactions are addressed to. Stake stays in the
+/// balance of its validator, locked until withdrawn.
+pub const STAKE_ACCOUNT: &str = "icn:stake";
+
+/// What a stake transaction does with the stake of its sender, who signs it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum StakeAction {
+    /// Locks `amount` of the staking currency out of the sender's spendable
+    /// balance as stake.
+    Bond { amount: f64 },
+    /// Starts unbonding `amount` of the stake that counts.
+    Unbond { amount: f64 },
+    /// Unlocks the unbonded stake whose unbonding period is over.
+    Withdraw,
+}
+
+/// The stake a validator must have bonded for its votes to count.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StakeRequirement {
+    pub currency: CurrencyType,
+    pub minimum: f64,
+    /// How long bonded currency waits before it counts as stake.
+    pub bonding_period: Duration,
+    /// How long unbonded currency stays slashable before it can be withdrawn.
+    pub unbonding_period: Duration,
+}
+
+impl Default for StakeRequirement {
+    fn default() -> Self {
+        StakeRequirement {
+            currency: CurrencyType::BasicNeeds,
+            minimum: 100.0,
+            bonding_period: Duration::from_secs(24 * 3600),
+            unbonding_period: Duration::from_secs(7 * 24 * 3600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Stake {
+    active: f64,
+    /// Amounts bonded and when they start to count.
+    bonding: Vec<(f64, DateTime<Utc>)>,
+    /// Amounts unbonded and when they can be withdrawn.
+    unbonding: Vec<(f64, DateTime<Utc>)>,
+}
+
+impl Stake {
+    fn total(&self) -> f64 {
+        self.active + self.bonding.iter().chain(&self.unbonding).map(|(amount, _)| amount).sum::<f64>()
+    }
+
+    /// Counts the bonded amounts whose bonding period is over as active.
+    fn mature(&mut self, now: DateTime<Utc>) {
+        let (matured, bonding): (Vec<_>, Vec<_>) = self.bonding.drain(..).partition(|(_, at)| *at <= now);
+        self.active += matured.iter().map(|(amount, _)| amount).sum::<f64>();
+        self.bonding = bonding;
+    }
+}
+
+/// A member's stake as reported to queries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StakeInfo {
+    /// Counts toward the requirement.
+    pub bonded: f64,
+    /// Still in its bonding period.
+    pub bonding: f64,
+    /// In its unbonding period.
+    pub unbonding: f64,
+    /// Unbonded and ready to withdraw.
+    pub withdrawable: f64,
+}
+
+/// Currency bonded by validators. Without a requirement staking is off and
+/// every validator's votes count; with one, only validators with at least
+/// the minimum bonded. Stake, bonded or unbonding, can be slashed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StakeLedger {
+    requirement: Option<StakeRequirement>,
+    stakes: BTreeMap<String, Stake>,
+    /// Everything slashed so far.
+    slashed: f64,
+    #[serde(skip)]
+    clock: SharedClock,
+}
+
+impl StakeLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_requirement(mut self, requirement: StakeRequirement) -> Self {
+        self.requirement = Some(requirement);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn requirement(&self) -> Option<&StakeRequirement> {
+        self.requirement.as_ref()
+    }
+
+    fn enabled(&self) -> Result<&StakeRequirement, String> {
+        self.requirement.as_ref().ok_or_else(|| "Staking is not enabled".to_string())
+    }
+
+    /// Bonds `amount` for `member_id` at `now`, returning when it starts to
+    /// count.
+    pub fn bond(&mut self, member_id: &str, amount: f64, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        let counts_at = now + chrono::Duration::from_std(self.enabled()?.bonding_period).map_err(|e| e.to_string())?;
+        if amount <= 0.0 {
+            return Err("Bonded amount must be positive".to_string());
+        }
+        self.stakes.entry(member_id.to_string()).or_default().bonding.push((amount, counts_at));
+        info!("{} bonded {}, counting from {}", member_id, amount, counts_at);
+        Ok(counts_at)
+    }
+
+    /// Starts unbonding `amount` of the stake that counts at `now`, returning
+    /// when it can be withdrawn.
+    pub fn unbond(&mut self, member_id: &str, amount: f64, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        let withdrawable_at = now + chrono::Duration::from_std(self.enabled()?.unbonding_period).map_err(|e| e.to_string())?;
+        let stake = self.stakes.get_mut(member_id).ok_or_else(|| format!("{} has no stake", member_id))?;
+        stake.mature(now);
+        if amount <= 0.0 || amount > stake.active {
+            return Err(format!("{} has {} bonded, cannot unbond {}", member_id, stake.active, amount));
+        }
+        stake.active -= amount;
+        stake.unbonding.push((amount, withdrawable_at));
+        info!("{} unbonding {}, withdrawable from {}", member_id, amount, withdrawable_at);
+        Ok(withdrawable_at)
+    }
+
+    /// Releases the unbonded amounts whose unbonding period is over at
+    /// `now`, returning their sum.
+    pub fn withdraw(&mut self, member_id: &str, now: DateTime<Utc>) -> f64 {
+        let stake = match self.stakes.get_mut(member_id) {
+            Some(stake) => stake,
+            None => return 0.0,
+        };
+        let (released, unbonding): (Vec<_>, Vec<_>) = stake.unbonding.drain(..).partition(|(_, at)| *at <= now);
+        stake.unbonding = unbonding;
+        if stake.total() <= 0.0 {
+            self.stakes.remove(member_id);
+        }
+        released.iter().map(|(amount, _)| amount).sum()
+    }
+
+    pub fn info(&self, member_id: &str) -> StakeInfo {
+        let now = self.clock.now();
+        let mut info = StakeInfo::default();
+        if let Some(stake) = self.stakes.get(member_id) {
+            info.bonded = stake.active;
+            for (amount, at) in &stake.bonding {
+                if *at <= now { info.bonded += amount } else { info.bonding += amount }
+            }
+            for (amount, at) in &stake.unbonding {
+                if *at <= now { info.withdrawable += amount } else { info.unbonding += amount }
+            }
+        }
+        info
+    }
+
+    /// The stake of `member_id` that counts toward the requirement.
+    pub fn bonded(&self, member_id: &str) -> f64 {
+        self.info(member_id).bonded
+    }
+
+    /// Everything `member_id` has bonded and not yet withdrawn.
+    pub fn locked(&self, member_id: &str) -> f64 {
+        self.stakes.get(member_id).map_or(0.0, Stake::total)
+    }
+
+    /// The members with stake bonded and not yet withdrawn, in order.
+    pub fn stakers(&self) -> impl Iterator<Item = &str> {
+        self.stakes.iter().filter(|(_, stake)| stake.total() > 0.0).map(|(member_id, _)| member_id.as_str())
+    }
+
+    pub fn total_bonded(&self) -> f64 {
+        self.stakes.keys().map(|member_id| self.bonded(member_id)).sum()
+    }
+
+    pub fn total_slashed(&self) -> f64 {
+        self.slashed
+    }
+
+    /// Whether the votes of `member_id` count: always when staking is off.
+    pub fn meets_requirement(&self, member_id: &str) -> bool {
+        self.requirement.as_ref().is_none_or(|requirement| self.bonded(member_id) >= requirement.minimum)
+    }
+
+    /// Takes `fraction` of everything `member_id` has at stake, unbonding
+    /// amounts included, and returns the amount taken.
+    pub fn slash(&mut self, member_id: &str, fraction: f64) -> f64 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let stake = match self.stakes.get_mut(member_id) {
+            Some(stake) => stake,
+            None => return 0.0,
+        };
+        let taken = stake.total() * fraction;
+        stake.active *= 1.0 - fraction;
+        for (amount, _) in stake.bonding.iter_mut().chain(stake.unbonding.iter_mut()) {
+            *amount *= 1.0 - fraction;
+        }
+        self.slashed += taken;
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn test_bonding_and_unbonding_periods() {
+        let clock = MockClock::new();
+        let day = Duration::from_secs(24 * 3600);
+        let mut ledger = StakeLedger::new()
+            .with_requirement(StakeRequirement { minimum: 100.0, bonding_period: day, unbonding_period: day * 7, ..StakeRequirement::default() })
+            .with_clock(clock.clone().into());
+
+        ledger.bond("Alice", 150.0, clock.now()).unwrap();
+        assert!(!ledger.meets_requirement("Alice"));
+        assert_eq!(ledger.info("Alice"), StakeInfo { bonding: 150.0, ..StakeInfo::default() });
+        assert!(ledger.unbond("Alice", 50.0, clock.now()).is_err());
+
+        clock.advance(day);
+        assert!(ledger.meets_requirement("Alice"));
+        ledger.unbond("Alice", 60.0, clock.now()).unwrap();
+        assert!(!ledger.meets_requirement("Alice"));
+        assert_eq!(ledger.withdraw("Alice", clock.now()), 0.0);
+
+        // Unbonding stake can still be slashed
+        assert_eq!(ledger.slash("Alice", 0.5), 75.0);
+        clock.advance(day * 7);
+        assert_eq!(ledger.info("Alice"), StakeInfo { bonded: 45.0, withdrawable: 30.0, ..StakeInfo::default() });
+        assert_eq!(ledger.withdraw("Alice", clock.now()), 30.0);
+        assert_eq!(ledger.locked("Alice"), 45.0);
+        assert!(StakeLedger::new().bond("Alice", 1.0, clock.now()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// src/consensus/vote.rs
+use std::collections::BTreeMap;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Serialize, Deserialize};
+
+/// Share of its stake and reputation a validator loses for equivocating.
+pub const EQUIVOCATION_SLASH_FRACTION: f64 = 0.5;
+
+/// A validator's vote on the block at a height, signed with its key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedVote {
+    pub member_id: String,
+    pub block_hash: String,
+    pub height: u64,
+    pub approve: bool,
+    pub signature: Vec<u8>,
+}
+
+impl SignedVote {
+    pub fn new(member_id: String, block_hash: String, height: u64, approve: bool, keypair: &Keypair) -> Self {
+        let mut vote = SignedVote { member_id, block_hash, height, approve, signature: Vec::new() };
+        vote.signature = keypair.sign(&vote.signing_payload()).to_bytes().to_vec();
+        vote
+    }
+
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(self.member_id.as_bytes());
+        bytes.extend_from_slice(self.block_hash.as_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.push(self.approve as u8);
+        bytes
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        Signature::from_bytes(&self.signature)
+            .is_ok_and(|signature| public_key.verify(&self.signing_payload(), &signature).is_ok())
+    }
+
+    /// Whether the two votes approve different blocks at the same height,
+    /// which an honest validator never does.
+    pub fn conflicts_with(&self, other: &SignedVote) -> bool {
+        self.member_id == other.member_id
+            && self.height == other.height
+            && self.block_hash != other.block_hash
+            && self.approve
+            && other.approve
+    }
+}
+
+/// Two conflicting votes signed by the same validator. Anyone holding its
+/// key can check the proof, so it can be passed on and acted upon by nodes
+/// that saw neither vote.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub first: SignedVote,
+    pub second: SignedVote,
+}
+
+impl Equivocation {
+    pub fn member_id(&self) -> &str {
+        &self.first.member_id
+    }
+
+    pub fn height(&self) -> u64 {
+        self.first.height
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> Result<(), String> {
+        if !self.first.conflicts_with(&self.second) {
+            return Err("Votes do not conflict".to_string());
+        }
+        if !self.first.verify(public_key) || !self.second.verify(public_key) {
+            return Err(format!("Votes are not signed by {}", self.member_id()));
+        }
+        Ok(())
+    }
+}
+
+/// The signed votes seen, by validator, and the equivocations found among
+/// them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VoteLog {
+    votes: BTreeMap<String, Vec<SignedVote>>,
+    equivocations: Vec<Equivocation>,
+}
+
+impl VoteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `vote`, returning the proof if it conflicts with one stored
+    /// before. Votes already seen are not stored again.
+    pub fn record(&mut self, vote: &SignedVote) -> Option<Equivocation> {
+        let seen = self.votes.entry(vote.member_id.clone()).or_default();
+        if seen.contains(vote) {
+            return None;
+        }
+        let conflict = seen.iter().find(|earlier| earlier.conflicts_with(vote)).cloned();
+        seen.push(vote.clone());
+        conflict.map(|first| Equivocation { first, second: vote.clone() })
+    }
+
+    /// The votes `member_id` signed at `height`.
+    pub fn votes(&self, member_id: &str, height: u64) -> Vec<&SignedVote> {
+        self.votes.get(member_id).into_iter().flatten().filter(|vote| vote.height == height).collect()
+    }
+
+    /// Whether `member_id` has already been punished for equivocating at
+    /// `height`.
+    pub fn is_punished(&self, member_id: &str, height: u64) -> bool {
+        self.equivocations.iter().any(|proof| proof.member_id() == member_id && proof.height() == height)
+    }
+
+    pub fn add_equivocation(&mut self, proof: Equivocation) {
+        self.equivocations.push(proof);
+    }
+
+    pub fn equivocations(&self) -> &[Equivocation] {
+        &self.equivocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_conflicting_votes_detected() {
+        let keypair = Keypair::generate(&mut OsRng {});
+        let vote = |hash: &str, height, approve| SignedVote::new("Alice".to_string(), hash.to_string(), height, approve, &keypair);
+        let mut log = VoteLog::new();
+        assert!(log.record(&vote("a", 1, true)).is_none());
+        assert!(log.record(&vote("a", 1, true)).is_none());
+        assert!(log.record(&vote("b", 1, false)).is_none(), "rejecting a competing block is fine");
+        assert!(log.record(&vote("c", 2, true)).is_none());
+        let proof = log.record(&vote("b", 1, true)).unwrap();
+        assert_eq!(proof.verify(&keypair.public), Ok(()));
+        assert_eq!(log.votes("Alice", 1).len(), 3);
+
+        let mut forged = proof.clone();
+        forged.second.block_hash = "d".to_string();
+        assert!(forged.verify(&keypair.public).is_err());
+        assert!(proof.verify(&Keypair::generate(&mut OsRng {}).public).is_err());
+    }
+}
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
+use crate::clock::SharedClock;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum CurrencyType {
@@ -20,6 +22,17 @@ pub enum CurrencyType {
     Bond(String),
 }
 
+impl CurrencyType {
+    /// The currencies every network has, as opposed to those created on it.
+    pub fn standard() -> [CurrencyType; 10] {
+        [
+            CurrencyType::BasicNeeds, CurrencyType::Education, CurrencyType::Environmental, CurrencyType::Community,
+            CurrencyType::Volunteer, CurrencyType::Storage, CurrencyType::Processing, CurrencyType::Energy,
+            CurrencyType::Luxury, CurrencyType::Service,
+        ]
+    }
+}
+
 impl fmt::Display for CurrencyType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -29,6 +42,27 @@ impl fmt::Display for CurrencyType {
     }
 }
 
+/// Reads the names `Display` and `Debug` write, ignoring case, so that
+/// `basicneeds`, `Custom(hours)` and `Bond(b-1)` are all understood.
+impl FromStr for CurrencyType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if let Some((kind, inner)) = name.strip_suffix(')').and_then(|name| name.split_once('(')) {
+            let inner = inner.trim_matches('"').to_string();
+            return match kind.to_lowercase().as_str() {
+                "custom" => Ok(CurrencyType::Custom(inner)),
+                "assettoken" => Ok(CurrencyType::AssetToken(inner)),
+                "bond" => Ok(CurrencyType::Bond(inner)),
+                _ => Err(format!("Unknown currency {}", name)),
+            };
+        }
+        CurrencyType::standard().into_iter()
+            .find(|currency| currency.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown currency {}", name))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Currency {
     pub currency_type: CurrencyType,
@@ -41,7 +75,11 @@ pub struct Currency {
 impl Currency {
     #[allow(dead_code)]
     pub fn new(currency_type: CurrencyType, initial_supply: f64, issuance_rate: f64) -> Self {
-        let now = Utc::now();
+        Self::created_at(currency_type, initial_supply, issuance_rate, Utc::now())
+    }
+
+    #[allow(dead_code)]
+    pub fn created_at(currency_type: CurrencyType, initial_supply: f64, issuance_rate: f64, now: DateTime<Utc>) -> Self {
         Currency {
             currency_type,
             total_supply: initial_supply,
@@ -53,23 +91,37 @@ impl Currency {
 
     #[allow(dead_code)]
     pub fn mint(&mut self, amount: f64) {
+        self.mint_at(amount, Utc::now());
+    }
+
+    #[allow(dead_code)]
+    pub fn mint_at(&mut self, amount: f64, now: DateTime<Utc>) {
         self.total_supply += amount;
-        self.last_issuance = Utc::now();
+        self.last_issuance = now;
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CurrencySystem {
     pub currencies: HashMap<CurrencyType, Currency>,
+    #[serde(skip)]
+    clock: SharedClock,
 }
 
 impl CurrencySystem {
     #[allow(dead_code)]
     pub fn new() -> Self {
+        CurrencySystem::with_clock(SharedClock::default())
+    }
+
+    /// A system issuing currency for the time passed on `clock`.
+    #[allow(dead_code)]
+    pub fn with_clock(clock: SharedClock) -> Self {
         let mut system = CurrencySystem {
             currencies: HashMap::new(),
+            clock,
         };
-        
+
         system.add_currency(CurrencyType::BasicNeeds, 1_000_000.0, 0.01);
         system.add_currency(CurrencyType::Education, 500_000.0, 0.005);
         system.add_currency(CurrencyType::Environmental, 750_000.0, 0.008);
@@ -86,7 +138,7 @@ impl CurrencySystem {
 
     #[allow(dead_code)]
     pub fn add_currency(&mut self, currency_type: CurrencyType, initial_supply: f64, issuance_rate: f64) {
-        let currency = Currency::new(currency_type.clone(), initial_supply, issuance_rate);
+        let currency = Currency::created_at(currency_type.clone(), initial_supply, issuance_rate, self.clock.now());
         self.currencies.insert(currency_type, currency);
     }
 
@@ -112,11 +164,11 @@ impl CurrencySystem {
 
     #[allow(dead_code)]
     pub fn adaptive_issuance(&mut self) {
-        let now = Utc::now();
+        let now = self.clock.now();
         for currency in self.currencies.values_mut() {
             let time_since_last_issuance = now.signed_duration_since(currency.last_issuance);
             let issuance_amount = currency.total_supply * currency.issuance_rate * time_since_last_issuance.num_milliseconds() as f64 / 86_400_000.0; // Daily rate
-            currency.mint(issuance_amount);
+            currency.mint_at(issuance_amount, now);
         }
     }
 }
@@ -153,4 +205,25 @@ impl Wallet {
     pub fn get_balance(&self, currency_type: &CurrencyType) -> f64 {
         *self.balances.get(currency_type).unwrap_or(&0.0)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use std::time::Duration;
+
+    #[test]
+    fn test_adaptive_issuance_follows_clock() {
+        let clock = MockClock::new();
+        let mut system = CurrencySystem::with_clock(clock.clone().into());
+        system.adaptive_issuance();
+        assert_eq!(system.get_currency(&CurrencyType::BasicNeeds).unwrap().total_supply, 1_000_000.0);
+
+        clock.advance(Duration::from_secs(86_400));
+        system.adaptive_issuance();
+        let currency = system.get_currency(&CurrencyType::BasicNeeds).unwrap();
+        assert_eq!(currency.total_supply, 1_010_000.0);
+        assert_eq!(currency.last_issuance, clock.now());
+    }
+}
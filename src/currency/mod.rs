@@ -1,3 +1,5 @@
 mod currency;
+pub mod ubi;
 
-pub use self::currency::CurrencyType;
\ No newline at end of file
+pub use self::currency::CurrencyType;
+pub use self::ubi::{Ubi, UbiConfig};
@@ -0,0 +1,136 @@
+// src/currency/ubi.rs
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Keypair;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::blockchain::{Blockchain, Transaction};
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::identity::PersonhoodRegistry;
+use crate::wallet::address_of;
+
+/// Gas limit of the payments of basic income.
+const UBI_GAS_LIMIT: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UbiConfig {
+    /// What each person is paid per period.
+    pub amount: f64,
+    pub currency_type: CurrencyType,
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
+
+impl Default for UbiConfig {
+    fn default() -> Self {
+        UbiConfig {
+            amount: 10.0,
+            currency_type: CurrencyType::BasicNeeds,
+            period: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Pays a basic income to every DID attested to belong to a unique human,
+/// once per period; a person with several DIDs is paid once. The income is
+/// paid from an ordinary account the community funds, whose key signs the
+/// payments.
+#[derive(Debug, Default)]
+pub struct Ubi {
+    config: UbiConfig,
+    account: Option<Keypair>,
+    /// Time of the last payment to each DID.
+    last_paid: HashMap<String, DateTime<Utc>>,
+    clock: SharedClock,
+}
+
+impl Ubi {
+    pub fn new(config: UbiConfig) -> Self {
+        Ubi { config, ..Self::default() }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Pays the income from the account of `keypair`.
+    pub fn with_account(mut self, keypair: Keypair) -> Self {
+        self.account = Some(keypair);
+        self
+    }
+
+    /// The address the income is paid from, if there is an account.
+    pub fn address(&self) -> Option<String> {
+        self.account.as_ref().map(|keypair| address_of(&keypair.public))
+    }
+
+    pub fn config(&self) -> &UbiConfig {
+        &self.config
+    }
+
+    /// When `did` may next be paid, or None if it may now.
+    pub fn next_payment(&self, did: &str) -> Option<DateTime<Utc>> {
+        let period = chrono::Duration::from_std(self.config.period).expect("period out of range");
+        self.last_paid.get(did).map(|last| *last + period).filter(|next| *next > self.clock.now())
+    }
+
+    /// Queues this period's payment to `did` on `blockchain` and returns it.
+    /// Refused unless `personhood` attests that `did` is a unique human.
+    pub fn issue(&mut self, blockchain: &mut Blockchain, did: &str, personhood: &PersonhoodRegistry) -> Result<Transaction> {
+        let keypair = self.account.as_ref().ok_or_else(|| Error::UbiError("Basic income has no account to be paid from".to_string()))?;
+        personhood.check(did, &blockchain.revocation_registry).map_err(Error::IdentityError)?;
+        if let Some(next) = self.next_payment(did) {
+            return Err(Error::UbiError(format!("{} was paid this period; the next payment is due {}", did, next.to_rfc3339())));
+        }
+        let mut payment = Transaction::new(address_of(&keypair.public), did.to_string(), self.config.amount, self.config.currency_type.clone(), UBI_GAS_LIMIT)
+            .on_network(&blockchain.spec.network_id);
+        payment.sign(keypair).map_err(Error::UbiError)?;
+        blockchain.add_transaction(payment.clone())?;
+        self.last_paid.insert(did.to_string(), self.clock.now());
+        info!("Paid {} {} basic income to {}", self.config.amount, self.config.currency_type, did);
+        Ok(payment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use crate::identity::{Attestation, DecentralizedIdentity, DidManager};
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_ubi_is_paid_to_persons_once_per_period() {
+        let clock = MockClock::new();
+        let mut ubi = Ubi::new(UbiConfig::default()).with_clock(clock.clone().into()).with_account(Keypair::generate(&mut OsRng {}));
+        let address = ubi.address().unwrap();
+        let mut personhood = PersonhoodRegistry::new(2, chrono::Duration::days(365)).with_clock(clock.clone().into());
+        let [verifier, alice] = std::array::from_fn(|_| Keypair::generate(&mut OsRng {}));
+        let alice_did = address_of(&alice.public);
+        personhood.add_verifier(&DecentralizedIdentity::from_public_key(verifier.public, HashMap::new()).id);
+        personhood.attest(Attestation::new(&alice_did, &verifier, clock.now()), &DidManager::new()).unwrap();
+        let mut blockchain = Blockchain::with_spec(crate::blockchain::ChainSpec::default().with_allocation(&address, 100.0, CurrencyType::BasicNeeds));
+
+        assert_eq!(ubi.issue(&mut blockchain, "did:icn:sybil", &personhood).unwrap_err().code(), 600);
+        ubi.issue(&mut blockchain, &alice_did, &personhood).unwrap();
+        assert_eq!(ubi.issue(&mut blockchain, &alice_did, &personhood).unwrap_err().code(), 1700);
+        blockchain.create_block("Miner1".to_string()).unwrap();
+        assert_eq!(blockchain.get_currency_balance(&alice_did, &CurrencyType::BasicNeeds), 10.0);
+        assert_eq!(blockchain.get_currency_balance(&address, &CurrencyType::BasicNeeds), 90.0);
+
+        clock.advance(Duration::from_secs(7 * 24 * 60 * 60 + 1));
+        assert!(ubi.issue(&mut blockchain, &alice_did, &personhood).is_ok());
+        let revocation = crate::identity::RevocationAction::RevokeDid { did_id: alice_did.clone(), reason: "duplicate".to_string() };
+        let mut revoke = Transaction::revoke(alice_did.clone(), revocation, 1000);
+        revoke.sign(&alice).unwrap();
+        blockchain.add_transaction(revoke).unwrap();
+        blockchain.create_block("Miner1".to_string()).unwrap();
+        clock.advance(Duration::from_secs(7 * 24 * 60 * 60 + 1));
+        assert!(ubi.issue(&mut blockchain, &alice_did, &personhood).is_err(), "revoked DIDs are not paid");
+    }
+}
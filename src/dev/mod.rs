@@ -0,0 +1,129 @@
+// src/dev/mod.rs
+//! Development mode: a single-node chain that seals a block for every
+//! transaction submitted, with checks that only get in the way of writing
+//! contracts turned off, and accounts funded from the start. The accounts'
+//! keys are derived from fixed seeds, so they are the same on every run and
+//! must never hold anything of value.
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::blockchain::{Blockchain, ChainSpec};
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::wallet;
+
+/// The author of the blocks sealed in development mode.
+pub const DEV_ACCOUNT: &str = "icn:dev";
+
+/// What development mode changes; each check can be kept on by itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevConfig {
+    /// Seal a block as soon as a transaction is queued.
+    pub instant_seal: bool,
+    /// Accept block votes without checking their signatures against the
+    /// keys the validators registered.
+    pub skip_signature_checks: bool,
+    /// Treat every block as approved, however few validators voted.
+    pub skip_quorum: bool,
+    /// How many test accounts to fund.
+    pub accounts: usize,
+    /// What each test account starts with, in each standard currency.
+    pub funding: f64,
+}
+
+impl Default for DevConfig {
+    fn default() -> Self {
+        DevConfig {
+            instant_seal: true,
+            skip_signature_checks: true,
+            skip_quorum: true,
+            accounts: 10,
+            funding: 1_000_000.0,
+        }
+    }
+}
+
+/// A test account of development mode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevAccount {
+    pub name: String,
+    pub address: String,
+    /// Hex Ed25519 secret key, as `wallet import` takes it.
+    pub secret_key: String,
+}
+
+impl DevAccount {
+    pub fn keypair(&self) -> Result<Keypair> {
+        let bytes = hex::decode(&self.secret_key).map_err(|e| Error::WalletError(format!("Key {} is corrupt: {}", self.name, e)))?;
+        let secret = SecretKey::from_bytes(&bytes).map_err(|e| Error::WalletError(format!("Key {} is corrupt: {}", self.name, e)))?;
+        let public = PublicKey::from(&secret);
+        Ok(Keypair { secret, public })
+    }
+}
+
+/// The first `count` test accounts, `dev0`, `dev1`, ..., whose keys are the
+/// SHA-256 of their names.
+pub fn accounts(count: usize) -> Vec<DevAccount> {
+    (0..count).map(|index| {
+        let name = format!("dev{}", index);
+        let secret = SecretKey::from_bytes(&Sha256::digest(name.as_bytes())).expect("a SHA-256 digest is a valid secret key");
+        DevAccount {
+            address: wallet::address_of(&PublicKey::from(&secret)),
+            secret_key: hex::encode(secret.as_bytes()),
+            name,
+        }
+    }).collect()
+}
+
+/// A new chain in development mode. Its genesis block funds the test
+/// accounts, which are also its validators.
+pub fn genesis(config: DevConfig) -> Result<(Blockchain, Vec<DevAccount>)> {
+    let accounts = accounts(config.accounts);
+    let mut spec = ChainSpec::default();
+    for account in &accounts {
+        for currency in CurrencyType::standard() {
+            spec = spec.with_allocation(&account.address, config.funding, currency);
+        }
+    }
+    let mut blockchain = Blockchain::with_spec(spec);
+    for account in &accounts {
+        blockchain.consensus.add_member(account.address.clone(), true);
+        blockchain.consensus.register_key(&account.address, &account.keypair()?.public).map_err(Error::ConsensusError)?;
+    }
+    info!("Development chain funded {} accounts", accounts.len());
+    blockchain.dev = Some(config);
+    Ok((blockchain, accounts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Transaction;
+    use crate::consensus::SignedVote;
+
+    #[test]
+    fn test_dev_chain_seals_instantly_and_funds_accounts() {
+        let config = DevConfig { accounts: 2, ..DevConfig::default() };
+        let (mut blockchain, dev_accounts) = genesis(config).unwrap();
+        assert_eq!(dev_accounts, accounts(2), "the accounts are the same on every run");
+        assert_eq!(blockchain.get_currency_balance(&dev_accounts[0].address, &CurrencyType::Energy), 1_000_000.0);
+
+        let mut transfer = Transaction::new(dev_accounts[0].address.clone(), dev_accounts[1].address.clone(), 5.0, CurrencyType::Energy, 1000);
+        transfer.sign(&dev_accounts[0].keypair().unwrap()).unwrap();
+        blockchain.add_transaction(transfer).unwrap();
+        assert_eq!(blockchain.chain.len(), 2);
+        assert!(blockchain.pending_transactions.is_empty());
+        assert_eq!(blockchain.get_currency_balance(&dev_accounts[1].address, &CurrencyType::Energy), 1_000_005.0);
+
+        let hash = blockchain.chain[1].hash.clone();
+        assert!(blockchain.is_block_approved(&hash), "no quorum is needed");
+        let mut unsigned = SignedVote::new(dev_accounts[1].address.clone(), hash, 1, true, &dev_accounts[0].keypair().unwrap());
+        unsigned.signature.clear();
+        assert!(blockchain.submit_vote(&unsigned).is_ok());
+
+        blockchain.dev = Some(DevConfig { skip_signature_checks: false, ..DevConfig::default() });
+        assert!(blockchain.submit_vote(&unsigned).is_err());
+    }
+}
@@ -1,48 +1,104 @@
 // src/error.rs
 
-use std::fmt;
-use std::error::Error as StdError;
+use thiserror::Error;
+use crate::sharding::ShardingError;
+use crate::vm::VmError;
 
-#[derive(Debug)]
+/// Errors of every part of the node. Each kind has a stable `code`, reported
+/// by the API next to the message so clients need not parse the latter.
+#[derive(Error, Debug)]
 pub enum Error {
+    #[error("Blockchain error: {0}")]
     BlockchainError(String),
+    #[error("Consensus error: {0}")]
     ConsensusError(String),
+    #[error("Governance error: {0}")]
     GovernanceError(String),
-    ShardingError(String),
+    #[error("Sharding error: {0}")]
+    ShardingError(#[from] ShardingError),
+    #[error("Network error: {0}")]
     NetworkError(String),
+    #[error("Identity error: {0}")]
+    IdentityError(String),
+    #[error("Smart contract error: {0}")]
     SmartContractError(String),
-    VmError(String),
-    IoError(std::io::Error),
+    #[error("VM error: {0}")]
+    VmError(#[from] VmError),
+    /// Something asked for through the API does not exist.
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// The API was not given the subsystem a request needs.
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Storage error: {0}")]
+    StorageError(#[from] sled::Error),
+    #[error("Bridge error: {0}")]
+    BridgeError(String),
+    #[error("IPFS error: {0}")]
+    IpfsError(String),
+    #[error("Webhook error: {0}")]
+    WebhookError(String),
+    #[error("Wallet error: {0}")]
+    WalletError(String),
+    #[error("Faucet error: {0}")]
+    FaucetError(String),
+    #[error("UBI error: {0}")]
+    UbiError(String),
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Error {
+    /// The kind of error as a number: hundreds tell the subsystem, units
+    /// refine it where the subsystem has typed errors.
+    pub fn code(&self) -> u16 {
         match self {
-            Error::BlockchainError(msg) => write!(f, "Blockchain error: {}", msg),
-            Error::ConsensusError(msg) => write!(f, "Consensus error: {}", msg),
-            Error::GovernanceError(msg) => write!(f, "Governance error: {}", msg),
-            Error::ShardingError(msg) => write!(f, "Sharding error: {}", msg),
-            Error::NetworkError(msg) => write!(f, "Network error: {}", msg),
-            Error::SmartContractError(msg) => write!(f, "Smart contract error: {}", msg),
-            Error::VmError(msg) => write!(f, "VM error: {}", msg),
-            Error::IoError(e) => write!(f, "I/O error: {}", e),
+            Error::BlockchainError(_) => 100,
+            Error::ConsensusError(_) => 200,
+            Error::GovernanceError(_) => 300,
+            Error::ShardingError(e) => 400 + match e {
+                ShardingError::ShardNotFound(_) => 1,
+                ShardingError::InsufficientBalance(_) => 2,
+                ShardingError::InvalidTransaction(_) => 3,
+                ShardingError::ShardLockFailed(_) => 4,
+                ShardingError::CrossShardCommunicationError(_) => 5,
+                ShardingError::Rejected(_) => 0,
+            },
+            Error::NetworkError(_) => 500,
+            Error::IdentityError(_) => 600,
+            Error::SmartContractError(_) => 700,
+            Error::VmError(_) => 800,
+            Error::NotFound(_) => 900,
+            Error::Unavailable(_) => 901,
+            Error::IoError(_) => 1000,
+            Error::StorageError(_) => 1100,
+            Error::BridgeError(_) => 1200,
+            Error::IpfsError(_) => 1300,
+            Error::WebhookError(_) => 1400,
+            Error::WalletError(_) => 1500,
+            Error::FaucetError(_) => 1600,
+            Error::UbiError(_) => 1700,
         }
     }
 }
 
-impl StdError for Error {
-    fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        match self {
-            Error::IoError(e) => Some(e),
-            _ => None,
-        }
-    }
-}
+pub type Result<T> = std::result::Result<T, Error>;
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::IoError(err)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_keep_codes() {
+        let error: Error = ShardingError::ShardNotFound(7).into();
+        assert_eq!(error.code(), 401);
+        assert_eq!(error.to_string(), "Sharding error: Shard not found: 7");
+
+        let error: Error = VmError::DivisionByZero.into();
+        assert_eq!(error.code(), 800);
+        assert!(matches!(error, Error::VmError(VmError::DivisionByZero)));
+
+        let error: Error = std::io::Error::new(std::io::ErrorKind::NotFound, "gone").into();
+        assert_eq!(error.code(), 1000);
     }
 }
-
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
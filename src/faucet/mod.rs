@@ -0,0 +1,168 @@
+// src/faucet/mod.rs
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::Keypair;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+use crate::blockchain::{Blockchain, Transaction};
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::wallet::address_of;
+
+/// Gas limit of the transfers the faucet submits.
+const FAUCET_GAS_LIMIT: u64 = 1000;
+
+/// Set by operators of development networks; the faucet is off unless
+/// `enabled`, so that it never pays out on a production network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaucetConfig {
+    pub enabled: bool,
+    /// What one request is given, in each currency.
+    pub drip: Vec<(CurrencyType, f64)>,
+    /// How long a DID, and the IP address it asked from, wait between
+    /// requests.
+    #[serde(with = "humantime_serde")]
+    pub cooldown: Duration,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        FaucetConfig {
+            enabled: false,
+            drip: CurrencyType::standard().into_iter().map(|currency| (currency, 10.0)).collect(),
+            cooldown: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Hands out small amounts of each currency to developers trying the
+/// network, at most once per cooldown to each DID and to each IP address.
+/// The funds come from an ordinary account the operator funds, whose key
+/// the faucet signs its transfers with.
+#[derive(Debug, Default)]
+pub struct Faucet {
+    config: FaucetConfig,
+    account: Option<Keypair>,
+    /// Time of the last drip to each DID and each IP address.
+    by_did: HashMap<String, DateTime<Utc>>,
+    by_ip: HashMap<IpAddr, DateTime<Utc>>,
+    clock: SharedClock,
+}
+
+impl Faucet {
+    pub fn new(config: FaucetConfig) -> Self {
+        Faucet { config, ..Self::default() }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Pays the drips from the account of `keypair`.
+    pub fn with_account(mut self, keypair: Keypair) -> Self {
+        self.account = Some(keypair);
+        self
+    }
+
+    /// The address the drips are paid from, if the faucet has an account.
+    pub fn address(&self) -> Option<String> {
+        self.account.as_ref().map(|keypair| address_of(&keypair.public))
+    }
+
+    pub fn config(&self) -> &FaucetConfig {
+        &self.config
+    }
+
+    /// Changes the faucet's settings; cooldowns already running keep going.
+    pub fn set_config(&mut self, config: FaucetConfig) {
+        self.config = config;
+    }
+
+    /// When `did`, asking from `ip`, may next be given funds, or None if
+    /// it may now.
+    pub fn next_drip(&self, did: &str, ip: Option<IpAddr>) -> Option<DateTime<Utc>> {
+        let cooldown = chrono::Duration::from_std(self.config.cooldown).expect("cooldown out of range");
+        let last = [self.by_did.get(did), ip.and_then(|ip| self.by_ip.get(&ip))].into_iter().flatten().max()?;
+        Some(*last + cooldown).filter(|next| *next > self.clock.now())
+    }
+
+    /// Queues the transfers of one drip to `did` on `blockchain` and returns
+    /// them.
+    pub fn drip(&mut self, blockchain: &mut Blockchain, did: &str, ip: Option<IpAddr>) -> Result<Vec<Transaction>> {
+        if !self.config.enabled {
+            return Err(Error::FaucetError("The faucet is not enabled on this network".to_string()));
+        }
+        let keypair = self.account.as_ref().ok_or_else(|| Error::FaucetError("The faucet has no account to pay from".to_string()))?;
+        if !did.starts_with("did:") {
+            return Err(Error::FaucetError(format!("{} is not a DID", did)));
+        }
+        if let Some(next) = self.next_drip(did, ip) {
+            return Err(Error::FaucetError(format!("Funds were given recently; ask again after {}", next.to_rfc3339())));
+        }
+        let mut transactions = Vec::new();
+        for (currency, amount) in &self.config.drip {
+            let mut transaction = Transaction::new(address_of(&keypair.public), did.to_string(), *amount, currency.clone(), FAUCET_GAS_LIMIT)
+                .on_network(&blockchain.spec.network_id);
+            transaction.sign(keypair).map_err(Error::FaucetError)?;
+            transactions.push(transaction);
+        }
+        for transaction in &transactions {
+            blockchain.add_transaction(transaction.clone())?;
+        }
+
+        let now = self.clock.now();
+        self.forget_before(now - chrono::Duration::from_std(self.config.cooldown).expect("cooldown out of range"));
+        self.by_did.insert(did.to_string(), now);
+        if let Some(ip) = ip {
+            self.by_ip.insert(ip, now);
+        }
+        info!("Faucet gave {} {} transfers", did, transactions.len());
+        Ok(transactions)
+    }
+
+    /// Drops drips whose cooldown is over, so that the tables stay small.
+    fn forget_before(&mut self, cutoff: DateTime<Utc>) {
+        self.by_did.retain(|_, last| *last > cutoff);
+        self.by_ip.retain(|_, last| *last > cutoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::ChainSpec;
+    use crate::clock::MockClock;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_drip_is_rate_limited_per_did_and_ip() {
+        let clock = MockClock::new();
+        let config = FaucetConfig { enabled: true, cooldown: Duration::from_secs(3600), ..FaucetConfig::default() };
+        assert!(Faucet::new(config.clone()).drip(&mut Blockchain::new(), "did:icn:alice", None).is_err(), "there is nothing to pay from");
+        let mut faucet = Faucet::new(config).with_clock(clock.clone().into()).with_account(Keypair::generate(&mut OsRng {}));
+        let address = faucet.address().unwrap();
+        let spec = CurrencyType::standard().into_iter().fold(ChainSpec::default(), |spec, currency_type| spec.with_allocation(&address, 100.0, currency_type));
+        let mut blockchain = Blockchain::with_spec(spec);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let transfers = faucet.drip(&mut blockchain, "did:icn:alice", Some(ip)).unwrap();
+        assert_eq!(transfers.len(), CurrencyType::standard().len());
+        blockchain.create_block("Miner1".to_string()).unwrap();
+        assert_eq!(blockchain.get_currency_balance("did:icn:alice", &CurrencyType::Energy), 10.0);
+        assert_eq!(blockchain.get_currency_balance(&address, &CurrencyType::Energy), 90.0);
+
+        assert!(faucet.drip(&mut blockchain, "did:icn:alice", None).is_err());
+        assert!(faucet.drip(&mut blockchain, "did:icn:bob", Some(ip)).is_err(), "the IP address waits too");
+        assert!(faucet.drip(&mut blockchain, "bob", None).is_err());
+        clock.advance(Duration::from_secs(3601));
+        assert!(faucet.next_drip("did:icn:alice", Some(ip)).is_none());
+        assert!(faucet.drip(&mut blockchain, "did:icn:bob", Some(ip)).is_ok());
+
+        faucet.set_config(FaucetConfig::default());
+        assert_eq!(faucet.drip(&mut blockchain, "did:icn:carol", None).unwrap_err().code(), 1600);
+    }
+}
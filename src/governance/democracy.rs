@@ -3,7 +3,15 @@
 use std::collections::HashMap;
 use chrono::{DateTime, Utc, Duration};
 use serde::{Serialize, Deserialize};
-use log::{info, error, debug, warn};
+use tracing::{info, error, debug, warn};
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+use crate::identity::PersonhoodRegistry;
+use crate::ipfs::Cid;
+use crate::reputation::ReputationStore;
+
+/// Credits each person may spend on a quadratic vote on one proposal.
+pub const QUADRATIC_CREDITS: f64 = 100.0;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ProposalCategory {
@@ -25,6 +33,47 @@ pub enum ProposalType {
     Constitutional,
     EconomicAdjustment,
     NetworkUpgrade,
+    /// Halts the chain, or resumes it if halted; see `CircuitBreaker`.
+    Emergency,
+}
+
+/// What a proposal does to the chain once it passes and the validators
+/// enact it; see `blockchain::enactment`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ProposalAction {
+    /// Switches the feature flag `feature` on from the block at
+    /// `activation_height`.
+    ScheduleFeature { feature: String, activation_height: u64 },
+    /// Lets the grantor of the revocable vesting grant `vesting_id` revoke
+    /// it.
+    ApproveRevocation { vesting_id: String },
+    /// Decides the dispute over the service agreement `agreement_id`, paying
+    /// the provider or refunding the client.
+    ResolveDispute { agreement_id: String, for_provider: bool },
+    /// Replaces the shares of every block reserved for transactions in
+    /// essential currencies.
+    ReserveLanes { reserved: Vec<(CurrencyType, f64)> },
+    /// Halts the chain from the next block until it is resumed.
+    Halt { reason: String },
+    /// Resumes a halted chain from the next block.
+    Resume,
+}
+
+impl ProposalAction {
+    pub fn proposal_type(&self) -> ProposalType {
+        match self {
+            ProposalAction::ScheduleFeature { .. } => ProposalType::NetworkUpgrade,
+            ProposalAction::Halt { .. } | ProposalAction::Resume => ProposalType::Emergency,
+            ProposalAction::ApproveRevocation { .. } | ProposalAction::ResolveDispute { .. } | ProposalAction::ReserveLanes { .. } => ProposalType::EconomicAdjustment,
+        }
+    }
+
+    pub fn category(&self) -> ProposalCategory {
+        match self {
+            ProposalAction::ScheduleFeature { .. } | ProposalAction::Halt { .. } | ProposalAction::Resume => ProposalCategory::Technical,
+            ProposalAction::ApproveRevocation { .. } | ProposalAction::ResolveDispute { .. } | ProposalAction::ReserveLanes { .. } => ProposalCategory::Economic,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -40,6 +89,12 @@ pub struct Proposal {
     pub category: ProposalCategory,
     pub required_quorum: f64,
     pub execution_timestamp: Option<DateTime<Utc>>,
+    /// Documents too large for the description, stored on IPFS.
+    #[serde(default)]
+    pub attachments: Vec<Cid>,
+    /// What the proposal does once enacted, voted on with the rest.
+    #[serde(default)]
+    pub action: Option<ProposalAction>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -54,6 +109,7 @@ pub struct Vote {
 pub struct DemocraticSystem {
     proposals: HashMap<String, Proposal>,
     votes: HashMap<String, Vec<Vote>>,
+    clock: SharedClock,
 }
 
 impl DemocraticSystem {
@@ -62,9 +118,16 @@ impl DemocraticSystem {
         DemocraticSystem {
             proposals: HashMap::new(),
             votes: HashMap::new(),
+            clock: SharedClock::default(),
         }
     }
 
+    /// Dates proposals and votes, and decides when voting ends, by `clock`.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn create_proposal(
         &mut self,
         title: String,
@@ -76,25 +139,44 @@ impl DemocraticSystem {
         required_quorum: f64,
         execution_timestamp: Option<DateTime<Utc>>
     ) -> Result<String, String> {
-        let id = format!("prop_{}", Utc::now().timestamp());
+        let now = self.clock.now();
+        let id = format!("prop_{}", now.timestamp());
         let proposal = Proposal {
             id: id.clone(),
             title,
             description,
             proposer,
-            created_at: Utc::now(),
-            voting_ends_at: Utc::now() + voting_duration,
+            created_at: now,
+            voting_ends_at: now + voting_duration,
             status: ProposalStatus::Active,
             proposal_type,
             category,
             required_quorum,
             execution_timestamp,
+            attachments: Vec::new(),
+            action: None,
         };
         self.proposals.insert(id.clone(), proposal);
         info!("New proposal created: {}", id);
         Ok(id)
     }
 
+    /// Creates a proposal to take `action`, of the type and category the
+    /// action falls under.
+    pub fn propose_action(
+        &mut self,
+        title: String,
+        proposer: String,
+        voting_duration: Duration,
+        required_quorum: f64,
+        action: ProposalAction
+    ) -> Result<String, String> {
+        let description = format!("{:?}", action);
+        let id = self.create_proposal(title, description, proposer, voting_duration, action.proposal_type(), action.category(), required_quorum, None)?;
+        self.proposals.get_mut(&id).expect("the proposal was just created").action = Some(action);
+        Ok(id)
+    }
+
     pub fn vote(
         &mut self,
         voter: String,
@@ -109,7 +191,7 @@ impl DemocraticSystem {
             return Err("Voting is not active for this proposal".to_string());
         }
 
-        if Utc::now() > proposal.voting_ends_at {
+        if self.clock.now() > proposal.voting_ends_at {
             error!("Attempted to vote on expired proposal: {}", proposal_id);
             return Err("Voting period has ended".to_string());
         }
@@ -119,7 +201,7 @@ impl DemocraticSystem {
             proposal_id: proposal_id.clone(),
             in_favor,
             weight,
-            timestamp: Utc::now(),
+            timestamp: self.clock.now(),
         };
 
         self.votes.entry(proposal_id.clone()).or_insert_with(Vec::new).push(vote);
@@ -127,6 +209,46 @@ impl DemocraticSystem {
         Ok(())
     }
 
+    /// Casts a vote weighted by the voter's standing in the shared reputation store.
+    pub fn vote_with_reputation(
+        &mut self,
+        voter: String,
+        proposal_id: String,
+        in_favor: bool,
+        reputation: &ReputationStore
+    ) -> Result<(), String> {
+        let weight = reputation.governance_weight(&voter);
+        if weight <= 0.0 {
+            return Err("Voter has no governance weight".to_string());
+        }
+        self.vote(voter, proposal_id, in_favor, weight)
+    }
+
+    /// Casts a quadratic vote: spending `credits`, out of the
+    /// `QUADRATIC_CREDITS` each person has per proposal, weighs the square
+    /// root of them. Only a DID attested to be a unique person votes this
+    /// way, and only once per proposal, since spreading credits over several
+    /// identities or votes would undo the square root.
+    pub fn vote_quadratic(
+        &mut self,
+        voter: String,
+        proposal_id: String,
+        in_favor: bool,
+        credits: f64,
+        personhood: &PersonhoodRegistry
+    ) -> Result<(), String> {
+        if !personhood.is_person(&voter) {
+            return Err(format!("{} has no valid proof of personhood", voter));
+        }
+        if !credits.is_finite() || credits <= 0.0 || credits > QUADRATIC_CREDITS {
+            return Err(format!("A quadratic vote spends between 0 and {} credits", QUADRATIC_CREDITS));
+        }
+        if self.votes.get(&proposal_id).is_some_and(|votes| votes.iter().any(|vote| vote.voter == voter)) {
+            return Err(format!("{} has already voted on {}", voter, proposal_id));
+        }
+        self.vote(voter, proposal_id, in_favor, credits.sqrt())
+    }
+
     pub fn tally_votes(&mut self, proposal_id: &str) -> Result<(), String> {
         let proposal = self.proposals.get_mut(proposal_id).ok_or("Proposal not found")?;
         
@@ -135,7 +257,7 @@ impl DemocraticSystem {
             return Err("Proposal is not active".to_string());
         }
 
-        if Utc::now() < proposal.voting_ends_at {
+        if self.clock.now() < proposal.voting_ends_at {
             warn!("Attempted to tally votes before voting period ended: {}", proposal_id);
             return Err("Voting period has not ended yet".to_string());
         }
@@ -162,6 +284,18 @@ impl DemocraticSystem {
         Ok(())
     }
 
+    /// Attaches an IPFS document to a proposal still open for voting.
+    pub fn attach(&mut self, proposal_id: &str, cid: Cid) -> Result<(), String> {
+        let proposal = self.proposals.get_mut(proposal_id).ok_or("Proposal not found")?;
+        if proposal.status != ProposalStatus::Active {
+            return Err("Proposal is not active".to_string());
+        }
+        if !proposal.attachments.contains(&cid) {
+            proposal.attachments.push(cid);
+        }
+        Ok(())
+    }
+
     pub fn get_proposal(&self, proposal_id: &str) -> Option<&Proposal> {
         self.proposals.get(proposal_id)
     }
@@ -170,6 +304,10 @@ impl DemocraticSystem {
         self.votes.get(proposal_id)
     }
 
+    pub fn list_proposals(&self) -> Vec<&Proposal> {
+        self.proposals.values().collect()
+    }
+
     pub fn list_active_proposals(&self) -> Vec<&Proposal> {
         self.proposals.values()
             .filter(|p| p.status == ProposalStatus::Active)
@@ -193,6 +331,7 @@ impl DemocraticSystem {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
 
     #[test]
     fn test_create_proposal() {
@@ -214,7 +353,8 @@ mod tests {
 
     #[test]
     fn test_vote_and_tally() {
-        let mut system = DemocraticSystem::new();
+        let clock = MockClock::new();
+        let mut system = DemocraticSystem::new().with_clock(clock.clone().into());
         let proposal_id = system.create_proposal(
             "Test Proposal".to_string(),
             "This is a test proposal".to_string(),
@@ -230,8 +370,10 @@ mod tests {
         system.vote("Charlie".to_string(), proposal_id.clone(), false, 1.0).unwrap();
         system.vote("David".to_string(), proposal_id.clone(), true, 1.0).unwrap();
 
-        // Wait for voting period to end
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        assert!(system.tally_votes(&proposal_id).is_err());
+        // Let the voting period end
+        clock.advance(std::time::Duration::from_secs(2));
+        assert!(system.vote("Eve".to_string(), proposal_id.clone(), false, 5.0).is_err());
 
         let tally_result = system.tally_votes(&proposal_id);
         assert!(tally_result.is_ok());
@@ -239,4 +381,56 @@ mod tests {
         let proposal = system.get_proposal(&proposal_id).unwrap();
         assert_eq!(proposal.status, ProposalStatus::Passed);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_vote_with_reputation() {
+        let mut system = DemocraticSystem::new();
+        let proposal_id = system.create_proposal(
+            "Test Proposal".to_string(),
+            "This is a test proposal".to_string(),
+            "Alice".to_string(),
+            Duration::days(7),
+            ProposalType::Constitutional,
+            ProposalCategory::Technical,
+            0.5,
+            None,
+        ).unwrap();
+
+        let mut reputation = ReputationStore::new();
+        reputation.register("Bob");
+        reputation.record_contribution("Bob", crate::reputation::ContributionCategory::Community, 3.0).unwrap();
+
+        system.vote_with_reputation("Bob".to_string(), proposal_id.clone(), true, &reputation).unwrap();
+        assert!(system.vote_with_reputation("Eve".to_string(), proposal_id.clone(), true, &reputation).is_err());
+        assert_eq!(system.get_votes(&proposal_id).unwrap()[0].weight, 2.0);
+    }
+
+    #[test]
+    fn test_quadratic_vote_needs_personhood() {
+        use crate::identity::{Attestation, DecentralizedIdentity, DidManager};
+        use ed25519_dalek::Keypair;
+        use rand::rngs::OsRng;
+
+        let mut system = DemocraticSystem::new();
+        let proposal_id = system.create_proposal(
+            "Test Proposal".to_string(),
+            "This is a test proposal".to_string(),
+            "Alice".to_string(),
+            Duration::days(7),
+            ProposalType::Constitutional,
+            ProposalCategory::Economic,
+            0.5,
+            None,
+        ).unwrap();
+        let verifier = Keypair::generate(&mut OsRng {});
+        let mut personhood = PersonhoodRegistry::new(3, Duration::days(365));
+        personhood.add_verifier(&DecentralizedIdentity::from_public_key(verifier.public, HashMap::new()).id);
+        personhood.attest(Attestation::new("did:icn:bob", &verifier, Utc::now()), &DidManager::new()).unwrap();
+
+        assert!(system.vote_quadratic("did:icn:sybil".to_string(), proposal_id.clone(), true, 16.0, &personhood).is_err());
+        assert!(system.vote_quadratic("did:icn:bob".to_string(), proposal_id.clone(), true, 101.0, &personhood).is_err());
+        system.vote_quadratic("did:icn:bob".to_string(), proposal_id.clone(), true, 16.0, &personhood).unwrap();
+        assert!(system.vote_quadratic("did:icn:bob".to_string(), proposal_id.clone(), true, 9.0, &personhood).is_err(), "one vote per proposal");
+        assert_eq!(system.get_votes(&proposal_id).unwrap()[0].weight, 4.0);
+    }
+}
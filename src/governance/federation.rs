@@ -0,0 +1,296 @@
+// src/governance/federation.rs
+
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::Duration;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+use super::democracy::{DemocraticSystem, ProposalCategory, ProposalStatus, ProposalType};
+
+/// A cooperative of a federation: its members, the currencies it issued,
+/// and the governance body its members vote in.
+pub struct Cooperative {
+    pub id: String,
+    pub name: String,
+    members: BTreeSet<String>,
+    currencies: Vec<CurrencyType>,
+    pub governance: DemocraticSystem,
+}
+
+impl Cooperative {
+    pub fn members(&self) -> &BTreeSet<String> {
+        &self.members
+    }
+
+    pub fn is_member(&self, member: &str) -> bool {
+        self.members.contains(member)
+    }
+
+    /// The currencies issued by the cooperative, named within its id.
+    pub fn currencies(&self) -> &[CurrencyType] {
+        &self.currencies
+    }
+
+    /// Votes on a proposal of the governance body, as a member; every member
+    /// has one vote.
+    pub fn vote(&mut self, member: &str, proposal_id: &str, in_favor: bool) -> Result<(), String> {
+        if !self.is_member(member) {
+            return Err(format!("{} is not a member of {}", member, self.id));
+        }
+        self.governance.vote(member.to_string(), proposal_id.to_string(), in_favor, 1.0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum FederationProposalStatus {
+    /// Still before the governance bodies of the cooperatives.
+    Ratifying,
+    Ratified,
+    /// Too few cooperatives are left to ratify it.
+    Rejected,
+}
+
+/// A proposal to the whole federation. It is put to the governance body of
+/// every cooperative, and adopted once enough of them pass it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FederationProposal {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    /// The cooperative that proposed it.
+    pub proposer: String,
+    /// How many cooperatives must ratify it.
+    pub required_ratifications: usize,
+    /// The proposal put to each cooperative, by cooperative.
+    pub local_proposals: BTreeMap<String, String>,
+    pub ratified_by: Vec<String>,
+    pub rejected_by: Vec<String>,
+    pub status: FederationProposalStatus,
+}
+
+/// Several cooperatives sharing one network, each governing itself, and
+/// governing the federation together by ratifying its proposals.
+pub struct Federation {
+    cooperatives: BTreeMap<String, Cooperative>,
+    proposals: BTreeMap<String, FederationProposal>,
+    clock: SharedClock,
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Federation {
+    pub fn new() -> Self {
+        debug!("Creating new Federation");
+        Federation {
+            cooperatives: BTreeMap::new(),
+            proposals: BTreeMap::new(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Dates the proposals of the federation and its cooperatives by `clock`.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Admits the cooperative `id` to the federation, with no members yet.
+    pub fn join(&mut self, id: &str, name: &str) -> Result<(), String> {
+        if id.is_empty() || id.contains(':') {
+            return Err(format!("Invalid cooperative id {:?}", id));
+        }
+        if self.cooperatives.contains_key(id) {
+            return Err(format!("Cooperative {} is already in the federation", id));
+        }
+        self.cooperatives.insert(id.to_string(), Cooperative {
+            id: id.to_string(),
+            name: name.to_string(),
+            members: BTreeSet::new(),
+            currencies: Vec::new(),
+            governance: DemocraticSystem::new().with_clock(self.clock.clone()),
+        });
+        info!("Cooperative {} joined the federation", id);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Cooperative> {
+        self.cooperatives.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Cooperative> {
+        self.cooperatives.get_mut(id)
+    }
+
+    pub fn cooperatives(&self) -> Vec<&Cooperative> {
+        self.cooperatives.values().collect()
+    }
+
+    /// Registers `member` with the cooperative `id`.
+    pub fn admit(&mut self, id: &str, member: &str) -> Result<(), String> {
+        let cooperative = self.cooperatives.get_mut(id).ok_or_else(|| format!("No cooperative {}", id))?;
+        if !cooperative.members.insert(member.to_string()) {
+            return Err(format!("{} is already a member of {}", member, id));
+        }
+        Ok(())
+    }
+
+    /// Removes `member` from the cooperative `id`.
+    pub fn expel(&mut self, id: &str, member: &str) -> Result<(), String> {
+        let cooperative = self.cooperatives.get_mut(id).ok_or_else(|| format!("No cooperative {}", id))?;
+        if !cooperative.members.remove(member) {
+            return Err(format!("{} is not a member of {}", member, id));
+        }
+        Ok(())
+    }
+
+    /// Issues a currency of the cooperative `id`, named `id:name` so that
+    /// cooperatives may pick the same names.
+    pub fn issue_currency(&mut self, id: &str, name: &str) -> Result<CurrencyType, String> {
+        let cooperative = self.cooperatives.get_mut(id).ok_or_else(|| format!("No cooperative {}", id))?;
+        let currency = CurrencyType::Custom(format!("{}:{}", id, name));
+        if cooperative.currencies.contains(&currency) {
+            return Err(format!("{} already issues {}", id, currency));
+        }
+        cooperative.currencies.push(currency.clone());
+        Ok(currency)
+    }
+
+    /// Proposes a change to the whole federation on behalf of the cooperative
+    /// `proposer`. Every cooperative votes on ratifying it for
+    /// `voting_duration`, passing it by a majority with half its members
+    /// voting; `required_ratifications` of them must.
+    pub fn propose(
+        &mut self,
+        proposer: &str,
+        title: String,
+        description: String,
+        voting_duration: Duration,
+        required_ratifications: usize,
+    ) -> Result<String, String> {
+        if !self.cooperatives.contains_key(proposer) {
+            return Err(format!("No cooperative {}", proposer));
+        }
+        if required_ratifications == 0 || required_ratifications > self.cooperatives.len() {
+            return Err(format!("Between 1 and {} cooperatives must ratify a proposal", self.cooperatives.len()));
+        }
+        let id = format!("fed_{}", self.proposals.len() + 1);
+        let mut local_proposals = BTreeMap::new();
+        for cooperative in self.cooperatives.values_mut() {
+            let local_id = cooperative.governance.create_proposal(
+                title.clone(),
+                format!("Ratification of federation proposal {}: {}", id, description),
+                proposer.to_string(),
+                voting_duration,
+                ProposalType::Constitutional,
+                ProposalCategory::Constitutional,
+                cooperative.members.len() as f64 / 2.0,
+                None,
+            )?;
+            local_proposals.insert(cooperative.id.clone(), local_id);
+        }
+        info!("{} proposed {} to the federation", proposer, id);
+        self.proposals.insert(id.clone(), FederationProposal {
+            id: id.clone(),
+            title,
+            description,
+            proposer: proposer.to_string(),
+            required_ratifications,
+            local_proposals,
+            ratified_by: Vec::new(),
+            rejected_by: Vec::new(),
+            status: FederationProposalStatus::Ratifying,
+        });
+        Ok(id)
+    }
+
+    /// Tallies the votes of the cooperatives whose voting on a proposal has
+    /// ended, and decides it once enough have ratified it or too few are
+    /// left to. A cooperative none of whose members voted rejects it.
+    pub fn tally(&mut self, proposal_id: &str) -> Result<FederationProposalStatus, String> {
+        let proposal = self.proposals.get_mut(proposal_id).ok_or("Proposal not found")?;
+        if proposal.status != FederationProposalStatus::Ratifying {
+            return Ok(proposal.status.clone());
+        }
+        for (cooperative_id, local_id) in &proposal.local_proposals {
+            if proposal.ratified_by.contains(cooperative_id) || proposal.rejected_by.contains(cooperative_id) {
+                continue;
+            }
+            let governance = &mut self.cooperatives.get_mut(cooperative_id).expect("cooperatives never leave").governance;
+            let local = governance.get_proposal(local_id).ok_or("Ratification not found")?;
+            if local.status == ProposalStatus::Active && self.clock.now() < local.voting_ends_at {
+                continue;
+            }
+            if local.status == ProposalStatus::Active && governance.get_votes(local_id).is_some() {
+                governance.tally_votes(local_id)?;
+            }
+            match governance.get_proposal(local_id).map(|local| &local.status) {
+                Some(ProposalStatus::Passed) | Some(ProposalStatus::Implemented) => proposal.ratified_by.push(cooperative_id.clone()),
+                _ => proposal.rejected_by.push(cooperative_id.clone()),
+            }
+        }
+        if proposal.ratified_by.len() >= proposal.required_ratifications {
+            proposal.status = FederationProposalStatus::Ratified;
+            info!("Federation proposal {} ratified by {}", proposal_id, proposal.ratified_by.join(", "));
+        } else if proposal.local_proposals.len() - proposal.rejected_by.len() < proposal.required_ratifications {
+            proposal.status = FederationProposalStatus::Rejected;
+            info!("Federation proposal {} rejected by {}", proposal_id, proposal.rejected_by.join(", "));
+        }
+        Ok(proposal.status.clone())
+    }
+
+    pub fn get_proposal(&self, proposal_id: &str) -> Option<&FederationProposal> {
+        self.proposals.get(proposal_id)
+    }
+
+    pub fn list_proposals(&self) -> Vec<&FederationProposal> {
+        self.proposals.values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_federation_proposal_needs_enough_ratifications() {
+        let clock = MockClock::new();
+        let mut federation = Federation::new().with_clock(clock.clone().into());
+        for (id, members) in [("bakery", ["alice", "bob"]), ("farm", ["carol", "dave"]), ("mill", ["erin", "frank"])] {
+            federation.join(id, id).unwrap();
+            for member in members {
+                federation.admit(id, member).unwrap();
+            }
+        }
+        assert_eq!(federation.issue_currency("farm", "grain").unwrap(), CurrencyType::Custom("farm:grain".to_string()));
+        assert!(federation.issue_currency("farm", "grain").is_err());
+
+        let id = federation.propose("farm", "Shared delivery".to_string(), String::new(), Duration::days(1), 2).unwrap();
+        let ratify = |federation: &mut Federation, cooperative: &str, member: &str, in_favor: bool| {
+            let local_id = federation.get_proposal(&id).unwrap().local_proposals[cooperative].clone();
+            federation.get_mut(cooperative).unwrap().vote(member, &local_id, in_favor)
+        };
+        assert!(ratify(&mut federation, "bakery", "carol", true).is_err(), "members vote only in their own cooperative");
+        ratify(&mut federation, "bakery", "alice", true).unwrap();
+        ratify(&mut federation, "farm", "carol", false).unwrap();
+        ratify(&mut federation, "farm", "dave", false).unwrap();
+        assert_eq!(federation.tally(&id).unwrap(), FederationProposalStatus::Ratifying, "voting has not ended");
+
+        clock.advance(std::time::Duration::from_secs(86_401));
+        assert_eq!(federation.tally(&id).unwrap(), FederationProposalStatus::Rejected, "the farm rejected it and the mill never voted");
+        assert_eq!(federation.get_proposal(&id).unwrap().ratified_by, vec!["bakery".to_string()]);
+
+        let id = federation.propose("mill", "Shared accounts".to_string(), String::new(), Duration::days(1), 2).unwrap();
+        for (cooperative, member) in [("bakery", "bob"), ("mill", "erin")] {
+            let local_id = federation.get_proposal(&id).unwrap().local_proposals[cooperative].clone();
+            federation.get_mut(cooperative).unwrap().vote(member, &local_id, true).unwrap();
+        }
+        clock.advance(std::time::Duration::from_secs(86_401));
+        assert_eq!(federation.tally(&id).unwrap(), FederationProposalStatus::Ratified);
+    }
+}
@@ -1,5 +1,7 @@
 // src/governance/mod.rs
 
 pub mod democracy;
+pub mod federation;
 
-pub use democracy::{DemocraticSystem, ProposalCategory, ProposalType};
\ No newline at end of file
+pub use democracy::{DemocraticSystem, ProposalAction, ProposalCategory, ProposalType};
+pub use federation::{Cooperative, Federation, FederationProposal, FederationProposalStatus};
\ No newline at end of file
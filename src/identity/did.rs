@@ -1,8 +1,13 @@
 use chrono::{DateTime, Utc};
-use ed25519_dalek::{Keypair, PublicKey, Signature, Verifier};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use crate::blockchain::Transaction;
+use super::revocation::{RevocationAction, RevocationRegistry};
+
+/// Gas limit of the revocation transactions `DidManager::revoke_did` makes.
+pub const REVOCATION_GAS_LIMIT: u64 = 1000;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DecentralizedIdentity {
@@ -10,11 +15,10 @@ pub struct DecentralizedIdentity {
     #[serde(with = "public_key_serde")]
     pub public_key: PublicKey,
     pub created_at: DateTime<Utc>,
-    pub reputation: f64,
     pub attributes: HashMap<String, String>,
 }
 
-mod public_key_serde {
+pub(crate) mod public_key_serde {
     use ed25519_dalek::PublicKey;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
@@ -39,19 +43,17 @@ impl DecentralizedIdentity {
     pub fn new(attributes: HashMap<String, String>) -> (Self, Keypair) {
         let mut csprng = OsRng {};
         let keypair: Keypair = Keypair::generate(&mut csprng);
-        let public_key = keypair.public;
-        let id = format!("did:icn:{}", hex::encode(public_key.to_bytes()));
+        (Self::from_public_key(keypair.public, attributes), keypair)
+    }
 
-        (
-            Self {
-                id,
-                public_key,
-                created_at: Utc::now(),
-                reputation: 1.0,
-                attributes,
-            },
-            keypair,
-        )
+    /// The identity of a key generated by its holder, who keeps the secret.
+    pub fn from_public_key(public_key: PublicKey, attributes: HashMap<String, String>) -> Self {
+        Self {
+            id: format!("did:icn:{}", hex::encode(public_key.to_bytes())),
+            public_key,
+            created_at: Utc::now(),
+            attributes,
+        }
     }
 
     pub fn verify_signature(&self, message: &[u8], signature: &Signature) -> bool {
@@ -59,21 +61,169 @@ impl DecentralizedIdentity {
     }
 }
 
+/// A key rotation published by a DID controller. The record is signed with
+/// the key being retired, which proves continuity between the old and new key.
+/// It carries no time of its own: the node applying it anchors it at the
+/// height of its chain, so the signer cannot backdate it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub did_id: String,
+    #[serde(with = "public_key_serde")]
+    pub previous_key: PublicKey,
+    #[serde(with = "public_key_serde")]
+    pub new_key: PublicKey,
+    /// How many rotations of the DID came before, so that a rotation cannot
+    /// be replayed once the DID has rotated past it.
+    pub sequence: u64,
+    pub signature: Vec<u8>,
+}
+
+impl KeyRotation {
+    pub fn new(did_id: &str, current_keypair: &Keypair, new_key: PublicKey, sequence: u64) -> Self {
+        let mut rotation = KeyRotation {
+            did_id: did_id.to_string(),
+            previous_key: current_keypair.public,
+            new_key,
+            sequence,
+            signature: Vec::new(),
+        };
+        rotation.signature = current_keypair.sign(&rotation.signing_payload()).to_bytes().to_vec();
+        rotation
+    }
+
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(self.did_id.as_bytes());
+        bytes.extend_from_slice(&self.previous_key.to_bytes());
+        bytes.extend_from_slice(&self.new_key.to_bytes());
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        bytes
+    }
+
+    pub fn verify(&self) -> bool {
+        match Signature::from_bytes(&self.signature) {
+            Ok(signature) => self.previous_key.verify(&self.signing_payload(), &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// A key that was (or is) authoritative for a DID over a range of blocks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRecord {
+    #[serde(with = "public_key_serde")]
+    pub public_key: PublicKey,
+    /// Height of the chain when the key took over; the first key of a DID
+    /// holds from the genesis block.
+    pub valid_from: u64,
+    /// Height of the chain when the key was rotated out.
+    pub valid_until: Option<u64>,
+}
+
+impl KeyRecord {
+    fn was_valid_at(&self, height: u64) -> bool {
+        height >= self.valid_from && self.valid_until.is_none_or(|until| height < until)
+    }
+}
+
 pub struct DidManager {
     dids: HashMap<String, DecentralizedIdentity>,
+    key_history: HashMap<String, Vec<KeyRecord>>,
+    /// The signed rotations applied to each DID, oldest first, which prove
+    /// its current key to those who only know its identifier.
+    rotations: HashMap<String, Vec<KeyRotation>>,
 }
 
 impl DidManager {
     pub fn new() -> Self {
         DidManager {
             dids: HashMap::new(),
+            key_history: HashMap::new(),
+            rotations: HashMap::new(),
         }
     }
 
     pub fn add_did(&mut self, did: DecentralizedIdentity) {
+        self.key_history.insert(did.id.clone(), vec![KeyRecord {
+            public_key: did.public_key,
+            valid_from: 0,
+            valid_until: None,
+        }]);
         self.dids.insert(did.id.clone(), did);
     }
 
+    /// Applies a key rotation, anchored at `height`, the height of the chain
+    /// when it is applied: the new key signs from the block at `height` on.
+    /// The rotation must be signed by the DID's current key; the retired key
+    /// is kept in the history so that signatures it made in earlier blocks
+    /// remain verifiable.
+    pub fn rotate_key(&mut self, rotation: KeyRotation, height: u64) -> Result<(), String> {
+        let did = self.dids.get_mut(&rotation.did_id)
+            .ok_or_else(|| format!("DID not found: {}", rotation.did_id))?;
+
+        if did.public_key != rotation.previous_key {
+            return Err("Rotation does not reference the current key".to_string());
+        }
+        if !rotation.verify() {
+            return Err("Invalid rotation signature".to_string());
+        }
+        if rotation.new_key == rotation.previous_key {
+            return Err("New key must differ from the current key".to_string());
+        }
+
+        let history = self.key_history.entry(rotation.did_id.clone()).or_default();
+        if rotation.sequence + 1 != history.len() as u64 {
+            return Err(format!("Rotation {} is not the next of {}", rotation.sequence, rotation.did_id));
+        }
+        if let Some(current) = history.last_mut() {
+            if height < current.valid_from {
+                return Err("Rotation predates the current key".to_string());
+            }
+            current.valid_until = Some(height);
+        }
+        history.push(KeyRecord {
+            public_key: rotation.new_key,
+            valid_from: height,
+            valid_until: None,
+        });
+        did.public_key = rotation.new_key;
+        self.rotations.entry(rotation.did_id.clone()).or_default().push(rotation);
+        Ok(())
+    }
+
+    pub fn get_key_history(&self, did_id: &str) -> Option<&Vec<KeyRecord>> {
+        self.key_history.get(did_id)
+    }
+
+    /// The rotations applied to a DID, oldest first.
+    pub fn get_rotations(&self, did_id: &str) -> &[KeyRotation] {
+        self.rotations.get(did_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Verifies a signature anchored in the block at `height`, as the chain
+    /// records it, against whichever key was authoritative for the DID then.
+    pub fn verify_historical_signature(
+        &self,
+        did_id: &str,
+        message: &[u8],
+        signature: &Signature,
+        height: u64,
+    ) -> Result<bool, String> {
+        let history = self.key_history.get(did_id)
+            .ok_or_else(|| format!("DID not found: {}", did_id))?;
+        Ok(history.iter()
+            .find(|record| record.was_valid_at(height))
+            .is_some_and(|record| record.public_key.verify(message, signature).is_ok()))
+    }
+
+    /// Forgets a DID and its key history, as when its registration is
+    /// undone; unlike `revoke_did` this leaves no record.
+    pub fn remove_did(&mut self, id: &str) -> Option<DecentralizedIdentity> {
+        self.key_history.remove(id);
+        self.rotations.remove(id);
+        self.dids.remove(id)
+    }
+
     pub fn get_did(&self, id: &str) -> Option<&DecentralizedIdentity> {
         self.dids.get(id)
     }
@@ -90,12 +240,45 @@ impl DidManager {
             Err(format!("DID not found: {}", did_id))
         }
     }
+
+    /// The transaction revoking a known DID in the on-chain registry, signed
+    /// with `keypair` by `revoked_by`, the DID itself or the issuer of a
+    /// credential it holds, to submit to the chain.
+    pub fn revoke_did(
+        &self,
+        did_id: &str,
+        revoked_by: &str,
+        reason: &str,
+        keypair: &Keypair,
+    ) -> Result<Transaction, String> {
+        if !self.dids.contains_key(did_id) {
+            return Err(format!("DID not found: {}", did_id));
+        }
+        let action = RevocationAction::RevokeDid { did_id: did_id.to_string(), reason: reason.to_string() };
+        let mut transaction = Transaction::revoke(revoked_by.to_string(), action, REVOCATION_GAS_LIMIT);
+        transaction.sign(keypair)?;
+        Ok(transaction)
+    }
+
+    /// Verifies that a signature was made by the DID's current key and that
+    /// the DID has not been revoked network-wide.
+    pub fn verify_identity(
+        &self,
+        did_id: &str,
+        message: &[u8],
+        signature: &Signature,
+        registry: &RevocationRegistry,
+    ) -> Result<bool, String> {
+        if registry.is_did_revoked(did_id) {
+            return Err(format!("DID has been revoked: {}", did_id));
+        }
+        self.verify_signature(did_id, message, signature)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ed25519_dalek::Signer;
 
     #[test]
     fn test_add_and_get_did() {
@@ -133,4 +316,70 @@ mod tests {
 
         assert!(!manager.verify_signature(&did_id, invalid_message, &signature).unwrap());
     }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut manager = DidManager::new();
+        let (did, old_keypair) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        manager.add_did(did);
+
+        let message = b"signed before rotation";
+        let old_signature = old_keypair.sign(message);
+
+        let new_keypair = Keypair::generate(&mut OsRng {});
+        let rotation = KeyRotation::new(&did_id, &old_keypair, new_keypair.public, 0);
+        manager.rotate_key(rotation.clone(), 5).unwrap();
+        assert!(manager.rotate_key(rotation, 6).is_err(), "a rotation applies once");
+
+        assert_eq!(manager.get_did(&did_id).unwrap().public_key, new_keypair.public);
+        assert_eq!(manager.get_key_history(&did_id).unwrap().len(), 2);
+
+        // New transactions require the current key
+        assert!(!manager.verify_signature(&did_id, message, &old_signature).unwrap());
+        let new_signature = new_keypair.sign(message);
+        assert!(manager.verify_signature(&did_id, message, &new_signature).unwrap());
+
+        // Old signatures remain verifiable against the key history, in the
+        // blocks before the rotation only
+        assert!(manager.verify_historical_signature(&did_id, message, &old_signature, 4).unwrap());
+        assert!(!manager.verify_historical_signature(&did_id, message, &old_signature, 5).unwrap());
+        assert!(manager.verify_historical_signature(&did_id, message, &new_signature, 5).unwrap());
+    }
+
+    #[test]
+    fn test_key_rotation_requires_current_key() {
+        let mut manager = DidManager::new();
+        let (did, _keypair) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        manager.add_did(did);
+
+        let attacker = Keypair::generate(&mut OsRng {});
+        let rotation = KeyRotation::new(&did_id, &attacker, attacker.public, 0);
+        assert!(manager.rotate_key(rotation, 1).is_err());
+
+        let (_, other_keypair) = DecentralizedIdentity::new(HashMap::new());
+        let mut forged = KeyRotation::new(&did_id, &attacker, other_keypair.public, 0);
+        forged.previous_key = manager.get_did(&did_id).unwrap().public_key;
+        assert!(manager.rotate_key(forged, 1).is_err());
+    }
+
+    #[test]
+    fn test_revoked_identity_fails_verification() {
+        let mut manager = DidManager::new();
+        let mut registry = RevocationRegistry::new();
+        let (did, keypair) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        manager.add_did(did);
+
+        let message = b"test message";
+        let signature = keypair.sign(message);
+        assert!(manager.verify_identity(&did_id, message, &signature, &registry).unwrap());
+
+        let revocation = manager.revoke_did(&did_id, &did_id, "Compromised key", &keypair).unwrap();
+        registry.apply(&revocation, 0).unwrap();
+        assert!(registry.is_did_revoked(&did_id));
+        assert!(manager.verify_identity(&did_id, message, &signature, &registry).is_err());
+        assert!(manager.revoke_did("did:icn:unknown", &did_id, "", &keypair).is_err());
+    }
 }
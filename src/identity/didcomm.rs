@@ -0,0 +1,242 @@
+// src/identity/didcomm.rs
+use std::collections::{BTreeMap, VecDeque};
+use chrono::{DateTime, Utc};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+/// Name prefix under which messages queued for a DID are served on the ICN
+/// data plane, as `/icn/didcomm/<did>/<sequence>`.
+pub const DIDCOMM_NAME_PREFIX: &str = "/icn/didcomm/";
+/// One-way Noise pattern: the sender knows the recipient's static key and
+/// sends its own, encrypted, so the recipient learns who wrote the message.
+const NOISE_PATTERN: &str = "Noise_X_25519_ChaChaPoly_SHA256";
+const NOISE_MAX_MESSAGE: usize = 65535;
+/// Ephemeral key, encrypted static key and the payload's tag.
+const NOISE_OVERHEAD: usize = 32 + 32 + 16 + 16;
+/// Largest serialized plaintext message that can be packed.
+pub const MAX_MESSAGE_SIZE: usize = NOISE_MAX_MESSAGE - NOISE_OVERHEAD;
+/// Messages kept for a recipient that has not picked them up. The oldest are
+/// dropped beyond this.
+pub const MAX_QUEUED_MESSAGES: usize = 256;
+
+/// The X25519 public key of a DID's Ed25519 key, as used for key agreement.
+pub fn x25519_public_key(public_key: &PublicKey) -> Result<[u8; 32], String> {
+    CompressedEdwardsY(public_key.to_bytes())
+        .decompress()
+        .map(|point| point.to_montgomery().to_bytes())
+        .ok_or_else(|| "Identity key is not a valid Ed25519 point".to_string())
+}
+
+/// The X25519 secret matching `x25519_public_key` of the keypair's public key.
+pub fn x25519_secret_key(keypair: &Keypair) -> [u8; 32] {
+    let hash = Sha512::digest(keypair.secret.as_bytes());
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&hash[..32]);
+    secret[0] &= 248;
+    secret[31] &= 127;
+    secret[31] |= 64;
+    secret
+}
+
+/// A message between members, before encryption. `thread_id` ties related
+/// messages together, e.g. those about one dispute or proposal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlainMessage {
+    pub id: String,
+    /// Tells the recipient how to read `body`, e.g. `dispute/statement`.
+    pub message_type: String,
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub body: serde_json::Value,
+}
+
+impl PlainMessage {
+    pub fn new(message_type: &str, from: &str, to: &str, body: serde_json::Value) -> Self {
+        PlainMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            message_type: message_type.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            thread_id: None,
+            created_at: Utc::now(),
+            body,
+        }
+    }
+
+    pub fn in_thread(mut self, thread_id: &str) -> Self {
+        self.thread_id = Some(thread_id.to_string());
+        self
+    }
+
+    /// Encrypts the message for `recipient_key`, the current key of the `to`
+    /// DID. Only the recipient can read it or learn who sent it.
+    pub fn pack(&self, sender: &Keypair, recipient_key: &PublicKey) -> Result<EncryptedMessage, String> {
+        let plaintext = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        if plaintext.len() > MAX_MESSAGE_SIZE {
+            return Err(format!("Message of {} bytes exceeds the limit of {}", plaintext.len(), MAX_MESSAGE_SIZE));
+        }
+        let sender_secret = x25519_secret_key(sender);
+        let recipient_public = x25519_public_key(recipient_key)?;
+        let mut noise = snow::Builder::new(NOISE_PATTERN.parse().map_err(noise_error)?)
+            .local_private_key(&sender_secret)
+            .remote_public_key(&recipient_public)
+            .build_initiator()
+            .map_err(noise_error)?;
+        let mut ciphertext = vec![0u8; NOISE_MAX_MESSAGE];
+        let length = noise.write_message(&plaintext, &mut ciphertext).map_err(noise_error)?;
+        ciphertext.truncate(length);
+        Ok(EncryptedMessage { to: self.to.clone(), ciphertext })
+    }
+}
+
+fn noise_error(e: snow::Error) -> String {
+    format!("Noise error: {}", e)
+}
+
+/// A packed message. Only the recipient's DID is visible, so that it can be
+/// routed and queued.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncryptedMessage {
+    pub to: String,
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedMessage {
+    /// Hex SHA-256 of the ciphertext.
+    pub fn id(&self) -> String {
+        hex::encode(Sha256::digest(&self.ciphertext))
+    }
+
+    /// Decrypts a message addressed to `recipient`'s DID. `sender_key` gives
+    /// the current key of a DID; the message is only accepted if it was
+    /// encrypted by the key of the DID it claims to come from.
+    pub fn unpack(&self, recipient: &Keypair, sender_key: impl Fn(&str) -> Option<PublicKey>) -> Result<PlainMessage, String> {
+        let recipient_secret = x25519_secret_key(recipient);
+        let mut noise = snow::Builder::new(NOISE_PATTERN.parse().map_err(noise_error)?)
+            .local_private_key(&recipient_secret)
+            .build_responder()
+            .map_err(noise_error)?;
+        let mut plaintext = vec![0u8; NOISE_MAX_MESSAGE];
+        let length = noise.read_message(&self.ciphertext, &mut plaintext).map_err(noise_error)?;
+        let message: PlainMessage = serde_json::from_slice(&plaintext[..length]).map_err(|e| e.to_string())?;
+        if message.to != self.to {
+            return Err(format!("Message for {} was delivered to {}", message.to, self.to));
+        }
+        let claimed_key = sender_key(&message.from).ok_or_else(|| format!("Unknown sender {}", message.from))?;
+        if noise.get_remote_static() != Some(&x25519_public_key(&claimed_key)?[..]) {
+            return Err(format!("Message was not sent by {}", message.from));
+        }
+        Ok(message)
+    }
+}
+
+/// `/icn/didcomm/<did>/<sequence>`
+pub fn message_name(did_id: &str, sequence: u64) -> String {
+    format!("{}{}/{}", DIDCOMM_NAME_PREFIX, did_id, sequence)
+}
+
+pub fn parse_message_name(name: &str) -> Option<(&str, u64)> {
+    let (did_id, sequence) = name.strip_prefix(DIDCOMM_NAME_PREFIX)?.rsplit_once('/')?;
+    Some((did_id, sequence.parse().ok()?))
+}
+
+#[derive(Debug, Clone, Default)]
+struct Queue {
+    /// Sequence number of the first message in `messages`.
+    first: u64,
+    messages: VecDeque<EncryptedMessage>,
+}
+
+/// Messages held for recipients that are offline, by recipient DID. Each
+/// recipient's messages are numbered in order of arrival, so that they can
+/// be fetched one by one as named data from wherever the recipient is.
+#[derive(Debug, Clone, Default)]
+pub struct Mailbox {
+    queues: BTreeMap<String, Queue>,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a message for its recipient, returning its sequence number.
+    /// The same message is queued only once.
+    pub fn deliver(&mut self, message: EncryptedMessage) -> u64 {
+        let queue = self.queues.entry(message.to.clone()).or_default();
+        if let Some(position) = queue.messages.iter().position(|queued| *queued == message) {
+            return queue.first + position as u64;
+        }
+        queue.messages.push_back(message);
+        if queue.messages.len() > MAX_QUEUED_MESSAGES {
+            queue.messages.pop_front();
+            queue.first += 1;
+        }
+        queue.first + queue.messages.len() as u64 - 1
+    }
+
+    /// The message numbered `sequence` for `did_id`, if still queued.
+    pub fn get(&self, did_id: &str, sequence: u64) -> Option<&EncryptedMessage> {
+        let queue = self.queues.get(did_id)?;
+        queue.messages.get(sequence.checked_sub(queue.first)? as usize)
+    }
+
+    /// The sequence number the next message for `did_id` will get.
+    pub fn next_sequence(&self, did_id: &str) -> u64 {
+        self.queues.get(did_id).map_or(0, |queue| queue.first + queue.messages.len() as u64)
+    }
+
+    pub fn pending(&self, did_id: &str) -> usize {
+        self.queues.get(did_id).map_or(0, |queue| queue.messages.len())
+    }
+
+    /// Removes and returns the messages queued for `did_id`.
+    pub fn take(&mut self, did_id: &str) -> Vec<EncryptedMessage> {
+        match self.queues.get_mut(did_id) {
+            Some(queue) => {
+                queue.first += queue.messages.len() as u64;
+                queue.messages.drain(..).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DecentralizedIdentity;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let (alice, alice_keys) = DecentralizedIdentity::new(HashMap::new());
+        let (bob, bob_keys) = DecentralizedIdentity::new(HashMap::new());
+        let (_, mallory_keys) = DecentralizedIdentity::new(HashMap::new());
+        let keys: HashMap<String, PublicKey> = [(alice.id.clone(), alice.public_key), (bob.id.clone(), bob.public_key)].into();
+        let lookup = |did: &str| keys.get(did).copied();
+
+        let message = PlainMessage::new("dispute/statement", &alice.id, &bob.id, serde_json::json!({ "text": "The delivery was late" }))
+            .in_thread("dispute-7");
+        let packed = message.pack(&alice_keys, &bob.public_key).unwrap();
+        assert_eq!(packed.unpack(&bob_keys, lookup).unwrap(), message);
+        assert!(packed.unpack(&mallory_keys, lookup).is_err(), "only the recipient can read it");
+
+        let forged = message.pack(&mallory_keys, &bob.public_key).unwrap();
+        assert!(forged.unpack(&bob_keys, lookup).unwrap_err().contains("not sent by"));
+
+        let mut mailbox = Mailbox::new();
+        assert_eq!(mailbox.deliver(packed.clone()), 0);
+        assert_eq!(mailbox.deliver(forged), 1);
+        assert_eq!(mailbox.deliver(packed.clone()), 0, "duplicates are not queued twice");
+        assert_eq!(mailbox.get(&bob.id, 0), Some(&packed));
+        assert_eq!(mailbox.take(&bob.id).len(), 2);
+        assert_eq!((mailbox.pending(&bob.id), mailbox.next_sequence(&bob.id)), (0, 2));
+        assert_eq!(parse_message_name(&message_name(&bob.id, 2)), Some((bob.id.as_str(), 2)));
+    }
+}
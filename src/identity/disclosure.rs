@@ -0,0 +1,396 @@
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use super::did::public_key_serde;
+use crate::ipfs::Cid;
+use crate::wallet::address_of;
+
+/// Largest integer value that can take part in a range predicate. Range proofs
+/// use hash chains whose length is bounded by this value.
+pub const MAX_PREDICATE_VALUE: u64 = 100_000;
+
+/// A pair of hash-chain values: one for lower bounds and one for upper bounds.
+type ChainPair = ([u8; 32], [u8; 32]);
+
+fn hash(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn hash_chain(seed: &[u8; 32], iterations: u64) -> [u8; 32] {
+    let mut current = *seed;
+    for _ in 0..iterations {
+        current = hash(&current);
+    }
+    current
+}
+
+fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn salted_hash(name: &str, value: &str, salt: &[u8; 32]) -> [u8; 32] {
+    let mut data = Vec::new();
+    data.extend_from_slice(salt);
+    data.extend_from_slice(name.as_bytes());
+    data.push(0);
+    data.extend_from_slice(value.as_bytes());
+    hash(&data)
+}
+
+/// Public commitment to a single attribute. Numeric attributes additionally
+/// carry two hash-chain commitments, `H^v(up_seed)` and `H^(MAX - v)(down_seed)`,
+/// which allow proving lower and upper bounds without revealing `v`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AttributeCommitment {
+    pub name: String,
+    pub value_hash: [u8; 32],
+    pub range_commitments: Option<ChainPair>,
+}
+
+/// The holder's private opening information for an attribute commitment.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttributeSecret {
+    pub value: String,
+    salt: [u8; 32],
+    range_seeds: Option<ChainPair>,
+}
+
+/// A credential whose attributes are only visible as commitments, signed by
+/// the issuing DID.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedCredential {
+    pub subject: String,
+    pub issuer: String,
+    #[serde(with = "public_key_serde")]
+    pub issuer_key: PublicKey,
+    pub commitments: Vec<AttributeCommitment>,
+    /// Supporting documents stored on IPFS, covered by the signature.
+    #[serde(default)]
+    pub evidence: Vec<Cid>,
+    pub signature: Vec<u8>,
+}
+
+impl CommittedCredential {
+    /// Issues a credential over `attributes`. Values that parse as integers no
+    /// larger than [`MAX_PREDICATE_VALUE`] support range predicates.
+    pub fn issue(
+        issuer: &str,
+        issuer_keypair: &Keypair,
+        subject: &str,
+        attributes: &HashMap<String, String>,
+    ) -> (Self, HashMap<String, AttributeSecret>) {
+        Self::issue_with_evidence(issuer, issuer_keypair, subject, attributes, Vec::new())
+    }
+
+    /// Like `issue`, referencing supporting documents stored on IPFS.
+    pub fn issue_with_evidence(
+        issuer: &str,
+        issuer_keypair: &Keypair,
+        subject: &str,
+        attributes: &HashMap<String, String>,
+        evidence: Vec<Cid>,
+    ) -> (Self, HashMap<String, AttributeSecret>) {
+        let mut names: Vec<&String> = attributes.keys().collect();
+        names.sort();
+
+        let mut commitments = Vec::new();
+        let mut secrets = HashMap::new();
+        for name in names {
+            let value = &attributes[name];
+            let salt = random_bytes();
+            let numeric = value.parse::<u64>().ok().filter(|v| *v <= MAX_PREDICATE_VALUE);
+            let range_seeds = numeric.map(|_| (random_bytes(), random_bytes()));
+            let range_commitments = numeric.zip(range_seeds).map(|(v, (up, down))| {
+                (hash_chain(&up, v), hash_chain(&down, MAX_PREDICATE_VALUE - v))
+            });
+
+            commitments.push(AttributeCommitment {
+                name: name.clone(),
+                value_hash: salted_hash(name, value, &salt),
+                range_commitments,
+            });
+            secrets.insert(name.clone(), AttributeSecret {
+                value: value.clone(),
+                salt,
+                range_seeds,
+            });
+        }
+
+        let mut credential = CommittedCredential {
+            subject: subject.to_string(),
+            issuer: issuer.to_string(),
+            issuer_key: issuer_keypair.public,
+            commitments,
+            evidence,
+            signature: Vec::new(),
+        };
+        credential.signature = issuer_keypair.sign(&credential.signing_payload()).to_bytes().to_vec();
+        (credential, secrets)
+    }
+
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(self.subject.as_bytes());
+        bytes.extend_from_slice(self.issuer.as_bytes());
+        bytes.extend_from_slice(&self.issuer_key.to_bytes());
+        bytes.extend_from_slice(&serde_json::to_vec(&self.commitments).unwrap_or_default());
+        // Left out when empty, so credentials issued without evidence keep
+        // their signatures
+        if !self.evidence.is_empty() {
+            bytes.extend_from_slice(&serde_json::to_vec(&self.evidence).unwrap_or_default());
+        }
+        bytes
+    }
+
+    /// Checks the signature, made with the key the issuer DID derives from, so
+    /// that nobody can issue in the name of another DID.
+    pub fn verify_signature(&self) -> bool {
+        if self.issuer != address_of(&self.issuer_key) {
+            return false;
+        }
+        match Signature::from_bytes(&self.signature) {
+            Ok(signature) => self.issuer_key.verify(&self.signing_payload(), &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn commitment(&self, name: &str) -> Option<&AttributeCommitment> {
+        self.commitments.iter().find(|c| c.name == name)
+    }
+}
+
+/// A statement about an attribute. Dates should be encoded as integers (for
+/// example a year, or days since the epoch) to be usable in range predicates.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Predicate {
+    Equals(String),
+    GreaterOrEqual(u64),
+    LessThan(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum ProofData {
+    Opening { value: String, salt: [u8; 32] },
+    ChainLink([u8; 32]),
+}
+
+/// A proof that an attribute of a committed credential satisfies a predicate.
+/// Only equality proofs reveal the attribute value. The holder signs it over
+/// the verifier's nonce, so it cannot be presented by anyone else or replayed
+/// to another verifier.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisclosureProof {
+    pub credential: CommittedCredential,
+    pub attribute: String,
+    pub predicate: Predicate,
+    proof: ProofData,
+    /// The challenge of the verifier the proof was made for.
+    pub nonce: Vec<u8>,
+    #[serde(with = "public_key_serde")]
+    pub holder_key: PublicKey,
+    holder_signature: Vec<u8>,
+}
+
+impl DisclosureProof {
+    pub fn create(
+        credential: &CommittedCredential,
+        secrets: &HashMap<String, AttributeSecret>,
+        holder_keypair: &Keypair,
+        attribute: &str,
+        predicate: Predicate,
+        nonce: &[u8],
+    ) -> Result<Self, String> {
+        let secret = secrets.get(attribute)
+            .ok_or_else(|| format!("No secret held for attribute: {}", attribute))?;
+
+        let proof = match &predicate {
+            Predicate::Equals(expected) => {
+                if &secret.value != expected {
+                    return Err("Attribute does not satisfy the predicate".to_string());
+                }
+                ProofData::Opening { value: secret.value.clone(), salt: secret.salt }
+            }
+            Predicate::GreaterOrEqual(bound) => {
+                let (value, (up_seed, _)) = Self::numeric(secret)?;
+                if value < *bound {
+                    return Err("Attribute does not satisfy the predicate".to_string());
+                }
+                ProofData::ChainLink(hash_chain(&up_seed, value - bound))
+            }
+            Predicate::LessThan(bound) => {
+                let (value, (_, down_seed)) = Self::numeric(secret)?;
+                if value >= *bound || *bound > MAX_PREDICATE_VALUE + 1 {
+                    return Err("Attribute does not satisfy the predicate".to_string());
+                }
+                // value < bound  <=>  MAX - value >= MAX - bound + 1
+                ProofData::ChainLink(hash_chain(&down_seed, bound - 1 - value))
+            }
+        };
+
+        let mut disclosure = DisclosureProof {
+            credential: credential.clone(),
+            attribute: attribute.to_string(),
+            predicate,
+            proof,
+            nonce: nonce.to_vec(),
+            holder_key: holder_keypair.public,
+            holder_signature: Vec::new(),
+        };
+        disclosure.holder_signature = holder_keypair.sign(&disclosure.signing_payload()).to_bytes().to_vec();
+        Ok(disclosure)
+    }
+
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.credential.signature);
+        bytes.extend_from_slice(self.attribute.as_bytes());
+        bytes.extend_from_slice(&serde_json::to_vec(&self.predicate).unwrap_or_default());
+        bytes.extend_from_slice(&serde_json::to_vec(&self.proof).unwrap_or_default());
+        bytes.extend_from_slice(&self.nonce);
+        bytes
+    }
+
+    /// Checks that the proof was made by the subject of the credential, with
+    /// the key their DID derives from, for the verifier's `nonce`.
+    fn verify_holder(&self, nonce: &[u8]) -> bool {
+        if self.nonce != nonce || self.credential.subject != address_of(&self.holder_key) {
+            return false;
+        }
+        match Signature::from_bytes(&self.holder_signature) {
+            Ok(signature) => self.holder_key.verify(&self.signing_payload(), &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn numeric(secret: &AttributeSecret) -> Result<(u64, ChainPair), String> {
+        let value = secret.value.parse::<u64>().map_err(|_| "Attribute is not numeric".to_string())?;
+        let seeds = secret.range_seeds.ok_or("Attribute does not support range predicates")?;
+        Ok((value, seeds))
+    }
+
+    /// Checks the issuer and holder signatures, that the proof was made for
+    /// `nonce`, and that it establishes the predicate against the committed
+    /// attribute.
+    pub fn verify(&self, nonce: &[u8]) -> bool {
+        if !self.credential.verify_signature() || !self.verify_holder(nonce) {
+            return false;
+        }
+        let commitment = match self.credential.commitment(&self.attribute) {
+            Some(commitment) => commitment,
+            None => return false,
+        };
+
+        match (&self.predicate, &self.proof) {
+            (Predicate::Equals(expected), ProofData::Opening { value, salt }) => {
+                value == expected && salted_hash(&self.attribute, value, salt) == commitment.value_hash
+            }
+            (Predicate::GreaterOrEqual(bound), ProofData::ChainLink(link)) => {
+                *bound <= MAX_PREDICATE_VALUE
+                    && commitment.range_commitments
+                        .is_some_and(|(up, _)| hash_chain(link, *bound) == up)
+            }
+            (Predicate::LessThan(bound), ProofData::ChainLink(link)) => {
+                (1..=MAX_PREDICATE_VALUE + 1).contains(bound)
+                    && commitment.range_commitments
+                        .is_some_and(|(_, down)| hash_chain(link, MAX_PREDICATE_VALUE + 1 - bound) == down)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: &[u8] = b"verifier challenge";
+
+    fn issue() -> (CommittedCredential, HashMap<String, AttributeSecret>, Keypair) {
+        let issuer_keypair = Keypair::generate(&mut OsRng {});
+        let holder_keypair = Keypair::generate(&mut OsRng {});
+        let mut attributes = HashMap::new();
+        attributes.insert("membership_year".to_string(), "2019".to_string());
+        attributes.insert("cooperative".to_string(), "coopX".to_string());
+        let (credential, secrets) = CommittedCredential::issue(
+            &address_of(&issuer_keypair.public),
+            &issuer_keypair,
+            &address_of(&holder_keypair.public),
+            &attributes,
+        );
+        (credential, secrets, holder_keypair)
+    }
+
+    #[test]
+    fn test_range_predicates() {
+        let (credential, secrets, holder) = issue();
+
+        let before_2023 = DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::LessThan(2023), NONCE).unwrap();
+        assert!(before_2023.verify(NONCE));
+
+        let since_2010 = DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::GreaterOrEqual(2010), NONCE).unwrap();
+        assert!(since_2010.verify(NONCE));
+
+        assert!(DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::LessThan(2019), NONCE).is_err());
+        assert!(DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::GreaterOrEqual(2020), NONCE).is_err());
+    }
+
+    #[test]
+    fn test_range_proof_cannot_be_stretched() {
+        let (credential, secrets, holder) = issue();
+        let mut proof = DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::LessThan(2023), NONCE).unwrap();
+        proof.predicate = Predicate::LessThan(2015);
+        assert!(!proof.verify(NONCE));
+
+        let mut proof = DisclosureProof::create(&credential, &secrets, &holder, "membership_year", Predicate::GreaterOrEqual(2010), NONCE).unwrap();
+        proof.predicate = Predicate::GreaterOrEqual(2020);
+        assert!(!proof.verify(NONCE));
+    }
+
+    #[test]
+    fn test_equality_and_tampering() {
+        let (credential, secrets, holder) = issue();
+        let proof = DisclosureProof::create(&credential, &secrets, &holder, "cooperative", Predicate::Equals("coopX".to_string()), NONCE).unwrap();
+        assert!(proof.verify(NONCE));
+
+        let mut tampered = proof.clone();
+        tampered.credential.subject = "did:icn:mallory".to_string();
+        assert!(!tampered.verify(NONCE));
+
+        let mut wrong_value = proof;
+        wrong_value.predicate = Predicate::Equals("coopY".to_string());
+        assert!(!wrong_value.verify(NONCE));
+    }
+
+    #[test]
+    fn test_issuer_key_must_derive_issuer_did() {
+        let impostor = Keypair::generate(&mut OsRng {});
+        let holder = Keypair::generate(&mut OsRng {});
+        let mut attributes = HashMap::new();
+        attributes.insert("cooperative".to_string(), "coopX".to_string());
+        let (credential, secrets) = CommittedCredential::issue("did:icn:coop", &impostor, &address_of(&holder.public), &attributes);
+        assert!(!credential.verify_signature());
+
+        let proof = DisclosureProof::create(&credential, &secrets, &holder, "cooperative", Predicate::Equals("coopX".to_string()), NONCE).unwrap();
+        assert!(!proof.verify(NONCE));
+    }
+
+    #[test]
+    fn test_proof_is_bound_to_holder_and_nonce() {
+        let (credential, secrets, holder) = issue();
+        let proof = DisclosureProof::create(&credential, &secrets, &holder, "cooperative", Predicate::Equals("coopX".to_string()), NONCE).unwrap();
+        assert!(!proof.verify(b"another verifier"), "not replayable to another verifier");
+
+        // Someone who got hold of the credential and its openings cannot
+        // present it as their own
+        let thief = Keypair::generate(&mut OsRng {});
+        let stolen = DisclosureProof::create(&credential, &secrets, &thief, "cooperative", Predicate::Equals("coopX".to_string()), NONCE).unwrap();
+        assert!(!stolen.verify(NONCE));
+    }
+}
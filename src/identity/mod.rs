@@ -1,3 +1,13 @@
 pub mod did;
+pub mod didcomm;
+pub mod disclosure;
+pub mod personhood;
+pub mod resolution;
+pub mod revocation;
 
-pub use did::DecentralizedIdentity;
\ No newline at end of file
+pub use did::{DecentralizedIdentity, DidManager};
+pub use didcomm::{EncryptedMessage, Mailbox, PlainMessage};
+pub use disclosure::{CommittedCredential, DisclosureProof, Predicate};
+pub use personhood::{Attestation, PersonhoodRegistry};
+pub use resolution::{DataVerification, DidDocument, DidResolution, DidResolver};
+pub use revocation::{RevocationAction, RevocationRegistry};
\ No newline at end of file
@@ -0,0 +1,225 @@
+// src/identity/personhood.rs
+
+use std::collections::{BTreeSet, HashMap};
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::clock::SharedClock;
+use super::did::{public_key_serde, DecentralizedIdentity, DidManager};
+use super::revocation::RevocationRegistry;
+
+/// A statement, signed by the attester's key, that the DID `subject` belongs
+/// to a unique human.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attestation {
+    pub subject: String,
+    /// The DID whose current key is `attester_key`.
+    pub attester: String,
+    #[serde(with = "public_key_serde")]
+    pub attester_key: PublicKey,
+    pub attested_at: DateTime<Utc>,
+    pub signature: Vec<u8>,
+}
+
+impl Attestation {
+    pub fn new(subject: &str, attester_keypair: &Keypair, attested_at: DateTime<Utc>) -> Self {
+        let attester = DecentralizedIdentity::from_public_key(attester_keypair.public, HashMap::new()).id;
+        Self::by_did(subject, &attester, attester_keypair, attested_at)
+    }
+
+    /// Like `new`, for an attester whose DID has rotated to `attester_keypair`.
+    pub fn by_did(subject: &str, attester: &str, attester_keypair: &Keypair, attested_at: DateTime<Utc>) -> Self {
+        let mut attestation = Attestation {
+            subject: subject.to_string(),
+            attester: attester.to_string(),
+            attester_key: attester_keypair.public,
+            attested_at,
+            signature: Vec::new(),
+        };
+        attestation.signature = attester_keypair.sign(&attestation.signing_payload()).to_bytes().to_vec();
+        attestation
+    }
+
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"icn-personhood:");
+        payload.extend_from_slice(self.subject.as_bytes());
+        payload.push(0);
+        payload.extend_from_slice(self.attester.as_bytes());
+        payload.extend_from_slice(&self.attested_at.timestamp().to_le_bytes());
+        payload
+    }
+
+    /// Whether the attestation was signed by the current key of its attester
+    /// DID: the key its rotations in `dids` lead to, or for a DID that has
+    /// not been registered, the key it derives from.
+    pub fn verify(&self, dids: &DidManager) -> bool {
+        let signed_by_attester = match dids.get_did(&self.attester) {
+            Some(attester) => attester.public_key == self.attester_key,
+            None => DecentralizedIdentity::from_public_key(self.attester_key, HashMap::new()).id == self.attester,
+        };
+        signed_by_attester && Signature::from_bytes(&self.signature)
+            .is_ok_and(|signature| self.attester_key.verify(&self.signing_payload(), &signature).is_ok())
+    }
+}
+
+/// Who vouches that DIDs belong to unique humans, so that one person cannot
+/// count as several members. A DID is a person while an attestation of a
+/// trusted verifier, or of `member_attestations` members who are persons
+/// themselves, is fresher than `validity`.
+pub struct PersonhoodRegistry {
+    verifiers: BTreeSet<String>,
+    member_attestations: usize,
+    validity: Duration,
+    attestations: HashMap<String, Vec<Attestation>>,
+    clock: SharedClock,
+}
+
+impl PersonhoodRegistry {
+    pub fn new(member_attestations: usize, validity: Duration) -> Self {
+        PersonhoodRegistry {
+            verifiers: BTreeSet::new(),
+            member_attestations,
+            validity,
+            attestations: HashMap::new(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Trusts the attestations of the DID `verifier` on their own.
+    pub fn add_verifier(&mut self, verifier: &str) {
+        self.verifiers.insert(verifier.to_string());
+    }
+
+    pub fn remove_verifier(&mut self, verifier: &str) -> bool {
+        self.verifiers.remove(verifier)
+    }
+
+    pub fn is_verifier(&self, did: &str) -> bool {
+        self.verifiers.contains(did)
+    }
+
+    /// Records an attestation, made by a trusted verifier or by a member who
+    /// is a person, of someone else. Each attester counts once per subject; a
+    /// new attestation replaces their old one.
+    pub fn attest(&mut self, attestation: Attestation, dids: &DidManager) -> Result<(), String> {
+        if !attestation.verify(dids) {
+            return Err("Invalid attestation signature".to_string());
+        }
+        if attestation.subject == attestation.attester {
+            return Err("Nobody attests their own personhood".to_string());
+        }
+        if attestation.attested_at > self.clock.now() {
+            return Err("The attestation is dated in the future".to_string());
+        }
+        if !self.is_verifier(&attestation.attester) && !self.is_person(&attestation.attester) {
+            return Err(format!("{} is neither a verifier nor an attested member", attestation.attester));
+        }
+        info!("{} attested the personhood of {}", attestation.attester, attestation.subject);
+        let attestations = self.attestations.entry(attestation.subject.clone()).or_default();
+        attestations.retain(|existing| existing.attester != attestation.attester);
+        attestations.push(attestation);
+        Ok(())
+    }
+
+    /// Withdraws the attestation of `attester` for `subject`.
+    pub fn withdraw(&mut self, subject: &str, attester: &str) -> bool {
+        let attestations = match self.attestations.get_mut(subject) {
+            Some(attestations) => attestations,
+            None => return false,
+        };
+        let before = attestations.len();
+        attestations.retain(|attestation| attestation.attester != attester);
+        before != attestations.len()
+    }
+
+    /// The unexpired attestations of `subject`.
+    pub fn attestations_of(&self, subject: &str) -> Vec<&Attestation> {
+        let cutoff = self.clock.now() - self.validity;
+        self.attestations.get(subject)
+            .map(|attestations| attestations.iter().filter(|attestation| attestation.attested_at > cutoff).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether `did` is attested to belong to a unique human.
+    pub fn is_person(&self, did: &str) -> bool {
+        let attestations = self.attestations_of(did);
+        attestations.iter().any(|attestation| self.is_verifier(&attestation.attester))
+            || (self.member_attestations > 0 && attestations.len() >= self.member_attestations)
+    }
+
+    /// Like `is_person`, also failing for a DID revoked network-wide.
+    pub fn check(&self, did: &str, revocations: &RevocationRegistry) -> Result<(), String> {
+        if revocations.is_did_revoked(did) {
+            return Err(format!("DID has been revoked: {}", did));
+        }
+        if !self.is_person(did) {
+            return Err(format!("{} has no valid proof of personhood", did));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use crate::identity::did::KeyRotation;
+    use rand::rngs::OsRng;
+
+    fn did_of(keypair: &Keypair) -> String {
+        DecentralizedIdentity::from_public_key(keypair.public, HashMap::new()).id
+    }
+
+    #[test]
+    fn test_personhood_from_a_verifier_or_enough_members() {
+        let clock = MockClock::new();
+        let mut registry = PersonhoodRegistry::new(2, Duration::days(365)).with_clock(clock.clone().into());
+        let [verifier, alice, bob, carol] = std::array::from_fn(|_| Keypair::generate(&mut OsRng {}));
+        registry.add_verifier(&did_of(&verifier));
+        let dids = DidManager::new();
+        let now = clock.now();
+
+        assert!(registry.attest(Attestation::new(&did_of(&carol), &alice, now), &dids).is_err(), "alice is not attested yet");
+        registry.attest(Attestation::new(&did_of(&alice), &verifier, now), &dids).unwrap();
+        registry.attest(Attestation::new(&did_of(&bob), &verifier, now), &dids).unwrap();
+        assert!(registry.is_person(&did_of(&alice)));
+
+        registry.attest(Attestation::new(&did_of(&carol), &alice, now), &dids).unwrap();
+        registry.attest(Attestation::new(&did_of(&carol), &alice, now), &dids).unwrap();
+        assert!(!registry.is_person(&did_of(&carol)), "one member counts once");
+        let mut forged = Attestation::new(&did_of(&carol), &bob, now);
+        forged.attester = did_of(&verifier);
+        assert!(registry.attest(forged, &dids).is_err());
+        registry.attest(Attestation::new(&did_of(&carol), &bob, now), &dids).unwrap();
+        assert!(registry.is_person(&did_of(&carol)));
+
+        clock.advance(std::time::Duration::from_secs(366 * 24 * 60 * 60));
+        assert!(!registry.is_person(&did_of(&alice)), "attestations expire");
+    }
+
+    #[test]
+    fn test_attester_signs_with_its_rotated_key() {
+        let clock = MockClock::new();
+        let mut registry = PersonhoodRegistry::new(2, Duration::days(365)).with_clock(clock.clone().into());
+        let (verifier, old_key) = DecentralizedIdentity::new(HashMap::new());
+        let verifier_id = verifier.id.clone();
+        registry.add_verifier(&verifier_id);
+        let mut dids = DidManager::new();
+        dids.add_did(verifier);
+        let new_key = Keypair::generate(&mut OsRng {});
+        dids.rotate_key(KeyRotation::new(&verifier_id, &old_key, new_key.public, 0), 1).unwrap();
+        let [alice, bob] = std::array::from_fn(|_| Keypair::generate(&mut OsRng {}));
+
+        assert!(registry.attest(Attestation::by_did(&did_of(&alice), &verifier_id, &old_key, clock.now()), &dids).is_err(), "the retired key");
+        registry.attest(Attestation::by_did(&did_of(&alice), &verifier_id, &new_key, clock.now()), &dids).unwrap();
+        assert!(registry.is_person(&did_of(&alice)));
+        assert!(registry.attest(Attestation::by_did(&did_of(&bob), &verifier_id, &bob, clock.now()), &dids).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use crate::network::Packet;
+use crate::node::ContentStore;
+use super::did::{DecentralizedIdentity, DidManager, KeyRecord, KeyRotation};
+use super::revocation::RevocationRegistry;
+
+/// Name prefix under which DID documents are published on the ICN data plane.
+pub const DID_NAME_PREFIX: &str = "/icn/did/";
+
+/// The resolvable view of a DID: the identity itself, its key history and the
+/// signed rotations that lead from the key its identifier derives from to its
+/// current key. Whether the DID is revoked is not part of it; that is read
+/// from the revocation registry of the chain.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DidDocument {
+    pub identity: DecentralizedIdentity,
+    pub key_history: Vec<KeyRecord>,
+    #[serde(default)]
+    pub rotations: Vec<KeyRotation>,
+}
+
+impl DidDocument {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| e.to_string())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Checks that the document proves its current key. A `did:icn`
+    /// identifier is derived from the DID's first key, and each later key
+    /// must have been handed over by a rotation signed with the key before
+    /// it, so nobody but the controller can append a key.
+    pub fn verify(&self) -> Result<(), String> {
+        let first = self.key_history.first().ok_or("DID document has no keys")?;
+        if format!("did:icn:{}", hex::encode(first.public_key.to_bytes())) != self.identity.id {
+            return Err("DID document key history does not match its identifier".to_string());
+        }
+        if self.rotations.len() + 1 != self.key_history.len() {
+            return Err(format!("DID document has {} keys but {} rotations", self.key_history.len(), self.rotations.len()));
+        }
+        for (sequence, (rotation, keys)) in self.rotations.iter().zip(self.key_history.windows(2)).enumerate() {
+            let links = rotation.did_id == self.identity.id
+                && rotation.sequence == sequence as u64
+                && rotation.previous_key == keys[0].public_key
+                && rotation.new_key == keys[1].public_key;
+            if !links || !rotation.verify() {
+                return Err(format!("Rotation {} of {} is not signed by the key it replaces", sequence, self.identity.id));
+            }
+        }
+        if self.key_history.last().map(|record| record.public_key) != Some(self.identity.public_key) {
+            return Err("DID document does not end with its current key".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub fn did_content_name(did_id: &str) -> String {
+    format!("{}{}", DID_NAME_PREFIX, did_id)
+}
+
+pub fn did_from_content_name(name: &str) -> Option<&str> {
+    name.strip_prefix(DID_NAME_PREFIX).filter(|id| id.starts_with("did:icn:"))
+}
+
+/// Outcome of a resolution attempt on the local node.
+#[derive(Debug)]
+pub enum DidResolution {
+    Resolved(Box<DidDocument>),
+    /// The DID is not known locally; the interest must be sent to the network.
+    Pending(Packet),
+}
+
+/// Outcome of checking a Data packet against its publisher's DID.
+#[derive(Debug, PartialEq)]
+pub enum DataVerification {
+    Verified,
+    /// The publisher's DID document is not known here yet and must be
+    /// resolved before the packet can be checked.
+    UnknownPublisher(String),
+    Rejected(String),
+}
+
+pub struct DidResolver;
+
+impl DidResolver {
+    /// Resolves a DID from the local registry, or from a document cached in
+    /// the content store that still proves its key.
+    pub fn resolve_locally(
+        did_id: &str,
+        did_manager: &DidManager,
+        content_store: &ContentStore,
+    ) -> DidResolution {
+        if let Some(document) = Self::local_document(did_id, did_manager) {
+            return DidResolution::Resolved(Box::new(document));
+        }
+
+        let name = did_content_name(did_id);
+        let cached = content_store.get(&name)
+            .and_then(|bytes| DidDocument::from_bytes(&bytes).ok())
+            .filter(|document| document.identity.id == did_id && document.verify().is_ok());
+        if let Some(document) = cached {
+            return DidResolution::Resolved(Box::new(document));
+        }
+
+        DidResolution::Pending(Packet::interest(&name))
+    }
+
+    /// Answers a DID interest from the local registry or the content store.
+    pub fn answer_interest(
+        interest: &Packet,
+        did_manager: &DidManager,
+        content_store: &ContentStore,
+    ) -> Option<Packet> {
+        let did_id = did_from_content_name(&interest.name)?;
+        let content = match Self::local_document(did_id, did_manager) {
+            Some(document) => document.to_bytes().ok()?,
+            None => content_store.get(&interest.name)?,
+        };
+        Some(Packet::data(&interest.name, content))
+    }
+
+    /// Validates a DID document received from the network, down to every
+    /// rotation of its keys, and caches it.
+    pub fn accept_data(data: &Packet, content_store: &mut ContentStore) -> Result<DidDocument, String> {
+        let did_id = did_from_content_name(&data.name)
+            .ok_or_else(|| format!("Not a DID document name: {}", data.name))?;
+        let document = DidDocument::from_bytes(&data.content)?;
+        if document.identity.id != did_id {
+            return Err("DID document does not match the requested DID".to_string());
+        }
+        document.verify()?;
+        content_store.add(data.name.clone(), data.content.clone());
+        Ok(document)
+    }
+
+    /// Checks that a Data packet is signed by the current key of the DID its
+    /// key locator names, and that the DID has not been revoked. Only a
+    /// document whose rotations prove that key is trusted, whether it comes
+    /// from the local registry or the content store.
+    pub fn verify_data(
+        data: &Packet,
+        did_manager: &DidManager,
+        registry: &RevocationRegistry,
+        content_store: &ContentStore,
+    ) -> DataVerification {
+        let did_id = match (&data.signature, data.publisher()) {
+            (None, _) => return DataVerification::Rejected("unsigned".to_string()),
+            (Some(info), None) => return DataVerification::Rejected(format!("key locator {} is not a DID", info.key_locator)),
+            (Some(_), Some(did_id)) => did_id,
+        };
+        let document = match Self::resolve_locally(did_id, did_manager, content_store) {
+            DidResolution::Resolved(document) => document,
+            DidResolution::Pending(_) => return DataVerification::UnknownPublisher(did_id.to_string()),
+        };
+        if registry.is_did_revoked(did_id) {
+            return DataVerification::Rejected(format!("publisher {} has been revoked", did_id));
+        }
+        if !data.verify(&document.identity.public_key) {
+            return DataVerification::Rejected(format!("signature does not match publisher {}", did_id));
+        }
+        DataVerification::Verified
+    }
+
+    fn local_document(did_id: &str, did_manager: &DidManager) -> Option<DidDocument> {
+        let identity = did_manager.get_did(did_id)?.clone();
+        let key_history = did_manager.get_key_history(did_id).cloned().unwrap_or_default();
+        Some(DidDocument {
+            identity,
+            key_history,
+            rotations: did_manager.get_rotations(did_id).to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_resolution_across_nodes() {
+        // The node that knows the DID
+        let mut publisher = DidManager::new();
+        let mut attributes = HashMap::new();
+        attributes.insert("cooperative".to_string(), "coopX".to_string());
+        let (did, _keypair) = DecentralizedIdentity::new(attributes);
+        let did_id = did.id.clone();
+        publisher.add_did(did);
+        let publisher_store = ContentStore::new();
+
+        // A remote node that does not
+        let remote = DidManager::new();
+        let mut remote_store = ContentStore::new();
+
+        let interest = match DidResolver::resolve_locally(&did_id, &remote, &remote_store) {
+            DidResolution::Pending(interest) => interest,
+            DidResolution::Resolved(_) => panic!("DID should not resolve locally"),
+        };
+        assert_eq!(interest.name, did_content_name(&did_id));
+
+        let data = DidResolver::answer_interest(&interest, &publisher, &publisher_store).unwrap();
+        let document = DidResolver::accept_data(&data, &mut remote_store).unwrap();
+        assert_eq!(document.identity.attributes["cooperative"], "coopX");
+
+        // Subsequent resolutions are served from the cache
+        match DidResolver::resolve_locally(&did_id, &remote, &remote_store) {
+            DidResolution::Resolved(cached) => assert_eq!(cached.identity.id, did_id),
+            DidResolution::Pending(_) => panic!("DID should resolve from the content store"),
+        }
+    }
+
+    #[test]
+    fn test_reject_mismatched_document() {
+        let mut publisher = DidManager::new();
+        let (did, _) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        publisher.add_did(did);
+        let store = ContentStore::new();
+
+        let interest = Packet::interest(&did_content_name(&did_id));
+        let mut data = DidResolver::answer_interest(&interest, &publisher, &store).unwrap();
+        let (other, _) = DecentralizedIdentity::new(HashMap::new());
+        data.name = did_content_name(&other.id);
+
+        let mut remote_store = ContentStore::new();
+        assert!(DidResolver::accept_data(&data, &mut remote_store).is_err());
+        assert!(remote_store.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_rotated_did_through_its_rotations() {
+        let mut publisher = DidManager::new();
+        let (did, keypair) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        publisher.add_did(did);
+        let new_keypair = Keypair::generate(&mut OsRng {});
+        publisher.rotate_key(KeyRotation::new(&did_id, &keypair, new_keypair.public, 0), 5).unwrap();
+
+        let interest = Packet::interest(&did_content_name(&did_id));
+        let data = DidResolver::answer_interest(&interest, &publisher, &ContentStore::new()).unwrap();
+        let mut remote_store = ContentStore::new();
+        let document = DidResolver::accept_data(&data, &mut remote_store).unwrap();
+        assert_eq!(document.identity.public_key, new_keypair.public);
+
+        // Without the rotation the new key is not proven
+        let mut stripped = document.clone();
+        stripped.rotations.clear();
+        assert!(stripped.verify().is_err());
+    }
+
+    #[test]
+    fn test_verify_data_against_publisher_did() {
+        let mut did_manager = DidManager::new();
+        let mut registry = RevocationRegistry::new();
+        let content_store = ContentStore::new();
+        let (did, keypair) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        let data = Packet::data("/coopX/docs", vec![1]).signed(&did_id, &keypair);
+
+        assert_eq!(
+            DidResolver::verify_data(&data, &did_manager, &registry, &content_store),
+            DataVerification::UnknownPublisher(did_id.clone())
+        );
+        did_manager.add_did(did);
+        assert_eq!(DidResolver::verify_data(&data, &did_manager, &registry, &content_store), DataVerification::Verified);
+
+        let forged = Packet { content: vec![2], ..data.clone() };
+        assert!(matches!(DidResolver::verify_data(&forged, &did_manager, &registry, &content_store), DataVerification::Rejected(_)));
+        let unsigned = Packet::data("/coopX/docs", vec![1]);
+        assert!(matches!(DidResolver::verify_data(&unsigned, &did_manager, &registry, &content_store), DataVerification::Rejected(_)));
+
+        let revocation = did_manager.revoke_did(&did_id, &did_id, "key compromised", &keypair).unwrap();
+        registry.apply(&revocation, 0).unwrap();
+        assert!(matches!(DidResolver::verify_data(&data, &did_manager, &registry, &content_store), DataVerification::Rejected(_)));
+    }
+
+    #[test]
+    fn test_verify_data_rejects_forged_document_with_appended_key() {
+        let did_manager = DidManager::new();
+        let registry = RevocationRegistry::new();
+        let (did, _) = DecentralizedIdentity::new(HashMap::new());
+        let did_id = did.id.clone();
+        let victim_key = did.public_key;
+
+        // An attacker appends its own key to the victim's history and signs
+        // the rotation with it, since it does not hold the victim's key
+        let attacker = Keypair::generate(&mut OsRng {});
+        let forged = DidDocument {
+            identity: DecentralizedIdentity { public_key: attacker.public, ..did },
+            key_history: vec![
+                KeyRecord { public_key: victim_key, valid_from: 0, valid_until: Some(1) },
+                KeyRecord { public_key: attacker.public, valid_from: 1, valid_until: None },
+            ],
+            rotations: vec![KeyRotation {
+                previous_key: victim_key,
+                ..KeyRotation::new(&did_id, &attacker, attacker.public, 0)
+            }],
+        };
+        let name = did_content_name(&did_id);
+        let mut content_store = ContentStore::new();
+        assert!(DidResolver::accept_data(&Packet::data(&name, forged.to_bytes().unwrap()), &mut content_store).is_err());
+        assert!(content_store.is_empty());
+
+        // Even when it sits in the content store, it is not trusted
+        content_store.add(name, forged.to_bytes().unwrap());
+        let data = Packet::data("/coopX/docs", vec![1]).signed(&did_id, &attacker);
+        assert_eq!(
+            DidResolver::verify_data(&data, &did_manager, &registry, &content_store),
+            DataVerification::UnknownPublisher(did_id)
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+use crate::blockchain::payout::event;
+use crate::blockchain::Transaction;
+use crate::smart_contract::ContractEvent;
+
+/// The account revocation transactions are sent to.
+pub const REVOCATION_ACCOUNT: &str = "icn:revocation";
+
+/// What a revocation transaction does. Its sender, whose key must sign it,
+/// is the issuer or controller acting.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RevocationAction {
+    /// Opens a status list issued by the sender.
+    CreateStatusList { list_id: String },
+    /// Gives the next index of the sender's list to a credential it issued
+    /// to `holder`.
+    RegisterCredential { list_id: String, holder: String },
+    /// Revokes the credential at `index` of the sender's list.
+    RevokeCredential { list_id: String, index: usize },
+    /// Revokes `did_id`, as its controller or as the issuer of a credential
+    /// registered to it.
+    RevokeDid { did_id: String, reason: String },
+}
+
+/// Why and when an identity or credential was revoked.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RevocationEntry {
+    pub revoked_by: String,
+    pub reason: String,
+    pub revoked_at: DateTime<Utc>,
+}
+
+/// A bitstring status list. Each credential issued under the list is assigned
+/// an index; a set bit means the credential at that index is revoked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusList {
+    pub id: String,
+    pub issuer: String,
+    bits: Vec<u8>,
+    next_index: usize,
+    /// The holder of the credential at each index.
+    #[serde(default)]
+    holders: Vec<String>,
+}
+
+impl StatusList {
+    pub fn new(id: String, issuer: String) -> Self {
+        StatusList {
+            id,
+            issuer,
+            bits: Vec::new(),
+            next_index: 0,
+            holders: Vec::new(),
+        }
+    }
+
+    pub fn allocate_index(&mut self, holder: &str) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.holders.push(holder.to_string());
+        if self.bits.len() * 8 < self.next_index {
+            self.bits.push(0);
+        }
+        index
+    }
+
+    pub fn set_revoked(&mut self, index: usize) -> Result<(), String> {
+        if index >= self.next_index {
+            return Err(format!("Status index {} has not been allocated", index));
+        }
+        self.bits[index / 8] |= 1 << (index % 8);
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, index: usize) -> bool {
+        self.bits.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    /// Whether a credential of the list was issued to `holder`.
+    pub fn has_holder(&self, holder: &str) -> bool {
+        self.holders.iter().any(|registered| registered == holder)
+    }
+}
+
+/// Chain-held registry of revoked DIDs and credential status lists, so that
+/// every node can check revocation status without asking the issuer. It only
+/// changes by the signed revocation transactions of blocks.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RevocationRegistry {
+    revoked_dids: HashMap<String, RevocationEntry>,
+    status_lists: HashMap<String, StatusList>,
+}
+
+impl RevocationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the revocation transaction of a block timestamped
+    /// `timestamp`, returning the event for its receipt. One not signed by
+    /// its sender, or whose sender may not act on the list or DID it names,
+    /// fails.
+    pub fn apply(&mut self, transaction: &Transaction, timestamp: i64) -> Result<ContractEvent, String> {
+        let action = transaction.revocation.as_ref().ok_or("Not a revocation transaction")?;
+        if transaction.amount != 0.0 {
+            return Err("Revocation transactions move no funds".to_string());
+        }
+        transaction.check_signed_by(&transaction.from)?;
+        let by = transaction.from.as_str();
+        match action {
+            RevocationAction::CreateStatusList { list_id } => {
+                self.create_status_list(list_id.clone(), by.to_string())?;
+                Ok(event(list_id, "StatusListCreated", by.to_string()))
+            }
+            RevocationAction::RegisterCredential { list_id, holder } => {
+                let index = self.register_credential(list_id, by, holder)?;
+                Ok(event(list_id, "CredentialRegistered", format!("{} {}", index, holder)))
+            }
+            RevocationAction::RevokeCredential { list_id, index } => {
+                self.revoke_credential(list_id, *index, by)?;
+                Ok(event(list_id, "CredentialRevoked", index.to_string()))
+            }
+            RevocationAction::RevokeDid { did_id, reason } => {
+                let revoked_at = DateTime::from_timestamp(timestamp, 0).ok_or("Invalid block timestamp")?;
+                self.revoke_did(did_id, by, reason, revoked_at)?;
+                Ok(event(did_id, "DidRevoked", reason.clone()))
+            }
+        }
+    }
+
+    /// Whether `by` may revoke `did_id`: it is the DID itself, or the issuer
+    /// of a credential registered to it.
+    pub fn may_revoke(&self, did_id: &str, by: &str) -> bool {
+        by == did_id || self.status_lists.values().any(|list| list.issuer == by && list.has_holder(did_id))
+    }
+
+    fn revoke_did(&mut self, did_id: &str, revoked_by: &str, reason: &str, revoked_at: DateTime<Utc>) -> Result<(), String> {
+        if !self.may_revoke(did_id, revoked_by) {
+            return Err(format!("{} is neither {} nor the issuer of its credentials", revoked_by, did_id));
+        }
+        if self.revoked_dids.contains_key(did_id) {
+            return Err(format!("DID already revoked: {}", did_id));
+        }
+        info!("{} revoked DID {}: {}", revoked_by, did_id, reason);
+        self.revoked_dids.insert(did_id.to_string(), RevocationEntry {
+            revoked_by: revoked_by.to_string(),
+            reason: reason.to_string(),
+            revoked_at,
+        });
+        Ok(())
+    }
+
+    pub fn is_did_revoked(&self, did_id: &str) -> bool {
+        self.revoked_dids.contains_key(did_id)
+    }
+
+    pub fn get_did_revocation(&self, did_id: &str) -> Option<&RevocationEntry> {
+        self.revoked_dids.get(did_id)
+    }
+
+    fn create_status_list(&mut self, list_id: String, issuer: String) -> Result<(), String> {
+        if self.status_lists.contains_key(&list_id) {
+            return Err(format!("Status list already exists: {}", list_id));
+        }
+        self.status_lists.insert(list_id.clone(), StatusList::new(list_id, issuer));
+        Ok(())
+    }
+
+    pub fn get_status_list(&self, list_id: &str) -> Option<&StatusList> {
+        self.status_lists.get(list_id)
+    }
+
+    /// Reserves a slot in a status list for a credential newly issued to
+    /// `holder`. Only the issuer of the status list may register.
+    fn register_credential(&mut self, list_id: &str, issuer: &str, holder: &str) -> Result<usize, String> {
+        let list = self.status_lists.get_mut(list_id)
+            .ok_or_else(|| format!("Status list not found: {}", list_id))?;
+        if list.issuer != issuer {
+            return Err("Only the issuer may register credentials in this list".to_string());
+        }
+        Ok(list.allocate_index(holder))
+    }
+
+    /// Revokes a credential. Only the issuer of the status list may revoke.
+    fn revoke_credential(&mut self, list_id: &str, index: usize, issuer: &str) -> Result<(), String> {
+        let list = self.status_lists.get_mut(list_id)
+            .ok_or_else(|| format!("Status list not found: {}", list_id))?;
+        if list.issuer != issuer {
+            return Err("Only the issuer may revoke credentials in this list".to_string());
+        }
+        list.set_revoked(index)
+    }
+
+    pub fn is_credential_revoked(&self, list_id: &str, index: usize) -> bool {
+        self.status_lists.get(list_id).is_some_and(|list| list.is_revoked(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use crate::wallet::address_of;
+
+    fn signed(keypair: &Keypair, action: RevocationAction) -> Transaction {
+        let mut transaction = Transaction::revoke(address_of(&keypair.public), action, 1000);
+        transaction.sign(keypair).unwrap();
+        transaction
+    }
+
+    #[test]
+    fn test_revoke_did() {
+        let mut registry = RevocationRegistry::new();
+        let [alice, coop, mallory] = std::array::from_fn(|_| Keypair::generate(&mut OsRng {}));
+        let alice_did = address_of(&alice.public);
+        assert!(!registry.is_did_revoked(&alice_did));
+
+        let revoke = |reason: &str| RevocationAction::RevokeDid { did_id: alice_did.clone(), reason: reason.to_string() };
+        assert!(registry.apply(&signed(&mallory, revoke("Mallory says so")), 0).is_err());
+        let mut unsigned = Transaction::revoke(alice_did.clone(), revoke("Unsigned"), 1000);
+        assert!(registry.apply(&unsigned, 0).is_err());
+        unsigned.sign(&mallory).unwrap();
+        assert!(registry.apply(&unsigned, 0).is_err(), "signed by another key than the sender's");
+        assert!(!registry.is_did_revoked(&alice_did));
+
+        // The issuer of a credential Alice holds may revoke her
+        registry.apply(&signed(&coop, RevocationAction::CreateStatusList { list_id: "membership".to_string() }), 0).unwrap();
+        registry.apply(&signed(&coop, RevocationAction::RegisterCredential { list_id: "membership".to_string(), holder: alice_did.clone() }), 0).unwrap();
+        registry.apply(&signed(&coop, revoke("Left the cooperative")), 60).unwrap();
+        assert!(registry.is_did_revoked(&alice_did));
+        let entry = registry.get_did_revocation(&alice_did).unwrap();
+        assert_eq!((entry.reason.as_str(), entry.revoked_at.timestamp()), ("Left the cooperative", 60));
+        assert!(registry.apply(&signed(&alice, revoke("again")), 0).is_err());
+    }
+
+    #[test]
+    fn test_credential_status_list() {
+        let mut registry = RevocationRegistry::new();
+        let [coop, mallory] = std::array::from_fn(|_| Keypair::generate(&mut OsRng {}));
+        let list_id = "membership".to_string();
+        registry.apply(&signed(&coop, RevocationAction::CreateStatusList { list_id: list_id.clone() }), 0).unwrap();
+        assert!(registry.apply(&signed(&mallory, RevocationAction::CreateStatusList { list_id: list_id.clone() }), 0).is_err());
+
+        let register = |keypair: &Keypair| signed(keypair, RevocationAction::RegisterCredential { list_id: list_id.clone(), holder: "did:icn:alice".to_string() });
+        assert!(registry.apply(&register(&mallory), 0).is_err());
+        for _ in 0..10 {
+            registry.apply(&register(&coop), 0).unwrap();
+        }
+
+        let revoke = |keypair: &Keypair, index| signed(keypair, RevocationAction::RevokeCredential { list_id: list_id.clone(), index });
+        assert!(registry.apply(&revoke(&mallory, 9), 0).is_err());
+        registry.apply(&revoke(&coop, 9), 0).unwrap();
+
+        assert!(registry.is_credential_revoked("membership", 9));
+        assert!(!registry.is_credential_revoked("membership", 8));
+        assert!(registry.apply(&revoke(&coop, 10), 0).is_err());
+    }
+}
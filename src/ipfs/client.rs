@@ -0,0 +1,179 @@
+// src/ipfs/client.rs
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::RwLock;
+use std::time::Duration;
+use serde::Deserialize;
+use crate::error::{Error, Result};
+use super::{Cid, MAX_BLOCK_SIZE};
+
+/// How long a request to the IPFS daemon may take.
+const KUBO_TIMEOUT: Duration = Duration::from_secs(30);
+const MULTIPART_BOUNDARY: &str = "icn-ipfs-boundary";
+
+/// An IPFS node the ICN node stores and pins content on. Content is kept as
+/// single raw blocks, so that its CID can be checked against the bytes.
+pub trait IpfsClient: Send + Sync {
+    /// Stores `content`, returning its CID.
+    fn add(&self, content: &[u8]) -> Result<Cid>;
+    /// The block for `cid`, or None if IPFS cannot find it. Callers check the
+    /// content against the CID.
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>>;
+    /// Keeps the block for `cid` from being garbage collected.
+    fn pin(&self, cid: &Cid) -> Result<()>;
+}
+
+/// Blocks kept in memory, standing in for an IPFS node in tests and
+/// simulations.
+#[derive(Debug, Default)]
+pub struct MemoryIpfs {
+    blocks: RwLock<HashMap<Cid, Vec<u8>>>,
+    pins: RwLock<Vec<Cid>>,
+}
+
+impl MemoryIpfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.pins.read().unwrap().contains(cid)
+    }
+}
+
+impl IpfsClient for MemoryIpfs {
+    fn add(&self, content: &[u8]) -> Result<Cid> {
+        let cid = Cid::of(content);
+        self.blocks.write().unwrap().insert(cid, content.to_vec());
+        Ok(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        Ok(self.blocks.read().unwrap().get(cid).cloned())
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        if !self.blocks.read().unwrap().contains_key(cid) {
+            return Err(Error::IpfsError(format!("Block {} not found", cid)));
+        }
+        let mut pins = self.pins.write().unwrap();
+        if !pins.contains(cid) {
+            pins.push(*cid);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+/// Talks to a Kubo (go-ipfs) daemon through its RPC API, by default on
+/// port 5001 of the local host.
+#[derive(Debug, Clone)]
+pub struct KuboClient {
+    address: SocketAddr,
+    timeout: Duration,
+}
+
+impl KuboClient {
+    pub fn new(address: SocketAddr) -> Self {
+        KuboClient { address, timeout: KUBO_TIMEOUT }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// POSTs to `path` and returns the body of a 200 response.
+    fn post(&self, path: &str, body: Option<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&self.address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let (content_type, body) = body.unwrap_or_default();
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            path, self.address, body.len()
+        );
+        if !content_type.is_empty() {
+            request.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&body)?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| Error::IpfsError("Malformed response from IPFS".to_string()))?;
+        let head = String::from_utf8_lossy(&response[..split]).to_lowercase();
+        let mut body = response[split + 4..].to_vec();
+        if head.lines().any(|line| line.starts_with("transfer-encoding:") && line.contains("chunked")) {
+            body = dechunk(&body)?;
+        }
+        let status = head.split_whitespace().nth(1).unwrap_or_default();
+        if status != "200" {
+            return Err(Error::IpfsError(format!("IPFS answered {}: {}", status, String::from_utf8_lossy(&body).trim())));
+        }
+        Ok(body)
+    }
+}
+
+impl IpfsClient for KuboClient {
+    fn add(&self, content: &[u8]) -> Result<Cid> {
+        if content.len() > MAX_BLOCK_SIZE {
+            return Err(Error::IpfsError(format!("Content of {} bytes does not fit in one block", content.len())));
+        }
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            MULTIPART_BOUNDARY
+        ).into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+        let response = self.post(
+            &format!("/api/v0/add?cid-version=1&raw-leaves=true&pin=true&chunker=size-{}", MAX_BLOCK_SIZE),
+            Some((format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY), body)),
+        )?;
+        let added: AddResponse = serde_json::from_slice(&response)
+            .map_err(|e| Error::IpfsError(format!("Unexpected add response: {}", e)))?;
+        let cid: Cid = added.hash.parse().map_err(Error::IpfsError)?;
+        if !cid.verify(content) {
+            return Err(Error::IpfsError(format!("IPFS stored the content as {}, not as a raw block", cid)));
+        }
+        Ok(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        match self.post(&format!("/api/v0/block/get?arg={}&offline=true", cid), None) {
+            Ok(block) => Ok(Some(block)),
+            Err(Error::IpfsError(e)) if e.contains("not found") => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn pin(&self, cid: &Cid) -> Result<()> {
+        self.post(&format!("/api/v0/pin/add?arg={}", cid), None).map(|_| ())
+    }
+}
+
+/// Decodes an HTTP body sent with chunked transfer encoding.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || Error::IpfsError("Malformed chunked response from IPFS".to_string());
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").ok_or_else(malformed)?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16).ok())
+            .ok_or_else(malformed)?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size).ok_or_else(malformed)?);
+        body = body.get(size + 2..).ok_or_else(malformed)?;
+    }
+}
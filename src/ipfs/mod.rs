@@ -0,0 +1,153 @@
+// src/ipfs/mod.rs
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+pub mod client;
+
+pub use client::{IpfsClient, KuboClient, MemoryIpfs};
+
+/// Name prefix under which IPFS content is served on the ICN data plane.
+pub const IPFS_NAME_PREFIX: &str = "/icn/ipfs/";
+/// Scheme of IPFS references in free-form metadata, e.g. `ipfs://bafk...`.
+pub const IPFS_URI_SCHEME: &str = "ipfs://";
+/// Largest content stored as a single IPFS block. Larger content would be
+/// split by IPFS into a DAG whose CID cannot be checked against the bytes.
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// CIDv1 version, raw binary codec and sha2-256 multihash with its length.
+const CID_PREFIX: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+/// Multibase prefix of lowercase base32 without padding.
+const BASE32_PREFIX: char = 'b';
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// The identifier of a raw IPFS block: a CIDv1 over the SHA-256 of the
+/// content, written in base32 as IPFS does (`bafkrei...`). Content fetched by
+/// CID, from IPFS or from peers, is checked against it before it is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cid {
+    digest: [u8; 32],
+}
+
+impl Cid {
+    /// The CID of `content` stored as a raw block.
+    pub fn of(content: &[u8]) -> Self {
+        Cid { digest: Sha256::digest(content).into() }
+    }
+
+    pub fn verify(&self, content: &[u8]) -> bool {
+        Self::of(content) == *self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        CID_PREFIX.iter().chain(self.digest.iter()).copied().collect()
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_bytes();
+        let mut encoded = String::with_capacity(1 + (bytes.len() * 8).div_ceil(5));
+        encoded.push(BASE32_PREFIX);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for byte in bytes {
+            buffer = (buffer << 8) | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+        }
+        f.write_str(&encoded)
+    }
+}
+
+impl FromStr for Cid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.strip_prefix(BASE32_PREFIX)
+            .ok_or_else(|| format!("{} is not a base32 CIDv1", s))?;
+        let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in encoded.bytes() {
+            let value = BASE32_ALPHABET.iter().position(|&a| a == c)
+                .ok_or_else(|| format!("Invalid character {:?} in CID {}", c as char, s))?;
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+            }
+        }
+        match bytes.strip_prefix(&CID_PREFIX[..]) {
+            Some(digest) if digest.len() == 32 => Ok(Cid { digest: digest.try_into().unwrap() }),
+            _ => Err(format!("{} is not the CID of a raw sha2-256 block", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Cid {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cid> for String {
+    fn from(cid: Cid) -> Self {
+        cid.to_string()
+    }
+}
+
+/// `/icn/ipfs/<cid>`
+pub fn content_name(cid: &Cid) -> String {
+    format!("{}{}", IPFS_NAME_PREFIX, cid)
+}
+
+pub fn cid_from_content_name(name: &str) -> Option<Cid> {
+    name.strip_prefix(IPFS_NAME_PREFIX)?.parse().ok()
+}
+
+pub fn is_ipfs_name(name: &str) -> bool {
+    name.starts_with(IPFS_NAME_PREFIX)
+}
+
+/// The CIDs of the `ipfs://` references anywhere in `value`.
+pub fn references_in(value: &serde_json::Value) -> Vec<Cid> {
+    match value {
+        serde_json::Value::String(s) => s.strip_prefix(IPFS_URI_SCHEME)
+            .and_then(|cid| cid.parse().ok())
+            .into_iter()
+            .collect(),
+        serde_json::Value::Array(values) => values.iter().flat_map(references_in).collect(),
+        serde_json::Value::Object(fields) => fields.values().flat_map(references_in).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cid_matches_ipfs() {
+        // `ipfs add --cid-version 1 --raw-leaves` of an empty file
+        let cid = Cid::of(b"");
+        assert_eq!(cid.to_string(), "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku");
+        assert_eq!(cid.to_string().parse::<Cid>(), Ok(cid));
+        assert!(cid.verify(b""));
+        assert!(!cid.verify(b"tampered"));
+        assert!("QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".parse::<Cid>().is_err());
+
+        let metadata = serde_json::json!({ "deed": format!("ipfs://{}", cid), "photos": ["ipfs://nonsense", "https://example.org"] });
+        assert_eq!(references_in(&metadata), vec![cid]);
+        assert_eq!(cid_from_content_name(&content_name(&cid)), Some(cid));
+    }
+}
@@ -1 +1,115 @@
-//use log::{info, warn, error, debug}; // or remove if not needed
+use crate::blockchain::{Block, Transaction};
+use crate::network::packet::Packet;
+use std::str::FromStr;
+use tracing::{info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written to standard error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One human readable line per event, prefixed with its spans.
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of every enclosing span,
+    /// for log collectors to index.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+/// Installs the global subscriber. What is logged is chosen by `RUST_LOG`,
+/// e.g. `RUST_LOG=icn_node::sharding=debug`, and defaults to `info`.
+/// Records of dependencies using the `log` crate are passed on as well.
+pub fn init(format: LogFormat) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+}
+
+// Every part of the node enters the same span for the same item, so that
+// filtering the logs on one field, e.g. `tx`, follows a transaction through
+// sharding, consensus and networking alike.
+
+/// The span of work on a transaction, identified by its hash.
+pub fn transaction_span(transaction: &Transaction) -> Span {
+    info_span!("transaction", tx = %transaction.hash())
+}
+
+/// The span of work on a block, identified by its index and hash.
+pub fn block_span(block: &Block) -> Span {
+    info_span!("block", index = block.index, hash = %block.hash)
+}
+
+/// The span of forwarding a packet that arrived on `face`.
+pub fn packet_span(packet: &Packet, face: &str) -> Span {
+    info_span!("packet", name = %packet.name, kind = ?packet.packet_type, face)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::CurrencyType;
+    use crate::sharding::{CrossShardTransactionManager, ShardingManager};
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cross_shard_transaction_can_be_followed() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let mut manager = ShardingManager::new(2, 10);
+        manager.add_address_to_shard("Alice".to_string(), 0);
+        manager.add_address_to_shard("Bob".to_string(), 1);
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 100.0).unwrap();
+        let mut transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), 40.0, CurrencyType::BasicNeeds, 1000);
+        transaction.sign(&Keypair::generate(&mut OsRng {})).unwrap();
+
+        let tx_id = tracing::subscriber::with_default(subscriber, || {
+            let _span = transaction_span(&transaction).entered();
+            CrossShardTransactionManager::new().execute(&manager, transaction.clone(), 0, 1).unwrap()
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let messages: Vec<&str> = lines.iter().map(|line| line["fields"]["message"].as_str().unwrap()).collect();
+        assert!(messages.contains(&format!("Shard 0 prepared debit of {}", tx_id).as_str()), "{:?}", messages);
+        assert!(messages.iter().any(|message| message.contains("committed")));
+        for line in &lines {
+            assert_eq!(line["spans"][0]["tx"], transaction.hash());
+            assert_eq!(line["span"]["id"], tx_id);
+        }
+    }
+}
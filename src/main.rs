@@ -1,26 +1,442 @@
-use log::{info, warn, error};
+use tracing::{info, warn};
 use chrono::Utc;
 use std::collections::HashMap;
 use std::error::Error;
+use std::io::{self, BufRead, Write};
 use std::sync::Arc;
 
-use icn_node::blockchain::Transaction;
+use icn_node::blockchain::{archive, encoding, FeeEstimate, Transaction};
 use icn_node::consensus::PoCConsensus;
 use icn_node::currency::CurrencyType;
+use icn_node::dev::{self, DevConfig};
 use icn_node::governance::{DemocraticSystem, ProposalType, ProposalCategory};
 use icn_node::identity::DecentralizedIdentity;
+use icn_node::logging::{self, LogFormat};
 use icn_node::network::Network;
+use icn_node::network::protocol::DEFAULT_NETWORK_ID;
 use icn_node::network::node::{Node, NodeType};
-use icn_node::vm::CSCLCompiler;
+use ed25519_dalek::Keypair;
+use rand::rngs::OsRng;
+use icn_node::vm::{eval_cscl, Breakpoint, CoopVM, CSCLCompiler, CsclEnv, Debugger, FileResolver, Opcode, StopReason};
+use icn_node::vm::repl::needs_more_input;
+use icn_node::wallet::{self, Keystore};
 use icn_node::IcnNode;
-use icn_node::error::Error as IcnNodeError;
+
+const USAGE: &str = "Usage: icn_node [--log-format <text|json>] [debug-contract <file.cscl> [--break <pc|opcode>]... [--trace] [--run] | cscl-repl [--modules <dir>] | verify-chain <file> | wallet ... | --dev [--listen <addr>] [--accounts <n>] [--check-signatures] [--require-quorum] [--no-instant-seal]]";
+const WALLET_USAGE: &str = "Usage: icn_node wallet [--keystore <dir>] [--node <url>] <new <name> | import <name> | list | balances <name|address> | fees [<currency>...] | transfer <name> <to> <amount> <currency> [--network <id>] [--gas <limit>] [--gas-price <price>] [--out <file>] [--yes] | submit <file> [--yes]>";
+/// Where `wallet` sends transactions and asks for balances, unless told
+/// otherwise by `--node` or `ICN_NODE`.
+const DEFAULT_NODE_URL: &str = "http://127.0.0.1:50051";
 
 fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut log_format = LogFormat::Text;
+    if args.first().map(String::as_str) == Some("--log-format") {
+        log_format = args.get(1).ok_or(USAGE)?.parse()?;
+        args.drain(..2);
+    }
+    logging::init(log_format).map_err(|e| e.to_string())?;
+    match args.first().map(String::as_str) {
+        None => run_simulation(),
+        Some("debug-contract") => debug_contract(&args[1..]),
+        Some("cscl-repl") => cscl_repl(&args[1..]),
+        Some("verify-chain") => verify_chain(&args[1..]),
+        Some("wallet") => wallet_command(&args[1..]),
+        Some("--dev") => run_dev_node(&args[1..]),
+        Some(_) => Err(USAGE.into()),
+    }
+}
+
+/// Re-validates a chain archive written by `Blockchain::export` and prints
+/// what was checked. Fails on the first invalid block.
+fn verify_chain(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = match args {
+        [path] => path,
+        _ => return Err(USAGE.into()),
+    };
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let audit = archive::verify(file)?;
+    println!("{} blocks verified", audit.blocks);
+    println!("{} transactions, {} signatures verified, {} unsigned", audit.transactions, audit.signatures_verified, audit.unsigned_transactions);
+    println!("{} blocks approved by vote, {} voted on without approval", audit.approved_blocks, audit.unapproved_blocks);
+    Ok(())
+}
+
+/// Manages the keys of a keystore, by default `~/.icn/keystore` or
+/// `ICN_KEYSTORE`, and signs transfers with them. Transfers can be saved to
+/// a file with `--out` to be submitted later from a machine that reaches a
+/// node; each transaction is shown for confirmation before it is sent.
+fn wallet_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut keystore_dir = std::env::var_os("ICN_KEYSTORE").map(std::path::PathBuf::from).unwrap_or_else(|| {
+        std::path::Path::new(&std::env::var_os("HOME").unwrap_or_else(|| ".".into())).join(".icn").join("keystore")
+    });
+    let mut node_url = std::env::var("ICN_NODE").unwrap_or_else(|_| DEFAULT_NODE_URL.to_string());
+    let mut args = args;
+    loop {
+        match args {
+            [flag, dir, rest @ ..] if flag == "--keystore" => (keystore_dir, args) = (dir.into(), rest),
+            [flag, url, rest @ ..] if flag == "--node" => (node_url, args) = (url.clone(), rest),
+            _ => break,
+        }
+    }
+    let keystore = Keystore::open(&keystore_dir)?;
+    match args {
+        [command, name] if command == "new" => {
+            let key = keystore.create(name)?;
+            println!("{} {}", key.name, key.address);
+        }
+        [command, name] if command == "import" => {
+            print!("Secret key of {} (hex): ", name);
+            io::stdout().flush()?;
+            let mut secret = String::new();
+            io::stdin().lock().read_line(&mut secret)?;
+            let key = keystore.import(name, &secret)?;
+            println!("{} {}", key.name, key.address);
+        }
+        [command] if command == "list" => {
+            for key in keystore.list()? {
+                println!("{:<16} {}", key.name, key.address);
+            }
+        }
+        [command, who] if command == "balances" => {
+            let address = keystore.get(who).map(|key| key.address).unwrap_or_else(|_| who.clone());
+            for (currency, balance, shard_id) in list_balances(&node_url, &address)? {
+                let place = shard_id.map_or("chain".to_string(), |shard_id| format!("shard {}", shard_id));
+                println!("{:<24} {:>16} {}", currency, balance, place);
+            }
+        }
+        [command, currencies @ ..] if command == "fees" => {
+            let currencies = currencies.iter().map(|currency| currency.parse()).collect::<Result<Vec<CurrencyType>, _>>()?;
+            println!("{:<24} {:>10} {:>10} {:>10} {:>9}", "currency", "low", "standard", "fast", "pending");
+            for estimate in estimate_fees(&node_url, &currencies)? {
+                println!(
+                    "{:<24} {:>10.3} {:>10.3} {:>10.3} {:>9}",
+                    estimate.currency_type, estimate.low, estimate.standard, estimate.fast, estimate.pending,
+                );
+            }
+        }
+        [command, name, to, amount, currency, options @ ..] if command == "transfer" => {
+            let (mut gas_limit, mut gas_price, mut out, mut yes) = (wallet::DEFAULT_GAS_LIMIT, 0.0, None, false);
+            let mut network_id = DEFAULT_NETWORK_ID.to_string();
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--network" => network_id = options.next().ok_or(WALLET_USAGE)?.clone(),
+                    "--gas" => gas_limit = options.next().ok_or(WALLET_USAGE)?.parse()?,
+                    "--gas-price" => gas_price = options.next().ok_or(WALLET_USAGE)?.parse()?,
+                    "--out" => out = Some(options.next().ok_or(WALLET_USAGE)?),
+                    "--yes" | "-y" => yes = true,
+                    _ => return Err(WALLET_USAGE.into()),
+                }
+            }
+            // Recipients may be named by the keys they are kept under
+            let to = keystore.get(to).map(|key| key.address).unwrap_or_else(|_| to.clone());
+            let transaction = keystore.get(name)?.sign_transfer(&network_id, &to, amount.parse()?, currency.parse::<CurrencyType>()?, gas_limit, gas_price)?;
+            println!("{}", wallet::describe(&transaction));
+            match out {
+                Some(path) => {
+                    std::fs::write(path, encoding::encode(&transaction)?)?;
+                    println!("Saved to {}; send it with `wallet submit {}`", path, path);
+                }
+                None => confirm_and_submit(&node_url, transaction, yes)?,
+            }
+        }
+        [command, path, options @ ..] if command == "submit" => {
+            let yes = match options {
+                [] => false,
+                [flag] if flag == "--yes" || flag == "-y" => true,
+                _ => return Err(WALLET_USAGE.into()),
+            };
+            let transaction: Transaction = encoding::decode(&std::fs::read(path)?)?;
+            println!("{}", wallet::describe(&transaction));
+            confirm_and_submit(&node_url, transaction, yes)?;
+        }
+        _ => return Err(WALLET_USAGE.into()),
+    }
+    Ok(())
+}
+
+fn confirm_and_submit(node_url: &str, transaction: Transaction, yes: bool) -> Result<(), Box<dyn Error>> {
+    if !yes {
+        print!("Submit to {}? [y/N] ", node_url);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            println!("Not submitted");
+            return Ok(());
+        }
+    }
+    println!("Submitted {}", submit_transaction(node_url, transaction)?);
+    Ok(())
+}
+
+#[cfg(feature = "grpc")]
+fn submit_transaction(node_url: &str, transaction: Transaction) -> Result<String, Box<dyn Error>> {
+    use icn_client::v1::{node_control_client::NodeControlClient, SubmitTransactionRequest};
+    use icn_node::api::grpc;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let mut client = NodeControlClient::connect(node_url.to_string()).await?;
+        let request = SubmitTransactionRequest { transaction: Some(grpc::transaction_to_proto(&transaction)) };
+        Ok::<_, Box<dyn Error>>(client.submit_transaction(request).await?.into_inner().transaction_hash)
+    })
+}
+
+/// Balances by currency, with the shard holding each unless it is on the
+/// chain.
+type Balances = Vec<(CurrencyType, f64, Option<u64>)>;
+
+#[cfg(feature = "grpc")]
+fn list_balances(node_url: &str, address: &str) -> Result<Balances, Box<dyn Error>> {
+    use icn_client::v1::{node_control_client::NodeControlClient, ListBalancesRequest};
+    use icn_node::api::grpc;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let mut client = NodeControlClient::connect(node_url.to_string()).await?;
+        let balances = client.list_balances(ListBalancesRequest { address: address.to_string() }).await?.into_inner().balances;
+        balances.into_iter().map(|balance| {
+            let currency = grpc::currency_from_proto(balance.currency.unwrap_or_default())?;
+            Ok((currency, balance.balance, balance.shard_id))
+        }).collect::<Result<_, Box<dyn Error>>>()
+    })
+}
+
+#[cfg(feature = "grpc")]
+fn estimate_fees(node_url: &str, currencies: &[CurrencyType]) -> Result<Vec<FeeEstimate>, Box<dyn Error>> {
+    use icn_client::v1::{node_control_client::NodeControlClient, EstimateFeesRequest};
+    use icn_node::api::grpc;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let mut client = NodeControlClient::connect(node_url.to_string()).await?;
+        let request = EstimateFeesRequest { currencies: currencies.iter().map(grpc::currency_to_proto).collect() };
+        let estimates = client.estimate_fees(request).await?.into_inner().estimates;
+        estimates.into_iter().map(|estimate| Ok(FeeEstimate {
+            currency_type: grpc::currency_from_proto(estimate.currency.unwrap_or_default())?,
+            low: estimate.low,
+            standard: estimate.standard,
+            fast: estimate.fast,
+            block_fullness: estimate.block_fullness,
+            pending: estimate.pending as usize,
+        })).collect::<Result<_, Box<dyn Error>>>()
+    })
+}
+
+#[cfg(not(feature = "grpc"))]
+fn submit_transaction(_node_url: &str, _transaction: Transaction) -> Result<String, Box<dyn Error>> {
+    Err("This build cannot reach a node; rebuild with --features grpc, or save the transaction with --out".into())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn list_balances(_node_url: &str, _address: &str) -> Result<Balances, Box<dyn Error>> {
+    Err("This build cannot reach a node; rebuild with --features grpc".into())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn estimate_fees(_node_url: &str, _currencies: &[CurrencyType]) -> Result<Vec<FeeEstimate>, Box<dyn Error>> {
+    Err("This build cannot reach a node; rebuild with --features grpc".into())
+}
+
+/// Runs a single-node development chain, serving node control on
+/// `--listen` until interrupted. Its test accounts are printed with their
+/// keys, ready for `wallet import`.
+fn run_dev_node(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config = DevConfig::default();
+    let mut listen = DEFAULT_NODE_URL.trim_start_matches("http://").to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().ok_or(USAGE)?.clone(),
+            "--accounts" => config.accounts = args.next().ok_or(USAGE)?.parse()?,
+            "--check-signatures" => config.skip_signature_checks = false,
+            "--require-quorum" => config.skip_quorum = false,
+            "--no-instant-seal" => config.instant_seal = false,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let funding = config.funding;
+    let (blockchain, accounts) = dev::genesis(config)?;
+    println!("Development chain; these accounts hold {} of each currency:", funding);
+    for account in &accounts {
+        println!("  {:<6} {}\n         secret key {}", account.name, account.address, account.secret_key);
+    }
+    serve_dev_node(blockchain, &listen)
+}
+
+#[cfg(feature = "grpc")]
+fn serve_dev_node(blockchain: icn_node::blockchain::Blockchain, listen: &str) -> Result<(), Box<dyn Error>> {
+    use icn_node::api::{grpc::GrpcService, ApiLayer};
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let api = ApiLayer::new(
+            Arc::new(tokio::sync::RwLock::new(blockchain)),
+            Arc::new(tokio::sync::RwLock::new(DemocraticSystem::new())),
+        );
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        println!("Serving node control on http://{}", listener.local_addr()?);
+        tokio::select! {
+            served = GrpcService::new(Arc::new(api)).serve(listener) => served?,
+            interrupted = tokio::signal::ctrl_c() => interrupted?,
+        }
+        Ok(())
+    })
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_dev_node(_blockchain: icn_node::blockchain::Blockchain, _listen: &str) -> Result<(), Box<dyn Error>> {
+    Err("This build cannot serve node control; rebuild with --features grpc".into())
+}
+
+/// Runs a CSCL file under the debugger. Breakpoints and tracing can be set
+/// up from the command line; commands are then read from standard input,
+/// unless `--run` is given, in which case the program runs to the end,
+/// printing the machine state at every breakpoint.
+fn debug_contract(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut path = None;
+    let mut breakpoints = Vec::new();
+    let mut trace = false;
+    let mut run = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--break" | "-b" => breakpoints.push(Breakpoint::parse(args.next().ok_or(USAGE)?)),
+            "--trace" | "-t" => trace = true,
+            "--run" | "-r" => run = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+
+    let source = std::fs::read_to_string(path)?;
+    // Imports are looked up next to the contract
+    let root = std::path::Path::new(path).parent().unwrap_or_else(|| std::path::Path::new("."));
+    let program = match compile(CSCLCompiler::new(&source).with_resolver(FileResolver::new(root))) {
+        Ok(program) => program,
+        Err(errors) => {
+            eprintln!("{}", errors);
+            return Err(format!("could not compile {}", path).into());
+        }
+    };
+    for (pc, opcode) in program.iter().enumerate() {
+        println!("{:>5} {:?}", pc, opcode);
+    }
+
+    let mut debugger = Debugger::new(CoopVM::new(program));
+    for breakpoint in breakpoints {
+        debugger.add_breakpoint(breakpoint);
+    }
+    if trace {
+        debugger.enable_trace();
+    }
+
+    if run {
+        loop {
+            match debugger.continue_run() {
+                Ok(StopReason::Breakpoint(pc)) => {
+                    println!("Breakpoint at pc {}: {}", pc, debugger.location());
+                    println!("stack: {}", debugger.execute_command("stack"));
+                    println!("memory:\n{}", debugger.execute_command("memory"));
+                }
+                Ok(StopReason::Halted) => break,
+                Err(e) => {
+                    println!("Error: {}\n{}", e, debugger.location());
+                    break;
+                }
+            }
+        }
+    } else {
+        println!("{}", debugger.location());
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                break;
+            }
+            match line.trim() {
+                "" => continue,
+                "q" | "quit" => break,
+                command => println!("{}", debugger.execute_command(command)),
+            }
+        }
+    }
+
+    if trace {
+        println!("trace:\n{}", debugger.execute_command("trace"));
+    }
+    println!("memory:\n{}", debugger.execute_command("memory"));
+    println!("events:\n{}", debugger.execute_command("events"));
+    Ok(())
+}
+
+/// Compiles CSCL source, with every error found on its own line.
+fn compile(mut compiler: CSCLCompiler) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    compiler.compile().map_err(|errors| {
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n").into()
+    })
+}
+
+/// Reads CSCL snippets from standard input and evaluates each one against
+/// the same scratch VM, printing the stack it leaves. Input continues over
+/// several lines while brackets are open.
+fn cscl_repl(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let modules = match args {
+        [] => ".",
+        [flag, dir] if flag == "--modules" => dir.as_str(),
+        _ => return Err(USAGE.into()),
+    };
+    let mut env = CsclEnv::new().with_resolver(FileResolver::new(modules));
+    println!("CSCL REPL. Commands: :memory, :events, :quit");
+
+    let stdin = io::stdin();
+    let mut source = String::new();
+    loop {
+        print!("{}", if source.is_empty() { "cscl> " } else { "  ... " });
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        if source.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" | ":q" => break,
+                ":memory" => {
+                    let mut variables: Vec<String> = env.vm().get_memory().iter().map(|(name, value)| format!("{} = {:?}", name, value)).collect();
+                    variables.sort();
+                    println!("{}", variables.join("\n"));
+                    continue;
+                }
+                ":events" => {
+                    for (name, value) in env.vm().get_events() {
+                        println!("{}: {:?}", name, value);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        source.push_str(&line);
+        if needs_more_input(&source) {
+            continue;
+        }
+        match eval_cscl(&source, &mut env) {
+            Ok(stack) => {
+                for value in stack {
+                    println!("{:?}", value);
+                }
+            }
+            Err(e) => println!("Error: {}", e),
+        }
+        source.clear();
+    }
+    Ok(())
+}
+
+fn run_simulation() -> Result<(), Box<dyn Error>> {
     info!("Starting ICN Node");
 
     let node = Arc::new(IcnNode::new());
-    let mut network = Network::new();
+    let protocol = node.blockchain.read().unwrap().spec.protocol_info();
+    let mut network = Network::new().with_protocol(protocol);
     let mut consensus = PoCConsensus::new(0.5, 0.66);
     let mut democratic_system = DemocraticSystem::new();
 
@@ -41,10 +457,10 @@ fn setup_network_and_consensus(network: &mut Network, consensus: &mut PoCConsens
     network.add_node(node1);
     network.add_node(node2);
 
-    consensus.add_member("Alice".to_string(), false)?;
-    consensus.add_member("Bob".to_string(), false)?;
-    consensus.add_member("Charlie".to_string(), false)?;
-    consensus.add_member("CorpX".to_string(), true)?;
+    consensus.add_member("Alice".to_string(), false);
+    consensus.add_member("Bob".to_string(), false);
+    consensus.add_member("Charlie".to_string(), false);
+    consensus.add_member("CorpX".to_string(), true);
 
     Ok(())
 }
@@ -61,16 +477,15 @@ fn process_initial_transactions(node: Arc<IcnNode>) -> Result<(), Box<dyn Error>
         1000,
     );
 
-    node.with_blockchain(|blockchain| {
-        blockchain.add_transaction(tx.clone())?;
-        blockchain.create_block("Alice".to_string())?;
-        if let Some(latest_block) = blockchain.get_latest_block() {
-            info!("New block created: {:?}", latest_block);
-        } else {
-            warn!("No blocks in the blockchain to broadcast");
-        }
-        Ok(())
-    })?;
+    node.with_chain(|blockchain| {
+        blockchain.add_transaction(tx)?;
+        blockchain.create_block("Alice".to_string())
+    })??;
+    if let Some(latest_block) = node.blockchain.read().unwrap().get_latest_block() {
+        info!("New block created: {:?}", latest_block);
+    } else {
+        warn!("No blocks in the blockchain to broadcast");
+    }
 
     Ok(())
 }
@@ -90,11 +505,10 @@ fn create_and_vote_on_proposal(democratic_system: &mut DemocraticSystem) -> Resu
     democratic_system.vote("Bob".to_string(), proposal_id.clone(), true, 1.0)?;
     democratic_system.vote("Charlie".to_string(), proposal_id.clone(), false, 1.0)?;
     democratic_system.vote("David".to_string(), proposal_id.clone(), true, 1.0)?;
-    democratic_system.tally_votes(&proposal_id)?;
 
     let proposal = democratic_system.get_proposal(&proposal_id)
         .ok_or("Proposal not found after voting")?;
-    info!("Proposal status after voting: {:?}", proposal.status);
+    info!("Proposal status after voting: {:?}, voting ends at {}", proposal.status, proposal.voting_ends_at);
 
     Ok(())
 }
@@ -107,43 +521,30 @@ fn compile_and_run_cscl(node: Arc<IcnNode>) -> Result<(), Box<dyn Error>> {
     emit("Result", z);
     "#;
 
-    let mut compiler = CSCLCompiler::new(cscl_code);
-    let opcodes = compiler.compile()?;
+    let opcodes = compile(CSCLCompiler::new(cscl_code).with_optimizer())?;
     
-    node.with_coop_vm(|coop_vm| {
-        coop_vm.load_program(opcodes);
-        coop_vm.run().map_err(IcnNodeError::VmError)
-    })?;
+    node.run_program(opcodes)?;
 
     Ok(())
 }
 
 fn simulate_cross_shard_transaction(node: Arc<IcnNode>) -> Result<(), Box<dyn Error>> {
-    node.process_cross_shard_transaction(|sharding_manager| {
-        sharding_manager.add_address_to_shard("Alice".to_string(), 0);
-        sharding_manager.add_address_to_shard("Bob".to_string(), 1);
-        sharding_manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0)
-    })?;
+    setup_shards(&node)?;
 
-    let transaction = Transaction::new(
+    let keypair = Keypair::generate(&mut OsRng {});
+    let mut transaction = Transaction::new(
         "Alice".to_string(),
         "Bob".to_string(),
         500.0,
         CurrencyType::BasicNeeds,
         1000,
     );
+    transaction.sign(&keypair)?;
+    node.process_cross_shard_transaction(&transaction)?;
 
-    node.process_cross_shard_transaction(|sharding_manager| {
-        sharding_manager.transfer_between_shards(0, 1, &transaction)
-    })?;
-
-    let alice_balance = node.with_sharding_manager(|sharding_manager| {
-        sharding_manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds)
-    })?;
-
-    let bob_balance = node.with_sharding_manager(|sharding_manager| {
-        sharding_manager.get_balance("Bob".to_string(), CurrencyType::BasicNeeds)
-    })?;
+    let sharding_manager = node.sharding_manager.read().unwrap();
+    let alice_balance = sharding_manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds)?;
+    let bob_balance = sharding_manager.get_balance("Bob".to_string(), CurrencyType::BasicNeeds)?;
 
     info!("Alice's balance after cross-shard transaction: {:?}", alice_balance);
     info!("Bob's balance after cross-shard transaction: {:?}", bob_balance);
@@ -151,18 +552,22 @@ fn simulate_cross_shard_transaction(node: Arc<IcnNode>) -> Result<(), Box<dyn Er
     Ok(())
 }
 
+fn setup_shards(node: &IcnNode) -> Result<(), Box<dyn Error>> {
+    let mut sharding_manager = node.sharding_manager.write().unwrap();
+    sharding_manager.add_address_to_shard("Alice".to_string(), 0);
+    sharding_manager.add_address_to_shard("Bob".to_string(), 1);
+    sharding_manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0)?;
+    Ok(())
+}
+
 fn print_final_state(node: &Arc<IcnNode>, consensus: &PoCConsensus, democratic_system: &DemocraticSystem) {
     info!("Blockchain state:");
-    if let Err(e) = node.with_blockchain(|blockchain| {
-        info!("Number of blocks: {}", blockchain.chain.len());
-        if let Some(latest_block) = blockchain.get_latest_block() {
-            info!("Latest block hash: {}", latest_block.hash);
-        } else {
-            warn!("No blocks in the blockchain");
-        }
-        Ok(())
-    }) {
-        error!("Failed to read blockchain state: {}", e);
+    let blockchain = node.blockchain.read().unwrap();
+    info!("Number of blocks: {}", blockchain.chain.len());
+    if let Some(latest_block) = blockchain.get_latest_block() {
+        info!("Latest block hash: {}", latest_block.hash);
+    } else {
+        warn!("No blocks in the blockchain");
     }
 
     info!("Consensus state:");
@@ -173,25 +578,18 @@ fn print_final_state(node: &Arc<IcnNode>, consensus: &PoCConsensus, democratic_s
     info!("Number of active proposals: {}", democratic_system.list_active_proposals().len());
 
     info!("Sharding state:");
-    if let Err(e) = node.with_sharding_manager(|sharding_manager| {
-        info!("Number of shards: {}", sharding_manager.get_shard_count());
-        Ok(())
-    }) {
-        error!("Failed to read sharding state: {}", e);
-    }
+    info!("Number of shards: {}", node.sharding_manager.read().unwrap().get_shard_count());
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use icn_node::governance::democracy::ProposalStatus;
-    use ed25519_dalek::Keypair;
-    use rand::rngs::OsRng;
 
     #[test]
     fn test_icn_node_creation() {
         let node = Arc::new(IcnNode::new());
-        assert!(node.with_blockchain(|blockchain| Ok(blockchain.chain.len() == 1)).unwrap());
+        assert_eq!(node.blockchain.read().unwrap().chain.len(), 1);
         info!("ICN Node creation test passed");
     }
 
@@ -199,11 +597,7 @@ mod tests {
     fn test_cross_shard_transaction() -> Result<(), Box<dyn Error>> {
         let node = Arc::new(IcnNode::new());
 
-        node.process_cross_shard_transaction(|sharding_manager| {
-            sharding_manager.add_address_to_shard("Alice".to_string(), 0);
-            sharding_manager.add_address_to_shard("Bob".to_string(), 1);
-            sharding_manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0)
-        })?;
+        setup_shards(&node)?;
 
         let mut csprng = OsRng{};
         let keypair: Keypair = Keypair::generate(&mut csprng);
@@ -217,17 +611,11 @@ mod tests {
         );
         transaction.sign(&keypair)?;
 
-        node.process_cross_shard_transaction(|sharding_manager| {
-            sharding_manager.transfer_between_shards(0, 1, &transaction)
-        })?;
-
-        let alice_balance = node.with_sharding_manager(|sharding_manager| {
-            sharding_manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds)
-        })?;
+        node.process_cross_shard_transaction(&transaction)?;
 
-        let bob_balance = node.with_sharding_manager(|sharding_manager| {
-            sharding_manager.get_balance("Bob".to_string(), CurrencyType::BasicNeeds)
-        })?;
+        let sharding_manager = node.sharding_manager.read().unwrap();
+        let alice_balance = sharding_manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds)?;
+        let bob_balance = sharding_manager.get_balance("Bob".to_string(), CurrencyType::BasicNeeds)?;
 
         assert_eq!(alice_balance, 500.0);
         assert_eq!(bob_balance, 500.0);
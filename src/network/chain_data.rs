@@ -0,0 +1,135 @@
+use std::time::Duration;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::blockchain::{encoding, Blockchain, Versioned};
+use crate::error::{Error, Result};
+use super::packet::{Packet, PacketType};
+use super::sync::MAX_HEADERS_PER_REQUEST;
+
+/// Blocks, header ranges and transactions are published under this prefix.
+/// They carry their own integrity through the hash chain, so unlike other
+/// content they are not signed by a publisher DID, only by the node serving
+/// them when it has an identity, which makes bad data attributable to it.
+pub const CHAIN_NAME_PREFIX: &str = "/icn/chain/";
+/// A header range grows with the chain, so an answer is only briefly fresh.
+pub const HEADERS_FRESHNESS: Duration = Duration::from_secs(1);
+const HEADERS_COMPONENT: &str = "headers";
+const TRANSACTION_COMPONENT: &str = "tx";
+
+/// The chain data a name under `CHAIN_NAME_PREFIX` refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainName {
+    /// `/icn/chain/<height>`
+    Block(u64),
+    /// `/icn/chain/headers/<start>/<max>`
+    Headers { start: u64, max: u32 },
+    /// `/icn/chain/tx/<hash>`
+    Transaction(String),
+}
+
+impl ChainName {
+    pub fn parse(name: &str) -> Option<Self> {
+        let components: Vec<&str> = name.strip_prefix(CHAIN_NAME_PREFIX)?.split('/').collect();
+        match components.as_slice() {
+            [HEADERS_COMPONENT, start, max] => Some(ChainName::Headers { start: start.parse().ok()?, max: max.parse().ok()? }),
+            [TRANSACTION_COMPONENT, hash] if !hash.is_empty() => Some(ChainName::Transaction(hash.to_string())),
+            [height] => height.parse().ok().map(ChainName::Block),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ChainName::Block(height) => format!("{}{}", CHAIN_NAME_PREFIX, height),
+            ChainName::Headers { start, max } => format!("{}{}/{}/{}", CHAIN_NAME_PREFIX, HEADERS_COMPONENT, start, max),
+            ChainName::Transaction(hash) => format!("{}{}/{}", CHAIN_NAME_PREFIX, TRANSACTION_COMPONENT, hash),
+        }
+    }
+
+    pub fn interest(&self) -> Packet {
+        Packet::interest(&self.name())
+    }
+}
+
+pub fn is_chain_name(name: &str) -> bool {
+    name.starts_with(CHAIN_NAME_PREFIX)
+}
+
+/// Answers an interest for chain data from the local chain. Returns `None`
+/// when the name is not chain data this node holds; a header range past the
+/// tip is answered with no headers instead.
+pub fn answer_interest(chain: &Blockchain, interest: &Packet) -> Result<Option<Packet>> {
+    if interest.packet_type != PacketType::Interest {
+        return Err(Error::NetworkError(format!("Expected an interest for {}", interest.name)));
+    }
+    let packet = match ChainName::parse(&interest.name) {
+        Some(ChainName::Block(height)) => match chain.chain.get(height as usize) {
+            Some(block) => Packet::data(&interest.name, encoding::encode(block)?),
+            None => return Ok(None),
+        },
+        Some(ChainName::Headers { start, max }) => {
+            let headers = chain.headers(start, max.min(MAX_HEADERS_PER_REQUEST) as usize);
+            Packet::data(&interest.name, encode(&headers)?).with_freshness_period(HEADERS_FRESHNESS)
+        }
+        Some(ChainName::Transaction(hash)) => match chain.find_transaction(&hash) {
+            Some(transaction) => Packet::data(&interest.name, encoding::encode(transaction)?),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    Ok(Some(packet))
+}
+
+/// Decodes the content of a chain Data packet.
+pub fn decode<T: DeserializeOwned>(data: &Packet) -> Result<T> {
+    serde_json::from_slice(&data.content).map_err(|e| Error::NetworkError(format!("Invalid chain data {}: {}", data.name, e)))
+}
+
+/// Decodes a block or transaction from the content of a chain Data packet;
+/// see `blockchain::encoding`.
+pub fn decode_versioned<T: Versioned>(data: &Packet) -> Result<T> {
+    encoding::decode(&data.content).map_err(|e| Error::NetworkError(format!("Invalid chain data {}: {}", data.name, e)))
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| Error::NetworkError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, BlockHeader, ChainSpec, Transaction};
+    use crate::currency::CurrencyType;
+
+    #[test]
+    fn test_chain_names() {
+        for name in [ChainName::Block(7), ChainName::Headers { start: 1, max: 512 }, ChainName::Transaction("ab12".to_string())] {
+            assert_eq!(ChainName::parse(&name.name()), Some(name));
+        }
+        assert_eq!(ChainName::Block(7).name(), "/icn/chain/7");
+        assert_eq!(ChainName::parse("/icn/chain/tip"), None);
+        assert_eq!(ChainName::parse("/icn/chain/tx/"), None);
+        assert_eq!(ChainName::parse("/coopX/chain/7"), None);
+    }
+
+    #[test]
+    fn test_interests_answered_from_chain() {
+        let mut chain = Blockchain::with_spec(ChainSpec::default().with_allocation("alice", 5.0, CurrencyType::BasicNeeds));
+        let transaction = Transaction::new("alice".to_string(), "bob".to_string(), 5.0, CurrencyType::BasicNeeds, 1000);
+        chain.add_transaction(transaction.clone()).unwrap();
+        chain.create_block("proposer".to_string()).unwrap();
+
+        let data = answer_interest(&chain, &ChainName::Block(1).interest()).unwrap().unwrap();
+        let block: Block = decode_versioned(&data).unwrap();
+        assert_eq!(block.hash, chain.chain[1].hash);
+        assert!(answer_interest(&chain, &ChainName::Block(2).interest()).unwrap().is_none());
+
+        let data = answer_interest(&chain, &ChainName::Headers { start: 0, max: 10 }.interest()).unwrap().unwrap();
+        let headers: Vec<BlockHeader> = decode(&data).unwrap();
+        assert_eq!(headers.len(), 2);
+
+        let data = answer_interest(&chain, &ChainName::Transaction(transaction.hash()).interest()).unwrap().unwrap();
+        assert_eq!(decode_versioned::<Transaction>(&data).unwrap(), transaction);
+        assert!(answer_interest(&chain, &ChainName::Transaction("unknown".to_string()).interest()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::debug;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use super::node::Node;
+use super::transport::Message;
+
+/// Bucket size, and the number of nodes a provider record is stored on.
+pub const K: usize = 20;
+/// Number of peers queried in parallel at each step of a lookup.
+pub const ALPHA: usize = 3;
+/// Provider records not refreshed within this period are dropped.
+pub const PROVIDER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// A lookup with no answer after this long may be started again.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+const KEY_BITS: usize = 256;
+
+/// Position in the DHT keyspace. Node ids and content name prefixes are both
+/// hashed into it, so a prefix is stored on the nodes whose ids are closest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DhtKey([u8; 32]);
+
+impl DhtKey {
+    pub fn of(value: &str) -> Self {
+        DhtKey(Sha256::digest(value.as_bytes()).into())
+    }
+
+    /// XOR distance between two keys.
+    pub fn distance(&self, other: &DhtKey) -> DhtKey {
+        let mut distance = [0u8; 32];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        DhtKey(distance)
+    }
+
+    fn leading_zeros(&self) -> usize {
+        let mut zeros = 0;
+        for byte in self.0 {
+            if byte != 0 {
+                return zeros + byte.leading_zeros() as usize;
+            }
+            zeros += 8;
+        }
+        zeros
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DhtMessage {
+    FindNode { target: String },
+    Nodes { target: String, nodes: Vec<Node> },
+    /// Announces that the sender serves content under `prefix`.
+    AddProvider { prefix: String, provider: Node },
+    GetProviders { prefix: String },
+    Providers { prefix: String, providers: Vec<Node>, closer: Vec<Node> },
+}
+
+/// A content name prefix and a node serving it.
+pub type Route = (String, Node);
+
+/// What handling a DHT message produced: messages to send, peers learned
+/// along the way and routes for the FIB.
+#[derive(Debug, Default)]
+pub struct DhtOutcome {
+    pub requests: Vec<(String, Message)>,
+    pub learned: Vec<Node>,
+    pub routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Lookup {
+    Node(String),
+    Providers(String),
+}
+
+impl Lookup {
+    fn key(&self) -> DhtKey {
+        match self {
+            Lookup::Node(target) | Lookup::Providers(target) => DhtKey::of(target),
+        }
+    }
+
+    fn request(&self) -> Message {
+        Message::Dht(match self {
+            Lookup::Node(target) => DhtMessage::FindNode { target: target.clone() },
+            Lookup::Providers(prefix) => DhtMessage::GetProviders { prefix: prefix.clone() },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct LookupState {
+    queried: HashSet<String>,
+    started_at: Instant,
+}
+
+/// Kademlia routing table and provider records for named content. Lookups are
+/// iterative: each answer brings closer nodes, which are queried in turn until
+/// providers are found or nobody closer is left.
+#[derive(Debug)]
+pub struct Dht {
+    local_id: String,
+    local_key: DhtKey,
+    buckets: Vec<VecDeque<Node>>,
+    providers: HashMap<String, HashMap<String, (Node, Instant)>>,
+    lookups: HashMap<Lookup, LookupState>,
+}
+
+impl Dht {
+    pub fn new(local_id: &str) -> Self {
+        Dht {
+            local_id: local_id.to_string(),
+            local_key: DhtKey::of(local_id),
+            buckets: vec![VecDeque::new(); KEY_BITS],
+            providers: HashMap::new(),
+            lookups: HashMap::new(),
+        }
+    }
+
+    pub fn local_id(&self) -> &str {
+        &self.local_id
+    }
+
+    /// Changes the local id, e.g. once the transport is started, and re-buckets
+    /// the known peers around it.
+    pub fn set_local_id(&mut self, local_id: &str) {
+        let peers = self.peers();
+        self.local_id = local_id.to_string();
+        self.local_key = DhtKey::of(local_id);
+        self.buckets = vec![VecDeque::new(); KEY_BITS];
+        for node in peers {
+            self.add_peer(node);
+        }
+    }
+
+    /// Inserts or refreshes a peer. Full buckets keep their long-lived peers,
+    /// so the new one is dropped and false is returned.
+    pub fn add_peer(&mut self, node: Node) -> bool {
+        let bucket = match self.bucket_index(&node.id) {
+            Some(bucket) => &mut self.buckets[bucket],
+            None => return false,
+        };
+        if let Some(position) = bucket.iter().position(|known| known.id == node.id) {
+            bucket.remove(position);
+        } else if bucket.len() >= K {
+            return false;
+        }
+        bucket.push_back(node);
+        true
+    }
+
+    pub fn remove_peer(&mut self, node_id: &str) {
+        if let Some(bucket) = self.bucket_index(node_id) {
+            self.buckets[bucket].retain(|node| node.id != node_id);
+        }
+    }
+
+    pub fn peers(&self) -> Vec<Node> {
+        self.buckets.iter().flatten().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Up to `count` known peers closest to `key`.
+    pub fn closest(&self, key: &DhtKey, count: usize) -> Vec<Node> {
+        let mut peers = self.peers();
+        peers.sort_by_key(|node| DhtKey::of(&node.id).distance(key));
+        peers.truncate(count);
+        peers
+    }
+
+    pub fn add_provider(&mut self, prefix: &str, provider: Node, now: Instant) {
+        self.providers.entry(prefix.to_string())
+            .or_default()
+            .insert(provider.id.clone(), (provider, now));
+    }
+
+    /// Unexpired providers known for exactly `prefix`.
+    pub fn providers(&mut self, prefix: &str, now: Instant) -> Vec<Node> {
+        let records = match self.providers.get_mut(prefix) {
+            Some(records) => records,
+            None => return vec![],
+        };
+        records.retain(|_, (_, stored_at)| now.duration_since(*stored_at) < PROVIDER_TTL);
+        records.values().map(|(node, _)| node.clone()).collect()
+    }
+
+    /// Records `local` as a provider of `prefix` and stores the record on the
+    /// `K` peers closest to the prefix.
+    pub fn provide(&mut self, prefix: &str, local: Node, now: Instant) -> Vec<(String, Message)> {
+        self.add_provider(prefix, local.clone(), now);
+        self.closest(&DhtKey::of(prefix), K).into_iter()
+            .map(|node| (node.id, Message::Dht(DhtMessage::AddProvider {
+                prefix: prefix.to_string(),
+                provider: local.clone(),
+            })))
+            .collect()
+    }
+
+    /// Looks for our own id to fill the routing table with nearby peers.
+    pub fn bootstrap(&mut self, now: Instant) -> Vec<(String, Message)> {
+        self.start_lookup(Lookup::Node(self.local_id.clone()), now)
+    }
+
+    /// Resolves routes for every prefix of `name`, e.g. `/coopX/docs/a`,
+    /// `/coopX/docs` and `/coopX`. Prefixes with known providers become routes
+    /// right away; the others start a lookup whose answers arrive through
+    /// `handle`.
+    pub fn find_providers(&mut self, name: &str, now: Instant) -> DhtOutcome {
+        let mut outcome = DhtOutcome::default();
+        for prefix in name_prefixes(name) {
+            let mut providers = self.providers(&prefix, now);
+            providers.retain(|node| node.id != self.local_id);
+            if providers.is_empty() {
+                outcome.requests.extend(self.start_lookup(Lookup::Providers(prefix), now));
+            } else {
+                outcome.routes.extend(providers.into_iter().map(|node| (prefix.clone(), node)));
+            }
+        }
+        outcome
+    }
+
+    pub fn handle(&mut self, peer_id: &str, message: DhtMessage, now: Instant) -> DhtOutcome {
+        let mut outcome = DhtOutcome::default();
+        match message {
+            DhtMessage::FindNode { target } => {
+                let nodes = self.closest_excluding(&DhtKey::of(&target), peer_id);
+                outcome.requests.push((peer_id.to_string(), Message::Dht(DhtMessage::Nodes { target, nodes })));
+            }
+            DhtMessage::Nodes { target, nodes } => {
+                self.learn(nodes, &mut outcome);
+                outcome.requests = self.continue_lookup(&Lookup::Node(target), now);
+            }
+            DhtMessage::AddProvider { prefix, provider } => {
+                // Nodes may only announce themselves
+                if provider.id == peer_id {
+                    debug!("{} provides {}", peer_id, prefix);
+                    self.add_provider(&prefix, provider, now);
+                }
+            }
+            DhtMessage::GetProviders { prefix } => {
+                let providers = self.providers(&prefix, now);
+                let closer = self.closest_excluding(&DhtKey::of(&prefix), peer_id);
+                outcome.requests.push((peer_id.to_string(), Message::Dht(DhtMessage::Providers { prefix, providers, closer })));
+            }
+            DhtMessage::Providers { prefix, providers, closer } => {
+                self.learn(closer, &mut outcome);
+                let lookup = Lookup::Providers(prefix.clone());
+                if providers.is_empty() {
+                    outcome.requests = self.continue_lookup(&lookup, now);
+                } else {
+                    self.lookups.remove(&lookup);
+                    for provider in providers {
+                        if provider.id != self.local_id {
+                            self.add_provider(&prefix, provider.clone(), now);
+                            outcome.routes.push((prefix.clone(), provider));
+                        }
+                    }
+                }
+            }
+        }
+        outcome
+    }
+
+    fn bucket_index(&self, node_id: &str) -> Option<usize> {
+        let zeros = DhtKey::of(node_id).distance(&self.local_key).leading_zeros();
+        if zeros == KEY_BITS {
+            None
+        } else {
+            Some(KEY_BITS - 1 - zeros)
+        }
+    }
+
+    fn closest_excluding(&self, key: &DhtKey, excluded: &str) -> Vec<Node> {
+        let mut nodes = self.closest(key, K + 1);
+        nodes.retain(|node| node.id != excluded);
+        nodes.truncate(K);
+        nodes
+    }
+
+    fn learn(&mut self, nodes: Vec<Node>, outcome: &mut DhtOutcome) {
+        for node in nodes {
+            if node.id != self.local_id && self.add_peer(node.clone()) {
+                outcome.learned.push(node);
+            }
+        }
+    }
+
+    fn start_lookup(&mut self, lookup: Lookup, now: Instant) -> Vec<(String, Message)> {
+        let running = self.lookups.get(&lookup)
+            .is_some_and(|state| now.duration_since(state.started_at) < LOOKUP_TIMEOUT);
+        if running {
+            return vec![];
+        }
+        self.lookups.insert(lookup.clone(), LookupState { queried: HashSet::new(), started_at: now });
+        self.continue_lookup(&lookup, now)
+    }
+
+    /// Queries up to `ALPHA` of the closest peers not asked yet. The lookup
+    /// ends once every one of the `K` closest peers has been asked.
+    fn continue_lookup(&mut self, lookup: &Lookup, now: Instant) -> Vec<(String, Message)> {
+        let closest = self.closest(&lookup.key(), K);
+        let state = match self.lookups.get_mut(lookup) {
+            Some(state) => state,
+            None => return vec![],
+        };
+        let next: Vec<Node> = closest.into_iter()
+            .filter(|node| !state.queried.contains(&node.id))
+            .take(ALPHA)
+            .collect();
+        if next.is_empty() {
+            self.lookups.remove(lookup);
+            return vec![];
+        }
+        state.started_at = now;
+        next.into_iter()
+            .map(|node| {
+                state.queried.insert(node.id.clone());
+                (node.id, lookup.request())
+            })
+            .collect()
+    }
+}
+
+impl Default for Dht {
+    /// A table without a local id yet; see `set_local_id`.
+    fn default() -> Self {
+        Dht::new("")
+    }
+}
+
+/// `/a/b/c` yields `/a/b/c`, `/a/b` and `/a`, longest first.
+pub fn name_prefixes(name: &str) -> Vec<String> {
+    let components: Vec<&str> = name.split('/').filter(|component| !component.is_empty()).collect();
+    (1..=components.len()).rev()
+        .map(|len| format!("/{}", components[..len].join("/")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::node::NodeType;
+
+    fn node(id: &str) -> Node {
+        Node::new(id, NodeType::CooperativeServer, &format!("10.0.0.1:{}", 7000 + id.len()))
+    }
+
+    fn requests_to(outcome: &[(String, Message)]) -> Vec<String> {
+        outcome.iter().map(|(peer_id, _)| peer_id.clone()).collect()
+    }
+
+    #[test]
+    fn test_name_prefixes() {
+        assert_eq!(name_prefixes("/coopX/docs/report"), vec!["/coopX/docs/report", "/coopX/docs", "/coopX"]);
+        assert!(name_prefixes("/").is_empty());
+    }
+
+    #[test]
+    fn test_routing_table_buckets() {
+        let mut dht = Dht::new("local");
+        assert!(!dht.add_peer(node("local")));
+        for i in 0..50 {
+            dht.add_peer(node(&format!("peer{}", i)));
+        }
+        assert!(dht.add_peer(node("peer0")), "known peers are refreshed");
+        let target = DhtKey::of("peer7");
+        assert_eq!(dht.closest(&target, 1)[0].id, "peer7");
+
+        let closest = dht.closest(&target, 5);
+        let distances: Vec<DhtKey> = closest.iter().map(|node| DhtKey::of(&node.id).distance(&target)).collect();
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        dht.remove_peer("peer7");
+        assert_ne!(dht.closest(&target, 1)[0].id, "peer7");
+    }
+
+    #[test]
+    fn test_iterative_provider_lookup() {
+        // provider --announces--> registry <--knows-- hub <--knows-- consumer
+        let mut registry = Dht::new("registry");
+        let mut hub = Dht::new("hub");
+        let mut consumer = Dht::new("consumer");
+        let now = Instant::now();
+        hub.add_peer(node("registry"));
+        consumer.add_peer(node("hub"));
+
+        let mut provider = Dht::new("provider");
+        provider.add_peer(node("registry"));
+        for (peer_id, message) in provider.provide("/coopX/docs", node("provider"), now) {
+            assert_eq!(peer_id, "registry");
+            if let Message::Dht(message) = message {
+                registry.handle("provider", message, now);
+            }
+        }
+
+        let mut outcome = consumer.find_providers("/coopX/docs/report", now);
+        let mut routes = Vec::new();
+        let mut rounds = 0;
+        while let Some((peer_id, Message::Dht(request))) = outcome.requests.pop() {
+            rounds += 1;
+            let server = match peer_id.as_str() {
+                "hub" => &mut hub,
+                "registry" => &mut registry,
+                other => panic!("Unexpected peer {}", other),
+            };
+            let replies = server.handle("consumer", request, now).requests;
+            for (_, reply) in replies {
+                if let Message::Dht(reply) = reply {
+                    let next = consumer.handle(&peer_id, reply, now);
+                    routes.extend(next.routes);
+                    outcome.requests.extend(next.requests);
+                }
+            }
+            assert!(rounds < 20, "lookup did not terminate");
+        }
+
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].0, "/coopX/docs");
+        assert_eq!(routes[0].1.id, "provider");
+        assert!(requests_to(&consumer.bootstrap(now)).contains(&"hub".to_string()));
+        // Cached providers are returned without another lookup
+        assert_eq!(consumer.find_providers("/coopX/docs/other", now).routes.len(), 1);
+    }
+
+    #[test]
+    fn test_spoofed_provider_ignored() {
+        let mut dht = Dht::new("local");
+        let now = Instant::now();
+        dht.handle("mallory", DhtMessage::AddProvider { prefix: "/coopX".to_string(), provider: node("victim") }, now);
+        assert!(dht.providers("/coopX", now).is_empty());
+
+        dht.handle("victim", DhtMessage::AddProvider { prefix: "/coopX".to_string(), provider: node("victim") }, now);
+        assert_eq!(dht.providers("/coopX", now).len(), 1);
+        assert!(dht.providers("/coopX", now + PROVIDER_TTL).is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use tracing::{debug, info, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+use crate::error::{Error, Result};
+use super::node::{Node, NodeType};
+
+/// mDNS service type advertised by ICN nodes on the local network.
+pub const MDNS_SERVICE_TYPE: &str = "_icn._tcp.local.";
+/// Peers that fail this many consecutive connection attempts are forgotten.
+pub const MAX_PEER_FAILURES: u32 = 5;
+/// Upper bound on the number of peers returned in a single `Peers` message.
+pub const MAX_SHARED_PEERS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub node: Node,
+    pub last_seen: DateTime<Utc>,
+    pub failures: u32,
+}
+
+/// Known peers, optionally persisted as JSON so a restarted node can
+/// reconnect without going back to its bootstrap list.
+#[derive(Debug, Clone, Default)]
+pub struct PeerStore {
+    path: Option<PathBuf>,
+    peers: HashMap<String, PeerRecord>,
+}
+
+impl PeerStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a store backed by `path`, loading any peers saved there earlier.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let peers = if path.exists() {
+            let contents = fs::read(&path)?;
+            serde_json::from_slice(&contents)
+                .map_err(|e| Error::NetworkError(format!("Corrupt peer store {}: {}", path.display(), e)))?
+        } else {
+            HashMap::new()
+        };
+        Ok(PeerStore { path: Some(path), peers })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let contents = serde_json::to_vec_pretty(&self.peers).map_err(|e| Error::NetworkError(e.to_string()))?;
+            fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+
+    /// Records a peer as seen now. Returns true if the peer was not known before.
+    pub fn upsert(&mut self, node: Node) -> bool {
+        match self.peers.get_mut(&node.id) {
+            Some(record) => {
+                record.node = node;
+                record.last_seen = Utc::now();
+                record.failures = 0;
+                false
+            }
+            None => {
+                self.peers.insert(node.id.clone(), PeerRecord {
+                    node,
+                    last_seen: Utc::now(),
+                    failures: 0,
+                });
+                true
+            }
+        }
+    }
+
+    /// Counts a failed connection attempt. Returns true if the peer was evicted.
+    pub fn record_failure(&mut self, node_id: &str) -> bool {
+        let evict = match self.peers.get_mut(node_id) {
+            Some(record) => {
+                record.failures += 1;
+                record.failures >= MAX_PEER_FAILURES
+            }
+            None => false,
+        };
+        if evict {
+            self.peers.remove(node_id);
+        }
+        evict
+    }
+
+    pub fn remove(&mut self, node_id: &str) -> Option<PeerRecord> {
+        self.peers.remove(node_id)
+    }
+
+    pub fn get(&self, node_id: &str) -> Option<&PeerRecord> {
+        self.peers.get(node_id)
+    }
+
+    /// Known peers, most recently seen first.
+    pub fn peers(&self) -> Vec<Node> {
+        let mut records: Vec<&PeerRecord> = self.peers.values().collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.last_seen));
+        records.into_iter().map(|record| record.node.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+fn node_type_name(node_type: &NodeType) -> &'static str {
+    match node_type {
+        NodeType::PersonalDevice => "personal",
+        NodeType::CooperativeServer => "cooperative",
+        NodeType::GovernmentServer => "government",
+    }
+}
+
+fn parse_node_type(name: &str) -> Option<NodeType> {
+    match name {
+        "personal" => Some(NodeType::PersonalDevice),
+        "cooperative" => Some(NodeType::CooperativeServer),
+        "government" => Some(NodeType::GovernmentServer),
+        _ => None,
+    }
+}
+
+/// Builds a peer from a resolved mDNS service record.
+pub fn node_from_service(info: &ServiceInfo) -> Option<Node> {
+    let id = info.get_property_val_str("id")?;
+    let node_type = parse_node_type(info.get_property_val_str("type")?)?;
+    let ip = info.get_addresses_v4().into_iter().next()?;
+    Some(Node::new(id, node_type, &format!("{}:{}", ip, info.get_port())))
+}
+
+/// Advertises the local node over mDNS and reports other ICN nodes found on
+/// the local network. Stops advertising when dropped.
+pub struct MdnsDiscovery {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl std::fmt::Debug for MdnsDiscovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MdnsDiscovery").field("fullname", &self.fullname).finish()
+    }
+}
+
+impl MdnsDiscovery {
+    pub fn start(local: &Node, port: u16) -> Result<(Self, mpsc::Receiver<Node>)> {
+        let daemon = ServiceDaemon::new().map_err(|e| Error::NetworkError(format!("mDNS error: {}", e)))?;
+        let properties = [("id", local.id.as_str()), ("type", node_type_name(&local.node_type))];
+        let service = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &local.id,
+            &format!("{}.local.", local.id),
+            "",
+            port,
+            &properties[..],
+        )
+        .map_err(|e| Error::NetworkError(format!("mDNS error: {}", e)))?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service).map_err(|e| Error::NetworkError(format!("mDNS error: {}", e)))?;
+        let events = daemon.browse(MDNS_SERVICE_TYPE).map_err(|e| Error::NetworkError(format!("mDNS error: {}", e)))?;
+
+        let (sender, receiver) = mpsc::channel(32);
+        let local_id = local.id.clone();
+        std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                if let ServiceEvent::ServiceResolved(info) = event {
+                    match node_from_service(&info) {
+                        Some(node) if node.id == local_id => {}
+                        Some(node) => {
+                            debug!("Discovered peer {} at {} via mDNS", node.id, node.address);
+                            if sender.blocking_send(node).is_err() {
+                                break;
+                            }
+                        }
+                        None => warn!("Ignoring malformed mDNS record {}", info.get_fullname()),
+                    }
+                }
+            }
+        });
+
+        info!("Advertising {} over mDNS on port {}", local.id, port);
+        Ok((MdnsDiscovery { daemon, fullname }, receiver))
+    }
+}
+
+impl Drop for MdnsDiscovery {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_store_persistence() {
+        let path = std::env::temp_dir().join(format!("icn-peers-{}.json", uuid::Uuid::new_v4()));
+        let mut store = PeerStore::open(&path).unwrap();
+        assert!(store.upsert(Node::new("node1", NodeType::CooperativeServer, "10.0.0.1:7000")));
+        assert!(!store.upsert(Node::new("node1", NodeType::CooperativeServer, "10.0.0.1:7001")));
+        store.save().unwrap();
+
+        let reloaded = PeerStore::open(&path).unwrap();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded.get("node1").unwrap().node.address, "10.0.0.1:7001");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failing_peer_evicted() {
+        let mut store = PeerStore::new();
+        store.upsert(Node::new("flaky", NodeType::PersonalDevice, "10.0.0.2:7000"));
+        for _ in 1..MAX_PEER_FAILURES {
+            assert!(!store.record_failure("flaky"));
+        }
+        assert!(store.record_failure("flaky"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_node_from_service() {
+        let properties = [("id", "coop1"), ("type", "cooperative")];
+        let info = ServiceInfo::new(MDNS_SERVICE_TYPE, "coop1", "coop1.local.", "192.168.1.20", 7000, &properties[..]).unwrap();
+        let node = node_from_service(&info).unwrap();
+        assert_eq!(node.id, "coop1");
+        assert_eq!(node.address, "192.168.1.20:7000");
+        assert!(matches!(node.node_type, NodeType::CooperativeServer));
+
+        let anonymous = ServiceInfo::new(MDNS_SERVICE_TYPE, "x", "x.local.", "192.168.1.21", 7000, &[("type", "personal")][..]).unwrap();
+        assert!(node_from_service(&anonymous).is_none());
+    }
+}
@@ -0,0 +1,292 @@
+use std::sync::Mutex;
+use chrono::Utc;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use lru::LruCache;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::blockchain::{Block, Transaction};
+use crate::consensus::{EmergencyCall, SignedVote};
+use super::secure::NodeIdentity;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GossipPayload {
+    Block(Block),
+    Transaction(Box<Transaction>),
+    /// A validator's call to halt or resume the chain.
+    Emergency(EmergencyCall),
+    /// A validator's signed vote on a block.
+    Vote(SignedVote),
+}
+
+/// A block, transaction, emergency call or vote being disseminated through the network. The id is
+/// derived from the payload so the same item published twice is deduplicated.
+///
+/// The origin signs the id with its node identity, so that every node the
+/// message reaches can tell who published it; only the hop budget is left
+/// out, as forwarders spend it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipMessage {
+    pub id: String,
+    pub origin: String,
+    pub created_at_ms: i64,
+    pub ttl: u8,
+    pub payload: GossipPayload,
+    /// Identity key of the origin.
+    #[serde(default)]
+    pub origin_key: Option<Vec<u8>>,
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl GossipMessage {
+    pub fn new(origin: &str, ttl: u8, payload: GossipPayload) -> Self {
+        GossipMessage {
+            id: Self::payload_id(&payload),
+            origin: origin.to_string(),
+            created_at_ms: Utc::now().timestamp_millis(),
+            ttl,
+            payload,
+            origin_key: None,
+            signature: None,
+        }
+    }
+
+    /// Signs the message as its origin, `identity`.
+    pub fn signed(mut self, identity: &NodeIdentity) -> Self {
+        self.origin = identity.node_id.clone();
+        self.origin_key = Some(identity.public_key().to_bytes().to_vec());
+        self.signature = Some(identity.sign(&self.signed_portion()).to_bytes().to_vec());
+        self
+    }
+
+    /// The key the origin signed with, if the message is signed.
+    pub fn origin_public_key(&self) -> Option<PublicKey> {
+        self.origin_key.as_ref().and_then(|key| PublicKey::from_bytes(key).ok())
+    }
+
+    /// Whether the message is signed by the key it names and its id matches
+    /// its payload. Unsigned messages never verify.
+    pub fn verify(&self) -> bool {
+        let (public_key, signature) = match (self.origin_public_key(), &self.signature) {
+            (Some(public_key), Some(signature)) => (public_key, signature),
+            _ => return false,
+        };
+        self.id == Self::payload_id(&self.payload)
+            && Signature::from_bytes(signature).is_ok_and(|signature| public_key.verify(&self.signed_portion(), &signature).is_ok())
+    }
+
+    fn payload_id(payload: &GossipPayload) -> String {
+        let encoded = serde_json::to_vec(payload).unwrap_or_default();
+        hex::encode(Sha256::digest(&encoded))
+    }
+
+    fn signed_portion(&self) -> Vec<u8> {
+        let mut signed = b"icn-gossip:".to_vec();
+        signed.extend_from_slice(self.id.as_bytes());
+        signed.push(0);
+        signed.extend_from_slice(self.origin.as_bytes());
+        signed.extend_from_slice(&self.created_at_ms.to_le_bytes());
+        signed
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// Number of peers each message is pushed to per hop.
+    pub fanout: usize,
+    /// Maximum number of hops a message travels from its origin.
+    pub ttl: u8,
+    /// Number of recently seen message ids remembered for deduplication.
+    pub seen_cache_size: usize,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig {
+            fanout: 6,
+            ttl: 8,
+            seen_cache_size: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GossipMetrics {
+    pub published: u64,
+    pub received: u64,
+    pub duplicates: u64,
+    pub forwarded: u64,
+    pub latency_samples: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+impl GossipMetrics {
+    /// Mean time between publication at the origin and first receipt here.
+    pub fn average_latency_ms(&self) -> Option<f64> {
+        if self.latency_samples == 0 {
+            None
+        } else {
+            Some(self.total_latency_ms as f64 / self.latency_samples as f64)
+        }
+    }
+}
+
+/// Deduplication and fanout state shared by every handle to a `Network`.
+pub struct Gossip {
+    config: GossipConfig,
+    seen: Mutex<LruCache<String, ()>>,
+    metrics: Mutex<GossipMetrics>,
+}
+
+impl std::fmt::Debug for Gossip {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Gossip").field("config", &self.config).finish()
+    }
+}
+
+impl Gossip {
+    pub fn new(config: GossipConfig) -> Self {
+        Gossip {
+            seen: Mutex::new(LruCache::new(config.seen_cache_size)),
+            metrics: Mutex::new(GossipMetrics::default()),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &GossipConfig {
+        &self.config
+    }
+
+    /// Wraps a locally produced payload for dissemination, signed by this
+    /// node's identity.
+    pub fn publish(&self, identity: &NodeIdentity, payload: GossipPayload) -> GossipMessage {
+        let message = GossipMessage::new(&identity.node_id, self.config.ttl, payload).signed(identity);
+        self.seen.lock().unwrap().put(message.id.clone(), ());
+        self.metrics.lock().unwrap().published += 1;
+        message
+    }
+
+    /// Records receipt of a message. Returns true the first time a message id
+    /// is seen; duplicates should be neither delivered nor forwarded.
+    pub fn receive(&self, message: &GossipMessage) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let mut metrics = self.metrics.lock().unwrap();
+        if seen.contains(&message.id) {
+            metrics.duplicates += 1;
+            return false;
+        }
+        seen.put(message.id.clone(), ());
+        metrics.received += 1;
+
+        let latency = Utc::now().timestamp_millis().saturating_sub(message.created_at_ms).max(0) as u64;
+        metrics.latency_samples += 1;
+        metrics.total_latency_ms += latency;
+        metrics.max_latency_ms = metrics.max_latency_ms.max(latency);
+        true
+    }
+
+    /// The copy of `message` to pass on, or `None` once its hop budget is spent.
+    pub fn next_hop(&self, message: &GossipMessage) -> Option<GossipMessage> {
+        if message.ttl == 0 {
+            return None;
+        }
+        let mut forwarded = message.clone();
+        forwarded.ttl -= 1;
+        Some(forwarded)
+    }
+
+    /// Picks up to `fanout` random peers, skipping the sender and the origin.
+    pub fn select_peers(&self, peers: &[String], message: &GossipMessage, sender: Option<&str>) -> Vec<String> {
+        let candidates: Vec<&String> = peers.iter()
+            .filter(|peer| Some(peer.as_str()) != sender && **peer != message.origin)
+            .collect();
+        candidates
+            .choose_multiple(&mut rand::thread_rng(), self.config.fanout)
+            .map(|peer| (*peer).clone())
+            .collect()
+    }
+
+    pub fn record_forwarded(&self, count: usize) {
+        self.metrics.lock().unwrap().forwarded += count as u64;
+    }
+
+    pub fn metrics(&self) -> GossipMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+}
+
+impl Default for Gossip {
+    fn default() -> Self {
+        Gossip::new(GossipConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::CurrencyType;
+
+    fn transaction() -> GossipPayload {
+        GossipPayload::Transaction(Box::new(Transaction::new(
+            "alice".to_string(),
+            "bob".to_string(),
+            10.0,
+            CurrencyType::BasicNeeds,
+            1000,
+        )))
+    }
+
+    #[test]
+    fn test_duplicates_suppressed() {
+        let gossip = Gossip::default();
+        let message = GossipMessage::new("origin", 3, transaction());
+        assert!(gossip.receive(&message));
+        assert!(!gossip.receive(&message));
+
+        let metrics = gossip.metrics();
+        assert_eq!(metrics.received, 1);
+        assert_eq!(metrics.duplicates, 1);
+        assert!(metrics.average_latency_ms().is_some());
+    }
+
+    #[test]
+    fn test_own_messages_not_redelivered() {
+        let gossip = Gossip::default();
+        let message = gossip.publish(&NodeIdentity::generate("local"), transaction());
+        assert!(!gossip.receive(&message));
+    }
+
+    #[test]
+    fn test_messages_signed_by_their_origin() {
+        let origin = NodeIdentity::generate("origin");
+        let message = GossipMessage::new("origin", 3, transaction()).signed(&origin);
+        assert!(message.verify());
+        assert_eq!(message.origin_public_key(), Some(origin.public_key()));
+        assert!(Gossip::default().next_hop(&message).unwrap().verify(), "forwarding keeps the signature");
+
+        assert!(!GossipMessage::new("origin", 3, transaction()).verify());
+        let impersonated = GossipMessage { origin: "someone-else".to_string(), ..message.clone() };
+        assert!(!impersonated.verify());
+        let mut swapped = message.clone();
+        swapped.payload = GossipPayload::Block(crate::blockchain::Block::genesis());
+        assert!(!swapped.verify());
+        swapped.id = GossipMessage::new("origin", 3, swapped.payload.clone()).id;
+        assert!(!swapped.verify());
+    }
+
+    #[test]
+    fn test_ttl_and_fanout() {
+        let gossip = Gossip::new(GossipConfig { fanout: 2, ttl: 1, seen_cache_size: 16 });
+        let message = gossip.publish(&NodeIdentity::generate("origin"), transaction());
+        let hop = gossip.next_hop(&message).unwrap();
+        assert_eq!(hop.ttl, 0);
+        assert!(gossip.next_hop(&hop).is_none());
+
+        let peers: Vec<String> = ["origin", "sender", "a", "b", "c"].iter().map(|p| p.to_string()).collect();
+        let selected = gossip.select_peers(&peers, &message, Some("sender"));
+        assert_eq!(selected.len(), 2);
+        assert!(selected.iter().all(|peer| peer != "origin" && peer != "sender"));
+    }
+}
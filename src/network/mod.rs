@@ -1,7 +1,33 @@
+pub mod chain_data;
+pub mod dht;
+pub mod discovery;
+pub mod gossip;
 pub mod node;
 pub mod network;
+pub mod outbox;
 pub mod packet;
+pub mod peer_scoring;
+pub mod protocol;
+#[cfg(feature = "libp2p")]
+pub mod p2p;
+pub mod secure;
+pub mod segmentation;
+pub mod sync;
+pub mod transport;
 
+pub use self::chain_data::ChainName;
+pub use self::dht::{Dht, DhtMessage};
+pub use self::discovery::{MdnsDiscovery, PeerStore};
+pub use self::gossip::{GossipConfig, GossipMessage, GossipMetrics, GossipPayload};
 pub use self::node::Node;
 pub use self::network::Network;
-pub use self::packet::{Packet, PacketType};
+pub use self::outbox::Outbox;
+pub use self::packet::{ContentMeta, NackReason, Packet, PacketType, SignatureInfo};
+pub use self::peer_scoring::{BanEntry, Misbehavior, PeerScoring};
+pub use self::protocol::{NegotiatedProtocol, ProtocolInfo};
+pub use self::secure::NodeIdentity;
+pub use self::segmentation::{Manifest, SegmentFetcher};
+pub use self::sync::BlockSync;
+pub use self::transport::{InboundMessage, Message, TcpTransport, Transport};
+#[cfg(feature = "libp2p")]
+pub use self::p2p::Libp2pTransport;
@@ -1,34 +1,439 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use serde::{Serialize, Deserialize};
-use crate::blockchain::Block;
-use super::node::Node;
+use tracing::{info, warn, Instrument};
+use tokio::sync::mpsc;
+use crate::blockchain::{Block, Transaction};
+use crate::consensus::{EmergencyCall, SignedVote};
+use crate::error::{Error, Result};
+use super::dht::{Dht, DhtMessage, Route};
+use super::discovery::{MdnsDiscovery, PeerStore, MAX_SHARED_PEERS};
+use super::gossip::{Gossip, GossipConfig, GossipMessage, GossipMetrics, GossipPayload};
+use super::node::{Node, NodeType};
+use super::outbox::{self, Outbox};
+use super::peer_scoring::{BanEntry, Misbehavior, PeerScoring, Rejection, ScoringConfig};
+use super::protocol::ProtocolInfo;
+use super::secure::NodeIdentity;
+use super::transport::{InboundMessage, Message, TcpTransport, Transport};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Network {
     nodes: HashMap<String, Node>,
+    #[serde(skip)]
+    transport: Option<Arc<dyn Transport>>,
+    #[serde(skip)]
+    peer_store: PeerStore,
+    #[serde(skip)]
+    mdns: Option<Arc<MdnsDiscovery>>,
+    #[serde(skip)]
+    gossip: Arc<Gossip>,
+    #[serde(skip)]
+    scoring: Arc<Mutex<PeerScoring>>,
+    #[serde(skip)]
+    protocol: ProtocolInfo,
+    #[serde(skip)]
+    dht: Arc<Mutex<Dht>>,
+    /// Signs the gossip this node publishes.
+    #[serde(skip)]
+    identity: Option<Arc<NodeIdentity>>,
 }
 
 impl Network {
     pub fn new() -> Self {
         Network {
             nodes: HashMap::new(),
+            transport: None,
+            peer_store: PeerStore::new(),
+            mdns: None,
+            gossip: Arc::new(Gossip::default()),
+            scoring: Arc::new(Mutex::new(PeerScoring::default())),
+            protocol: ProtocolInfo::default(),
+            dht: Arc::new(Mutex::new(Dht::default())),
+            identity: None,
         }
     }
 
+    /// Sets the identity gossip is signed with, for networks whose transport
+    /// is set with `set_transport`; `start` sets it from its own.
+    pub fn with_identity(mut self, identity: NodeIdentity) -> Self {
+        self.identity = Some(Arc::new(identity));
+        self
+    }
+
+    /// Sets the network id, genesis hash and features announced to peers when
+    /// the transport is started.
+    pub fn with_protocol(mut self, protocol: ProtocolInfo) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_scoring_config(mut self, config: ScoringConfig) -> Self {
+        self.scoring = Arc::new(Mutex::new(PeerScoring::new(config)));
+        self
+    }
+
+    pub fn with_gossip_config(mut self, config: GossipConfig) -> Self {
+        self.gossip = Arc::new(Gossip::new(config));
+        self
+    }
+
+    /// Creates a network whose peers are persisted in `peer_store`. Peers saved
+    /// by an earlier run are known immediately.
+    pub fn with_peer_store(peer_store: PeerStore) -> Self {
+        let mut network = Network::new();
+        for node in peer_store.peers() {
+            network.dht.lock().unwrap().add_peer(node.clone());
+            network.nodes.insert(node.id.clone(), node);
+        }
+        network.peer_store = peer_store;
+        network
+    }
+
+    /// Starts listening for peers on `listen_addr`, authenticating connections
+    /// with `identity`. Messages received from any peer are delivered on the
+    /// returned channel.
+    pub async fn start(&mut self, identity: NodeIdentity, listen_addr: &str) -> Result<mpsc::Receiver<InboundMessage>> {
+        self.identity = Some(Arc::new(identity.clone()));
+        let (transport, inbound) = TcpTransport::bind_with_protocol(identity, self.protocol.clone(), listen_addr).await?;
+        self.set_transport(Arc::new(transport));
+        Ok(inbound)
+    }
+
+    /// Starts the libp2p stack instead of the plain TCP transport. `listen_addr`
+    /// may be a multiaddr or a `host:port` pair.
+    #[cfg(feature = "libp2p")]
+    pub async fn start_libp2p(&mut self, identity: NodeIdentity, listen_addr: &str) -> Result<mpsc::Receiver<InboundMessage>> {
+        self.identity = Some(Arc::new(identity.clone()));
+        let (transport, inbound) = super::p2p::Libp2pTransport::bind(identity, listen_addr).await?;
+        self.set_transport(Arc::new(transport));
+        Ok(inbound)
+    }
+
+    /// Uses an already running transport, e.g. a custom backend.
+    pub fn set_transport(&mut self, transport: Arc<dyn Transport>) {
+        self.dht.lock().unwrap().set_local_id(transport.local_id());
+        self.transport = Some(transport);
+    }
+
+    pub fn transport(&self) -> Option<&Arc<dyn Transport>> {
+        self.transport.as_ref()
+    }
+
+    /// Sends a message to a known peer, connecting to its address first if needed.
+    pub async fn send(&self, peer_id: &str, message: Message) -> Result<()> {
+        let transport = self.transport.as_ref()
+            .ok_or_else(|| Error::NetworkError("Network transport not started".to_string()))?;
+        let address = self.nodes.get(peer_id).map(|node| node.address.as_str());
+        outbox::deliver(transport.as_ref(), peer_id, address, &message).await
+    }
+
+    /// Starts a network worker sending over this network's transport.
+    pub fn outbox(&self) -> Outbox {
+        Outbox::spawn(self.transport.clone())
+    }
+
+    /// Queues a message for a known peer on a network worker.
+    pub async fn queue(&self, outbox: &Outbox, peer_id: &str, message: Message) {
+        let address = self.nodes.get(peer_id).map(|node| node.address.clone());
+        outbox.send(peer_id, address, message).await
+    }
+
+    /// Publishes a message to all connected peers.
+    pub async fn broadcast(&self, message: Message) -> Result<()> {
+        let transport = self.transport.as_ref()
+            .ok_or_else(|| Error::NetworkError("Network transport not started".to_string()))?;
+        transport.broadcast(&message).await
+    }
+
+    /// Starts disseminating a locally produced block to the whole network.
+    /// Returns the number of peers it was pushed to.
+    pub async fn publish_block(&self, block: Block) -> Result<usize> {
+        let span = crate::logging::block_span(&block);
+        self.publish(GossipPayload::Block(block)).instrument(span).await
+    }
+
+    /// Starts disseminating a transaction submitted to this node.
+    pub async fn publish_transaction(&self, transaction: Transaction) -> Result<usize> {
+        let span = crate::logging::transaction_span(&transaction);
+        self.publish(GossipPayload::Transaction(Box::new(transaction))).instrument(span).await
+    }
+
+    /// Starts disseminating a validator's call to halt or resume the chain.
+    pub async fn publish_emergency(&self, call: EmergencyCall) -> Result<usize> {
+        let span = tracing::info_span!("emergency", member = %call.member_id, action = ?call.action);
+        self.publish(GossipPayload::Emergency(call)).instrument(span).await
+    }
+
+    /// Starts disseminating this node's vote on a block.
+    pub async fn publish_vote(&self, vote: SignedVote) -> Result<usize> {
+        let span = tracing::info_span!("block", hash = %vote.block_hash);
+        self.publish(GossipPayload::Vote(vote)).instrument(span).await
+    }
+
+    async fn publish(&self, payload: GossipPayload) -> Result<usize> {
+        if self.transport.is_none() {
+            return Err(Error::NetworkError("Network transport not started".to_string()));
+        }
+        let identity = self.identity.as_ref()
+            .ok_or_else(|| Error::NetworkError("Network has no identity to sign gossip with".to_string()))?;
+        let message = self.gossip.publish(identity, payload);
+        Ok(self.push_gossip(&message, None).await)
+    }
+
+    /// Handles a gossip message from `sender`. The payload is returned the
+    /// first time the message is seen, after forwarding it to a random subset
+    /// of peers; duplicates return `None`. A message not signed by its origin,
+    /// or signed with another key than the one known for it, is dropped and
+    /// counted against `sender`.
+    pub async fn handle_gossip(&self, sender: &str, message: GossipMessage) -> Option<GossipPayload> {
+        let known_key = self.nodes.get(&message.origin).and_then(Node::key);
+        if !message.verify() || known_key.is_some_and(|key| Some(key) != message.origin_public_key()) {
+            warn!("Dropping gossip {} from {} not signed by its origin {}", message.id, sender, message.origin);
+            self.report_misbehavior(sender, Misbehavior::InvalidSignature).await;
+            return None;
+        }
+        if !self.gossip.receive(&message) {
+            return None;
+        }
+        if let Some(next) = self.gossip.next_hop(&message) {
+            let span = match &message.payload {
+                GossipPayload::Block(block) => crate::logging::block_span(block),
+                GossipPayload::Transaction(transaction) => crate::logging::transaction_span(transaction),
+                GossipPayload::Emergency(call) => tracing::info_span!("emergency", member = %call.member_id, action = ?call.action),
+                GossipPayload::Vote(vote) => tracing::info_span!("block", hash = %vote.block_hash),
+            };
+            self.push_gossip(&next, Some(sender)).instrument(span).await;
+        }
+        Some(message.payload)
+    }
+
+    async fn push_gossip(&self, message: &GossipMessage, sender: Option<&str>) -> usize {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => return 0,
+        };
+        let peers = self.gossip.select_peers(&transport.connected_peers().await, message, sender);
+        let mut pushed = 0;
+        for peer_id in peers {
+            match transport.send(&peer_id, &Message::Gossip(message.clone())).await {
+                Ok(()) => pushed += 1,
+                Err(e) => warn!("Failed to gossip {} to {}: {}", message.id, peer_id, e),
+            }
+        }
+        self.gossip.record_forwarded(pushed);
+        pushed
+    }
+
+    /// Applies rate limits and bans to an inbound message. Returns false if the
+    /// message must be dropped; banned peers are also disconnected.
+    pub async fn admit(&self, peer_id: &str, message: &Message) -> bool {
+        let result = self.scoring.lock().unwrap().check_message(peer_id, message, Instant::now());
+        match result {
+            Ok(()) => true,
+            Err(Rejection::RateLimited) => {
+                warn!("Rate limited {} from {}", super::peer_scoring::MessageKind::of(message).name(), peer_id);
+                false
+            }
+            Err(Rejection::Banned) => {
+                self.disconnect(peer_id).await;
+                false
+            }
+        }
+    }
+
+    /// Penalizes a peer for a protocol violation, disconnecting it if this
+    /// gets it banned.
+    pub async fn report_misbehavior(&self, peer_id: &str, misbehavior: Misbehavior) {
+        let ban = self.scoring.lock().unwrap().report(peer_id, misbehavior, Instant::now());
+        if let Some(ban) = ban {
+            warn!("Banned peer {} until {}: {}", peer_id, ban.expires_at, ban.reason);
+            self.disconnect(peer_id).await;
+        }
+    }
+
+    pub fn banned_peers(&self) -> Vec<BanEntry> {
+        self.scoring.lock().unwrap().bans()
+    }
+
+    pub fn unban_peer(&self, peer_id: &str) -> bool {
+        self.scoring.lock().unwrap().unban(peer_id)
+    }
+
+    pub fn peer_score(&self, peer_id: &str) -> f64 {
+        self.scoring.lock().unwrap().score(peer_id)
+    }
+
+    async fn disconnect(&self, peer_id: &str) {
+        if let Some(transport) = &self.transport {
+            transport.disconnect(peer_id).await;
+        }
+    }
+
+    /// Announces that this node serves content under `prefix` so that other
+    /// nodes' interests can be routed here. Returns the number of peers the
+    /// provider record was stored on.
+    pub async fn provide(&self, prefix: &str, node_type: NodeType) -> Result<usize> {
+        let transport = self.transport.as_ref()
+            .ok_or_else(|| Error::NetworkError("Network transport not started".to_string()))?;
+        let local = self.local_node(transport.as_ref(), node_type, &transport.listen_addr());
+        let requests = self.dht.lock().unwrap().provide(prefix, local, Instant::now());
+        Ok(self.send_dht(requests).await)
+    }
+
+    /// Returns the routes already known for `name` and starts DHT lookups for
+    /// the prefixes without one. Routes found later are returned by
+    /// `handle_dht`.
+    pub async fn find_providers(&self, name: &str) -> Vec<Route> {
+        let outcome = self.dht.lock().unwrap().find_providers(name, Instant::now());
+        self.send_dht(outcome.requests).await;
+        outcome.routes
+    }
+
+    /// Asks peers for the nodes closest to us, filling the DHT routing table.
+    pub async fn bootstrap_dht(&self) -> usize {
+        let requests = self.dht.lock().unwrap().bootstrap(Instant::now());
+        self.send_dht(requests).await
+    }
+
+    /// Handles a DHT message from `peer_id`, adding any nodes it mentions to
+    /// the known peers. Returns the routes a provider lookup just resolved.
+    pub async fn handle_dht(&mut self, peer_id: &str, message: DhtMessage) -> Vec<Route> {
+        let outcome = self.dht.lock().unwrap().handle(peer_id, message, Instant::now());
+        self.merge_peers(outcome.learned);
+        for (_, provider) in &outcome.routes {
+            if !self.nodes.contains_key(&provider.id) {
+                self.add_node(provider.clone());
+            }
+        }
+        self.send_dht(outcome.requests).await;
+        outcome.routes
+    }
+
+    async fn send_dht(&self, requests: Vec<(String, Message)>) -> usize {
+        let mut sent = 0;
+        for (peer_id, message) in requests {
+            match self.send(&peer_id, message).await {
+                Ok(()) => sent += 1,
+                Err(e) => warn!("Failed to send DHT request to {}: {}", peer_id, e),
+            }
+        }
+        sent
+    }
+
+    pub fn gossip_metrics(&self) -> GossipMetrics {
+        self.gossip.metrics()
+    }
+
     pub fn add_node(&mut self, node: Node) {
+        self.dht.lock().unwrap().add_peer(node.clone());
+        self.peer_store.upsert(node.clone());
+        self.save_peers();
         self.nodes.insert(node.id.clone(), node);
     }
 
     pub fn remove_node(&mut self, node_id: &str) {
+        self.dht.lock().unwrap().remove_peer(node_id);
         self.nodes.remove(node_id);
+        self.peer_store.remove(node_id);
+        self.save_peers();
+    }
+
+    pub fn peer_store(&self) -> &PeerStore {
+        &self.peer_store
+    }
+
+    fn save_peers(&self) {
+        if let Err(e) = self.peer_store.save() {
+            warn!("Failed to persist peer store: {}", e);
+        }
+    }
+
+    /// Connects to the given bootstrap peers and asks each for its peer list.
+    /// Returns how many bootstrap peers were reached.
+    pub async fn bootstrap(&mut self, bootstrap_peers: &[Node]) -> usize {
+        for node in bootstrap_peers {
+            self.add_node(node.clone());
+        }
+        let mut reached = 0;
+        for node in bootstrap_peers {
+            match self.send(&node.id, Message::GetPeers).await {
+                Ok(()) => reached += 1,
+                Err(e) => {
+                    warn!("Bootstrap peer {} unreachable: {}", node.id, e);
+                    if self.peer_store.record_failure(&node.id) {
+                        self.nodes.remove(&node.id);
+                        self.dht.lock().unwrap().remove_peer(&node.id);
+                    }
+                    self.save_peers();
+                }
+            }
+        }
+        info!("Bootstrapped from {}/{} peers", reached, bootstrap_peers.len());
+        reached
+    }
+
+    /// The peers shared in reply to a `GetPeers` request from `requester`.
+    pub fn peer_list(&self, requester: &str) -> Vec<Node> {
+        self.peer_store.peers().into_iter()
+            .filter(|node| node.id != requester)
+            .take(MAX_SHARED_PEERS)
+            .collect()
+    }
+
+    /// Adds peers learned from another node or from mDNS, skipping ourselves.
+    /// Returns the number of previously unknown peers.
+    pub fn merge_peers(&mut self, peers: Vec<Node>) -> usize {
+        let local_id = self.transport.as_ref().map(|transport| transport.local_id().to_string());
+        let mut added = 0;
+        for node in peers {
+            if Some(&node.id) == local_id.as_ref() {
+                continue;
+            }
+            if !self.nodes.contains_key(&node.id) {
+                added += 1;
+            }
+            self.add_node(node);
+        }
+        added
+    }
+
+    /// Advertises this node over mDNS and returns the peers discovered on the
+    /// local network. Feed them to `merge_peers`.
+    pub fn start_mdns(&mut self, node_type: NodeType) -> Result<mpsc::Receiver<Node>> {
+        let transport = self.transport.as_ref()
+            .ok_or_else(|| Error::NetworkError("Network transport not started".to_string()))?;
+        let listen_addr = transport.listen_addr();
+        let port = listen_addr.parse::<std::net::SocketAddr>()
+            .map_err(|_| Error::NetworkError(format!("Cannot advertise non-TCP address {} over mDNS", listen_addr)))?
+            .port();
+        let local = self.local_node(transport.as_ref(), node_type, &listen_addr);
+        let (mdns, discovered) = MdnsDiscovery::start(&local, port)?;
+        self.mdns = Some(Arc::new(mdns));
+        Ok(discovered)
+    }
+
+    /// This node as announced to others, with its identity key.
+    fn local_node(&self, transport: &dyn Transport, node_type: NodeType, address: &str) -> Node {
+        let node = Node::new(transport.local_id(), node_type, address);
+        match &self.identity {
+            Some(identity) => node.with_public_key(&identity.public_key()),
+            None => node,
+        }
     }
 
     pub fn get_node(&self, node_id: &str) -> Option<&Node> {
         self.nodes.get(node_id)
     }
 
+    /// The known peer listening on `addr`, used to turn FIB next hops into peers.
+    pub fn node_by_address(&self, addr: &std::net::SocketAddr) -> Option<&Node> {
+        self.nodes.values().find(|node| node.address.parse().ok() == Some(*addr))
+    }
+
     pub fn broadcast_block(&self, block: &Block) {
-        println!("Broadcasting block {} to all nodes", block.index);
+        let _span = crate::logging::block_span(block).entered();
+        info!("Broadcasting block to all nodes");
         // Actual implementation would involve network communication
     }
 
@@ -45,23 +450,11 @@ impl Network {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum PacketType {
-    Interest,
-    Data,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Packet {
-    pub packet_type: PacketType,
-    pub name: String,
-    pub content: Vec<u8>,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::network::node::NodeType;
+    use crate::network::{Packet, PacketType};
 
     #[test]
     fn test_network_operations() {
@@ -90,20 +483,59 @@ mod tests {
             hash: "hash".to_string(),
             nonce: 0,
             gas_used: 0,
+            logs_bloom: Default::default(),
             smart_contract_results: HashMap::new(),
+            contract_gas: Default::default(),
+            shard_roots: Default::default(),
+            receipts_root: String::new(),
+            protocol_version: 1,
+            proposer: String::new(),
+            proposer_signature: None,
         };
         network.broadcast_block(&block);
 
         network.synchronize_blockchain(&[block]);
     }
 
+    #[tokio::test]
+    async fn test_peer_exchange_lists() {
+        let mut network = Network::new();
+        network.start(NodeIdentity::generate("local"), "127.0.0.1:0").await.unwrap();
+        network.add_node(Node::new("node1", NodeType::CooperativeServer, "10.0.0.1:7000"));
+
+        let learned = vec![
+            Node::new("local", NodeType::PersonalDevice, "127.0.0.1:1"),
+            Node::new("node1", NodeType::CooperativeServer, "10.0.0.1:7000"),
+            Node::new("node2", NodeType::PersonalDevice, "10.0.0.2:7000"),
+        ];
+        assert_eq!(network.merge_peers(learned), 1);
+        assert!(network.get_node("local").is_none());
+
+        let shared: Vec<String> = network.peer_list("node2").into_iter().map(|node| node.id).collect();
+        assert_eq!(shared, vec!["node1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_network_send() {
+        let mut network1 = Network::new();
+        let mut network2 = Network::new();
+        network1.start(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let mut inbound2 = network2.start(NodeIdentity::generate("node2"), "127.0.0.1:0").await.unwrap();
+
+        let node2_addr = network2.transport().unwrap().listen_addr();
+        network1.add_node(Node::new("node2", NodeType::CooperativeServer, &node2_addr));
+
+        let packet = Packet::data("/coopX/docs", vec![1, 2, 3]);
+        network1.send("node2", Message::Packet(packet.clone())).await.unwrap();
+        let (from, _) = inbound2.recv().await.unwrap();
+        assert_eq!(from, "node1");
+
+        assert!(network1.send("unknown", Message::Packet(packet)).await.is_err());
+    }
+
     #[test]
     fn test_packet_creation() {
-        let packet = Packet {
-            packet_type: PacketType::Interest,
-            name: "test_packet".to_string(),
-            content: vec![1, 2, 3, 4],
-        };
+        let packet = Packet { content: vec![1, 2, 3, 4], ..Packet::interest("test_packet") };
 
         assert_eq!(packet.name, "test_packet");
         assert_eq!(packet.content, vec![1, 2, 3, 4]);
@@ -1,3 +1,4 @@
+use ed25519_dalek::PublicKey;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -12,6 +13,9 @@ pub struct Node {
     pub id: String,
     pub node_type: NodeType,
     pub address: String,
+    /// Hex of the Ed25519 key the node signs its messages with, if known.
+    #[serde(default)]
+    pub public_key: Option<String>,
 }
 
 impl Node {
@@ -20,6 +24,17 @@ impl Node {
             id: id.to_string(),
             node_type,
             address: address.to_string(),
+            public_key: None,
         }
     }
+
+    pub fn with_public_key(mut self, public_key: &PublicKey) -> Self {
+        self.public_key = Some(hex::encode(public_key.to_bytes()));
+        self
+    }
+
+    pub fn key(&self) -> Option<PublicKey> {
+        let bytes = hex::decode(self.public_key.as_ref()?).ok()?;
+        PublicKey::from_bytes(&bytes).ok()
+    }
 }
@@ -0,0 +1,61 @@
+// src/network/outbox.rs
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::warn;
+use crate::error::{Error, Result};
+use super::transport::{Message, Transport};
+
+/// Messages the node may queue for sending before it has to wait.
+pub const OUTBOX_QUEUE_SIZE: usize = 256;
+
+struct Outbound {
+    peer_id: String,
+    address: Option<String>,
+    message: Message,
+}
+
+/// Queue of the network worker, which sends messages in the order they were
+/// queued. A slow or unreachable peer holds up the queue rather than the
+/// processing of inbound messages; once the queue is full, `send` waits.
+#[derive(Clone)]
+pub struct Outbox {
+    queue: mpsc::Sender<Outbound>,
+}
+
+impl Outbox {
+    /// Starts the network worker on the current runtime. It stops once every
+    /// handle to the outbox is dropped.
+    pub fn spawn(transport: Option<Arc<dyn Transport>>) -> Self {
+        let (queue, mut receiver) = mpsc::channel::<Outbound>(OUTBOX_QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(outbound) = receiver.recv().await {
+                let result = match &transport {
+                    Some(transport) => deliver(transport.as_ref(), &outbound.peer_id, outbound.address.as_deref(), &outbound.message).await,
+                    None => Err(Error::NetworkError("Network transport not started".to_string())),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to send to {}: {}", outbound.peer_id, e);
+                }
+            }
+        });
+        Outbox { queue }
+    }
+
+    /// Queues a message for `peer_id`, to be sent after connecting to
+    /// `address` should the peer not be connected by then.
+    pub async fn send(&self, peer_id: &str, address: Option<String>, message: Message) {
+        let outbound = Outbound { peer_id: peer_id.to_string(), address, message };
+        if self.queue.send(outbound).await.is_err() {
+            warn!("Network worker has stopped, dropping message to {}", peer_id);
+        }
+    }
+}
+
+/// Sends a message, connecting to the peer at `address` first if needed.
+pub(super) async fn deliver(transport: &dyn Transport, peer_id: &str, address: Option<&str>, message: &Message) -> Result<()> {
+    if !transport.is_connected(peer_id).await {
+        let address = address.ok_or_else(|| Error::NetworkError(format!("Unknown peer: {}", peer_id)))?;
+        transport.connect(peer_id, address).await?;
+    }
+    transport.send(peer_id, message).await
+}
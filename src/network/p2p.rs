@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::StreamExt;
+use libp2p::core::ConnectedPoint;
+use libp2p::gossipsub::{self, IdentTopic, MessageAuthenticity};
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use libp2p::{identify, kad, noise, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder};
+use tracing::{debug, info, warn};
+use tokio::sync::{mpsc, oneshot};
+use crate::error::{Error, Result};
+use super::gossip::{GossipMessage, GossipPayload};
+use super::packet::PacketType;
+use super::secure::NodeIdentity;
+use super::transport::{InboundMessage, Message, Transport, MAX_FRAME_SIZE};
+
+pub const BLOCKS_TOPIC: &str = "icn/blocks";
+pub const TRANSACTIONS_TOPIC: &str = "icn/transactions";
+pub const INTERESTS_TOPIC: &str = "icn/interests";
+
+const PROTOCOL_VERSION: &str = "/icn/2.0.0";
+const AGENT_PREFIX: &str = "icn-node/";
+const DIRECT_PROTOCOL: StreamProtocol = StreamProtocol::new("/icn/message/2.0.0");
+const INBOUND_QUEUE_SIZE: usize = 1024;
+const COMMAND_QUEUE_SIZE: usize = 256;
+const GOSSIP_MAX_TRANSMIT: usize = 1024 * 1024;
+
+fn p2p_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::NetworkError(format!("libp2p error: {}", e))
+}
+
+// Kept apart from the crate's `Result` alias, which the derive would otherwise pick up.
+mod behaviour {
+    use libp2p::swarm::NetworkBehaviour;
+    use libp2p::{gossipsub, identify, kad, request_response};
+    use crate::network::transport::Message;
+
+    #[derive(NetworkBehaviour)]
+    pub(super) struct Behaviour {
+        pub(super) gossipsub: gossipsub::Behaviour,
+        pub(super) identify: identify::Behaviour,
+        pub(super) kad: kad::Behaviour<kad::store::MemoryStore>,
+        pub(super) direct: request_response::json::Behaviour<Message, ()>,
+    }
+}
+
+use behaviour::{Behaviour, BehaviourEvent};
+
+/// Maps libp2p peer ids to ICN node ids. Node ids are learned from the
+/// agent version a peer advertises over identify.
+#[derive(Debug, Default)]
+struct PeerBook {
+    by_node: HashMap<String, PeerId>,
+    by_peer: HashMap<PeerId, String>,
+}
+
+impl PeerBook {
+    fn insert(&mut self, node_id: String, peer: PeerId) {
+        self.by_node.insert(node_id.clone(), peer);
+        self.by_peer.insert(peer, node_id);
+    }
+
+    fn remove_peer(&mut self, peer: &PeerId) {
+        if let Some(node_id) = self.by_peer.remove(peer) {
+            self.by_node.remove(&node_id);
+        }
+    }
+
+    fn node_id(&self, peer: &PeerId) -> String {
+        self.by_peer.get(peer).cloned().unwrap_or_else(|| peer.to_string())
+    }
+}
+
+enum Command {
+    Dial { node_id: String, addr: Multiaddr, reply: oneshot::Sender<Result<()>> },
+    Send { peer: PeerId, message: Box<Message> },
+    Publish { topic: IdentTopic, data: Vec<u8>, reply: oneshot::Sender<Result<()>> },
+    Disconnect { peer: PeerId },
+}
+
+/// Full P2P backend built on libp2p: identify maps peers to node ids, Kademlia
+/// keeps the routing table, gossipsub carries blocks, transactions and
+/// interests, and a request-response protocol carries direct messages.
+#[derive(Debug, Clone)]
+pub struct Libp2pTransport {
+    local_id: String,
+    peer_id: PeerId,
+    listen_addr: Multiaddr,
+    peers: Arc<Mutex<PeerBook>>,
+    commands: mpsc::Sender<Command>,
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Command::Dial { node_id, addr, .. } => write!(f, "Dial({}, {})", node_id, addr),
+            Command::Send { peer, .. } => write!(f, "Send({})", peer),
+            Command::Publish { topic, .. } => write!(f, "Publish({})", topic),
+            Command::Disconnect { peer } => write!(f, "Disconnect({})", peer),
+        }
+    }
+}
+
+/// Accepts either a multiaddr or a `host:port` socket address.
+pub fn parse_multiaddr(addr: &str) -> Result<Multiaddr> {
+    if addr.starts_with('/') {
+        return addr.parse().map_err(p2p_error);
+    }
+    let socket: SocketAddr = addr.parse().map_err(p2p_error)?;
+    Ok(Multiaddr::from(socket.ip()).with(Protocol::Tcp(socket.port())))
+}
+
+fn topic_for(message: &Message) -> Result<IdentTopic> {
+    match message {
+        Message::Block(_)
+        | Message::Gossip(GossipMessage { payload: GossipPayload::Block(_) | GossipPayload::Emergency(_) | GossipPayload::Vote(_), .. }) => {
+            Ok(IdentTopic::new(BLOCKS_TOPIC))
+        }
+        Message::Transaction(_) | Message::Gossip(GossipMessage { payload: GossipPayload::Transaction(_), .. }) => {
+            Ok(IdentTopic::new(TRANSACTIONS_TOPIC))
+        }
+        Message::Packet(packet) if packet.packet_type == PacketType::Interest => Ok(IdentTopic::new(INTERESTS_TOPIC)),
+        Message::Packet(_) => Err(Error::NetworkError(
+            "Data and nack packets follow the reverse path of an interest and cannot be broadcast".to_string(),
+        )),
+        _ => Err(Error::NetworkError(
+            "Peer exchange and sync messages are sent directly".to_string(),
+        )),
+    }
+}
+
+impl Libp2pTransport {
+    /// Starts a swarm listening on `addr` and subscribes to the ICN topics.
+    pub async fn bind(identity: NodeIdentity, addr: &str) -> Result<(Self, mpsc::Receiver<InboundMessage>)> {
+        let keypair = libp2p::identity::Keypair::ed25519_from_bytes(identity.secret_key_bytes())
+            .map_err(p2p_error)?;
+        let local_id = identity.node_id.clone();
+        let agent_version = format!("{}{}", AGENT_PREFIX, local_id);
+
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+            .map_err(p2p_error)?
+            .with_behaviour(|key| {
+                let peer_id = key.public().to_peer_id();
+                let gossip_config = gossipsub::ConfigBuilder::default()
+                    .max_transmit_size(GOSSIP_MAX_TRANSMIT)
+                    .build()?;
+                let mut kad = kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id));
+                kad.set_mode(Some(kad::Mode::Server));
+                Ok(Behaviour {
+                    gossipsub: gossipsub::Behaviour::new(MessageAuthenticity::Signed(key.clone()), gossip_config)?,
+                    identify: identify::Behaviour::new(
+                        identify::Config::new(PROTOCOL_VERSION.to_string(), key.public())
+                            .with_agent_version(agent_version),
+                    ),
+                    kad,
+                    direct: request_response::json::Behaviour::new(
+                        [(DIRECT_PROTOCOL, ProtocolSupport::Full)],
+                        request_response::Config::default(),
+                    ),
+                })
+            })
+            .map_err(p2p_error)?
+            .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+
+        for topic in [BLOCKS_TOPIC, TRANSACTIONS_TOPIC, INTERESTS_TOPIC] {
+            swarm.behaviour_mut().gossipsub.subscribe(&IdentTopic::new(topic)).map_err(p2p_error)?;
+        }
+        swarm.listen_on(parse_multiaddr(addr)?).map_err(p2p_error)?;
+        let listen_addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                break address;
+            }
+        };
+
+        let peer_id = *swarm.local_peer_id();
+        let peers = Arc::new(Mutex::new(PeerBook::default()));
+        let (inbound, receiver) = mpsc::channel(INBOUND_QUEUE_SIZE);
+        let (commands, command_receiver) = mpsc::channel(COMMAND_QUEUE_SIZE);
+
+        let event_loop = EventLoop {
+            swarm,
+            peers: Arc::clone(&peers),
+            inbound,
+            pending_dials: HashMap::new(),
+            awaiting_identify: HashMap::new(),
+        };
+        tokio::spawn(event_loop.run(command_receiver));
+
+        info!("Node {} ({}) listening on {}", local_id, peer_id, listen_addr);
+        let transport = Libp2pTransport {
+            local_id,
+            peer_id,
+            listen_addr,
+            peers,
+            commands,
+        };
+        Ok((transport, receiver))
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    fn peer_for(&self, node_id: &str) -> Result<PeerId> {
+        self.peers.lock().unwrap().by_node.get(node_id).copied()
+            .ok_or_else(|| Error::NetworkError(format!("Not connected to peer {}", node_id)))
+    }
+
+    async fn command(&self, command: Command) -> Result<()> {
+        self.commands.send(command).await
+            .map_err(|_| Error::NetworkError("libp2p event loop stopped".to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for Libp2pTransport {
+    fn local_id(&self) -> &str {
+        &self.local_id
+    }
+
+    fn listen_addr(&self) -> String {
+        self.listen_addr.clone().with(Protocol::P2p(self.peer_id)).to_string()
+    }
+
+    async fn connect(&self, peer_id: &str, addr: &str) -> Result<()> {
+        if self.is_connected(peer_id).await {
+            return Ok(());
+        }
+        let (reply, response) = oneshot::channel();
+        self.command(Command::Dial {
+            node_id: peer_id.to_string(),
+            addr: parse_multiaddr(addr)?,
+            reply,
+        }).await?;
+        response.await.map_err(|_| Error::NetworkError("libp2p event loop stopped".to_string()))?
+    }
+
+    async fn send(&self, peer_id: &str, message: &Message) -> Result<()> {
+        let peer = self.peer_for(peer_id)?;
+        self.command(Command::Send { peer, message: Box::new(message.clone()) }).await
+    }
+
+    async fn broadcast(&self, message: &Message) -> Result<()> {
+        let topic = topic_for(message)?;
+        let data = serde_json::to_vec(message).map_err(|e| Error::NetworkError(e.to_string()))?;
+        if data.len() > MAX_FRAME_SIZE {
+            return Err(Error::NetworkError(format!("Frame too large: {} bytes", data.len())));
+        }
+        let (reply, response) = oneshot::channel();
+        self.command(Command::Publish { topic, data, reply }).await?;
+        response.await.map_err(|_| Error::NetworkError("libp2p event loop stopped".to_string()))?
+    }
+
+    async fn disconnect(&self, peer_id: &str) {
+        if let Ok(peer) = self.peer_for(peer_id) {
+            let _ = self.command(Command::Disconnect { peer }).await;
+        }
+    }
+
+    async fn is_connected(&self, peer_id: &str) -> bool {
+        self.peers.lock().unwrap().by_node.contains_key(peer_id)
+    }
+
+    async fn connected_peers(&self) -> Vec<String> {
+        self.peers.lock().unwrap().by_node.keys().cloned().collect()
+    }
+}
+
+type DialReply = (String, oneshot::Sender<Result<()>>);
+
+struct EventLoop {
+    swarm: Swarm<Behaviour>,
+    peers: Arc<Mutex<PeerBook>>,
+    inbound: mpsc::Sender<InboundMessage>,
+    pending_dials: HashMap<ConnectionId, DialReply>,
+    awaiting_identify: HashMap<PeerId, Vec<DialReply>>,
+}
+
+impl EventLoop {
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+        loop {
+            tokio::select! {
+                event = self.swarm.select_next_some() => self.handle_event(event),
+                command = commands.recv() => match command {
+                    Some(command) => self.handle_command(command),
+                    None => break,
+                },
+            }
+        }
+        debug!("libp2p event loop stopped");
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Dial { node_id, addr, reply } => {
+                let opts = DialOpts::from(addr);
+                let connection_id = opts.connection_id();
+                match self.swarm.dial(opts) {
+                    Ok(()) => {
+                        self.pending_dials.insert(connection_id, (node_id, reply));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(p2p_error(e)));
+                    }
+                }
+            }
+            Command::Send { peer, message } => {
+                self.swarm.behaviour_mut().direct.send_request(&peer, *message);
+            }
+            Command::Publish { topic, data, reply } => {
+                let result = match self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+                    Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => Ok(()),
+                    Err(e) => Err(p2p_error(e)),
+                };
+                let _ = reply.send(result);
+            }
+            Command::Disconnect { peer } => {
+                let _ = self.swarm.disconnect_peer_id(peer);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                if let ConnectedPoint::Dialer { address, .. } = endpoint {
+                    self.swarm.behaviour_mut().kad.add_address(&peer_id, address);
+                }
+                if let Some(dial) = self.pending_dials.remove(&connection_id) {
+                    self.awaiting_identify.entry(peer_id).or_default().push(dial);
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, error, .. } => {
+                if let Some((node_id, reply)) = self.pending_dials.remove(&connection_id) {
+                    let _ = reply.send(Err(Error::NetworkError(format!("Failed to reach {}: {}", node_id, error))));
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                self.peers.lock().unwrap().remove_peer(&peer_id);
+                for (node_id, reply) in self.awaiting_identify.remove(&peer_id).unwrap_or_default() {
+                    let _ = reply.send(Err(Error::NetworkError(format!("Connection to {} closed", node_id))));
+                }
+                debug!("Connection to {} closed", peer_id);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info })) => {
+                self.handle_identify(peer_id, info);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message, .. })) => {
+                let source = message.source.unwrap_or(propagation_source);
+                self.deliver(source, &message.data);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Direct(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request, channel, .. },
+            })) => {
+                let node_id = self.peers.lock().unwrap().node_id(&peer);
+                self.forward(node_id, request);
+                let _ = self.swarm.behaviour_mut().direct.send_response(channel, ());
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Direct(request_response::Event::OutboundFailure { peer, error, .. })) => {
+                warn!("Failed to deliver message to {}: {}", peer, error);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_identify(&mut self, peer_id: PeerId, info: identify::Info) {
+        let node_id = match info.agent_version.strip_prefix(AGENT_PREFIX) {
+            Some(node_id) if info.protocol_version == PROTOCOL_VERSION => node_id.to_string(),
+            _ => {
+                debug!("Ignoring non-ICN peer {} ({})", peer_id, info.agent_version);
+                return;
+            }
+        };
+        for address in info.listen_addrs {
+            self.swarm.behaviour_mut().kad.add_address(&peer_id, address);
+        }
+        self.peers.lock().unwrap().insert(node_id.clone(), peer_id);
+        info!("Identified peer {} as {}", peer_id, node_id);
+
+        for (expected, reply) in self.awaiting_identify.remove(&peer_id).unwrap_or_default() {
+            let result = if expected == node_id {
+                Ok(())
+            } else {
+                Err(Error::NetworkError(format!("Expected peer {} but reached {}", expected, node_id)))
+            };
+            let _ = reply.send(result);
+        }
+    }
+
+    fn deliver(&mut self, source: PeerId, data: &[u8]) {
+        match serde_json::from_slice(data) {
+            Ok(message) => {
+                let node_id = self.peers.lock().unwrap().node_id(&source);
+                self.forward(node_id, message);
+            }
+            Err(e) => warn!("Dropping malformed gossip from {}: {}", source, e),
+        }
+    }
+
+    fn forward(&self, node_id: String, message: Message) {
+        if self.inbound.try_send((node_id, message)).is_err() {
+            warn!("Inbound queue full, dropping message");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Packet;
+
+    fn interest(name: &str) -> Message {
+        Message::Packet(Packet::interest(name))
+    }
+
+    #[test]
+    fn test_parse_multiaddr() {
+        assert_eq!(parse_multiaddr("127.0.0.1:4001").unwrap().to_string(), "/ip4/127.0.0.1/tcp/4001");
+        assert_eq!(parse_multiaddr("/ip4/10.0.0.1/tcp/9000").unwrap().to_string(), "/ip4/10.0.0.1/tcp/9000");
+        assert!(parse_multiaddr("not an address").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_direct_send_and_gossip() {
+        let (node1, _inbound1) = Libp2pTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (node2, mut inbound2) = Libp2pTransport::bind(NodeIdentity::generate("node2"), "127.0.0.1:0").await.unwrap();
+
+        node1.connect("node2", &node2.listen_addr()).await.unwrap();
+        assert!(node1.is_connected("node2").await);
+
+        node1.send("node2", &interest("/coopX/direct")).await.unwrap();
+        let (from, message) = inbound2.recv().await.unwrap();
+        assert_eq!(from, "node1");
+        assert!(matches!(message, Message::Packet(packet) if packet.name == "/coopX/direct"));
+
+        // Gossipsub needs a moment to learn the peer's subscriptions
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                node1.broadcast(&interest("/coopX/gossip")).await.unwrap();
+                if let Ok(Some((_, Message::Packet(packet)))) =
+                    tokio::time::timeout(Duration::from_millis(200), inbound2.recv()).await
+                {
+                    break packet.name;
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(received, "/coopX/gossip");
+    }
+
+    #[tokio::test]
+    async fn test_data_packets_are_not_broadcast() {
+        let (node, _inbound) = Libp2pTransport::bind(NodeIdentity::generate("node"), "127.0.0.1:0").await.unwrap();
+        let data = Message::Packet(Packet::data("/coopX/docs", vec![1]));
+        assert!(node.broadcast(&data).await.is_err());
+    }
+}
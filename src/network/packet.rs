@@ -1,12 +1,205 @@
-#[derive(Clone, Debug)]
+use std::time::Duration;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Serialize, Deserialize};
+use crate::identity::resolution::{did_content_name, did_from_content_name};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum PacketType {
     Interest,
     Data,
+    /// Sent back along the path of an interest that cannot be satisfied, so
+    /// downstream nodes learn of the failure before the interest times out.
+    Nack(NackReason),
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NackReason {
+    /// No FIB entry matches the name.
+    NoRoute,
+    /// The node is too busy to take on another pending interest.
+    Congestion,
+    /// The producer for the name has no such content.
+    NoData,
+}
+
+/// Freshness and versioning of the content carried by a Data packet.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContentMeta {
+    /// How long after arrival a cached copy may answer interests that must be
+    /// fresh. Content without a freshness period is stale as soon as it is
+    /// cached.
+    pub freshness_period_ms: Option<u64>,
+    /// Producer-assigned version; caches never replace content with an older one.
+    pub version: Option<u64>,
+}
+
+impl ContentMeta {
+    pub fn freshness_period(&self) -> Option<Duration> {
+        self.freshness_period_ms.map(Duration::from_millis)
+    }
+}
+
+/// Proof of who published a Data packet. The key locator is the name of the
+/// publisher's DID document, so a node that lacks the key can fetch it over
+/// the network like any other content.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SignatureInfo {
+    pub key_locator: String,
+    pub signature: Vec<u8>,
+}
+
+/// Hops an interest may travel before it is dropped.
+pub const DEFAULT_HOP_LIMIT: u8 = 32;
+
+fn default_hop_limit() -> u8 {
+    DEFAULT_HOP_LIMIT
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Packet {
     pub packet_type: PacketType,
     pub name: String,
     pub content: Vec<u8>,
-}
\ No newline at end of file
+    /// Set on interests that must not be answered from stale cached content.
+    #[serde(default)]
+    pub must_be_fresh: bool,
+    #[serde(default)]
+    pub meta: ContentMeta,
+    /// Set on Data packets by their publisher; unsigned data is not cached.
+    #[serde(default)]
+    pub signature: Option<SignatureInfo>,
+    /// Random per interest; a node seeing the same name and nonce again knows
+    /// the interest has looped. Zero on Data and Nack packets.
+    #[serde(default)]
+    pub nonce: u32,
+    /// Decremented by every forwarder; interests arriving with none left are
+    /// dropped.
+    #[serde(default = "default_hop_limit")]
+    pub hop_limit: u8,
+}
+
+impl Packet {
+    pub fn interest(name: &str) -> Self {
+        Packet {
+            packet_type: PacketType::Interest,
+            name: name.to_string(),
+            content: Vec::new(),
+            must_be_fresh: false,
+            meta: ContentMeta::default(),
+            signature: None,
+            nonce: rand::random(),
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+
+    pub fn data(name: &str, content: Vec<u8>) -> Self {
+        Packet {
+            packet_type: PacketType::Data,
+            name: name.to_string(),
+            content,
+            must_be_fresh: false,
+            meta: ContentMeta::default(),
+            signature: None,
+            nonce: 0,
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+
+    /// Rejects the interest for `name`.
+    pub fn nack(name: &str, reason: NackReason) -> Self {
+        Packet {
+            packet_type: PacketType::Nack(reason),
+            name: name.to_string(),
+            content: Vec::new(),
+            must_be_fresh: false,
+            meta: ContentMeta::default(),
+            signature: None,
+            nonce: 0,
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+
+    pub fn with_hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    pub fn with_must_be_fresh(mut self) -> Self {
+        self.must_be_fresh = true;
+        self
+    }
+
+    pub fn with_freshness_period(mut self, period: Duration) -> Self {
+        self.meta.freshness_period_ms = Some(period.as_millis() as u64);
+        self
+    }
+
+    pub fn with_version(mut self, version: u64) -> Self {
+        self.meta.version = Some(version);
+        self
+    }
+
+    /// Signs the name, content and metadata as the publisher `did_id`. Must be
+    /// the last builder called, as later changes invalidate the signature.
+    pub fn signed(mut self, did_id: &str, keypair: &Keypair) -> Self {
+        let key_locator = did_content_name(did_id);
+        let signature = keypair.sign(&self.signed_portion(&key_locator));
+        self.signature = Some(SignatureInfo { key_locator, signature: signature.to_bytes().to_vec() });
+        self
+    }
+
+    /// The DID whose key signed this packet, if it is signed.
+    pub fn publisher(&self) -> Option<&str> {
+        self.signature.as_ref().and_then(|info| did_from_content_name(&info.key_locator))
+    }
+
+    /// Checks the signature against the publisher's key. Unsigned packets
+    /// never verify.
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        let info = match &self.signature {
+            Some(info) => info,
+            None => return false,
+        };
+        Signature::from_bytes(&info.signature)
+            .is_ok_and(|signature| public_key.verify(&self.signed_portion(&info.key_locator), &signature).is_ok())
+    }
+
+    /// Checks the signature against the key the publisher DID itself names,
+    /// as in the `did:icn:<key>` of a node identity, with no need to resolve
+    /// it. Unsigned packets never verify.
+    pub fn verify_origin(&self) -> bool {
+        self.publisher()
+            .and_then(|did_id| did_id.strip_prefix("did:icn:"))
+            .and_then(|key| hex::decode(key).ok())
+            .and_then(|key| PublicKey::from_bytes(&key).ok())
+            .is_some_and(|public_key| self.verify(&public_key))
+    }
+
+    fn signed_portion(&self, key_locator: &str) -> Vec<u8> {
+        serde_json::to_vec(&(&self.name, &self.content, &self.meta, key_locator))
+            .expect("packet fields always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DecentralizedIdentity;
+
+    #[test]
+    fn test_signed_data_verifies_against_publisher_key() {
+        let (did, keypair) = DecentralizedIdentity::new(std::collections::HashMap::new());
+        let (other, _) = DecentralizedIdentity::new(std::collections::HashMap::new());
+        let data = Packet::data("/coopX/docs", vec![1, 2, 3]).with_version(2).signed(&did.id, &keypair);
+
+        assert_eq!(data.publisher(), Some(did.id.as_str()));
+        assert!(data.verify(&did.public_key));
+        assert!(!data.verify(&other.public_key));
+        assert!(!Packet::data("/coopX/docs", vec![1, 2, 3]).verify(&did.public_key));
+
+        let tampered = Packet { content: vec![9], ..data.clone() };
+        assert!(!tampered.verify(&did.public_key));
+        let renamed = Packet { name: "/coopY/docs".to_string(), ..data };
+        assert!(!renamed.verify(&did.public_key));
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use super::packet::PacketType;
+use super::transport::Message;
+
+/// Message classes that are rate limited independently of each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MessageKind {
+    Interest,
+    Data,
+    Transaction,
+    Block,
+    Sync,
+    Discovery,
+    Routing,
+}
+
+impl MessageKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::Interest => "interest",
+            MessageKind::Data => "data",
+            MessageKind::Transaction => "transaction",
+            MessageKind::Block => "block",
+            MessageKind::Sync => "sync",
+            MessageKind::Discovery => "discovery",
+            MessageKind::Routing => "routing",
+        }
+    }
+
+    pub fn of(message: &Message) -> Self {
+        match message {
+            Message::Packet(packet) if packet.packet_type == PacketType::Interest => MessageKind::Interest,
+            Message::Packet(_) | Message::DidComm(_) => MessageKind::Data,
+            Message::Transaction(_) => MessageKind::Transaction,
+            Message::Block(_) => MessageKind::Block,
+            Message::Gossip(gossip) => match gossip.payload {
+                super::gossip::GossipPayload::Block(_)
+                | super::gossip::GossipPayload::Emergency(_)
+                | super::gossip::GossipPayload::Vote(_) => MessageKind::Block,
+                super::gossip::GossipPayload::Transaction(_) => MessageKind::Transaction,
+            },
+            Message::Status { .. } => MessageKind::Sync,
+            Message::Dht(_) => MessageKind::Routing,
+            Message::GetPeers
+            | Message::Peers(_)
+            | Message::Handshake(_)
+            | Message::Disconnect { .. } => MessageKind::Discovery,
+        }
+    }
+}
+
+/// Ways a peer can misbehave, each costing it score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Misbehavior {
+    InvalidBlock,
+    InvalidHeaders,
+    MalformedPacket,
+    /// A message signed with a key that is not its origin's.
+    InvalidSignature,
+    SpamInterest,
+    RateLimited,
+}
+
+impl Misbehavior {
+    pub fn penalty(&self) -> f64 {
+        match self {
+            Misbehavior::InvalidBlock => 50.0,
+            Misbehavior::InvalidHeaders => 50.0,
+            Misbehavior::MalformedPacket => 10.0,
+            Misbehavior::InvalidSignature => 50.0,
+            Misbehavior::SpamInterest => 5.0,
+            Misbehavior::RateLimited => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScoringConfig {
+    pub rate_limits: HashMap<MessageKind, RateLimit>,
+    /// Peers whose score falls to or below this value are banned.
+    pub ban_threshold: f64,
+    pub ban_duration: Duration,
+    /// Score regained per second of good behaviour, up to zero.
+    pub recovery_per_second: f64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        let rate_limits = [
+            (MessageKind::Interest, RateLimit { burst: 200.0, per_second: 100.0 }),
+            (MessageKind::Data, RateLimit { burst: 200.0, per_second: 100.0 }),
+            (MessageKind::Transaction, RateLimit { burst: 100.0, per_second: 50.0 }),
+            (MessageKind::Block, RateLimit { burst: 20.0, per_second: 5.0 }),
+            (MessageKind::Sync, RateLimit { burst: 50.0, per_second: 20.0 }),
+            (MessageKind::Discovery, RateLimit { burst: 5.0, per_second: 0.2 }),
+            (MessageKind::Routing, RateLimit { burst: 100.0, per_second: 20.0 }),
+        ].into_iter().collect();
+        ScoringConfig {
+            rate_limits,
+            ban_threshold: -100.0,
+            ban_duration: Duration::hours(1),
+            recovery_per_second: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    limit: RateLimit,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket { tokens: limit.burst, limit, refilled_at: now }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeerState {
+    score: f64,
+    updated_at: Instant,
+    buckets: HashMap<MessageKind, TokenBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub peer_id: String,
+    pub reason: String,
+    pub banned_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    Banned,
+    RateLimited,
+}
+
+/// Tracks per-peer behaviour. Every inbound message passes through
+/// `check_message`; handlers report protocol violations with `report`.
+#[derive(Debug, Default)]
+pub struct PeerScoring {
+    config: ScoringConfig,
+    peers: HashMap<String, PeerState>,
+    bans: HashMap<String, BanEntry>,
+}
+
+impl PeerScoring {
+    pub fn new(config: ScoringConfig) -> Self {
+        PeerScoring {
+            config,
+            peers: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    /// Admits or rejects an inbound message. Exceeding a rate limit costs the
+    /// peer score and may get it banned.
+    pub fn check_message(&mut self, peer_id: &str, message: &Message, now: Instant) -> Result<(), Rejection> {
+        if self.is_banned(peer_id) {
+            return Err(Rejection::Banned);
+        }
+        let kind = MessageKind::of(message);
+        let limit = self.config.rate_limits.get(&kind).copied();
+        let state = self.peer(peer_id, now);
+        let allowed = match limit {
+            Some(limit) => state.buckets.entry(kind)
+                .or_insert_with(|| TokenBucket::new(limit, now))
+                .try_take(now),
+            None => true,
+        };
+        if allowed {
+            return Ok(());
+        }
+        let misbehavior = if kind == MessageKind::Interest { Misbehavior::SpamInterest } else { Misbehavior::RateLimited };
+        self.report(peer_id, misbehavior, now);
+        Err(if self.is_banned(peer_id) { Rejection::Banned } else { Rejection::RateLimited })
+    }
+
+    /// Penalizes a peer. Returns the ban if this pushed the peer over the threshold.
+    pub fn report(&mut self, peer_id: &str, misbehavior: Misbehavior, now: Instant) -> Option<BanEntry> {
+        let threshold = self.config.ban_threshold;
+        let state = self.peer(peer_id, now);
+        state.score -= misbehavior.penalty();
+        if state.score > threshold || self.is_banned(peer_id) {
+            return None;
+        }
+        let duration = self.config.ban_duration;
+        Some(self.ban(peer_id, &format!("Score fell below threshold after {:?}", misbehavior), duration))
+    }
+
+    pub fn ban(&mut self, peer_id: &str, reason: &str, duration: Duration) -> BanEntry {
+        let banned_at = Utc::now();
+        let entry = BanEntry {
+            peer_id: peer_id.to_string(),
+            reason: reason.to_string(),
+            banned_at,
+            expires_at: banned_at + duration,
+        };
+        self.bans.insert(peer_id.to_string(), entry.clone());
+        entry
+    }
+
+    /// Lifts a ban and resets the peer's score.
+    pub fn unban(&mut self, peer_id: &str) -> bool {
+        self.peers.remove(peer_id);
+        self.bans.remove(peer_id).is_some()
+    }
+
+    pub fn is_banned(&self, peer_id: &str) -> bool {
+        self.bans.get(peer_id).is_some_and(|ban| ban.expires_at > Utc::now())
+    }
+
+    /// Active bans. Expired bans are dropped and the peer starts over.
+    pub fn bans(&mut self) -> Vec<BanEntry> {
+        let now = Utc::now();
+        let expired: Vec<String> = self.bans.values()
+            .filter(|ban| ban.expires_at <= now)
+            .map(|ban| ban.peer_id.clone())
+            .collect();
+        for peer_id in expired {
+            self.unban(&peer_id);
+        }
+        self.bans.values().cloned().collect()
+    }
+
+    pub fn score(&self, peer_id: &str) -> f64 {
+        self.peers.get(peer_id).map_or(0.0, |state| state.score)
+    }
+
+    fn peer(&mut self, peer_id: &str, now: Instant) -> &mut PeerState {
+        let recovery = self.config.recovery_per_second;
+        let state = self.peers.entry(peer_id.to_string()).or_insert_with(|| PeerState {
+            score: 0.0,
+            updated_at: now,
+            buckets: HashMap::new(),
+        });
+        // Recover in whole seconds so penalties reported back to back add up exactly
+        let elapsed = now.saturating_duration_since(state.updated_at).as_secs();
+        if elapsed > 0 {
+            state.score = (state.score + elapsed as f64 * recovery).min(0.0);
+            state.updated_at += std::time::Duration::from_secs(elapsed);
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Packet;
+
+    fn interest() -> Message {
+        Message::Packet(Packet::interest("/coopX/spam"))
+    }
+
+    #[test]
+    fn test_rate_limit_per_message_kind() {
+        let mut config = ScoringConfig::default();
+        config.rate_limits.insert(MessageKind::Interest, RateLimit { burst: 3.0, per_second: 1.0 });
+        let mut scoring = PeerScoring::new(config);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(scoring.check_message("peer1", &interest(), now).is_ok());
+        }
+        assert_eq!(scoring.check_message("peer1", &interest(), now), Err(Rejection::RateLimited));
+        assert!(scoring.score("peer1") < 0.0);
+
+        // Other message kinds and other peers have their own buckets
+        assert!(scoring.check_message("peer1", &Message::GetPeers, now).is_ok());
+        assert!(scoring.check_message("peer2", &interest(), now).is_ok());
+
+        // Tokens refill over time
+        let later = now + std::time::Duration::from_secs(2);
+        assert!(scoring.check_message("peer1", &interest(), later).is_ok());
+    }
+
+    #[test]
+    fn test_misbehaving_peer_banned() {
+        let mut scoring = PeerScoring::default();
+        let now = Instant::now();
+        assert!(scoring.report("peer1", Misbehavior::InvalidBlock, now).is_none());
+        let ban = scoring.report("peer1", Misbehavior::InvalidHeaders, now).unwrap();
+        assert_eq!(ban.peer_id, "peer1");
+        assert!(scoring.is_banned("peer1"));
+        assert_eq!(scoring.check_message("peer1", &Message::GetPeers, now), Err(Rejection::Banned));
+        assert_eq!(scoring.bans().len(), 1);
+
+        assert!(scoring.unban("peer1"));
+        assert!(!scoring.is_banned("peer1"));
+        assert_eq!(scoring.score("peer1"), 0.0);
+    }
+
+    #[test]
+    fn test_expired_ban_lifted_and_score_recovers() {
+        let mut scoring = PeerScoring::default();
+        scoring.ban("peer1", "testing", Duration::zero());
+        assert!(!scoring.is_banned("peer1"));
+        assert!(scoring.bans().is_empty());
+
+        let now = Instant::now();
+        scoring.report("peer2", Misbehavior::MalformedPacket, now);
+        scoring.check_message("peer2", &Message::GetPeers, now + std::time::Duration::from_secs(50)).unwrap();
+        assert!(scoring.score("peer2") > -10.0);
+    }
+}
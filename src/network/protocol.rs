@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::blockchain::Block;
+use crate::error::{Error, Result};
+
+/// Wire protocol version spoken by this build.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version this build can still talk to. Version 1 synced
+/// blocks with dedicated messages instead of chain data interests.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+pub const DEFAULT_NETWORK_ID: &str = "icn-mainnet";
+
+/// Optional capabilities advertised during the handshake. Features are plain
+/// strings so that peers running newer builds can announce capabilities this
+/// build does not know about; only the common subset is used.
+pub mod features {
+    pub const GOSSIP: &str = "gossip";
+    pub const BLOCK_SYNC: &str = "block-sync";
+    pub const PEER_EXCHANGE: &str = "peer-exchange";
+
+    pub const SUPPORTED: &[&str] = &[GOSSIP, BLOCK_SYNC, PEER_EXCHANGE];
+}
+
+/// Exchanged over the encrypted channel before any other message so that nodes
+/// on different networks, chains or incompatible versions part ways cleanly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+    pub min_version: u32,
+    pub network_id: String,
+    pub genesis_hash: String,
+    pub features: Vec<String>,
+}
+
+/// The parameters both ends agreed on for a connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NegotiatedProtocol {
+    pub version: u32,
+    pub features: BTreeSet<String>,
+}
+
+impl NegotiatedProtocol {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+impl ProtocolInfo {
+    pub fn new(network_id: &str, genesis_hash: &str) -> Self {
+        ProtocolInfo {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            network_id: network_id.to_string(),
+            genesis_hash: genesis_hash.to_string(),
+            features: features::SUPPORTED.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    /// Checks that a peer can be talked to and settles on the highest version
+    /// and the features both sides support. The outcome is the same whichever
+    /// side runs it, so both ends reach the same decision.
+    pub fn negotiate(&self, remote: &ProtocolInfo) -> Result<NegotiatedProtocol> {
+        if remote.network_id != self.network_id {
+            return Err(Error::NetworkError(format!(
+                "Peer is on network {}, expected {}",
+                remote.network_id, self.network_id
+            )));
+        }
+        if remote.genesis_hash != self.genesis_hash {
+            return Err(Error::NetworkError(format!(
+                "Peer has genesis block {}, expected {}",
+                remote.genesis_hash, self.genesis_hash
+            )));
+        }
+        let version = self.version.min(remote.version);
+        if version < self.min_version.max(remote.min_version) {
+            return Err(Error::NetworkError(format!(
+                "Incompatible protocol versions: local {} (min {}), remote {} (min {})",
+                self.version, self.min_version, remote.version, remote.min_version
+            )));
+        }
+        let features = self.features.iter()
+            .filter(|feature| remote.features.contains(feature))
+            .cloned()
+            .collect();
+        Ok(NegotiatedProtocol { version, features })
+    }
+}
+
+impl Default for ProtocolInfo {
+    fn default() -> Self {
+        ProtocolInfo::new(DEFAULT_NETWORK_ID, &Block::genesis().hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_common_features_and_version() {
+        let local = ProtocolInfo::default();
+        let remote = ProtocolInfo {
+            version: PROTOCOL_VERSION + 1,
+            features: vec![features::GOSSIP.to_string(), "future-feature".to_string()],
+            ..ProtocolInfo::default()
+        };
+
+        let negotiated = local.negotiate(&remote).unwrap();
+        assert_eq!(negotiated, remote.negotiate(&local).unwrap());
+        assert_eq!(negotiated.version, PROTOCOL_VERSION);
+        assert!(negotiated.supports(features::GOSSIP));
+        assert!(!negotiated.supports(features::BLOCK_SYNC));
+        assert!(!negotiated.supports("future-feature"));
+    }
+
+    #[test]
+    fn test_incompatible_peers_rejected() {
+        let local = ProtocolInfo::default();
+
+        let other_network = ProtocolInfo::new("icn-testnet", &local.genesis_hash);
+        assert!(local.negotiate(&other_network).is_err());
+
+        let other_chain = ProtocolInfo::new(DEFAULT_NETWORK_ID, "deadbeef");
+        assert!(local.negotiate(&other_chain).is_err());
+
+        let newer = ProtocolInfo {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION + 1,
+            ..ProtocolInfo::default()
+        };
+        assert!(local.negotiate(&newer).is_err());
+        assert!(newer.negotiate(&local).is_err());
+    }
+}
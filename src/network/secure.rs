@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use rand::rngs::OsRng;
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::error::{Error, Result};
+use crate::identity::DecentralizedIdentity;
+use super::packet::Packet;
+use super::transport::{Message, MAX_FRAME_SIZE};
+
+const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+const NOISE_MAX_MESSAGE: usize = 65535;
+const NOISE_TAG_LEN: usize = 16;
+const PLAINTEXT_CHUNK: usize = NOISE_MAX_MESSAGE - NOISE_TAG_LEN;
+const STATIC_KEY_CONTEXT: &[u8] = b"icn-noise-static:";
+
+fn noise_error(e: snow::Error) -> Error {
+    Error::NetworkError(format!("Noise error: {}", e))
+}
+
+/// A node's long-term Ed25519 identity. Each transport generates a fresh Noise
+/// static key and signs it with this identity, binding the encrypted channel
+/// to the node id.
+pub struct NodeIdentity {
+    pub node_id: String,
+    keypair: Keypair,
+}
+
+impl NodeIdentity {
+    pub fn generate(node_id: &str) -> Self {
+        NodeIdentity {
+            node_id: node_id.to_string(),
+            keypair: Keypair::generate(&mut OsRng {}),
+        }
+    }
+
+    pub fn from_keypair(node_id: &str, keypair: Keypair) -> Self {
+        NodeIdentity {
+            node_id: node_id.to_string(),
+            keypair,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.keypair.sign(message)
+    }
+
+    /// The self-certifying DID of the identity key, `did:icn:<key>`, which
+    /// names the node as the publisher of the packets it signs.
+    pub fn did(&self) -> String {
+        DecentralizedIdentity::from_public_key(self.keypair.public, HashMap::new()).id
+    }
+
+    /// Signs a Data packet this node serves, so that whoever receives it,
+    /// directly or from a cache, can tell which node it came from.
+    pub fn sign_packet(&self, packet: Packet) -> Packet {
+        packet.signed(&self.did(), &self.keypair)
+    }
+
+    #[cfg(feature = "libp2p")]
+    pub(crate) fn secret_key_bytes(&self) -> [u8; 32] {
+        self.keypair.secret.to_bytes()
+    }
+}
+
+impl Clone for NodeIdentity {
+    fn clone(&self) -> Self {
+        let keypair = Keypair::from_bytes(&self.keypair.to_bytes()).expect("a keypair round-trips through its bytes");
+        NodeIdentity::from_keypair(&self.node_id, keypair)
+    }
+}
+
+impl fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeIdentity")
+            .field("node_id", &self.node_id)
+            .field("public_key", &hex::encode(self.keypair.public.to_bytes()))
+            .finish()
+    }
+}
+
+/// Sent inside the encrypted handshake so each side learns who it is talking to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IdentityPayload {
+    node_id: String,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// The authenticated identity of the remote end of a secure channel.
+#[derive(Debug, Clone)]
+pub struct RemoteIdentity {
+    pub node_id: String,
+    pub public_key: PublicKey,
+}
+
+/// Local key material used for every Noise handshake performed by a transport.
+#[derive(Debug)]
+pub struct HandshakeKeys {
+    identity: NodeIdentity,
+    noise_private: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl HandshakeKeys {
+    pub fn new(identity: NodeIdentity) -> Result<Self> {
+        let params = NOISE_PATTERN.parse().map_err(noise_error)?;
+        let noise_keypair = snow::Builder::new(params).generate_keypair().map_err(noise_error)?;
+
+        let mut signed = STATIC_KEY_CONTEXT.to_vec();
+        signed.extend_from_slice(&noise_keypair.public);
+        let payload = IdentityPayload {
+            node_id: identity.node_id.clone(),
+            public_key: identity.public_key().to_bytes().to_vec(),
+            signature: identity.sign(&signed).to_bytes().to_vec(),
+        };
+        let payload = serde_json::to_vec(&payload).map_err(|e| Error::NetworkError(e.to_string()))?;
+
+        Ok(HandshakeKeys {
+            identity,
+            noise_private: noise_keypair.private,
+            payload,
+        })
+    }
+
+    pub fn identity(&self) -> &NodeIdentity {
+        &self.identity
+    }
+
+    fn builder(&self) -> Result<snow::Builder<'_>> {
+        let params = NOISE_PATTERN.parse().map_err(noise_error)?;
+        Ok(snow::Builder::new(params).local_private_key(&self.noise_private))
+    }
+}
+
+async fn write_handshake<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> Result<()> {
+    writer.write_all(&(message.len() as u16).to_be_bytes()).await?;
+    writer.write_all(message).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_handshake<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length).await?;
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+fn verify_remote(payload: &[u8], remote_static: Option<&[u8]>) -> Result<RemoteIdentity> {
+    let payload: IdentityPayload = serde_json::from_slice(payload)
+        .map_err(|e| Error::NetworkError(format!("Malformed identity payload: {}", e)))?;
+    let remote_static = remote_static
+        .ok_or_else(|| Error::NetworkError("Peer did not reveal a static key".to_string()))?;
+    let public_key = PublicKey::from_bytes(&payload.public_key)
+        .map_err(|e| Error::NetworkError(format!("Invalid peer identity key: {}", e)))?;
+    let signature = Signature::from_bytes(&payload.signature)
+        .map_err(|e| Error::NetworkError(format!("Invalid identity signature: {}", e)))?;
+
+    let mut signed = STATIC_KEY_CONTEXT.to_vec();
+    signed.extend_from_slice(remote_static);
+    public_key.verify(&signed, &signature)
+        .map_err(|_| Error::NetworkError(format!("Peer {} failed to prove its identity", payload.node_id)))?;
+
+    Ok(RemoteIdentity {
+        node_id: payload.node_id,
+        public_key,
+    })
+}
+
+/// Runs the initiator side of a Noise XX handshake.
+pub async fn handshake_initiator<S>(stream: &mut S, keys: &HandshakeKeys) -> Result<(SecureSession, RemoteIdentity)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = keys.builder()?.build_initiator().map_err(noise_error)?;
+    let mut buffer = vec![0u8; NOISE_MAX_MESSAGE];
+
+    // -> e
+    let len = state.write_message(&[], &mut buffer).map_err(noise_error)?;
+    write_handshake(stream, &buffer[..len]).await?;
+
+    // <- e, ee, s, es
+    let message = read_handshake(stream).await?;
+    let mut payload = vec![0u8; NOISE_MAX_MESSAGE];
+    let len = state.read_message(&message, &mut payload).map_err(noise_error)?;
+    let remote = verify_remote(&payload[..len], state.get_remote_static())?;
+
+    // -> s, se
+    let len = state.write_message(&keys.payload, &mut buffer).map_err(noise_error)?;
+    write_handshake(stream, &buffer[..len]).await?;
+
+    let transport = state.into_transport_mode().map_err(noise_error)?;
+    Ok((SecureSession::new(transport), remote))
+}
+
+/// Runs the responder side of a Noise XX handshake.
+pub async fn handshake_responder<S>(stream: &mut S, keys: &HandshakeKeys) -> Result<(SecureSession, RemoteIdentity)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut state = keys.builder()?.build_responder().map_err(noise_error)?;
+    let mut buffer = vec![0u8; NOISE_MAX_MESSAGE];
+    let mut payload = vec![0u8; NOISE_MAX_MESSAGE];
+
+    // -> e
+    let message = read_handshake(stream).await?;
+    state.read_message(&message, &mut payload).map_err(noise_error)?;
+
+    // <- e, ee, s, es
+    let len = state.write_message(&keys.payload, &mut buffer).map_err(noise_error)?;
+    write_handshake(stream, &buffer[..len]).await?;
+
+    // -> s, se
+    let message = read_handshake(stream).await?;
+    let len = state.read_message(&message, &mut payload).map_err(noise_error)?;
+    let remote = verify_remote(&payload[..len], state.get_remote_static())?;
+
+    let transport = state.into_transport_mode().map_err(noise_error)?;
+    Ok((SecureSession::new(transport), remote))
+}
+
+/// Encrypted message framing for an established Noise session. A frame is the
+/// big-endian `u32` plaintext length followed by the ciphertext chunks, each
+/// prefixed with its `u16` length.
+#[derive(Clone)]
+pub struct SecureSession {
+    state: Arc<Mutex<snow::TransportState>>,
+}
+
+impl fmt::Debug for SecureSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecureSession")
+    }
+}
+
+impl SecureSession {
+    fn new(state: snow::TransportState) -> Self {
+        SecureSession {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub async fn write_message<W: AsyncWrite + Unpin>(&self, writer: &mut W, message: &Message) -> Result<()> {
+        let plaintext = serde_json::to_vec(message).map_err(|e| Error::NetworkError(e.to_string()))?;
+        if plaintext.len() > MAX_FRAME_SIZE {
+            return Err(Error::NetworkError(format!("Frame too large: {} bytes", plaintext.len())));
+        }
+
+        let mut frame = (plaintext.len() as u32).to_be_bytes().to_vec();
+        {
+            let mut state = self.state.lock().unwrap();
+            let mut buffer = vec![0u8; NOISE_MAX_MESSAGE];
+            for chunk in plaintext.chunks(PLAINTEXT_CHUNK) {
+                let len = state.write_message(chunk, &mut buffer).map_err(noise_error)?;
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+                frame.extend_from_slice(&buffer[..len]);
+            }
+        }
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Reads and decrypts one frame. Returns `None` when the peer closed the connection.
+    pub async fn read_message<R: AsyncRead + Unpin>(&self, reader: &mut R) -> Result<Option<Message>> {
+        let mut length_bytes = [0u8; 4];
+        match reader.read_exact(&mut length_bytes).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(Error::NetworkError(format!("Frame too large: {} bytes", length)));
+        }
+
+        let mut plaintext = Vec::with_capacity(length);
+        let mut buffer = vec![0u8; NOISE_MAX_MESSAGE];
+        while plaintext.len() < length {
+            let chunk = read_handshake(reader).await?;
+            let len = self.state.lock().unwrap()
+                .read_message(&chunk, &mut buffer)
+                .map_err(noise_error)?;
+            plaintext.extend_from_slice(&buffer[..len]);
+        }
+        if plaintext.len() != length {
+            return Err(Error::NetworkError("Frame length mismatch".to_string()));
+        }
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|e| Error::NetworkError(format!("Malformed frame: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::Packet;
+
+    #[tokio::test]
+    async fn test_handshake_and_encrypted_frames() {
+        let (mut client, mut server) = tokio::io::duplex(256 * 1024);
+        let alice = HandshakeKeys::new(NodeIdentity::generate("alice")).unwrap();
+        let bob = HandshakeKeys::new(NodeIdentity::generate("bob")).unwrap();
+        let bob_key = bob.identity().public_key();
+
+        let responder = tokio::spawn(async move {
+            let result = handshake_responder(&mut server, &bob).await.unwrap();
+            (result, server)
+        });
+        let (alice_session, remote) = handshake_initiator(&mut client, &alice).await.unwrap();
+        let ((bob_session, alice_remote), mut server) = responder.await.unwrap();
+        assert_eq!(remote.node_id, "bob");
+        assert_eq!(remote.public_key, bob_key);
+        assert_eq!(alice_remote.node_id, "alice");
+
+        // Large enough to span several Noise messages
+        let packet = Packet::data("/coopX/archive", vec![7u8; 200_000]);
+        let writer = tokio::spawn(async move {
+            alice_session.write_message(&mut client, &Message::Packet(packet)).await.unwrap();
+        });
+        match bob_session.read_message(&mut server).await.unwrap() {
+            Some(Message::Packet(packet)) => assert_eq!(packet.content.len(), 200_000),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tampered_ciphertext_rejected() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let alice = HandshakeKeys::new(NodeIdentity::generate("alice")).unwrap();
+        let bob = HandshakeKeys::new(NodeIdentity::generate("bob")).unwrap();
+        let responder = tokio::spawn(async move {
+            let (session, _) = handshake_responder(&mut server, &bob).await.unwrap();
+            (session, server)
+        });
+        let (alice_session, _) = handshake_initiator(&mut client, &alice).await.unwrap();
+        let (bob_session, mut server) = responder.await.unwrap();
+
+        let mut raw = Vec::new();
+        let packet = Packet::interest("/x");
+        alice_session.write_message(&mut raw, &Message::Packet(packet)).await.unwrap();
+        let last = raw.len() - 1;
+        raw[last] ^= 0xff;
+        client.write_all(&raw).await.unwrap();
+        assert!(bob_session.read_message(&mut server).await.is_err());
+    }
+}
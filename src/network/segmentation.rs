@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::error::{Error, Result};
+use super::packet::Packet;
+
+/// Payload bytes carried by each segment.
+pub const DEFAULT_SEGMENT_SIZE: usize = 8 * 1024;
+/// Segment interests a fetch keeps outstanding at once.
+pub const DEFAULT_PIPELINE_WINDOW: usize = 8;
+/// How long a fetch waits for any awaited packet before asking again.
+pub const FETCH_STALL_TIMEOUT: Duration = Duration::from_secs(4);
+/// Stalls in a row after which a fetch gives up.
+pub const MAX_FETCH_STALLS: u32 = 3;
+const SEGMENT_MARKER: &str = "seg=";
+const MANIFEST_COMPONENT: &str = "manifest";
+
+/// `/name/seg=N`
+pub fn segment_name(name: &str, index: u64) -> String {
+    format!("{}/{}{}", name, SEGMENT_MARKER, index)
+}
+
+pub fn manifest_name(name: &str) -> String {
+    format!("{}/{}", name, MANIFEST_COMPONENT)
+}
+
+/// Splits `/name/seg=N` into the content name and segment index.
+pub fn parse_segment_name(name: &str) -> Option<(&str, u64)> {
+    let (base, last) = name.rsplit_once('/')?;
+    let index = last.strip_prefix(SEGMENT_MARKER)?.parse().ok()?;
+    Some((base, index))
+}
+
+/// Published under `/name/manifest` alongside the segments; tells a fetcher
+/// how many segments to ask for and lets it check the reassembled content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub size: u64,
+    pub segment_count: u64,
+    pub segment_size: u64,
+    /// Hex SHA-256 of the whole content.
+    pub hash: String,
+}
+
+impl Manifest {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| Error::NetworkError(e.to_string()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(|e| Error::NetworkError(format!("Invalid manifest: {}", e)))
+    }
+}
+
+/// Cuts `content` into Data packets for `/name/seg=0..N` plus the manifest
+/// packet, which comes first. Packets are unsigned; the publisher signs each.
+pub fn segment(name: &str, content: &[u8], segment_size: usize) -> Result<(Manifest, Vec<Packet>)> {
+    if segment_size == 0 {
+        return Err(Error::NetworkError("Segment size must be positive".to_string()));
+    }
+    let manifest = Manifest {
+        name: name.to_string(),
+        size: content.len() as u64,
+        segment_count: content.len().div_ceil(segment_size) as u64,
+        segment_size: segment_size as u64,
+        hash: hex::encode(Sha256::digest(content)),
+    };
+    let mut packets = vec![Packet::data(&manifest_name(name), manifest.to_bytes()?)];
+    packets.extend(content.chunks(segment_size).enumerate()
+        .map(|(index, chunk)| Packet::data(&segment_name(name, index as u64), chunk.to_vec())));
+    Ok((manifest, packets))
+}
+
+/// Drives the retrieval of segmented content without doing any I/O itself:
+/// the caller sends the interests it returns and feeds back the Data packets
+/// that arrive. The manifest is fetched first, then segments are requested
+/// keeping up to `window` of them in flight.
+pub struct SegmentFetcher {
+    name: String,
+    window: usize,
+    manifest: Option<Manifest>,
+    received: BTreeMap<u64, Vec<u8>>,
+    in_flight: BTreeSet<u64>,
+    next: u64,
+}
+
+impl SegmentFetcher {
+    pub fn new(name: &str, window: usize) -> Self {
+        SegmentFetcher {
+            name: name.to_string(),
+            window: window.max(1),
+            manifest: None,
+            received: BTreeMap::new(),
+            in_flight: BTreeSet::new(),
+            next: 0,
+        }
+    }
+
+    /// The interests to send first.
+    pub fn start(&self) -> Vec<Packet> {
+        vec![Packet::interest(&manifest_name(&self.name))]
+    }
+
+    /// Names of the packets still awaited.
+    pub fn awaiting(&self) -> Vec<String> {
+        match &self.manifest {
+            None => vec![manifest_name(&self.name)],
+            Some(_) => self.in_flight.iter().map(|index| segment_name(&self.name, *index)).collect(),
+        }
+    }
+
+    /// Interests to send again after a stall.
+    pub fn outstanding(&self) -> Vec<Packet> {
+        self.awaiting().iter().map(|name| Packet::interest(name)).collect()
+    }
+
+    /// Takes an arriving manifest or segment and returns the interests that
+    /// refill the pipeline. Packets that are not part of this fetch are ignored.
+    pub fn on_data(&mut self, data: &Packet) -> Result<Vec<Packet>> {
+        if self.manifest.is_none() && data.name == manifest_name(&self.name) {
+            let manifest = Manifest::from_bytes(&data.content)?;
+            if manifest.name != self.name {
+                return Err(Error::NetworkError(format!("Manifest is for {}, not {}", manifest.name, self.name)));
+            }
+            self.manifest = Some(manifest);
+            return Ok(self.fill_window());
+        }
+        match parse_segment_name(&data.name) {
+            Some((base, index)) if base == self.name && self.in_flight.remove(&index) => {
+                self.received.insert(index, data.content.clone());
+                Ok(self.fill_window())
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.manifest.as_ref().is_some_and(|manifest| self.received.len() as u64 == manifest.segment_count)
+    }
+
+    /// Concatenates the segments and checks them against the manifest.
+    pub fn assemble(self) -> Result<Vec<u8>> {
+        let manifest = self.manifest.ok_or_else(|| Error::NetworkError("Manifest not received".to_string()))?;
+        if self.received.len() as u64 != manifest.segment_count {
+            return Err(Error::NetworkError(format!(
+                "Received {} of {} segments of {}",
+                self.received.len(), manifest.segment_count, manifest.name
+            )));
+        }
+        let content: Vec<u8> = self.received.into_values().flatten().collect();
+        if content.len() as u64 != manifest.size || hex::encode(Sha256::digest(&content)) != manifest.hash {
+            return Err(Error::NetworkError(format!("Reassembled {} does not match its manifest", manifest.name)));
+        }
+        Ok(content)
+    }
+
+    fn fill_window(&mut self) -> Vec<Packet> {
+        let segment_count = match &self.manifest {
+            Some(manifest) => manifest.segment_count,
+            None => return vec![],
+        };
+        let mut interests = Vec::new();
+        while self.in_flight.len() < self.window && self.next < segment_count {
+            self.in_flight.insert(self.next);
+            interests.push(Packet::interest(&segment_name(&self.name, self.next)));
+            self.next += 1;
+        }
+        interests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(packets: &[Packet], name: &str) -> Packet {
+        packets.iter().find(|packet| packet.name == name).cloned().unwrap()
+    }
+
+    #[test]
+    fn test_segment_names() {
+        assert_eq!(segment_name("/coopX/archive", 3), "/coopX/archive/seg=3");
+        assert_eq!(parse_segment_name("/coopX/archive/seg=3"), Some(("/coopX/archive", 3)));
+        assert_eq!(parse_segment_name("/coopX/archive/manifest"), None);
+    }
+
+    #[test]
+    fn test_pipelined_fetch_reassembles_content() {
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let (manifest, packets) = segment("/coopX/archive", &content, 1000).unwrap();
+        assert_eq!(manifest.segment_count, 10);
+        assert_eq!(packets.len(), 11);
+
+        let mut fetcher = SegmentFetcher::new("/coopX/archive", 4);
+        let mut pending: Vec<String> = fetcher.start().into_iter().map(|interest| interest.name).collect();
+        let mut max_in_flight = 0;
+        while let Some(name) = pending.pop() {
+            let interests = fetcher.on_data(&deliver(&packets, &name)).unwrap();
+            pending.extend(interests.into_iter().map(|interest| interest.name));
+            max_in_flight = max_in_flight.max(pending.len());
+        }
+        assert_eq!(max_in_flight, 4);
+        assert!(fetcher.is_complete());
+        assert_eq!(fetcher.assemble().unwrap(), content);
+    }
+
+    #[test]
+    fn test_corrupted_segment_rejected() {
+        let content = vec![7u8; 2500];
+        let (_, mut packets) = segment("/coopX/archive", &content, 1000).unwrap();
+        packets[2].content[0] = 0;
+
+        let mut fetcher = SegmentFetcher::new("/coopX/archive", 8);
+        fetcher.on_data(&packets[0]).unwrap();
+        for packet in &packets[1..] {
+            fetcher.on_data(packet).unwrap();
+        }
+        assert!(fetcher.is_complete());
+        assert!(fetcher.assemble().is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+use crate::blockchain::{Block, BlockHeader, Blockchain, MAX_REORG_DEPTH};
+use crate::error::{Error, Result};
+use super::chain_data::{self, ChainName};
+use super::packet::{Packet, PacketType};
+use super::transport::Message;
+
+pub const MAX_HEADERS_PER_REQUEST: u32 = 512;
+pub const BLOCKS_PER_BATCH: u32 = 32;
+/// Batches of block interests a single peer is asked for at once, keeping
+/// each peer well inside its interest rate limit.
+pub const MAX_BATCHES_PER_PEER: usize = 2;
+/// Requests unanswered for this long are reassigned to another peer.
+pub const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outgoing requests produced by the sync state machine, addressed by peer id.
+pub type SyncRequests = Vec<(String, Message)>;
+
+/// Catches a lagging node up with its peers over interest/data exchange.
+/// Header ranges are fetched from the best peer and validated against the
+/// local chain first; blocks are then requested by name, `/icn/chain/<height>`,
+/// in batches spread across every peer that has them and applied in order
+/// once each body matches its header.
+/// A peer on a longer fork is found by headers that do not link to the
+/// local tip: headers are then asked for from further back, twice as far
+/// each time, up to `MAX_REORG_DEPTH` blocks, and once every block of the
+/// fork is in the chain switches to it.
+#[derive(Debug, Default)]
+pub struct BlockSync {
+    peer_heights: HashMap<String, u64>,
+    headers: VecDeque<BlockHeader>,
+    header_request: Option<(String, Instant)>,
+    batches: HashMap<u64, (String, Instant)>,
+    bodies: BTreeMap<u64, Block>,
+    next_peer: usize,
+    /// How far back of the tip headers are asked for from.
+    lookback: u64,
+}
+
+impl BlockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.header_request.is_some() || !self.headers.is_empty()
+    }
+
+    pub fn peer_height(&self, peer_id: &str) -> Option<u64> {
+        self.peer_heights.get(peer_id).copied()
+    }
+
+    pub fn forget_peer(&mut self, peer_id: &str) {
+        self.peer_heights.remove(peer_id);
+    }
+
+    /// Records the height a peer reported and starts downloading headers if it
+    /// is ahead of us.
+    pub fn on_status(&mut self, peer_id: &str, height: u64, chain: &Blockchain) -> SyncRequests {
+        let known = self.peer_heights.entry(peer_id.to_string()).or_insert(0);
+        *known = (*known).max(height);
+        self.request_headers(chain, Instant::now())
+    }
+
+    pub fn on_headers(&mut self, peer_id: &str, headers: Vec<BlockHeader>, chain: &Blockchain) -> Result<SyncRequests> {
+        if self.header_request.as_ref().is_some_and(|(requested, _)| requested == peer_id) {
+            self.header_request = None;
+        }
+        if let Some(last) = headers.last() {
+            let known = self.peer_heights.entry(peer_id.to_string()).or_insert(0);
+            *known = (*known).max(last.index + 1);
+            // Headers asked for from behind the tip start with blocks we hold
+            let first_new = if self.headers.is_empty() {
+                headers.iter().position(|header| chain.chain.get(header.index as usize).is_none_or(|local| local.hash != header.hash))
+            } else {
+                Some(0)
+            };
+            let headers = match first_new {
+                Some(first_new) => headers[first_new..].to_vec(),
+                None => vec![],
+            };
+            if let Some(first) = headers.first() {
+                let mut candidate: Vec<BlockHeader> = self.headers.iter().cloned().collect();
+                candidate.extend(headers.iter().cloned());
+                if let Err(e) = chain.validate_headers(&candidate) {
+                    let forked = self.headers.is_empty() && first.index > 1
+                        && chain.chain.get(first.index as usize - 1).is_none_or(|parent| parent.hash != first.previous_hash);
+                    if !forked || self.lookback >= MAX_REORG_DEPTH as u64 {
+                        return Err(e);
+                    }
+                    self.lookback = (self.lookback * 2).max(1);
+                    debug!("Headers from {} fork from ours before {}, looking {} blocks back", peer_id, first.index, self.lookback);
+                    return Ok(self.request_headers(chain, Instant::now()));
+                }
+                debug!("Accepted {} headers from {} up to {}", headers.len(), peer_id, last.index);
+                self.lookback = 0;
+                self.headers.extend(headers);
+            }
+        } else {
+            // The peer has nothing beyond what we already hold
+            let next = self.next_height(chain);
+            self.peer_heights.insert(peer_id.to_string(), next);
+        }
+
+        let now = Instant::now();
+        let mut requests = self.request_bodies(now, &HashMap::new());
+        requests.extend(self.request_headers(chain, now));
+        Ok(requests)
+    }
+
+    /// Takes a Data packet or nack for chain data a peer sent in answer to
+    /// one of our interests. A nack needs no action: the batch it belongs to
+    /// is reassigned once it stalls. Data signed by the node that served it
+    /// must carry that node's valid signature.
+    pub fn on_data(&mut self, peer_id: &str, data: &Packet, chain: &mut Blockchain) -> Result<(usize, SyncRequests)> {
+        if data.signature.is_some() && !data.verify_origin() {
+            return Err(Error::NetworkError(format!("{} from {} is not signed by its publisher", data.name, peer_id)));
+        }
+        match (ChainName::parse(&data.name), &data.packet_type) {
+            (Some(_), PacketType::Nack(reason)) => {
+                debug!("{} cannot serve {}: {:?}", peer_id, data.name, reason);
+                Ok((0, vec![]))
+            }
+            (Some(ChainName::Headers { .. }), PacketType::Data) => {
+                let requests = self.on_headers(peer_id, chain_data::decode(data)?, chain)?;
+                Ok((0, requests))
+            }
+            (Some(ChainName::Block(height)), PacketType::Data) => {
+                let block: Block = chain_data::decode_versioned(data)?;
+                if block.index != height {
+                    return Err(Error::NetworkError(format!("{} answered {} with block {}", peer_id, data.name, block.index)));
+                }
+                self.on_blocks(peer_id, vec![block], chain)
+            }
+            _ => Err(Error::NetworkError(format!("Unexpected sync packet {} from {}", data.name, peer_id))),
+        }
+    }
+
+    /// Stores bodies that match their validated headers and appends every
+    /// block that now connects to the chain tip. Returns the number of blocks
+    /// applied along with any follow-up requests.
+    pub fn on_blocks(&mut self, peer_id: &str, blocks: Vec<Block>, chain: &mut Blockchain) -> Result<(usize, SyncRequests)> {
+        for block in blocks {
+            let header = self.headers.front()
+                .and_then(|front| block.index.checked_sub(front.index))
+                .and_then(|offset| self.headers.get(offset as usize))
+                .ok_or_else(|| Error::NetworkError(format!("Unrequested block {} from {}", block.index, peer_id)))?;
+            if block.header() != *header {
+                return Err(Error::NetworkError(format!("Block {} from {} does not match its header", block.index, peer_id)));
+            }
+            self.bodies.insert(block.index, block);
+        }
+
+        let mut applied = 0;
+        let first = self.headers.front().map_or(chain.height(), |header| header.index);
+        if first < chain.height() {
+            let end = first + self.headers.len() as u64;
+            if (first..end).all(|index| self.bodies.contains_key(&index)) {
+                let fork: Vec<Block> = std::mem::take(&mut self.bodies).into_values().collect();
+                self.headers.clear();
+                self.batches.clear();
+                applied = fork.len();
+                chain.reorganize(fork)?;
+            }
+        } else {
+            while let Some(block) = self.bodies.remove(&chain.height()) {
+                chain.append_block(block)?;
+                self.headers.pop_front();
+                applied += 1;
+            }
+        }
+        if applied > 0 {
+            info!("Synced {} blocks, chain height is now {}", applied, chain.height());
+        }
+        let height = self.headers.front().map_or(chain.height(), |header| header.index);
+        let end = height + self.headers.len() as u64;
+        let bodies = &self.bodies;
+        self.batches.retain(|start, _| {
+            (*start..(*start + BLOCKS_PER_BATCH as u64).min(end)).any(|index| index >= height && !bodies.contains_key(&index))
+        });
+
+        let now = Instant::now();
+        let mut requests = self.request_bodies(now, &HashMap::new());
+        requests.extend(self.request_headers(chain, now));
+        Ok((applied, requests))
+    }
+
+    /// Drops requests older than `SYNC_REQUEST_TIMEOUT` and sends them again,
+    /// preferring a different peer.
+    pub fn reassign_stalled(&mut self, now: Instant, chain: &Blockchain) -> SyncRequests {
+        let stalled = |(_, requested_at): &(String, Instant)| now.duration_since(*requested_at) >= SYNC_REQUEST_TIMEOUT;
+        if self.header_request.as_ref().is_some_and(stalled) {
+            self.header_request = None;
+        }
+        let mut avoid = HashMap::new();
+        self.batches.retain(|start, request| {
+            if stalled(request) {
+                avoid.insert(*start, request.0.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        let mut requests = self.request_bodies(now, &avoid);
+        requests.extend(self.request_headers(chain, now));
+        requests
+    }
+
+    fn request_headers(&mut self, chain: &Blockchain, now: Instant) -> SyncRequests {
+        if self.header_request.is_some() {
+            return vec![];
+        }
+        let next = self.next_height(chain);
+        let best = self.peer_heights.iter()
+            .filter(|(_, height)| **height > next)
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(peer_id, _)| peer_id.clone());
+        match best {
+            Some(peer_id) => {
+                self.header_request = Some((peer_id.clone(), now));
+                let start = if self.headers.is_empty() { next.saturating_sub(self.lookback).max(1) } else { next };
+                let interest = ChainName::Headers { start, max: MAX_HEADERS_PER_REQUEST }.interest();
+                vec![(peer_id, Message::Packet(interest))]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Index of the first block no header is held for.
+    fn next_height(&self, chain: &Blockchain) -> u64 {
+        self.headers.back().map_or(chain.height(), |header| header.index + 1)
+    }
+
+    fn request_bodies(&mut self, now: Instant, avoid: &HashMap<u64, String>) -> SyncRequests {
+        let mut requests = Vec::new();
+        let first = match self.headers.front() {
+            Some(header) => header.index,
+            None => return requests,
+        };
+        let end = first + self.headers.len() as u64;
+
+        let mut start = first;
+        while start < end {
+            let count = (end - start).min(BLOCKS_PER_BATCH as u64);
+            let complete = (start..start + count).all(|index| self.bodies.contains_key(&index));
+            if !complete && !self.batches.contains_key(&start) {
+                if let Some(peer_id) = self.pick_peer(start + count, avoid.get(&start)) {
+                    self.batches.insert(start, (peer_id.clone(), now));
+                    requests.extend((start..start + count)
+                        .filter(|index| !self.bodies.contains_key(index))
+                        .map(|index| (peer_id.clone(), Message::Packet(ChainName::Block(index).interest()))));
+                }
+            }
+            start += count;
+        }
+        requests
+    }
+
+    /// Round-robins over the peers known to have at least `height` blocks and
+    /// fewer than `MAX_BATCHES_PER_PEER` batches in flight, skipping `avoid`
+    /// unless it is the only one.
+    fn pick_peer(&mut self, height: u64, avoid: Option<&String>) -> Option<String> {
+        let batches = &self.batches;
+        let mut eligible: Vec<&String> = self.peer_heights.iter()
+            .filter(|(_, peer_height)| **peer_height >= height)
+            .filter(|(peer_id, _)| batches.values().filter(|(requested, _)| requested == *peer_id).count() < MAX_BATCHES_PER_PEER)
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        if eligible.len() > 1 {
+            eligible.retain(|peer_id| Some(*peer_id) != avoid);
+        }
+        if eligible.is_empty() {
+            return None;
+        }
+        eligible.sort();
+        let peer_id = eligible[self.next_peer % eligible.len()].clone();
+        self.next_peer += 1;
+        Some(peer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{ChainSpec, Transaction};
+    use crate::currency::CurrencyType;
+    use crate::network::packet::NackReason;
+    use crate::network::NodeIdentity;
+
+    /// An empty chain whose genesis block funds alice.
+    fn funded_chain() -> Blockchain {
+        Blockchain::with_spec(ChainSpec::default().with_allocation("alice", 1_000_000.0, CurrencyType::BasicNeeds))
+    }
+
+    fn chain_with(blocks: usize) -> Blockchain {
+        let mut chain = funded_chain();
+        for i in 0..blocks {
+            chain.add_transaction(Transaction::new("alice".to_string(), "bob".to_string(), i as f64, CurrencyType::BasicNeeds, 1000)).unwrap();
+            chain.create_block("proposer".to_string()).unwrap();
+        }
+        chain
+    }
+
+    fn serve(source: &Blockchain, request: &Message) -> Packet {
+        match request {
+            Message::Packet(interest) => chain_data::answer_interest(source, interest).unwrap()
+                .unwrap_or_else(|| Packet::nack(&interest.name, NackReason::NoData)),
+            other => panic!("Unexpected request: {:?}", other),
+        }
+    }
+
+    fn requested_blocks(requests: &SyncRequests) -> Vec<(String, u64)> {
+        requests.iter()
+            .filter_map(|(peer_id, request)| match request {
+                Message::Packet(interest) => match ChainName::parse(&interest.name) {
+                    Some(ChainName::Block(height)) => Some((peer_id.clone(), height)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sync_from_multiple_peers() {
+        let source = chain_with(70);
+        let mut local = funded_chain();
+        let mut sync = BlockSync::new();
+
+        let mut queue = sync.on_status("peer1", source.height(), &local);
+        queue.extend(sync.on_status("peer2", source.height(), &local));
+        let mut served_by = std::collections::HashSet::new();
+        while let Some((peer_id, request)) = queue.pop() {
+            let data = serve(&source, &request);
+            if matches!(ChainName::parse(&data.name), Some(ChainName::Block(_))) {
+                served_by.insert(peer_id.clone());
+            }
+            queue.extend(sync.on_data(&peer_id, &data, &mut local).unwrap().1);
+        }
+
+        assert_eq!(local.height(), source.height());
+        assert_eq!(local.get_latest_block().unwrap().hash, source.get_latest_block().unwrap().hash);
+        assert_eq!(served_by.len(), 2, "bodies should be fetched from both peers");
+        assert!(!sync.is_syncing());
+    }
+
+    #[test]
+    fn test_invalid_headers_and_bodies_rejected() {
+        let source = chain_with(3);
+        let local = funded_chain();
+        let mut sync = BlockSync::new();
+        sync.on_status("peer1", source.height(), &local);
+
+        let mut headers = source.headers(1, 10);
+        headers[2].previous_hash = "bogus".to_string();
+        assert!(sync.on_headers("peer1", headers, &local).is_err());
+
+        let mut local = funded_chain();
+        sync.on_headers("peer1", source.headers(1, 10), &local).unwrap();
+        let mut blocks = source.blocks(1, 10);
+        blocks[0].transactions.clear();
+        assert!(sync.on_blocks("peer1", blocks, &mut local).is_err());
+        assert_eq!(local.height(), 1);
+    }
+
+    #[test]
+    fn test_switches_to_a_longer_fork() {
+        let source = chain_with(4);
+        let mut local = funded_chain();
+        for _ in 0..2 {
+            local.add_transaction(Transaction::new("alice".to_string(), "dave".to_string(), 1.0, CurrencyType::BasicNeeds, 1000)).unwrap();
+            local.create_block("proposer".to_string()).unwrap();
+        }
+        let mut sync = BlockSync::new();
+
+        let mut queue = sync.on_status("peer1", source.height(), &local);
+        let mut lookbacks = 0;
+        while let Some((peer_id, request)) = queue.pop() {
+            let data = serve(&source, &request);
+            if matches!(ChainName::parse(&data.name), Some(ChainName::Headers { .. })) {
+                lookbacks += 1;
+            }
+            queue.extend(sync.on_data(&peer_id, &data, &mut local).unwrap().1);
+        }
+
+        assert_eq!(lookbacks, 3, "headers asked for from 3, 2 and 1");
+        assert_eq!(local.height(), source.height());
+        assert_eq!(local.get_latest_block().unwrap().hash, source.get_latest_block().unwrap().hash);
+        assert!(!sync.is_syncing());
+    }
+
+    #[test]
+    fn test_stalled_batch_reassigned() {
+        let source = chain_with(5);
+        let local = funded_chain();
+        let mut sync = BlockSync::new();
+        sync.on_status("slow", source.height(), &local);
+        let requests = sync.on_headers("slow", source.headers(1, 10), &local).unwrap();
+        let slow: Vec<(String, u64)> = (1..=5).map(|height| ("slow".to_string(), height)).collect();
+        assert_eq!(requested_blocks(&requests), slow);
+
+        sync.on_status("fast", source.height(), &local);
+        let later = Instant::now() + SYNC_REQUEST_TIMEOUT;
+        let retried = sync.reassign_stalled(later, &local);
+        let fast: Vec<(String, u64)> = (1..=5).map(|height| ("fast".to_string(), height)).collect();
+        assert_eq!(requested_blocks(&retried), fast);
+    }
+
+    #[test]
+    fn test_batches_per_peer_limited() {
+        let source = chain_with(200);
+        let mut local = funded_chain();
+        let mut sync = BlockSync::new();
+        sync.on_status("peer1", source.height(), &local);
+        let requests = sync.on_headers("peer1", source.headers(1, 512), &local).unwrap();
+        let requested = requested_blocks(&requests);
+        assert_eq!(requested.len(), MAX_BATCHES_PER_PEER * BLOCKS_PER_BATCH as usize);
+
+        // Completing the first batch frees a slot for the next one
+        let mut follow_up = Vec::new();
+        for (peer_id, height) in requested.iter().take(BLOCKS_PER_BATCH as usize) {
+            let data = serve(&source, &Message::Packet(ChainName::Block(*height).interest()));
+            follow_up.extend(sync.on_data(peer_id, &data, &mut local).unwrap().1);
+        }
+        assert_eq!(local.height(), 1 + BLOCKS_PER_BATCH as u64);
+        assert_eq!(requested_blocks(&follow_up).first(), Some(&("peer1".to_string(), 1 + 2 * BLOCKS_PER_BATCH as u64)));
+
+        let nack = Packet::nack(&ChainName::Block(100).name(), NackReason::NoData);
+        assert_eq!(sync.on_data("peer1", &nack, &mut local).unwrap().0, 0);
+        let wrong = Packet::data(&ChainName::Block(40).name(), serde_json::to_vec(&source.chain[41]).unwrap());
+        assert!(sync.on_data("peer1", &wrong, &mut local).is_err());
+    }
+
+    #[test]
+    fn test_chain_data_signed_by_the_serving_node() {
+        let source = chain_with(1);
+        let mut local = funded_chain();
+        let mut sync = BlockSync::new();
+        let server = NodeIdentity::generate("peer1");
+        sync.on_status("peer1", source.height(), &local);
+        let headers = server.sign_packet(serve(&source, &Message::Packet(ChainName::Headers { start: 1, max: 512 }.interest())));
+        assert_eq!(headers.publisher(), Some(server.did().as_str()));
+        sync.on_data("peer1", &headers, &mut local).unwrap();
+
+        let block = server.sign_packet(serve(&source, &Message::Packet(ChainName::Block(1).interest())));
+        let impostor = NodeIdentity::generate("peer1").sign_packet(block.clone()).signature.unwrap();
+        let mut forged = block.clone();
+        forged.signature.as_mut().unwrap().signature = impostor.signature;
+        assert!(sync.on_data("peer1", &forged, &mut local).is_err());
+        assert_eq!(sync.on_data("peer1", &block, &mut local).unwrap().0, 1);
+    }
+}
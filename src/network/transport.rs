@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::fmt::Debug;
+use std::sync::Arc;
+use async_trait::async_trait;
+use ed25519_dalek::PublicKey;
+use serde::{Serialize, Deserialize};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+use crate::blockchain::{Block, Transaction};
+use crate::error::{Error, Result};
+use crate::identity::EncryptedMessage;
+use super::dht::DhtMessage;
+use super::gossip::GossipMessage;
+use super::node::Node;
+use super::packet::Packet;
+use super::protocol::{NegotiatedProtocol, ProtocolInfo};
+use super::secure::{self, HandshakeKeys, NodeIdentity, RemoteIdentity, SecureSession};
+
+/// Frames larger than this are rejected to bound memory use per peer.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+const INBOUND_QUEUE_SIZE: usize = 1024;
+
+/// Messages exchanged between nodes over a peer connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Packet(Packet),
+    Block(Block),
+    Transaction(Box<Transaction>),
+    GetPeers,
+    Peers(Vec<Node>),
+    Gossip(GossipMessage),
+    /// Announces the sender's chain; peers that are behind fetch the missing
+    /// headers and blocks as named chain data.
+    Status { height: u64, tip_hash: String },
+    Dht(DhtMessage),
+    /// First message on every connection; see `ProtocolInfo::negotiate`.
+    Handshake(ProtocolInfo),
+    /// Sent before closing a connection the sender refuses to keep.
+    Disconnect { reason: String },
+    /// An encrypted message for a member, queued by the receiving node until
+    /// the member picks it up.
+    DidComm(EncryptedMessage),
+}
+
+/// A message received from a peer, tagged with the peer's node id.
+pub type InboundMessage = (String, Message);
+
+/// A peer-to-peer backend. Operators choose between the minimal `TcpTransport`
+/// and, with the `libp2p` feature, the pubsub-based `Libp2pTransport`.
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    fn local_id(&self) -> &str;
+
+    /// The address other nodes should use to reach this one.
+    fn listen_addr(&self) -> String;
+
+    async fn connect(&self, peer_id: &str, addr: &str) -> Result<()>;
+    async fn send(&self, peer_id: &str, message: &Message) -> Result<()>;
+
+    /// Delivers a message to every connected peer.
+    async fn broadcast(&self, message: &Message) -> Result<()>;
+
+    async fn disconnect(&self, peer_id: &str);
+    async fn is_connected(&self, peer_id: &str) -> bool;
+    async fn connected_peers(&self) -> Vec<String>;
+}
+
+#[derive(Debug)]
+struct PeerConnection {
+    writer: Mutex<OwnedWriteHalf>,
+    session: SecureSession,
+    protocol: NegotiatedProtocol,
+}
+
+type Connections = Arc<Mutex<HashMap<String, Arc<PeerConnection>>>>;
+
+/// Async TCP transport keeping one connection per peer. Every connection
+/// performs a Noise XX handshake signed with the node's Ed25519 identity, so
+/// traffic is encrypted and both ends are authenticated by node id.
+///
+/// Peers whose key was registered with `trust_peer` must present that key;
+/// otherwise the first key seen for a node id is pinned for later connections.
+/// Once encrypted, both ends exchange their `ProtocolInfo` and drop peers on
+/// another network, chain or incompatible protocol version.
+#[derive(Clone, Debug)]
+pub struct TcpTransport {
+    keys: Arc<HandshakeKeys>,
+    protocol: Arc<ProtocolInfo>,
+    local_addr: SocketAddr,
+    connections: Connections,
+    trusted_keys: Arc<Mutex<HashMap<String, PublicKey>>>,
+    inbound: mpsc::Sender<InboundMessage>,
+}
+
+impl TcpTransport {
+    /// Binds a listener and starts accepting peers. Inbound messages from all
+    /// peers are delivered on the returned receiver.
+    pub async fn bind(identity: NodeIdentity, addr: &str) -> Result<(Self, mpsc::Receiver<InboundMessage>)> {
+        Self::bind_with_protocol(identity, ProtocolInfo::default(), addr).await
+    }
+
+    /// Like `bind`, but only accepts peers compatible with `protocol`.
+    pub async fn bind_with_protocol(
+        identity: NodeIdentity,
+        protocol: ProtocolInfo,
+        addr: &str,
+    ) -> Result<(Self, mpsc::Receiver<InboundMessage>)> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (inbound, receiver) = mpsc::channel(INBOUND_QUEUE_SIZE);
+        let transport = TcpTransport {
+            keys: Arc::new(HandshakeKeys::new(identity)?),
+            protocol: Arc::new(protocol),
+            local_addr,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            trusted_keys: Arc::new(Mutex::new(HashMap::new())),
+            inbound,
+        };
+
+        let acceptor = transport.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, remote)) => {
+                        debug!("Accepted connection from {}", remote);
+                        let transport = acceptor.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = transport.handle_inbound(stream).await {
+                                warn!("Inbound connection from {} failed: {}", remote, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept connection: {}", e),
+                }
+            }
+        });
+
+        info!("Node {} listening on {}", transport.local_id(), local_addr);
+        Ok((transport, receiver))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keys.identity().public_key()
+    }
+
+    /// Pins the identity key a peer must present when connecting.
+    pub async fn trust_peer(&self, peer_id: &str, public_key: PublicKey) {
+        self.trusted_keys.lock().await.insert(peer_id.to_string(), public_key);
+    }
+
+    pub async fn peer_key(&self, peer_id: &str) -> Option<PublicKey> {
+        self.trusted_keys.lock().await.get(peer_id).copied()
+    }
+
+    pub fn protocol(&self) -> &ProtocolInfo {
+        &self.protocol
+    }
+
+    /// The protocol version and features agreed with a connected peer.
+    pub async fn peer_protocol(&self, peer_id: &str) -> Option<NegotiatedProtocol> {
+        self.connections.lock().await.get(peer_id).map(|connection| connection.protocol.clone())
+    }
+
+    async fn open(&self, peer_id: &str, addr: &str) -> Result<()> {
+        if self.is_connected(peer_id).await {
+            return Ok(());
+        }
+        let mut stream = TcpStream::connect(addr).await?;
+        let (session, remote) = secure::handshake_initiator(&mut stream, &self.keys).await?;
+        if remote.node_id != peer_id {
+            return Err(Error::NetworkError(format!("Expected peer {} but reached {}", peer_id, remote.node_id)));
+        }
+        self.authenticate(&remote).await?;
+
+        session.write_message(&mut stream, &Message::Handshake((*self.protocol).clone())).await?;
+        let reply = session.read_message(&mut stream).await?;
+        let protocol = self.check_protocol(&mut stream, &session, peer_id, reply).await?;
+
+        self.register(remote.node_id, stream, session, protocol).await;
+        info!("Connected to peer {} at {}", peer_id, addr);
+        Ok(())
+    }
+
+    async fn write(&self, peer_id: &str, message: &Message) -> Result<()> {
+        let connection = self.connections.lock().await.get(peer_id).cloned()
+            .ok_or_else(|| Error::NetworkError(format!("Not connected to peer {}", peer_id)))?;
+        let result = connection.session
+            .write_message(&mut *connection.writer.lock().await, message)
+            .await;
+        if result.is_err() {
+            self.disconnect(peer_id).await;
+        }
+        result
+    }
+
+    async fn authenticate(&self, remote: &RemoteIdentity) -> Result<()> {
+        let mut trusted = self.trusted_keys.lock().await;
+        match trusted.get(&remote.node_id) {
+            Some(key) if *key != remote.public_key => Err(Error::NetworkError(format!(
+                "Peer {} presented an unexpected identity key",
+                remote.node_id
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                trusted.insert(remote.node_id.clone(), remote.public_key);
+                Ok(())
+            }
+        }
+    }
+
+    async fn handle_inbound(&self, mut stream: TcpStream) -> Result<()> {
+        let (session, remote) = secure::handshake_responder(&mut stream, &self.keys).await?;
+        self.authenticate(&remote).await?;
+
+        let hello = session.read_message(&mut stream).await?;
+        let protocol = self.check_protocol(&mut stream, &session, &remote.node_id, hello).await?;
+        session.write_message(&mut stream, &Message::Handshake((*self.protocol).clone())).await?;
+
+        self.register(remote.node_id, stream, session, protocol).await;
+        Ok(())
+    }
+
+    /// Checks the protocol info a peer sent as its first message. If the two
+    /// nodes cannot talk, the peer is told why before the connection is dropped.
+    async fn check_protocol(
+        &self,
+        stream: &mut TcpStream,
+        session: &SecureSession,
+        peer_id: &str,
+        message: Option<Message>,
+    ) -> Result<NegotiatedProtocol> {
+        let result = match message {
+            Some(Message::Handshake(remote)) => self.protocol.negotiate(&remote),
+            Some(Message::Disconnect { reason }) => {
+                return Err(Error::NetworkError(format!("Peer {} refused the connection: {}", peer_id, reason)));
+            }
+            Some(_) => Err(Error::NetworkError(format!("Peer {} did not start with a handshake", peer_id))),
+            None => {
+                return Err(Error::NetworkError(format!("Peer {} closed the connection during the handshake", peer_id)));
+            }
+        };
+        if let Err(e) = &result {
+            let reason = Message::Disconnect { reason: e.to_string() };
+            let _ = session.write_message(stream, &reason).await;
+            let _ = stream.shutdown().await;
+        }
+        result
+    }
+
+    async fn register(&self, peer_id: String, stream: TcpStream, session: SecureSession, protocol: NegotiatedProtocol) {
+        debug!("Negotiated protocol v{} with {} ({:?})", protocol.version, peer_id, protocol.features);
+        let (reader, writer) = stream.into_split();
+        let connection = Arc::new(PeerConnection {
+            writer: Mutex::new(writer),
+            session: session.clone(),
+            protocol,
+        });
+        self.connections.lock().await.insert(peer_id.clone(), connection);
+        let transport = self.clone();
+        tokio::spawn(async move {
+            transport.read_loop(peer_id, reader, session).await;
+        });
+    }
+
+    async fn read_loop(&self, peer_id: String, mut reader: OwnedReadHalf, session: SecureSession) {
+        loop {
+            match session.read_message(&mut reader).await {
+                Ok(Some(Message::Disconnect { reason })) => {
+                    info!("Peer {} disconnected: {}", peer_id, reason);
+                    break;
+                }
+                Ok(Some(Message::Handshake(_))) => {
+                    warn!("Ignoring repeated handshake from {}", peer_id);
+                }
+                Ok(Some(message)) => {
+                    if self.inbound.send((peer_id.clone(), message)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Dropping connection to {}: {}", peer_id, e);
+                    break;
+                }
+            }
+        }
+        self.connections.lock().await.remove(&peer_id);
+        debug!("Connection to {} closed", peer_id);
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    fn local_id(&self) -> &str {
+        &self.keys.identity().node_id
+    }
+
+    fn listen_addr(&self) -> String {
+        self.local_addr.to_string()
+    }
+
+    /// Opens a connection to a peer unless one is already established.
+    async fn connect(&self, peer_id: &str, addr: &str) -> Result<()> {
+        self.open(peer_id, addr).await
+    }
+
+    async fn send(&self, peer_id: &str, message: &Message) -> Result<()> {
+        self.write(peer_id, message).await
+    }
+
+    async fn broadcast(&self, message: &Message) -> Result<()> {
+        for peer_id in self.connected_peers().await {
+            if let Err(e) = self.write(&peer_id, message).await {
+                warn!("Failed to broadcast to {}: {}", peer_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self, peer_id: &str) {
+        if let Some(connection) = self.connections.lock().await.remove(peer_id) {
+            let _ = connection.writer.lock().await.shutdown().await;
+            info!("Disconnected from peer {}", peer_id);
+        }
+    }
+
+    async fn is_connected(&self, peer_id: &str) -> bool {
+        self.connections.lock().await.contains_key(peer_id)
+    }
+
+    async fn connected_peers(&self) -> Vec<String> {
+        self.connections.lock().await.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::features;
+
+    #[tokio::test]
+    async fn test_send_between_transports() {
+        let (node1, _inbound1) = TcpTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (node2, mut inbound2) = TcpTransport::bind(NodeIdentity::generate("node2"), "127.0.0.1:0").await.unwrap();
+
+        node1.connect("node2", &node2.local_addr().to_string()).await.unwrap();
+        let packet = Packet::interest("/coopX/docs");
+        node1.send("node2", &Message::Packet(packet)).await.unwrap();
+
+        let (from, message) = inbound2.recv().await.unwrap();
+        assert_eq!(from, "node1");
+        match message {
+            Message::Packet(packet) => assert_eq!(packet.name, "/coopX/docs"),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(node2.is_connected("node1").await);
+        assert_eq!(node2.peer_key("node1").await, Some(node1.public_key()));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_all_peers() {
+        let (hub, _inbound) = TcpTransport::bind(NodeIdentity::generate("hub"), "127.0.0.1:0").await.unwrap();
+        let (peer1, mut inbound1) = TcpTransport::bind(NodeIdentity::generate("peer1"), "127.0.0.1:0").await.unwrap();
+        let (peer2, mut inbound2) = TcpTransport::bind(NodeIdentity::generate("peer2"), "127.0.0.1:0").await.unwrap();
+        hub.connect("peer1", &peer1.listen_addr()).await.unwrap();
+        hub.connect("peer2", &peer2.listen_addr()).await.unwrap();
+
+        let packet = Packet::interest("/coopX/news");
+        hub.broadcast(&Message::Packet(packet)).await.unwrap();
+        assert_eq!(inbound1.recv().await.unwrap().0, "hub");
+        assert_eq!(inbound2.recv().await.unwrap().0, "hub");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_peer_id_rejected() {
+        let (node1, _inbound1) = TcpTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (node2, _inbound2) = TcpTransport::bind(NodeIdentity::generate("node2"), "127.0.0.1:0").await.unwrap();
+
+        assert!(node1.connect("node3", &node2.local_addr().to_string()).await.is_err());
+        assert!(!node1.is_connected("node3").await);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_key_mismatch_rejected() {
+        let (node1, _inbound1) = TcpTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (impostor, _inbound2) = TcpTransport::bind(NodeIdentity::generate("node2"), "127.0.0.1:0").await.unwrap();
+
+        node1.trust_peer("node2", NodeIdentity::generate("node2").public_key()).await;
+        assert!(node1.connect("node2", &impostor.local_addr().to_string()).await.is_err());
+        assert!(!node1.is_connected("node2").await);
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_peer_disconnected() {
+        let genesis = ProtocolInfo::default().genesis_hash;
+        let (node1, _inbound1) = TcpTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (testnet, _inbound2) = TcpTransport::bind_with_protocol(
+            NodeIdentity::generate("node2"),
+            ProtocolInfo::new("icn-testnet", &genesis),
+            "127.0.0.1:0",
+        ).await.unwrap();
+
+        let err = node1.connect("node2", &testnet.listen_addr()).await.unwrap_err();
+        assert!(err.to_string().contains("icn-testnet"), "{}", err);
+        assert!(!node1.is_connected("node2").await);
+        assert!(!testnet.is_connected("node1").await);
+    }
+
+    #[tokio::test]
+    async fn test_features_negotiated() {
+        let mut limited = ProtocolInfo::default();
+        limited.features.retain(|feature| feature != features::GOSSIP);
+        let (node1, _inbound1) = TcpTransport::bind(NodeIdentity::generate("node1"), "127.0.0.1:0").await.unwrap();
+        let (node2, _inbound2) = TcpTransport::bind_with_protocol(NodeIdentity::generate("node2"), limited, "127.0.0.1:0").await.unwrap();
+
+        node1.connect("node2", &node2.listen_addr()).await.unwrap();
+        let negotiated = node1.peer_protocol("node2").await.unwrap();
+        assert!(!negotiated.supports(features::GOSSIP));
+        assert!(negotiated.supports(features::BLOCK_SYNC));
+    }
+}
@@ -1,86 +1,564 @@
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::error::Result;
+use crate::network::packet::{ContentMeta, Packet, SignatureInfo};
+use super::storage::NodeStorage;
 
 const MAX_CACHE_SIZE: usize = 1000;
+const MAX_CACHE_BYTES: usize = 64 * 1024 * 1024;
+const MAX_DISK_BYTES: usize = 1024 * 1024 * 1024;
+const CONTENT_TREE: &str = "content";
+const CONTENT_ORDER_TREE: &str = "content_order";
 const DEFAULT_TTL: Duration = Duration::from_secs(3600);
 
+/// Which unpinned entry makes room when the store is full. Expired entries
+/// are always evicted first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum EvictionPolicy {
+    /// Least recently used
+    Lru,
+    /// Least frequently used, ties broken by recency
+    Lfu,
+    /// Oldest insertion
+    Fifo,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentStoreConfig {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub policy: EvictionPolicy,
+    pub default_ttl: Duration,
+    /// Bound on the content kept on disk, when the store has a disk tier.
+    pub max_disk_bytes: usize,
+}
+
+impl Default for ContentStoreConfig {
+    fn default() -> Self {
+        ContentStoreConfig {
+            max_entries: MAX_CACHE_SIZE,
+            max_bytes: MAX_CACHE_BYTES,
+            policy: EvictionPolicy::Lru,
+            default_ttl: DEFAULT_TTL,
+            max_disk_bytes: MAX_DISK_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContentStoreStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub pinned: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Content that could not be stored because it did not fit.
+    pub rejected: u64,
+    pub disk_entries: usize,
+    pub disk_bytes: usize,
+}
+
 struct CacheEntry {
     content: Vec<u8>,
+    meta: ContentMeta,
+    signature: Option<SignatureInfo>,
+    received_at: Instant,
     timestamp: Instant,
     ttl: Duration,
+    pinned: bool,
+    inserted: u64,
+    // Updated through `&self` so lookups can share the store behind a read lock
+    last_access: AtomicU64,
+    hits: AtomicU64,
 }
 
+impl CacheEntry {
+    /// Still cached; expired entries are kept only until the next cleanup.
+    fn is_live(&self) -> bool {
+        self.pinned || self.timestamp.elapsed() < self.ttl
+    }
+
+    /// Within the producer's freshness period, so it may answer interests that
+    /// must be fresh.
+    fn is_fresh(&self) -> bool {
+        self.meta.freshness_period().is_some_and(|period| self.received_at.elapsed() < period)
+    }
+
+    fn encode(&self, seq: u64) -> Vec<u8> {
+        let header = DiskHeader {
+            meta: self.meta.clone(),
+            signature: self.signature.clone(),
+            received_at: unix_millis(self.received_at),
+            expires_at: (!self.pinned).then(|| unix_millis(self.timestamp) + self.ttl.as_millis() as u64),
+            seq,
+        };
+        let header = serde_json::to_vec(&header).expect("content headers serialize");
+        let mut bytes = (header.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(header);
+        bytes.extend(&self.content);
+        bytes
+    }
+
+    fn decode(bytes: &[u8], tick: u64) -> Option<(CacheEntry, u64)> {
+        let header_len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let header: DiskHeader = serde_json::from_slice(bytes.get(4..4 + header_len)?).ok()?;
+        let now = unix_millis(Instant::now());
+        let ago = |millis: u64| Instant::now().checked_sub(Duration::from_millis(now.saturating_sub(millis))).unwrap_or_else(Instant::now);
+        let entry = CacheEntry {
+            content: bytes[4 + header_len..].to_vec(),
+            meta: header.meta,
+            signature: header.signature,
+            received_at: ago(header.received_at),
+            timestamp: Instant::now(),
+            ttl: Duration::from_millis(header.expires_at.unwrap_or(now).saturating_sub(now)),
+            pinned: header.expires_at.is_none(),
+            inserted: tick,
+            last_access: AtomicU64::new(tick),
+            hits: AtomicU64::new(0),
+        };
+        Some((entry, header.seq))
+    }
+}
+
+/// Milliseconds since the Unix epoch at `instant`, which must be past.
+fn unix_millis(instant: Instant) -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.saturating_sub(instant.elapsed()).as_millis() as u64
+}
+
+/// How an entry is kept on disk, ahead of its content. Times are wall clock
+/// milliseconds so that they hold across restarts.
+#[derive(Serialize, Deserialize)]
+struct DiskHeader {
+    meta: ContentMeta,
+    signature: Option<SignatureInfo>,
+    received_at: u64,
+    /// None for pinned entries.
+    expires_at: Option<u64>,
+    /// Position in the order entries were written, oldest first.
+    seq: u64,
+}
+
+/// Every entry of a store written through to a `NodeStorage`, so that what
+/// memory had to evict, and after a restart the whole cache, is still served
+/// from disk. Bounded by `max_disk_bytes`, dropping the oldest unpinned
+/// writes first.
+struct DiskTier {
+    content: sled::Tree,
+    /// Names by the `seq` they were last written with.
+    order: sled::Tree,
+    bytes: usize,
+    next_seq: u64,
+}
+
+impl DiskTier {
+    fn open(storage: &NodeStorage) -> Result<Self> {
+        let content = storage.tree(CONTENT_TREE)?;
+        let order = storage.tree(CONTENT_ORDER_TREE)?;
+        let mut bytes = 0;
+        for item in content.iter() {
+            let (_, value) = item?;
+            bytes += Self::content_len(&value);
+        }
+        let next_seq = match order.last()? {
+            Some((key, _)) => Self::seq(&key) + 1,
+            None => 0,
+        };
+        Ok(DiskTier { content, order, bytes, next_seq })
+    }
+
+    fn store(&mut self, name: &str, entry: &CacheEntry, max_bytes: usize) -> Result<()> {
+        self.remove(name, 0)?;
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.content.insert(name, entry.encode(seq))?;
+        self.order.insert(seq.to_be_bytes(), name)?;
+        self.bytes += entry.content.len();
+        // Oldest first, the entry just written included; pinned entries stay
+        for item in self.order.iter() {
+            if self.bytes <= max_bytes {
+                break;
+            }
+            let name = String::from_utf8_lossy(&item?.1).into_owned();
+            if !self.get(&name, 0)?.is_some_and(|entry| entry.pinned) {
+                self.remove(&name, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The entry for `name` unless it has expired.
+    fn get(&self, name: &str, tick: u64) -> Result<Option<CacheEntry>> {
+        Ok(self.content.get(name)?
+            .and_then(|bytes| CacheEntry::decode(&bytes, tick))
+            .map(|(entry, _)| entry)
+            .filter(|entry| entry.is_live()))
+    }
+
+    fn remove(&mut self, name: &str, tick: u64) -> Result<Option<CacheEntry>> {
+        let bytes = match self.content.remove(name)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        self.bytes -= Self::content_len(&bytes);
+        Ok(CacheEntry::decode(&bytes, tick).map(|(entry, seq)| {
+            let _ = self.order.remove(seq.to_be_bytes());
+            entry
+        }))
+    }
+
+    fn remove_expired(&mut self) -> Result<()> {
+        let mut expired = Vec::new();
+        for item in self.content.iter() {
+            let (name, bytes) = item?;
+            if CacheEntry::decode(&bytes, 0).is_none_or(|(entry, _)| !entry.is_live()) {
+                expired.push(String::from_utf8_lossy(&name).into_owned());
+            }
+        }
+        for name in expired {
+            self.remove(&name, 0)?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    fn content_len(bytes: &[u8]) -> usize {
+        let header_len = bytes.get(..4).map_or(0, |len| u32::from_be_bytes(len.try_into().unwrap()) as usize);
+        bytes.len().saturating_sub(4 + header_len)
+    }
+
+    fn seq(key: &[u8]) -> u64 {
+        key.try_into().map(u64::from_be_bytes).unwrap_or(0)
+    }
+}
+
+/// Caches data packets by name, bounded by entry count and total bytes.
+/// Pinned entries are never evicted or expired until unpinned.
+///
+/// Entries stay cached for the store's TTL, but only answer interests that
+/// must be fresh within their freshness period. Stale content is thus
+/// refreshed by forwarding the interest instead of being served forever.
+///
+/// With a disk tier, see `with_disk`, the bounds above apply to memory only:
+/// content evicted from memory is still served from disk, and the cache
+/// survives restarts.
 pub struct ContentStore {
     cache: HashMap<String, CacheEntry>,
+    disk: Option<DiskTier>,
+    config: ContentStoreConfig,
+    bytes: usize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: u64,
+    rejected: u64,
 }
 
 impl ContentStore {
     pub fn new() -> Self {
+        Self::with_config(ContentStoreConfig::default())
+    }
+
+    pub fn with_config(config: ContentStoreConfig) -> Self {
         ContentStore {
             cache: HashMap::new(),
+            disk: None,
+            config,
+            bytes: 0,
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: 0,
+            rejected: 0,
         }
     }
 
-    pub fn add(&mut self, name: String, content: Vec<u8>) {
-        self.cache.insert(name, CacheEntry {
+    /// Writes every entry through to `storage`, which keeps up to
+    /// `max_disk_bytes` of content and what was cached there before.
+    pub fn with_disk(mut self, storage: &NodeStorage) -> Result<Self> {
+        self.disk = Some(DiskTier::open(storage)?);
+        Ok(self)
+    }
+
+    pub fn config(&self) -> &ContentStoreConfig {
+        &self.config
+    }
+
+    /// Stores content under `name`, evicting entries as needed to stay within
+    /// capacity. Returns false if the content could not be made to fit.
+    pub fn add(&mut self, name: String, content: Vec<u8>) -> bool {
+        self.add_with_meta(name, content, ContentMeta::default())
+    }
+
+    /// Like `add`, keeping the content's freshness period and version. Content
+    /// older than the cached version is ignored and false is returned.
+    pub fn add_with_meta(&mut self, name: String, content: Vec<u8>, meta: ContentMeta) -> bool {
+        self.insert(name, content, meta, None)
+    }
+
+    fn insert(&mut self, name: String, content: Vec<u8>, meta: ContentMeta, signature: Option<SignatureInfo>) -> bool {
+        let cached_version = match self.cache.get(&name) {
+            Some(entry) => entry.meta.version.filter(|_| entry.is_live()),
+            None => self.disk_get(&name).and_then(|entry| entry.meta.version),
+        };
+        if cached_version.zip(meta.version).is_some_and(|(cached, version)| cached > version) {
+            return false;
+        }
+        let pinned = self.is_pinned(&name);
+        let (pinned_entries, pinned_bytes) = self.cache.iter()
+            .filter(|(other, entry)| entry.pinned && **other != name)
+            .fold((0, 0), |(count, bytes), (_, entry)| (count + 1, bytes + entry.content.len()));
+        let fits = pinned_entries < self.config.max_entries && pinned_bytes + content.len() <= self.config.max_bytes;
+        if !pinned && !fits {
+            self.rejected += 1;
+            return false;
+        }
+        self.remove_entry(&name);
+
+        let tick = self.tick();
+        self.bytes += content.len();
+        self.cache.insert(name.clone(), CacheEntry {
             content,
+            meta,
+            signature,
+            received_at: Instant::now(),
             timestamp: Instant::now(),
-            ttl: DEFAULT_TTL,
+            ttl: self.config.default_ttl,
+            pinned,
+            inserted: tick,
+            last_access: AtomicU64::new(tick),
+            hits: AtomicU64::new(0),
         });
+        self.write_through(&name);
+        self.make_room(&name);
+        true
+    }
 
-        if self.cache.len() > MAX_CACHE_SIZE {
-            self.evict_oldest();
-        }
+    /// Caches a Data packet together with its signature, so that interests
+    /// answered from the cache carry the publisher's proof.
+    pub fn add_packet(&mut self, packet: &Packet) -> bool {
+        self.insert(packet.name.clone(), packet.content.clone(), packet.meta.clone(), packet.signature.clone())
     }
 
     pub fn get(&self, name: &str) -> Option<Vec<u8>> {
-        self.cache.get(name).and_then(|entry| {
-            if entry.timestamp.elapsed() < entry.ttl {
-                Some(entry.content.clone())
-            } else {
-                None
-            }
+        self.lookup(name, false).map(|(content, _)| content)
+    }
+
+    /// Finds content for an interest. With `must_be_fresh`, content past its
+    /// freshness period is treated as missing.
+    pub fn lookup(&self, name: &str, must_be_fresh: bool) -> Option<(Vec<u8>, ContentMeta)> {
+        self.find(name, must_be_fresh, |entry| (entry.content.clone(), entry.meta.clone()))
+    }
+
+    /// Like `lookup`, rebuilding the cached Data packet with its signature.
+    pub fn lookup_packet(&self, name: &str, must_be_fresh: bool) -> Option<Packet> {
+        self.find(name, must_be_fresh, |entry| Packet {
+            meta: entry.meta.clone(),
+            signature: entry.signature.clone(),
+            ..Packet::data(name, entry.content.clone())
         })
     }
 
-    pub fn get_and_pop(&mut self, name: &str) -> Option<Vec<u8>> {
-        if let Some(entry) = self.cache.remove(name) {
-            if entry.timestamp.elapsed() < entry.ttl {
-                Some(entry.content)
-            } else {
-                None
+    /// Reads the usable entry for `name`, from memory or else from disk.
+    fn find<T>(&self, name: &str, must_be_fresh: bool, read: impl FnOnce(&CacheEntry) -> T) -> Option<T> {
+        let usable = |entry: &CacheEntry| entry.is_live() && (!must_be_fresh || entry.is_fresh());
+        let found = match self.cache.get(name) {
+            Some(entry) if usable(entry) => {
+                entry.last_access.store(self.tick(), Ordering::Relaxed);
+                entry.hits.fetch_add(1, Ordering::Relaxed);
+                Some(read(entry))
             }
-        } else {
-            None
+            Some(_) => None,
+            None => self.disk_get(name).filter(|entry| usable(entry)).map(|entry| read(&entry)),
+        };
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn get_and_pop(&mut self, name: &str) -> Option<Vec<u8>> {
+        let on_disk = self.disk_remove(name);
+        self.remove_entry(name)
+            .or(on_disk)
+            .filter(|entry| entry.is_live())
+            .map(|entry| entry.content)
+    }
+
+    /// Protects an entry from eviction and expiry. Returns false if there is
+    /// no such entry.
+    pub fn pin(&mut self, name: &str) -> bool {
+        match self.entry_mut(name) {
+            Some(entry) => entry.pinned = true,
+            None => return false,
         }
+        self.write_through(name);
+        true
     }
 
-    fn evict_oldest(&mut self) {
-        if let Some(oldest_key) = self.cache.keys().next().cloned() {
-            self.cache.remove(&oldest_key);
+    pub fn unpin(&mut self, name: &str) {
+        if let Some(entry) = self.entry_mut(name) {
+            entry.pinned = false;
+            entry.timestamp = Instant::now();
+            self.write_through(name);
+        }
+    }
+
+    pub fn is_pinned(&self, name: &str) -> bool {
+        match self.cache.get(name) {
+            Some(entry) => entry.pinned,
+            None => self.disk_get(name).is_some_and(|entry| entry.pinned),
         }
     }
 
     pub fn remove_expired(&mut self) {
-        let now = Instant::now();
-        self.cache.retain(|_, entry| now.duration_since(entry.timestamp) < entry.ttl);
+        let expired: Vec<String> = self.cache.iter()
+            .filter(|(_, entry)| !entry.is_live())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            self.remove_entry(&name);
+        }
+        if let Some(Err(e)) = self.disk.as_mut().map(DiskTier::remove_expired) {
+            warn!("Failed to remove expired content from disk: {}", e);
+        }
     }
 
     pub fn set_ttl(&mut self, name: &str, ttl: Duration) {
-        if let Some(entry) = self.cache.get_mut(name) {
+        if let Some(entry) = self.entry_mut(name) {
             entry.ttl = ttl;
+            self.write_through(name);
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.cache.is_empty()
     }
+
+    /// Total size of the cached content.
+    pub fn size_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> ContentStoreStats {
+        let (disk_entries, disk_bytes) = self.disk.as_ref().map_or((0, 0), |disk| (disk.len(), disk.bytes));
+        ContentStoreStats {
+            entries: self.cache.len(),
+            bytes: self.bytes,
+            pinned: self.cache.values().filter(|entry| entry.pinned).count(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions,
+            rejected: self.rejected,
+            disk_entries,
+            disk_bytes,
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Evicts entries other than `keep` from memory until it is within
+    /// capacity. With a disk tier they stay cached there.
+    fn make_room(&mut self, keep: &str) {
+        while self.over_capacity() {
+            match self.victim(keep) {
+                Some(victim) => {
+                    self.remove_entry(&victim);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// The entry for `name`, brought back into memory if only on disk.
+    fn entry_mut(&mut self, name: &str) -> Option<&mut CacheEntry> {
+        if !self.cache.contains_key(name) {
+            let entry = self.disk_get(name)?;
+            self.bytes += entry.content.len();
+            self.cache.insert(name.to_string(), entry);
+            self.make_room(name);
+        }
+        self.cache.get_mut(name)
+    }
+
+    fn write_through(&mut self, name: &str) {
+        if let (Some(disk), Some(entry)) = (self.disk.as_mut(), self.cache.get(name)) {
+            if let Err(e) = disk.store(name, entry, self.config.max_disk_bytes) {
+                warn!("Failed to write {} to disk: {}", name, e);
+            }
+        }
+    }
+
+    fn disk_get(&self, name: &str) -> Option<CacheEntry> {
+        let disk = self.disk.as_ref()?;
+        disk.get(name, self.tick()).unwrap_or_else(|e| {
+            warn!("Failed to read {} from disk: {}", name, e);
+            None
+        })
+    }
+
+    fn disk_remove(&mut self, name: &str) -> Option<CacheEntry> {
+        let tick = self.tick();
+        self.disk.as_mut()?.remove(name, tick).unwrap_or_else(|e| {
+            warn!("Failed to remove {} from disk: {}", name, e);
+            None
+        })
+    }
+
+    fn over_capacity(&self) -> bool {
+        self.cache.len() > self.config.max_entries || self.bytes > self.config.max_bytes
+    }
+
+    fn remove_entry(&mut self, name: &str) -> Option<CacheEntry> {
+        let entry = self.cache.remove(name)?;
+        self.bytes -= entry.content.len();
+        Some(entry)
+    }
+
+    /// The unpinned entry to evict next, never `keep`.
+    fn victim(&self, keep: &str) -> Option<String> {
+        let candidates = self.cache.iter().filter(|(name, entry)| !entry.pinned && name.as_str() != keep);
+        if let Some((name, _)) = candidates.clone().find(|(_, entry)| !entry.is_live()) {
+            return Some(name.clone());
+        }
+        let rank = |entry: &CacheEntry| match self.config.policy {
+            EvictionPolicy::Lru => (entry.last_access.load(Ordering::Relaxed), 0),
+            EvictionPolicy::Lfu => (entry.hits.load(Ordering::Relaxed), entry.last_access.load(Ordering::Relaxed)),
+            EvictionPolicy::Fifo => (entry.inserted, 0),
+        };
+        candidates
+            .min_by_key(|(_, entry)| rank(entry))
+            .map(|(name, _)| name.clone())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn store(policy: EvictionPolicy, max_entries: usize, max_bytes: usize) -> ContentStore {
+        ContentStore::with_config(ContentStoreConfig {
+            max_entries,
+            max_bytes,
+            policy,
+            default_ttl: DEFAULT_TTL,
+            max_disk_bytes: MAX_DISK_BYTES,
+        })
+    }
+
     #[test]
     fn test_content_store() {
         let mut cs = ContentStore::new();
@@ -100,4 +578,121 @@ mod tests {
         cs.remove_expired();
         assert_eq!(cs.get("test2"), Some(vec![5, 6, 7, 8]));
     }
+
+    #[test]
+    fn test_eviction_policies() {
+        let mut lru = store(EvictionPolicy::Lru, 2, 1024);
+        lru.add("/a".to_string(), vec![1]);
+        lru.add("/b".to_string(), vec![2]);
+        lru.get("/a");
+        lru.add("/c".to_string(), vec![3]);
+        assert!(lru.get("/a").is_some());
+        assert!(lru.get("/b").is_none());
+
+        let mut lfu = store(EvictionPolicy::Lfu, 2, 1024);
+        lfu.add("/a".to_string(), vec![1]);
+        lfu.add("/b".to_string(), vec![2]);
+        lfu.get("/a");
+        lfu.get("/a");
+        lfu.get("/b");
+        lfu.add("/c".to_string(), vec![3]);
+        assert!(lfu.get("/a").is_some());
+        assert!(lfu.get("/b").is_none());
+
+        let mut fifo = store(EvictionPolicy::Fifo, 2, 1024);
+        fifo.add("/a".to_string(), vec![1]);
+        fifo.add("/b".to_string(), vec![2]);
+        fifo.get("/a");
+        fifo.add("/c".to_string(), vec![3]);
+        assert!(fifo.get("/a").is_none());
+        assert!(fifo.get("/b").is_some());
+        assert_eq!(fifo.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_byte_capacity_and_pinning() {
+        let mut cs = store(EvictionPolicy::Lru, 100, 10);
+        assert!(cs.add("/block/1".to_string(), vec![0; 6]));
+        assert!(cs.pin("/block/1"));
+        assert!(cs.add("/a".to_string(), vec![0; 4]));
+        assert!(cs.add("/b".to_string(), vec![0; 4]));
+        assert!(cs.get("/a").is_none());
+        assert_eq!(cs.size_bytes(), 10);
+
+        // Does not fit next to the pinned entry and the pinned entry stays
+        assert!(!cs.add("/big".to_string(), vec![0; 5]));
+        assert!(!cs.add("/huge".to_string(), vec![0; 11]));
+        assert!(cs.get("/block/1").is_some());
+
+        cs.set_ttl("/block/1", Duration::ZERO);
+        assert!(cs.get("/block/1").is_some(), "pinned entries do not expire");
+        cs.unpin("/block/1");
+        assert!(cs.add("/big".to_string(), vec![0; 5]));
+
+        let stats = cs.stats();
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.pinned, 0);
+        assert!(stats.hits >= 2);
+    }
+
+    #[test]
+    fn test_disk_tier_spills_and_survives_restart() {
+        let path = std::env::temp_dir().join(format!("icn-content-store-{}", uuid::Uuid::new_v4()));
+        // The store restarts on the same database; reopening the path could
+        // race sled releasing its lock
+        let storage = NodeStorage::open(&path).unwrap();
+        {
+            let mut cs = store(EvictionPolicy::Lru, 1, 1024).with_disk(&storage).unwrap();
+            let signature = SignatureInfo { key_locator: "did:icn:coopX".to_string(), signature: vec![7] };
+            assert!(cs.add_packet(&Packet { signature: Some(signature.clone()), ..Packet::data("/coopX/a", vec![1]) }));
+            assert!(cs.add("/coopX/b".to_string(), vec![2]));
+            assert!(cs.add("/coopX/c".to_string(), vec![3]));
+            assert_eq!(cs.len(), 1);
+
+            // Evicted from memory, still served from disk
+            assert_eq!(cs.lookup_packet("/coopX/a", false).unwrap().signature, Some(signature));
+            assert_eq!(cs.get_and_pop("/coopX/b"), Some(vec![2]));
+            assert!(cs.get("/coopX/b").is_none());
+            assert!(cs.pin("/coopX/a"));
+            assert_eq!(cs.stats().disk_entries, 2);
+            storage.flush().unwrap();
+        }
+
+        let config = ContentStoreConfig { max_disk_bytes: 2, ..ContentStoreConfig::default() };
+        let mut cs = ContentStore::with_config(config).with_disk(&storage).unwrap();
+        assert!(cs.is_empty());
+        assert_eq!(cs.get("/coopX/a"), Some(vec![1]));
+        assert!(cs.is_pinned("/coopX/a"));
+
+        // The oldest writes make room on disk, but pinned content stays and
+        // what does not fit beside it is kept in memory only
+        assert!(cs.add("/coopX/d".to_string(), vec![4, 4]));
+        let stats = cs.stats();
+        assert_eq!((stats.disk_entries, stats.disk_bytes), (1, 1));
+        assert!(cs.get("/coopX/c").is_none());
+        assert_eq!(cs.get("/coopX/a"), Some(vec![1]));
+        assert_eq!(cs.get("/coopX/d"), Some(vec![4, 4]));
+        drop((cs, storage));
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_freshness_and_versions() {
+        let mut cs = ContentStore::new();
+        let meta = |freshness_ms, version| ContentMeta { freshness_period_ms: Some(freshness_ms), version: Some(version) };
+        assert!(cs.add_with_meta("/coopX/prices".to_string(), vec![2], meta(50, 2)));
+        assert_eq!(cs.lookup("/coopX/prices", true).unwrap().1.version, Some(2));
+
+        // Older versions never replace newer ones
+        assert!(!cs.add_with_meta("/coopX/prices".to_string(), vec![1], meta(50, 1)));
+        assert_eq!(cs.get("/coopX/prices"), Some(vec![2]));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cs.lookup("/coopX/prices", true).is_none());
+        assert_eq!(cs.lookup("/coopX/prices", false).unwrap().0, vec![2]);
+
+        cs.add("/coopX/static".to_string(), vec![3]);
+        assert!(cs.lookup("/coopX/static", true).is_none(), "no freshness period means stale");
+    }
 }
@@ -0,0 +1,104 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use crate::clock::SharedClock;
+
+/// Longer than an interest lifetime, so that an interest still looping after
+/// its PIT entry is gone is recognised.
+pub const DEAD_NONCE_LIFETIME: Duration = Duration::from_secs(6);
+pub const MAX_DEAD_NONCES: usize = 50_000;
+
+/// Remembers the name and nonce of interests whose PIT entries were satisfied
+/// or expired, so copies that come back around a loop afterwards are dropped
+/// instead of opening a new entry.
+pub struct DeadNonceList {
+    nonces: HashMap<(String, u32), Instant>,
+    queue: VecDeque<(Instant, String, u32)>,
+    lifetime: Duration,
+    clock: SharedClock,
+}
+
+impl DeadNonceList {
+    pub fn new() -> Self {
+        Self::with_lifetime(DEAD_NONCE_LIFETIME)
+    }
+
+    pub fn with_lifetime(lifetime: Duration) -> Self {
+        DeadNonceList {
+            nonces: HashMap::new(),
+            queue: VecDeque::new(),
+            lifetime,
+            clock: SharedClock::default(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn add(&mut self, name: &str, nonce: u32) {
+        self.evict();
+        let now = self.clock.instant();
+        self.nonces.insert((name.to_string(), nonce), now);
+        self.queue.push_back((now, name.to_string(), nonce));
+        while self.queue.len() > MAX_DEAD_NONCES {
+            self.pop_oldest();
+        }
+    }
+
+    pub fn contains(&self, name: &str, nonce: u32) -> bool {
+        self.nonces.get(&(name.to_string(), nonce)).is_some_and(|added| self.clock.instant().saturating_duration_since(*added) < self.lifetime)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nonces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nonces.is_empty()
+    }
+
+    fn evict(&mut self) {
+        let now = self.clock.instant();
+        while self.queue.front().is_some_and(|(added, _, _)| now.saturating_duration_since(*added) >= self.lifetime) {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((added, name, nonce)) = self.queue.pop_front() {
+            // A later `add` of the same nonce has its own place in the queue
+            let key = (name, nonce);
+            if self.nonces.get(&key) == Some(&added) {
+                self.nonces.remove(&key);
+            }
+        }
+    }
+}
+
+impl Default for DeadNonceList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_dead_nonces_expire() {
+        let clock = MockClock::new();
+        let mut dead = DeadNonceList::with_lifetime(Duration::from_millis(20)).with_clock(clock.clone().into());
+        dead.add("/coopX/docs", 7);
+        assert!(dead.contains("/coopX/docs", 7));
+        assert!(!dead.contains("/coopX/docs", 8));
+        assert!(!dead.contains("/coopX/other", 7));
+
+        clock.advance(Duration::from_millis(30));
+        assert!(!dead.contains("/coopX/docs", 7));
+        dead.add("/coopX/docs", 8);
+        assert_eq!(dead.len(), 1);
+    }
+}
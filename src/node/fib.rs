@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use super::strategy::{BestRoute, ForwardingStrategy, Measurements};
 
 /// Represents an entry in the Forwarding Information Base (FIB).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FibEntry {
     pub name: String,           // The name of the content or prefix.
     pub next_hops: Vec<SocketAddr>, // The list of next hop addresses.
@@ -47,9 +51,23 @@ impl FibEntry {
     }
 }
 
-/// Represents the Forwarding Information Base (FIB) which stores FIB entries.
+/// The routes and strategy choices of a FIB, as saved across restarts.
+/// Measurements are left out and learned again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FibSnapshot {
+    pub entries: Vec<FibEntry>,
+    /// Prefixes and the names of the strategies chosen for them.
+    pub strategies: Vec<(String, String)>,
+}
+
+/// Represents the Forwarding Information Base (FIB) which stores FIB entries,
+/// along with the forwarding strategy chosen for each name prefix and the
+/// measurements the strategies rank next hops by.
 pub struct ForwardingInformationBase {
     entries: HashMap<String, FibEntry>, // The collection of FIB entries, indexed by name.
+    strategies: HashMap<String, Arc<dyn ForwardingStrategy>>, // Strategy choices, indexed by prefix.
+    default_strategy: Arc<dyn ForwardingStrategy>,
+    measurements: Measurements,
 }
 
 impl ForwardingInformationBase {
@@ -61,6 +79,9 @@ impl ForwardingInformationBase {
     pub fn new() -> Self {
         ForwardingInformationBase {
             entries: HashMap::new(),
+            strategies: HashMap::new(),
+            default_strategy: Arc::new(BestRoute),
+            measurements: Measurements::default(),
         }
     }
 
@@ -116,6 +137,87 @@ impl ForwardingInformationBase {
             .map(|(_, entry)| entry)
     }
 
+    /// Chooses the forwarding strategy for names under a prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The name prefix the strategy applies to.
+    /// * `strategy` - The strategy to forward its interests with.
+    pub fn set_strategy(&mut self, prefix: String, strategy: Arc<dyn ForwardingStrategy>) {
+        self.strategies.insert(prefix, strategy);
+    }
+
+    /// Finds the strategy for a name by longest prefix match.
+    ///
+    /// # Returns
+    ///
+    /// * The strategy chosen for the longest matching prefix, or best route if none was chosen.
+    pub fn strategy_for(&self, name: &str) -> Arc<dyn ForwardingStrategy> {
+        self.strategies
+            .iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(|| Arc::clone(&self.default_strategy), |(_, strategy)| Arc::clone(strategy))
+    }
+
+    /// Selects the next hops an interest for `name` is sent to.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the interest.
+    /// * `exclude` - Next hops that must not be chosen, e.g. ones already tried.
+    ///
+    /// # Returns
+    ///
+    /// * The next hops picked by the strategy for the name, empty if there is no route.
+    pub fn select_next_hops(&self, name: &str, exclude: &[SocketAddr]) -> Vec<SocketAddr> {
+        let candidates: Vec<SocketAddr> = match self.longest_prefix_match(name) {
+            Some(entry) => entry.next_hops.iter().copied().filter(|hop| !exclude.contains(hop)).collect(),
+            None => return vec![],
+        };
+        self.strategy_for(name).select(&candidates, &self.measurements)
+    }
+
+    pub fn measurements(&self) -> &Measurements {
+        &self.measurements
+    }
+
+    pub fn measurements_mut(&mut self) -> &mut Measurements {
+        &mut self.measurements
+    }
+
+    /// Copies the entries and strategy choices, to be restored after a restart.
+    pub fn snapshot(&self) -> FibSnapshot {
+        FibSnapshot {
+            entries: self.entries.values().cloned().collect(),
+            strategies: self.strategies.iter()
+                .map(|(prefix, strategy)| (prefix.clone(), strategy.name().to_string()))
+                .collect(),
+        }
+    }
+
+    /// Adds the entries and strategy choices of a snapshot. Strategies this
+    /// node does not know are skipped.
+    ///
+    /// # Returns
+    ///
+    /// * The number of entries restored.
+    pub fn restore(&mut self, snapshot: FibSnapshot) -> usize {
+        let restored = snapshot.entries.len();
+        for entry in snapshot.entries {
+            for next_hop in entry.next_hops {
+                self.add_entry(entry.name.clone(), next_hop);
+            }
+        }
+        for (prefix, name) in snapshot.strategies {
+            match super::strategy::by_name(&name) {
+                Some(strategy) => self.set_strategy(prefix, strategy),
+                None => warn!("Unknown forwarding strategy {} for {}", name, prefix),
+            }
+        }
+        restored
+    }
+
     /// Checks if the FIB is empty.
     ///
     /// # Returns
@@ -164,4 +266,20 @@ mod tests {
         assert_eq!(entry.next_hops.len(), 1);
         assert_eq!(entry.next_hops[0], addr2);
     }
+
+    #[test]
+    fn test_strategy_per_prefix() {
+        let mut fib = ForwardingInformationBase::new();
+        let addr1: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let addr2: SocketAddr = "127.0.0.1:8001".parse().unwrap();
+        fib.add_entry("/coopX".to_string(), addr1);
+        fib.add_entry("/coopX".to_string(), addr2);
+        fib.set_strategy("/coopX/governance".to_string(), crate::node::strategy::by_name("multicast").unwrap());
+
+        assert_eq!(fib.strategy_for("/coopX/docs").name(), "best-route");
+        assert_eq!(fib.select_next_hops("/coopX/docs", &[]), vec![addr1]);
+        assert_eq!(fib.select_next_hops("/coopX/docs", &[addr1]), vec![addr2]);
+        assert_eq!(fib.select_next_hops("/coopX/governance/vote", &[]), vec![addr1, addr2]);
+        assert!(fib.select_next_hops("/coopY", &[]).is_empty());
+    }
 }
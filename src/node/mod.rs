@@ -1,9 +1,16 @@
 // src/node/mod.rs
 
 pub mod content_store;
+pub mod dead_nonce_list;
 pub mod fib;
 pub mod pending_interest_table;
+pub mod storage;
+pub mod strategy;
+pub mod worker;
 
-pub use content_store::ContentStore;
-pub use fib::ForwardingInformationBase;
-pub use pending_interest_table::PendingInterestTable;
\ No newline at end of file
+pub use content_store::{ContentStore, ContentStoreConfig, ContentStoreStats, EvictionPolicy};
+pub use fib::{FibSnapshot, ForwardingInformationBase};
+pub use pending_interest_table::{PendingInterestTable, PitRecord, LOCAL_FACE};
+pub use storage::NodeStorage;
+pub use strategy::ForwardingStrategy;
+pub use worker::Worker;
\ No newline at end of file
@@ -1,41 +1,205 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::clock::SharedClock;
+use super::dead_nonce_list::DeadNonceList;
 
 const DEFAULT_INTEREST_LIFETIME: Duration = Duration::from_secs(4);
+/// Beyond this many pending interests new ones are refused as congestion.
+pub const MAX_PENDING_INTERESTS: usize = 10_000;
+/// Face under which this node's own interests are recorded.
+pub const LOCAL_FACE: &str = "local";
+/// Wait before the first retransmission of an unsatisfied local interest;
+/// doubles after each attempt.
+pub const INITIAL_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(500);
+/// With the timeout doubling from 500ms, three attempts go out at 0.5s, 1.5s
+/// and 3.5s, all before the interest expires.
+pub const MAX_RETRANSMISSIONS: u32 = 3;
+/// How often the forwarder checks for interests due for retransmission.
+pub const RETRANSMISSION_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
 struct PitEntry {
     interfaces: Vec<String>,
     timestamp: Instant,
+    retransmissions: u32,
+    retransmit_at: Instant,
+    /// Next hops the interest was sent to and when, for measuring them.
+    out_hops: Vec<(SocketAddr, Instant)>,
+    /// Nonces of the interests aggregated here and the faces they came from.
+    nonces: Vec<(u32, String)>,
+}
+
+/// A pending interest as saved across restarts. Next hops it was sent to are
+/// left out; the interest is sent again once restored.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PitRecord {
+    pub name: String,
+    pub interfaces: Vec<String>,
+    /// How long the interest had been pending when saved.
+    pub age_ms: u64,
+    pub nonces: Vec<(u32, String)>,
 }
 
 pub struct PendingInterestTable {
     entries: HashMap<String, PitEntry>,
+    dead_nonces: DeadNonceList,
+    clock: SharedClock,
 }
 
 impl PendingInterestTable {
     pub fn new() -> Self {
         PendingInterestTable {
             entries: HashMap::new(),
+            dead_nonces: DeadNonceList::new(),
+            clock: SharedClock::default(),
         }
     }
 
-    pub fn add_interest(&mut self, name: String, interface: &str) {
+    /// Times entries, and the dead nonces they leave, by `clock`.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.dead_nonces = self.dead_nonces.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    fn age(&self, entry: &PitEntry) -> Duration {
+        self.clock.instant().saturating_duration_since(entry.timestamp)
+    }
+
+    /// Records an interest arriving on `interface`. Returns true if it must be
+    /// forwarded: it opened a new entry, or it came again from a face already
+    /// waiting, which means that consumer is retransmitting. Returns false if
+    /// it was aggregated with an interest another face has pending.
+    pub fn add_interest(&mut self, name: String, interface: &str) -> bool {
+        let expired = self.entries.get(&name).is_some_and(|e| self.age(e) >= DEFAULT_INTEREST_LIFETIME);
+        if expired {
+            self.remove_entry(&name);
+        }
+        let mut forward = false;
+        let now = self.clock.instant();
         self.entries
             .entry(name)
             .and_modify(|e| {
-                if !e.interfaces.contains(&interface.to_string()) {
+                if e.interfaces.contains(&interface.to_string()) {
+                    forward = true;
+                } else {
                     e.interfaces.push(interface.to_string());
                 }
-                e.timestamp = Instant::now();
+                e.timestamp = now;
             })
-            .or_insert(PitEntry {
-                interfaces: vec![interface.to_string()],
-                timestamp: Instant::now(),
+            .or_insert_with(|| {
+                forward = true;
+                PitEntry {
+                    interfaces: vec![interface.to_string()],
+                    timestamp: now,
+                    retransmissions: 0,
+                    retransmit_at: now + INITIAL_RETRANSMISSION_TIMEOUT,
+                    out_hops: Vec::new(),
+                    nonces: Vec::new(),
+                }
             });
+        forward
+    }
+
+    /// Whether an interest has been here before: its nonce was already seen
+    /// for this name on another face, or belonged to an interest that was
+    /// recently satisfied or expired. A consumer repeating its own interest
+    /// on the same face is a retransmission, not a loop.
+    pub fn is_looping(&self, name: &str, nonce: u32, interface: &str) -> bool {
+        self.dead_nonces.contains(name, nonce)
+            || self.entries.get(name).is_some_and(|entry| {
+                entry.nonces.iter().any(|(seen, face)| *seen == nonce && face != interface)
+            })
+    }
+
+    pub fn record_nonce(&mut self, name: &str, nonce: u32, interface: &str) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            if !entry.nonces.iter().any(|(seen, face)| *seen == nonce && face == interface) {
+                entry.nonces.push((nonce, interface.to_string()));
+            }
+        }
+    }
+
+    /// Notes that the interest for `name` was just sent to `next_hops`.
+    pub fn record_out_hops(&mut self, name: &str, next_hops: &[SocketAddr]) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            let now = self.clock.instant();
+            entry.out_hops.retain(|(hop, _)| !next_hops.contains(hop));
+            entry.out_hops.extend(next_hops.iter().map(|hop| (*hop, now)));
+        }
+    }
+
+    pub fn out_hops(&self, name: &str) -> Vec<(SocketAddr, Instant)> {
+        self.entries.get(name).map(|entry| entry.out_hops.clone()).unwrap_or_default()
+    }
+
+    /// Drops expired entries and returns the next hops that never answered them.
+    pub fn take_expired(&mut self) -> Vec<SocketAddr> {
+        self.expired_names().iter()
+            .filter_map(|name| self.remove_entry(name))
+            .flat_map(|entry| entry.out_hops.into_iter().map(|(hop, _)| hop))
+            .collect()
+    }
+
+    /// Whether a new entry would exceed `MAX_PENDING_INTERESTS`. Expired
+    /// entries are cleared first so they do not count against the limit.
+    pub fn is_full(&mut self) -> bool {
+        if self.entries.len() >= MAX_PENDING_INTERESTS {
+            self.clear_expired();
+        }
+        self.entries.len() >= MAX_PENDING_INTERESTS
+    }
+
+    /// Stops waiting on `interface` for `name`, dropping the entry once no
+    /// face is left.
+    pub fn remove_interface(&mut self, name: &str, interface: &str) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.interfaces.retain(|face| face != interface);
+            if entry.interfaces.is_empty() {
+                self.remove_entry(name);
+            }
+        }
+    }
+
+    /// Names of this node's own unsatisfied interests whose retransmission
+    /// timer has fired. Each returned interest is scheduled for its next
+    /// attempt with the timeout doubled, up to `MAX_RETRANSMISSIONS`.
+    pub fn due_retransmissions(&mut self, now: Instant) -> Vec<String> {
+        let mut due = Vec::new();
+        for (name, entry) in self.entries.iter_mut() {
+            let live = now.saturating_duration_since(entry.timestamp) < DEFAULT_INTEREST_LIFETIME;
+            if !live
+                || entry.retransmissions >= MAX_RETRANSMISSIONS
+                || entry.retransmit_at > now
+                || !entry.interfaces.iter().any(|face| face == LOCAL_FACE)
+            {
+                continue;
+            }
+            entry.retransmissions += 1;
+            entry.retransmit_at = now + INITIAL_RETRANSMISSION_TIMEOUT * 2u32.pow(entry.retransmissions);
+            due.push(name.clone());
+        }
+        due
+    }
+
+    /// Removes the entry for `name` and returns the interfaces waiting for it.
+    pub fn take_interest(&mut self, name: &str) -> Option<Vec<String>> {
+        self.remove_entry(name)
+            .filter(|entry| self.age(entry) < DEFAULT_INTEREST_LIFETIME)
+            .map(|entry| entry.interfaces)
+    }
+
+    /// Names of unexpired pending interests starting with `prefix`.
+    pub fn pending_names(&self, prefix: &str) -> Vec<String> {
+        self.entries.iter()
+            .filter(|(name, entry)| name.starts_with(prefix) && self.age(entry) < DEFAULT_INTEREST_LIFETIME)
+            .map(|(name, _)| name.clone())
+            .collect()
     }
 
     pub fn remove_interest(&mut self, name: &str) {
-        self.entries.remove(name);
+        self.remove_entry(name);
     }
 
     pub fn has_pending_interest(&self, name: &str) -> bool {
@@ -55,21 +219,79 @@ impl PendingInterestTable {
     }
 
     pub fn clear_expired(&mut self) {
-        self.entries.retain(|_, entry| entry.timestamp.elapsed() < DEFAULT_INTEREST_LIFETIME);
+        for name in self.expired_names() {
+            self.remove_entry(&name);
+        }
     }
 
     pub fn is_empty(&self) -> bool {
         self.entries.is_empty()
     }
+
+    /// The unexpired entries, to be restored after a restart.
+    pub fn snapshot(&self) -> Vec<PitRecord> {
+        self.entries.iter()
+            .filter(|(_, entry)| self.age(entry) < DEFAULT_INTEREST_LIFETIME)
+            .map(|(name, entry)| PitRecord {
+                name: name.clone(),
+                interfaces: entry.interfaces.clone(),
+                age_ms: self.age(entry).as_millis() as u64,
+                nonces: entry.nonces.clone(),
+            })
+            .collect()
+    }
+
+    /// Adds the entries of a snapshot that have not expired since, with
+    /// this node's own interests due for retransmission at once. Returns the
+    /// number restored.
+    pub fn restore(&mut self, records: Vec<PitRecord>) -> usize {
+        let now = self.clock.instant();
+        let mut restored = 0;
+        for record in records {
+            let age = Duration::from_millis(record.age_ms);
+            let timestamp = match now.checked_sub(age) {
+                Some(timestamp) if age < DEFAULT_INTEREST_LIFETIME => timestamp,
+                _ => continue,
+            };
+            self.entries.insert(record.name, PitEntry {
+                interfaces: record.interfaces,
+                timestamp,
+                retransmissions: 0,
+                retransmit_at: now,
+                out_hops: Vec::new(),
+                nonces: record.nonces,
+            });
+            restored += 1;
+        }
+        restored
+    }
+
+    fn expired_names(&self) -> Vec<String> {
+        self.entries.iter()
+            .filter(|(_, entry)| self.age(entry) >= DEFAULT_INTEREST_LIFETIME)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Removes an entry, moving its nonces to the dead nonce list.
+    fn remove_entry(&mut self, name: &str) -> Option<PitEntry> {
+        let entry = self.entries.remove(name)?;
+        for (nonce, _) in &entry.nonces {
+            self.dead_nonces.add(name, *nonce);
+        }
+        Some(entry)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
 
     #[test]
     fn test_pending_interest_table() {
-        let mut pit = PendingInterestTable::new();
+        let clock = MockClock::new();
+        let mut pit = PendingInterestTable::new().with_clock(clock.clone().into());
         
         pit.add_interest("test".to_string(), "interface1");
         assert!(pit.has_pending_interest("test"));
@@ -84,8 +306,67 @@ mod tests {
         assert!(!pit.has_pending_interest("test"));
 
         pit.add_interest("test_expired".to_string(), "interface1");
-        std::thread::sleep(Duration::from_secs(5));
+        clock.advance(Duration::from_secs(5));
         pit.clear_expired();
         assert!(!pit.has_pending_interest("test_expired"));
     }
+
+    #[test]
+    fn test_interest_aggregation() {
+        let mut pit = PendingInterestTable::new();
+        assert!(pit.add_interest("/coopX/docs".to_string(), "peer1"));
+        assert!(!pit.add_interest("/coopX/docs".to_string(), "peer2"));
+        // The same face asking again is a retransmission and goes upstream again
+        assert!(pit.add_interest("/coopX/docs".to_string(), "peer1"));
+        assert_eq!(pit.pending_names("/coopX"), vec!["/coopX/docs".to_string()]);
+
+        assert_eq!(pit.take_interest("/coopX/docs"), Some(vec!["peer1".to_string(), "peer2".to_string()]));
+        assert!(pit.take_interest("/coopX/docs").is_none());
+    }
+
+    #[test]
+    fn test_retransmission_backoff() {
+        let mut pit = PendingInterestTable::new();
+        pit.add_interest("/coopX/mine".to_string(), LOCAL_FACE);
+        pit.add_interest("/coopX/theirs".to_string(), "peer1");
+        let start = Instant::now();
+
+        assert!(pit.due_retransmissions(start).is_empty());
+        let mut attempts = Vec::new();
+        let mut now = start;
+        while now < start + DEFAULT_INTEREST_LIFETIME {
+            if !pit.due_retransmissions(now).is_empty() {
+                attempts.push(now.duration_since(start).as_millis() / 100);
+            }
+            now += Duration::from_millis(100);
+        }
+        // Only our own interest is retried, with the wait doubling each time
+        assert_eq!(attempts, vec![5, 15, 35]);
+    }
+
+    #[test]
+    fn test_remove_interface() {
+        let mut pit = PendingInterestTable::new();
+        pit.add_interest("/coopX/docs".to_string(), "peer1");
+        pit.add_interest("/coopX/docs".to_string(), "peer2");
+        pit.remove_interface("/coopX/docs", "peer1");
+        assert_eq!(pit.get_incoming_interfaces("/coopX/docs"), Some(vec!["peer2".to_string()]));
+        pit.remove_interface("/coopX/docs", "peer2");
+        assert!(!pit.has_pending_interest("/coopX/docs"));
+    }
+
+    #[test]
+    fn test_looping_interest_detected() {
+        let mut pit = PendingInterestTable::new();
+        pit.add_interest("/coopX/docs".to_string(), "peer1");
+        pit.record_nonce("/coopX/docs", 42, "peer1");
+        assert!(!pit.is_looping("/coopX/docs", 42, "peer1"), "retransmission on the same face");
+        assert!(pit.is_looping("/coopX/docs", 42, "peer2"));
+        assert!(!pit.is_looping("/coopX/docs", 43, "peer2"));
+
+        // Still recognised after the entry is satisfied
+        pit.take_interest("/coopX/docs");
+        assert!(pit.is_looping("/coopX/docs", 42, "peer3"));
+        assert!(!pit.is_looping("/coopX/docs", 44, "peer3"));
+    }
 }
@@ -0,0 +1,120 @@
+// src/node/storage.rs
+use std::path::Path;
+use crate::blockchain::Blockchain;
+use crate::error::Result;
+use super::fib::{FibSnapshot, ForwardingInformationBase};
+use super::pending_interest_table::{PendingInterestTable, PitRecord};
+
+const TABLES_TREE: &str = "tables";
+const PIT_KEY: &str = "pit";
+const FIB_KEY: &str = "fib";
+const CHAIN_KEY: &str = "chain";
+
+/// The database a node keeps its state in across restarts: the disk tier of
+/// the content store and, when saved, the PIT, the FIB and the chain.
+/// Handles are cheap to clone and share the database.
+#[derive(Clone)]
+pub struct NodeStorage {
+    db: sled::Db,
+}
+
+impl NodeStorage {
+    /// Opens the database in the directory at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(NodeStorage { db: sled::open(path)? })
+    }
+
+    /// A database deleted once the last handle is dropped.
+    pub fn temporary() -> Result<Self> {
+        Ok(NodeStorage { db: sled::Config::new().temporary(true).open()? })
+    }
+
+    pub(crate) fn tree(&self, name: &str) -> Result<sled::Tree> {
+        Ok(self.db.open_tree(name)?)
+    }
+
+    pub fn save_pit(&self, pit: &PendingInterestTable) -> Result<()> {
+        self.save(PIT_KEY, &pit.snapshot())
+    }
+
+    /// Restores the saved PIT entries that have not expired since into
+    /// `pit`, returning how many there were.
+    pub fn load_pit(&self, pit: &mut PendingInterestTable) -> Result<usize> {
+        Ok(self.load::<Vec<PitRecord>>(PIT_KEY)?.map_or(0, |records| pit.restore(records)))
+    }
+
+    pub fn save_fib(&self, fib: &ForwardingInformationBase) -> Result<()> {
+        self.save(FIB_KEY, &fib.snapshot())
+    }
+
+    /// Restores the saved FIB entries into `fib`, returning how many there were.
+    pub fn load_fib(&self, fib: &mut ForwardingInformationBase) -> Result<usize> {
+        Ok(self.load::<FibSnapshot>(FIB_KEY)?.map_or(0, |snapshot| fib.restore(snapshot)))
+    }
+
+    pub fn save_chain(&self, blockchain: &Blockchain) -> Result<()> {
+        self.save(CHAIN_KEY, blockchain)
+    }
+
+    /// The chain saved last, if any; see `Blockchain::load`.
+    pub fn load_chain(&self) -> Result<Option<Blockchain>> {
+        match self.tree(TABLES_TREE)?.get(CHAIN_KEY)? {
+            Some(bytes) => Ok(Some(Blockchain::load(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes everything saved so far to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn save<T: serde::Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let bytes = serde_json::to_vec(value).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.tree(TABLES_TREE)?.insert(key, bytes)?;
+        Ok(())
+    }
+
+    fn load<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.tree(TABLES_TREE)?.get(key)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::LOCAL_FACE;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_forwarding_tables_restored() {
+        let storage = NodeStorage::temporary().unwrap();
+        let next_hop: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let mut fib = ForwardingInformationBase::new();
+        fib.add_entry("/coopX".to_string(), next_hop);
+        fib.set_strategy("/coopX/governance".to_string(), crate::node::strategy::by_name("multicast").unwrap());
+        let mut pit = PendingInterestTable::new();
+        pit.add_interest("/coopX/docs".to_string(), LOCAL_FACE);
+        pit.record_nonce("/coopX/docs", 42, LOCAL_FACE);
+        storage.save_fib(&fib).unwrap();
+        storage.save_pit(&pit).unwrap();
+
+        let mut restored_fib = ForwardingInformationBase::new();
+        assert_eq!(storage.load_fib(&mut restored_fib).unwrap(), 1);
+        assert_eq!(restored_fib.get_next_hops("/coopX"), Some(&vec![next_hop]));
+        assert_eq!(restored_fib.strategy_for("/coopX/governance/vote").name(), "multicast");
+
+        let mut restored_pit = PendingInterestTable::new();
+        assert_eq!(storage.load_pit(&mut restored_pit).unwrap(), 1);
+        assert!(restored_pit.is_looping("/coopX/docs", 42, "peer1"));
+        // Sent again at once, the next hops it went to being unknown
+        assert_eq!(restored_pit.due_retransmissions(std::time::Instant::now()), vec!["/coopX/docs".to_string()]);
+
+        assert_eq!(NodeStorage::temporary().unwrap().load_pit(&mut PendingInterestTable::new()).unwrap(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Sends an occasional interest to an alternative next hop every this many
+/// interests, so the probe strategy notices when another path gets better.
+pub const DEFAULT_PROBE_INTERVAL: usize = 8;
+
+/// What the forwarder has observed about a next hop.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NextHopStats {
+    /// Smoothed round-trip time of satisfied interests.
+    pub srtt: Option<Duration>,
+    /// Nacks and timeouts since the last satisfied interest.
+    pub failures: u32,
+}
+
+/// Per next hop measurements shared by all strategies.
+#[derive(Debug, Default)]
+pub struct Measurements {
+    hops: HashMap<SocketAddr, NextHopStats>,
+}
+
+impl Measurements {
+    pub fn get(&self, next_hop: &SocketAddr) -> NextHopStats {
+        self.hops.get(next_hop).copied().unwrap_or_default()
+    }
+
+    pub fn record_rtt(&mut self, next_hop: SocketAddr, rtt: Duration) {
+        let stats = self.hops.entry(next_hop).or_default();
+        stats.srtt = Some(match stats.srtt {
+            Some(srtt) => srtt.mul_f64(0.875) + rtt.mul_f64(0.125),
+            None => rtt,
+        });
+        stats.failures = 0;
+    }
+
+    pub fn record_failure(&mut self, next_hop: SocketAddr) {
+        self.hops.entry(next_hop).or_default().failures += 1;
+    }
+
+    /// `next_hops` ordered from most to least promising: fewest recent
+    /// failures, then lowest RTT, unmeasured hops last. Ties keep FIB order.
+    pub fn ranked(&self, next_hops: &[SocketAddr]) -> Vec<SocketAddr> {
+        let mut ranked = next_hops.to_vec();
+        ranked.sort_by_key(|hop| {
+            let stats = self.get(hop);
+            (stats.failures, stats.srtt.unwrap_or(Duration::MAX))
+        });
+        ranked
+    }
+}
+
+/// Decides which of a FIB entry's next hops an interest is sent to. Chosen
+/// per name prefix, so each namespace can be forwarded the way it needs.
+pub trait ForwardingStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Picks the hops to send an interest to, out of the candidates in FIB
+    /// order. Returning none leaves the interest unforwarded.
+    fn select(&self, next_hops: &[SocketAddr], measurements: &Measurements) -> Vec<SocketAddr>;
+}
+
+impl fmt::Debug for dyn ForwardingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Sends each interest to the single best performing next hop.
+#[derive(Debug, Default)]
+pub struct BestRoute;
+
+impl ForwardingStrategy for BestRoute {
+    fn name(&self) -> &'static str {
+        "best-route"
+    }
+
+    fn select(&self, next_hops: &[SocketAddr], measurements: &Measurements) -> Vec<SocketAddr> {
+        measurements.ranked(next_hops).into_iter().take(1).collect()
+    }
+}
+
+/// Sends each interest to every next hop, e.g. for announcements that all
+/// cooperatives must see.
+#[derive(Debug, Default)]
+pub struct Multicast;
+
+impl ForwardingStrategy for Multicast {
+    fn name(&self) -> &'static str {
+        "multicast"
+    }
+
+    fn select(&self, next_hops: &[SocketAddr], _measurements: &Measurements) -> Vec<SocketAddr> {
+        next_hops.to_vec()
+    }
+}
+
+/// Spreads interests over the next hops in turn, skipping hops that are
+/// currently failing while any other is healthy.
+#[derive(Debug, Default)]
+pub struct LoadBalancing {
+    turn: AtomicUsize,
+}
+
+impl ForwardingStrategy for LoadBalancing {
+    fn name(&self) -> &'static str {
+        "load-balancing"
+    }
+
+    fn select(&self, next_hops: &[SocketAddr], measurements: &Measurements) -> Vec<SocketAddr> {
+        let healthy: Vec<SocketAddr> = next_hops.iter().copied().filter(|hop| measurements.get(hop).failures == 0).collect();
+        let candidates = if healthy.is_empty() { next_hops } else { &healthy[..] };
+        if candidates.is_empty() {
+            return vec![];
+        }
+        let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+        vec![candidates[turn % candidates.len()]]
+    }
+}
+
+/// Best route that every `interval` interests also sends a copy to another
+/// next hop, taking turns, so their measurements stay current.
+#[derive(Debug)]
+pub struct Probe {
+    interval: usize,
+    count: AtomicUsize,
+}
+
+impl Probe {
+    pub fn new(interval: usize) -> Self {
+        Probe { interval: interval.max(1), count: AtomicUsize::new(0) }
+    }
+}
+
+impl Default for Probe {
+    fn default() -> Self {
+        Probe::new(DEFAULT_PROBE_INTERVAL)
+    }
+}
+
+impl ForwardingStrategy for Probe {
+    fn name(&self) -> &'static str {
+        "probe"
+    }
+
+    fn select(&self, next_hops: &[SocketAddr], measurements: &Measurements) -> Vec<SocketAddr> {
+        let ranked = measurements.ranked(next_hops);
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut selected: Vec<SocketAddr> = ranked.iter().copied().take(1).collect();
+        if ranked.len() > 1 && count.is_multiple_of(self.interval) {
+            let alternatives = &ranked[1..];
+            selected.push(alternatives[(count / self.interval - 1) % alternatives.len()]);
+        }
+        selected
+    }
+}
+
+/// Looks a strategy up by the name operators use to configure it.
+pub fn by_name(name: &str) -> Option<Arc<dyn ForwardingStrategy>> {
+    match name {
+        "best-route" => Some(Arc::new(BestRoute)),
+        "multicast" => Some(Arc::new(Multicast)),
+        "load-balancing" => Some(Arc::new(LoadBalancing::default())),
+        "probe" => Some(Arc::new(Probe::default())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hops() -> Vec<SocketAddr> {
+        vec!["127.0.0.1:9001".parse().unwrap(), "127.0.0.1:9002".parse().unwrap(), "127.0.0.1:9003".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_best_route_follows_measurements() {
+        let hops = hops();
+        let mut measurements = Measurements::default();
+        assert_eq!(BestRoute.select(&hops, &measurements), vec![hops[0]]);
+
+        measurements.record_rtt(hops[0], Duration::from_millis(80));
+        measurements.record_rtt(hops[1], Duration::from_millis(20));
+        assert_eq!(BestRoute.select(&hops, &measurements), vec![hops[1]]);
+
+        measurements.record_failure(hops[1]);
+        assert_eq!(BestRoute.select(&hops, &measurements), vec![hops[0]]);
+        assert_eq!(Multicast.select(&hops, &measurements), hops);
+    }
+
+    #[test]
+    fn test_load_balancing_rotates_over_healthy_hops() {
+        let hops = hops();
+        let mut measurements = Measurements::default();
+        measurements.record_failure(hops[2]);
+        let strategy = LoadBalancing::default();
+        let picks: Vec<SocketAddr> = (0..4).flat_map(|_| strategy.select(&hops, &measurements)).collect();
+        assert_eq!(picks, vec![hops[0], hops[1], hops[0], hops[1]]);
+    }
+
+    #[test]
+    fn test_probe_tries_alternatives_periodically() {
+        let hops = hops();
+        let measurements = Measurements::default();
+        let strategy = Probe::new(2);
+        assert_eq!(strategy.select(&hops, &measurements), vec![hops[0]]);
+        assert_eq!(strategy.select(&hops, &measurements), vec![hops[0], hops[1]]);
+        assert_eq!(strategy.select(&hops, &measurements), vec![hops[0]]);
+        assert_eq!(strategy.select(&hops, &measurements), vec![hops[0], hops[2]]);
+        assert_eq!(by_name("multicast").unwrap().name(), "multicast");
+        assert!(by_name("flood").is_none());
+    }
+}
@@ -0,0 +1,109 @@
+// src/node/worker.rs
+use crate::error::{Error, Result};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info_span};
+
+/// Jobs a worker holds before callers have to wait for it.
+pub const WORKER_QUEUE_SIZE: usize = 100;
+
+type Job<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+/// Owns a piece of node state on a thread of its own and runs jobs sent to
+/// it one at a time, so that slow work on one subsystem, e.g. a long VM run,
+/// never holds a lock another subsystem needs. The job queue is bounded:
+/// once it is full `call` waits for room, and `try_submit` refuses the job.
+///
+/// The thread stops once every handle is dropped.
+pub struct Worker<S> {
+    name: String,
+    jobs: mpsc::Sender<Job<S>>,
+}
+
+impl<S: Send + 'static> Worker<S> {
+    pub fn spawn(name: &str, state: S) -> Self {
+        Self::with_capacity(name, state, WORKER_QUEUE_SIZE)
+    }
+
+    pub fn with_capacity(name: &str, mut state: S, capacity: usize) -> Self {
+        let (jobs, mut receiver) = mpsc::channel::<Job<S>>(capacity);
+        let span = info_span!("worker", name);
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let _span = span.entered();
+                while let Some(job) = receiver.blocking_recv() {
+                    job(&mut state);
+                }
+                debug!("Worker stopped");
+            })
+            .expect("failed to spawn worker thread");
+        Worker { name: name.to_string(), jobs }
+    }
+
+    /// Runs `job` on the worker and returns its result, waiting for room in
+    /// the queue first.
+    pub async fn call<R, F>(&self, job: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> R + Send + 'static,
+    {
+        let (reply, response) = oneshot::channel();
+        let job: Job<S> = Box::new(move |state| {
+            let _ = reply.send(job(state));
+        });
+        self.jobs.send(job).await.map_err(|_| self.stopped())?;
+        response.await.map_err(|_| self.stopped())
+    }
+
+    /// `call` for callers outside of async code. Blocks the calling thread.
+    pub fn call_blocking<R, F>(&self, job: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut S) -> R + Send + 'static,
+    {
+        futures::executor::block_on(self.call(job))
+    }
+
+    /// Queues `job` without waiting for it to run, failing at once if the
+    /// worker is too busy to take it.
+    pub fn try_submit<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce(&mut S) + Send + 'static,
+    {
+        self.jobs.try_send(Box::new(job)).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => Error::Unavailable(format!("{} worker is busy", self.name)),
+            mpsc::error::TrySendError::Closed(_) => self.stopped(),
+        })
+    }
+
+    fn stopped(&self) -> Error {
+        Error::Unavailable(format!("{} worker has stopped", self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc as std_mpsc;
+
+    #[test]
+    fn test_worker_applies_backpressure() {
+        let worker = Worker::with_capacity("counter", 0u32, 1);
+        let (release, blocked) = std_mpsc::channel::<()>();
+        worker.try_submit(move |count| {
+            blocked.recv().unwrap();
+            *count += 1;
+        }).unwrap();
+
+        // The first job may still be queued; either way the queue fills up
+        let mut accepted = 0;
+        while worker.try_submit(|count| *count += 1).is_ok() {
+            accepted += 1;
+        }
+        assert!(accepted <= 1);
+        assert!(matches!(worker.try_submit(|_| ()), Err(Error::Unavailable(_))));
+
+        release.send(()).unwrap();
+        assert_eq!(worker.call_blocking(|count| *count).unwrap(), 1 + accepted);
+    }
+}
@@ -0,0 +1,252 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use tracing::{debug, info};
+use crate::clock::SharedClock;
+
+/// The kinds of work a member can be credited for.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContributionCategory {
+    Validation,
+    Storage,
+    Compute,
+    ContentServing,
+    Governance,
+    Community,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReputationConfig {
+    /// Reputation every member starts with and decays towards.
+    pub baseline: f64,
+    /// Fraction of earned reputation lost per day of inactivity.
+    pub decay_rate: f64,
+    /// Fraction of outstanding penalties forgiven per day.
+    pub rehabilitation_rate: f64,
+    pub min_reputation: f64,
+    pub max_reputation: f64,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig {
+            baseline: 1.0,
+            decay_rate: 0.01,
+            rehabilitation_rate: 0.05,
+            min_reputation: 0.0,
+            max_reputation: 100.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReputationRecord {
+    pub contributions: HashMap<ContributionCategory, f64>,
+    pub penalties: f64,
+    pub last_updated: DateTime<Utc>,
+}
+
+impl ReputationRecord {
+    fn new(now: DateTime<Utc>) -> Self {
+        ReputationRecord {
+            contributions: HashMap::new(),
+            penalties: 0.0,
+            last_updated: now,
+        }
+    }
+}
+
+/// Single source of truth for member reputation, shared by consensus voting,
+/// governance vote weighting and peer scoring.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReputationStore {
+    records: HashMap<String, ReputationRecord>,
+    config: ReputationConfig,
+    #[serde(skip)]
+    clock: SharedClock,
+}
+
+impl ReputationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: ReputationConfig) -> Self {
+        ReputationStore {
+            records: HashMap::new(),
+            config,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Dates updates, and measures the inactivity `decay` punishes, by `clock`.
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    pub fn config(&self) -> &ReputationConfig {
+        &self.config
+    }
+
+    pub fn register(&mut self, member_id: &str) {
+        self.records
+            .entry(member_id.to_string())
+            .or_insert_with(|| ReputationRecord::new(self.clock.now()));
+    }
+
+    pub fn is_registered(&self, member_id: &str) -> bool {
+        self.records.contains_key(member_id)
+    }
+
+    pub fn remove(&mut self, member_id: &str) {
+        self.records.remove(member_id);
+    }
+
+    pub fn record_contribution(&mut self, member_id: &str, category: ContributionCategory, amount: f64) -> Result<(), String> {
+        if amount < 0.0 {
+            return Err("Contribution amount must be non-negative; use penalize for losses".to_string());
+        }
+        let record = self.records.get_mut(member_id)
+            .ok_or_else(|| format!("Member not found: {}", member_id))?;
+        *record.contributions.entry(category).or_insert(0.0) += amount;
+        record.last_updated = self.clock.now();
+        debug!("Recorded {:?} contribution of {} for {}", category, amount, member_id);
+        Ok(())
+    }
+
+    pub fn penalize(&mut self, member_id: &str, amount: f64, reason: &str) -> Result<(), String> {
+        let record = self.records.get_mut(member_id)
+            .ok_or_else(|| format!("Member not found: {}", member_id))?;
+        record.penalties += amount.abs();
+        record.last_updated = self.clock.now();
+        info!("Penalized {} by {}: {}", member_id, amount.abs(), reason);
+        Ok(())
+    }
+
+    /// Applies a signed adjustment, crediting positive changes to `category`
+    /// and treating negative changes as penalties.
+    pub fn adjust(&mut self, member_id: &str, category: ContributionCategory, delta: f64) -> Result<(), String> {
+        if delta >= 0.0 {
+            self.record_contribution(member_id, category, delta)
+        } else {
+            self.penalize(member_id, delta, "Reputation adjustment")
+        }
+    }
+
+    pub fn get_reputation(&self, member_id: &str) -> Option<f64> {
+        self.records.get(member_id).map(|record| {
+            let earned: f64 = record.contributions.values().sum();
+            (self.config.baseline + earned - record.penalties)
+                .clamp(self.config.min_reputation, self.config.max_reputation)
+        })
+    }
+
+    pub fn get_contribution(&self, member_id: &str, category: ContributionCategory) -> f64 {
+        self.records.get(member_id)
+            .and_then(|record| record.contributions.get(&category).copied())
+            .unwrap_or(0.0)
+    }
+
+    /// Decays earned reputation and forgives penalties in proportion to the
+    /// time elapsed since each record was last updated.
+    pub fn apply_decay(&mut self, now: DateTime<Utc>) {
+        for record in self.records.values_mut() {
+            let elapsed_days = now.signed_duration_since(record.last_updated).num_seconds() as f64 / 86_400.0;
+            if elapsed_days <= 0.0 {
+                continue;
+            }
+            let decay = (1.0 - self.config.decay_rate).powf(elapsed_days);
+            let rehabilitation = (1.0 - self.config.rehabilitation_rate).powf(elapsed_days);
+            for score in record.contributions.values_mut() {
+                *score *= decay;
+            }
+            record.penalties *= rehabilitation;
+            record.last_updated = now;
+        }
+    }
+
+    /// `apply_decay` up to the present time of the store's clock.
+    pub fn decay(&mut self) {
+        let now = self.clock.now();
+        self.apply_decay(now);
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &String> {
+        self.records.keys()
+    }
+
+    /// Weight of a member's vote on a block.
+    pub fn consensus_weight(&self, member_id: &str) -> f64 {
+        self.get_reputation(member_id).unwrap_or(0.0)
+    }
+
+    /// Weight of a member's vote on a governance proposal. Square-root scaling
+    /// keeps highly reputed members from dominating democratic decisions.
+    pub fn governance_weight(&self, member_id: &str) -> f64 {
+        self.get_reputation(member_id).map_or(0.0, f64::sqrt)
+    }
+
+    /// Peer score in `[0, 1]` relative to the configured maximum.
+    pub fn peer_score(&self, member_id: &str) -> f64 {
+        self.get_reputation(member_id)
+            .map_or(0.0, |reputation| reputation / self.config.max_reputation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::Duration;
+
+    #[test]
+    fn test_contributions_and_penalties() {
+        let mut store = ReputationStore::new();
+        store.register("Alice");
+        assert_eq!(store.get_reputation("Alice"), Some(1.0));
+
+        store.record_contribution("Alice", ContributionCategory::Validation, 2.0).unwrap();
+        store.record_contribution("Alice", ContributionCategory::Storage, 1.0).unwrap();
+        assert_eq!(store.get_reputation("Alice"), Some(4.0));
+        assert_eq!(store.get_contribution("Alice", ContributionCategory::Storage), 1.0);
+
+        store.penalize("Alice", 10.0, "Invalid block").unwrap();
+        assert_eq!(store.get_reputation("Alice"), Some(0.0));
+        assert!(store.record_contribution("Bob", ContributionCategory::Compute, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_decay_and_rehabilitation() {
+        let clock = MockClock::new();
+        let mut store = ReputationStore::new().with_clock(clock.clone().into());
+        store.register("Alice");
+        store.register("Bob");
+        store.record_contribution("Alice", ContributionCategory::Validation, 10.0).unwrap();
+        store.penalize("Bob", 0.5, "Spam").unwrap();
+
+        clock.advance(Duration::days(30).to_std().unwrap());
+        store.decay();
+
+        let alice = store.get_reputation("Alice").unwrap();
+        assert!(alice < 11.0 && alice > 1.0);
+        let bob = store.get_reputation("Bob").unwrap();
+        assert!(bob > 0.5 && bob < 1.0);
+    }
+
+    #[test]
+    fn test_adapters() {
+        let mut store = ReputationStore::new();
+        store.register("Alice");
+        store.record_contribution("Alice", ContributionCategory::Governance, 8.0).unwrap();
+
+        assert_eq!(store.consensus_weight("Alice"), 9.0);
+        assert_eq!(store.governance_weight("Alice"), 3.0);
+        assert_eq!(store.peer_score("Alice"), 0.09);
+        assert_eq!(store.consensus_weight("Unknown"), 0.0);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::{debug, info, info_span};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::blockchain::Blockchain;
+use crate::consensus::PoCConsensus;
+use crate::error::{Error, Result};
+use super::ShardingError;
+
+/// Share of a shard's validator weight that must approve a shard header.
+pub const DEFAULT_SHARD_THRESHOLD: f64 = 0.66;
+const GENESIS_SEED: &str = "icn-beacon-genesis";
+
+/// What a shard's validators agreed on at some height, published through the
+/// beacon so other shards and the main chain can refer to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardHeader {
+    pub shard_id: u64,
+    pub epoch: u64,
+    pub height: u64,
+    pub state_root: String,
+    pub block_hash: String,
+    pub proposer: String,
+    /// Validators of the shard that approved the header.
+    pub approvals: Vec<String>,
+}
+
+/// Coordinates the per-shard consensus instances. Each epoch the registered
+/// validators are shuffled with randomness derived from the previous epoch
+/// and dealt out over the shards, every shard getting its own `PoCConsensus`
+/// over its validators. Shard headers approved by enough of a shard's
+/// validators are accepted and exposed to the other shards, and their state
+/// roots can be anchored in the main chain.
+pub struct Beacon {
+    shard_count: u64,
+    threshold: f64,
+    epoch: u64,
+    randomness: String,
+    validators: BTreeSet<String>,
+    assignments: HashMap<u64, Vec<String>>,
+    consensus: HashMap<u64, PoCConsensus>,
+    headers: HashMap<u64, ShardHeader>,
+}
+
+impl Beacon {
+    pub fn new(shard_count: u64) -> Self {
+        Beacon {
+            shard_count,
+            threshold: DEFAULT_SHARD_THRESHOLD,
+            epoch: 0,
+            randomness: hex::encode(Sha256::digest(GENESIS_SEED.as_bytes())),
+            validators: BTreeSet::new(),
+            assignments: HashMap::new(),
+            consensus: HashMap::new(),
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn randomness(&self) -> &str {
+        &self.randomness
+    }
+
+    /// Adds a validator to the pool; it is assigned a shard from the next epoch.
+    pub fn register_validator(&mut self, validator_id: &str) {
+        self.validators.insert(validator_id.to_string());
+    }
+
+    pub fn remove_validator(&mut self, validator_id: &str) {
+        self.validators.remove(validator_id);
+    }
+
+    /// Starts the next epoch: derives its randomness from the previous
+    /// epoch's randomness and accepted shard headers, then reassigns every
+    /// validator to a shard.
+    pub fn advance_epoch(&mut self) {
+        let mut hasher = Sha256::new();
+        hasher.update(self.randomness.as_bytes());
+        hasher.update(self.epoch.to_le_bytes());
+        let mut shard_ids: Vec<&u64> = self.headers.keys().collect();
+        shard_ids.sort();
+        for shard_id in shard_ids {
+            hasher.update(self.headers[shard_id].block_hash.as_bytes());
+        }
+        self.randomness = hex::encode(hasher.finalize());
+        self.epoch += 1;
+        self.assign_validators();
+        info!("Beacon epoch {} started with {} validators over {} shards", self.epoch, self.validators.len(), self.shard_count);
+    }
+
+    pub fn shard_validators(&self, shard_id: u64) -> &[String] {
+        self.assignments.get(&shard_id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn shard_of_validator(&self, validator_id: &str) -> Option<u64> {
+        self.assignments.iter()
+            .find(|(_, validators)| validators.iter().any(|validator| validator == validator_id))
+            .map(|(shard_id, _)| *shard_id)
+    }
+
+    /// The consensus instance of a shard for the current epoch.
+    pub fn consensus(&self, shard_id: u64) -> Option<&PoCConsensus> {
+        self.consensus.get(&shard_id)
+    }
+
+    pub fn consensus_mut(&mut self, shard_id: u64) -> Option<&mut PoCConsensus> {
+        self.consensus.get_mut(&shard_id)
+    }
+
+    /// Accepts a header proposed by a validator of its shard in the current
+    /// epoch once validators holding at least the threshold share of the
+    /// shard's voting weight approve it.
+    pub fn submit_header(&mut self, header: ShardHeader) -> Result<()> {
+        let _span = info_span!("shard_header", shard = header.shard_id, height = header.height, proposer = %header.proposer).entered();
+        if header.epoch != self.epoch {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("Header for epoch {} submitted in epoch {}", header.epoch, self.epoch))));
+        }
+        let validators = self.shard_validators(header.shard_id);
+        if !validators.contains(&header.proposer) {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("{} is not a validator of shard {}", header.proposer, header.shard_id))));
+        }
+        if let Some(previous) = self.headers.get(&header.shard_id) {
+            if header.height <= previous.height {
+                return Err(Error::ShardingError(ShardingError::Rejected(format!("Shard {} header at height {} is not newer than {}", header.shard_id, header.height, previous.height))));
+            }
+        }
+        let consensus = self.consensus.get(&header.shard_id)
+            .ok_or_else(|| Error::ShardingError(ShardingError::Rejected(format!("No consensus for shard {}", header.shard_id))))?;
+        let tally = consensus.tally_approvals(&header.approvals);
+        if !consensus.is_approved(&tally) {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("Shard {} header lacks approvals: {:.2} of {:.2}", header.shard_id, tally.approve, tally.total))));
+        }
+        debug!("Accepted header of shard {} at height {}", header.shard_id, header.height);
+        self.headers.insert(header.shard_id, header);
+        Ok(())
+    }
+
+    /// The latest accepted header of a shard, as seen by the other shards.
+    pub fn shard_header(&self, shard_id: u64) -> Option<&ShardHeader> {
+        self.headers.get(&shard_id)
+    }
+
+    /// Queues the state roots of the latest accepted shard headers to be
+    /// anchored in the next main chain block.
+    pub fn anchor(&self, blockchain: &mut Blockchain) {
+        let roots: BTreeMap<u64, String> = self.headers.iter()
+            .map(|(shard_id, header)| (*shard_id, header.state_root.clone()))
+            .collect();
+        blockchain.anchor_shard_roots(roots);
+    }
+
+    fn assign_validators(&mut self) {
+        let mut shuffled: Vec<(String, String)> = self.validators.iter()
+            .map(|validator| {
+                let mut hasher = Sha256::new();
+                hasher.update(self.randomness.as_bytes());
+                hasher.update(validator.as_bytes());
+                (hex::encode(hasher.finalize()), validator.clone())
+            })
+            .collect();
+        shuffled.sort();
+
+        self.assignments.clear();
+        self.consensus.clear();
+        for shard_id in 0..self.shard_count {
+            self.assignments.insert(shard_id, Vec::new());
+            self.consensus.insert(shard_id, PoCConsensus::new(self.threshold, self.threshold));
+        }
+        if self.shard_count == 0 {
+            return;
+        }
+        for (position, (_, validator)) in shuffled.into_iter().enumerate() {
+            let shard_id = position as u64 % self.shard_count;
+            if let Some(consensus) = self.consensus.get_mut(&shard_id) {
+                consensus.add_member(validator.clone(), true);
+            }
+            self.assignments.entry(shard_id).or_default().push(validator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon_with(validators: usize) -> Beacon {
+        let mut beacon = Beacon::new(2);
+        for i in 0..validators {
+            beacon.register_validator(&format!("validator{}", i));
+        }
+        beacon.advance_epoch();
+        beacon
+    }
+
+    fn header(beacon: &Beacon, shard_id: u64, height: u64, approvals: usize) -> ShardHeader {
+        let validators = beacon.shard_validators(shard_id);
+        ShardHeader {
+            shard_id,
+            epoch: beacon.epoch(),
+            height,
+            state_root: format!("root-{}-{}", shard_id, height),
+            block_hash: format!("block-{}-{}", shard_id, height),
+            proposer: validators[0].clone(),
+            approvals: validators[..approvals].to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_validators_reassigned_each_epoch() {
+        let mut beacon = beacon_with(8);
+        assert_eq!(beacon.shard_validators(0).len(), 4);
+        assert_eq!(beacon.shard_validators(1).len(), 4);
+        assert_eq!(beacon.consensus(0).unwrap().members.len(), 4);
+
+        let first: Vec<String> = beacon.shard_validators(0).to_vec();
+        let randomness = beacon.randomness().to_string();
+        let mut same = beacon_with(8);
+        assert_eq!(same.shard_validators(0), first.as_slice(), "assignment is deterministic");
+
+        let mut reshuffled = false;
+        for _ in 0..5 {
+            beacon.advance_epoch();
+            same.advance_epoch();
+            assert_eq!(beacon.shard_validators(1), same.shard_validators(1));
+            reshuffled |= beacon.shard_validators(0) != first.as_slice();
+        }
+        assert_ne!(beacon.randomness(), randomness);
+        assert!(reshuffled);
+    }
+
+    #[test]
+    fn test_shard_header_needs_approvals() {
+        let mut beacon = beacon_with(6);
+        assert!(beacon.submit_header(header(&beacon, 0, 1, 1)).is_err());
+        beacon.submit_header(header(&beacon, 0, 1, 2)).unwrap();
+        assert_eq!(beacon.shard_header(0).unwrap().state_root, "root-0-1");
+        assert!(beacon.submit_header(header(&beacon, 0, 1, 3)).is_err(), "height must advance");
+
+        let mut outsider = header(&beacon, 1, 1, 3);
+        outsider.proposer = beacon.shard_validators(0)[0].clone();
+        assert!(beacon.submit_header(outsider).is_err());
+
+        let stale = header(&beacon, 1, 1, 3);
+        beacon.advance_epoch();
+        assert!(beacon.submit_header(stale).is_err());
+    }
+
+    #[test]
+    fn test_shard_roots_anchored_in_main_chain() {
+        let mut beacon = beacon_with(4);
+        beacon.submit_header(header(&beacon, 0, 3, 2)).unwrap();
+        beacon.submit_header(header(&beacon, 1, 5, 2)).unwrap();
+
+        let mut blockchain = Blockchain::new();
+        beacon.anchor(&mut blockchain);
+        blockchain.create_block("proposer".to_string()).unwrap();
+        assert_eq!(blockchain.anchored_shard_root(1), Some((1, &"root-1-5".to_string())));
+        assert!(blockchain.validate_chain().is_ok());
+
+        blockchain.chain[1].shard_roots.insert(0, "forged".to_string());
+        assert!(blockchain.validate_chain().is_err());
+    }
+}
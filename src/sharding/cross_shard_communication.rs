@@ -1,46 +1,43 @@
 use crate::blockchain::Transaction;
-use crate::sharding::ShardingManager;
-use crate::currency::CurrencyType;
+use crate::sharding::{CrossShardTransactionManager, CrossShardTransactionStatus, ShardingError, ShardingManager};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use uuid::Uuid;
+use tracing::error;
 use crate::error::{Error, Result};
 
-#[derive(Clone, Debug)]
-pub struct CrossShardTransaction {
-    pub transaction: Transaction,
-    pub from_shard: u64,
-    pub to_shard: u64,
-    pub status: CrossShardTransactionStatus,
-}
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum CrossShardTransactionStatus {
-    Initiated,
-    LockAcquired,
-    Committed,
-    Failed(String),
-}
-
+/// Runs cross-shard transfers in the background, one worker per source
+/// shard, using the two-phase commit of `CrossShardTransactionManager`.
 pub struct CrossShardCommunicator {
     sharding_manager: Arc<Mutex<ShardingManager>>,
-    pending_transactions: HashMap<String, CrossShardTransaction>,
-    tx_channels: HashMap<u64, mpsc::Sender<CrossShardTransaction>>,
+    coordinator: Arc<Mutex<CrossShardTransactionManager>>,
+    tx_channels: HashMap<u64, mpsc::Sender<String>>,
 }
 
 impl CrossShardCommunicator {
     pub fn new(sharding_manager: Arc<Mutex<ShardingManager>>) -> Self {
+        Self::with_coordinator(sharding_manager, CrossShardTransactionManager::new())
+    }
+
+    /// Uses `coordinator`, e.g. one with a persisted log, after settling the
+    /// transfers it left unfinished.
+    pub fn with_coordinator(sharding_manager: Arc<Mutex<ShardingManager>>, mut coordinator: CrossShardTransactionManager) -> Self {
+        if let Err(e) = coordinator.recover(&sharding_manager.lock().unwrap()) {
+            error!("Failed to recover cross-shard transactions: {}", e);
+        }
+        let coordinator = Arc::new(Mutex::new(coordinator));
         let mut tx_channels = HashMap::new();
         let shard_count = sharding_manager.lock().unwrap().get_shard_count();
         for i in 0..shard_count {
-            let (tx, mut rx) = mpsc::channel(100);
+            let (tx, mut rx) = mpsc::channel::<String>(100);
             tx_channels.insert(i, tx);
             let sm = Arc::clone(&sharding_manager);
+            let coordinator = Arc::clone(&coordinator);
             tokio::spawn(async move {
-                while let Some(transaction) = rx.recv().await {
-                    if let Err(e) = Self::process_transaction(sm.clone(), transaction).await {
-                        eprintln!("Error processing cross-shard transaction: {}", e);
+                while let Some(tx_id) = rx.recv().await {
+                    let sm = sm.lock().unwrap();
+                    if let Err(e) = coordinator.lock().unwrap().run(&sm, &tx_id) {
+                        error!("Failed to process cross-shard transaction {}: {}", tx_id, e);
                     }
                 }
             });
@@ -48,57 +45,42 @@ impl CrossShardCommunicator {
 
         CrossShardCommunicator {
             sharding_manager,
-            pending_transactions: HashMap::new(),
+            coordinator,
             tx_channels,
         }
     }
 
     pub async fn initiate_cross_shard_transaction(&mut self, transaction: Transaction) -> Result<String> {
-        let sharding_manager = self.sharding_manager.lock().unwrap();
-        let from_shard = sharding_manager.get_shard_for_address(&transaction.from);
-        let to_shard = sharding_manager.get_shard_for_address(&transaction.to);
-
-        if from_shard == to_shard {
-            return Err(Error::ShardingError("Not a cross-shard transaction".to_string()));
-        }
-
-        let cross_shard_tx = CrossShardTransaction {
-            transaction: transaction.clone(),
-            from_shard,
-            to_shard,
-            status: CrossShardTransactionStatus::Initiated,
+        let (from_shard, to_shard) = {
+            let sharding_manager = self.sharding_manager.lock().unwrap();
+            (sharding_manager.get_shard_for_address(&transaction.from), sharding_manager.get_shard_for_address(&transaction.to))
         };
+        let tx = self.tx_channels.get(&from_shard)
+            .ok_or_else(|| Error::ShardingError(ShardingError::Rejected(format!("Channel for shard {} not found", from_shard))))?;
 
-        let tx_id = Uuid::new_v4().to_string();
-        self.pending_transactions.insert(tx_id.clone(), cross_shard_tx.clone());
-
-        if let Some(tx) = self.tx_channels.get(&from_shard) {
-            tx.send(cross_shard_tx).await.map_err(|e| Error::ShardingError(e.to_string()))?;
-        } else {
-            return Err(Error::ShardingError(format!("Channel for shard {} not found", from_shard)));
-        }
-
+        let tx_id = self.coordinator.lock().unwrap().begin(transaction, from_shard, to_shard)?;
+        tx.send(tx_id.clone()).await.map_err(|e| Error::ShardingError(ShardingError::Rejected(e.to_string())))?;
         Ok(tx_id)
     }
 
-    async fn process_transaction(sharding_manager: Arc<Mutex<ShardingManager>>, mut transaction: CrossShardTransaction) -> Result<()> {
-        // Phase 1: Lock funds in the source shard
-        {
-            let mut sm = sharding_manager.lock().unwrap();
-            sm.transfer_between_shards(transaction.from_shard, transaction.to_shard, &transaction.transaction)?;
-        }
-        transaction.status = CrossShardTransactionStatus::Committed;
-        Ok(())
+    /// Aborts transfers stuck preparing past the timeout, refunding their
+    /// locked funds. Returns their ids.
+    pub fn abort_expired(&self) -> Result<Vec<String>> {
+        let sharding_manager = self.sharding_manager.lock().unwrap();
+        self.coordinator.lock().unwrap().abort_expired(&sharding_manager)
     }
 
     pub fn get_transaction_status(&self, tx_id: &str) -> Option<CrossShardTransactionStatus> {
-        self.pending_transactions.get(tx_id).map(|tx| tx.status.clone())
+        self.coordinator.lock().unwrap().status(tx_id)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::currency::CurrencyType;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
     use tokio;
 
     #[tokio::test]
@@ -113,13 +95,14 @@ mod tests {
             sm.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0).unwrap();
         }
 
-        let transaction = Transaction::new(
+        let mut transaction = Transaction::new(
             "Alice".to_string(),
             "Bob".to_string(),
             200.0,
             CurrencyType::BasicNeeds,
             1000,
         );
+        transaction.sign(&Keypair::generate(&mut OsRng {})).unwrap();
 
         let tx_id = communicator.initiate_cross_shard_transaction(transaction).await.unwrap();
 
@@ -149,13 +132,14 @@ mod tests {
             sm.initialize_balance("Charlie".to_string(), CurrencyType::BasicNeeds, 100.0).unwrap();
         }
 
-        let transaction = Transaction::new(
+        let mut transaction = Transaction::new(
             "Charlie".to_string(),
             "Dave".to_string(),
             200.0,
             CurrencyType::BasicNeeds,
             1000,
         );
+        transaction.sign(&Keypair::generate(&mut OsRng {})).unwrap();
 
         let tx_id = communicator.initiate_cross_shard_transaction(transaction).await.unwrap();
 
@@ -163,11 +147,12 @@ mod tests {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
         let status = communicator.get_transaction_status(&tx_id).unwrap();
-        assert_eq!(status, CrossShardTransactionStatus::Failed("Insufficient balance".to_string()));
+        assert!(matches!(status, CrossShardTransactionStatus::Aborted(_)));
 
         let sm = sharding_manager.lock().unwrap();
         let charlie_balance = sm.get_balance("Charlie".to_string(), CurrencyType::BasicNeeds).unwrap();
         let dave_balance = sm.get_balance("Dave".to_string(), CurrencyType::BasicNeeds).unwrap();
+        assert_eq!(sm.get_locked_balance("Charlie", &CurrencyType::BasicNeeds).unwrap(), 0.0);
         
         assert_eq!(charlie_balance, 100.0);
         assert_eq!(dave_balance, 0.0);
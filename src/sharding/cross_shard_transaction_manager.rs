@@ -1,109 +1,498 @@
-use crate::blockchain::Transaction;
-use crate::sharding::ShardingManager;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, info_span, warn};
+use serde::{Serialize, Deserialize};
 use uuid::Uuid;
+use crate::blockchain::Transaction;
+use crate::error::{Error, Result};
+use super::ShardingError;
+use super::ShardingManager;
+
+/// Transactions still preparing after this long are aborted and their locked
+/// funds refunded.
+pub const PREPARE_TIMEOUT_SECS: i64 = 30;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CrossShardTransactionStatus {
+    /// Asking both shards to prepare; aborted if either refuses.
+    Preparing,
+    /// Both shards prepared and the decision to commit is recorded; a
+    /// restarted coordinator finishes the commit.
+    Committing,
+    Committed,
+    Aborted(String),
+}
+
+impl CrossShardTransactionStatus {
+    pub fn is_final(&self) -> bool {
+        matches!(self, CrossShardTransactionStatus::Committed | CrossShardTransactionStatus::Aborted(_))
+    }
+}
+
+/// The steps of a transfer recorded in its audit trail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrossShardPhase {
+    /// Recorded by the coordinator, nothing locked yet.
+    Initiated,
+    /// The source shard locked the sender's funds.
+    Locked,
+    /// The destination shard agreed to credit the recipient as well.
+    Prepared,
+    Committed,
+    Aborted,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub phase: CrossShardPhase,
+    pub at: DateTime<Utc>,
+    /// The shard that acted, or why the transfer was aborted.
+    pub detail: Option<String>,
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CrossShardTransaction {
+    pub id: String,
     pub transaction: Transaction,
     pub from_shard: u64,
     pub to_shard: u64,
     pub status: CrossShardTransactionStatus,
+    pub started_at: DateTime<Utc>,
+    /// Every phase the transfer went through, oldest first.
+    #[serde(default)]
+    pub audit: Vec<AuditEntry>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum CrossShardTransactionStatus {
-    Initiated,
-    LockAcquired,
-    Committed,
-    Failed(String),
+impl CrossShardTransaction {
+    /// The payments of the transfer, each with the id its shards know it by
+    /// and the shards it goes from and to. A swap has two, the second going
+    /// back from the destination shard to the source shard.
+    pub fn legs(&self) -> Vec<(String, u64, u64, Transaction)> {
+        let mut legs = vec![(self.id.clone(), self.from_shard, self.to_shard, self.transaction.clone())];
+        if let Some(counter_leg) = self.transaction.counter_leg() {
+            legs.push((format!("{}/counter", self.id), self.to_shard, self.from_shard, counter_leg));
+        }
+        legs
+    }
+
+    /// When the transfer first reached `phase`, if it did.
+    pub fn phase_timestamp(&self, phase: CrossShardPhase) -> Option<DateTime<Utc>> {
+        self.audit.iter().find(|entry| entry.phase == phase).map(|entry| entry.at)
+    }
 }
 
-pub struct CrossShardCommunicator {
-    sharding_manager: Arc<Mutex<ShardingManager>>,
-    pending_transactions: HashMap<String, CrossShardTransaction>,
-    tx_channels: HashMap<u64, mpsc::Sender<CrossShardTransaction>>,
+/// Coordinates transfers between shards with two-phase commit. The source
+/// shard locks the sender's funds and the destination shard agrees to credit
+/// the recipient; only when both have prepared is the commit decided and
+/// applied to both. Swaps prepare both payments, each in both shards, before
+/// either is committed. A refusal or a timeout aborts the transfer instead, which
+/// refunds the locked funds.
+///
+/// Every status change is written to the transaction log before the shards
+/// are told, so `recover` can finish or roll back transfers that were in
+/// flight when the node stopped.
+#[derive(Debug)]
+pub struct CrossShardTransactionManager {
+    path: Option<PathBuf>,
+    transactions: HashMap<String, CrossShardTransaction>,
+    prepare_timeout: Duration,
 }
 
-impl CrossShardCommunicator {
-    pub fn new(sharding_manager: Arc<Mutex<ShardingManager>>) -> Self {
-        let mut tx_channels = HashMap::new();
-        let shard_count = sharding_manager.lock().unwrap().get_shard_count();
-        for i in 0..shard_count {
-            let (tx, mut rx) = mpsc::channel(100);
-            tx_channels.insert(i, tx);
-            let sm = Arc::clone(&sharding_manager);
-            tokio::spawn(async move {
-                while let Some(transaction) = rx.recv().await {
-                    if let Err(e) = Self::process_transaction(sm.clone(), transaction).await {
-                        eprintln!("Error processing cross-shard transaction: {}", e);
-                    }
-                }
-            });
+impl CrossShardTransactionManager {
+    /// A coordinator that keeps its log in memory only.
+    pub fn new() -> Self {
+        CrossShardTransactionManager {
+            path: None,
+            transactions: HashMap::new(),
+            prepare_timeout: Duration::seconds(PREPARE_TIMEOUT_SECS),
         }
+    }
 
-        CrossShardCommunicator {
-            sharding_manager,
-            pending_transactions: HashMap::new(),
-            tx_channels,
-        }
+    /// Opens a coordinator whose log is persisted as JSON at `path`, loading
+    /// the transactions recorded there earlier.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let transactions = if path.exists() {
+            let contents = fs::read(&path)?;
+            serde_json::from_slice(&contents)
+                .map_err(|e| Error::ShardingError(ShardingError::Rejected(format!("Corrupt cross-shard log {}: {}", path.display(), e))))?
+        } else {
+            HashMap::new()
+        };
+        Ok(CrossShardTransactionManager { path: Some(path), transactions, ..Self::new() })
+    }
+
+    pub fn with_prepare_timeout(mut self, timeout: Duration) -> Self {
+        self.prepare_timeout = timeout;
+        self
     }
 
-    pub async fn initiate_cross_shard_transaction(&mut self, transaction: Transaction) -> Result<String, String> {
-        let sharding_manager = self.sharding_manager.lock().unwrap();
-        let from_shard = sharding_manager.get_shard_for_address(&transaction.from);
-        let to_shard = sharding_manager.get_shard_for_address(&transaction.to);
+    pub fn get(&self, tx_id: &str) -> Option<&CrossShardTransaction> {
+        self.transactions.get(tx_id)
+    }
+
+    pub fn status(&self, tx_id: &str) -> Option<CrossShardTransactionStatus> {
+        self.get(tx_id).map(|transaction| transaction.status.clone())
+    }
 
+    pub fn audit_trail(&self, tx_id: &str) -> Option<&[AuditEntry]> {
+        self.get(tx_id).map(|transaction| transaction.audit.as_slice())
+    }
+
+    /// Unfinished transfers started more than `older_than` ago, oldest first.
+    pub fn stuck_transactions(&self, older_than: Duration) -> Vec<&CrossShardTransaction> {
+        let now = Utc::now();
+        let mut stuck: Vec<&CrossShardTransaction> = self.transactions.values()
+            .filter(|record| !record.status.is_final() && now - record.started_at > older_than)
+            .collect();
+        stuck.sort_by_key(|record| record.started_at);
+        stuck
+    }
+
+    /// Records a new transfer and returns its id. Nothing is locked until
+    /// `run` starts the prepare phase.
+    pub fn begin(&mut self, transaction: Transaction, from_shard: u64, to_shard: u64) -> Result<String> {
         if from_shard == to_shard {
-            return Err("Not a cross-shard transaction".to_string());
+            return Err(Error::ShardingError(ShardingError::Rejected("Not a cross-shard transaction".to_string())));
         }
-
-        let cross_shard_tx = CrossShardTransaction {
-            transaction: transaction.clone(),
+        let id = Uuid::new_v4().to_string();
+        let started_at = Utc::now();
+        self.transactions.insert(id.clone(), CrossShardTransaction {
+            id: id.clone(),
+            transaction,
             from_shard,
             to_shard,
-            status: CrossShardTransactionStatus::Initiated,
+            status: CrossShardTransactionStatus::Preparing,
+            started_at,
+            audit: vec![AuditEntry { phase: CrossShardPhase::Initiated, at: started_at, detail: None }],
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Begins a transfer and runs it to the end, returning its id once
+    /// committed and the abort reason as an error otherwise.
+    pub fn execute(&mut self, sharding_manager: &ShardingManager, transaction: Transaction, from_shard: u64, to_shard: u64) -> Result<String> {
+        let tx_id = self.begin(transaction, from_shard, to_shard)?;
+        let status = self.run(sharding_manager, &tx_id)?;
+        Self::outcome(tx_id, status)
+    }
+
+    /// Like `execute`, with `coordinator` locked only while the transfer is
+    /// recorded; see `run_shared`.
+    pub fn execute_shared(coordinator: &RwLock<Self>, sharding_manager: &ShardingManager, transaction: Transaction, from_shard: u64, to_shard: u64) -> Result<String> {
+        let tx_id = coordinator.write().unwrap().begin(transaction, from_shard, to_shard)?;
+        let status = Self::run_shared(coordinator, sharding_manager, &tx_id)?;
+        Self::outcome(tx_id, status)
+    }
+
+    fn outcome(tx_id: String, status: CrossShardTransactionStatus) -> Result<String> {
+        match status {
+            CrossShardTransactionStatus::Committed => Ok(tx_id),
+            CrossShardTransactionStatus::Aborted(reason) => Err(Error::ShardingError(ShardingError::Rejected(reason))),
+            status => Err(Error::ShardingError(ShardingError::Rejected(format!("Cross-shard transaction {} left {:?}", tx_id, status)))),
+        }
+    }
+
+    /// Drives a transfer as far as it goes and returns where it ended up.
+    pub fn run(&mut self, sharding_manager: &ShardingManager, tx_id: &str) -> Result<CrossShardTransactionStatus> {
+        let coordinator = RwLock::new(std::mem::take(self));
+        let status = Self::run_shared(&coordinator, sharding_manager, tx_id);
+        *self = coordinator.into_inner().unwrap();
+        status
+    }
+
+    /// Like `run`, with `coordinator` locked only to read and record the
+    /// progress of the transfer. The shards prepare and commit with it
+    /// unlocked, so that transfers between other shards go on meanwhile.
+    pub fn run_shared(coordinator: &RwLock<Self>, sharding_manager: &ShardingManager, tx_id: &str) -> Result<CrossShardTransactionStatus> {
+        let (record, prepare_timeout) = {
+            let coordinator = coordinator.read().unwrap();
+            let record = coordinator.transactions.get(tx_id)
+                .ok_or_else(|| Error::ShardingError(ShardingError::Rejected(format!("Unknown cross-shard transaction {}", tx_id))))?
+                .clone();
+            (record, coordinator.prepare_timeout)
         };
+        let _span = info_span!("cross_shard", id = %tx_id, tx = %record.transaction.hash(), from_shard = record.from_shard, to_shard = record.to_shard).entered();
 
-        let tx_id = Uuid::new_v4().to_string();
-        self.pending_transactions.insert(tx_id.clone(), cross_shard_tx.clone());
+        let legs = record.legs();
+        let shards = |shard_of: fn(&(String, u64, u64, Transaction)) -> u64| {
+            let shards: Vec<String> = legs.iter().map(|leg| shard_of(leg).to_string()).collect();
+            format!("shard {}", shards.join(", "))
+        };
+        if record.status == CrossShardTransactionStatus::Preparing {
+            if Utc::now() - record.started_at >= prepare_timeout {
+                return Self::abort_shared(coordinator, sharding_manager, &record, "Timed out while preparing".to_string());
+            }
+            for (leg_id, from_shard, _, transaction) in &legs {
+                if let Err(e) = sharding_manager.prepare_debit(leg_id, *from_shard, transaction) {
+                    return Self::abort_shared(coordinator, sharding_manager, &record, e.to_string());
+                }
+            }
+            coordinator.write().unwrap().record(tx_id, CrossShardPhase::Locked, Some(shards(|leg| leg.1)));
+            for (leg_id, _, to_shard, transaction) in &legs {
+                if let Err(e) = sharding_manager.prepare_credit(leg_id, *to_shard, transaction) {
+                    return Self::abort_shared(coordinator, sharding_manager, &record, e.to_string());
+                }
+            }
+            let mut coordinator = coordinator.write().unwrap();
+            coordinator.record(tx_id, CrossShardPhase::Prepared, Some(shards(|leg| leg.2)));
+            coordinator.set_status(tx_id, CrossShardTransactionStatus::Committing)?;
+        }
 
-        if let Some(tx) = self.tx_channels.get(&from_shard) {
-            tx.send(cross_shard_tx).await.map_err(|e| e.to_string())?;
-        } else {
-            return Err(format!("Channel for shard {} not found", from_shard));
+        if coordinator.read().unwrap().status(tx_id) == Some(CrossShardTransactionStatus::Committing) {
+            for (leg_id, from_shard, to_shard, _) in &legs {
+                sharding_manager.commit_prepared(leg_id, *from_shard)?;
+                sharding_manager.commit_prepared(leg_id, *to_shard)?;
+            }
+            let mut coordinator = coordinator.write().unwrap();
+            coordinator.record(tx_id, CrossShardPhase::Committed, None);
+            coordinator.set_status(tx_id, CrossShardTransactionStatus::Committed)?;
+            info!("Cross-shard transaction {} committed from shard {} to shard {}", tx_id, record.from_shard, record.to_shard);
         }
+        let status = coordinator.read().unwrap().status(tx_id);
+        Ok(status.unwrap_or(CrossShardTransactionStatus::Preparing))
+    }
 
-        Ok(tx_id)
+    /// Aborts transfers that have been preparing for longer than the prepare
+    /// timeout. Returns their ids.
+    pub fn abort_expired(&mut self, sharding_manager: &ShardingManager) -> Result<Vec<String>> {
+        let now = Utc::now();
+        let expired: Vec<CrossShardTransaction> = self.transactions.values()
+            .filter(|record| record.status == CrossShardTransactionStatus::Preparing && now - record.started_at >= self.prepare_timeout)
+            .cloned()
+            .collect();
+        for record in &expired {
+            self.abort(sharding_manager, record, "Timed out while preparing".to_string())?;
+        }
+        Ok(expired.into_iter().map(|record| record.id).collect())
     }
 
-    async fn process_transaction(sharding_manager: Arc<Mutex<ShardingManager>>, mut transaction: CrossShardTransaction) -> Result<(), String> {
-        // Phase 1: Lock funds in the source shard
-        {
-            let mut sm = sharding_manager.lock().unwrap();
-            sm.lock_funds(&transaction.transaction.from, &transaction.transaction.currency_type, transaction.transaction.amount, transaction.from_shard)?;
+    /// Settles the transfers left unfinished by a previous run: those that had
+    /// been decided are committed, the rest are aborted.
+    pub fn recover(&mut self, sharding_manager: &ShardingManager) -> Result<usize> {
+        let unfinished: Vec<CrossShardTransaction> = self.transactions.values()
+            .filter(|record| !record.status.is_final())
+            .cloned()
+            .collect();
+        for record in &unfinished {
+            if record.status == CrossShardTransactionStatus::Committing {
+                self.run(sharding_manager, &record.id)?;
+            } else {
+                self.abort(sharding_manager, record, "Coordinator restarted while preparing".to_string())?;
+            }
         }
-        transaction.status = CrossShardTransactionStatus::LockAcquired;
+        Ok(unfinished.len())
+    }
 
-        // Phase 2: Commit the transaction in the destination shard
-        {
-            let mut sm = sharding_manager.lock().unwrap();
-            sm.add_balance(&transaction.transaction.to, transaction.transaction.currency_type.clone(), transaction.transaction.amount)?;
+    fn abort(&mut self, sharding_manager: &ShardingManager, record: &CrossShardTransaction, reason: String) -> Result<CrossShardTransactionStatus> {
+        let status = self.mark_aborted(record, reason)?;
+        Self::release(sharding_manager, record);
+        Ok(status)
+    }
+
+    fn abort_shared(coordinator: &RwLock<Self>, sharding_manager: &ShardingManager, record: &CrossShardTransaction, reason: String) -> Result<CrossShardTransactionStatus> {
+        let status = coordinator.write().unwrap().mark_aborted(record, reason)?;
+        Self::release(sharding_manager, record);
+        Ok(status)
+    }
+
+    fn mark_aborted(&mut self, record: &CrossShardTransaction, reason: String) -> Result<CrossShardTransactionStatus> {
+        warn!("Aborting cross-shard transaction {}: {}", record.id, reason);
+        self.record(&record.id, CrossShardPhase::Aborted, Some(reason.clone()));
+        let status = CrossShardTransactionStatus::Aborted(reason);
+        self.set_status(&record.id, status.clone())?;
+        Ok(status)
+    }
+
+    /// Has the shards of a transfer release what they prepared for it.
+    fn release(sharding_manager: &ShardingManager, record: &CrossShardTransaction) {
+        for (leg_id, from_shard, to_shard, _) in record.legs() {
+            for shard_id in [from_shard, to_shard] {
+                if let Err(e) = sharding_manager.abort_prepared(&leg_id, shard_id) {
+                    warn!("Shard {} failed to abort {}: {}", shard_id, leg_id, e);
+                }
+            }
         }
+    }
+
+    /// Appends to the audit trail; it is saved with the next status change.
+    fn record(&mut self, tx_id: &str, phase: CrossShardPhase, detail: Option<String>) {
+        if let Some(record) = self.transactions.get_mut(tx_id) {
+            record.audit.push(AuditEntry { phase, at: Utc::now(), detail });
+        }
+    }
 
-        // Phase 3: Finalize by removing the lock in the source shard
-        {
-            let mut sm = sharding_manager.lock().unwrap();
-            sm.remove_fund_lock(&transaction.transaction.from, &transaction.transaction.currency_type, transaction.transaction.amount, transaction.from_shard)?;
+    fn set_status(&mut self, tx_id: &str, status: CrossShardTransactionStatus) -> Result<()> {
+        if let Some(record) = self.transactions.get_mut(tx_id) {
+            record.status = status;
         }
+        self.save()
+    }
 
-        transaction.status = CrossShardTransactionStatus::Committed;
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let contents = serde_json::to_vec_pretty(&self.transactions).map_err(|e| Error::ShardingError(ShardingError::Rejected(e.to_string())))?;
+            fs::write(path, contents)?;
+        }
         Ok(())
     }
+}
+
+impl Default for CrossShardTransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::CurrencyType;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    fn setup(balance: f64) -> ShardingManager {
+        let mut manager = ShardingManager::new(2, 10);
+        manager.add_address_to_shard("Alice".to_string(), 0);
+        manager.add_address_to_shard("Bob".to_string(), 1);
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, balance).unwrap();
+        manager
+    }
+
+    fn transfer(amount: f64) -> Transaction {
+        let keypair = Keypair::generate(&mut OsRng {});
+        let mut transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), amount, CurrencyType::BasicNeeds, 1000);
+        transaction.sign(&keypair).unwrap();
+        transaction
+    }
+
+    fn balance(manager: &ShardingManager, address: &str) -> f64 {
+        manager.get_balance(address.to_string(), CurrencyType::BasicNeeds).unwrap()
+    }
+
+    #[test]
+    fn test_refused_prepare_aborts_and_refunds() {
+        let manager = setup(100.0);
+        let mut coordinator = CrossShardTransactionManager::new();
+        let tx_id = coordinator.begin(transfer(200.0), 0, 1).unwrap();
+        assert!(matches!(coordinator.run(&manager, &tx_id).unwrap(), CrossShardTransactionStatus::Aborted(_)));
+        assert_eq!(balance(&manager, "Alice"), 100.0);
+        assert_eq!(balance(&manager, "Bob"), 0.0);
+
+        // The destination refuses after the source has locked the funds
+        let tx_id = coordinator.begin(transfer(50.0), 0, 7).unwrap();
+        assert!(matches!(coordinator.run(&manager, &tx_id).unwrap(), CrossShardTransactionStatus::Aborted(_)));
+        assert_eq!(balance(&manager, "Alice"), 100.0);
+        assert_eq!(manager.get_locked_balance("Alice", &CurrencyType::BasicNeeds).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_swap_commits_both_payments_or_neither() {
+        let mut manager = ShardingManager::new(2, 10);
+        let keys = [Keypair::generate(&mut OsRng {}), Keypair::generate(&mut OsRng {})];
+        let [alice, bob] = [0, 1].map(|index| crate::wallet::address_of(&keys[index].public));
+        manager.add_address_to_shard(alice.clone(), 0);
+        manager.add_address_to_shard(bob.clone(), 1);
+        manager.initialize_balance(alice.clone(), CurrencyType::Education, 100.0).unwrap();
+        manager.initialize_balance(bob.clone(), CurrencyType::Energy, 5.0).unwrap();
+        let swap = |energy| {
+            let mut swap = Transaction::swap(alice.clone(), bob.clone(), 10.0, CurrencyType::Education, energy, CurrencyType::Energy, 1000);
+            swap.sign(&keys[0]).unwrap();
+            swap.countersign(&keys[1]).unwrap();
+            swap
+        };
+        let holdings = |address: &str| [CurrencyType::Education, CurrencyType::Energy].map(|currency| manager.get_balance(address.to_string(), currency).unwrap());
+
+        let mut coordinator = CrossShardTransactionManager::new();
+        assert!(coordinator.execute(&manager, swap(8.0), 0, 1).is_err(), "Bob cannot pay");
+        assert_eq!((holdings(&alice), holdings(&bob)), ([100.0, 0.0], [0.0, 5.0]));
+        assert_eq!(manager.get_locked_balance(&alice, &CurrencyType::Education).unwrap(), 0.0);
+
+        coordinator.execute(&manager, swap(4.0), 0, 1).unwrap();
+        assert_eq!((holdings(&alice), holdings(&bob)), ([90.0, 4.0], [10.0, 1.0]));
+    }
 
-    pub fn get_transaction_status(&self, tx_id: &str) -> Option<CrossShardTransactionStatus> {
-        self.pending_transactions.get(tx_id).map(|tx| tx.status.clone())
+    #[test]
+    fn test_shared_coordinator_runs_transfers_side_by_side() {
+        let manager = setup(100.0);
+        let coordinator = RwLock::new(CrossShardTransactionManager::new());
+        let ids: Vec<String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| CrossShardTransactionManager::execute_shared(&coordinator, &manager, transfer(10.0), 0, 1).unwrap()))
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        let coordinator = coordinator.into_inner().unwrap();
+        assert!(ids.iter().all(|id| coordinator.status(id) == Some(CrossShardTransactionStatus::Committed)));
+        assert_eq!(balance(&manager, "Alice"), 60.0);
+        assert_eq!(balance(&manager, "Bob"), 40.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_expired_prepare_aborted() {
+        let manager = setup(100.0);
+        let mut coordinator = CrossShardTransactionManager::new().with_prepare_timeout(Duration::zero());
+        let tx_id = coordinator.begin(transfer(40.0), 0, 1).unwrap();
+        manager.prepare_debit(&tx_id, 0, &coordinator.get(&tx_id).unwrap().transaction).unwrap();
+        assert_eq!(balance(&manager, "Alice"), 60.0);
+
+        assert_eq!(coordinator.abort_expired(&manager).unwrap(), vec![tx_id.clone()]);
+        assert!(matches!(coordinator.status(&tx_id), Some(CrossShardTransactionStatus::Aborted(_))));
+        assert_eq!(balance(&manager, "Alice"), 100.0);
+        assert_eq!(balance(&manager, "Bob"), 0.0);
+    }
+
+    #[test]
+    fn test_audit_trail_and_stuck_transactions() {
+        let manager = setup(100.0);
+        let mut coordinator = CrossShardTransactionManager::new();
+        let committed = coordinator.execute(&manager, transfer(10.0), 0, 1).unwrap();
+        let phases: Vec<CrossShardPhase> = coordinator.audit_trail(&committed).unwrap().iter().map(|entry| entry.phase).collect();
+        assert_eq!(phases, vec![CrossShardPhase::Initiated, CrossShardPhase::Locked, CrossShardPhase::Prepared, CrossShardPhase::Committed]);
+        let record = coordinator.get(&committed).unwrap();
+        assert!(record.phase_timestamp(CrossShardPhase::Initiated).unwrap() <= record.phase_timestamp(CrossShardPhase::Committed).unwrap());
+        assert_eq!(record.phase_timestamp(CrossShardPhase::Aborted), None);
+
+        assert!(coordinator.execute(&manager, transfer(10.0), 0, 7).is_err());
+        let aborted = coordinator.transactions.values().find(|record| record.id != committed).unwrap();
+        let last = aborted.audit.last().unwrap();
+        assert_eq!(last.phase, CrossShardPhase::Aborted);
+        assert!(last.detail.is_some());
+
+        let pending = coordinator.begin(transfer(10.0), 0, 1).unwrap();
+        assert!(coordinator.stuck_transactions(Duration::seconds(60)).is_empty());
+        let stuck = coordinator.stuck_transactions(Duration::milliseconds(-1));
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].id, pending);
+    }
+
+    #[test]
+    fn test_log_recovered_after_restart() {
+        let path = std::env::temp_dir().join(format!("icn-cross-shard-{}.json", Uuid::new_v4()));
+        let manager = setup(100.0);
+        let (committing, preparing) = {
+            let mut coordinator = CrossShardTransactionManager::open(&path).unwrap();
+            let committing = coordinator.begin(transfer(30.0), 0, 1).unwrap();
+            let preparing = coordinator.begin(transfer(20.0), 0, 1).unwrap();
+            // Both shards voted yes and the decision was logged, then the node stopped
+            let transaction = coordinator.get(&committing).unwrap().transaction.clone();
+            manager.prepare_debit(&committing, 0, &transaction).unwrap();
+            manager.prepare_credit(&committing, 1, &transaction).unwrap();
+            coordinator.set_status(&committing, CrossShardTransactionStatus::Committing).unwrap();
+            manager.prepare_debit(&preparing, 0, &coordinator.get(&preparing).unwrap().transaction).unwrap();
+            (committing, preparing)
+        };
+
+        let mut coordinator = CrossShardTransactionManager::open(&path).unwrap();
+        assert_eq!(coordinator.recover(&manager).unwrap(), 2);
+        assert_eq!(coordinator.status(&committing), Some(CrossShardTransactionStatus::Committed));
+        assert!(matches!(coordinator.status(&preparing), Some(CrossShardTransactionStatus::Aborted(_))));
+        assert_eq!(balance(&manager, "Alice"), 70.0);
+        assert_eq!(balance(&manager, "Bob"), 30.0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+// src/sharding/locality.rs
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+/// The region of addresses and nodes that declared none.
+pub const UNKNOWN_REGION: &str = "unknown";
+
+/// Chooses the shard of nodes and addresses by the region they declared, so
+/// that parties near each other share a shard and most transfers between
+/// them stay inside it.
+pub trait LocalityPolicy: Send + Sync {
+    /// The shard, out of `shard_count`, that `region` is served by.
+    fn shard_for_region(&self, region: &str, shard_count: u64) -> u64;
+}
+
+/// Pins regions to shards, spreading the regions not pinned over all shards
+/// by the hash of their name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegionMap {
+    shards: HashMap<String, u64>,
+}
+
+impl RegionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_region(mut self, region: &str, shard_id: u64) -> Self {
+        self.shards.insert(region.to_string(), shard_id);
+        self
+    }
+}
+
+impl LocalityPolicy for RegionMap {
+    fn shard_for_region(&self, region: &str, shard_count: u64) -> u64 {
+        match self.shards.get(region) {
+            Some(shard_id) if *shard_id < shard_count => *shard_id,
+            _ => {
+                let hash = Sha256::digest(region.as_bytes());
+                u64::from_le_bytes(hash[..8].try_into().unwrap_or([0; 8])) % shard_count
+            }
+        }
+    }
+}
+
+/// Transfers seen by the sharding manager, by the regions of their parties.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RegionTraffic {
+    /// Transfers between parties of the same region.
+    pub local: u64,
+    /// Transfers between parties of different regions.
+    pub cross_region: u64,
+    /// Transfers by sending region, then receiving region.
+    pub routes: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl RegionTraffic {
+    pub(crate) fn record(&mut self, from_region: &str, to_region: &str) {
+        if from_region == to_region {
+            self.local += 1;
+        } else {
+            self.cross_region += 1;
+        }
+        *self.routes.entry(from_region.to_string()).or_default().entry(to_region.to_string()).or_insert(0) += 1;
+    }
+
+    /// Share of the transfers that crossed regions, 0 when none were seen.
+    pub fn cross_region_ratio(&self) -> f64 {
+        let total = self.local + self.cross_region;
+        if total == 0 {
+            0.0
+        } else {
+            self.cross_region as f64 / total as f64
+        }
+    }
+}
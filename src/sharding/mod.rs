@@ -1,15 +1,27 @@
 use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use crate::blockchain::{Block, Transaction};
 use crate::network::Node;
 use crate::currency::CurrencyType;
 use std::sync::{Arc, Mutex};
 use ed25519_dalek::{PublicKey, Signature, Verifier};
-use log::{info, error, warn, debug};
+use tracing::{info, error, warn, debug};
 use crate::error::{Error, Result};
 use thiserror::Error;
 
+pub mod beacon;
 pub mod cross_shard_communication;
+pub mod cross_shard_transaction_manager;
+pub mod locality;
+pub mod rebalancer;
+pub mod state_sync;
+
+pub use beacon::{Beacon, ShardHeader};
+pub use cross_shard_transaction_manager::{AuditEntry, CrossShardPhase, CrossShardTransaction, CrossShardTransactionManager, CrossShardTransactionStatus};
+pub use locality::{LocalityPolicy, RegionMap, RegionTraffic, UNKNOWN_REGION};
+pub use rebalancer::{AddressMove, Rebalancer};
+pub use state_sync::{ShardSnapshot, ShardStateSync};
 
 #[derive(Error, Debug)]
 pub enum ShardingError {
@@ -23,6 +35,20 @@ pub enum ShardingError {
     ShardLockFailed(String),
     #[error("Cross-shard communication error: {0}")]
     CrossShardCommunicationError(String),
+    /// Refused for a reason specific to the operation, e.g. a shard header
+    /// without enough approvals.
+    #[error("{0}")]
+    Rejected(String),
+}
+
+/// One side of a cross-shard transfer that a shard has voted to commit and
+/// that awaits the coordinator's decision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PreparedTransfer {
+    /// The sender's funds are locked in the source shard.
+    Debit(Transaction),
+    /// The destination shard has agreed to credit the recipient.
+    Credit(Transaction),
 }
 
 pub struct Shard {
@@ -31,6 +57,33 @@ pub struct Shard {
     pub blockchain: Vec<Block>,
     pub balances: HashMap<String, HashMap<CurrencyType, f64>>,
     pub locked_funds: HashMap<String, HashMap<CurrencyType, f64>>,
+    /// Cross-shard transfers prepared in this shard, by transaction id.
+    pub prepared: HashMap<String, PreparedTransfer>,
+    /// Transactions applied in this shard, cross-shard ones included.
+    pub transaction_count: u64,
+    /// Transactions applied in this shard per address they touched.
+    pub address_activity: HashMap<String, u64>,
+}
+
+impl Shard {
+    fn record_activity(&mut self, addresses: &[&str]) {
+        self.transaction_count += 1;
+        for address in addresses {
+            *self.address_activity.entry(address.to_string()).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Load of a shard as observed by this node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardMetrics {
+    pub shard_id: u64,
+    pub transaction_count: u64,
+    pub account_count: usize,
+    pub node_count: usize,
+    /// Summed activity of the addresses now in the shard. Unlike
+    /// `transaction_count` it follows addresses moved to another shard.
+    pub address_activity: u64,
 }
 
 pub struct ShardingManager {
@@ -39,6 +92,11 @@ pub struct ShardingManager {
     nodes_per_shard: usize,
     address_to_shard: HashMap<String, u64>,
     current_shard_id: u64,
+    beacon: Beacon,
+    locality: Box<dyn LocalityPolicy>,
+    /// Declared region of addresses and node ids.
+    regions: HashMap<String, String>,
+    traffic: Mutex<RegionTraffic>,
 }
 
 impl ShardingManager {
@@ -51,6 +109,9 @@ impl ShardingManager {
                 blockchain: Vec::new(),
                 balances: HashMap::new(),
                 locked_funds: HashMap::new(),
+                prepared: HashMap::new(),
+                transaction_count: 0,
+                address_activity: HashMap::new(),
             })));
         }
         
@@ -61,6 +122,52 @@ impl ShardingManager {
             nodes_per_shard,
             address_to_shard: HashMap::new(),
             current_shard_id: 0,
+            beacon: Beacon::new(shard_count),
+            locality: Box::new(RegionMap::new()),
+            regions: HashMap::new(),
+            traffic: Mutex::new(RegionTraffic::default()),
+        }
+    }
+
+    pub fn with_locality_policy(mut self, policy: Box<dyn LocalityPolicy>) -> Self {
+        self.locality = policy;
+        self
+    }
+
+    /// Records that `address` is in `region` and moves it, balances and all,
+    /// to the shard the locality policy serves the region from.
+    pub fn declare_region(&mut self, address: &str, region: &str) -> Result<u64> {
+        let shard_id = self.locality.shard_for_region(region, self.shard_count);
+        self.move_address(address, shard_id)?;
+        self.address_to_shard.insert(address.to_string(), shard_id);
+        self.regions.insert(address.to_string(), region.to_string());
+        Ok(shard_id)
+    }
+
+    /// Assigns `node` to the shard serving `region`.
+    pub fn assign_node_by_region(&mut self, node: Node, region: &str) -> Result<u64> {
+        let shard_id = self.locality.shard_for_region(region, self.shard_count);
+        let node_id = node.id.clone();
+        self.assign_node_to_shard(node, shard_id)?;
+        self.regions.insert(node_id, region.to_string());
+        Ok(shard_id)
+    }
+
+    /// The region `address` or node id declared, if any.
+    pub fn region_of(&self, id: &str) -> Option<&str> {
+        self.regions.get(id).map(String::as_str)
+    }
+
+    /// Transfers processed so far, local and cross-region.
+    pub fn region_traffic(&self) -> RegionTraffic {
+        self.traffic.lock().map(|traffic| traffic.clone()).unwrap_or_default()
+    }
+
+    fn record_traffic(&self, transaction: &Transaction) {
+        let from_region = self.region_of(&transaction.from).unwrap_or(UNKNOWN_REGION);
+        let to_region = self.region_of(&transaction.to).unwrap_or(UNKNOWN_REGION);
+        if let Ok(mut traffic) = self.traffic.lock() {
+            traffic.record(from_region, to_region);
         }
     }
 
@@ -68,17 +175,24 @@ impl ShardingManager {
         self.shard_count
     }
 
-    pub fn process_transaction(&mut self, shard_id: u64, transaction: &Transaction) -> Result<()> {
+    pub fn process_transaction(&self, shard_id: u64, transaction: &Transaction) -> Result<()> {
+        let _span = crate::logging::transaction_span(transaction).entered();
         let shard = self.shards.get(&shard_id)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(shard_id).to_string()))?;
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
         let mut shard = shard.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
 
-        if !self.verify_transaction(&shard, transaction) {
-            return Err(Error::ShardingError(ShardingError::InvalidTransaction("Transaction verification failed".to_string()).to_string()));
+        // both payments of a swap are checked before either is made
+        let legs = transaction.legs();
+        if !legs.iter().all(|leg| self.verify_transaction(&shard, leg)) {
+            return Err(Error::ShardingError(ShardingError::InvalidTransaction("Transaction verification failed".to_string())));
         }
 
-        self.update_balances(&mut shard, transaction)?;
+        for leg in &legs {
+            self.update_balances(&mut shard, leg)?;
+            shard.record_activity(&[&leg.from, &leg.to]);
+            self.record_traffic(leg);
+        }
 
         Ok(())
     }
@@ -88,7 +202,7 @@ impl ShardingManager {
         let sender_balance = sender_balances.entry(transaction.currency_type.clone()).or_insert(0.0);
         
         if *sender_balance < transaction.amount {
-            return Err(Error::ShardingError(ShardingError::InsufficientBalance(format!("Insufficient balance for sender: {}", transaction.from)).to_string()));
+            return Err(Error::ShardingError(ShardingError::InsufficientBalance(format!("Insufficient balance for sender: {}", transaction.from))));
         }
         
         *sender_balance -= transaction.amount;
@@ -100,38 +214,244 @@ impl ShardingManager {
         Ok(())
     }
 
-    pub fn transfer_between_shards(&mut self, from_shard: u64, to_shard: u64, transaction: &Transaction) -> Result<()> {
-        let from_shard_arc = self.shards.get(&from_shard)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(from_shard).to_string()))?;
-        let to_shard_arc = self.shards.get(&to_shard)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(to_shard).to_string()))?;
-        
-        let mut from_shard = from_shard_arc.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
-        let mut to_shard = to_shard_arc.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+    /// Moves funds to another shard with the two-phase commit protocol of
+    /// `CrossShardTransactionManager`. Nothing changes if either shard refuses.
+    ///
+    /// Only one shard is locked at a time, so transfers in opposite directions
+    /// can run concurrently without deadlocking.
+    pub fn transfer_between_shards(&self, from_shard: u64, to_shard: u64, transaction: &Transaction) -> Result<()> {
+        CrossShardTransactionManager::new().execute(self, transaction.clone(), from_shard, to_shard).map(|_| ())
+    }
+
+    /// Phase one in the source shard: checks the transaction and locks the
+    /// sender's funds until the coordinator decides.
+    pub fn prepare_debit(&self, tx_id: &str, shard_id: u64, transaction: &Transaction) -> Result<()> {
+        let mut shard = self.lock_shard(shard_id)?;
+        if shard.prepared.contains_key(tx_id) {
+            return Ok(());
+        }
+        if !self.verify_transaction(&shard, transaction) {
+            return Err(Error::ShardingError(ShardingError::InvalidTransaction("Transaction verification failed in the source shard".to_string())));
+        }
+        self.lock_funds(&mut shard, transaction)?;
+        shard.prepared.insert(tx_id.to_string(), PreparedTransfer::Debit(transaction.clone()));
+        debug!("Shard {} prepared debit of {}", shard_id, tx_id);
+        Ok(())
+    }
+
+    /// Phase one in the destination shard: agrees to credit the recipient.
+    pub fn prepare_credit(&self, tx_id: &str, shard_id: u64, transaction: &Transaction) -> Result<()> {
+        let mut shard = self.lock_shard(shard_id)?;
+        if transaction.amount <= 0.0 {
+            return Err(Error::ShardingError(ShardingError::InvalidTransaction("Amount must be positive".to_string())));
+        }
+        shard.prepared.entry(tx_id.to_string()).or_insert_with(|| PreparedTransfer::Credit(transaction.clone()));
+        debug!("Shard {} prepared credit of {}", shard_id, tx_id);
+        Ok(())
+    }
 
-        if !self.verify_transaction(&from_shard, transaction) {
-            return Err(Error::ShardingError(ShardingError::InvalidTransaction("Transaction verification failed in the source shard".to_string()).to_string()));
+    /// Phase two: makes a prepared side of a transfer permanent. Committing a
+    /// transaction the shard no longer holds is a no-op, so retries are safe.
+    pub fn commit_prepared(&self, tx_id: &str, shard_id: u64) -> Result<()> {
+        let mut shard = self.lock_shard(shard_id)?;
+        match shard.prepared.remove(tx_id) {
+            Some(PreparedTransfer::Debit(transaction)) => {
+                self.remove_fund_lock(&mut shard, &transaction)?;
+                shard.record_activity(&[&transaction.from]);
+                self.record_traffic(&transaction);
+                Ok(())
+            }
+            Some(PreparedTransfer::Credit(transaction)) => {
+                self.add_balance_to_shard(&mut shard, &transaction.to, &transaction.currency_type, transaction.amount)?;
+                shard.record_activity(&[&transaction.to]);
+                Ok(())
+            }
+            None => Ok(()),
         }
+    }
+
+    /// Undoes a prepared side of a transfer, refunding locked funds to the
+    /// sender. Shards that never prepared the transaction have nothing to undo.
+    pub fn abort_prepared(&self, tx_id: &str, shard_id: u64) -> Result<()> {
+        let mut shard = self.lock_shard(shard_id)?;
+        if let Some(PreparedTransfer::Debit(transaction)) = shard.prepared.remove(tx_id) {
+            self.remove_fund_lock(&mut shard, &transaction)?;
+            self.add_balance_to_shard(&mut shard, &transaction.from, &transaction.currency_type, transaction.amount)?;
+            info!("Refunded {} {} to {} in shard {}", transaction.amount, transaction.currency_type, transaction.from, shard_id);
+        }
+        Ok(())
+    }
+
+    pub fn beacon(&self) -> &Beacon {
+        &self.beacon
+    }
+
+    pub fn beacon_mut(&mut self) -> &mut Beacon {
+        &mut self.beacon
+    }
+
+    /// Submits the current state of a shard to the beacon as a header
+    /// proposed by `proposer` and approved by `approvals`.
+    pub fn propose_shard_header(&mut self, shard_id: u64, proposer: &str, approvals: Vec<String>) -> Result<ShardHeader> {
+        let (height, block_hash) = {
+            let shard = self.lock_shard(shard_id)?;
+            (shard.blockchain.len() as u64, shard.blockchain.last().map(|block| block.hash.clone()).unwrap_or_default())
+        };
+        let header = ShardHeader {
+            shard_id,
+            epoch: self.beacon.epoch(),
+            height,
+            state_root: self.shard_state_root(shard_id)?,
+            block_hash,
+            proposer: proposer.to_string(),
+            approvals,
+        };
+        self.beacon.submit_header(header.clone())?;
+        Ok(header)
+    }
 
-        self.lock_funds(&mut from_shard, transaction)?;
-        self.add_balance_to_shard(&mut to_shard, &transaction.to, &transaction.currency_type, transaction.amount)?;
-        self.remove_fund_lock(&mut from_shard, transaction)?;
+    pub fn shard_members(&self, shard_id: u64) -> Result<Vec<Node>> {
+        Ok(self.lock_shard(shard_id)?.nodes.clone())
+    }
+
+    /// The state a new member of the shard needs, with the most recent
+    /// `RECENT_SHARD_BLOCKS` blocks of the shard chain.
+    pub fn shard_snapshot(&self, shard_id: u64) -> Result<ShardSnapshot> {
+        let shard = self.lock_shard(shard_id)?;
+        let flatten = |accounts: &HashMap<String, HashMap<CurrencyType, f64>>| {
+            let mut entries: Vec<(String, CurrencyType, f64)> = accounts.iter()
+                .flat_map(|(address, amounts)| amounts.iter().map(move |(currency, amount)| (address.clone(), currency.clone(), *amount)))
+                .collect();
+            entries.sort_by(|a, b| (&a.0, a.1.to_string()).cmp(&(&b.0, b.1.to_string())));
+            entries
+        };
+        let mut prepared: Vec<(String, PreparedTransfer)> = shard.prepared.iter().map(|(id, transfer)| (id.clone(), transfer.clone())).collect();
+        prepared.sort_by(|a, b| a.0.cmp(&b.0));
+        let skip = shard.blockchain.len().saturating_sub(state_sync::RECENT_SHARD_BLOCKS);
+        Ok(ShardSnapshot {
+            shard_id,
+            balances: flatten(&shard.balances),
+            locked_funds: flatten(&shard.locked_funds),
+            prepared,
+            recent_blocks: shard.blockchain[skip..].to_vec(),
+        })
+    }
 
-        info!("Cross-shard transaction completed from shard {} to shard {}", from_shard.id, to_shard.id);
+    pub fn shard_state_root(&self, shard_id: u64) -> Result<String> {
+        Ok(self.shard_snapshot(shard_id)?.root())
+    }
+
+    /// Replaces the local copy of a shard's state with a verified snapshot and
+    /// makes it the shard this node validates.
+    pub fn join_shard(&mut self, snapshot: ShardSnapshot) -> Result<()> {
+        let shard_id = snapshot.shard_id;
+        {
+            let mut shard = self.lock_shard(shard_id)?;
+            let expand = |entries: Vec<(String, CurrencyType, f64)>| {
+                let mut accounts: HashMap<String, HashMap<CurrencyType, f64>> = HashMap::new();
+                for (address, currency, amount) in entries {
+                    accounts.entry(address).or_default().insert(currency, amount);
+                }
+                accounts
+            };
+            shard.balances = expand(snapshot.balances);
+            shard.locked_funds = expand(snapshot.locked_funds);
+            shard.prepared = snapshot.prepared.into_iter().collect();
+            shard.blockchain = snapshot.recent_blocks;
+        }
+        self.set_current_shard_id(shard_id);
+        info!("Joined shard {} and started validating it", shard_id);
         Ok(())
     }
 
+    pub fn shard_metrics(&self, shard_id: u64) -> Result<ShardMetrics> {
+        let shard = self.lock_shard(shard_id)?;
+        Ok(ShardMetrics {
+            shard_id,
+            transaction_count: shard.transaction_count,
+            account_count: shard.balances.len(),
+            node_count: shard.nodes.len(),
+            address_activity: shard.address_activity.values().sum(),
+        })
+    }
+
+    /// Metrics of every shard, ordered by shard id.
+    pub fn load_metrics(&self) -> Result<Vec<ShardMetrics>> {
+        (0..self.shard_count).map(|shard_id| self.shard_metrics(shard_id)).collect()
+    }
+
+    /// The `limit` most active addresses of a shard with their transaction
+    /// counts, busiest first.
+    pub fn hot_addresses(&self, shard_id: u64, limit: usize) -> Result<Vec<(String, u64)>> {
+        let shard = self.lock_shard(shard_id)?;
+        let mut addresses: Vec<(String, u64)> = shard.address_activity.iter()
+            .map(|(address, count)| (address.clone(), *count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        addresses.truncate(limit);
+        Ok(addresses)
+    }
+
+    /// Reassigns `address` to another shard, carrying its balances and
+    /// activity along. Refused while the address has funds locked or a
+    /// transfer prepared in its current shard.
+    pub fn move_address(&mut self, address: &str, to_shard: u64) -> Result<()> {
+        let from_shard = self.get_shard_for_address(address);
+        if from_shard == to_shard {
+            return Ok(());
+        }
+        if !self.shards.contains_key(&to_shard) {
+            return Err(Error::ShardingError(ShardingError::ShardNotFound(to_shard)));
+        }
+        let (balances, activity) = {
+            let mut shard = self.lock_shard(from_shard)?;
+            let busy = shard.locked_funds.contains_key(address) || shard.prepared.values().any(|transfer| match transfer {
+                PreparedTransfer::Debit(transaction) => transaction.from == address,
+                PreparedTransfer::Credit(transaction) => transaction.to == address,
+            });
+            if busy {
+                return Err(Error::ShardingError(ShardingError::Rejected(format!("{} has a cross-shard transfer in flight in shard {}", address, from_shard))));
+            }
+            (shard.balances.remove(address), shard.address_activity.remove(address))
+        };
+        {
+            let mut shard = self.lock_shard(to_shard)?;
+            for (currency_type, amount) in balances.unwrap_or_default() {
+                self.add_balance_to_shard(&mut shard, address, &currency_type, amount)?;
+            }
+            if let Some(activity) = activity {
+                *shard.address_activity.entry(address.to_string()).or_insert(0) += activity;
+            }
+        }
+        self.address_to_shard.insert(address.to_string(), to_shard);
+        info!("Moved address {} from shard {} to shard {}", address, from_shard, to_shard);
+        Ok(())
+    }
+
+    /// Funds of `address` locked by prepared transfers.
+    pub fn get_locked_balance(&self, address: &str, currency_type: &CurrencyType) -> Result<f64> {
+        let shard = self.lock_shard(self.get_shard_for_address(address))?;
+        Ok(shard.locked_funds.get(address).and_then(|locked| locked.get(currency_type)).copied().unwrap_or(0.0))
+    }
+
+    /// Callers must not hold another shard's guard while taking this one;
+    /// anything spanning shards goes through the two-phase commit instead.
+    fn lock_shard(&self, shard_id: u64) -> Result<std::sync::MutexGuard<'_, Shard>> {
+        self.shards.get(&shard_id)
+            .ok_or(ShardingError::ShardNotFound(shard_id))?
+            .lock()
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))
+    }
+
     fn lock_funds(&self, shard: &mut Shard, transaction: &Transaction) -> Result<()> {
         let sender_balances = shard.balances.get_mut(&transaction.from)
-            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("Sender not found".to_string()).to_string()))?;
+            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("Sender not found".to_string())))?;
         
         let balance = sender_balances.get_mut(&transaction.currency_type)
-            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("Currency not found".to_string()).to_string()))?;
+            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("Currency not found".to_string())))?;
 
         if *balance < transaction.amount {
-            return Err(Error::ShardingError(ShardingError::InsufficientBalance("Insufficient balance".to_string()).to_string()));
+            return Err(Error::ShardingError(ShardingError::InsufficientBalance("Insufficient balance".to_string())));
         }
 
         *balance -= transaction.amount;
@@ -148,13 +468,13 @@ impl ShardingManager {
 
     fn remove_fund_lock(&self, shard: &mut Shard, transaction: &Transaction) -> Result<()> {
         let locked_funds = shard.locked_funds.get_mut(&transaction.from)
-            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("No locked funds found".to_string()).to_string()))?;
+            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("No locked funds found".to_string())))?;
 
         let locked_amount = locked_funds.get_mut(&transaction.currency_type)
-            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("No locked funds for this currency".to_string()).to_string()))?;
+            .ok_or_else(|| Error::ShardingError(ShardingError::InsufficientBalance("No locked funds for this currency".to_string())))?;
 
         if *locked_amount < transaction.amount {
-            return Err(Error::ShardingError(ShardingError::InsufficientBalance("Insufficient locked funds".to_string()).to_string()));
+            return Err(Error::ShardingError(ShardingError::InsufficientBalance("Insufficient locked funds".to_string())));
         }
 
         *locked_amount -= transaction.amount;
@@ -173,10 +493,10 @@ impl ShardingManager {
     pub fn add_balance(&mut self, address: &str, currency_type: CurrencyType, amount: f64) -> Result<()> {
         let shard_id = self.get_shard_for_address(address);
         let shard = self.shards.get_mut(&shard_id)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(shard_id).to_string()))?;
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
         
         let mut shard = shard.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
     
         let balance = shard.balances
             .entry(address.to_string())
@@ -202,12 +522,12 @@ impl ShardingManager {
 
     pub fn assign_node_to_shard(&mut self, node: Node, shard_id: u64) -> Result<()> {
         let shard = self.shards.get(&shard_id)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(shard_id).to_string()))?;
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
         let mut shard = shard.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
         if shard.nodes.len() >= self.nodes_per_shard {
             error!("Failed to assign node to shard {}: Shard is full", shard_id);
-            return Err(Error::ShardingError(ShardingError::ShardLockFailed(format!("Shard {} is full", shard_id)).to_string()));
+            return Err(Error::ShardingError(ShardingError::ShardLockFailed(format!("Shard {} is full", shard_id))));
         }
         shard.nodes.push(node.clone());
         info!("Assigned node {} to shard {}", node.id, shard_id);
@@ -236,12 +556,26 @@ impl ShardingManager {
         info!("Added address {} to shard {}", address, shard_id);
     }
 
+    /// Forgets `address`: its shard assignment and its balances there.
+    pub fn remove_address(&mut self, address: &str) -> Result<()> {
+        let shard_id = self.get_shard_for_address(address);
+        if let Some(shard) = self.shards.get(&shard_id) {
+            let mut shard = shard.lock()
+                .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
+            shard.balances.remove(address);
+        }
+        self.address_to_shard.remove(address);
+        self.regions.remove(address);
+        info!("Removed address {} from shard {}", address, shard_id);
+        Ok(())
+    }
+
     pub fn initialize_balance(&mut self, address: String, currency_type: CurrencyType, amount: f64) -> Result<()> {
         let shard_id = self.get_shard_for_address(&address);
         let shard = self.shards.get_mut(&shard_id)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(shard_id).to_string()))?;
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
         let mut shard = shard.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
         
         shard.balances
             .entry(address.clone())
@@ -255,9 +589,9 @@ impl ShardingManager {
     pub fn get_balance(&self, address: String, currency_type: CurrencyType) -> Result<f64> {
         let shard_id = self.get_shard_for_address(&address);
         let shard = self.shards.get(&shard_id)
-            .ok_or_else(|| Error::ShardingError(ShardingError::ShardNotFound(shard_id).to_string()))?;
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
         let shard = shard.lock()
-            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string()).to_string()))?;
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
         
         let balance = shard.balances
             .get(&address)
@@ -268,6 +602,22 @@ impl ShardingManager {
         Ok(balance)
     }
 
+    /// The balances of `address` in its shard, by currency.
+    pub fn get_balances(&self, address: &str) -> Result<Vec<(CurrencyType, f64)>> {
+        let shard_id = self.get_shard_for_address(address);
+        let shard = self.shards.get(&shard_id)
+            .ok_or(ShardingError::ShardNotFound(shard_id))?;
+        let shard = shard.lock()
+            .map_err(|e| Error::ShardingError(ShardingError::ShardLockFailed(e.to_string())))?;
+
+        let mut balances: Vec<(CurrencyType, f64)> = shard.balances
+            .get(address)
+            .map(|balances| balances.iter().map(|(currency, balance)| (currency.clone(), *balance)).collect())
+            .unwrap_or_default();
+        balances.sort_by_key(|(currency, _)| currency.to_string());
+        Ok(balances)
+    }
+
     fn verify_transaction(&self, shard: &Shard, transaction: &Transaction) -> bool {
         debug!("Checking balance for sender: {}", transaction.from);
         if let Some(sender_balances) = shard.balances.get(&transaction.from) {
@@ -285,6 +635,11 @@ impl ShardingManager {
             return false;
         }
 
+        if let Err(e) = transaction.check_swap() {
+            warn!("Invalid swap: {}", e);
+            return false;
+        }
+
         debug!("Verifying transaction signature");
         if let (Some(public_key), Some(signature)) = (&transaction.public_key, &transaction.signature) {
             let public_key = PublicKey::from_bytes(public_key).unwrap();
@@ -477,6 +832,85 @@ mod tests {
         assert_eq!(manager.get_balance("Grace".to_string(), CurrencyType::BasicNeeds).unwrap(), 0.0);
     }
 
+    #[test]
+    fn test_concurrent_opposite_transfers_do_not_deadlock() {
+        const TRANSFERS: usize = 200;
+        let mut manager = ShardingManager::new(2, 10);
+        manager.add_address_to_shard("Alice".to_string(), 0);
+        manager.add_address_to_shard("Bob".to_string(), 1);
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0).unwrap();
+        manager.initialize_balance("Bob".to_string(), CurrencyType::BasicNeeds, 1000.0).unwrap();
+        let manager = Arc::new(manager);
+
+        let (done, finished) = std::sync::mpsc::channel();
+        for (from, to, from_shard, to_shard) in [("Alice", "Bob", 0, 1), ("Bob", "Alice", 1, 0)] {
+            let manager = Arc::clone(&manager);
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let keypair = Keypair::generate(&mut OsRng {});
+                let mut transaction = Transaction::new(from.to_string(), to.to_string(), 1.0, CurrencyType::BasicNeeds, 1000);
+                transaction.sign(&keypair).unwrap();
+                for _ in 0..TRANSFERS {
+                    manager.transfer_between_shards(from_shard, to_shard, &transaction).unwrap();
+                }
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..2 {
+            finished.recv_timeout(std::time::Duration::from_secs(30)).expect("transfers deadlocked");
+        }
+
+        assert_eq!(manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds).unwrap(), 1000.0);
+        assert_eq!(manager.get_balance("Bob".to_string(), CurrencyType::BasicNeeds).unwrap(), 1000.0);
+        assert_eq!(manager.get_locked_balance("Alice", &CurrencyType::BasicNeeds).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_regions_keep_neighbours_in_one_shard() {
+        let policy = RegionMap::new().with_region("lisbon", 1).with_region("oslo", 3);
+        let mut manager = ShardingManager::new(4, 10).with_locality_policy(Box::new(policy));
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 1000.0).unwrap();
+        assert_eq!(manager.declare_region("Alice", "lisbon").unwrap(), 1);
+        assert_eq!(manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds).unwrap(), 1000.0, "balances follow the address");
+        manager.declare_region("Bob", "lisbon").unwrap();
+        manager.declare_region("Olga", "oslo").unwrap();
+        let node = Node::new("node1", NodeType::PersonalDevice, "127.0.0.1:8000");
+        assert_eq!(manager.assign_node_by_region(node, "oslo").unwrap(), 3);
+        assert_eq!(manager.region_of("node1"), Some("oslo"));
+
+        let keypair = Keypair::generate(&mut OsRng {});
+        for (to, shard_id) in [("Bob", 1), ("Olga", 3)] {
+            let mut transaction = Transaction::new("Alice".to_string(), to.to_string(), 10.0, CurrencyType::BasicNeeds, 1000);
+            transaction.sign(&keypair).unwrap();
+            if shard_id == 1 {
+                manager.process_transaction(1, &transaction).unwrap();
+            } else {
+                manager.transfer_between_shards(1, shard_id, &transaction).unwrap();
+            }
+        }
+
+        let traffic = manager.region_traffic();
+        assert_eq!((traffic.local, traffic.cross_region), (1, 1));
+        assert_eq!(traffic.routes["lisbon"]["oslo"], 1);
+        assert_eq!(traffic.cross_region_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_shard_header_carries_state_root() {
+        let mut manager = ShardingManager::new(2, 10);
+        manager.add_address_to_shard("Alice".to_string(), 1);
+        manager.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 10.0).unwrap();
+        for validator in ["v1", "v2"] {
+            manager.beacon_mut().register_validator(validator);
+        }
+        manager.beacon_mut().advance_epoch();
+        let validators = manager.beacon().shard_validators(1).to_vec();
+
+        let header = manager.propose_shard_header(1, &validators[0], validators.clone()).unwrap();
+        assert_eq!(header.state_root, manager.shard_state_root(1).unwrap());
+        assert_eq!(manager.beacon().shard_header(1), Some(&header));
+    }
+
     #[test]
     fn test_verify_transaction() {
         let mut manager = ShardingManager::new(4, 10);
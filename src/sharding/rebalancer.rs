@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use chrono::Duration;
+use tracing::{info, warn};
+use serde::{Serialize, Deserialize};
+use crate::error::{Error, Result};
+use crate::governance::{DemocraticSystem, ProposalCategory, ProposalType};
+use crate::governance::democracy::ProposalStatus;
+use super::ShardingManager;
+
+/// The busiest shard must see this many times the address activity of the
+/// quietest one before a rebalance is proposed.
+pub const DEFAULT_IMBALANCE_RATIO: f64 = 2.0;
+/// Addresses moved by a single proposal at most.
+pub const DEFAULT_MAX_MOVES: usize = 16;
+const DEFAULT_VOTING_PERIOD_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressMove {
+    pub address: String,
+    pub from_shard: u64,
+    pub to_shard: u64,
+}
+
+/// Moves hot addresses from the most active shard to the least active one,
+/// but only with the network's consent: each plan is put to a governance vote
+/// and applied by `apply_passed` once the proposal passes.
+///
+/// Addresses are picked busiest first, skipping any whose move would leave
+/// the receiving shard busier than the one it relieves.
+pub struct Rebalancer {
+    proposer: String,
+    imbalance_ratio: f64,
+    max_moves: usize,
+    voting_period: Duration,
+    required_quorum: f64,
+    /// Moves awaiting a vote, by proposal id.
+    pending: HashMap<String, Vec<AddressMove>>,
+}
+
+impl Rebalancer {
+    /// A rebalancer submitting its proposals as `proposer`.
+    pub fn new(proposer: &str) -> Self {
+        Rebalancer {
+            proposer: proposer.to_string(),
+            imbalance_ratio: DEFAULT_IMBALANCE_RATIO,
+            max_moves: DEFAULT_MAX_MOVES,
+            voting_period: Duration::hours(DEFAULT_VOTING_PERIOD_HOURS),
+            required_quorum: 0.5,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn with_imbalance_ratio(mut self, ratio: f64) -> Self {
+        self.imbalance_ratio = ratio;
+        self
+    }
+
+    pub fn with_max_moves(mut self, max_moves: usize) -> Self {
+        self.max_moves = max_moves;
+        self
+    }
+
+    pub fn with_voting_period(mut self, voting_period: Duration) -> Self {
+        self.voting_period = voting_period;
+        self
+    }
+
+    pub fn with_required_quorum(mut self, required_quorum: f64) -> Self {
+        self.required_quorum = required_quorum;
+        self
+    }
+
+    pub fn pending_moves(&self, proposal_id: &str) -> Option<&[AddressMove]> {
+        self.pending.get(proposal_id).map(Vec::as_slice)
+    }
+
+    /// The moves that would even out the busiest and the quietest shard, or
+    /// none if their load is within the imbalance ratio.
+    pub fn plan(&self, sharding_manager: &ShardingManager) -> Result<Vec<AddressMove>> {
+        let metrics = sharding_manager.load_metrics()?;
+        let (hot, cold) = match (
+            metrics.iter().max_by_key(|m| (m.address_activity, std::cmp::Reverse(m.shard_id))),
+            metrics.iter().min_by_key(|m| (m.address_activity, m.shard_id)),
+        ) {
+            (Some(hot), Some(cold)) if hot.shard_id != cold.shard_id => (hot, cold),
+            _ => return Ok(vec![]),
+        };
+        if (hot.address_activity as f64) <= self.imbalance_ratio * cold.address_activity.max(1) as f64 {
+            return Ok(vec![]);
+        }
+
+        let excess = (hot.address_activity - cold.address_activity) / 2;
+        let mut moved = 0;
+        let mut moves = Vec::new();
+        for (address, activity) in sharding_manager.hot_addresses(hot.shard_id, usize::MAX)? {
+            if moves.len() >= self.max_moves || moved >= excess {
+                break;
+            }
+            let already_pending = self.pending.values().flatten().any(|m| m.address == address);
+            if already_pending || moved + activity > excess {
+                continue;
+            }
+            moved += activity;
+            moves.push(AddressMove { address, from_shard: hot.shard_id, to_shard: cold.shard_id });
+        }
+        Ok(moves)
+    }
+
+    /// Puts the current plan to a vote. Returns the proposal id, or `None`
+    /// when the shards are balanced enough.
+    pub fn propose(&mut self, sharding_manager: &ShardingManager, governance: &mut DemocraticSystem) -> Result<Option<String>> {
+        let moves = self.plan(sharding_manager)?;
+        let (from_shard, to_shard) = match moves.first() {
+            Some(first) => (first.from_shard, first.to_shard),
+            None => return Ok(None),
+        };
+        let addresses: Vec<&str> = moves.iter().map(|m| m.address.as_str()).collect();
+        let proposal_id = governance.create_proposal(
+            format!("Rebalance shard {} into shard {}", from_shard, to_shard),
+            format!("Move {} addresses from shard {} to shard {}: {}", moves.len(), from_shard, to_shard, addresses.join(", ")),
+            self.proposer.clone(),
+            self.voting_period,
+            ProposalType::NetworkUpgrade,
+            ProposalCategory::Technical,
+            self.required_quorum,
+            None,
+        ).map_err(Error::GovernanceError)?;
+        info!("Proposed moving {} addresses from shard {} to shard {} as {}", moves.len(), from_shard, to_shard, proposal_id);
+        self.pending.insert(proposal_id.clone(), moves);
+        Ok(Some(proposal_id))
+    }
+
+    /// Applies the moves of proposals that passed and forgets those that were
+    /// rejected. Returns the moves applied.
+    pub fn apply_passed(&mut self, sharding_manager: &mut ShardingManager, governance: &mut DemocraticSystem) -> Result<Vec<AddressMove>> {
+        let mut applied = Vec::new();
+        let proposal_ids: Vec<String> = self.pending.keys().cloned().collect();
+        for proposal_id in proposal_ids {
+            let status = governance.get_proposal(&proposal_id).map(|proposal| proposal.status.clone());
+            match status {
+                Some(ProposalStatus::Active) => continue,
+                Some(ProposalStatus::Passed) => {
+                    for address_move in self.pending.remove(&proposal_id).unwrap_or_default() {
+                        match sharding_manager.move_address(&address_move.address, address_move.to_shard) {
+                            Ok(()) => applied.push(address_move),
+                            Err(e) => warn!("Skipping move of {} in {}: {}", address_move.address, proposal_id, e),
+                        }
+                    }
+                    governance.mark_as_implemented(&proposal_id).map_err(Error::GovernanceError)?;
+                }
+                _ => {
+                    self.pending.remove(&proposal_id);
+                }
+            }
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Transaction;
+    use crate::clock::MockClock;
+    use crate::currency::CurrencyType;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    fn pay(manager: &mut ShardingManager, from: &str, to: &str, times: usize) {
+        let keypair = Keypair::generate(&mut OsRng {});
+        for _ in 0..times {
+            let mut transaction = Transaction::new(from.to_string(), to.to_string(), 1.0, CurrencyType::BasicNeeds, 1000);
+            transaction.sign(&keypair).unwrap();
+            manager.process_transaction(0, &transaction).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_hot_addresses_moved_after_vote() {
+        let mut manager = ShardingManager::new(2, 10);
+        for address in ["Alice", "Bob", "Carol", "Dave"] {
+            manager.add_address_to_shard(address.to_string(), 0);
+            manager.initialize_balance(address.to_string(), CurrencyType::BasicNeeds, 100.0).unwrap();
+        }
+        pay(&mut manager, "Alice", "Bob", 6);
+        pay(&mut manager, "Carol", "Dave", 2);
+        let metrics = manager.load_metrics().unwrap();
+        assert_eq!((metrics[0].transaction_count, metrics[0].account_count), (8, 4));
+        assert_eq!(metrics[1].transaction_count, 0);
+
+        let clock = MockClock::new();
+        let mut governance = DemocraticSystem::new().with_clock(clock.clone().into());
+        let mut rebalancer = Rebalancer::new("rebalancer").with_voting_period(Duration::hours(1));
+        let proposal_id = rebalancer.propose(&manager, &mut governance).unwrap().unwrap();
+        let addresses: Vec<&str> = rebalancer.pending_moves(&proposal_id).unwrap().iter().map(|m| m.address.as_str()).collect();
+        assert_eq!(addresses, vec!["Alice", "Carol"], "moving Bob as well would overload shard 1");
+
+        assert!(rebalancer.apply_passed(&mut manager, &mut governance).unwrap().is_empty(), "nothing moves before the vote");
+        governance.vote("member".to_string(), proposal_id.clone(), true, 1.0).unwrap();
+        clock.advance(std::time::Duration::from_secs(2 * 3600));
+        governance.tally_votes(&proposal_id).unwrap();
+
+        assert_eq!(rebalancer.apply_passed(&mut manager, &mut governance).unwrap().len(), 2);
+        assert_eq!(manager.get_shard_for_address("Carol"), 1);
+        assert_eq!(manager.get_balance("Alice".to_string(), CurrencyType::BasicNeeds).unwrap(), 94.0);
+        let metrics = manager.load_metrics().unwrap();
+        assert_eq!((metrics[1].account_count, metrics[1].address_activity), (2, 8));
+        assert_eq!(metrics[0].address_activity, 8);
+        assert_eq!(governance.get_proposal(&proposal_id).unwrap().status, ProposalStatus::Implemented);
+        assert!(rebalancer.plan(&manager).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::blockchain::Block;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use super::ShardingError;
+use crate::network::{Packet, PacketType};
+use super::PreparedTransfer;
+
+/// Shard state is published under `/icn/shard/<id>/root` and
+/// `/icn/shard/<id>/state` by every member of the shard.
+pub const SHARD_NAME_PREFIX: &str = "/icn/shard/";
+/// Blocks of the shard chain handed to a new member along with its state.
+pub const RECENT_SHARD_BLOCKS: usize = 64;
+const ROOT_COMPONENT: &str = "root";
+const STATE_COMPONENT: &str = "state";
+
+/// Outgoing interests produced by the state sync, addressed by peer id.
+pub type ShardSyncRequests = Vec<(String, Packet)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardName {
+    /// `/icn/shard/<id>/root`
+    Root(u64),
+    /// `/icn/shard/<id>/state`
+    State(u64),
+}
+
+impl ShardName {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (shard_id, component) = name.strip_prefix(SHARD_NAME_PREFIX)?.split_once('/')?;
+        let shard_id = shard_id.parse().ok()?;
+        match component {
+            ROOT_COMPONENT => Some(ShardName::Root(shard_id)),
+            STATE_COMPONENT => Some(ShardName::State(shard_id)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ShardName::Root(shard_id) => format!("{}{}/{}", SHARD_NAME_PREFIX, shard_id, ROOT_COMPONENT),
+            ShardName::State(shard_id) => format!("{}{}/{}", SHARD_NAME_PREFIX, shard_id, STATE_COMPONENT),
+        }
+    }
+}
+
+pub fn is_shard_name(name: &str) -> bool {
+    name.starts_with(SHARD_NAME_PREFIX)
+}
+
+/// Everything a new member needs to start validating a shard. Entries are
+/// sorted so that every member holding the same state computes the same root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardSnapshot {
+    pub shard_id: u64,
+    pub balances: Vec<(String, CurrencyType, f64)>,
+    pub locked_funds: Vec<(String, CurrencyType, f64)>,
+    pub prepared: Vec<(String, PreparedTransfer)>,
+    pub recent_blocks: Vec<Block>,
+}
+
+impl ShardSnapshot {
+    /// Hex SHA-256 committing to the whole snapshot.
+    pub fn root(&self) -> String {
+        hex::encode(Sha256::digest(&self.to_bytes().unwrap_or_default()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| Error::ShardingError(ShardingError::Rejected(e.to_string())))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(|e| Error::ShardingError(ShardingError::Rejected(format!("Invalid shard snapshot: {}", e))))
+    }
+
+    /// Checks the snapshot against the state root the shard agreed on and
+    /// that its recent blocks form a valid chain.
+    pub fn verify(&self, root: &str) -> Result<()> {
+        if self.root() != root {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("Snapshot of shard {} does not match state root {}", self.shard_id, root))));
+        }
+        for block in &self.recent_blocks {
+            if block.hash != block.calculate_hash() {
+                return Err(Error::ShardingError(ShardingError::Rejected(format!("Shard block {} has an invalid hash", block.index))));
+            }
+        }
+        for pair in self.recent_blocks.windows(2) {
+            if pair[1].index != pair[0].index + 1 || pair[1].previous_hash != pair[0].hash {
+                return Err(Error::ShardingError(ShardingError::Rejected(format!("Shard block {} does not link to its parent", pair[1].index))));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Brings a node newly assigned to a shard up to date with the shard's
+/// members, without doing any I/O itself. Every member is asked for its state
+/// root; once a majority of members report the same root, the full snapshot
+/// is fetched from one of them and accepted only if it hashes to that root.
+/// A member serving a mismatching snapshot is skipped for the next one.
+#[derive(Debug)]
+pub struct ShardStateSync {
+    shard_id: u64,
+    members: Vec<String>,
+    roots: HashMap<String, String>,
+    state_request: Option<String>,
+    rejected: HashSet<String>,
+    snapshot: Option<ShardSnapshot>,
+}
+
+impl ShardStateSync {
+    pub fn new(shard_id: u64, members: Vec<String>) -> Self {
+        ShardStateSync {
+            shard_id,
+            members,
+            roots: HashMap::new(),
+            state_request: None,
+            rejected: HashSet::new(),
+            snapshot: None,
+        }
+    }
+
+    pub fn shard_id(&self) -> u64 {
+        self.shard_id
+    }
+
+    /// The interests to send first: a state root request to every member.
+    pub fn start(&self) -> ShardSyncRequests {
+        self.members.iter()
+            .map(|member| (member.clone(), Packet::interest(&ShardName::Root(self.shard_id).name())))
+            .collect()
+    }
+
+    /// The root reported by a majority of the shard's members, if any.
+    pub fn agreed_root(&self) -> Option<&String> {
+        let quorum = self.members.len() / 2 + 1;
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        for root in self.roots.values() {
+            *counts.entry(root).or_insert(0) += 1;
+        }
+        counts.into_iter().find(|(_, count)| *count >= quorum).map(|(root, _)| root)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// The verified snapshot, once the sync is complete.
+    pub fn take_snapshot(&mut self) -> Option<ShardSnapshot> {
+        self.snapshot.take()
+    }
+
+    /// Takes a Data packet or nack a member sent in answer to one of our
+    /// interests and returns the interests to send next. An invalid snapshot,
+    /// or data signed with a key other than its publisher's, is an error;
+    /// `retry` then asks another member.
+    pub fn on_data(&mut self, peer_id: &str, data: &Packet) -> Result<ShardSyncRequests> {
+        if !self.members.iter().any(|member| member == peer_id) {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("{} is not a member of shard {}", peer_id, self.shard_id))));
+        }
+        if data.signature.is_some() && !data.verify_origin() {
+            return Err(Error::ShardingError(ShardingError::Rejected(format!("{} from {} is not signed by its publisher", data.name, peer_id))));
+        }
+        match (ShardName::parse(&data.name), &data.packet_type) {
+            (Some(ShardName::Root(shard_id)), PacketType::Data) if shard_id == self.shard_id => {
+                let root = String::from_utf8(data.content.clone())
+                    .map_err(|_| Error::ShardingError(ShardingError::Rejected(format!("Invalid state root from {}", peer_id))))?;
+                debug!("{} reports state root {} for shard {}", peer_id, root, shard_id);
+                self.roots.insert(peer_id.to_string(), root);
+                Ok(self.retry())
+            }
+            (Some(ShardName::State(shard_id)), PacketType::Data) if shard_id == self.shard_id => {
+                if self.state_request.as_deref() != Some(peer_id) {
+                    return Err(Error::ShardingError(ShardingError::Rejected(format!("Unrequested state of shard {} from {}", shard_id, peer_id))));
+                }
+                self.state_request = None;
+                let root = self.agreed_root().cloned()
+                    .ok_or_else(|| Error::ShardingError(ShardingError::Rejected(format!("No agreed state root for shard {}", shard_id))))?;
+                let verified = ShardSnapshot::from_bytes(&data.content)
+                    .and_then(|snapshot| snapshot.verify(&root).map(|_| snapshot));
+                match verified {
+                    Ok(snapshot) => {
+                        info!("Fetched state of shard {} from {} at root {}", shard_id, peer_id, root);
+                        self.snapshot = Some(snapshot);
+                        Ok(vec![])
+                    }
+                    Err(e) => {
+                        self.rejected.insert(peer_id.to_string());
+                        Err(e)
+                    }
+                }
+            }
+            (Some(ShardName::State(_)), PacketType::Nack(reason)) if self.state_request.as_deref() == Some(peer_id) => {
+                warn!("{} cannot serve the state of shard {}: {:?}", peer_id, self.shard_id, reason);
+                self.state_request = None;
+                self.rejected.insert(peer_id.to_string());
+                Ok(self.retry())
+            }
+            (Some(_), PacketType::Nack(_)) => Ok(vec![]),
+            _ => Err(Error::ShardingError(ShardingError::Rejected(format!("Unexpected shard sync packet {} from {}", data.name, peer_id)))),
+        }
+    }
+
+    /// Requests the snapshot from a member that reported the agreed root,
+    /// unless a request is outstanding or the sync is complete.
+    pub fn retry(&mut self) -> ShardSyncRequests {
+        if self.snapshot.is_some() || self.state_request.is_some() {
+            return vec![];
+        }
+        let root = match self.agreed_root() {
+            Some(root) => root.clone(),
+            None => return vec![],
+        };
+        let mut candidates: Vec<&String> = self.roots.iter()
+            .filter(|(member, reported)| **reported == root && !self.rejected.contains(*member))
+            .map(|(member, _)| member)
+            .collect();
+        candidates.sort();
+        match candidates.first() {
+            Some(member) => {
+                let member = (*member).clone();
+                self.state_request = Some(member.clone());
+                vec![(member, Packet::interest(&ShardName::State(self.shard_id).name()))]
+            }
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sharding::ShardingManager;
+
+    fn serve(manager: &ShardingManager, name: ShardName) -> Packet {
+        match name {
+            ShardName::Root(shard_id) => Packet::data(&name.name(), manager.shard_state_root(shard_id).unwrap().into_bytes()),
+            ShardName::State(shard_id) => Packet::data(&name.name(), manager.shard_snapshot(shard_id).unwrap().to_bytes().unwrap()),
+        }
+    }
+
+    fn members() -> Vec<String> {
+        vec!["member1".to_string(), "member2".to_string(), "member3".to_string()]
+    }
+
+    #[test]
+    fn test_shard_names() {
+        assert_eq!(ShardName::parse("/icn/shard/2/root"), Some(ShardName::Root(2)));
+        assert_eq!(ShardName::parse(&ShardName::State(2).name()), Some(ShardName::State(2)));
+        assert_eq!(ShardName::parse("/icn/shard/2/other"), None);
+    }
+
+    #[test]
+    fn test_state_fetched_once_members_agree() {
+        let mut honest = ShardingManager::new(2, 10);
+        honest.add_address_to_shard("Alice".to_string(), 1);
+        honest.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 250.0).unwrap();
+        let mut forged = ShardingManager::new(2, 10);
+        forged.add_address_to_shard("Alice".to_string(), 1);
+        forged.initialize_balance("Alice".to_string(), CurrencyType::BasicNeeds, 9000.0).unwrap();
+
+        let mut sync = ShardStateSync::new(1, members());
+        assert_eq!(sync.start().len(), 3);
+        assert!(sync.on_data("member1", &serve(&honest, ShardName::Root(1))).unwrap().is_empty());
+        assert!(sync.on_data("member2", &serve(&forged, ShardName::Root(1))).unwrap().is_empty());
+        let requests = sync.on_data("member3", &serve(&honest, ShardName::Root(1))).unwrap();
+        assert_eq!(sync.agreed_root(), Some(&honest.shard_state_root(1).unwrap()));
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "member1");
+
+        // member1 turns out to serve a snapshot that does not match the root
+        assert!(sync.on_data("member1", &serve(&forged, ShardName::State(1))).is_err());
+        let requests = sync.retry();
+        assert_eq!(requests[0].0, "member3");
+        assert!(sync.on_data("member3", &serve(&honest, ShardName::State(1))).unwrap().is_empty());
+        let snapshot = sync.take_snapshot().unwrap();
+
+        let mut joining = ShardingManager::new(2, 10);
+        joining.add_address_to_shard("Alice".to_string(), 1);
+        joining.join_shard(snapshot).unwrap();
+        assert_eq!(joining.get_balance("Alice".to_string(), CurrencyType::BasicNeeds).unwrap(), 250.0);
+        assert_eq!(joining.get_current_shard_id(), 1);
+    }
+}
@@ -0,0 +1,477 @@
+// src/sim/mod.rs
+//! Runs several nodes in one process over a simulated network, so that
+//! consensus, sharding and gossip can be tested deterministically. Nothing
+//! is sent over sockets and no time passes while waiting: messages are
+//! events on a virtual clock, delivered in order of arrival time, and
+//! latency, losses and partitions come from a seeded `SimNetwork`. The
+//! nodes' own clocks follow the virtual one.
+
+pub mod network;
+pub mod scenario;
+
+pub use network::{SimNetwork, SimStats, Topology};
+pub use scenario::{Scenario, Step};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use ed25519_dalek::Keypair;
+use tracing::{debug, warn};
+use crate::blockchain::{Block, Blockchain, ChainSpec};
+use crate::clock::MockClock;
+use crate::consensus::SignedVote;
+use crate::error::Result;
+use crate::identity::DecentralizedIdentity;
+use crate::network::{chain_data, Message, Packet, PacketType};
+use crate::{ForwardAction, IcnNode, LOCAL_FACE};
+
+/// How to set up a simulation.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub nodes: usize,
+    pub topology: Topology,
+    /// One-way delay of every link.
+    pub latency: Duration,
+    /// Most a delivery may randomly take longer than `latency`.
+    pub jitter: Duration,
+    /// Share of messages lost in transit.
+    pub drop_rate: f64,
+    /// Seeds the losses and jitter; runs with the same seed are identical.
+    pub seed: u64,
+    /// The chain every node starts from; its allocations fund the transfers
+    /// scenarios submit.
+    pub spec: ChainSpec,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            nodes: 4,
+            topology: Topology::FullMesh,
+            latency: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+            seed: 0,
+            spec: ChainSpec::default(),
+        }
+    }
+}
+
+enum Event {
+    Deliver { from: usize, to: usize, message: Box<Message> },
+    Step(Step),
+}
+
+/// A set of nodes on a simulated network. Node `i` has the peer id
+/// `node<i>`, which is also the face its packets arrive on at its
+/// neighbors, and its own DID, known to every node so that the content it
+/// publishes verifies everywhere.
+pub struct Simulation {
+    nodes: Vec<Arc<IcnNode>>,
+    ids: Vec<String>,
+    identities: Vec<(String, Keypair)>,
+    /// Keys of the members voting in scenarios.
+    voters: HashMap<String, Keypair>,
+    pub network: SimNetwork,
+    clock: MockClock,
+    now: Duration,
+    /// Pending events by arrival time, in the order they were scheduled.
+    events: BTreeMap<(Duration, u64), Event>,
+    next_event: u64,
+    /// Hashes of the transactions and blocks each node has gossiped on.
+    seen: Vec<HashSet<String>>,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let mut network = SimNetwork::new(config.nodes, config.topology, config.seed);
+        network.set_latency(config.latency, config.jitter);
+        network.set_drop_rate(config.drop_rate);
+
+        let ids: Vec<String> = (0..config.nodes).map(|i| format!("node{}", i)).collect();
+        let identities: Vec<_> = (0..config.nodes)
+            .map(|_| DecentralizedIdentity::new(HashMap::new()))
+            .collect();
+        let clock = MockClock::new();
+        let nodes: Vec<Arc<IcnNode>> = (0..config.nodes).map(|i| {
+            let node = IcnNode::new();
+            *node.blockchain.write().unwrap() = Blockchain::with_spec(config.spec.clone());
+            let node = node.with_clock(clock.clone().into());
+            for (identity, _) in &identities {
+                node.did_manager.write().unwrap().add_did(identity.clone());
+            }
+            for j in (0..config.nodes).filter(|&j| j != i) {
+                node.bind_face(&ids[j], Self::address(j));
+            }
+            Arc::new(node)
+        }).collect();
+
+        Simulation {
+            nodes,
+            ids,
+            identities: identities.into_iter().map(|(identity, keypair)| (identity.id, keypair)).collect(),
+            voters: HashMap::new(),
+            network,
+            clock,
+            now: Duration::ZERO,
+            events: BTreeMap::new(),
+            next_event: 0,
+            seen: vec![HashSet::new(); config.nodes],
+        }
+    }
+
+    /// The address node `i` is known by in FIB next hops.
+    pub fn address(i: usize) -> SocketAddr {
+        SocketAddr::from(([10, 0, (i / 250) as u8, (i % 250) as u8 + 1], 7000))
+    }
+
+    pub fn node(&self, i: usize) -> &Arc<IcnNode> {
+        &self.nodes[i]
+    }
+
+    pub fn nodes(&self) -> &[Arc<IcnNode>] {
+        &self.nodes
+    }
+
+    /// Time on the virtual clock since the simulation started.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// The clock of every node, moved along with the virtual clock.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    fn advance_to(&mut self, time: Duration) {
+        if time > self.now {
+            self.clock.advance(time - self.now);
+            self.now = time;
+        }
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.network.stats
+    }
+
+    /// Chain heights of the nodes, in order.
+    pub fn heights(&self) -> Vec<u64> {
+        self.nodes.iter().map(|node| node.blockchain.read().unwrap().height()).collect()
+    }
+
+    /// Schedules the steps of `scenario`, timed from now.
+    pub fn load(&mut self, scenario: Scenario) -> Result<()> {
+        for (_, step) in &scenario.steps {
+            if let Some(node) = step.nodes().into_iter().find(|&node| node >= self.nodes.len()) {
+                return Err(scenario::invalid(format!("no node {} among {}", node, self.nodes.len())));
+            }
+        }
+        for (time, step) in scenario.steps {
+            self.schedule(self.now + time, Event::Step(step));
+        }
+        Ok(())
+    }
+
+    /// Runs `scenario` and then every message it set off.
+    pub fn run_scenario(&mut self, scenario: Scenario) -> Result<&SimStats> {
+        self.load(scenario)?;
+        self.run();
+        Ok(self.stats())
+    }
+
+    /// Processes events until none are left.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Processes the events due up to `time` after the start, then moves the
+    /// clock to it.
+    pub fn run_until(&mut self, time: Duration) {
+        while self.events.keys().next().is_some_and(|&(at, _)| at <= time) {
+            self.step();
+        }
+        self.advance_to(time);
+    }
+
+    /// Processes the next event, returning whether there was one.
+    pub fn step(&mut self) -> bool {
+        let ((at, _), event) = match self.events.pop_first() {
+            Some(next) => next,
+            None => return false,
+        };
+        self.advance_to(at);
+        match event {
+            Event::Deliver { from, to, message } => {
+                self.network.stats.delivered += 1;
+                self.deliver(from, to, *message);
+            }
+            Event::Step(step) => self.apply(step),
+        }
+        true
+    }
+
+    /// Sends a message from one node to another over the simulated network.
+    pub fn send(&mut self, from: usize, to: usize, message: Message) {
+        if let Some(delay) = self.network.transmit(from, to) {
+            self.schedule(self.now + delay, Event::Deliver { from, to, message: Box::new(message) });
+        }
+    }
+
+    fn schedule(&mut self, at: Duration, event: Event) {
+        self.events.insert((at, self.next_event), event);
+        self.next_event += 1;
+    }
+
+    fn index(&self, peer_id: &str) -> Option<usize> {
+        self.ids.iter().position(|id| id == peer_id)
+    }
+
+    /// Passes a transaction or block on to every neighbor but the one it came
+    /// from, the first time a node sees it.
+    fn gossip(&mut self, node: usize, from: Option<usize>, hash: String, message: Message) -> bool {
+        if !self.seen[node].insert(hash) {
+            return false;
+        }
+        for neighbor in self.network.neighbors(node) {
+            if Some(neighbor) != from {
+                self.send(node, neighbor, message.clone());
+            }
+        }
+        true
+    }
+
+    fn deliver(&mut self, from: usize, to: usize, message: Message) {
+        let node = Arc::clone(&self.nodes[to]);
+        let sender = self.ids[from].clone();
+        match message {
+            Message::Packet(packet) if packet.packet_type != PacketType::Interest && chain_data::is_chain_name(&packet.name) => {
+                let requests = node.chain.call_blocking(move |chain| chain.accept_chain_data(&sender, &packet));
+                match requests.and_then(|requests| requests) {
+                    Ok(requests) => self.send_requests(to, requests),
+                    Err(e) => warn!("{} rejected chain data from {}: {}", self.ids[to], self.ids[from], e),
+                }
+            }
+            Message::Packet(packet) => match node.forward(packet, &sender) {
+                Ok(actions) => self.dispatch(to, Some(from), actions),
+                Err(e) => warn!("{} failed to process a packet from {}: {}", self.ids[to], self.ids[from], e),
+            },
+            Message::Transaction(transaction) => {
+                if self.gossip(to, Some(from), transaction.hash(), Message::Transaction(transaction.clone())) {
+                    if let Err(e) = node.with_chain(move |blockchain| blockchain.add_transaction(*transaction)) {
+                        warn!("{} dropped a transaction: {}", self.ids[to], e);
+                    }
+                }
+            }
+            Message::Block(block) => {
+                if self.gossip(to, Some(from), block.hash.clone(), Message::Block(block.clone())) {
+                    // the simulation does not model votes
+                    let requests = node.chain.call_blocking(move |chain| chain.accept_block(&sender, block, None));
+                    match requests.and_then(|result| result.map(|(requests, _)| requests)) {
+                        Ok(requests) => self.send_requests(to, requests),
+                        Err(e) => warn!("{} rejected a block from {}: {}", self.ids[to], self.ids[from], e),
+                    }
+                }
+            }
+            Message::Status { height, .. } => {
+                match node.chain.call_blocking(move |chain| chain.accept_status(&sender, height)) {
+                    Ok((requests, ahead)) => {
+                        if ahead {
+                            let status = node.status();
+                            self.send(to, from, status);
+                        }
+                        self.send_requests(to, requests);
+                    }
+                    Err(e) => warn!("{} failed to handle status from {}: {}", self.ids[to], self.ids[from], e),
+                }
+            }
+            other => debug!("{} ignoring {:?} from {}", self.ids[to], std::mem::discriminant(&other), self.ids[from]),
+        }
+    }
+
+    fn send_requests(&mut self, from: usize, requests: crate::network::sync::SyncRequests) {
+        for (peer_id, message) in requests {
+            match self.index(&peer_id) {
+                Some(to) => self.send(from, to, message),
+                None => warn!("No simulated node {}", peer_id),
+            }
+        }
+    }
+
+    /// Sends the packets chosen by the forwarder of `node`, never returning an
+    /// interest to the node it came from.
+    fn dispatch(&mut self, node: usize, incoming: Option<usize>, actions: Vec<ForwardAction>) {
+        for action in actions {
+            let (to, packet) = match action {
+                ForwardAction::ToFace(face, packet) => (self.index(&face), packet),
+                ForwardAction::ToNextHop(next_hop, packet) => {
+                    let to = (0..self.nodes.len()).find(|&i| Self::address(i) == next_hop);
+                    if to.is_some() && to == incoming {
+                        continue;
+                    }
+                    (to, packet)
+                }
+            };
+            match to {
+                Some(to) => self.send(node, to, Message::Packet(packet)),
+                None => warn!("{} has no simulated peer for {}", self.ids[node], packet.name),
+            }
+        }
+    }
+
+    fn apply(&mut self, step: Step) {
+        debug!("{:?} at {:?}", step, self.now);
+        let result = match step {
+            Step::Submit { node, transaction } => {
+                let hash = transaction.hash();
+                let message = Message::Transaction(transaction.clone());
+                self.nodes[node].with_chain(move |blockchain| blockchain.add_transaction(*transaction))
+                    .and_then(|result| result)
+                    .map(|_| {
+                        self.gossip(node, None, hash, message);
+                    })
+            }
+            Step::ProduceBlock { node } => {
+                let author = self.ids[node].clone();
+                self.nodes[node].with_chain(move |blockchain| {
+                    blockchain.create_block(author)?;
+                    Ok::<Block, crate::Error>(blockchain.chain.last().unwrap().clone())
+                }).and_then(|result| result).map(|block| {
+                    self.gossip(node, None, block.hash.clone(), Message::Block(block));
+                })
+            }
+            Step::Vote { node, member, approve } => {
+                let keypair = self.voters.entry(member.clone()).or_insert_with(|| Keypair::generate(&mut rand::rngs::OsRng {}));
+                let keypair = Keypair::from_bytes(&keypair.to_bytes()).expect("keypair bytes round-trip");
+                self.nodes[node].with_chain(move |blockchain| {
+                    if !blockchain.consensus.is_validator(&member) {
+                        blockchain.consensus.add_member(member.clone(), true);
+                    }
+                    blockchain.consensus.register_key(&member, &keypair.public).map_err(crate::Error::ConsensusError)?;
+                    let tip = blockchain.chain.last().unwrap();
+                    let vote = SignedVote::new(member, tip.hash.clone(), tip.index, approve, &keypair);
+                    blockchain.submit_vote(&vote).map(|_| ())
+                }).and_then(|result| result)
+            }
+            Step::AnnounceStatus { node } => {
+                let status = self.nodes[node].status();
+                for neighbor in self.network.neighbors(node) {
+                    self.send(node, neighbor, status.clone());
+                }
+                Ok(())
+            }
+            Step::Fund { node, address, amount } => self.nodes[node].sharding_manager.write().unwrap()
+                .initialize_balance(address, crate::CurrencyType::BasicNeeds, amount),
+            Step::CrossShard { node, transaction } => self.nodes[node].process_cross_shard_transaction(&transaction),
+            Step::Publish { node, name, content } => {
+                let (did_id, keypair) = &self.identities[node];
+                let data = Packet::data(&name, content).signed(did_id, keypair);
+                self.nodes[node].register_prefix(&name);
+                self.nodes[node].content_store.write().unwrap().add_packet(&data);
+                for other in (0..self.nodes.len()).filter(|&other| other != node) {
+                    if let Some(hop) = self.network.next_hop(other, node) {
+                        self.nodes[other].fib.write().unwrap().add_entry(name.clone(), Self::address(hop));
+                    }
+                }
+                Ok(())
+            }
+            Step::Fetch { node, name } => self.nodes[node].forward(Packet::interest(&name), LOCAL_FACE)
+                .map(|actions| self.dispatch(node, None, actions)),
+            Step::Partition(groups) => {
+                self.network.partition(self.nodes.len(), &groups);
+                Ok(())
+            }
+            Step::Heal => {
+                self.network.heal();
+                Ok(())
+            }
+            Step::SetDropRate(rate) => {
+                self.network.set_drop_rate(rate);
+                Ok(())
+            }
+            Step::SetLatency(latency) => {
+                self.network.set_latency(latency, Duration::ZERO);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            warn!("Scenario step failed at {:?}: {}", self.now, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::CurrencyType;
+
+    fn config(topology: Topology) -> SimConfig {
+        let spec = ChainSpec::default()
+            .with_allocation("Alice", 100.0, CurrencyType::BasicNeeds)
+            .with_allocation("Bob", 100.0, CurrencyType::BasicNeeds);
+        SimConfig { topology, spec, ..SimConfig::default() }
+    }
+
+    #[test]
+    fn test_gossip_and_partition_heal() {
+        let mut sim = Simulation::new(config(Topology::Line));
+        let scenario = Scenario::parse("
+            at 0ms partition 0,1 2,3
+            at 0ms submit 0 Alice Bob 10
+            at 50ms block 0
+            at 100ms heal
+            at 100ms status 1
+        ").unwrap();
+        sim.load(scenario).unwrap();
+
+        sim.run_until(Duration::from_millis(49));
+        // The transaction crossed one hop and stopped at the partition
+        assert_eq!(sim.node(1).blockchain.read().unwrap().pending_transactions.len(), 1);
+        assert!(sim.node(2).blockchain.read().unwrap().pending_transactions.is_empty());
+        assert_eq!(sim.stats().partitioned, 1);
+
+        sim.run_until(Duration::from_millis(99));
+        assert_eq!(sim.heights(), vec![2, 2, 1, 1]);
+
+        sim.run();
+        assert_eq!(sim.heights(), vec![2, 2, 2, 1]);
+        // Status only reaches the neighbors; once node 2 announces, node 3 follows
+        sim.load(Scenario::new().at(Duration::ZERO, Step::AnnounceStatus { node: 2 })).unwrap();
+        sim.run();
+        assert_eq!(sim.heights(), vec![2, 2, 2, 2]);
+        let tip = sim.node(0).blockchain.read().unwrap().chain[1].hash.clone();
+        assert_eq!(sim.node(3).blockchain.read().unwrap().chain[1].hash, tip);
+    }
+
+    #[test]
+    fn test_same_seed_same_run() {
+        let run = |seed| {
+            let mut sim = Simulation::new(SimConfig {
+                nodes: 5,
+                jitter: Duration::from_millis(20),
+                drop_rate: 0.3,
+                seed,
+                ..config(Topology::FullMesh)
+            });
+            let scenario = Scenario::parse("
+                at 0ms publish 4 /coop/doc minutes of the assembly
+                at 10ms fetch 0 /coop/doc
+                at 10ms submit 1 Alice Bob 3
+                at 20ms submit 2 Bob Carol 1
+            ").unwrap();
+            sim.run_scenario(scenario).unwrap().clone()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+
+        let mut sim = Simulation::new(SimConfig { topology: Topology::Line, ..SimConfig::default() });
+        sim.run_scenario(Scenario::parse("at 0ms publish 3 /coop/doc hello\nat 1ms fetch 0 /coop/doc").unwrap()).unwrap();
+        let (content, _) = sim.node(0).content_store.read().unwrap().lookup("/coop/doc", false).unwrap();
+        assert_eq!(content, b"hello");
+        // Three hops there and three back
+        assert_eq!(sim.now(), Duration::from_millis(61));
+        assert_eq!(sim.clock().elapsed(), sim.now());
+        assert!(Scenario::parse("at 5 block 0").is_err());
+        assert!(sim.load(Scenario::parse("at 0ms block 9").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// src/sim/network.rs
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::Duration;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+/// How the simulated nodes are linked to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Every node is linked to every other.
+    FullMesh,
+    /// Node `i` is linked to `i - 1` and `i + 1`, so messages between the
+    /// ends cross every node in between.
+    Line,
+    /// A line whose ends are linked as well.
+    Ring,
+}
+
+/// What happened to the messages sent over the simulated network.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost to the drop rate.
+    pub dropped: u64,
+    /// Refused because sender and receiver were on different sides of a partition.
+    pub partitioned: u64,
+}
+
+/// The links between simulated nodes and the conditions on them. Losses are
+/// drawn from a generator seeded by the simulation, so that a run is the
+/// same every time.
+pub struct SimNetwork {
+    links: BTreeSet<(usize, usize)>,
+    latency: Duration,
+    jitter: Duration,
+    drop_rate: f64,
+    link_latency: HashMap<(usize, usize), Duration>,
+    /// The side of the partition each node is on, if the network is partitioned.
+    sides: Option<Vec<usize>>,
+    rng: StdRng,
+    pub stats: SimStats,
+}
+
+impl SimNetwork {
+    pub fn new(nodes: usize, topology: Topology, seed: u64) -> Self {
+        let links = (0..nodes)
+            .flat_map(|a| (a + 1..nodes).map(move |b| (a, b)))
+            .filter(|&(a, b)| match topology {
+                Topology::FullMesh => true,
+                Topology::Line => b == a + 1,
+                Topology::Ring => b == a + 1 || (a == 0 && b == nodes - 1 && nodes > 2),
+            })
+            .collect();
+        SimNetwork {
+            links,
+            latency: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+            link_latency: HashMap::new(),
+            sides: None,
+            rng: StdRng::seed_from_u64(seed),
+            stats: SimStats::default(),
+        }
+    }
+
+    /// Sets the one-way delay of every link without one of its own, and the
+    /// most a delivery may randomly take longer.
+    pub fn set_latency(&mut self, latency: Duration, jitter: Duration) {
+        self.latency = latency;
+        self.jitter = jitter;
+    }
+
+    pub fn set_link_latency(&mut self, a: usize, b: usize, latency: Duration) {
+        self.link_latency.insert(Self::key(a, b), latency);
+    }
+
+    /// Sets the share of messages lost in transit, between 0 and 1.
+    pub fn set_drop_rate(&mut self, drop_rate: f64) {
+        self.drop_rate = drop_rate.clamp(0.0, 1.0);
+    }
+
+    pub fn connect(&mut self, a: usize, b: usize) {
+        self.links.insert(Self::key(a, b));
+    }
+
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        self.links.remove(&Self::key(a, b));
+    }
+
+    /// Splits the nodes into `groups` that cannot reach each other until
+    /// `heal`. Nodes in none of the groups form one more group of their own.
+    pub fn partition(&mut self, nodes: usize, groups: &[Vec<usize>]) {
+        let mut sides = vec![groups.len(); nodes];
+        for (side, group) in groups.iter().enumerate() {
+            for &node in group {
+                sides[node] = side;
+            }
+        }
+        self.sides = Some(sides);
+    }
+
+    pub fn heal(&mut self) {
+        self.sides = None;
+    }
+
+    pub fn is_partitioned(&self, a: usize, b: usize) -> bool {
+        self.sides.as_ref().is_some_and(|sides| sides[a] != sides[b])
+    }
+
+    /// The nodes linked to `node`, whether or not a partition separates them.
+    pub fn neighbors(&self, node: usize) -> Vec<usize> {
+        self.links.iter()
+            .filter_map(|&(a, b)| if a == node { Some(b) } else if b == node { Some(a) } else { None })
+            .collect()
+    }
+
+    /// The neighbor of `from` on a shortest path to `to`.
+    pub fn next_hop(&self, from: usize, to: usize) -> Option<usize> {
+        let mut first_hops = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                return first_hops.get(&to).copied().filter(|&hop| hop != from);
+            }
+            for neighbor in self.neighbors(node) {
+                if !first_hops.contains_key(&neighbor) {
+                    let first_hop = if node == from { neighbor } else { first_hops[&node] };
+                    first_hops.insert(neighbor, first_hop);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        None
+    }
+
+    /// Decides the fate of a message from `from` to `to`: the delay after
+    /// which it arrives, or `None` if it never does.
+    pub fn transmit(&mut self, from: usize, to: usize) -> Option<Duration> {
+        self.stats.sent += 1;
+        if !self.links.contains(&Self::key(from, to)) || self.is_partitioned(from, to) {
+            self.stats.partitioned += 1;
+            return None;
+        }
+        if self.drop_rate > 0.0 && self.rng.gen::<f64>() < self.drop_rate {
+            self.stats.dropped += 1;
+            return None;
+        }
+        let latency = self.link_latency.get(&Self::key(from, to)).copied().unwrap_or(self.latency);
+        let jitter = match self.jitter.as_micros() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_micros(self.rng.gen_range(0, max + 1)),
+        };
+        Some(latency + jitter)
+    }
+
+    fn key(a: usize, b: usize) -> (usize, usize) {
+        (a.min(b), a.max(b))
+    }
+}
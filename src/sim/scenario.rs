@@ -0,0 +1,163 @@
+// src/sim/scenario.rs
+use std::time::Duration;
+use crate::blockchain::Transaction;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+
+/// Something a scenario makes happen at a point in simulated time.
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Queues a transaction at a node, which gossips it to the others.
+    Submit { node: usize, transaction: Box<Transaction> },
+    /// Seals a node's pending transactions in a block and gossips the block.
+    ProduceBlock { node: usize },
+    /// Signs a vote on the tip of a node's chain on behalf of `member`, who
+    /// is made a validator there first.
+    Vote { node: usize, member: String, approve: bool },
+    /// Sends a node's status to its neighbors, which sync from it if behind.
+    AnnounceStatus { node: usize },
+    /// Credits `address` in a node's shards.
+    Fund { node: usize, address: String, amount: f64 },
+    /// Processes a transfer on the shard workers of a node.
+    CrossShard { node: usize, transaction: Box<Transaction> },
+    /// Makes a node the producer of signed content under `name`, routing
+    /// every other node toward it.
+    Publish { node: usize, name: String, content: Vec<u8> },
+    /// Expresses an interest for `name` at a node.
+    Fetch { node: usize, name: String },
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    SetDropRate(f64),
+    SetLatency(Duration),
+}
+
+impl Step {
+    /// The nodes the step refers to.
+    pub fn nodes(&self) -> Vec<usize> {
+        match self {
+            Step::Submit { node, .. } | Step::ProduceBlock { node } | Step::Vote { node, .. }
+            | Step::AnnounceStatus { node } | Step::Fund { node, .. } | Step::CrossShard { node, .. }
+            | Step::Publish { node, .. } | Step::Fetch { node, .. } => vec![*node],
+            Step::Partition(groups) => groups.concat(),
+            Step::Heal | Step::SetDropRate(_) | Step::SetLatency(_) => vec![],
+        }
+    }
+}
+
+/// Steps to run against a simulation, each at a time after its start.
+///
+/// Scenarios are built in code with `at`, or parsed from a script of one
+/// step per line, `at <time> <command> <arguments>`, times being given in
+/// `ms` or `s`:
+///
+/// ```text
+/// # Two sides of a partition build on their own chains
+/// at 0ms   partition 0,1 2,3
+/// at 0ms   submit 0 Alice Bob 10
+/// at 50ms  block 0
+/// at 100ms heal
+/// at 100ms status 0
+/// at 200ms publish 1 /coop/doc hello
+/// at 250ms fetch 3 /coop/doc
+/// at 300ms vote 0 validator yes
+/// at 300ms fund 2 Alice 100
+/// at 310ms transfer 2 Alice Bob 5
+/// at 400ms drop 0.1
+/// at 400ms latency 25ms
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub steps: Vec<(Duration, Step)>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at(mut self, time: Duration, step: Step) -> Self {
+        self.steps.push((time, step));
+        self
+    }
+
+    pub fn parse(script: &str) -> Result<Self> {
+        let mut scenario = Scenario::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_line(line).map_err(|reason| invalid(format!("line {}: {}", number + 1, reason)))?;
+            scenario.steps.push(step);
+        }
+        Ok(scenario)
+    }
+}
+
+fn parse_line(line: &str) -> std::result::Result<(Duration, Step), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (time, command, args) = match words.as_slice() {
+        ["at", time, command, args @ ..] => (parse_duration(time)?, *command, args),
+        _ => return Err("expected `at <time> <command>`".to_string()),
+    };
+    let node = || args.first().ok_or("missing node")?.parse::<usize>().map_err(|e| format!("invalid node: {}", e));
+    let transfer = || match args {
+        [_, from, to, amount] => Ok(Transaction::new(
+            from.to_string(),
+            to.to_string(),
+            amount.parse().map_err(|e| format!("invalid amount: {}", e))?,
+            CurrencyType::BasicNeeds,
+            1000,
+        )),
+        _ => Err(format!("expected `{} <node> <from> <to> <amount>`", command)),
+    };
+    let step = match (command, args) {
+        ("submit", _) => Step::Submit { node: node()?, transaction: Box::new(transfer()?) },
+        ("block", [_]) => Step::ProduceBlock { node: node()? },
+        ("vote", [_, member, approve]) => Step::Vote {
+            node: node()?,
+            member: member.to_string(),
+            approve: match *approve {
+                "yes" => true,
+                "no" => false,
+                other => return Err(format!("expected yes or no, got {}", other)),
+            },
+        },
+        ("status", [_]) => Step::AnnounceStatus { node: node()? },
+        ("fund", [_, address, amount]) => Step::Fund {
+            node: node()?,
+            address: address.to_string(),
+            amount: amount.parse().map_err(|e| format!("invalid amount: {}", e))?,
+        },
+        ("transfer", _) => Step::CrossShard { node: node()?, transaction: Box::new(transfer()?) },
+        ("publish", [_, name, content @ ..]) => Step::Publish {
+            node: node()?,
+            name: name.to_string(),
+            content: content.join(" ").into_bytes(),
+        },
+        ("fetch", [_, name]) => Step::Fetch { node: node()?, name: name.to_string() },
+        ("partition", groups) if !groups.is_empty() => Step::Partition(groups.iter()
+            .map(|group| group.split(',').map(|node| node.parse().map_err(|e| format!("invalid node {}: {}", node, e))).collect())
+            .collect::<std::result::Result<_, _>>()?),
+        ("heal", []) => Step::Heal,
+        ("drop", [rate]) => Step::SetDropRate(rate.parse().map_err(|e| format!("invalid drop rate: {}", e))?),
+        ("latency", [latency]) => Step::SetLatency(parse_duration(latency)?),
+        _ => return Err(format!("unknown command or wrong arguments: {}", command)),
+    };
+    Ok((time, step))
+}
+
+fn parse_duration(text: &str) -> std::result::Result<Duration, String> {
+    let (value, unit) = text.find(|c: char| !c.is_ascii_digit())
+        .map_or((text, ""), |at| text.split_at(at));
+    let value: u64 = value.parse().map_err(|_| format!("invalid time: {}", text))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        _ => Err(format!("time needs a unit of ms or s: {}", text)),
+    }
+}
+
+pub(super) fn invalid(reason: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, reason).into()
+}
@@ -0,0 +1,305 @@
+// src/smart_contract/code.rs
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::vm::{CoopVM, Opcode};
+use crate::vm::opcode::Value;
+use super::{ExecutionEnvironment, SmartContract};
+
+/// The account contract creation transactions are sent to.
+pub const CREATION_ACCOUNT: &str = "icn:contracts";
+/// Instructions a contract deployed from code may execute in one run.
+pub const MAX_CODE_STEPS: usize = 100_000;
+
+/// The machine compiled contract code runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CodeFormat {
+    CoopVm,
+    Wasm,
+}
+
+impl CodeFormat {
+    fn tag(&self) -> &'static [u8] {
+        match self {
+            CodeFormat::CoopVm => b"coopvm",
+            CodeFormat::Wasm => b"wasm",
+        }
+    }
+}
+
+/// Compiled contract code, known by the hash of its format and bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractCode {
+    pub format: CodeFormat,
+    pub bytecode: Vec<u8>,
+}
+
+impl ContractCode {
+    pub fn new(format: CodeFormat, bytecode: Vec<u8>) -> Self {
+        ContractCode { format, bytecode }
+    }
+
+    /// Code for the cooperative VM running `program`.
+    pub fn coop_vm(program: &[Opcode]) -> Self {
+        ContractCode::new(CodeFormat::CoopVm, serde_json::to_vec(program).unwrap_or_default())
+    }
+
+    /// The program of cooperative VM code. Fails for code of other formats,
+    /// which this node cannot run.
+    pub fn program(&self) -> Result<Vec<Opcode>, String> {
+        match self.format {
+            CodeFormat::CoopVm => serde_json::from_slice(&self.bytecode).map_err(|e| format!("Malformed CoopVM code: {}", e)),
+            CodeFormat::Wasm => Err("Wasm code cannot be run by this node".to_string()),
+        }
+    }
+
+    /// Hex SHA-256 of the format and the bytecode, the address of the code in
+    /// a `CodeStore`.
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"icn-code:");
+        hasher.update(self.format.tag());
+        hasher.update([0]);
+        hasher.update(&self.bytecode);
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// What a contract creation transaction deploys: code, and the arguments
+/// its constructor runs with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractCreation {
+    pub code: ContractCode,
+    pub args: Vec<Value>,
+}
+
+/// A contract deployed from code. Each run executes its program on the
+/// cooperative VM with the constructor arguments in memory as the list
+/// `args`, and the method and arguments it is called with as `method` and
+/// `input`, emitting the events of the program and returning the value left
+/// on top of the stack.
+#[derive(Serialize, Deserialize)]
+pub struct CodeContract {
+    pub contract_id: String,
+    pub program: Vec<Opcode>,
+    pub args: Vec<Value>,
+}
+
+impl CodeContract {
+    pub fn new(contract_id: String, creation: &ContractCreation) -> Result<Self, String> {
+        Ok(CodeContract { contract_id, program: creation.code.program()?, args: creation.args.clone() })
+    }
+}
+
+impl SmartContract for CodeContract {
+    fn execute(&self, env: &mut ExecutionEnvironment) -> Result<String, String> {
+        let mut vm = CoopVM::new(self.program.clone())
+            .with_memory("args", Value::List(self.args.clone()))
+            .with_memory("method", Value::String(env.input().method.clone()))
+            .with_memory("input", Value::List(env.input().args.clone()));
+        let mut steps = 0;
+        while !vm.is_halted() {
+            if steps == MAX_CODE_STEPS {
+                return Err(format!("Did not finish in {} steps", MAX_CODE_STEPS));
+            }
+            env.meter_step()?;
+            vm.step().map_err(|e| e.to_string())?;
+            steps += 1;
+        }
+        for (name, value) in vm.get_events() {
+            env.emit(name, value_text(value));
+        }
+        Ok(vm.take_stack().pop().map_or_else(String::new, |value| value_text(&value)))
+    }
+
+    fn id(&self) -> String {
+        self.contract_id.clone()
+    }
+}
+
+/// Strings as they are, other values as JSON.
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredCode {
+    code: ContractCode,
+    /// Deployed contracts running this code.
+    references: usize,
+}
+
+/// The address of the contract `deployer` deploys as its `nonce`th from
+/// code, counting from 0: the first 20 bytes of a hash of the three, in hex.
+/// Every node derives the same address, and no two deployments share one.
+pub fn contract_address(deployer: &str, nonce: u64, code_hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"icn-contract:");
+    hasher.update(deployer.as_bytes());
+    hasher.update([0]);
+    hasher.update(nonce.to_be_bytes());
+    hasher.update(code_hash.as_bytes());
+    hex::encode(&hasher.finalize()[..20])
+}
+
+/// Contract code kept in the chain state by its hash. Contracts deployed with
+/// identical code share one copy, and anyone holding a contract's code can
+/// check it is the code the contract id is bound to by hashing it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CodeStore {
+    code: BTreeMap<String, StoredCode>,
+    /// Code hash by contract id.
+    contracts: BTreeMap<String, String>,
+    /// Contracts deployed from code by each deployer so far.
+    #[serde(default)]
+    nonces: BTreeMap<String, u64>,
+    /// Constructor arguments of the contracts deployed by contract creation,
+    /// by contract id, to run them again once the node restarts.
+    #[serde(default)]
+    args: BTreeMap<String, Vec<Value>>,
+}
+
+impl CodeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds `contract_id` to `code`, storing the code unless identical code
+    /// is stored already. Returns the code hash.
+    pub fn deploy(&mut self, contract_id: &str, code: ContractCode) -> Result<String, String> {
+        if self.contracts.contains_key(contract_id) {
+            return Err(format!("Contract {} already has code", contract_id));
+        }
+        let hash = code.hash();
+        let stored = self.code.entry(hash.clone()).or_insert(StoredCode { code, references: 0 });
+        stored.references += 1;
+        if stored.references > 1 {
+            info!("Contract {} shares code {} with {} others", contract_id, hash, stored.references - 1);
+        }
+        self.contracts.insert(contract_id.to_string(), hash.clone());
+        Ok(hash)
+    }
+
+    /// Contracts `deployer` has deployed from code.
+    pub fn nonce(&self, deployer: &str) -> u64 {
+        self.nonces.get(deployer).copied().unwrap_or(0)
+    }
+
+    /// The address the next contract `deployer` deploys with `code` gets.
+    pub fn next_address(&self, deployer: &str, code: &ContractCode) -> String {
+        contract_address(deployer, self.nonce(deployer), &code.hash())
+    }
+
+    /// Deploys the code of `creation` for `deployer` at `next_address`,
+    /// which is returned.
+    pub fn deploy_by(&mut self, deployer: &str, creation: &ContractCreation) -> Result<String, String> {
+        let address = self.next_address(deployer, &creation.code);
+        self.deploy(&address, creation.code.clone())?;
+        self.args.insert(address.clone(), creation.args.clone());
+        *self.nonces.entry(deployer.to_string()).or_insert(0) += 1;
+        Ok(address)
+    }
+
+    /// The contracts deployed by contract creation, as they run. Those whose
+    /// code this node cannot run are left out.
+    pub fn code_contracts(&self) -> Vec<CodeContract> {
+        self.args.iter()
+            .filter_map(|(contract_id, args)| {
+                let program = self.code_of(contract_id)?.program().ok()?;
+                Some(CodeContract { contract_id: contract_id.clone(), program, args: args.clone() })
+            })
+            .collect()
+    }
+
+    /// Unbinds an undeployed contract, dropping its code once no contract
+    /// runs it.
+    pub fn remove(&mut self, contract_id: &str) -> bool {
+        self.args.remove(contract_id);
+        let hash = match self.contracts.remove(contract_id) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        if let Some(stored) = self.code.get_mut(&hash) {
+            stored.references -= 1;
+            if stored.references == 0 {
+                self.code.remove(&hash);
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&ContractCode> {
+        self.code.get(hash).map(|stored| &stored.code)
+    }
+
+    /// The hash of the code `contract_id` runs.
+    pub fn code_hash(&self, contract_id: &str) -> Option<&str> {
+        self.contracts.get(contract_id).map(String::as_str)
+    }
+
+    pub fn code_of(&self, contract_id: &str) -> Option<&ContractCode> {
+        self.get(self.code_hash(contract_id)?)
+    }
+
+    /// Whether `code` is the code `contract_id` is bound to, which a light
+    /// client given the code hash of the contract checks without the store.
+    pub fn verify(&self, contract_id: &str, code: &ContractCode) -> bool {
+        self.code_hash(contract_id) == Some(code.hash().as_str())
+    }
+
+    /// Distinct pieces of code stored.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_code_stored_once_and_verified_by_hash() {
+        let mut store = CodeStore::new();
+        let code = ContractCode::new(CodeFormat::CoopVm, vec![1, 2, 3]);
+        let hash = store.deploy("token-a", code.clone()).unwrap();
+        assert_eq!(store.deploy("token-b", code.clone()).unwrap(), hash);
+        assert!(store.deploy("token-a", code.clone()).is_err());
+        assert_eq!(store.len(), 1);
+        assert_ne!(ContractCode::new(CodeFormat::Wasm, vec![1, 2, 3]).hash(), hash, "the format is part of the address");
+
+        assert!(store.verify("token-b", &code));
+        assert!(!store.verify("token-b", &ContractCode::new(CodeFormat::CoopVm, vec![1, 2, 4])));
+        assert!(!store.verify("token-c", &code));
+
+        assert!(store.remove("token-a"));
+        assert_eq!(store.code_of("token-b"), Some(&code));
+        assert!(store.remove("token-b"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_addresses_derived_from_deployer_nonce_and_code() {
+        let mut store = CodeStore::new();
+        let code = ContractCode::new(CodeFormat::CoopVm, vec![1, 2, 3]);
+        let first = store.next_address("alice", &code);
+        assert_eq!(first, contract_address("alice", 0, &code.hash()));
+        let creation = ContractCreation { code: code.clone(), args: vec![] };
+        assert_eq!(store.deploy_by("alice", &creation).unwrap(), first);
+        let second = store.deploy_by("alice", &creation).unwrap();
+        assert_ne!(second, first, "the nonce moved on");
+        assert_ne!(store.next_address("bob", &code), first);
+        assert_eq!(store.nonce("alice"), 2);
+
+        // Reclaimed contracts do not free their address for reuse
+        store.remove(&first);
+        assert_eq!(store.next_address("alice", &code), contract_address("alice", 2, &code.hash()));
+    }
+}
@@ -1,27 +1,249 @@
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
-// use crate::currency::CurrencyType;
+use std::collections::HashMap;
 use erased_serde::serialize_trait_object;
-use log::{debug, info};
+use tracing::{debug, info};
+use crate::blockchain::Transaction;
+use crate::identity::disclosure::{DisclosureProof, Predicate};
+use crate::vm::opcode::Value;
 
-pub trait SmartContract: erased_serde::Serialize {
+pub mod code;
+
+pub use code::{CodeContract, CodeFormat, CodeStore, ContractCode, ContractCreation};
+
+pub trait SmartContract: erased_serde::Serialize + Send + Sync {
     fn execute(&self, env: &mut ExecutionEnvironment) -> Result<String, String>;
     fn id(&self) -> String;
+
+    /// Decides whether a transaction of an account that designated this
+    /// contract as its validation logic goes through. Contracts that are not
+    /// validation logic accept nothing.
+    fn validate(&self, _transaction: &Transaction, _context: &ValidationContext) -> Result<(), String> {
+        Err(format!("Contract {} does not validate transactions", self.id()))
+    }
+}
+
+/// What validation logic is told about the account it validates for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationContext {
+    /// Unix time in seconds of the block the transaction is going into.
+    pub timestamp: i64,
+    /// What the account sent in the currency of the transaction in the day
+    /// before, as of the chain before the block.
+    pub sent_today: f64,
 }
 
 serialize_trait_object!(SmartContract);
 
+/// Contracts deployed on chain, by id.
+#[derive(Default)]
+pub struct ContractRegistry {
+    contracts: HashMap<String, Box<dyn SmartContract>>,
+}
+
+impl ContractRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a contract under its id, which must not be taken yet.
+    pub fn register(&mut self, contract: Box<dyn SmartContract>) -> Result<String, String> {
+        let id = contract.id();
+        if self.contracts.contains_key(&id) {
+            return Err(format!("Contract {} is already deployed", id));
+        }
+        self.contracts.insert(id.clone(), contract);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&dyn SmartContract> {
+        self.contracts.get(id).map(|contract| contract.as_ref())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.contracts.contains_key(id)
+    }
+
+    /// Undeploys a contract, dropping its state.
+    pub fn remove(&mut self, id: &str) -> Option<Box<dyn SmartContract>> {
+        self.contracts.remove(id)
+    }
+
+    /// Bytes of the serialized state of a contract.
+    pub fn state_size(&self, id: &str) -> Option<usize> {
+        self.get(id).map(|contract| serde_json::to_vec(contract).map_or(0, |state| state.len()))
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.contracts.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+}
+
+/// Something a contract reports while running, e.g. that it created a token.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContractEvent {
+    pub contract_id: String,
+    pub name: String,
+    pub data: String,
+}
+
+/// What running a deployed contract would do, found without a transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContractCall {
+    pub contract_id: String,
+    pub result: String,
+    pub events: Vec<ContractEvent>,
+    /// Gas a transaction running the contract would use.
+    pub gas: u64,
+}
+
+/// The method a contract is called with, and its arguments. Transactions run
+/// contracts with none.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContractInput {
+    pub method: String,
+    pub args: Vec<Value>,
+}
+
+/// The one place contracts run, whether invoked by a transaction naming a
+/// deployed contract or executed directly by the node.
 #[derive(Default)]
 pub struct ExecutionEnvironment {
     pub state: String,
+    pub registry: ContractRegistry,
+    events: Vec<ContractEvent>,
+    /// What the running contract was called with.
+    input: ContractInput,
+    /// Steps the running contract may take, if it runs metered.
+    step_limit: Option<u64>,
+    /// Steps the running contract has taken.
+    steps: u64,
 }
 
 impl ExecutionEnvironment {
     pub fn new() -> Self {
         debug!("Creating new ExecutionEnvironment");
-        ExecutionEnvironment {
-            state: String::new(),
+        Self::default()
+    }
+
+    pub fn deploy(&mut self, contract: Box<dyn SmartContract>) -> Result<String, String> {
+        let id = self.registry.register(contract)?;
+        info!("Deployed smart contract {}", id);
+        Ok(id)
+    }
+
+    /// Runs a deployed contract.
+    pub fn execute(&mut self, id: &str) -> Result<String, String> {
+        // Taken out while it runs, as the contract gets the environment mutably
+        let contract = self.registry.contracts.remove(id)
+            .ok_or_else(|| format!("Contract {} is not deployed", id))?;
+        let result = self.execute_contract(contract.as_ref());
+        self.registry.contracts.insert(id.to_string(), contract);
+        result
+    }
+
+    /// Runs a deployed contract with `input` in a scratch environment holding
+    /// a copy of the state, for at most `max_steps` steps, returning its
+    /// result and events along with the steps it took. Nothing it does is
+    /// kept.
+    pub fn simulate(&self, id: &str, input: ContractInput, max_steps: u64) -> (Result<(String, Vec<ContractEvent>), String>, u64) {
+        let contract = match self.registry.get(id) {
+            Some(contract) => contract,
+            None => return (Err(format!("Contract {} is not deployed", id)), 0),
+        };
+        let mut scratch = ExecutionEnvironment { input, ..self.scratch() };
+        let (result, steps) = scratch.execute_contract_metered(contract, max_steps);
+        (result.map(|result| (result, scratch.take_events())), steps)
+    }
+
+    /// An environment holding a copy of the state and nothing else, to run a
+    /// contract in without holding on to this one.
+    pub fn scratch(&self) -> Self {
+        ExecutionEnvironment { state: self.state.clone(), ..Self::default() }
+    }
+
+    /// Keeps the state and events of a contract run in `scratch`, made while
+    /// the state was `forked_state`. Fails, keeping nothing, if the state
+    /// changed meanwhile.
+    pub fn commit(&mut self, mut scratch: ExecutionEnvironment, forked_state: &str) -> Result<(), String> {
+        if self.state != forked_state {
+            return Err("The contract state changed while the contract ran".to_string());
         }
+        self.state = scratch.state;
+        self.events.append(&mut scratch.events);
+        Ok(())
+    }
+
+    pub fn input(&self) -> &ContractInput {
+        &self.input
+    }
+
+    /// Like `execute`, failing the contract once it takes more than
+    /// `max_steps` steps. Returns the steps it took along with its result.
+    pub fn execute_metered(&mut self, id: &str, max_steps: u64) -> (Result<String, String>, u64) {
+        let contract = match self.registry.contracts.remove(id) {
+            Some(contract) => contract,
+            None => return (Err(format!("Contract {} is not deployed", id)), 0),
+        };
+        let outcome = self.execute_contract_metered(contract.as_ref(), max_steps);
+        self.registry.contracts.insert(id.to_string(), contract);
+        outcome
+    }
+
+    /// Like `execute_contract`, failing the contract once it takes more than
+    /// `max_steps` steps. Returns the steps it took along with its result.
+    pub fn execute_contract_metered(&mut self, contract: &dyn SmartContract, max_steps: u64) -> (Result<String, String>, u64) {
+        self.step_limit = Some(max_steps);
+        self.steps = 0;
+        let result = self.execute_contract(contract);
+        self.step_limit = None;
+        (result, std::mem::take(&mut self.steps))
+    }
+
+    /// Counts a step of the running contract, failing once it takes more than
+    /// it is metered for.
+    pub fn meter_step(&mut self) -> Result<(), String> {
+        if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+            return Err(format!("Out of gas after {} steps", self.steps));
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Runs a contract, deployed or not. The events of a failed contract are
+    /// dropped.
+    pub fn execute_contract(&mut self, contract: &dyn SmartContract) -> Result<String, String> {
+        debug!("Executing smart contract {}", contract.id());
+        let emitted = self.events.len();
+        let result = contract.execute(self);
+        for event in &mut self.events[emitted..] {
+            event.contract_id = contract.id();
+        }
+        if let Err(e) = &result {
+            info!("Smart contract {} failed: {}", contract.id(), e);
+            self.events.truncate(emitted);
+        }
+        result
+    }
+
+    /// Records an event of the running contract.
+    pub fn emit(&mut self, name: &str, data: String) {
+        self.events.push(ContractEvent { contract_id: String::new(), name: name.to_string(), data });
+    }
+
+    /// The events emitted since the last call.
+    pub fn take_events(&mut self) -> Vec<ContractEvent> {
+        std::mem::take(&mut self.events)
     }
 }
 
@@ -35,9 +257,10 @@ pub struct AssetTokenContract {
 }
 
 impl SmartContract for AssetTokenContract {
-    fn execute(&self, _env: &mut ExecutionEnvironment) -> Result<String, String> {
+    fn execute(&self, env: &mut ExecutionEnvironment) -> Result<String, String> {
         debug!("Executing AssetTokenContract: {}", self.asset_id);
         // Implementation would go here
+        env.emit("AssetTokenCreated", format!("{} owned by {}", self.asset_id, self.owner));
         info!("AssetTokenContract executed successfully: {}", self.asset_id);
         Ok("Asset token created".to_string())
     }
@@ -60,9 +283,10 @@ pub struct BondContract {
 }
 
 impl SmartContract for BondContract {
-    fn execute(&self, _env: &mut ExecutionEnvironment) -> Result<String, String> {
+    fn execute(&self, env: &mut ExecutionEnvironment) -> Result<String, String> {
         debug!("Executing BondContract: {}", self.bond_id);
         // Implementation would go here
+        env.emit("BondCreated", format!("{} issued by {}", self.bond_id, self.issuer));
         info!("BondContract executed successfully: {}", self.bond_id);
         Ok("Bond created".to_string())
     }
@@ -72,6 +296,89 @@ impl SmartContract for BondContract {
     }
 }
 
+/// Checks a selective disclosure proof about a member's attribute, e.g. that
+/// their membership predates a given year, without learning the attribute.
+#[derive(Serialize, Deserialize)]
+pub struct IdentityVerificationContract {
+    pub contract_id: String,
+    pub subject: String,
+    pub trusted_issuer: String,
+    pub attribute: String,
+    pub predicate: Predicate,
+    /// The challenge given to the subject, which the proof must be signed over.
+    pub nonce: Vec<u8>,
+    pub proof: DisclosureProof,
+}
+
+impl SmartContract for IdentityVerificationContract {
+    fn execute(&self, env: &mut ExecutionEnvironment) -> Result<String, String> {
+        debug!("Executing IdentityVerificationContract: {}", self.contract_id);
+        if self.proof.credential.subject != self.subject {
+            return Err("Proof was issued to a different subject".to_string());
+        }
+        if self.proof.credential.issuer != self.trusted_issuer {
+            return Err("Proof was not issued by the trusted issuer".to_string());
+        }
+        if self.proof.attribute != self.attribute || self.proof.predicate != self.predicate {
+            return Err("Proof does not match the required predicate".to_string());
+        }
+        // Also checks that the trusted issuer's own key signed the credential
+        // and that the subject signed the proof over our nonce
+        if !self.proof.verify(&self.nonce) {
+            return Err("Disclosure proof verification failed".to_string());
+        }
+        info!("Identity of {} verified for attribute {}", self.subject, self.attribute);
+        env.emit("IdentityVerified", format!("{} {}", self.subject, self.attribute));
+        Ok(format!("Identity verified: {}", self.subject))
+    }
+
+    fn id(&self) -> String {
+        self.contract_id.clone()
+    }
+}
+
+/// Validation logic for a shared account: whose signatures its transactions
+/// need, and how much it may send at once and in a day.
+#[derive(Serialize, Deserialize)]
+pub struct AccountPolicyContract {
+    pub contract_id: String,
+    /// Addresses whose signatures count.
+    pub signers: Vec<String>,
+    /// How many of `signers` must sign.
+    pub threshold: usize,
+    pub max_per_transaction: Option<f64>,
+    /// Limit on what is sent in a day, in each currency.
+    pub daily_limit: Option<f64>,
+}
+
+impl SmartContract for AccountPolicyContract {
+    fn execute(&self, _env: &mut ExecutionEnvironment) -> Result<String, String> {
+        Ok(format!("{} of {} signers required", self.threshold, self.signers.len()))
+    }
+
+    fn id(&self) -> String {
+        self.contract_id.clone()
+    }
+
+    fn validate(&self, transaction: &Transaction, context: &ValidationContext) -> Result<(), String> {
+        let signed = transaction.signers().iter().filter(|signer| self.signers.contains(signer)).count();
+        if signed < self.threshold {
+            return Err(format!("Signed by {} of the {} signers required", signed, self.threshold));
+        }
+        let amount: f64 = transaction.transfers().iter()
+            .filter(|transfer| transfer.from == transaction.from && transfer.currency_type == transaction.currency_type)
+            .map(|transfer| transfer.amount)
+            .sum();
+        if let Some(max) = self.max_per_transaction.filter(|max| amount > *max) {
+            return Err(format!("Sends {} at once, more than {}", amount, max));
+        }
+        if let Some(limit) = self.daily_limit.filter(|limit| context.sent_today + amount > *limit) {
+            return Err(format!("Sends {} after {} today, more than {} a day", amount, context.sent_today, limit));
+        }
+        Ok(())
+    }
+}
+
 impl AssetTokenContract {
     pub fn new(asset_id: String, name: String, description: String, owner: String, value: f64) -> Self {
         debug!("Creating new AssetTokenContract: {}", asset_id);
@@ -99,4 +406,69 @@ impl BondContract {
             owner,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::disclosure::CommittedCredential;
+    use crate::wallet::address_of;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_identity_verification_contract() {
+        let issuer_keypair = Keypair::generate(&mut OsRng {});
+        let issuer = address_of(&issuer_keypair.public);
+        let alice_keypair = Keypair::generate(&mut OsRng {});
+        let alice = address_of(&alice_keypair.public);
+        let mut attributes = HashMap::new();
+        attributes.insert("membership_year".to_string(), "2018".to_string());
+        let (credential, secrets) = CommittedCredential::issue(&issuer, &issuer_keypair, &alice, &attributes);
+        let proof = DisclosureProof::create(&credential, &secrets, &alice_keypair, "membership_year", Predicate::LessThan(2023), b"challenge").unwrap();
+
+        let mut contract = IdentityVerificationContract {
+            contract_id: "verify_alice".to_string(),
+            subject: alice,
+            trusted_issuer: issuer.clone(),
+            attribute: "membership_year".to_string(),
+            predicate: Predicate::LessThan(2023),
+            nonce: b"challenge".to_vec(),
+            proof,
+        };
+        let mut env = ExecutionEnvironment::new();
+        assert!(contract.execute(&mut env).is_ok());
+
+        contract.nonce = b"another challenge".to_vec();
+        assert!(contract.execute(&mut env).is_err(), "a proof made for another challenge is refused");
+        contract.nonce = b"challenge".to_vec();
+
+        // Naming the trusted issuer does not help a credential signed by another key
+        let (forged, secrets) = CommittedCredential::issue(&issuer, &Keypair::generate(&mut OsRng {}), &contract.subject, &attributes);
+        let genuine = std::mem::replace(&mut contract.proof, DisclosureProof::create(&forged, &secrets, &alice_keypair, "membership_year", Predicate::LessThan(2023), b"challenge").unwrap());
+        assert!(contract.execute(&mut env).is_err());
+        contract.proof = genuine;
+
+        contract.trusted_issuer = "did:icn:other".to_string();
+        assert!(contract.execute(&mut env).is_err());
+    }
+
+    #[test]
+    fn test_registry_runs_deployed_contracts() {
+        let mut env = ExecutionEnvironment::new();
+        let bond = BondContract::new("bond1".to_string(), "Solar".to_string(), "Panels".to_string(), "coop".to_string(), 100.0, Utc::now(), 0.03, "alice".to_string());
+        assert_eq!(env.deploy(Box::new(bond)), Ok("bond1".to_string()));
+        let duplicate = AssetTokenContract::new("bond1".to_string(), "Tractor".to_string(), String::new(), "bob".to_string(), 10.0);
+        assert!(env.deploy(Box::new(duplicate)).is_err());
+
+        assert_eq!(env.execute("bond1"), Ok("Bond created".to_string()));
+        assert!(env.registry.contains("bond1"), "still deployed after running");
+        assert!(env.execute("missing").is_err());
+
+        let (result, events) = env.simulate("bond1", ContractInput::default(), u64::MAX).0.unwrap();
+        assert_eq!(result, "Bond created");
+        assert_eq!(events[0].contract_id, "bond1");
+        assert_eq!(env.take_events().len(), 1, "only the real run left its events");
+    }
+}
@@ -0,0 +1,103 @@
+use super::opcode::Opcode;
+use std::collections::HashMap;
+
+/// A symbolic position in a program, resolved to a program counter when the
+/// program is assembled.
+pub type Label = usize;
+
+/// Code whose jump and call targets are still symbolic. Code generators emit
+/// these while the final addresses are unknown, and can concatenate fragments
+/// freely as long as their labels are unique.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Op(Opcode),
+    /// Marks the position of a label; emits no code.
+    Label(Label),
+    Jump(Label),
+    JumpIf(Label),
+    /// A `try` block whose catch block starts at the label.
+    Try(Label),
+    /// Marks the entry point of a named function; emits no code.
+    Function(String),
+    Call(String),
+}
+
+impl From<Opcode> for Instruction {
+    fn from(opcode: Opcode) -> Self {
+        Instruction::Op(opcode)
+    }
+}
+
+/// Turns symbolic code into a program for `CoopVM`. A first pass records the
+/// program counter of every label and function, a second one emits the
+/// opcodes with their targets patched in.
+pub fn assemble(instructions: Vec<Instruction>) -> Result<Vec<Opcode>, String> {
+    let mut labels: HashMap<Label, usize> = HashMap::new();
+    let mut functions: HashMap<String, usize> = HashMap::new();
+    let mut pc = 0;
+    for instruction in &instructions {
+        match instruction {
+            Instruction::Label(label) => {
+                if labels.insert(*label, pc).is_some() {
+                    return Err(format!("Label {} defined twice", label));
+                }
+            }
+            Instruction::Function(name) => {
+                if functions.insert(name.clone(), pc).is_some() {
+                    return Err(format!("Function {} defined twice", name));
+                }
+            }
+            _ => pc += 1,
+        }
+    }
+
+    let label = |label: &Label| labels.get(label).copied().ok_or_else(|| format!("Undefined label {}", label));
+    let mut program = Vec::with_capacity(pc);
+    for instruction in instructions {
+        match instruction {
+            Instruction::Op(opcode) => program.push(opcode),
+            Instruction::Label(_) | Instruction::Function(_) => {}
+            Instruction::Jump(target) => program.push(Opcode::Jump(label(&target)?)),
+            Instruction::JumpIf(target) => program.push(Opcode::JumpIf(label(&target)?)),
+            Instruction::Try(target) => program.push(Opcode::Try(label(&target)?)),
+            Instruction::Call(name) => {
+                let target = functions.get(&name).ok_or_else(|| format!("Undefined function {}", name))?;
+                program.push(Opcode::Call(*target));
+            }
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::opcode::Value;
+
+    #[test]
+    fn test_labels_resolved() {
+        let program = assemble(vec![
+            Instruction::Jump(1),
+            Instruction::Function("double".to_string()),
+            Opcode::Push(Value::Int(2)).into(),
+            Opcode::Mul.into(),
+            Opcode::Return.into(),
+            Instruction::Label(1),
+            Opcode::Push(Value::Int(21)).into(),
+            Instruction::Call("double".to_string()),
+            Instruction::Label(2),
+        ]).unwrap();
+        assert_eq!(program, vec![
+            Opcode::Jump(4),
+            Opcode::Push(Value::Int(2)),
+            Opcode::Mul,
+            Opcode::Return,
+            Opcode::Push(Value::Int(21)),
+            Opcode::Call(1),
+        ]);
+
+        assert!(assemble(vec![Instruction::Jump(7)]).is_err());
+        assert!(assemble(vec![Instruction::Call("missing".to_string())]).is_err());
+        assert!(assemble(vec![Instruction::Label(0), Instruction::Label(0)]).is_err());
+    }
+}
@@ -1,5 +1,9 @@
+use crate::vm::assembler::{assemble, Instruction, Label};
+use crate::vm::optimizer::optimize;
+use crate::vm::error::CompileError;
+use crate::vm::modules::ModuleResolver;
 use crate::vm::opcode::{Opcode, Value};
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
 
 // Define the tokens that the lexer will generate from source code
 #[derive(Debug, PartialEq, Clone)]
@@ -14,19 +18,33 @@ enum Token {
     Else,
     While,
     Function,
+    Struct,
+    Import,
+    As,
     Return,
+    Try,
+    Catch,
+    Revert,
     Vote,
     AllocateResource,
     UpdateReputation,
     CreateProposal,
     GetProposalStatus,
     Emit,
+    Contains,
+    ToInt,
+    ToFloat,
+    Len,
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Semicolon,
+    Colon,
     Comma,
+    Dot,
     Equals,
     Plus,
     Minus,
@@ -44,137 +62,204 @@ enum Token {
     Not,
 }
 
+impl Token {
+    // Describe the token for error messages
+    fn describe(&self) -> String {
+        let symbol = match self {
+            Token::Identifier(name) => return format!("identifier {}", name),
+            Token::Integer(value) => return format!("integer {}", value),
+            Token::Float(value) => return format!("float {}", value),
+            Token::String(value) => return format!("string {:?}", value),
+            Token::True => "true",
+            Token::False => "false",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::Function => "function",
+            Token::Struct => "struct",
+            Token::Import => "import",
+            Token::As => "as",
+            Token::Return => "return",
+            Token::Try => "try",
+            Token::Catch => "catch",
+            Token::Revert => "revert",
+            Token::Vote => "vote",
+            Token::AllocateResource => "allocate_resource",
+            Token::UpdateReputation => "update_reputation",
+            Token::CreateProposal => "create_proposal",
+            Token::GetProposalStatus => "get_proposal_status",
+            Token::Emit => "emit",
+            Token::Contains => "contains",
+            Token::ToInt => "int",
+            Token::ToFloat => "float",
+            Token::Len => "len",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Equals => "=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Multiply => "*",
+            Token::Divide => "/",
+            Token::Modulo => "%",
+            Token::DoubleEquals => "==",
+            Token::NotEquals => "!=",
+            Token::GreaterThan => ">",
+            Token::LessThan => "<",
+            Token::GreaterThanEquals => ">=",
+            Token::LessThanEquals => "<=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Not => "!",
+        };
+        format!("'{}'", symbol)
+    }
+
+    // Whether the token can end an operand, so that a '-' after it subtracts
+    fn ends_operand(&self) -> bool {
+        matches!(
+            self,
+            Token::Identifier(_) | Token::Integer(_) | Token::Float(_) | Token::String(_)
+                | Token::True | Token::False | Token::RParen | Token::RBracket
+        )
+    }
+}
+
+// A 1-based position in the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    line: usize,
+    column: usize,
+}
+
+impl CompileError {
+    fn at(span: Span, message: String) -> Self {
+        CompileError { message, line: span.line, column: span.column, module: None }
+    }
+}
+
+// A token with where it starts and where it ends in the source
+#[derive(Debug, Clone, PartialEq)]
+struct SpannedToken {
+    token: Token,
+    start: Span,
+    end: Span,
+}
+
 // Lexer for converting source code into tokens
 struct Lexer {
     input: Vec<char>,
     position: usize,
+    // Position of the first character of every line
+    line_starts: Vec<usize>,
+    // Errors that did not stop a token from being read
+    errors: Vec<CompileError>,
 }
 
 impl Lexer {
     // Create a new lexer with the given input string
     fn new(input: &str) -> Self {
+        let input: Vec<char> = input.chars().collect();
+        let line_starts = std::iter::once(0)
+            .chain(input.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(i, _)| i + 1))
+            .collect();
         Lexer {
-            input: input.chars().collect(),
+            input,
             position: 0,
+            line_starts,
+            errors: Vec::new(),
         }
     }
 
-    // Get the next token from the input
-    fn next_token(&mut self) -> Option<Token> {
-        self.skip_whitespace();
-
-        if self.position >= self.input.len() {
-            return None;
-        }
-
-        match self.input[self.position] {
-            '(' => {
-                self.position += 1;
-                Some(Token::LParen)
-            }
-            ')' => {
-                self.position += 1;
-                Some(Token::RParen)
-            }
-            '{' => {
-                self.position += 1;
-                Some(Token::LBrace)
-            }
-            '}' => {
-                self.position += 1;
-                Some(Token::RBrace)
+    // Get the next token from the input, which must not be at its end. A
+    // '-' right before a digit is the sign of a number unless it follows an
+    // operand, where it is a subtraction.
+    fn next_token(&mut self, previous: Option<&Token>) -> Result<Token, String> {
+        let token = match self.input[self.position] {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ';' => Token::Semicolon,
+            ':' => Token::Colon,
+            ',' => Token::Comma,
+            '.' => Token::Dot,
+            '+' => Token::Plus,
+            '-' if !previous.is_some_and(Token::ends_operand) && self.peek_next().is_some_and(|c| c.is_ascii_digit()) => {
+                return self.read_number();
             }
-            ';' => {
+            '-' => Token::Minus,
+            '*' => Token::Multiply,
+            '/' => Token::Divide,
+            '%' => Token::Modulo,
+            '=' => return Ok(self.read_operator(Token::Equals, '=', Token::DoubleEquals)),
+            '!' => return Ok(self.read_operator(Token::Not, '=', Token::NotEquals)),
+            '>' => return Ok(self.read_operator(Token::GreaterThan, '=', Token::GreaterThanEquals)),
+            '<' => return Ok(self.read_operator(Token::LessThan, '=', Token::LessThanEquals)),
+            c @ ('&' | '|') => {
                 self.position += 1;
-                Some(Token::Semicolon)
-            }
-            ',' => {
-                self.position += 1;
-                Some(Token::Comma)
-            }
-            '+' => {
-                self.position += 1;
-                Some(Token::Plus)
-            }
-            '-' => {
-                self.position += 1;
-                Some(Token::Minus)
-            }
-            '*' => {
-                self.position += 1;
-                Some(Token::Multiply)
-            }
-            '/' => {
-                self.position += 1;
-                Some(Token::Divide)
-            }
-            '%' => {
-                self.position += 1;
-                Some(Token::Modulo)
-            }
-            '=' => {
-                if self.peek_next() == Some('=') {
-                    self.position += 2;
-                    Some(Token::DoubleEquals)
-                } else {
-                    self.position += 1;
-                    Some(Token::Equals)
-                }
-            }
-            '!' => {
-                if self.peek_next() == Some('=') {
-                    self.position += 2;
-                    Some(Token::NotEquals)
-                } else {
-                    self.position += 1;
-                    Some(Token::Not)
+                if self.input.get(self.position) != Some(&c) {
+                    return Err(format!("unexpected character '{}', did you mean '{}{}'?", c, c, c));
                 }
+                if c == '&' { Token::And } else { Token::Or }
             }
-            '>' => {
-                if self.peek_next() == Some('=') {
-                    self.position += 2;
-                    Some(Token::GreaterThanEquals)
-                } else {
-                    self.position += 1;
-                    Some(Token::GreaterThan)
-                }
+            '"' => return self.read_string(),
+            c if c.is_alphabetic() => return Ok(self.read_identifier()),
+            c if c.is_ascii_digit() => return self.read_number(),
+            c => {
+                self.position += 1;
+                return Err(format!("unexpected character '{}'", c));
             }
-            '<' => {
-                if self.peek_next() == Some('=') {
-                    self.position += 2;
-                    Some(Token::LessThanEquals)
-                } else {
-                    self.position += 1;
-                    Some(Token::LessThan)
+        };
+        self.position += 1;
+        Ok(token)
+    }
+
+    // Read a one-character operator, or a two-character one if `second` follows
+    fn read_operator(&mut self, single: Token, second: char, double: Token) -> Token {
+        if self.peek_next() == Some(second) {
+            self.position += 2;
+            double
+        } else {
+            self.position += 1;
+            single
+        }
+    }
+
+    // Skip whitespace and comments: `//` to the end of the line and `/* */`,
+    // which does not nest
+    fn skip_whitespace(&mut self) -> Result<(), CompileError> {
+        loop {
+            match (self.input.get(self.position), self.peek_next()) {
+                (Some(c), _) if c.is_whitespace() => self.position += 1,
+                (Some('/'), Some('/')) => {
+                    while self.position < self.input.len() && self.input[self.position] != '\n' {
+                        self.position += 1;
+                    }
                 }
-            }
-            '&' => {
-                if self.peek_next() == Some('&') {
+                (Some('/'), Some('*')) => {
+                    let start = self.span(self.position);
                     self.position += 2;
-                    Some(Token::And)
-                } else {
-                    None // Invalid token
-                }
-            }
-            '|' => {
-                if self.peek_next() == Some('|') {
+                    loop {
+                        match (self.input.get(self.position), self.peek_next()) {
+                            (None, _) => return Err(CompileError::at(start, "unterminated comment".to_string())),
+                            (Some('*'), Some('/')) => break,
+                            _ => self.position += 1,
+                        }
+                    }
                     self.position += 2;
-                    Some(Token::Or)
-                } else {
-                    None // Invalid token
                 }
+                _ => return Ok(()),
             }
-            '"' => Some(self.read_string()),
-            c if c.is_alphabetic() => Some(self.read_identifier()),
-            c if c.is_digit(10) => Some(self.read_number()),
-            _ => None, // Invalid token
-        }
-    }
-
-    // Skip whitespace characters in the input
-    fn skip_whitespace(&mut self) {
-        while self.position < self.input.len() && self.input[self.position].is_whitespace() {
-            self.position += 1;
         }
     }
 
@@ -187,16 +272,41 @@ impl Lexer {
         }
     }
 
-    // Read a string token from the input
-    fn read_string(&mut self) -> Token {
+    // Read a string token from the input. A backslash escapes a quote,
+    // another backslash, or stands for a newline, tab or carriage return.
+    fn read_string(&mut self) -> Result<Token, String> {
         self.position += 1; // Skip opening quote
-        let start = self.position;
-        while self.position < self.input.len() && self.input[self.position] != '"' {
+        let mut value = String::new();
+        loop {
+            let c = match self.input.get(self.position) {
+                Some(c) => *c,
+                None => return Err("unterminated string".to_string()),
+            };
             self.position += 1;
+            match c {
+                '"' => break,
+                '\\' => {
+                    let escaped = match self.input.get(self.position) {
+                        Some(escaped) => *escaped,
+                        None => return Err("unterminated string".to_string()),
+                    };
+                    self.position += 1;
+                    match escaped {
+                        '"' => value.push('"'),
+                        '\\' => value.push('\\'),
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        'r' => value.push('\r'),
+                        other => {
+                            let span = self.span(self.position - 2);
+                            self.errors.push(CompileError::at(span, format!("unknown escape sequence \\{}", other)));
+                        }
+                    }
+                }
+                c => value.push(c),
+            }
         }
-        let value: String = self.input[start..self.position].iter().collect();
-        self.position += 1; // Skip closing quote
-        Token::String(value)
+        Ok(Token::String(value))
     }
 
     // Read an identifier token from the input
@@ -213,22 +323,35 @@ impl Lexer {
             "else" => Token::Else,
             "while" => Token::While,
             "function" => Token::Function,
+            "struct" => Token::Struct,
+            "import" => Token::Import,
+            "as" => Token::As,
             "return" => Token::Return,
+            "try" => Token::Try,
+            "catch" => Token::Catch,
+            "revert" => Token::Revert,
             "vote" => Token::Vote,
             "allocate_resource" => Token::AllocateResource,
             "update_reputation" => Token::UpdateReputation,
             "create_proposal" => Token::CreateProposal,
             "get_proposal_status" => Token::GetProposalStatus,
             "emit" => Token::Emit,
+            "contains" => Token::Contains,
+            "int" => Token::ToInt,
+            "float" => Token::ToFloat,
+            "len" => Token::Len,
             _ => Token::Identifier(value),
         }
     }
 
     // Read a number token from the input
-    fn read_number(&mut self) -> Token {
+    fn read_number(&mut self) -> Result<Token, String> {
         let start = self.position;
+        if self.input[self.position] == '-' {
+            self.position += 1;
+        }
         let mut is_float = false;
-        while self.position < self.input.len() && (self.input[self.position].is_digit(10) || self.input[self.position] == '.') {
+        while self.position < self.input.len() && (self.input[self.position].is_ascii_digit() || self.input[self.position] == '.') {
             if self.input[self.position] == '.' {
                 is_float = true;
             }
@@ -236,44 +359,238 @@ impl Lexer {
         }
         let value: String = self.input[start..self.position].iter().collect();
         if is_float {
-            Token::Float(value.parse().unwrap())
+            value.parse().map(Token::Float).map_err(|_| format!("invalid number {}", value))
         } else {
-            Token::Integer(value.parse().unwrap())
+            value.parse().map(Token::Integer).map_err(|_| format!("integer {} is too large", value))
         }
     }
+
+    // The line and column of a character position
+    fn span(&self, position: usize) -> Span {
+        let line = self.line_starts.partition_point(|start| *start <= position);
+        Span { line, column: position - self.line_starts[line - 1] + 1 }
+    }
+
+    // Get all tokens from the input. Characters that start no token are
+    // reported and skipped, so the rest of the input is still checked.
+    fn tokens(&mut self) -> (Vec<SpannedToken>, Vec<CompileError>) {
+        let mut tokens = Vec::new();
+        loop {
+            if let Err(error) = self.skip_whitespace() {
+                self.errors.push(error);
+            }
+            if self.position >= self.input.len() {
+                break;
+            }
+            let start = self.span(self.position);
+            match self.next_token(tokens.last().map(|spanned: &SpannedToken| &spanned.token)) {
+                Ok(token) => tokens.push(SpannedToken { token, start, end: self.span(self.position) }),
+                Err(message) => self.errors.push(CompileError::at(start, message)),
+            }
+        }
+        (tokens, std::mem::take(&mut self.errors))
+    }
+
+    // Where the input ends, for errors about missing tokens
+    fn end(&self) -> Span {
+        self.span(self.input.len())
+    }
 }
 
-// Parser for converting tokens into opcodes
-struct Parser {
-    tokens: Vec<Token>,
+// Parser for converting tokens into opcodes. Imported modules are parsed
+// by the same parser, in place of the import, so that they share its labels
+// and what it knows about functions and structs.
+struct Parser<'a> {
+    tokens: Vec<SpannedToken>,
     position: usize,
+    next_label: Label,
+    // Where the input ends
+    end: Span,
+    errors: Vec<CompileError>,
+    functions: HashMap<String, Span>,
+    calls: Vec<(String, Span, Option<String>)>,
+    // Fields of each struct, and the struct literals to check against them
+    structs: HashMap<String, (Vec<String>, Span)>,
+    struct_literals: Vec<StructLiteral>,
+    // Nesting of the braced blocks being parsed
+    block_depth: usize,
+    module: ModuleScope,
+    resolver: Option<&'a dyn ModuleResolver>,
+    // Modules compiled so far, and the chain of imports being compiled
+    compiled_modules: HashSet<String>,
+    importing: Vec<String>,
+    // Modules that could not be found, whose members are not reported as
+    // undefined on top of that
+    failed_modules: HashSet<String>,
+    // The code of the functions and modules defined at the top level, which
+    // a session keeps for later snippets
+    definition_code: Vec<Instruction>,
 }
 
-impl Parser {
+// What the snippets compiled so far in a session defined, so that later
+// snippets can use it. Their code comes first in every later program; being
+// definitions, it is jumped over.
+#[derive(Default)]
+pub(crate) struct Definitions {
+    code: Vec<Instruction>,
+    next_label: Label,
+    functions: HashMap<String, Span>,
+    structs: HashMap<String, (Vec<String>, Span)>,
+    aliases: HashMap<String, String>,
+    compiled_modules: HashSet<String>,
+}
+
+// The module being parsed. Functions and structs defined in a module are
+// named `path::name`; the contract itself has no path and keeps plain names.
+#[derive(Default)]
+struct ModuleScope {
+    path: Option<String>,
+    // Imported modules by the name they are referred to with
+    aliases: HashMap<String, String>,
+}
+
+impl ModuleScope {
+    // The full name of a function or struct defined in this module
+    fn qualify(&self, name: &str) -> String {
+        match &self.path {
+            Some(path) => format!("{}::{}", path, name),
+            None => name.to_string(),
+        }
+    }
+}
+
+// A struct literal, with where each of its fields is set
+struct StructLiteral {
+    name: String,
+    fields: Vec<(String, Span)>,
+    span: Span,
+    module: Option<String>,
+}
+
+impl<'a> Parser<'a> {
     // Create a new parser with the given tokens
-    fn new(tokens: Vec<Token>) -> Self {
+    fn new(tokens: Vec<SpannedToken>, end: Span, resolver: Option<&'a dyn ModuleResolver>) -> Self {
         Parser {
             tokens,
             position: 0,
+            next_label: 0,
+            end,
+            errors: Vec::new(),
+            functions: HashMap::new(),
+            calls: Vec::new(),
+            structs: HashMap::new(),
+            struct_literals: Vec::new(),
+            block_depth: 0,
+            module: ModuleScope::default(),
+            resolver,
+            compiled_modules: HashSet::new(),
+            importing: Vec::new(),
+            failed_modules: HashSet::new(),
+            definition_code: Vec::new(),
+        }
+    }
+
+    // Parse the tokens into a vector of opcodes, resolving jump targets.
+    // Parsing goes on after an error so that every error is reported.
+    fn parse(mut self, optimizing: bool) -> Result<Vec<Opcode>, Vec<CompileError>> {
+        let mut code = self.parse_statements();
+        self.check_references();
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        if optimizing {
+            code = optimize(code);
         }
+        assemble(code).map_err(|message| vec![CompileError::at(Span { line: 1, column: 1 }, message)])
+    }
+
+    // Start from what earlier snippets of a session defined
+    fn with_definitions(mut self, definitions: &Definitions) -> Self {
+        self.next_label = definitions.next_label;
+        self.functions = definitions.functions.clone();
+        self.structs = definitions.structs.clone();
+        self.module.aliases = definitions.aliases.clone();
+        self.compiled_modules = definitions.compiled_modules.clone();
+        self
     }
 
-    // Parse the tokens into a vector of opcodes
-    fn parse(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
-        let mut opcodes = Vec::new();
+    fn parse_statements(&mut self) -> Vec<Instruction> {
+        let mut code = Vec::new();
         while self.position < self.tokens.len() {
-            opcodes.append(&mut self.parse_statement()?);
+            code.append(&mut self.parse_recovering());
         }
-        Ok(opcodes)
+        code
     }
 
-    // Parse a single statement into opcodes
-    fn parse_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Report calls and struct literals referring to nothing, once the whole
+    // program, and so every function and struct, has been parsed
+    fn check_references(&mut self) {
+        for (name, span, module) in &self.calls {
+            if !self.functions.contains_key(name) && !self.in_failed_module(name) {
+                let mut error = CompileError::at(*span, format!("undefined function {}", name));
+                error.module = module.clone();
+                self.errors.push(error);
+            }
+        }
+        self.check_struct_literals();
+    }
+
+    // Parse a statement. On error, record it and skip to where the next
+    // statement likely starts: past the next ';' or braced block, or before
+    // a keyword starting a statement or the '}' closing the current block.
+    fn parse_recovering(&mut self) -> Vec<Instruction> {
+        let start = self.position;
+        let mut error = match self.parse_statement() {
+            Ok(code) => return code,
+            Err(error) => error,
+        };
+        if error.module.is_none() {
+            error.module = self.module.path.clone();
+        }
+        self.errors.push(error);
+        let mut depth = 0;
+        while let Some(token) = self.current_token() {
+            match token {
+                Token::Semicolon if depth == 0 => {
+                    self.position += 1;
+                    return Vec::new();
+                }
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => break,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.position += 1;
+                        return Vec::new();
+                    }
+                }
+                Token::If | Token::While | Token::Function | Token::Struct | Token::Import | Token::Try if depth == 0 && self.position > start => break,
+                _ => {}
+            }
+            self.position += 1;
+        }
+        if self.position == start {
+            self.position += 1;
+        }
+        Vec::new()
+    }
+
+    // Parse a single statement into instructions. Modules may only define
+    // functions and structs and import other modules.
+    fn parse_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        let definition = matches!(self.current_token(), Some(Token::Function) | Some(Token::Struct) | Some(Token::Import));
+        if self.module.path.is_some() && self.block_depth == 0 && !definition {
+            return Err(self.unexpected("a function, struct or import in a module"));
+        }
         match self.current_token() {
+            Some(Token::Import) => self.parse_import(),
             Some(Token::If) => self.parse_if_statement(),
             Some(Token::While) => self.parse_while_statement(),
             Some(Token::Function) => self.parse_function_definition(),
+            Some(Token::Struct) => self.parse_struct_definition(),
             Some(Token::Return) => self.parse_return_statement(),
+            Some(Token::Try) => self.parse_try_statement(),
+            Some(Token::Revert) => self.parse_revert_statement(),
             Some(Token::Identifier(_)) => self.parse_assignment_or_function_call(),
             Some(Token::Vote) => self.parse_vote_statement(),
             Some(Token::AllocateResource) => self.parse_allocate_resource_statement(),
@@ -281,148 +598,579 @@ impl Parser {
             Some(Token::CreateProposal) => self.parse_create_proposal_statement(),
             Some(Token::GetProposalStatus) => self.parse_get_proposal_status_statement(),
             Some(Token::Emit) => self.parse_emit_statement(),
-            _ => Err("Unexpected token in statement".into()),
+            _ => Err(self.unexpected("a statement")),
         }
     }
 
-    // Parse an if statement into opcodes
-    fn parse_if_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
-        // Implementation for parsing if statements
-        Err("If statement parsing not implemented yet".into())
+    // Parse a braced block of statements into instructions
+    fn parse_block(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::LBrace)?;
+        self.block_depth += 1;
+        let mut code = Vec::new();
+        while !matches!(self.current_token(), Some(Token::RBrace) | None) {
+            code.append(&mut self.parse_recovering());
+        }
+        self.block_depth -= 1;
+        self.consume_token(Token::RBrace)?;
+        Ok(code)
     }
 
-    // Parse a while loop into opcodes
-    fn parse_while_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
-        // Implementation for parsing while loops
-        Err("While statement parsing not implemented yet".into())
+    // Parse `import "coop_std/voting";`, or `import "coop_std/voting" as
+    // votes;`, which makes the module's functions and structs available as
+    // `voting.name` or `votes.name`. A module imported several times is
+    // compiled once.
+    fn parse_import(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::Import)?;
+        let span = self.current_span();
+        let path = self.consume_string()?;
+        let alias = if matches!(self.current_token(), Some(Token::As)) {
+            self.consume_token(Token::As)?;
+            self.consume_identifier()?
+        } else {
+            path.rsplit('/').next().unwrap_or_default().to_string()
+        };
+        self.consume_token(Token::Semicolon)?;
+        // The statement is complete, so errors from here on are recorded
+        // without skipping what follows
+        if self.block_depth > 0 {
+            self.import_error(span, "imports must be at the top level".to_string());
+            return Ok(Vec::new());
+        }
+        self.module.aliases.insert(alias, path.clone());
+
+        if self.importing.contains(&path) {
+            let cycle = [self.importing.as_slice(), &[path]].concat().join(" -> ");
+            self.import_error(span, format!("import cycle: {}", cycle));
+            return Ok(Vec::new());
+        }
+        if !self.compiled_modules.insert(path.clone()) {
+            return Ok(Vec::new());
+        }
+        let source = match self.resolver {
+            Some(resolver) => resolver.resolve(&path),
+            None => Err("no module resolver configured".to_string()),
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                self.import_error(span, format!("cannot import {}: {}", path, e));
+                self.failed_modules.insert(path);
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut lexer = Lexer::new(&source);
+        let (tokens, errors) = lexer.tokens();
+        self.errors.extend(errors.into_iter().map(|mut error| {
+            error.module = Some(path.clone());
+            error
+        }));
+        let tokens = std::mem::replace(&mut self.tokens, tokens);
+        let position = std::mem::replace(&mut self.position, 0);
+        let end = std::mem::replace(&mut self.end, lexer.end());
+        let module = std::mem::replace(&mut self.module, ModuleScope { path: Some(path.clone()), aliases: HashMap::new() });
+        self.importing.push(path);
+
+        let mut code = Vec::new();
+        while self.position < self.tokens.len() {
+            code.append(&mut self.parse_recovering());
+        }
+
+        self.importing.pop();
+        self.tokens = tokens;
+        self.position = position;
+        self.end = end;
+        self.module = module;
+        if self.module.path.is_none() {
+            self.definition_code.extend(code.iter().cloned());
+        }
+        Ok(code)
+    }
+
+    fn in_failed_module(&self, name: &str) -> bool {
+        name.split_once("::").is_some_and(|(path, _)| self.failed_modules.contains(path))
+    }
+
+    fn import_error(&mut self, span: Span, message: String) {
+        let error = CompileError { module: self.module.path.clone(), ..CompileError::at(span, message) };
+        self.errors.push(error);
+    }
+
+    // The full name of a function or struct: `alias.name` for one imported
+    // from the module known as `alias`, or `name` for one of this module
+    fn parse_qualified_name(&mut self, name: String) -> Result<String, CompileError> {
+        let path = match self.module.aliases.get(&name) {
+            Some(path) if matches!(self.current_token(), Some(Token::Dot)) => path.clone(),
+            _ => return Ok(self.module.qualify(&name)),
+        };
+        self.consume_token(Token::Dot)?;
+        let member = self.consume_identifier()?;
+        Ok(format!("{}::{}", path, member))
+    }
+
+    // Parse an if statement, with optional else or else-if branches
+    fn parse_if_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::If)?;
+        let else_label = self.new_label();
+        let end_label = self.new_label();
+        let mut code = self.parse_condition()?;
+        code.push(Opcode::Not.into());
+        code.push(Instruction::JumpIf(else_label));
+        code.append(&mut self.parse_block()?);
+        code.push(Instruction::Jump(end_label));
+        code.push(Instruction::Label(else_label));
+        if matches!(self.current_token(), Some(Token::Else)) {
+            self.consume_token(Token::Else)?;
+            if matches!(self.current_token(), Some(Token::If)) {
+                code.append(&mut self.parse_if_statement()?);
+            } else {
+                code.append(&mut self.parse_block()?);
+            }
+        }
+        code.push(Instruction::Label(end_label));
+        Ok(code)
+    }
+
+    // Parse a while loop into instructions
+    fn parse_while_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::While)?;
+        let start_label = self.new_label();
+        let end_label = self.new_label();
+        let mut code = vec![Instruction::Label(start_label)];
+        code.append(&mut self.parse_condition()?);
+        code.push(Opcode::Not.into());
+        code.push(Instruction::JumpIf(end_label));
+        code.append(&mut self.parse_block()?);
+        code.push(Instruction::Jump(start_label));
+        code.push(Instruction::Label(end_label));
+        Ok(code)
+    }
+
+    // Parse `try { } catch (e) { }`. Changes made in the try block are
+    // rolled back if it fails; the catch block gets the error message in `e`,
+    // which may be left out.
+    fn parse_try_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::Try)?;
+        let catch_label = self.new_label();
+        let end_label = self.new_label();
+        let mut code = vec![Instruction::Try(catch_label)];
+        code.append(&mut self.parse_block()?);
+        code.push(Opcode::EndTry.into());
+        code.push(Instruction::Jump(end_label));
+        code.push(Instruction::Label(catch_label));
+        self.consume_token(Token::Catch)?;
+        if matches!(self.current_token(), Some(Token::LParen)) {
+            self.consume_token(Token::LParen)?;
+            let name = self.consume_identifier()?;
+            self.consume_token(Token::RParen)?;
+            code.push(Opcode::Store(name).into());
+        } else {
+            code.push(Opcode::Pop.into());
+        }
+        code.append(&mut self.parse_block()?);
+        code.push(Instruction::Label(end_label));
+        Ok(code)
     }
 
-    // Parse a function definition into opcodes
-    fn parse_function_definition(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
-        // Implementation for parsing function definitions
-        Err("Function definition parsing not implemented yet".into())
+    // Parse `revert(message);`, which fails the current try block or the
+    // whole program
+    fn parse_revert_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::Revert)?;
+        self.consume_token(Token::LParen)?;
+        let mut code = self.parse_expression()?;
+        self.consume_token(Token::RParen)?;
+        code.push(Opcode::Revert.into());
+        self.consume_token(Token::Semicolon)?;
+        Ok(code)
     }
 
-    // Parse a return statement into opcodes
-    fn parse_return_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a function definition. Its code is jumped over where it is
+    // defined; the arguments are taken off the stack into locals on entry and
+    // a function that ends without returning returns 0.
+    fn parse_function_definition(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::Function)?;
+        let span = self.current_span();
+        let name = self.consume_identifier()?;
+        let name = self.module.qualify(&name);
+        if let Some(previous) = self.functions.insert(name.clone(), span) {
+            return Err(CompileError::at(span, format!("function {} defined twice, first on line {}", name, previous.line)));
+        }
+        self.consume_token(Token::LParen)?;
+        let mut parameters = Vec::new();
+        while !matches!(self.current_token(), Some(Token::RParen)) {
+            parameters.push(self.consume_identifier()?);
+            if matches!(self.current_token(), Some(Token::Comma)) {
+                self.consume_token(Token::Comma)?;
+            }
+        }
+        self.consume_token(Token::RParen)?;
+
+        let skip_label = self.new_label();
+        let mut code = vec![Instruction::Jump(skip_label), Instruction::Function(name)];
+        code.extend(parameters.into_iter().rev().map(|parameter| Opcode::Store(parameter).into()));
+        code.append(&mut self.parse_block()?);
+        code.push(Opcode::Push(Value::Int(0)).into());
+        code.push(Opcode::Return.into());
+        code.push(Instruction::Label(skip_label));
+        if self.module.path.is_none() {
+            self.definition_code.extend(code.iter().cloned());
+        }
+        Ok(code)
+    }
+
+    // Parse a return statement into instructions
+    fn parse_return_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::Return)?;
-        let mut opcodes = self.parse_expression()?;
-        opcodes.push(Opcode::Return);
+        let mut code = if matches!(self.current_token(), Some(Token::Semicolon)) {
+            vec![Opcode::Push(Value::Int(0)).into()]
+        } else {
+            self.parse_expression()?
+        };
+        code.push(Opcode::Return.into());
         self.consume_token(Token::Semicolon)?;
-        Ok(opcodes)
+        Ok(code)
     }
 
-    // Parse an assignment or function call into opcodes
-    fn parse_assignment_or_function_call(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse an assignment or function call into instructions
+    fn parse_assignment_or_function_call(&mut self) -> Result<Vec<Instruction>, CompileError> {
         let identifier = self.consume_identifier()?;
+        if self.module.aliases.contains_key(&identifier) && matches!(self.current_token(), Some(Token::Dot)) {
+            let name = self.parse_qualified_name(identifier)?;
+            let mut code = self.parse_function_call(name)?;
+            code.push(Opcode::Pop.into());
+            self.consume_token(Token::Semicolon)?;
+            return Ok(code);
+        }
         match self.current_token() {
             Some(Token::Equals) => self.parse_assignment(identifier),
-            Some(Token::LParen) => self.parse_function_call(identifier),
-            _ => Err("Expected '=' or '(' after identifier".into()),
+            Some(Token::LBracket) | Some(Token::Dot) => self.parse_path_assignment(identifier),
+            Some(Token::LParen) => {
+                // The call's return value is not used
+                let name = self.module.qualify(&identifier);
+                let mut code = self.parse_function_call(name)?;
+                code.push(Opcode::Pop.into());
+                self.consume_token(Token::Semicolon)?;
+                Ok(code)
+            }
+            _ => Err(self.unexpected("'=', '[', '.' or '('")),
         }
     }
 
-    // Parse an assignment statement into opcodes
-    fn parse_assignment(&mut self, identifier: String) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse an assignment statement into instructions
+    fn parse_assignment(&mut self, identifier: String) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::Equals)?;
-        let mut opcodes = self.parse_expression()?;
-        opcodes.push(Opcode::Store(identifier));
+        let mut code = self.parse_expression()?;
+        code.push(Opcode::Store(identifier).into());
         self.consume_token(Token::Semicolon)?;
-        Ok(opcodes)
+        Ok(code)
+    }
+
+    // Parse an assignment to an element or field, such as `xs[0] = value;`
+    // or `members[i].balance = value;`. Each container along the path is
+    // copied onto the stack with its key, the innermost one is updated, and
+    // every container is then set back into the one holding it, the outermost
+    // one being stored in `name` again.
+    fn parse_path_assignment(&mut self, identifier: String) -> Result<Vec<Instruction>, CompileError> {
+        let mut code = vec![Opcode::Load(identifier.clone()).into()];
+        code.append(&mut self.parse_accessor()?);
+        let mut depth = 1;
+        while matches!(self.current_token(), Some(Token::LBracket) | Some(Token::Dot)) {
+            code.push(Opcode::Copy(1).into());
+            code.push(Opcode::Copy(1).into());
+            code.push(Opcode::GetIndex.into());
+            code.append(&mut self.parse_accessor()?);
+            depth += 1;
+        }
+        self.consume_token(Token::Equals)?;
+        code.append(&mut self.parse_expression()?);
+        code.extend((0..depth).map(|_| Instruction::from(Opcode::SetIndex)));
+        code.push(Opcode::Store(identifier).into());
+        self.consume_token(Token::Semicolon)?;
+        Ok(code)
+    }
+
+    // Parse a bracketed index or key, or a `.field`, into the code pushing it
+    fn parse_accessor(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        if matches!(self.current_token(), Some(Token::Dot)) {
+            self.consume_token(Token::Dot)?;
+            let field = self.consume_identifier()?;
+            return Ok(vec![Opcode::Push(Value::String(field)).into()]);
+        }
+        self.consume_token(Token::LBracket)?;
+        let code = self.parse_expression()?;
+        self.consume_token(Token::RBracket)?;
+        Ok(code)
+    }
+
+    // Parse the indexing and field accesses following a value
+    fn parse_accessors(&mut self, mut code: Vec<Instruction>) -> Result<Vec<Instruction>, CompileError> {
+        while matches!(self.current_token(), Some(Token::LBracket) | Some(Token::Dot)) {
+            code.append(&mut self.parse_accessor()?);
+            code.push(Opcode::GetIndex.into());
+        }
+        Ok(code)
     }
 
-    // Parse a function call into opcodes
-    fn parse_function_call(&mut self, identifier: String) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a list literal such as `[1, 2, 3]`
+    fn parse_list_literal(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::LBracket)?;
+        let mut code = vec![Opcode::CreateList.into()];
+        while !matches!(self.current_token(), Some(Token::RBracket)) {
+            code.append(&mut self.parse_expression()?);
+            code.push(Opcode::ListAppend.into());
+            if matches!(self.current_token(), Some(Token::Comma)) {
+                self.consume_token(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume_token(Token::RBracket)?;
+        Ok(code)
+    }
+
+    // Parse `struct Member { name, balance }`. Structs are maps from field
+    // names to values; the definition only serves to check their literals.
+    fn parse_struct_definition(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::Struct)?;
+        let span = self.current_span();
+        let name = self.consume_identifier()?;
+        let name = self.module.qualify(&name);
+        self.consume_token(Token::LBrace)?;
+        let mut fields: Vec<String> = Vec::new();
+        while !matches!(self.current_token(), Some(Token::RBrace)) {
+            let field_span = self.current_span();
+            let field = self.consume_identifier()?;
+            if fields.contains(&field) {
+                return Err(CompileError::at(field_span, format!("field {} defined twice in struct {}", field, name)));
+            }
+            fields.push(field);
+            if matches!(self.current_token(), Some(Token::Comma)) {
+                self.consume_token(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume_token(Token::RBrace)?;
+        if let Some((_, previous)) = self.structs.insert(name.clone(), (fields, span)) {
+            return Err(CompileError::at(span, format!("struct {} defined twice, first on line {}", name, previous.line)));
+        }
+        Ok(Vec::new())
+    }
+
+    // Parse the fields of a struct literal such as `Member { name: "alice",
+    // balance: 10 }`, whose name has been consumed
+    fn parse_struct_literal(&mut self, name: String, span: Span) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::LBrace)?;
+        let mut code = vec![Opcode::CreateMap.into()];
+        let mut fields = Vec::new();
+        while !matches!(self.current_token(), Some(Token::RBrace)) {
+            let field_span = self.current_span();
+            let field = self.consume_identifier()?;
+            self.consume_token(Token::Colon)?;
+            code.push(Opcode::Push(Value::String(field.clone())).into());
+            code.append(&mut self.parse_expression()?);
+            code.push(Opcode::MapSet.into());
+            fields.push((field, field_span));
+            if matches!(self.current_token(), Some(Token::Comma)) {
+                self.consume_token(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume_token(Token::RBrace)?;
+        self.struct_literals.push(StructLiteral { name, fields, span, module: self.module.path.clone() });
+        Ok(code)
+    }
+
+    // Check that every struct literal sets exactly the fields of its struct,
+    // once the whole program, and so every struct, has been parsed
+    fn check_struct_literals(&mut self) {
+        for literal in &self.struct_literals {
+            let StructLiteral { name, fields, span, .. } = literal;
+            let error = |span: &Span, message: String| CompileError { module: literal.module.clone(), ..CompileError::at(*span, message) };
+            let defined = match self.structs.get(name) {
+                Some((defined, _)) => defined,
+                None if self.in_failed_module(name) => continue,
+                None => {
+                    self.errors.push(error(span, format!("undefined struct {}", name)));
+                    continue;
+                }
+            };
+            for (position, (field, field_span)) in fields.iter().enumerate() {
+                if !defined.contains(field) {
+                    self.errors.push(error(field_span, format!("struct {} has no field {}", name, field)));
+                } else if fields[..position].iter().any(|(other, _)| other == field) {
+                    self.errors.push(error(field_span, format!("field {} set twice", field)));
+                }
+            }
+            for field in defined {
+                if !fields.iter().any(|(set, _)| set == field) {
+                    self.errors.push(error(span, format!("missing field {} in struct {}", field, name)));
+                }
+            }
+        }
+    }
+
+    // Parse a map literal such as `{ "alice": 10, "bob": 20 }`
+    fn parse_map_literal(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::LBrace)?;
+        let mut code = vec![Opcode::CreateMap.into()];
+        while !matches!(self.current_token(), Some(Token::RBrace)) {
+            code.append(&mut self.parse_expression()?);
+            self.consume_token(Token::Colon)?;
+            code.append(&mut self.parse_expression()?);
+            code.push(Opcode::MapSet.into());
+            if matches!(self.current_token(), Some(Token::Comma)) {
+                self.consume_token(Token::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume_token(Token::RBrace)?;
+        Ok(code)
+    }
+
+    // Parse the arguments of a function call; the call leaves the return
+    // value on the stack
+    fn parse_function_call(&mut self, identifier: String) -> Result<Vec<Instruction>, CompileError> {
+        let span = self.tokens[self.position - 1].start;
+        self.calls.push((identifier.clone(), span, self.module.path.clone()));
         self.consume_token(Token::LParen)?;
-        let mut opcodes = Vec::new();
+        let mut code = Vec::new();
         while !matches!(self.current_token(), Some(Token::RParen)) {
-            opcodes.append(&mut self.parse_expression()?);
+            code.append(&mut self.parse_expression()?);
             if matches!(self.current_token(), Some(Token::Comma)) {
                 self.consume_token(Token::Comma)?;
             }
         }
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::Call(identifier));
-        self.consume_token(Token::Semicolon)?;
-        Ok(opcodes)
+        code.push(Instruction::Call(identifier));
+        Ok(code)
     }
 
-    // Parse a vote statement into opcodes
-    fn parse_vote_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a vote statement into instructions
+    fn parse_vote_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::Vote)?;
         self.consume_token(Token::LParen)?;
         let proposal_id = self.consume_string()?;
         self.consume_token(Token::Comma)?;
         let mut opcodes = self.parse_expression()?; // This should push a boolean onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::Vote(proposal_id));
+        opcodes.push(Opcode::Vote(proposal_id).into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse an allocate resource statement into opcodes
-    fn parse_allocate_resource_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse an allocate resource statement into instructions
+    fn parse_allocate_resource_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::AllocateResource)?;
         self.consume_token(Token::LParen)?;
         let resource_id = self.consume_string()?;
         self.consume_token(Token::Comma)?;
         let mut opcodes = self.parse_expression()?; // This should push an integer onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::AllocateResource(resource_id));
+        opcodes.push(Opcode::AllocateResource(resource_id).into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse an update reputation statement into opcodes
-    fn parse_update_reputation_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse an update reputation statement into instructions
+    fn parse_update_reputation_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::UpdateReputation)?;
         self.consume_token(Token::LParen)?;
         let address = self.consume_string()?;
         self.consume_token(Token::Comma)?;
         let mut opcodes = self.parse_expression()?; // This should push an integer onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::UpdateReputation(address));
+        opcodes.push(Opcode::UpdateReputation(address).into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse a create proposal statement into opcodes
-    fn parse_create_proposal_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a create proposal statement into instructions
+    fn parse_create_proposal_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::CreateProposal)?;
         self.consume_token(Token::LParen)?;
         let mut opcodes = self.parse_expression()?; // This should push a string onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::CreateProposal);
+        opcodes.push(Opcode::CreateProposal.into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse a get proposal status statement into opcodes
-    fn parse_get_proposal_status_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a get proposal status statement into instructions
+    fn parse_get_proposal_status_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::GetProposalStatus)?;
         self.consume_token(Token::LParen)?;
         let mut opcodes = self.parse_expression()?; // This should push a string onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::GetProposalStatus);
+        opcodes.push(Opcode::GetProposalStatus.into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse an emit statement into opcodes
-    fn parse_emit_statement(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse an emit statement into instructions
+    fn parse_emit_statement(&mut self) -> Result<Vec<Instruction>, CompileError> {
         self.consume_token(Token::Emit)?;
         self.consume_token(Token::LParen)?;
         let event_name = self.consume_string()?;
         self.consume_token(Token::Comma)?;
         let mut opcodes = self.parse_expression()?; // This should push the event data onto the stack
         self.consume_token(Token::RParen)?;
-        opcodes.push(Opcode::Emit(event_name));
+        opcodes.push(Opcode::Emit(event_name).into());
         self.consume_token(Token::Semicolon)?;
         Ok(opcodes)
     }
 
-    // Parse an expression into opcodes
-    fn parse_expression(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    // Parse a parenthesized condition, as used by if and while
+    fn parse_condition(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        self.consume_token(Token::LParen)?;
+        let code = self.parse_expression()?;
+        self.consume_token(Token::RParen)?;
+        Ok(code)
+    }
+
+    // Parse an expression into instructions; || binds loosest, then &&
+    fn parse_expression(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        let mut opcodes = self.parse_conjunction()?;
+        while matches!(self.current_token(), Some(Token::Or)) {
+            self.position += 1;
+            opcodes.append(&mut self.parse_conjunction()?);
+            opcodes.push(Opcode::Or.into());
+        }
+        Ok(opcodes)
+    }
+
+    fn parse_conjunction(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        let mut opcodes = self.parse_comparison()?;
+        while matches!(self.current_token(), Some(Token::And)) {
+            self.position += 1;
+            opcodes.append(&mut self.parse_comparison()?);
+            opcodes.push(Opcode::And.into());
+        }
+        Ok(opcodes)
+    }
+
+    // Comparisons without an opcode of their own negate the opposite one
+    fn parse_comparison(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        let mut opcodes = self.parse_sum()?;
+        let comparison: &[Opcode] = match self.current_token() {
+            Some(Token::DoubleEquals) => &[Opcode::Eq],
+            Some(Token::NotEquals) => &[Opcode::Eq, Opcode::Not],
+            Some(Token::LessThan) => &[Opcode::Lt],
+            Some(Token::GreaterThan) => &[Opcode::Gt],
+            Some(Token::LessThanEquals) => &[Opcode::Gt, Opcode::Not],
+            Some(Token::GreaterThanEquals) => &[Opcode::Lt, Opcode::Not],
+            _ => return Ok(opcodes),
+        };
+        let comparison = comparison.to_vec();
+        self.position += 1;
+        opcodes.append(&mut self.parse_sum()?);
+        opcodes.extend(comparison.into_iter().map(Instruction::from));
+        Ok(opcodes)
+    }
+
+    fn parse_sum(&mut self) -> Result<Vec<Instruction>, CompileError> {
         let mut opcodes = self.parse_term()?;
 
         while let Some(token) = self.current_token() {
@@ -430,12 +1178,12 @@ impl Parser {
                 Token::Plus => {
                     self.position += 1;
                     opcodes.append(&mut self.parse_term()?);
-                    opcodes.push(Opcode::Add);
+                    opcodes.push(Opcode::Add.into());
                 }
                 Token::Minus => {
                     self.position += 1;
                     opcodes.append(&mut self.parse_term()?);
-                    opcodes.push(Opcode::Sub);
+                    opcodes.push(Opcode::Sub.into());
                 }
                 _ => break,
             }
@@ -444,7 +1192,7 @@ impl Parser {
         Ok(opcodes)
     }
 
-    fn parse_term(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    fn parse_term(&mut self) -> Result<Vec<Instruction>, CompileError> {
         let mut opcodes = self.parse_factor()?;
 
         while let Some(token) = self.current_token() {
@@ -452,12 +1200,17 @@ impl Parser {
                 Token::Multiply => {
                     self.position += 1;
                     opcodes.append(&mut self.parse_factor()?);
-                    opcodes.push(Opcode::Mul);
+                    opcodes.push(Opcode::Mul.into());
                 }
                 Token::Divide => {
                     self.position += 1;
                     opcodes.append(&mut self.parse_factor()?);
-                    opcodes.push(Opcode::Div);
+                    opcodes.push(Opcode::Div.into());
+                }
+                Token::Modulo => {
+                    self.position += 1;
+                    opcodes.append(&mut self.parse_factor()?);
+                    opcodes.push(Opcode::Mod.into());
                 }
                 _ => break,
             }
@@ -466,82 +1219,156 @@ impl Parser {
         Ok(opcodes)
     }
 
-    fn parse_factor(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    fn parse_factor(&mut self) -> Result<Vec<Instruction>, CompileError> {
         let token = self.current_token().cloned();
         match token {
             Some(Token::Integer(value)) => {
                 self.position += 1;
-                Ok(vec![Opcode::Push(Value::Int(value))])
+                Ok(vec![Opcode::Push(Value::Int(value)).into()])
             }
             Some(Token::Float(value)) => {
                 self.position += 1;
-                Ok(vec![Opcode::Push(Value::Float(value))])
+                Ok(vec![Opcode::Push(Value::Float(value)).into()])
             }
             Some(Token::String(value)) => {
                 self.position += 1;
-                Ok(vec![Opcode::Push(Value::String(value))])
+                Ok(vec![Opcode::Push(Value::String(value)).into()])
             }
             Some(Token::True) => {
                 self.position += 1;
-                Ok(vec![Opcode::Push(Value::Bool(true))])
+                Ok(vec![Opcode::Push(Value::Bool(true)).into()])
             }
             Some(Token::False) => {
                 self.position += 1;
-                Ok(vec![Opcode::Push(Value::Bool(false))])
+                Ok(vec![Opcode::Push(Value::Bool(false)).into()])
             }
             Some(Token::Identifier(name)) => {
+                let span = self.current_span();
                 self.position += 1;
-                Ok(vec![Opcode::Load(name)])
+                let code = if self.module.aliases.contains_key(&name) && matches!(self.current_token(), Some(Token::Dot)) {
+                    let name = self.parse_qualified_name(name)?;
+                    match self.current_token() {
+                        Some(Token::LParen) => self.parse_function_call(name)?,
+                        Some(Token::LBrace) => self.parse_struct_literal(name, span)?,
+                        _ => return Err(self.unexpected("'(' or '{' after a module member")),
+                    }
+                } else {
+                    match self.current_token() {
+                        Some(Token::LParen) => self.parse_function_call(self.module.qualify(&name))?,
+                        Some(Token::LBrace) => self.parse_struct_literal(self.module.qualify(&name), span)?,
+                        _ => vec![Opcode::Load(name).into()],
+                    }
+                };
+                self.parse_accessors(code)
+            }
+            Some(Token::LBracket) => {
+                let code = self.parse_list_literal()?;
+                self.parse_accessors(code)
+            }
+            Some(Token::LBrace) => self.parse_map_literal(),
+            Some(Token::ToInt) | Some(Token::ToFloat) => {
+                let conversion = if token == Some(Token::ToInt) { Opcode::ToInt } else { Opcode::ToFloat };
+                self.position += 1;
+                self.consume_token(Token::LParen)?;
+                let mut opcodes = self.parse_expression()?;
+                self.consume_token(Token::RParen)?;
+                opcodes.push(conversion.into());
+                Ok(opcodes)
+            }
+            Some(Token::Contains) => {
+                self.position += 1;
+                self.consume_token(Token::LParen)?;
+                let mut opcodes = self.parse_expression()?;
+                self.consume_token(Token::Comma)?;
+                opcodes.append(&mut self.parse_expression()?);
+                self.consume_token(Token::RParen)?;
+                opcodes.push(Opcode::MapContains.into());
+                Ok(opcodes)
+            }
+            Some(Token::Not) => {
+                self.position += 1;
+                let mut opcodes = self.parse_factor()?;
+                opcodes.push(Opcode::Not.into());
+                Ok(opcodes)
+            }
+            Some(Token::Len) => {
+                self.position += 1;
+                self.consume_token(Token::LParen)?;
+                let mut opcodes = self.parse_expression()?;
+                self.consume_token(Token::RParen)?;
+                opcodes.push(Opcode::Length.into());
+                Ok(opcodes)
             }
             Some(Token::LParen) => {
                 self.position += 1;
                 let expr = self.parse_expression()?;
                 self.consume_token(Token::RParen)?;
-                Ok(expr)
+                self.parse_accessors(expr)
             }
-            _ => Err("Unexpected token in expression".into()),
+            _ => Err(self.unexpected("an expression")),
         }
     }
 
-    // Consume the next token if it matches the expected token
-    fn consume_token(&mut self, expected: Token) -> Result<(), Box<dyn Error>> {
+    // Consume the next token if it matches the expected token. A missing
+    // ';' is reported right after the token it should follow.
+    fn consume_token(&mut self, expected: Token) -> Result<(), CompileError> {
         if self.current_token() == Some(&expected) {
             self.position += 1;
             Ok(())
+        } else if expected == Token::Semicolon && self.position > 0 {
+            Err(CompileError::at(self.tokens[self.position - 1].end, "expected ';'".to_string()))
         } else {
-            Err(format!("Unexpected token: expected {:?}, found {:?}", expected, self.current_token()).into())
+            Err(self.unexpected(&expected.describe()))
         }
     }
 
     // Consume an identifier token
-    fn consume_identifier(&mut self) -> Result<String, Box<dyn Error>> {
+    fn consume_identifier(&mut self) -> Result<String, CompileError> {
         if let Some(Token::Identifier(name)) = self.current_token().cloned() {
             self.position += 1;
             Ok(name)
         } else {
-            Err(format!("Expected identifier, found {:?}", self.current_token()).into())
+            Err(self.unexpected("an identifier"))
         }
     }
 
     // Consume a string token
-    fn consume_string(&mut self) -> Result<String, Box<dyn Error>> {
+    fn consume_string(&mut self) -> Result<String, CompileError> {
         if let Some(Token::String(value)) = self.current_token().cloned() {
             self.position += 1;
             Ok(value)
         } else {
-            Err(format!("Expected string, found {:?}", self.current_token()).into())
+            Err(self.unexpected("a string"))
         }
     }
 
+    // An error at the current token, which is not what was expected
+    fn unexpected(&self, expected: &str) -> CompileError {
+        let found = self.current_token().map_or_else(|| "end of input".to_string(), Token::describe);
+        CompileError::at(self.current_span(), format!("expected {}, found {}", expected, found))
+    }
+
     // Get the current token
     fn current_token(&self) -> Option<&Token> {
-        self.tokens.get(self.position)
+        self.tokens.get(self.position).map(|spanned| &spanned.token)
+    }
+
+    fn current_span(&self) -> Span {
+        self.tokens.get(self.position).map_or(self.end, |spanned| spanned.start)
+    }
+
+    // Allocate a label no other code of this program uses
+    fn new_label(&mut self) -> Label {
+        self.next_label += 1;
+        self.next_label
     }
 }
 
 // Compiler for converting source code into opcodes
 pub struct CSCLCompiler {
     lexer: Lexer,
+    resolver: Option<Box<dyn ModuleResolver>>,
+    optimizing: bool,
 }
 
 impl CSCLCompiler {
@@ -549,37 +1376,104 @@ impl CSCLCompiler {
     pub fn new(input: &str) -> Self {
         CSCLCompiler {
             lexer: Lexer::new(input),
+            resolver: None,
+            optimizing: false,
         }
     }
 
-    // Compile the source code into a vector of opcodes
-    pub fn compile(&mut self) -> Result<Vec<Opcode>, Box<dyn Error>> {
-        let tokens = self.lexer.tokens();
-        let mut parser = Parser::new(tokens);
-        parser.parse()
+    // Resolve the modules the source imports with the given resolver;
+    // without one, imports fail
+    pub fn with_resolver<R: ModuleResolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    // Fold constants and drop dead code and redundant loads, so deployed
+    // contracts execute fewer instructions
+    pub fn with_optimizer(mut self) -> Self {
+        self.optimizing = true;
+        self
+    }
+
+    // Compile the source code into a vector of opcodes, or report every
+    // error found in it, in source order
+    pub fn compile(&mut self) -> Result<Vec<Opcode>, Vec<CompileError>> {
+        let (tokens, mut errors) = self.lexer.tokens();
+        let parser = Parser::new(tokens, self.lexer.end(), self.resolver.as_deref());
+        match parser.parse(self.optimizing) {
+            Ok(program) if errors.is_empty() => Ok(program),
+            Ok(_) => Err(errors),
+            Err(parse_errors) => {
+                errors.extend(parse_errors);
+                sort_errors(&mut errors);
+                Err(errors)
+            }
+        }
     }
 }
 
-impl Lexer {
-    // Get all tokens from the input
-    fn tokens(&mut self) -> Vec<Token> {
-        let mut tokens = Vec::new();
-        while let Some(token) = self.next_token() {
-            tokens.push(token);
+fn sort_errors(errors: &mut [CompileError]) {
+    errors.sort_by(|a, b| (&a.module, a.line, a.column).cmp(&(&b.module, b.line, b.column)));
+}
+
+// Compile a snippet of a session: a single expression, whose value is left
+// on the stack, or statements. The program also holds the code of what
+// earlier snippets defined, and what this one defines is added to it.
+pub(crate) fn compile_snippet(source: &str, definitions: &mut Definitions, resolver: Option<&dyn ModuleResolver>) -> Result<Vec<Opcode>, Vec<CompileError>> {
+    let mut lexer = Lexer::new(source);
+    let (tokens, errors) = lexer.tokens();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let end = lexer.end();
+
+    // Only identifiers start both expressions and statements; otherwise the
+    // first token tells which of the two the snippet is meant to be
+    let statement = !matches!(
+        tokens.first().map(|spanned| &spanned.token),
+        Some(Token::Integer(_)) | Some(Token::Float(_)) | Some(Token::String(_)) | Some(Token::True) | Some(Token::False)
+            | Some(Token::LParen) | Some(Token::LBracket) | Some(Token::LBrace) | Some(Token::Not)
+            | Some(Token::ToInt) | Some(Token::ToFloat) | Some(Token::Contains) | Some(Token::Len)
+    );
+    let mut parser = Parser::new(tokens.clone(), end, resolver).with_definitions(definitions);
+    let code = match parser.parse_expression() {
+        Ok(code) if parser.position == parser.tokens.len() => code,
+        Ok(_) if !statement => return Err(vec![parser.unexpected("end of input")]),
+        Err(error) if !statement => return Err(vec![error]),
+        _ => {
+            parser = Parser::new(tokens, end, resolver).with_definitions(definitions);
+            parser.parse_statements()
         }
-        tokens
+    };
+    parser.check_references();
+    if !parser.errors.is_empty() {
+        sort_errors(&mut parser.errors);
+        return Err(parser.errors);
     }
+
+    let program = assemble(definitions.code.iter().cloned().chain(code).collect())
+        .map_err(|message| vec![CompileError::at(Span { line: 1, column: 1 }, message)])?;
+    definitions.code.append(&mut parser.definition_code);
+    definitions.next_label = parser.next_label;
+    definitions.functions = parser.functions;
+    definitions.structs = parser.structs;
+    definitions.aliases = parser.module.aliases;
+    definitions.compiled_modules = parser.compiled_modules;
+    Ok(program)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vm::{CoopVM, MemoryResolver, VmError, VmLimits};
 
     #[test]
     fn test_lexer() {
         let input = "function test(x, y) { return x + y; }";
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokens();
+        let (tokens, errors) = lexer.tokens();
+        assert!(errors.is_empty());
+        let tokens: Vec<Token> = tokens.into_iter().map(|spanned| spanned.token).collect();
 
         assert_eq!(tokens, vec![
             Token::Function,
@@ -599,6 +1493,40 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_comments_escapes_and_negative_numbers() {
+        let input = "// a comment\nx = -5 - -2.5; /* spans\n lines */ y = x-1;\nmessage = \"say \\\"hi\\\"\\n\\\\\";";
+        let (tokens, errors) = Lexer::new(input).tokens();
+        assert!(errors.is_empty());
+        let tokens: Vec<Token> = tokens.into_iter().map(|spanned| spanned.token).collect();
+        assert_eq!(tokens, vec![
+            Token::Identifier("x".to_string()),
+            Token::Equals,
+            Token::Integer(-5),
+            Token::Minus,
+            Token::Float(-2.5),
+            Token::Semicolon,
+            Token::Identifier("y".to_string()),
+            Token::Equals,
+            Token::Identifier("x".to_string()),
+            Token::Minus,
+            Token::Integer(1),
+            Token::Semicolon,
+            Token::Identifier("message".to_string()),
+            Token::Equals,
+            Token::String("say \"hi\"\n\\".to_string()),
+            Token::Semicolon,
+        ]);
+
+        let vm = run("min = -9223372036854775808; total = 10 - -3; scaled = (total)-1; // done");
+        assert_eq!(vm.get_memory()["min"], Value::Int(i64::MIN));
+        assert_eq!(vm.get_memory()["total"], Value::Int(13));
+        assert_eq!(vm.get_memory()["scaled"], Value::Int(12));
+
+        let errors: Vec<String> = CSCLCompiler::new("a = \"bad \\q\"; b = 1;\n  /* never closed").compile().unwrap_err().iter().map(ToString::to_string).collect();
+        assert_eq!(errors, vec!["unknown escape sequence \\q at line 1:10", "unterminated comment at line 2:3"]);
+    }
+
     #[test]
     fn test_compiler() {
         let input = "x = 5 + 3 * 2; y = (10 - 4) / 2;";
@@ -620,4 +1548,276 @@ mod tests {
             Opcode::Store("y".to_string()),
         ]);
     }
+
+    #[test]
+    fn test_errors_reported_with_positions() {
+        let source = "x = 1;\ny = (2 + ;\nz = 3 @;\nif (x) { w = ; }\nv = missing(1)\nfunction f() { }\nfunction f() { }\n}";
+        let errors: Vec<String> = CSCLCompiler::new(source).compile().unwrap_err().iter().map(ToString::to_string).collect();
+        assert_eq!(errors, vec![
+            "expected an expression, found ';' at line 2:10",
+            "unexpected character '@' at line 3:7",
+            "expected an expression, found ';' at line 4:14",
+            "undefined function missing at line 5:5",
+            "expected ';' at line 5:15",
+            "function f defined twice, first on line 6 at line 7:10",
+            "expected a statement, found '}' at line 8:1",
+        ]);
+
+        let errors = CSCLCompiler::new("emit(\"x\", 99999999999999999999);\nwhile (true) {").compile().unwrap_err();
+        assert_eq!(errors[0], CompileError { message: "integer 99999999999999999999 is too large".to_string(), line: 1, column: 11, module: None });
+        assert_eq!(errors.last().unwrap().to_string(), "expected '}', found end of input at line 2:15");
+    }
+
+    fn run(source: &str) -> CoopVM {
+        let mut vm = CoopVM::new(CSCLCompiler::new(source).compile().unwrap());
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_if_else_jumps() {
+        let source = "x = 3; if (x > 5) { y = 1; } else if (x == 3) { y = 2; } else { y = 3; } z = y * 10;";
+        let vm = run(source);
+        assert_eq!(vm.get_memory()["y"], Value::Int(2));
+        assert_eq!(vm.get_memory()["z"], Value::Int(20));
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let vm = run("i = 0; total = 0; while (i < 5 && total != 100) { total = total + i; i = i + 1; }");
+        assert_eq!(vm.get_memory()["total"], Value::Int(10));
+        assert!(vm.get_stack().is_empty());
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "
+            function fib(n) {
+                if (n <= 1) { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+            n = 10;
+            result = fib(n);
+            log(result);
+            function log(value) { emit(\"Result\", value); }
+        ";
+        let vm = run(source);
+        assert_eq!(vm.get_memory()["result"], Value::Int(55));
+        assert_eq!(vm.get_memory()["n"], Value::Int(10), "parameters are local to the call");
+        assert!(vm.get_stack().is_empty());
+
+        assert!(CSCLCompiler::new("x = missing(1);").compile().is_err());
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "
+            allocations = { \"alice\": 10, \"bob\": 20 };
+            allocations[\"carol\"] = allocations[\"alice\"] + 5;
+            allocations[\"alice\"] = 0;
+            has_dave = contains(allocations, \"dave\");
+            if (contains(allocations, \"carol\")) { carol = allocations[\"carol\"]; }
+            nested = { \"inner\": { \"x\": 1 } };
+            x = nested[\"inner\"][\"x\"];
+        ";
+        let vm = run(source);
+        let memory = vm.get_memory();
+        let expected: std::collections::BTreeMap<String, Value> = [("alice", 0), ("bob", 20), ("carol", 15)]
+            .into_iter()
+            .map(|(member, amount)| (member.to_string(), Value::Int(amount)))
+            .collect();
+        assert_eq!(memory["allocations"], Value::Map(expected));
+        assert_eq!(memory["has_dave"], Value::Bool(false));
+        assert_eq!(memory["carol"], Value::Int(15));
+        assert_eq!(memory["x"], Value::Int(1));
+
+        let mut vm = CoopVM::new(CSCLCompiler::new("m = {}; y = m[\"missing\"];").compile().unwrap());
+        assert!(vm.run().is_err());
+    }
+
+    #[test]
+    fn test_lists_and_structs() {
+        let source = "
+            struct Member { name, balance }
+            members = [Member { name: \"alice\", balance: 10 }, Member { balance: 20, name: \"bob\" }];
+            members[1].balance = members[1].balance + 5;
+            grid = [[1, 2], [3, 4]];
+            grid[1][0] = len(members);
+            i = 0;
+            total = 0;
+            while (i < len(members)) { total = total + members[i].balance; i = i + 1; }
+            first = members[0].name;
+            corner = ([7, 8, 9])[len(grid)];
+        ";
+        let vm = run(source);
+        let memory = vm.get_memory();
+        assert_eq!(memory["total"], Value::Int(35));
+        assert_eq!(memory["first"], Value::String("alice".to_string()));
+        assert_eq!(memory["corner"], Value::Int(9));
+        let row = |values: &[i64]| Value::List(values.iter().map(|v| Value::Int(*v)).collect());
+        assert_eq!(memory["grid"], Value::List(vec![row(&[1, 2]), row(&[2, 4])]));
+        assert!(vm.get_stack().is_empty());
+
+        let mut vm = CoopVM::new(CSCLCompiler::new("xs = [1, 2]; xs[2] = 3;").compile().unwrap());
+        assert_eq!(vm.run(), Err(VmError::IndexOutOfBounds { index: 2, length: 2 }));
+
+        let source = "struct Point { x, y }\np = Point { x: 1, z: 2 };\nq = Line { a: 1 };";
+        let errors: Vec<String> = CSCLCompiler::new(source).compile().unwrap_err().iter().map(ToString::to_string).collect();
+        assert_eq!(errors, vec![
+            "missing field y in struct Point at line 2:5",
+            "struct Point has no field z at line 2:19",
+            "undefined struct Line at line 3:5",
+        ]);
+    }
+
+    #[test]
+    fn test_imports() {
+        let resolver = || MemoryResolver::new()
+            .with_module("coop_std/math", "function max(a, b) { if (a > b) { return a; } return b; }")
+            .with_module("coop_std/voting", "
+                import \"coop_std/math\";
+                struct Tally { yes, no }
+                function count(votes) {
+                    tally = Tally { yes: 0, no: 0 };
+                    i = 0;
+                    while (i < len(votes)) {
+                        if (votes[i]) { tally.yes = tally.yes + 1; } else { tally.no = tally.no + 1; }
+                        i = i + 1;
+                    }
+                    return tally;
+                }
+                function margin(votes) { tally = count(votes); return math.max(tally.yes, tally.no) - math.max(0, 0); }
+            ")
+            .with_module("a", "import \"b\";")
+            .with_module("b", "import \"a\";")
+            .with_module("broken", "function f() { return 1 }\nx = 1;");
+
+        let source = "
+            import \"coop_std/voting\";
+            import \"coop_std/math\" as m;
+            import \"coop_std/voting\";
+            function max(x, y) { return -1; }
+            tally = voting.count([true, false, true]);
+            margin = voting.margin([true, true, true, false]);
+            bigger = m.max(3, max(1, 2));
+            empty = voting.Tally { yes: 0, no: 0 };
+        ";
+        let program = CSCLCompiler::new(source).with_resolver(resolver()).compile().unwrap();
+        let definitions = program.windows(2).filter(|pair| pair[1] == Opcode::Store("b".to_string()) && matches!(pair[0], Opcode::Jump(_)));
+        assert_eq!(definitions.count(), 1, "coop_std/math is compiled once");
+        let mut vm = CoopVM::new(program);
+        vm.run().unwrap();
+        let memory = vm.get_memory();
+        let tally: std::collections::BTreeMap<String, Value> = [("no", 1), ("yes", 2)].into_iter()
+            .map(|(field, count)| (field.to_string(), Value::Int(count)))
+            .collect();
+        assert_eq!(memory["tally"], Value::Map(tally));
+        assert_eq!(memory["margin"], Value::Int(3));
+        assert_eq!(memory["bigger"], Value::Int(3));
+
+        let errors = |source: &str| -> Vec<String> {
+            CSCLCompiler::new(source).with_resolver(resolver()).compile().unwrap_err().iter().map(ToString::to_string).collect()
+        };
+        assert_eq!(errors("import \"a\";"), vec!["import cycle: a -> b -> a at line 1:8 of b"]);
+        assert_eq!(errors("import \"missing\";\nx = missing.f();\ny = ;"), vec![
+            "cannot import missing: module missing not found at line 1:8",
+            "expected an expression, found ';' at line 3:5",
+        ]);
+        assert_eq!(errors("import \"broken\";"), vec![
+            "expected ';' at line 1:24 of broken",
+            "expected a function, struct or import in a module, found identifier x at line 2:1 of broken",
+        ]);
+        assert_eq!(errors("x = voting.count([]);"), vec!["expected ';' at line 1:17"], "voting is not imported");
+        assert!(CSCLCompiler::new("import \"coop_std/math\";").compile().is_err(), "imports need a resolver");
+    }
+
+    #[test]
+    fn test_mixed_arithmetic() {
+        let source = "
+            share = 7 / 2;
+            exact = 7 / 2.0;
+            total = 1.5 + 2 * 3;
+            rest = 17 % 5;
+            units = int(total * 2);
+            ratio = float(units) / 4;
+            bigger = 3 > 2.5;
+            same = 2 == 2.0;
+        ";
+        let vm = run(source);
+        let memory = vm.get_memory();
+        assert_eq!(memory["share"], Value::Int(3));
+        assert_eq!(memory["exact"], Value::Float(3.5));
+        assert_eq!(memory["total"], Value::Float(7.5));
+        assert_eq!(memory["rest"], Value::Int(2));
+        assert_eq!(memory["units"], Value::Int(15));
+        assert_eq!(memory["ratio"], Value::Float(3.75));
+        assert_eq!(memory["bigger"], Value::Bool(true));
+        assert_eq!(memory["same"], Value::Bool(true));
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        for source in ["x = 1 / 0;", "x = 1.5 / 0.0;", "x = 5 % 0;", "x = \"a\" + 1;", "x = int(float(9223372036854775807) * 2);", "x = 9223372036854775807 + 1;"] {
+            let mut vm = CoopVM::new(CSCLCompiler::new(source).compile().unwrap());
+            assert!(vm.run().is_err(), "{} should fail", source);
+        }
+    }
+
+    #[test]
+    fn test_try_catch_rolls_back() {
+        let source = "
+            balance = 100;
+            function withdraw(amount) {
+                balance = balance - amount;
+                emit(\"Withdrawn\", amount);
+                if (balance < 0) { revert(\"Insufficient balance\"); }
+                return balance;
+            }
+            try { left = withdraw(30); } catch (e) { error = e; }
+            try { left = withdraw(500); } catch (e) { error = e; }
+            try { x = 1 / 0; } catch { divided = false; }
+        ";
+        let vm = run(source);
+        let memory = vm.get_memory();
+        assert_eq!(memory["left"], Value::Int(70));
+        assert_eq!(memory["error"], Value::String("Insufficient balance".to_string()));
+        assert_eq!(memory["divided"], Value::Bool(false));
+        assert!(!memory.contains_key("x"));
+        assert_eq!(vm.get_events().len(), 1, "the failed withdrawal's event is rolled back");
+        assert!(vm.get_stack().is_empty());
+    }
+
+    #[test]
+    fn test_uncaught_error_reverts_run() {
+        let mut vm = CoopVM::new(CSCLCompiler::new("a = 1; emit(\"A\", a);").compile().unwrap());
+        vm.run().unwrap();
+        vm.load_program(CSCLCompiler::new("a = 2; emit(\"B\", a); b = missing;").compile().unwrap());
+        assert_eq!(vm.run(), Err(VmError::VariableNotFound("missing".to_string())));
+        assert_eq!(vm.get_memory()["a"], Value::Int(1));
+        assert_eq!(vm.get_events().len(), 1);
+
+        vm.load_program(CSCLCompiler::new("revert(\"stop\");").compile().unwrap());
+        assert_eq!(vm.run(), Err(VmError::Reverted("stop".to_string())));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let limits = VmLimits { max_stack_depth: 16, max_memory_entries: 8, max_memory_bytes: 256, max_call_depth: 32 };
+        let exhausted = |source: &str| {
+            let mut vm = CoopVM::new(CSCLCompiler::new(source).compile().unwrap()).with_limits(limits.clone());
+            match vm.run() {
+                Err(VmError::ResourceExhausted { resource, .. }) => resource,
+                other => panic!("{} ran to {:?}", source, other),
+            }
+        };
+        assert_eq!(exhausted("function f() { return f(); } x = f();"), "call depth");
+        assert_eq!(exhausted(&format!("x = {}1{};", "1 + (".repeat(16), ")".repeat(16))), "stack depth");
+        assert_eq!(exhausted("a = 1; b = 2; c = 3; d = 4; e = 5; f = 6; g = 7; h = 8; i = 9;"), "memory entries");
+        assert_eq!(exhausted("s = \"0123456789\"; xs = [s]; while (true) { xs = [xs, xs]; }"), "memory bytes");
+
+        let mut vm = CoopVM::new(CSCLCompiler::new("function f(n) { if (n == 0) { return 0; } return f(n - 1) + 1; } x = f(20);").compile().unwrap())
+            .with_limits(VmLimits { max_memory_entries: 32, ..limits });
+        vm.run().unwrap();
+        assert_eq!(vm.get_memory()["x"], Value::Int(20));
+    }
 }
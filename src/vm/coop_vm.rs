@@ -1,11 +1,64 @@
+use super::error::VmError;
 use super::opcode::{Opcode, Value};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use tracing::{debug, info};
+
+/// How far a program may grow the machine before it is stopped with
+/// `VmError::ResourceExhausted`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmLimits {
+    /// Values on the stack at once.
+    pub max_stack_depth: usize,
+    /// Variables stored at once, in memory and the locals of every call in
+    /// progress together.
+    pub max_memory_entries: usize,
+    /// Bytes taken by the names and values of those variables, as estimated
+    /// by `Value::size`.
+    pub max_memory_bytes: usize,
+    /// Nested function calls.
+    pub max_call_depth: usize,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        VmLimits {
+            max_stack_depth: 1024,
+            max_memory_entries: 10_000,
+            max_memory_bytes: 1 << 20,
+            max_call_depth: 1024,
+        }
+    }
+}
+
+/// A function call in progress. Variables stored during the call are local to
+/// it; loads fall back to the program's memory.
+#[derive(Debug)]
+struct Frame {
+    return_pc: usize,
+    locals: HashMap<String, Value>,
+}
+
+/// A `try` block in progress, with what is needed to roll back to its start.
+#[derive(Debug)]
+struct Handler {
+    catch_pc: usize,
+    stack_len: usize,
+    frame_depth: usize,
+    memory: HashMap<String, Value>,
+    locals: Option<HashMap<String, Value>>,
+    events_len: usize,
+}
 
 pub struct CoopVM {
     stack: Vec<Value>,
     memory: HashMap<String, Value>,
     program: Vec<Opcode>,
     pc: usize,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    events: Vec<(String, Value)>,
+    limits: VmLimits,
 }
 
 impl CoopVM {
@@ -15,103 +68,371 @@ impl CoopVM {
             memory: HashMap::new(),
             program,
             pc: 0,
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            events: Vec::new(),
+            limits: VmLimits::default(),
         }
     }
 
+    pub fn with_limits(mut self, limits: VmLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets a variable in memory before the program runs, e.g. its inputs.
+    pub fn with_memory(mut self, name: &str, value: Value) -> Self {
+        self.memory.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn limits(&self) -> &VmLimits {
+        &self.limits
+    }
+
     pub fn load_program(&mut self, program: Vec<Opcode>) {
         self.program = program;
         self.pc = 0;
+        self.frames.clear();
+        self.handlers.clear();
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        while self.pc < self.program.len() {
-            self.execute_instruction()?;
-            self.pc += 1;
+    /// Runs the program to the end. Errors inside a `try` block are handed to
+    /// its catch block; any other error stops the run and rolls memory and
+    /// events back to where they were before it.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        let memory = self.memory.clone();
+        let events_len = self.events.len();
+        while !self.is_halted() {
+            if let Err(error) = self.step() {
+                self.memory = memory;
+                self.events.truncate(events_len);
+                self.frames.clear();
+                self.handlers.clear();
+                return Err(error);
+            }
         }
         Ok(())
     }
 
-    fn execute_instruction(&mut self) -> Result<(), String> {
+    /// Executes the instruction at the program counter. An error inside a
+    /// `try` block is handed to its catch block; any other error is returned
+    /// with the machine left as it was when the instruction failed, so it can
+    /// be inspected. Nothing is rolled back, unlike with `run`.
+    pub fn step(&mut self) -> Result<(), VmError> {
+        match self.execute_step() {
+            Err(error) if !self.trap(&error) => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn execute_step(&mut self) -> Result<(), VmError> {
+        self.pc = match self.execute_instruction()? {
+            Some(target) if target > self.program.len() => return Err(VmError::JumpOutOfRange(target)),
+            Some(target) => target,
+            None => self.pc + 1,
+        };
+        Ok(())
+    }
+
+    /// Hands an error to the innermost `try` block: drops the frames entered
+    /// since, restores the state saved when the block started and continues
+    /// in its catch block with the error message on the stack.
+    fn trap(&mut self, error: &VmError) -> bool {
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return false,
+        };
+        self.stack.truncate(handler.stack_len);
+        self.frames.truncate(handler.frame_depth);
+        self.memory = handler.memory;
+        if let (Some(frame), Some(locals)) = (self.frames.last_mut(), handler.locals) {
+            frame.locals = locals;
+        }
+        self.events.truncate(handler.events_len);
+        self.stack.push(Value::String(error.to_string()));
+        self.pc = handler.catch_pc;
+        true
+    }
+
+    /// Executes the instruction at the program counter and returns where
+    /// execution continues if not at the next instruction.
+    fn execute_instruction(&mut self) -> Result<Option<usize>, VmError> {
         let opcode = self.program[self.pc].clone();
         match opcode {
             Opcode::Push(value) => self.stack.push(value),
             Opcode::Pop => {
-                self.stack.pop().ok_or("Stack underflow")?;
-            }
-            Opcode::Add => self.binary_op(|a, b| a + b)?,
-            Opcode::Sub => self.binary_op(|a, b| a - b)?,
-            Opcode::Mul => self.binary_op(|a, b| a * b)?,
-            Opcode::Div => self.binary_op(|a, b| a / b)?,
-            Opcode::Eq => self.compare_op(|a, b| a == b)?,
-            Opcode::Lt => self.compare_op(|a, b| a < b)?,
-            Opcode::Gt => self.compare_op(|a, b| a > b)?,
+                self.stack.pop().ok_or(VmError::StackUnderflow)?;
+            }
+            Opcode::Add => self.binary_op(i64::checked_add, |a, b| a + b)?,
+            Opcode::Sub => self.binary_op(i64::checked_sub, |a, b| a - b)?,
+            Opcode::Mul => self.binary_op(i64::checked_mul, |a, b| a * b)?,
+            Opcode::Div => self.division_op(i64::checked_div, |a, b| a / b)?,
+            Opcode::Mod => self.division_op(i64::checked_rem, |a, b| a % b)?,
+            Opcode::ToInt => {
+                let value = match self.stack.pop().ok_or(VmError::StackUnderflow)? {
+                    Value::Int(i) => i,
+                    Value::Float(f) if f.is_finite() && f.trunc() >= i64::MIN as f64 && f.trunc() < i64::MAX as f64 => f.trunc() as i64,
+                    Value::Float(f) => return Err(VmError::InvalidConversion(f)),
+                    other => return Err(VmError::TypeMismatch { expected: "numeric", found: other.type_name() }),
+                };
+                self.stack.push(Value::Int(value));
+            }
+            Opcode::ToFloat => {
+                let value = match self.stack.pop().ok_or(VmError::StackUnderflow)? {
+                    Value::Int(i) => i as f64,
+                    Value::Float(f) => f,
+                    other => return Err(VmError::TypeMismatch { expected: "numeric", found: other.type_name() }),
+                };
+                self.stack.push(Value::Float(value));
+            }
+            Opcode::Eq => self.compare_op(|ordering| ordering == Some(Ordering::Equal))?,
+            Opcode::Lt => self.compare_op(|ordering| ordering == Some(Ordering::Less))?,
+            Opcode::Gt => self.compare_op(|ordering| ordering == Some(Ordering::Greater))?,
             Opcode::And => self.logic_op(|a, b| a && b)?,
             Opcode::Or => self.logic_op(|a, b| a || b)?,
             Opcode::Not => {
                 let a = self.pop_bool()?;
                 self.stack.push(Value::Bool(!a));
             }
-            Opcode::Return => return Ok(()), // For now, just return
+            Opcode::Return => {
+                let next = match self.frames.pop() {
+                    Some(frame) => frame.return_pc,
+                    None => self.program.len(),
+                };
+                // try blocks left by returning out of them no longer apply
+                let depth = self.frames.len();
+                self.handlers.retain(|handler| handler.frame_depth <= depth);
+                return Ok(Some(next));
+            }
+            Opcode::Copy(depth) => {
+                let value = self.stack.len().checked_sub(depth + 1)
+                    .map(|position| self.stack[position].clone())
+                    .ok_or(VmError::StackUnderflow)?;
+                self.stack.push(value);
+            }
             Opcode::Store(name) => {
-                let value = self.stack.pop().ok_or("Stack underflow")?;
-                self.memory.insert(name, value);
+                let value = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                match self.frames.last_mut() {
+                    Some(frame) => frame.locals.insert(name, value),
+                    None => self.memory.insert(name, value),
+                };
+                self.check_memory()?;
             }
             Opcode::Load(name) => {
-                let value = self.memory.get(&name).ok_or("Variable not found")?.clone();
+                let value = self.frames.last()
+                    .and_then(|frame| frame.locals.get(&name))
+                    .or_else(|| self.memory.get(&name))
+                    .ok_or_else(|| VmError::VariableNotFound(name.clone()))?
+                    .clone();
+                self.stack.push(value);
+            }
+            Opcode::Jump(target) => return Ok(Some(target)),
+            Opcode::JumpIf(target) => {
+                if self.pop_bool()? {
+                    return Ok(Some(target));
+                }
+            }
+            Opcode::Call(target) => {
+                if self.frames.len() >= self.limits.max_call_depth {
+                    return Err(VmError::ResourceExhausted { resource: "call depth", limit: self.limits.max_call_depth });
+                }
+                self.frames.push(Frame { return_pc: self.pc + 1, locals: HashMap::new() });
+                return Ok(Some(target));
+            }
+            Opcode::CreateMap => self.stack.push(Value::Map(BTreeMap::new())),
+            Opcode::MapGet => {
+                let key = self.pop_string()?;
+                let value = self.pop_map()?.remove(&key).ok_or(VmError::KeyNotFound(key))?;
                 self.stack.push(value);
             }
-            Opcode::Call(_) => return Err("Function calls not implemented yet".to_string()),
+            Opcode::MapSet => {
+                let value = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                let key = self.pop_string()?;
+                match self.stack.last_mut() {
+                    Some(Value::Map(map)) => {
+                        map.insert(key, value);
+                    }
+                    Some(other) => return Err(VmError::TypeMismatch { expected: "map", found: other.type_name() }),
+                    None => return Err(VmError::StackUnderflow),
+                }
+            }
+            Opcode::MapContains => {
+                let key = self.pop_string()?;
+                let contains = self.pop_map()?.contains_key(&key);
+                self.stack.push(Value::Bool(contains));
+            }
+            Opcode::CreateList => self.stack.push(Value::List(Vec::new())),
+            Opcode::ListAppend => {
+                let value = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                match self.stack.last_mut() {
+                    Some(Value::List(list)) => list.push(value),
+                    Some(other) => return Err(VmError::TypeMismatch { expected: "list", found: other.type_name() }),
+                    None => return Err(VmError::StackUnderflow),
+                }
+            }
+            Opcode::GetIndex => {
+                let index = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                let value = match (self.stack.pop().ok_or(VmError::StackUnderflow)?, index) {
+                    (Value::List(mut list), Value::Int(index)) => {
+                        let position = list_position(index, list.len())?;
+                        list.swap_remove(position)
+                    }
+                    (Value::Map(mut map), Value::String(key)) => map.remove(&key).ok_or(VmError::KeyNotFound(key))?,
+                    (Value::List(_), other) => return Err(VmError::TypeMismatch { expected: "integer", found: other.type_name() }),
+                    (Value::Map(_), other) => return Err(VmError::TypeMismatch { expected: "string", found: other.type_name() }),
+                    (other, _) => return Err(VmError::TypeMismatch { expected: "list or map", found: other.type_name() }),
+                };
+                self.stack.push(value);
+            }
+            Opcode::SetIndex => {
+                let value = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                let index = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                match (self.stack.last_mut(), index) {
+                    (Some(Value::List(list)), Value::Int(index)) => {
+                        let position = list_position(index, list.len())?;
+                        list[position] = value;
+                    }
+                    (Some(Value::Map(map)), Value::String(key)) => {
+                        map.insert(key, value);
+                    }
+                    (Some(Value::List(_)), other) => return Err(VmError::TypeMismatch { expected: "integer", found: other.type_name() }),
+                    (Some(Value::Map(_)), other) => return Err(VmError::TypeMismatch { expected: "string", found: other.type_name() }),
+                    (Some(other), _) => return Err(VmError::TypeMismatch { expected: "list or map", found: other.type_name() }),
+                    (None, _) => return Err(VmError::StackUnderflow),
+                }
+            }
+            Opcode::Length => {
+                let length = match self.stack.pop().ok_or(VmError::StackUnderflow)? {
+                    Value::List(list) => list.len(),
+                    Value::Map(map) => map.len(),
+                    Value::String(s) => s.chars().count(),
+                    other => return Err(VmError::TypeMismatch { expected: "list, map or string", found: other.type_name() }),
+                };
+                self.stack.push(Value::Int(length as i64));
+            }
+            Opcode::Try(catch_pc) => {
+                self.handlers.push(Handler {
+                    catch_pc,
+                    stack_len: self.stack.len(),
+                    frame_depth: self.frames.len(),
+                    memory: self.memory.clone(),
+                    locals: self.frames.last().map(|frame| frame.locals.clone()),
+                    events_len: self.events.len(),
+                });
+            }
+            Opcode::EndTry => {
+                self.handlers.pop();
+            }
+            Opcode::Revert => {
+                let message = self.pop_string()?;
+                return Err(VmError::Reverted(message));
+            }
             Opcode::Vote(proposal_id) => {
                 let vote = self.pop_bool()?;
-                println!("Voting {} on proposal {}", if vote { "Yes" } else { "No" }, proposal_id);
+                info!("Voting {} on proposal {}", if vote { "Yes" } else { "No" }, proposal_id);
             }
             Opcode::AllocateResource(resource_id) => {
                 let amount = self.pop_int()?;
-                println!("Allocating {} units of resource {}", amount, resource_id);
+                info!("Allocating {} units of resource {}", amount, resource_id);
             }
             Opcode::UpdateReputation(address) => {
                 let change = self.pop_int()?;
-                println!("Updating reputation of {} by {}", address, change);
+                info!("Updating reputation of {} by {}", address, change);
             }
             Opcode::CreateProposal => {
                 let description = self.pop_string()?;
-                println!("Creating proposal: {}", description);
+                info!("Creating proposal: {}", description);
                 self.stack.push(Value::String("new_proposal_id".to_string()));
             }
             Opcode::GetProposalStatus => {
                 let proposal_id = self.pop_string()?;
-                println!("Getting status of proposal: {}", proposal_id);
+                debug!("Getting status of proposal: {}", proposal_id);
                 self.stack.push(Value::String("Active".to_string()));
             }
             Opcode::Emit(event_name) => {
-                let event_data = self.stack.pop().ok_or("Stack underflow")?;
-                println!("Emitting event {}: {:?}", event_name, event_data);
+                let event_data = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                debug!("Emitting event {}: {:?}", event_name, event_data);
+                self.events.push((event_name, event_data));
             }
         }
+        if self.stack.len() > self.limits.max_stack_depth {
+            return Err(VmError::ResourceExhausted { resource: "stack depth", limit: self.limits.max_stack_depth });
+        }
+        Ok(None)
+    }
+
+    /// Memory only grows through `Store`, so it is measured after each one.
+    fn check_memory(&self) -> Result<(), VmError> {
+        let variables = || self.memory.iter().chain(self.frames.iter().flat_map(|frame| frame.locals.iter()));
+        if variables().count() > self.limits.max_memory_entries {
+            return Err(VmError::ResourceExhausted { resource: "memory entries", limit: self.limits.max_memory_entries });
+        }
+        if variables().map(|(name, value)| name.len() + value.size()).sum::<usize>() > self.limits.max_memory_bytes {
+            return Err(VmError::ResourceExhausted { resource: "memory bytes", limit: self.limits.max_memory_bytes });
+        }
         Ok(())
     }
 
-    fn binary_op<F>(&mut self, op: F) -> Result<(), String>
+    /// Integers stay integers and fail on overflow; as soon as a float is
+    /// involved the operation is done in floating point.
+    fn binary_op<I, F>(&mut self, int_op: I, float_op: F) -> Result<(), VmError>
     where
-        F: Fn(i64, i64) -> i64,
+        I: Fn(i64, i64) -> Option<i64>,
+        F: Fn(f64, f64) -> f64,
     {
-        let b = self.pop_int()?;
-        let a = self.pop_int()?;
-        self.stack.push(Value::Int(op(a, b)));
+        let b = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+        let a = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+        let result = match (a, b) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(int_op(a, b).ok_or(VmError::IntegerOverflow)?),
+            (Value::Int(a), Value::Float(b)) => Value::Float(float_op(a as f64, b)),
+            (Value::Float(a), Value::Int(b)) => Value::Float(float_op(a, b as f64)),
+            (Value::Float(a), Value::Float(b)) => Value::Float(float_op(a, b)),
+            (Value::Int(_) | Value::Float(_), other) | (other, _) => {
+                return Err(VmError::TypeMismatch { expected: "numeric", found: other.type_name() });
+            }
+        };
+        if let Value::Float(f) = result {
+            if !f.is_finite() {
+                return Err(VmError::FloatOverflow);
+            }
+        }
+        self.stack.push(result);
         Ok(())
     }
 
-    fn compare_op<F>(&mut self, op: F) -> Result<(), String>
+    fn division_op<I, F>(&mut self, int_op: I, float_op: F) -> Result<(), VmError>
+    where
+        I: Fn(i64, i64) -> Option<i64>,
+        F: Fn(f64, f64) -> f64,
+    {
+        match self.stack.last() {
+            Some(Value::Int(0)) => Err(VmError::DivisionByZero),
+            Some(Value::Float(f)) if *f == 0.0 => Err(VmError::DivisionByZero),
+            _ => self.binary_op(int_op, float_op),
+        }
+    }
+
+    /// Numbers compare by value whatever their type; other values only
+    /// compare with values of the same type.
+    fn compare_op<F>(&mut self, op: F) -> Result<(), VmError>
     where
-        F: Fn(&Value, &Value) -> bool,
+        F: Fn(Option<Ordering>) -> bool,
     {
-        let b = self.stack.pop().ok_or("Stack underflow")?;
-        let a = self.stack.pop().ok_or("Stack underflow")?;
-        self.stack.push(Value::Bool(op(&a, &b)));
+        let b = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+        let a = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+        let ordering = match (&a, &b) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (a, b) if std::mem::discriminant(a) == std::mem::discriminant(b) => a.partial_cmp(b),
+            _ => None,
+        };
+        self.stack.push(Value::Bool(op(ordering)));
         Ok(())
     }
 
-    fn logic_op<F>(&mut self, op: F) -> Result<(), String>
+    fn logic_op<F>(&mut self, op: F) -> Result<(), VmError>
     where
         F: Fn(bool, bool) -> bool,
     {
@@ -121,32 +442,84 @@ impl CoopVM {
         Ok(())
     }
 
-    fn pop_int(&mut self) -> Result<i64, String> {
-        match self.stack.pop().ok_or("Stack underflow")? {
+    fn pop_int(&mut self) -> Result<i64, VmError> {
+        match self.stack.pop().ok_or(VmError::StackUnderflow)? {
             Value::Int(i) => Ok(i),
-            _ => Err("Expected integer value".to_string()),
+            other => Err(VmError::TypeMismatch { expected: "integer", found: other.type_name() }),
         }
     }
 
-    fn pop_bool(&mut self) -> Result<bool, String> {
-        match self.stack.pop().ok_or("Stack underflow")? {
+    fn pop_bool(&mut self) -> Result<bool, VmError> {
+        match self.stack.pop().ok_or(VmError::StackUnderflow)? {
             Value::Bool(b) => Ok(b),
-            _ => Err("Expected boolean value".to_string()),
+            other => Err(VmError::TypeMismatch { expected: "boolean", found: other.type_name() }),
         }
     }
 
-    fn pop_string(&mut self) -> Result<String, String> {
-        match self.stack.pop().ok_or("Stack underflow")? {
+    fn pop_string(&mut self) -> Result<String, VmError> {
+        match self.stack.pop().ok_or(VmError::StackUnderflow)? {
             Value::String(s) => Ok(s),
-            _ => Err("Expected string value".to_string()),
+            other => Err(VmError::TypeMismatch { expected: "string", found: other.type_name() }),
+        }
+    }
+
+    fn pop_map(&mut self) -> Result<BTreeMap<String, Value>, VmError> {
+        match self.stack.pop().ok_or(VmError::StackUnderflow)? {
+            Value::Map(map) => Ok(map),
+            other => Err(VmError::TypeMismatch { expected: "map", found: other.type_name() }),
         }
     }
 
+    /// The index of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn program(&self) -> &[Opcode] {
+        &self.program
+    }
+
+    /// The instruction `step` would execute next, if the program has not ended.
+    pub fn current_opcode(&self) -> Option<&Opcode> {
+        self.program.get(self.pc)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    /// Function calls in progress.
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Variables local to the innermost function call, if inside one.
+    pub fn get_locals(&self) -> Option<&HashMap<String, Value>> {
+        self.frames.last().map(|frame| &frame.locals)
+    }
+
     pub fn get_stack(&self) -> &Vec<Value> {
         &self.stack
     }
 
+    /// Empties the stack, returning what was on it.
+    pub fn take_stack(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.stack)
+    }
+
     pub fn get_memory(&self) -> &HashMap<String, Value> {
         &self.memory
     }
+
+    /// Events emitted by the program, oldest first.
+    pub fn get_events(&self) -> &[(String, Value)] {
+        &self.events
+    }
+}
+
+/// The position of a list index, if within the list.
+fn list_position(index: i64, length: usize) -> Result<usize, VmError> {
+    usize::try_from(index).ok()
+        .filter(|position| *position < length)
+        .ok_or(VmError::IndexOutOfBounds { index, length })
 }
@@ -0,0 +1,280 @@
+use super::coop_vm::CoopVM;
+use super::error::VmError;
+use super::opcode::{Opcode, Value};
+use std::fmt;
+
+/// Where the debugger stops before executing an instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// At a program counter.
+    Pc(usize),
+    /// At any instruction with this opcode name, e.g. `Store` or `Emit`.
+    Opcode(String),
+}
+
+impl Breakpoint {
+    /// Parses a program counter, or failing that an opcode name.
+    pub fn parse(spec: &str) -> Self {
+        match spec.parse() {
+            Ok(pc) => Breakpoint::Pc(pc),
+            Err(_) => Breakpoint::Opcode(spec.to_string()),
+        }
+    }
+
+    fn matches(&self, pc: usize, opcode: &Opcode) -> bool {
+        match self {
+            Breakpoint::Pc(target) => *target == pc,
+            Breakpoint::Opcode(name) => opcode_name(opcode).eq_ignore_ascii_case(name),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(pc) => write!(f, "pc {}", pc),
+            Breakpoint::Opcode(name) => write!(f, "opcode {}", name),
+        }
+    }
+}
+
+/// Why `Debugger::continue_run` returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// A breakpoint matches the next instruction, at this program counter.
+    Breakpoint(usize),
+    Halted,
+}
+
+/// One executed instruction and the stack it left behind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub call_depth: usize,
+    pub stack: Vec<Value>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>5} {}{:?} -> {:?}", self.pc, "  ".repeat(self.call_depth), self.opcode, self.stack)
+    }
+}
+
+/// Runs a `CoopVM` an instruction at a time, stopping at breakpoints and
+/// optionally recording a trace of everything executed.
+///
+/// Unlike `CoopVM::run`, an uncaught error does not roll anything back: the
+/// machine stays as it was when the instruction failed so it can be inspected.
+pub struct Debugger {
+    vm: CoopVM,
+    breakpoints: Vec<Breakpoint>,
+    trace: Option<Vec<TraceEntry>>,
+}
+
+impl Debugger {
+    pub fn new(vm: CoopVM) -> Self {
+        Debugger {
+            vm,
+            breakpoints: Vec::new(),
+            trace: None,
+        }
+    }
+
+    pub fn vm(&self) -> &CoopVM {
+        &self.vm
+    }
+
+    pub fn into_vm(self) -> CoopVM {
+        self.vm
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Returns whether the breakpoint was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != breakpoint);
+        self.breakpoints.len() != before
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Starts recording every instruction executed from now on.
+    pub fn enable_trace(&mut self) {
+        self.trace.get_or_insert_with(Vec::new);
+    }
+
+    pub fn trace(&self) -> &[TraceEntry] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    /// Executes the next instruction. Returns the entry recorded for it, or
+    /// `None` if the program had already ended.
+    pub fn step(&mut self) -> Result<Option<TraceEntry>, VmError> {
+        let (pc, opcode) = match self.vm.current_opcode() {
+            Some(opcode) => (self.vm.pc(), opcode.clone()),
+            None => return Ok(None),
+        };
+        let call_depth = self.vm.call_depth();
+        self.vm.step()?;
+        let entry = TraceEntry { pc, opcode, call_depth, stack: self.vm.get_stack().clone() };
+        if let Some(trace) = &mut self.trace {
+            trace.push(entry.clone());
+        }
+        Ok(Some(entry))
+    }
+
+    /// Executes at least one instruction, then runs until a breakpoint
+    /// matches the next instruction or the program ends.
+    pub fn continue_run(&mut self) -> Result<StopReason, VmError> {
+        loop {
+            if self.step()?.is_none() {
+                return Ok(StopReason::Halted);
+            }
+            if self.vm.is_halted() {
+                return Ok(StopReason::Halted);
+            }
+            if self.at_breakpoint() {
+                return Ok(StopReason::Breakpoint(self.vm.pc()));
+            }
+        }
+    }
+
+    /// Whether a breakpoint matches the next instruction. Lets a session stop
+    /// before the very first instruction.
+    pub fn at_breakpoint(&self) -> bool {
+        match self.vm.current_opcode() {
+            Some(opcode) => self.breakpoints.iter().any(|b| b.matches(self.vm.pc(), opcode)),
+            None => false,
+        }
+    }
+
+    /// Runs one command of an interactive session and returns what to print.
+    /// See `HELP` for the commands understood.
+    pub fn execute_command(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        match (words.next().unwrap_or(""), words.next()) {
+            ("s" | "step", _) => match self.step() {
+                Ok(Some(entry)) => format!("{}\n{}", entry, self.location()),
+                Ok(None) => "Program has ended".to_string(),
+                Err(e) => format!("Error: {}\n{}", e, self.location()),
+            },
+            ("c" | "continue", _) => match self.continue_run() {
+                Ok(StopReason::Breakpoint(pc)) => format!("Breakpoint at pc {}\n{}", pc, self.location()),
+                Ok(StopReason::Halted) => "Program has ended".to_string(),
+                Err(e) => format!("Error: {}\n{}", e, self.location()),
+            },
+            ("b" | "break", Some(spec)) => {
+                let breakpoint = Breakpoint::parse(spec);
+                let message = format!("Breakpoint set at {}", breakpoint);
+                self.add_breakpoint(breakpoint);
+                message
+            }
+            ("d" | "delete", Some(spec)) => {
+                let breakpoint = Breakpoint::parse(spec);
+                if self.remove_breakpoint(&breakpoint) {
+                    format!("Breakpoint at {} removed", breakpoint)
+                } else {
+                    format!("No breakpoint at {}", breakpoint)
+                }
+            }
+            ("breakpoints", _) => self.breakpoints.iter().map(|b| b.to_string()).collect::<Vec<_>>().join("\n"),
+            ("stack", _) => format!("{:?}", self.vm.get_stack()),
+            ("memory", _) => format_variables(self.vm.get_memory().iter()),
+            ("locals", _) => match self.vm.get_locals() {
+                Some(locals) => format_variables(locals.iter()),
+                None => "Not inside a function".to_string(),
+            },
+            ("events", _) => self.vm.get_events().iter()
+                .map(|(name, value)| format!("{}: {:?}", name, value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ("trace", _) => self.trace().iter().map(|entry| entry.to_string()).collect::<Vec<_>>().join("\n"),
+            ("where", _) => self.location(),
+            ("help" | "h", _) => HELP.to_string(),
+            _ => format!("Unknown command: {}\n{}", command.trim(), HELP),
+        }
+    }
+
+    /// The next instruction and the call depth it runs at.
+    pub fn location(&self) -> String {
+        match self.vm.current_opcode() {
+            Some(opcode) => format!("next: {:>5} {:?} (depth {})", self.vm.pc(), opcode, self.vm.call_depth()),
+            None => "next: end of program".to_string(),
+        }
+    }
+}
+
+/// The commands understood by `Debugger::execute_command`.
+pub const HELP: &str = "\
+step (s)             execute the next instruction
+continue (c)         run to the next breakpoint or the end
+break (b) <pc|op>    stop at a program counter or an opcode name
+delete (d) <pc|op>   remove a breakpoint
+breakpoints          list breakpoints
+stack | memory | locals | events | trace | where
+quit (q)";
+
+/// The name of an opcode without its operand, e.g. `Push` for `Push(Int(1))`.
+pub fn opcode_name(opcode: &Opcode) -> String {
+    let debug = format!("{:?}", opcode);
+    debug.split('(').next().unwrap_or_default().to_string()
+}
+
+fn format_variables<'a>(variables: impl Iterator<Item = (&'a String, &'a Value)>) -> String {
+    let mut variables: Vec<String> = variables.map(|(name, value)| format!("{} = {:?}", name, value)).collect();
+    variables.sort();
+    variables.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::CSCLCompiler;
+
+    fn debugger(source: &str) -> Debugger {
+        let program = CSCLCompiler::new(source).compile().unwrap();
+        Debugger::new(CoopVM::new(program))
+    }
+
+    #[test]
+    fn test_breakpoints_and_trace() {
+        let mut debugger = debugger("x = 1; y = x + 2; emit(\"Result\", y);");
+        debugger.enable_trace();
+        debugger.add_breakpoint(Breakpoint::parse("Emit"));
+        debugger.add_breakpoint(Breakpoint::parse("1"));
+
+        assert_eq!(debugger.continue_run().unwrap(), StopReason::Breakpoint(1));
+        assert_eq!(debugger.vm().get_stack(), &vec![Value::Int(1)]);
+        let stop = debugger.continue_run().unwrap();
+        assert_eq!(debugger.vm().get_memory().get("y"), Some(&Value::Int(3)));
+        assert!(matches!(debugger.vm().current_opcode(), Some(Opcode::Emit(_))), "stopped at {:?}", stop);
+        assert!(debugger.vm().get_events().is_empty());
+
+        assert_eq!(debugger.continue_run().unwrap(), StopReason::Halted);
+        assert_eq!(debugger.vm().get_events().len(), 1);
+        assert_eq!(debugger.trace().len(), debugger.vm().program().len());
+        assert_eq!(debugger.trace()[0].opcode, Opcode::Push(Value::Int(1)));
+        assert_eq!(debugger.step().unwrap(), None);
+    }
+
+    #[test]
+    fn test_error_leaves_state_for_inspection() {
+        let mut debugger = debugger("x = 5; y = x / 0;");
+        assert!(debugger.execute_command("step").contains("Push(Int(5))"));
+        assert_eq!(debugger.execute_command("b div"), "Breakpoint set at opcode div");
+        assert!(debugger.execute_command("c").starts_with("Breakpoint at pc"));
+        assert!(debugger.execute_command("c").starts_with("Error: Division by zero"));
+        assert_eq!(debugger.execute_command("memory"), "x = Int(5)");
+        assert!(matches!(debugger.vm().current_opcode(), Some(Opcode::Div)));
+        assert_eq!(debugger.vm().get_stack().len(), 2);
+        assert!(debugger.execute_command("bogus").starts_with("Unknown command"));
+    }
+}
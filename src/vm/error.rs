@@ -0,0 +1,44 @@
+use thiserror::Error;
+
+/// Why a contract stopped. Unless caught by a `try` block, the error ends
+/// the run and every state change the run made is rolled back.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum VmError {
+    #[error("Stack underflow")]
+    StackUnderflow,
+    #[error("Expected {expected} value, found {found}")]
+    TypeMismatch { expected: &'static str, found: &'static str },
+    #[error("Variable not found: {0}")]
+    VariableNotFound(String),
+    #[error("Key not found: {0}")]
+    KeyNotFound(String),
+    #[error("Index {index} out of bounds for list of length {length}")]
+    IndexOutOfBounds { index: i64, length: usize },
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Integer overflow")]
+    IntegerOverflow,
+    #[error("Float overflow")]
+    FloatOverflow,
+    #[error("Float {0} does not fit an integer")]
+    InvalidConversion(f64),
+    #[error("Jump target {0} out of range")]
+    JumpOutOfRange(usize),
+    /// The contract went over one of the machine's `VmLimits`.
+    #[error("Resource exhausted: {resource} over the limit of {limit}")]
+    ResourceExhausted { resource: &'static str, limit: usize },
+    /// Raised by the contract itself with `revert`.
+    #[error("{0}")]
+    Reverted(String),
+}
+
+/// A problem found in CSCL source, at a 1-based line and column of the
+/// contract or, if `module` is set, of the imported module with that path.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{message} at line {line}:{column}{}", .module.as_ref().map(|module| format!(" of {}", module)).unwrap_or_default())]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub module: Option<String>,
+}
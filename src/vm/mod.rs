@@ -1,7 +1,19 @@
+pub mod assembler;
+pub mod debugger;
 mod compiler;
+pub mod error;
+pub mod modules;
 pub mod opcode;
+pub mod optimizer;
+pub mod repl;
 mod coop_vm;
 
+pub use assembler::{assemble, Instruction, Label};
 pub use compiler::CSCLCompiler;
+pub use debugger::{Breakpoint, Debugger, StopReason, TraceEntry};
+pub use error::{CompileError, VmError};
+pub use modules::{FileResolver, MemoryResolver, ModuleResolver};
 pub use opcode::Opcode;
-pub use coop_vm::CoopVM;
\ No newline at end of file
+pub use optimizer::optimize;
+pub use repl::{eval_cscl, CsclEnv, EvalError};
+pub use coop_vm::{CoopVM, VmLimits};
\ No newline at end of file
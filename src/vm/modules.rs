@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Finds the source of the modules CSCL contracts import, by the path given
+/// to `import`, e.g. `coop_std/voting`.
+pub trait ModuleResolver {
+    fn resolve(&self, path: &str) -> Result<String, String>;
+}
+
+/// Resolves `import "a/b";` to the file `a/b.cscl` under a root directory.
+/// Paths may not leave the root.
+pub struct FileResolver {
+    root: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileResolver { root: root.into() }
+    }
+}
+
+impl ModuleResolver for FileResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        let relative = Path::new(path);
+        if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(format!("module path {} must be relative and stay below the root", path));
+        }
+        let file = self.root.join(relative).with_extension("cscl");
+        std::fs::read_to_string(&file).map_err(|e| format!("cannot read {}: {}", file.display(), e))
+    }
+}
+
+/// Modules held in memory, e.g. a standard library built into the node.
+#[derive(Default)]
+pub struct MemoryResolver {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_module(mut self, path: &str, source: &str) -> Self {
+        self.modules.insert(path.to_string(), source.to_string());
+        self
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        self.modules.get(path).cloned().ok_or_else(|| format!("module {} not found", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_resolver_stays_in_root() {
+        let root = std::env::temp_dir().join(format!("cscl-modules-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("coop_std")).unwrap();
+        std::fs::write(root.join("coop_std/voting.cscl"), "function yes() { return true; }").unwrap();
+
+        let resolver = FileResolver::new(&root);
+        assert_eq!(resolver.resolve("coop_std/voting").unwrap(), "function yes() { return true; }");
+        assert!(resolver.resolve("coop_std/missing").is_err());
+        assert!(resolver.resolve("../coop_std/voting").is_err());
+        assert!(resolver.resolve("/etc/passwd").is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
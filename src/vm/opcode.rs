@@ -1,12 +1,44 @@
-#[derive(Debug, Clone, PartialEq, PartialOrd)] // Add PartialOrd here
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Value {
     Int(i64),
     Float(f64),
     Bool(bool),
     String(String),
+    /// Keyed records, e.g. member to allocation. Ordered so that iteration
+    /// and comparison are deterministic.
+    Map(BTreeMap<String, Value>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Map(_) => "map",
+            Value::List(_) => "list",
+        }
+    }
+
+    /// Roughly how many bytes the value takes, counting map keys and
+    /// elements but not the containers' own overhead.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::Int(_) | Value::Float(_) => 8,
+            Value::Bool(_) => 1,
+            Value::String(s) => s.len(),
+            Value::Map(map) => map.iter().map(|(key, value)| key.len() + value.size()).sum(),
+            Value::List(list) => list.iter().map(Value::size).sum(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Opcode {
     Push(Value),
     Pop,
@@ -14,16 +46,56 @@ pub enum Opcode {
     Sub,
     Mul,
     Div,
+    Mod,
+    /// Pops a number and pushes it as an integer, truncating floats.
+    ToInt,
+    /// Pops a number and pushes it as a float.
+    ToFloat,
     Eq,
     Lt,
     Gt,
     And,
     Or,
     Not,
+    /// Returns from the current call, or ends the program outside of one.
     Return,
+    /// Pushes a copy of the value this many places below the top of the
+    /// stack; 0 copies the top.
+    Copy(usize),
     Store(String),
     Load(String),
-    Call(String),
+    /// Continues at the given program counter.
+    Jump(usize),
+    /// Pops a boolean and continues at the given program counter if it is true.
+    JumpIf(usize),
+    /// Calls the function starting at the given program counter.
+    Call(usize),
+    /// Pushes an empty map.
+    CreateMap,
+    /// Pops a key and a map and pushes the value under the key.
+    MapGet,
+    /// Pops a value and a key and sets them in the map left on top.
+    MapSet,
+    /// Pops a key and a map and pushes whether the map has the key.
+    MapContains,
+    /// Pushes an empty list.
+    CreateList,
+    /// Pops a value and appends it to the list left on top.
+    ListAppend,
+    /// Pops an index and a list, or a key and a map, and pushes the element.
+    GetIndex,
+    /// Pops a value and an index or key and sets them in the list or map
+    /// left on top. List indices must already exist.
+    SetIndex,
+    /// Pops a list, map or string and pushes its number of elements.
+    Length,
+    /// Starts a protected block. An error raised before the matching
+    /// `EndTry` rolls state back to this point, pushes the error message and
+    /// continues at the given program counter.
+    Try(usize),
+    EndTry,
+    /// Pops a message and raises it as an error.
+    Revert,
     Vote(String),
     AllocateResource(String),
     UpdateReputation(String),
@@ -0,0 +1,191 @@
+use super::assembler::{Instruction, Label};
+use super::coop_vm::CoopVM;
+use super::opcode::{Opcode, Value};
+use std::collections::HashSet;
+
+/// Rewrites symbolic code into code with the same effect that executes fewer
+/// instructions. The passes are repeated until none of them finds anything
+/// more to do, as each one can open up work for the others: a folded
+/// condition turns a branch into dead code, whose removal leaves labels
+/// nothing jumps to, and so on.
+pub fn optimize(mut code: Vec<Instruction>) -> Vec<Instruction> {
+    loop {
+        let optimized = remove_unreachable(reuse_stored_values(fold_constants(code.clone())));
+        if optimized == code {
+            return code;
+        }
+        code = optimized;
+    }
+}
+
+/// Replaces operations on constants with their result, and conditional
+/// jumps on a constant with an unconditional jump or nothing. Operations that
+/// would fail are left for the program to fail at when run.
+fn fold_constants(code: Vec<Instruction>) -> Vec<Instruction> {
+    let mut folded: Vec<Instruction> = Vec::with_capacity(code.len());
+    for instruction in code {
+        match instruction {
+            Instruction::Op(opcode) if operand_count(&opcode) > 0 => {
+                let operands = operand_count(&opcode);
+                match constant_operands(&folded, operands).and_then(|values| evaluate(values, opcode.clone())) {
+                    Some(value) => {
+                        folded.truncate(folded.len() - operands);
+                        folded.push(Opcode::Push(value).into());
+                    }
+                    None => folded.push(opcode.into()),
+                }
+            }
+            Instruction::Op(Opcode::Pop) if matches!(folded.last(), Some(Instruction::Op(Opcode::Push(_)))) => {
+                folded.pop();
+            }
+            Instruction::JumpIf(target) => match folded.last() {
+                Some(Instruction::Op(Opcode::Push(Value::Bool(condition)))) => {
+                    let condition = *condition;
+                    folded.pop();
+                    if condition {
+                        folded.push(Instruction::Jump(target));
+                    }
+                }
+                _ => folded.push(Instruction::JumpIf(target)),
+            },
+            other => folded.push(other),
+        }
+    }
+    folded
+}
+
+/// How many values a foldable operation pops; 0 for anything else.
+fn operand_count(opcode: &Opcode) -> usize {
+    match opcode {
+        Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod
+        | Opcode::Eq | Opcode::Lt | Opcode::Gt | Opcode::And | Opcode::Or => 2,
+        Opcode::Not | Opcode::ToInt | Opcode::ToFloat => 1,
+        _ => 0,
+    }
+}
+
+/// The values pushed by the last instructions, if they are all pushes.
+fn constant_operands(code: &[Instruction], count: usize) -> Option<Vec<Value>> {
+    let start = code.len().checked_sub(count)?;
+    code[start..].iter().map(|instruction| match instruction {
+        Instruction::Op(Opcode::Push(value)) => Some(value.clone()),
+        _ => None,
+    }).collect()
+}
+
+/// Runs the operation on a scratch machine, so that folding follows the
+/// VM's own rules for overflow, mixed numbers and comparisons exactly.
+fn evaluate(operands: Vec<Value>, opcode: Opcode) -> Option<Value> {
+    let mut program: Vec<Opcode> = operands.into_iter().map(Opcode::Push).collect();
+    program.push(opcode);
+    let mut vm = CoopVM::new(program);
+    vm.run().ok()?;
+    vm.take_stack().pop()
+}
+
+/// Turns `Store x; Load x` into `Copy 0; Store x`, keeping the stored value
+/// on the stack instead of reading it back.
+fn reuse_stored_values(code: Vec<Instruction>) -> Vec<Instruction> {
+    let mut reused: Vec<Instruction> = Vec::with_capacity(code.len());
+    for instruction in code {
+        match (reused.last(), instruction) {
+            (Some(Instruction::Op(Opcode::Store(stored))), Instruction::Op(Opcode::Load(loaded))) if *stored == loaded => {
+                let store = reused.pop().unwrap();
+                reused.push(Opcode::Copy(0).into());
+                reused.push(store);
+            }
+            (_, instruction) => reused.push(instruction),
+        }
+    }
+    reused
+}
+
+/// Drops code after a `Jump`, `Return` or `Revert` that no jump or call can
+/// reach, labels nothing jumps to, and jumps to the very next instruction.
+fn remove_unreachable(code: Vec<Instruction>) -> Vec<Instruction> {
+    let targets: HashSet<Label> = code.iter().filter_map(|instruction| match instruction {
+        Instruction::Jump(label) | Instruction::JumpIf(label) | Instruction::Try(label) => Some(*label),
+        _ => None,
+    }).collect();
+
+    let mut live: Vec<Instruction> = Vec::with_capacity(code.len());
+    let mut reachable = true;
+    for instruction in code {
+        match instruction {
+            Instruction::Label(label) if !targets.contains(&label) => {}
+            Instruction::Label(label) => {
+                // only labels can lie between a jump and this label by now
+                let last = live.iter().rposition(|previous| !matches!(previous, Instruction::Label(_)));
+                if let Some(position) = last.filter(|position| live[*position] == Instruction::Jump(label)) {
+                    live.remove(position);
+                }
+                live.push(Instruction::Label(label));
+                reachable = true;
+            }
+            Instruction::Function(name) => {
+                live.push(Instruction::Function(name));
+                reachable = true;
+            }
+            _ if !reachable => {}
+            Instruction::Jump(_) | Instruction::Op(Opcode::Return) | Instruction::Op(Opcode::Revert) => {
+                live.push(instruction);
+                reachable = false;
+            }
+            other => live.push(other),
+        }
+    }
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::CSCLCompiler;
+
+    fn push(value: i64) -> Instruction {
+        Opcode::Push(Value::Int(value)).into()
+    }
+
+    #[test]
+    fn test_constants_folded_and_branches_removed() {
+        assert_eq!(optimize(vec![
+            push(2), push(3), Opcode::Mul.into(), push(1), Opcode::Add.into(), Opcode::Store("x".to_string()).into(),
+            Opcode::Load("x".to_string()).into(), push(0), Opcode::Div.into(),
+            push(i64::MAX), push(1), Opcode::Add.into(),
+        ]), vec![
+            push(7), Opcode::Copy(0).into(), Opcode::Store("x".to_string()).into(), push(0), Opcode::Div.into(),
+            push(i64::MAX), push(1), Opcode::Add.into(),
+        ]);
+
+        assert_eq!(optimize(vec![
+            Opcode::Push(Value::Bool(true)).into(), Opcode::Not.into(), Instruction::JumpIf(1),
+            push(1), Instruction::Jump(2),
+            Instruction::Label(1), push(2),
+            Instruction::Label(2), Opcode::Return.into(), push(3),
+            Instruction::Function("f".to_string()), push(4),
+        ]), vec![push(1), Opcode::Return.into(), Instruction::Function("f".to_string()), push(4)]);
+    }
+
+    #[test]
+    fn test_optimized_contract_behaves_the_same() {
+        let source = "
+            limit = 10 * 100;
+            if (limit > 500 && !false) { level = \"high\"; } else { level = \"low\"; }
+            function cap(x) { if (x > limit) { return limit; } return x; emit(\"Unreachable\", x); }
+            while (false) { limit = 0; }
+            total = cap(2500) + cap(7);
+            emit(\"Capped\", total);
+        ";
+        let plain = CSCLCompiler::new(source).compile().unwrap();
+        let optimized = CSCLCompiler::new(source).with_optimizer().compile().unwrap();
+        assert!(optimized.len() < plain.len(), "{} instructions, {} before", optimized.len(), plain.len());
+
+        let mut vms = [CoopVM::new(plain), CoopVM::new(optimized)];
+        for vm in &mut vms {
+            vm.run().unwrap();
+        }
+        assert_eq!(vms[0].get_events(), vms[1].get_events());
+        assert_eq!(vms[1].get_memory().get("level"), Some(&Value::String("high".to_string())));
+        assert_eq!(vms[1].get_memory().get("total"), Some(&Value::Int(1007)));
+    }
+}
@@ -0,0 +1,128 @@
+use super::compiler::{compile_snippet, Definitions};
+use super::coop_vm::CoopVM;
+use super::error::{CompileError, VmError};
+use super::modules::ModuleResolver;
+use super::opcode::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EvalError {
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Compile(Vec<CompileError>),
+    #[error("{0}")]
+    Runtime(#[from] VmError),
+}
+
+/// The state a series of snippets run against: a scratch `CoopVM`, whose
+/// memory and events carry over from one snippet to the next, and the
+/// functions, structs and imports the snippets defined.
+pub struct CsclEnv {
+    vm: CoopVM,
+    definitions: Definitions,
+    resolver: Option<Box<dyn ModuleResolver>>,
+}
+
+impl CsclEnv {
+    pub fn new() -> Self {
+        CsclEnv {
+            vm: CoopVM::new(Vec::new()),
+            definitions: Definitions::default(),
+            resolver: None,
+        }
+    }
+
+    pub fn with_resolver<R: ModuleResolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    pub fn vm(&self) -> &CoopVM {
+        &self.vm
+    }
+}
+
+impl Default for CsclEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compiles and runs a snippet of CSCL in `env` and returns the stack it
+/// leaves: the value of a lone expression such as `balance * 2`, and usually
+/// nothing for statements. A snippet that fails to run changes nothing.
+pub fn eval_cscl(source: &str, env: &mut CsclEnv) -> Result<Vec<Value>, EvalError> {
+    let program = compile_snippet(source, &mut env.definitions, env.resolver.as_deref()).map_err(EvalError::Compile)?;
+    env.vm.load_program(program);
+    let result = env.vm.run();
+    let stack = env.vm.take_stack();
+    result?;
+    Ok(stack)
+}
+
+/// Whether more lines are needed to complete the input, because a bracket,
+/// string or comment opened in it is still open.
+pub fn needs_more_input(source: &str) -> bool {
+    let mut depth: i64 = 0;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(_) => {}
+                    None => return true,
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => previous = c,
+                        None => return true,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippets_share_state() {
+        let mut env = CsclEnv::new();
+        assert_eq!(eval_cscl("1 + 2 * 3", &mut env).unwrap(), vec![Value::Int(7)]);
+        assert!(eval_cscl("balance = 100; function double(x) { return x * 2; }", &mut env).unwrap().is_empty());
+        assert_eq!(eval_cscl("double(balance) > 150", &mut env).unwrap(), vec![Value::Bool(true)]);
+        assert_eq!(eval_cscl("struct Share { member, amount }", &mut env).unwrap(), vec![]);
+        assert_eq!(eval_cscl("Share { member: \"alice\", amount: double(3) }.amount", &mut env).unwrap(), vec![Value::Int(6)]);
+
+        assert_eq!(eval_cscl("balance = 0; x = 1 / 0;", &mut env), Err(EvalError::Runtime(VmError::DivisionByZero)));
+        assert_eq!(eval_cscl("balance", &mut env).unwrap(), vec![Value::Int(100)], "a failed snippet is rolled back");
+        assert_eq!(eval_cscl("missing(1)", &mut env).unwrap_err().to_string(), "undefined function missing at line 1:1");
+        assert_eq!(eval_cscl("(1 + ", &mut env).unwrap_err().to_string(), "expected an expression, found end of input at line 1:6");
+        assert!(env.vm().get_stack().is_empty());
+    }
+
+    #[test]
+    fn test_needs_more_input() {
+        assert!(needs_more_input("function f(x) {"));
+        assert!(needs_more_input("s = \"open"));
+        assert!(needs_more_input("/* still"));
+        assert!(!needs_more_input("if (x) { y = \"}{\"; } // {"));
+    }
+}
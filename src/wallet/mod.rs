@@ -0,0 +1,339 @@
+// src/wallet/mod.rs
+//! Keys kept on disk for the members a person acts for, and the transfers
+//! signed with them. Nothing here needs a node: transactions are built and
+//! signed offline, saved as JSON if need be, and submitted later through the
+//! node's API.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use crate::blockchain::{AgreementAction, AllowanceAction, CrowdfundAction, DividendAction, MarketAction, OrganizationAction, SettlementAction, StandingOrderAction, StreamAction, Transaction, ValidUntil, ValidationAction, VestingAction};
+use crate::consensus::StakeAction;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::identity::RevocationAction;
+
+/// Gas limit of transfers unless one is given.
+pub const DEFAULT_GAS_LIMIT: u64 = 1000;
+
+/// The address of the member holding `public_key`: its DID, as
+/// `DecentralizedIdentity::new` names it.
+pub fn address_of(public_key: &PublicKey) -> String {
+    format!("did:icn:{}", hex::encode(public_key.to_bytes()))
+}
+
+/// A named key of the keystore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletKey {
+    pub name: String,
+    pub address: String,
+    /// Hex Ed25519 secret key.
+    secret_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WalletKey {
+    fn from_secret(name: &str, secret: SecretKey) -> Self {
+        let public = PublicKey::from(&secret);
+        WalletKey {
+            name: name.to_string(),
+            address: address_of(&public),
+            secret_key: hex::encode(secret.as_bytes()),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn keypair(&self) -> Result<Keypair> {
+        let bytes = hex::decode(&self.secret_key).map_err(|e| Error::WalletError(format!("Key {} is corrupt: {}", self.name, e)))?;
+        let secret = SecretKey::from_bytes(&bytes).map_err(|e| Error::WalletError(format!("Key {} is corrupt: {}", self.name, e)))?;
+        let public = PublicKey::from(&secret);
+        Ok(Keypair { secret, public })
+    }
+
+    /// Builds and signs a transfer of `amount` from this key's address on
+    /// the network `network_id`, offering `gas_price` per unit of gas.
+    pub fn sign_transfer(&self, network_id: &str, to: &str, amount: f64, currency_type: CurrencyType, gas_limit: u64, gas_price: f64) -> Result<Transaction> {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(Error::WalletError(format!("Cannot transfer {}", amount)));
+        }
+        if !gas_price.is_finite() || gas_price < 0.0 {
+            return Err(Error::WalletError(format!("Cannot offer a gas price of {}", gas_price)));
+        }
+        let mut transaction = Transaction::new(self.address.clone(), to.to_string(), amount, currency_type, gas_limit)
+            .with_gas_price(gas_price)
+            .on_network(network_id);
+        transaction.sign(&self.keypair()?).map_err(Error::WalletError)?;
+        Ok(transaction)
+    }
+}
+
+/// A directory of keys, one JSON file per key, readable only by its owner.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    /// Opens the keystore in `dir`, creating the directory if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Keystore { dir })
+    }
+
+    /// Generates a key under `name`, which must not be taken.
+    pub fn create(&self, name: &str) -> Result<WalletKey> {
+        let keypair = Keypair::generate(&mut OsRng {});
+        self.save(WalletKey::from_secret(name, keypair.secret))
+    }
+
+    /// Adds an existing key, given as the hex of its 32-byte secret.
+    pub fn import(&self, name: &str, secret_hex: &str) -> Result<WalletKey> {
+        let bytes = hex::decode(secret_hex.trim()).map_err(|e| Error::WalletError(format!("Secret key is not hex: {}", e)))?;
+        let secret = SecretKey::from_bytes(&bytes).map_err(|e| Error::WalletError(format!("Invalid secret key: {}", e)))?;
+        self.save(WalletKey::from_secret(name, secret))
+    }
+
+    /// All keys, by name.
+    pub fn list(&self) -> Result<Vec<WalletKey>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                keys.push(Self::read(&path)?);
+            }
+        }
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(keys)
+    }
+
+    /// The key named `name`, or whose address is `name`.
+    pub fn get(&self, name: &str) -> Result<WalletKey> {
+        let path = self.path(name);
+        if check_name(name).is_ok() && path.exists() {
+            return Self::read(&path);
+        }
+        self.list()?.into_iter()
+            .find(|key| key.address == name)
+            .ok_or_else(|| Error::WalletError(format!("No key named {}", name)))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    fn read(path: &Path) -> Result<WalletKey> {
+        serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| Error::WalletError(format!("{} is not a wallet key: {}", path.display(), e)))
+    }
+
+    fn save(&self, key: WalletKey) -> Result<WalletKey> {
+        check_name(&key.name)?;
+        if let Some(existing) = self.list()?.into_iter().find(|existing| existing.address == key.address) {
+            return Err(Error::WalletError(format!("The key is already kept as {}", existing.name)));
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(self.path(&key.name)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => Error::WalletError(format!("A key named {} already exists", key.name)),
+            _ => e.into(),
+        })?;
+        let json = serde_json::to_vec_pretty(&key).map_err(|e| Error::WalletError(e.to_string()))?;
+        file.write_all(&json)?;
+        Ok(key)
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Error::WalletError(format!("Key names are letters, digits, - and _, not {:?}", name)));
+    }
+    Ok(())
+}
+
+/// What a transaction does, for the signer to confirm before it is sent.
+pub fn describe(transaction: &Transaction) -> String {
+    let signer = transaction.public_key.as_deref()
+        .and_then(|bytes| PublicKey::from_bytes(bytes).ok())
+        .map(|public_key| address_of(&public_key));
+    let signed = match (&signer, transaction.verify()) {
+        (Some(signer), Ok(true)) if *signer == transaction.from => "yes, by the sender".to_string(),
+        (Some(signer), Ok(true)) => format!("yes, by {}, NOT the sender", signer),
+        (Some(_), _) => "INVALID signature".to_string(),
+        (None, _) => "no".to_string(),
+    };
+    let transfer = if transaction.is_batch() {
+        let outputs: Vec<String> = transaction.outputs.iter()
+            .map(|output| format!("\n    {} {} to {}", output.amount, output.currency_type, output.to))
+            .collect();
+        format!("Batch of {} transfers, all or none:{}", outputs.len(), outputs.concat())
+    } else {
+        format!("Transfer {} {}", transaction.amount, transaction.currency_type)
+    };
+    let mut description = format!(
+        "{}\n  from:   {}\n  to:     {}\n  gas:    up to {} at {} each\n  signed: {}\n  on:     {}\n  hash:   {}",
+        transfer, transaction.from, transaction.to, transaction.gas_limit, transaction.gas_price, signed, transaction.network_id, transaction.hash(),
+    );
+    if !transaction.cosignatures.is_empty() {
+        let cosigners: Vec<String> = transaction.signers().into_iter().filter(|cosigner| Some(cosigner) != signer.as_ref()).collect();
+        let invalid = transaction.cosignatures.len().saturating_sub(cosigners.len());
+        description.push_str(&format!("\n  cosigned by: {}", cosigners.join(", ")));
+        if invalid > 0 {
+            description.push_str(&format!(" and {} INVALID", invalid));
+        }
+    }
+    if let Some(swap) = &transaction.swap {
+        let countersigned = if transaction.check_swap().is_ok() { "yes" } else { "no" };
+        description.push_str(&format!("\n  in return {} {} from the recipient, countersigned: {}", swap.amount, swap.currency_type, countersigned));
+    }
+    match &transaction.stream {
+        Some(StreamAction::Open { recipient, rate }) => description.push_str(&format!("\n  streams {} per block to {}", rate, recipient)),
+        Some(StreamAction::Withdraw { stream_id }) => description.push_str(&format!("\n  withdraws from stream {}", stream_id)),
+        Some(StreamAction::Cancel { stream_id }) => description.push_str(&format!("\n  cancels stream {}", stream_id)),
+        None => {}
+    }
+    match &transaction.standing_order {
+        Some(StandingOrderAction::Create { recipient, amount, interval, ends_at }) => {
+            let until = ends_at.map(|ends_at| format!(" until {}", ends_at)).unwrap_or_default();
+            description.push_str(&format!("\n  pays {} {} to {} every {} blocks{}", amount, transaction.currency_type, recipient, interval, until));
+        }
+        Some(StandingOrderAction::Cancel { order_id }) => description.push_str(&format!("\n  cancels standing order {}", order_id)),
+        Some(StandingOrderAction::Execute { order_id, sequence }) => description.push_str(&format!("\n  payment {} of standing order {}", sequence + 1, order_id)),
+        None => {}
+    }
+    match &transaction.allowance {
+        Some(AllowanceAction::Grant { spender, limit, period }) => {
+            let per = period.map(|period| format!(" every {} seconds", period)).unwrap_or_default();
+            description.push_str(&format!("\n  allows {} to spend {} {}{}", spender, limit, transaction.currency_type, per));
+        }
+        Some(AllowanceAction::Revoke { spender }) => description.push_str(&format!("\n  revokes the allowance of {}", spender)),
+        Some(AllowanceAction::Spend { spender }) => description.push_str(&format!("\n  spent by {} out of its allowance", spender)),
+        None => {}
+    }
+    match &transaction.validation {
+        Some(ValidationAction::Designate { contract_id }) => description.push_str(&format!("\n  has contract {} validate the sender's transactions", contract_id)),
+        Some(ValidationAction::Clear) => description.push_str("\n  goes back to signatures validating the sender's transactions"),
+        None => {}
+    }
+    match &transaction.organization {
+        Some(OrganizationAction::Create { name }) => description.push_str(&format!("\n  founds organization {}", name)),
+        Some(OrganizationAction::Invite { organization, member, role }) => description.push_str(&format!("\n  makes {} {:?} of {}", member, role, organization)),
+        Some(OrganizationAction::Remove { organization, member }) => description.push_str(&format!("\n  removes {} from {}", member, organization)),
+        Some(OrganizationAction::Spend { by }) => description.push_str(&format!("\n  spent from the treasury by {}", by)),
+        None => {}
+    }
+    match &transaction.settlement {
+        Some(SettlementAction::Obligation { creditor, amount }) => description.push_str(&format!("\n  owes {} {} to {}, settled at the end of the epoch", amount, transaction.currency_type, creditor)),
+        Some(SettlementAction::Settle { epoch }) => description.push_str(&format!("\n  net payment settling the epoch ending at block {}", epoch)),
+        None => {}
+    }
+    match &transaction.dividend {
+        Some(DividendAction::Open { period_end, claim_blocks }) => {
+            description.push_str(&format!("\n  opens a distribution of surplus by patronage up to block {}, claimable for {} blocks", period_end, claim_blocks));
+        }
+        Some(DividendAction::Patronage { distribution_id, member, amount }) => description.push_str(&format!("\n  records {} of patronage by {} in distribution {}", amount, member, distribution_id)),
+        Some(DividendAction::Fund { distribution_id }) => description.push_str(&format!("\n  funds distribution {}", distribution_id)),
+        Some(DividendAction::Claim { distribution_id }) => description.push_str(&format!("\n  claims a share of distribution {}", distribution_id)),
+        None => {}
+    }
+    match &transaction.vesting {
+        Some(VestingAction::Grant { beneficiary, schedule, revocable }) => description.push_str(&format!(
+            "\n  vests for {} over {} blocks after a cliff of {}{}",
+            beneficiary, schedule.duration_blocks, schedule.cliff_blocks, if *revocable { ", revocable with governance approval" } else { "" }
+        )),
+        Some(VestingAction::Claim { vesting_id }) => description.push_str(&format!("\n  claims what has vested of grant {}", vesting_id)),
+        Some(VestingAction::Revoke { vesting_id }) => description.push_str(&format!("\n  revokes vesting grant {}", vesting_id)),
+        None => {}
+    }
+    match &transaction.crowdfund {
+        Some(CrowdfundAction::Launch { goal, deadline }) => description.push_str(&format!("\n  launches a campaign raising {} {} by block {}", goal, transaction.currency_type, deadline)),
+        Some(CrowdfundAction::Pledge { campaign_id }) => description.push_str(&format!("\n  pledges to campaign {}, refunded if it misses its goal", campaign_id)),
+        None => {}
+    }
+    match &transaction.market {
+        Some(MarketAction::Offer { resource, quantity, unit_price }) => description.push_str(&format!("\n  offers {} units of {:?} at {} each", quantity, resource, unit_price)),
+        Some(MarketAction::Request { resource, quantity, max_unit_price }) => description.push_str(&format!("\n  requests {} units of {:?} at up to {} each", quantity, resource, max_unit_price)),
+        Some(MarketAction::Fulfil { allocation_id, proof }) => description.push_str(&format!("\n  proves delivery of allocation {}: {}", allocation_id, proof)),
+        Some(MarketAction::Confirm { allocation_id }) => description.push_str(&format!("\n  confirms delivery of allocation {}, paying its provider", allocation_id)),
+        Some(MarketAction::Cancel { order_id }) => description.push_str(&format!("\n  cancels order {}", order_id)),
+        None => {}
+    }
+    match &transaction.agreement {
+        Some(AgreementAction::Open { provider, terms }) => description.push_str(&format!("\n  escrows the payment to {} for: {}", provider, terms)),
+        Some(AgreementAction::Release { agreement_id }) => description.push_str(&format!("\n  releases the payment of agreement {}", agreement_id)),
+        Some(AgreementAction::Dispute { agreement_id, reason }) => description.push_str(&format!("\n  disputes agreement {}: {}", agreement_id, reason)),
+        Some(AgreementAction::Evidence { agreement_id, .. }) => description.push_str(&format!("\n  submits evidence on agreement {}", agreement_id)),
+        Some(AgreementAction::Rule { agreement_id, for_provider }) => {
+            description.push_str(&format!("\n  votes to {} of agreement {}", if *for_provider { "pay the provider" } else { "refund the client" }, agreement_id));
+        }
+        None => {}
+    }
+    match &transaction.revocation {
+        Some(RevocationAction::CreateStatusList { list_id }) => description.push_str(&format!("\n  opens credential status list {}", list_id)),
+        Some(RevocationAction::RegisterCredential { list_id, holder }) => description.push_str(&format!("\n  registers a credential of {} in status list {}", holder, list_id)),
+        Some(RevocationAction::RevokeCredential { list_id, index }) => description.push_str(&format!("\n  revokes credential {} of status list {}", index, list_id)),
+        Some(RevocationAction::RevokeDid { did_id, reason }) => description.push_str(&format!("\n  revokes DID {}: {}", did_id, reason)),
+        None => {}
+    }
+    match &transaction.stake {
+        Some(StakeAction::Bond { amount }) => description.push_str(&format!("\n  bonds {} as validator stake", amount)),
+        Some(StakeAction::Unbond { amount }) => description.push_str(&format!("\n  unbonds {} of validator stake", amount)),
+        Some(StakeAction::Withdraw) => description.push_str("\n  withdraws the unbonded validator stake"),
+        None => {}
+    }
+    if let Some(contract_id) = &transaction.rent_for {
+        description.push_str(&format!("\n  pays rent for the storage of contract {}", contract_id));
+    }
+    if let Some(creation) = &transaction.contract_creation {
+        description.push_str(&format!("\n  deploys {} bytes of {:?} code with {} constructor arguments", creation.code.bytecode.len(), creation.code.format, creation.args.len()));
+    }
+    if let Some(contract_id) = &transaction.smart_contract_id {
+        description.push_str(&format!("\n  runs contract {}", contract_id));
+    }
+    match transaction.valid_until {
+        Some(ValidUntil::Height(height)) => description.push_str(&format!("\n  valid up to block {}", height)),
+        Some(ValidUntil::Timestamp(timestamp)) => description.push_str(&format!("\n  valid until {}", DateTime::from_timestamp(timestamp, 0).map_or(timestamp.to_string(), |time| time.to_rfc3339()))),
+        None => {}
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_sign_transfers() {
+        let dir = std::env::temp_dir().join(format!("icn-keystore-{}", uuid::Uuid::new_v4()));
+        let keystore = Keystore::open(&dir).unwrap();
+        let alice = keystore.create("alice").unwrap();
+        assert!(keystore.create("alice").is_err(), "names are not reused");
+        let imported = keystore.import("alice-again", &alice.secret_key);
+        assert!(imported.unwrap_err().to_string().contains("already kept as alice"));
+        assert!(keystore.create("../escape").is_err());
+
+        let transaction = keystore.get(&alice.address).unwrap()
+            .sign_transfer("icn-testnet", "did:icn:bob", 12.5, "basicneeds".parse().unwrap(), DEFAULT_GAS_LIMIT, 1.5).unwrap();
+        assert!(transaction.verify().unwrap());
+        let replayed = transaction.clone().on_network(crate::network::protocol::DEFAULT_NETWORK_ID);
+        assert!(!replayed.verify().unwrap(), "the network is signed");
+        assert!(describe(&transaction).contains("signed: yes, by the sender"));
+        assert_eq!(keystore.list().unwrap().len(), 1);
+        assert_eq!("Custom(hours)".parse::<CurrencyType>(), Ok(CurrencyType::Custom("hours".to_string())));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+// src/webhooks/http.rs
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use super::{signature, Notification, Subscription};
+
+/// Longest status line read from a webhook's response.
+const MAX_STATUS_LINE: usize = 1024;
+
+/// Where a webhook listens. Only plain HTTP is spoken; systems outside the
+/// operator's network should be reached through a TLS-terminating proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct WebhookUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("Webhook URL {} must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Invalid port in {}", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Webhook URL {} has no host", url));
+        }
+        Ok(WebhookUrl { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+impl TryFrom<String> for WebhookUrl {
+    type Error = String;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        url.parse()
+    }
+}
+
+impl From<WebhookUrl> for String {
+    fn from(url: WebhookUrl) -> Self {
+        url.to_string()
+    }
+}
+
+/// POSTs `notification` to the subscription's webhook, signed with its
+/// secret. Succeeds if the webhook answers with a 2xx status.
+pub async fn send(subscription: &Subscription, notification: &Notification, now: DateTime<Utc>, timeout: Duration) -> Result<(), String> {
+    let body = serde_json::to_vec(notification).map_err(|e| e.to_string())?;
+    let timestamp = now.timestamp();
+    let url = &subscription.url;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         X-ICN-Event: {}\r\nX-ICN-Delivery: {}\r\nX-ICN-Timestamp: {}\r\nX-ICN-Signature: {}\r\n\r\n",
+        url.path, url.host, url.port, body.len(),
+        notification.event.kind().name(), notification.id, timestamp, signature(&subscription.secret, timestamp, &body),
+    );
+    let mut request = request.into_bytes();
+    request.extend_from_slice(&body);
+    let exchange = async {
+        let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 256];
+        while !response.windows(2).any(|window| window == b"\r\n") && response.len() < MAX_STATUS_LINE {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[..read]);
+        }
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange).await
+        .map_err(|_| format!("{} did not answer within {:?}", url, timeout))?
+        .map_err(|e| format!("{}: {}", url, e))?;
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| format!("{} sent no HTTP status", url))?;
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(format!("{} answered {}", url, status))
+    }
+}
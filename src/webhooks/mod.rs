@@ -0,0 +1,438 @@
+// src/webhooks/mod.rs
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+use crate::blockchain::Blockchain;
+use crate::clock::SharedClock;
+use crate::currency::CurrencyType;
+use crate::error::{Error, Result};
+use crate::governance::DemocraticSystem;
+use crate::governance::democracy::ProposalStatus;
+
+pub mod http;
+
+pub use http::WebhookUrl;
+
+/// Failed deliveries kept for operators to look into.
+const MAX_DEAD_LETTERS: usize = 1000;
+
+/// What happened on the node, as sent to webhooks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    NewBlock {
+        index: u64,
+        hash: String,
+        transaction_count: usize,
+    },
+    ProposalPassed {
+        proposal_id: String,
+        title: String,
+    },
+    BalanceCredited {
+        address: String,
+        currency: CurrencyType,
+        amount: f64,
+        balance: f64,
+        transaction_hash: String,
+        block_index: u64,
+    },
+    ContractEvent {
+        contract_id: String,
+        name: String,
+        data: String,
+        transaction_hash: String,
+        block_index: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewBlock,
+    ProposalPassed,
+    BalanceCredited,
+    ContractEvent,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::NewBlock => "new_block",
+            EventKind::ProposalPassed => "proposal_passed",
+            EventKind::BalanceCredited => "balance_credited",
+            EventKind::ContractEvent => "contract_event",
+        }
+    }
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            WebhookEvent::NewBlock { .. } => EventKind::NewBlock,
+            WebhookEvent::ProposalPassed { .. } => EventKind::ProposalPassed,
+            WebhookEvent::BalanceCredited { .. } => EventKind::BalanceCredited,
+            WebhookEvent::ContractEvent { .. } => EventKind::ContractEvent,
+        }
+    }
+}
+
+/// Which events a subscription receives. Each criterion left empty matches
+/// every event.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebhookFilter {
+    #[serde(default)]
+    pub kinds: BTreeSet<EventKind>,
+    /// Accounts credited, or contracts emitting events. Blocks and
+    /// proposals have no address and always match.
+    #[serde(default)]
+    pub addresses: BTreeSet<String>,
+    /// Names of contract events.
+    #[serde(default)]
+    pub topics: BTreeSet<String>,
+}
+
+impl WebhookFilter {
+    pub fn matches(&self, event: &WebhookEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+        match event {
+            WebhookEvent::BalanceCredited { address, .. } => self.addresses.is_empty() || self.addresses.contains(address),
+            WebhookEvent::ContractEvent { contract_id, name, .. } => {
+                (self.addresses.is_empty() || self.addresses.contains(contract_id))
+                    && (self.topics.is_empty() || self.topics.contains(name))
+            }
+            WebhookEvent::NewBlock { .. } | WebhookEvent::ProposalPassed { .. } => true,
+        }
+    }
+}
+
+/// An operator's webhook. Payloads are signed with `secret`, so the
+/// receiving system can tell they come from this node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub url: WebhookUrl,
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub filter: WebhookFilter,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The body POSTed to a webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Stays the same across retries, so receivers can drop duplicates.
+    pub id: String,
+    pub subscription_id: String,
+    pub event: WebhookEvent,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A notification on its way, or given up on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub notification: Notification,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Attempts after which a notification is given up on.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each retry after it.
+    #[serde(with = "humantime_serde")]
+    pub initial_backoff: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+    /// How long a webhook may take to answer.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 8,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(600),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// HMAC-SHA256 of `message` under `key`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new().chain(block.map(|b| b ^ 0x36)).chain(message).finalize();
+    Sha256::new().chain(block.map(|b| b ^ 0x5c)).chain(inner).finalize().into()
+}
+
+/// The `X-ICN-Signature` of a payload sent at `timestamp`: the hex HMAC of
+/// `<timestamp>.<body>` under the subscription's secret. Covering the
+/// timestamp lets receivers refuse replayed notifications.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{}.", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    format!("sha256={}", hex::encode(hmac_sha256(secret.as_bytes(), &message)))
+}
+
+/// Tells back-office systems about blocks, passed proposals, credited
+/// balances and contract events as they happen, so they need not poll.
+/// The service is fed by `scan_chain` and `scan_governance`, which find
+/// what is new since their last call; `deliver_due` then POSTs each
+/// notification to the webhooks whose filter it matches, retrying with
+/// exponential backoff until the webhook answers with a 2xx status.
+#[derive(Debug, Default)]
+pub struct WebhookService {
+    config: WebhookConfig,
+    subscriptions: BTreeMap<String, Subscription>,
+    queue: VecDeque<Delivery>,
+    dead_letters: VecDeque<Delivery>,
+    /// Height of the last block scanned.
+    last_block: Option<u64>,
+    /// Passed proposals already notified; None before the first scan.
+    passed: Option<HashSet<String>>,
+    clock: SharedClock,
+}
+
+impl WebhookService {
+    pub fn new(config: WebhookConfig) -> Self {
+        WebhookService { config, ..Self::default() }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Registers a webhook, returning its id.
+    pub fn subscribe(&mut self, url: &str, secret: &str, filter: WebhookFilter) -> Result<String> {
+        let url: WebhookUrl = url.parse().map_err(Error::WebhookError)?;
+        if secret.is_empty() {
+            return Err(Error::WebhookError("A secret is needed to sign payloads".to_string()));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        info!("Registered webhook {} for {}", id, url);
+        self.subscriptions.insert(id.clone(), Subscription {
+            id: id.clone(),
+            url,
+            secret: secret.to_string(),
+            filter,
+            created_at: self.clock.now(),
+        });
+        Ok(id)
+    }
+
+    /// Removes a webhook and drops the notifications still queued for it.
+    pub fn unsubscribe(&mut self, id: &str) -> bool {
+        self.queue.retain(|delivery| delivery.notification.subscription_id != id);
+        self.subscriptions.remove(id).is_some()
+    }
+
+    pub fn subscriptions(&self) -> impl Iterator<Item = &Subscription> {
+        self.subscriptions.values()
+    }
+
+    /// Notifications waiting to be sent or retried.
+    pub fn pending(&self) -> impl Iterator<Item = &Delivery> {
+        self.queue.iter()
+    }
+
+    /// Notifications given up on, most recent last.
+    pub fn dead_letters(&self) -> impl Iterator<Item = &Delivery> {
+        self.dead_letters.iter()
+    }
+
+    /// Queues `event` for every webhook whose filter matches it.
+    pub fn publish(&mut self, event: WebhookEvent) {
+        let now = self.clock.now();
+        for subscription in self.subscriptions.values().filter(|subscription| subscription.filter.matches(&event)) {
+            self.queue.push_back(Delivery {
+                notification: Notification {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    subscription_id: subscription.id.clone(),
+                    event: event.clone(),
+                    created_at: now,
+                },
+                attempts: 0,
+                next_attempt: now,
+                last_error: None,
+            });
+        }
+    }
+
+    /// Publishes the events of the blocks added since the last scan. The
+    /// first scan only notes the tip, so past blocks are not replayed.
+    pub fn scan_chain(&mut self, blockchain: &Blockchain) {
+        let tip = blockchain.chain.len() as u64 - 1;
+        let first = match self.last_block {
+            Some(last_block) => last_block + 1,
+            None => tip + 1,
+        };
+        for block in blockchain.chain.iter().skip(first as usize) {
+            self.publish(WebhookEvent::NewBlock {
+                index: block.index,
+                hash: block.hash.clone(),
+                transaction_count: block.transactions.len(),
+            });
+            for receipt in block.transactions.iter().filter_map(|transaction| blockchain.get_transaction_receipt(&transaction.hash())) {
+                for change in receipt.balance_changes.iter().filter(|change| change.delta > 0.0) {
+                    self.publish(WebhookEvent::BalanceCredited {
+                        address: change.address.clone(),
+                        currency: change.currency_type.clone(),
+                        amount: change.delta,
+                        balance: change.balance,
+                        transaction_hash: receipt.transaction_hash.clone(),
+                        block_index: block.index,
+                    });
+                }
+                for event in &receipt.events {
+                    self.publish(WebhookEvent::ContractEvent {
+                        contract_id: event.contract_id.clone(),
+                        name: event.name.clone(),
+                        data: event.data.clone(),
+                        transaction_hash: receipt.transaction_hash.clone(),
+                        block_index: block.index,
+                    });
+                }
+            }
+        }
+        self.last_block = Some(tip.max(self.last_block.unwrap_or(0)));
+    }
+
+    /// Publishes the proposals that passed since the last scan. As with
+    /// `scan_chain`, the first scan only notes what had passed already.
+    pub fn scan_governance(&mut self, governance: &DemocraticSystem) {
+        let passed: Vec<_> = governance.list_proposals().into_iter()
+            .filter(|proposal| matches!(proposal.status, ProposalStatus::Passed | ProposalStatus::Implemented))
+            .collect();
+        let first_scan = self.passed.is_none();
+        let seen = self.passed.get_or_insert_with(HashSet::new);
+        let new: Vec<_> = passed.into_iter().filter(|proposal| seen.insert(proposal.id.clone())).collect();
+        if first_scan {
+            return;
+        }
+        for proposal in new {
+            self.publish(WebhookEvent::ProposalPassed { proposal_id: proposal.id.clone(), title: proposal.title.clone() });
+        }
+    }
+
+    /// Takes the notifications due to be sent, with the webhook each goes to.
+    pub fn take_due(&mut self) -> Vec<(Delivery, Subscription)> {
+        let now = self.clock.now();
+        let (due, waiting): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.queue).into_iter()
+            .partition(|delivery| delivery.next_attempt <= now);
+        self.queue = waiting;
+        due.into_iter()
+            .filter_map(|delivery| {
+                let subscription = self.subscriptions.get(&delivery.notification.subscription_id)?.clone();
+                Some((delivery, subscription))
+            })
+            .collect()
+    }
+
+    /// Records the outcome of sending a notification taken with `take_due`:
+    /// failures are retried later, or given up on after `max_attempts`.
+    pub fn report(&mut self, mut delivery: Delivery, outcome: std::result::Result<(), String>) {
+        delivery.attempts += 1;
+        let error = match outcome {
+            Ok(()) => {
+                debug!("Delivered notification {}", delivery.notification.id);
+                return;
+            }
+            Err(error) => error,
+        };
+        delivery.last_error = Some(error.clone());
+        if delivery.attempts >= self.config.max_attempts {
+            warn!("Giving up on notification {} after {} attempts: {}", delivery.notification.id, delivery.attempts, error);
+            self.dead_letters.push_back(delivery);
+            if self.dead_letters.len() > MAX_DEAD_LETTERS {
+                self.dead_letters.pop_front();
+            }
+            return;
+        }
+        let backoff = self.config.initial_backoff
+            .saturating_mul(2u32.saturating_pow(delivery.attempts - 1))
+            .min(self.config.max_backoff);
+        debug!("Notification {} failed ({}), retrying in {:?}", delivery.notification.id, error, backoff);
+        delivery.next_attempt = self.clock.now() + chrono::Duration::from_std(backoff).expect("max_backoff out of range");
+        self.queue.push_back(delivery);
+    }
+
+    pub fn config(&self) -> &WebhookConfig {
+        &self.config
+    }
+}
+
+/// Sends the notifications of `service` that are due, reporting back how
+/// each went. The service is only locked around the bookkeeping, not while
+/// webhooks are answering.
+pub async fn deliver_due(service: &std::sync::Mutex<WebhookService>) {
+    let (due, timeout, clock) = {
+        let mut service = service.lock().unwrap();
+        (service.take_due(), service.config.timeout, service.clock.clone())
+    };
+    for (delivery, subscription) in due {
+        let outcome = http::send(&subscription, &delivery.notification, clock.now(), timeout).await;
+        service.lock().unwrap().report(delivery, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_filters_and_retries() {
+        let clock = MockClock::new();
+        let config = WebhookConfig { max_attempts: 2, ..WebhookConfig::default() };
+        let mut service = WebhookService::new(config).with_clock(clock.clone().into());
+        assert!(service.subscribe("https://example.org/hook", "secret", WebhookFilter::default()).is_err());
+        let filter = WebhookFilter { kinds: [EventKind::ContractEvent].into(), topics: ["Paid".to_string()].into(), ..Default::default() };
+        let id = service.subscribe("http://127.0.0.1:8080/icn", "secret", filter).unwrap();
+
+        let event = |name: &str| WebhookEvent::ContractEvent {
+            contract_id: "invoices".to_string(),
+            name: name.to_string(),
+            data: "42".to_string(),
+            transaction_hash: "tx".to_string(),
+            block_index: 1,
+        };
+        service.publish(event("Issued"));
+        service.publish(WebhookEvent::NewBlock { index: 1, hash: "h".to_string(), transaction_count: 0 });
+        service.publish(event("Paid"));
+        let mut due = service.take_due();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.id, id);
+
+        let (delivery, _) = due.remove(0);
+        service.report(delivery, Err("connection refused".to_string()));
+        assert!(service.take_due().is_empty(), "retried after a backoff");
+        clock.advance(Duration::from_secs(1));
+        let (delivery, _) = service.take_due().remove(0);
+        service.report(delivery, Err("connection refused".to_string()));
+        assert_eq!(service.pending().count(), 0);
+        assert_eq!(service.dead_letters().next().unwrap().attempts, 2);
+    }
+}